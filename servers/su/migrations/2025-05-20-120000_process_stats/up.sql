@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS process_stats (
+    process_id VARCHAR(255) PRIMARY KEY,
+    message_count BIGINT NOT NULL DEFAULT 0,
+    byte_count BIGINT NOT NULL DEFAULT 0,
+    last_nonce INTEGER NULL
+);
+
+-- existing processes are counted by `su backfill-process-stats`, in batches
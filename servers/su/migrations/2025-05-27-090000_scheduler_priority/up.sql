@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN priority INTEGER NULL;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+DROP COLUMN IF EXISTS large_objects;
\ No newline at end of file
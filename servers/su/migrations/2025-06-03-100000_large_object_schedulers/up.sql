@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN large_objects BOOLEAN NULL;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+DROP COLUMN IF EXISTS weight;
\ No newline at end of file
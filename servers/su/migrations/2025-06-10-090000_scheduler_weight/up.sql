@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN weight INTEGER NULL;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+DROP COLUMN IF EXISTS public_url;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN public_url VARCHAR NULL;
\ No newline at end of file
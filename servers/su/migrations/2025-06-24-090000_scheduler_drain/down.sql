@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+DROP COLUMN IF EXISTS drain;
\ No newline at end of file
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN drain BOOLEAN NULL;
\ No newline at end of file
@@ -0,0 +1 @@
+DROP TABLE IF EXISTS chain_snapshots;
\ No newline at end of file
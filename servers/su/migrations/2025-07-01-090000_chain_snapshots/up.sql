@@ -0,0 +1,11 @@
+CREATE TABLE IF NOT EXISTS chain_snapshots (
+    row_id SERIAL PRIMARY KEY,
+    process_id VARCHAR(255) NOT NULL,
+    epoch INTEGER NOT NULL,
+    nonce INTEGER NOT NULL,
+    timestamp BIGINT NOT NULL,
+    hash_chain TEXT NOT NULL,
+    assignment_id VARCHAR(255) NOT NULL,
+    signature TEXT NOT NULL,
+    UNIQUE (process_id, nonce)
+);
\ No newline at end of file
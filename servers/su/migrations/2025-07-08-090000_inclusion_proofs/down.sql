@@ -0,0 +1 @@
+DROP TABLE IF EXISTS inclusion_proofs;
\ No newline at end of file
@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS inclusion_proofs (
+    row_id SERIAL PRIMARY KEY,
+    item_id VARCHAR(255) NOT NULL UNIQUE,
+    bundle_id VARCHAR(255) NOT NULL,
+    root VARCHAR(255) NOT NULL,
+    leaf_index INTEGER NOT NULL,
+    leaf_count INTEGER NOT NULL,
+    path TEXT NOT NULL
+);
\ No newline at end of file
@@ -0,0 +1 @@
+CREATE INDEX IF NOT EXISTS idx_messages_tags ON messages USING GIN ((message_data -> 'message' -> 'tags') jsonb_path_ops);
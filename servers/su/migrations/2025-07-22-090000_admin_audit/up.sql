@@ -0,0 +1,27 @@
+CREATE TABLE IF NOT EXISTS admin_audit (
+    seq BIGINT PRIMARY KEY,
+    timestamp BIGINT NOT NULL,
+    actor TEXT NOT NULL,
+    client TEXT NOT NULL,
+    action VARCHAR(255) NOT NULL,
+    target TEXT NOT NULL,
+    before_value JSONB,
+    after_value JSONB,
+    prev_hash VARCHAR(255) NOT NULL,
+    hash VARCHAR(255) NOT NULL
+);
+
+-- entries can only be appended, never changed or removed
+CREATE OR REPLACE FUNCTION admin_audit_append_only() RETURNS trigger AS $$
+BEGIN
+    RAISE EXCEPTION 'admin_audit is append only';
+END;
+$$ LANGUAGE plpgsql;
+
+CREATE TRIGGER admin_audit_no_update
+    BEFORE UPDATE OR DELETE ON admin_audit
+    FOR EACH ROW EXECUTE FUNCTION admin_audit_append_only();
+
+CREATE TRIGGER admin_audit_no_truncate
+    BEFORE TRUNCATE ON admin_audit
+    FOR EACH STATEMENT EXECUTE FUNCTION admin_audit_append_only();
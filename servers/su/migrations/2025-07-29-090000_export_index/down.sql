@@ -0,0 +1,2 @@
+DROP INDEX IF EXISTS idx_messages_timestamp_assignment_id;
+DROP INDEX IF EXISTS idx_processes_timestamp_process_id;
@@ -0,0 +1,2 @@
+CREATE INDEX IF NOT EXISTS idx_messages_timestamp_assignment_id ON messages(timestamp, assignment_id);
+CREATE INDEX IF NOT EXISTS idx_processes_timestamp_process_id ON processes(timestamp, process_id);
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN region VARCHAR NULL;
\ No newline at end of file
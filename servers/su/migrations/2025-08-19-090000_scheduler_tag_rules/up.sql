@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN tags_to_route VARCHAR NULL;
\ No newline at end of file
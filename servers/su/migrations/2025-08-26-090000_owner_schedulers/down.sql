@@ -0,0 +1 @@
+DROP TABLE IF EXISTS owner_schedulers;
\ No newline at end of file
@@ -0,0 +1,4 @@
+CREATE TABLE IF NOT EXISTS owner_schedulers (
+    owner VARCHAR(255) PRIMARY KEY,
+    scheduler_row_id INTEGER NOT NULL
+);
\ No newline at end of file
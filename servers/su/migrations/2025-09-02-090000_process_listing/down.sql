@@ -0,0 +1,5 @@
+DROP INDEX IF EXISTS idx_processes_spawned_at_process_id;
+DROP INDEX IF EXISTS idx_process_schedulers_spawned_at_process_id;
+DROP INDEX IF EXISTS idx_process_schedulers_scheduler_process_id;
+ALTER TABLE process_schedulers
+DROP COLUMN IF EXISTS spawned_at;
@@ -0,0 +1,5 @@
+ALTER TABLE process_schedulers
+ADD COLUMN IF NOT EXISTS spawned_at BIGINT NULL;
+CREATE INDEX IF NOT EXISTS idx_process_schedulers_scheduler_process_id ON process_schedulers(scheduler_row_id, process_id);
+CREATE INDEX IF NOT EXISTS idx_process_schedulers_spawned_at_process_id ON process_schedulers(spawned_at, process_id);
+CREATE INDEX IF NOT EXISTS idx_processes_spawned_at_process_id ON processes(((process_data -> 'process' ->> 'timestamp')::BIGINT), process_id);
@@ -0,0 +1,9 @@
+-- Lossy: up.sql trimmed every wallet and dropped blank entries, this
+-- joins the list back with plain commas so the original spacing and any
+-- empty entries are not restored. Routing reads both forms the same.
+UPDATE schedulers
+SET wallets_to_route = (
+    SELECT COALESCE(string_agg(wallet, ',' ORDER BY position), '')
+    FROM json_array_elements_text(wallets_to_route::json) WITH ORDINALITY AS listed(wallet, position)
+)
+WHERE wallets_to_route LIKE '[%';
@@ -0,0 +1,9 @@
+-- Wallets are trimmed and blank entries dropped, down.sql can't bring
+-- either back, see the note there.
+UPDATE schedulers
+SET wallets_to_route = (
+    SELECT COALESCE(json_agg(trim(wallet) ORDER BY position), '[]')::text
+    FROM unnest(string_to_array(wallets_to_route, ',')) WITH ORDINALITY AS listed(wallet, position)
+    WHERE trim(wallet) <> ''
+)
+WHERE wallets_to_route NOT LIKE '[%';
@@ -0,0 +1,2 @@
+ALTER TABLE schedulers
+ADD COLUMN wallets_policy VARCHAR NULL;
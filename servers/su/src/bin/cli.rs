@@ -3,6 +3,7 @@ use std::io;
 use su::domain::migrate_to_disk;
 use su::domain::migrate_to_local;
 use su::domain::sync_local_drives;
+use su::domain::verify_dual_write;
 
 #[tokio::main]
 async fn main() -> io::Result<()> {
@@ -10,7 +11,9 @@ async fn main() -> io::Result<()> {
 
     if args.len() < 2 {
         eprintln!("Usage: {} <function_name>", args[0]);
-        eprintln!("Available functions: migrate_to_disk, migrate_to_local, sync_local_drives");
+        eprintln!(
+            "Available functions: migrate_to_disk, migrate_to_local, sync_local_drives, verify_dual_write"
+        );
         return Ok(());
     }
 
@@ -36,9 +39,14 @@ async fn main() -> io::Result<()> {
         "sync_local_drives" => {
             sync_local_drives(interval).await.unwrap();
         }
+        "verify_dual_write" => {
+            verify_dual_write().await.unwrap();
+        }
         _ => {
             eprintln!("Invalid function name: {}", args[1]);
-            eprintln!("Available functions: migrate_to_disk, migrate_to_local, sync_local_drives");
+            eprintln!(
+                "Available functions: migrate_to_disk, migrate_to_local, sync_local_drives, verify_dual_write"
+            );
         }
     }
 
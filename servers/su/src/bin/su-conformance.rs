@@ -0,0 +1,33 @@
+use std::env;
+use std::io::{self, Error, ErrorKind};
+
+use su::domain::run_conformance;
+
+/*
+    su-conformance <url> [wallet]
+
+    Checks the su or router at url speaks the protocol,
+    with a wallet it also spawns a process and sends it
+    messages. Exits with 1 when a check fails.
+*/
+#[tokio::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
+    let target = args.get(1).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Usage: su-conformance <url> [wallet]",
+        )
+    })?;
+
+    match run_conformance(target, args.get(2).map(String::as_str)).await {
+        Ok(report) => {
+            println!("{}", report);
+            Ok(())
+        }
+        Err(report) => {
+            println!("{}", report);
+            std::process::exit(1)
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::domain::core::audit;
+use crate::domain::core::dal::{AdminAudit, AdminAuditErrorType, AdminChange, AuditEntry};
+
+/*
+  The admin audit log as json lines in ADMIN_AUDIT_PATH,
+  for an su without postgres. The file is only appended
+  to, the last entry is kept to chain the next one from.
+*/
+impl From<io::Error> for AdminAuditErrorType {
+    fn from(error: io::Error) -> Self {
+        AdminAuditErrorType::AuditError(format!("Admin audit io error: {}", error))
+    }
+}
+
+impl From<serde_json::Error> for AdminAuditErrorType {
+    fn from(error: serde_json::Error) -> Self {
+        AdminAuditErrorType::AuditError(format!("Invalid admin audit entry: {}", error))
+    }
+}
+
+pub fn now_millis() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as i64)
+        .unwrap_or(0)
+}
+
+pub struct AdminAuditFile {
+    path: PathBuf,
+    // the open file and the last entry in it
+    tail: Mutex<(File, Option<AuditEntry>)>,
+}
+
+impl AdminAuditFile {
+    pub fn new(path: &str) -> Result<Self, AdminAuditErrorType> {
+        let path = PathBuf::from(path);
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        let mut last = None;
+        for line in BufReader::new(File::open(&path)?).lines() {
+            let line = line?;
+            if !line.is_empty() {
+                last = Some(serde_json::from_str(&line)?);
+            }
+        }
+        Ok(AdminAuditFile {
+            path,
+            tail: Mutex::new((file, last)),
+        })
+    }
+}
+
+impl AdminAudit for AdminAuditFile {
+    fn append(&self, change: AdminChange) -> Result<AuditEntry, AdminAuditErrorType> {
+        let mut tail = self
+            .tail
+            .lock()
+            .map_err(|_| AdminAuditErrorType::AuditError("Audit log lock poisoned".to_string()))?;
+        let entry = audit::seal(tail.1.as_ref(), change, now_millis());
+        let mut line = serde_json::to_string(&entry)?;
+        line.push('\n');
+        tail.0.write_all(line.as_bytes())?;
+        tail.0.sync_data()?;
+        tail.1 = Some(entry.clone());
+        Ok(entry)
+    }
+
+    fn read(&self, from: i64, limit: i64) -> Result<Vec<AuditEntry>, AdminAuditErrorType> {
+        let mut entries = vec![];
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let line = line?;
+            if line.is_empty() {
+                continue;
+            }
+            let entry: AuditEntry = serde_json::from_str(&line)?;
+            if entry.seq >= from {
+                entries.push(entry);
+            }
+            if entries.len() as i64 >= limit {
+                break;
+            }
+        }
+        Ok(entries)
+    }
+}
+
+pub struct NoopAdminAudit;
+
+impl AdminAudit for NoopAdminAudit {
+    fn append(&self, change: AdminChange) -> Result<AuditEntry, AdminAuditErrorType> {
+        Ok(audit::seal(None, change, now_millis()))
+    }
+
+    fn read(&self, _from: i64, _limit: i64) -> Result<Vec<AuditEntry>, AdminAuditErrorType> {
+        Err(AdminAuditErrorType::AuditError(
+            "The admin audit log is off, set ADMIN_AUDIT_PATH".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_entries_chain_across_reopens() {
+        let path = std::env::temp_dir().join(format!("su-admin-audit-{}.log", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let change = |action: &str| AdminChange {
+            actor: "ops".to_string(),
+            client: "10.0.0.1".to_string(),
+            action: action.to_string(),
+            target: "target".to_string(),
+            before: None,
+            after: Some(json!({ "processes": ["p"] })),
+        };
+
+        let log = AdminAuditFile::new(path.to_str().unwrap()).unwrap();
+        let first = log.append(change("denylist.add")).unwrap();
+        drop(log);
+
+        let log = AdminAuditFile::new(path.to_str().unwrap()).unwrap();
+        let second = log.append(change("denylist.remove")).unwrap();
+        assert_eq!((second.seq, &second.prev_hash), (2, &first.hash));
+
+        let entries = log.read(1, 10).unwrap();
+        assert_eq!(entries, vec![first.clone(), second.clone()]);
+        assert_eq!(audit::verify_chain(None, &entries), Ok(()));
+        assert_eq!(log.read(2, 10).unwrap(), vec![second]);
+        assert_eq!(log.read(1, 1).unwrap(), vec![first]);
+        std::fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use ring::rand::{SecureRandom, SystemRandom};
+use sha2::{Digest, Sha256};
+
+use crate::domain::core::dal::{ApiKey, ApiKeyErrorType, ApiKeys};
+
+/*
+  Api keys for the read endpoints kept in the json file
+  at API_KEYS_PATH, a list of ApiKey. Only the sha256 of
+  a key is stored, as its id, so the file can't be used
+  to read as one of its clients. The file is read on
+  start and rewritten on every issue or revoke.
+*/
+pub struct ApiKeyFile {
+    keys: RwLock<BTreeMap<String, ApiKey>>,
+    path: Option<PathBuf>,
+}
+
+impl ApiKeyFile {
+    pub fn new(api_keys_path: &str) -> Result<Self, String> {
+        let path = match api_keys_path {
+            "" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        let keys = match &path {
+            Some(path) => load_keys(path)?,
+            None => BTreeMap::new(),
+        };
+        Ok(ApiKeyFile {
+            keys: RwLock::new(keys),
+            path,
+        })
+    }
+
+    /*
+      Applies a change to a copy of the keys and only keeps
+      it once the file has been written
+    */
+    fn update<F, T>(&self, change: F) -> Result<T, ApiKeyErrorType>
+    where
+        F: FnOnce(&mut BTreeMap<String, ApiKey>) -> Result<T, ApiKeyErrorType>,
+    {
+        let path = self.path.as_ref().ok_or(ApiKeyErrorType::ApiKeyError(
+            "API_KEYS_PATH is not set, api keys can't be saved".to_string(),
+        ))?;
+        let mut keys = self.keys.write().map_err(|_| lock_poisoned())?;
+
+        let mut updated = keys.clone();
+        let result = change(&mut updated)?;
+        save_keys(path, &updated)?;
+        *keys = updated;
+        Ok(result)
+    }
+}
+
+impl ApiKeys for ApiKeyFile {
+    fn validate(&self, key: &str) -> Option<ApiKey> {
+        let keys = self.keys.read().ok()?;
+        keys.get(&key_id(key)).cloned()
+    }
+
+    fn list(&self) -> Result<Vec<ApiKey>, ApiKeyErrorType> {
+        let keys = self.keys.read().map_err(|_| lock_poisoned())?;
+        Ok(keys.values().cloned().collect())
+    }
+
+    fn issue(
+        &self,
+        name: &str,
+        reads_per_minute: Option<u32>,
+    ) -> Result<(String, ApiKey), ApiKeyErrorType> {
+        if name.is_empty() {
+            return Err(ApiKeyErrorType::ApiKeyError(
+                "An api key needs a name".to_string(),
+            ));
+        }
+
+        let mut bytes = [0u8; 32];
+        SystemRandom::new()
+            .fill(&mut bytes)
+            .map_err(|_| ApiKeyErrorType::ApiKeyError("Failed to generate api key".to_string()))?;
+        let key = base64_url::encode(&bytes);
+        let api_key = ApiKey {
+            id: key_id(&key),
+            name: name.to_string(),
+            reads_per_minute,
+            created: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as i64)
+                .unwrap_or(0),
+        };
+
+        self.update(|keys| {
+            keys.insert(api_key.id.clone(), api_key.clone());
+            Ok(())
+        })?;
+        Ok((key, api_key))
+    }
+
+    fn revoke(&self, id: &str) -> Result<ApiKey, ApiKeyErrorType> {
+        self.update(|keys| {
+            keys.remove(id).ok_or(ApiKeyErrorType::NotFound(format!(
+                "Api key {} not found",
+                id
+            )))
+        })
+    }
+}
+
+fn key_id(key: &str) -> String {
+    base64_url::encode(&Sha256::digest(key.as_bytes()))
+}
+
+fn lock_poisoned() -> ApiKeyErrorType {
+    ApiKeyErrorType::ApiKeyError("Api key lock poisoned".to_string())
+}
+
+fn load_keys(path: &Path) -> Result<BTreeMap<String, ApiKey>, String> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let bytes = fs::read(path)
+        .map_err(|e| format!("Failed to read api keys file {}: {}", path.display(), e))?;
+    let keys: Vec<ApiKey> = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Invalid api keys file {}: {}", path.display(), e))?;
+    Ok(keys.into_iter().map(|key| (key.id.clone(), key)).collect())
+}
+
+// written next to the file and renamed so a crash can't leave half of it
+fn save_keys(path: &Path, keys: &BTreeMap<String, ApiKey>) -> Result<(), ApiKeyErrorType> {
+    let failed = |e: std::io::Error| {
+        ApiKeyErrorType::ApiKeyError(format!(
+            "Failed to write api keys file {}: {}",
+            path.display(),
+            e
+        ))
+    };
+    let json = serde_json::to_vec_pretty(&keys.values().collect::<Vec<_>>())
+        .map_err(|e| ApiKeyErrorType::ApiKeyError(e.to_string()))?;
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&tmp_path, json).map_err(failed)?;
+    fs::rename(&tmp_path, path).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn issued_keys_validate_until_revoked() {
+        let path = std::env::temp_dir().join(format!("su-api-keys-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let api_keys = ApiKeyFile::new(path.to_str().unwrap()).unwrap();
+
+        let (key, issued) = api_keys.issue("partner", Some(600)).unwrap();
+        assert_ne!(key, issued.id);
+        assert_eq!(api_keys.validate(&key), Some(issued.clone()));
+        assert_eq!(api_keys.validate(&issued.id), None);
+        assert!(api_keys.issue("", None).is_err());
+
+        let reloaded = ApiKeyFile::new(path.to_str().unwrap()).unwrap();
+        assert_eq!(reloaded.list().unwrap(), vec![issued.clone()]);
+
+        api_keys.revoke(&issued.id).unwrap();
+        assert_eq!(api_keys.validate(&key), None);
+        assert!(matches!(
+            api_keys.revoke(&issued.id),
+            Err(ApiKeyErrorType::NotFound(_))
+        ));
+        fs::remove_file(&path).unwrap();
+
+        let unsaved = ApiKeyFile::new("").unwrap();
+        assert!(unsaved.issue("partner", None).is_err());
+    }
+}
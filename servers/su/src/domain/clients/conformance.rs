@@ -0,0 +1,327 @@
+use std::time::Duration;
+
+use reqwest::header::{CONTENT_TYPE, LOCATION};
+use reqwest::redirect::Policy;
+use reqwest::{Client, Response};
+use serde_json::Value;
+use tokio::time::sleep;
+
+use crate::domain::clients::signer::ArweaveSigner;
+use crate::domain::core::conformance::{
+    check_info, check_message, check_nonces, check_page, check_process, check_timestamp,
+    check_write, message_item, spawn_item,
+};
+use crate::domain::core::dal::Signer;
+use crate::domain::core::deploy_check::CheckReport;
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// messages sent to the test process, enough for a second page
+const MESSAGES: usize = 2;
+
+// reads may come from a replica a little behind the writes
+const LISTING_ATTEMPTS: usize = 5;
+
+async fn json_body(response: Response, request: &str) -> Result<Value, String> {
+    let status = response.status();
+    let body = response
+        .text()
+        .await
+        .map_err(|e| format!("{} failed reading the body: {}", request, e))?;
+    if !status.is_success() {
+        let body: String = body.chars().take(200).collect();
+        return Err(format!("{} answered {}: {}", request, status, body));
+    }
+    serde_json::from_str(&body)
+        .map_err(|e| format!("{} answered a body that is not json: {}", request, e))
+}
+
+async fn get_json(client: &Client, url: &str) -> Result<Value, String> {
+    let request = format!("GET {}", url);
+    let response = client
+        .get(url)
+        .send()
+        .await
+        .map_err(|e| format!("{} failed: {}", request, e))?;
+    json_body(response, &request).await
+}
+
+async fn post_item(client: &Client, target: &str, item: Vec<u8>) -> Result<Value, String> {
+    let request = format!("POST {}/", target);
+    let response = client
+        .post(format!("{}/", target))
+        .header(CONTENT_TYPE, "application/octet-stream")
+        .body(item)
+        .send()
+        .await
+        .map_err(|e| format!("{} failed: {}", request, e))?;
+    json_body(response, &request).await
+}
+
+// an id nothing was written under has to be refused
+async fn unknown_id(client: &Client, target: &str) -> Result<String, String> {
+    let id = base64_url::encode(&rand::random::<[u8; 32]>());
+    let url = format!("{}/{}?process-id={}", target, id, id);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("GET {} failed: {}", url, e))?;
+    match response.status().is_client_error() {
+        true => Ok(format!("answered {}", response.status())),
+        false => Err(format!("GET {} answered {}", url, response.status())),
+    }
+}
+
+// returns the process id and the timestamp it was written at
+async fn spawn(
+    client: &Client,
+    target: &str,
+    signer: &dyn Signer,
+    scheduler: &str,
+) -> Result<(String, i64), String> {
+    let (process_id, item) = spawn_item(signer, scheduler).await?;
+    let body = post_item(client, target, item).await?;
+    Ok((process_id.clone(), check_write(&body, &process_id)?))
+}
+
+async fn send(
+    client: &Client,
+    target: &str,
+    signer: &dyn Signer,
+    process_id: &str,
+    sequence: usize,
+) -> Result<(String, i64), String> {
+    let (message_id, item) = message_item(signer, process_id, sequence).await?;
+    let body = post_item(client, target, item).await?;
+    Ok((message_id.clone(), check_write(&body, &message_id)?))
+}
+
+/*
+  A router either sends a read on to the scheduler of
+  the process with a 307 to the same path and query,
+  or answers it itself when it proxies
+*/
+async fn redirect(target: &str, process_id: &str) -> Result<String, String> {
+    let client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .redirect(Policy::none())
+        .build()
+        .map_err(|e| e.to_string())?;
+    let path = format!("/{}?process-id={}", process_id, process_id);
+    let url = format!("{}{}", target, path);
+    let response = client
+        .get(&url)
+        .send()
+        .await
+        .map_err(|e| format!("GET {} failed: {}", url, e))?;
+    let status = response.status().as_u16();
+    let location = response
+        .headers()
+        .get(LOCATION)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    match status {
+        307 if location.starts_with("http") && location.ends_with(&path) => {
+            Ok(format!("307 to {}", location))
+        }
+        307 => Err(format!(
+            "307 to {}, expected a scheduler url ending in {}",
+            location, path
+        )),
+        200 => Ok("answered in place, the router proxies".to_string()),
+        _ => Err(format!("GET {} answered {}", url, status)),
+    }
+}
+
+/*
+  Every page of the process's messages from the start,
+  one message a page, has to add up to the whole listing
+*/
+async fn paginate(
+    client: &Client,
+    target: &str,
+    process_id: &str,
+    expected: &[String],
+) -> Result<String, String> {
+    let base = format!(
+        "{}/{}?process-id={}&limit=1",
+        target, process_id, process_id
+    );
+    let mut ids: Vec<String> = vec![];
+    let mut nonces = vec![];
+    let mut url = base.clone();
+    for pages in 1.. {
+        let page = check_page(&get_json(client, &url).await?, process_id)?;
+        if page.ids.len() > 1 {
+            return Err(format!("limit=1 answered {} messages", page.ids.len()));
+        }
+        ids.extend(page.ids);
+        nonces.extend(page.nonces);
+        match (page.has_next_page, page.last_cursor) {
+            (true, Some(cursor)) if pages <= expected.len() + 1 => {
+                url = format!("{}&from={}", base, cursor);
+            }
+            (true, Some(_)) => return Err(format!("still has a next page after {}", pages)),
+            (true, None) => return Err("has a next page but no cursor".to_string()),
+            (false, _) => break,
+        }
+    }
+    check_nonces(&nonces)?;
+    match ids.as_slice() == expected {
+        true => Ok(format!("{} pages of one, same order", ids.len())),
+        false => Err(format!(
+            "pages hold {:?}, the whole listing {:?}",
+            ids, expected
+        )),
+    }
+}
+
+/*
+  Run the protocol checks against the su or router at
+  target, the rendered report is the error when any
+  failed. With a wallet a throwaway process is spawned
+  and sent messages to check writes and the reads of
+  them, without one only what needs no writes is run.
+*/
+pub async fn run_conformance(target: &str, wallet_path: Option<&str>) -> Result<String, String> {
+    let mut report = CheckReport::new();
+    let target = target.trim_end_matches('/');
+    let client = Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let info = get_json(&client, &format!("{}/info", target))
+        .await
+        .and_then(|body| check_info(&body));
+    report.add(
+        "info",
+        info.as_ref()
+            .map(|info| format!("{} in {} mode, signature verifies", info.address, info.mode))
+            .map_err(String::clone),
+    );
+    report.add("unknown id", unknown_id(&client, target).await);
+
+    let (info, wallet_path) = match (info, wallet_path) {
+        (Ok(info), Some(wallet_path)) => (info, wallet_path),
+        _ => return finish(report),
+    };
+    let signer = match ArweaveSigner::new(wallet_path) {
+        Ok(signer) => signer,
+        Err(e) => {
+            report.add("wallet", Err(e));
+            return finish(report);
+        }
+    };
+
+    let process_id = match spawn(&client, target, &signer, &info.address).await {
+        Ok((process_id, timestamp)) => {
+            report.add("spawn", Ok(format!("{} at {}", process_id, timestamp)));
+            process_id
+        }
+        Err(e) => {
+            report.add("spawn", Err(e));
+            return finish(report);
+        }
+    };
+
+    let mut message_ids = vec![];
+    for sequence in 0..MESSAGES {
+        let sent = send(&client, target, &signer, &process_id, sequence).await;
+        report.add(
+            &format!("message {}", sequence + 1),
+            sent.as_ref()
+                .map(|(message_id, timestamp)| format!("{} at {}", message_id, timestamp))
+                .map_err(String::clone),
+        );
+        if let Ok((message_id, _)) = sent {
+            message_ids.push(message_id);
+        }
+    }
+
+    let query = format!("?process-id={}", process_id);
+    report.add(
+        "timestamp",
+        get_json(&client, &format!("{}/timestamp{}", target, query))
+            .await
+            .and_then(|body| check_timestamp(&body)),
+    );
+    report.add(
+        "redirects",
+        match info.mode.as_str() {
+            "router" => redirect(target, &process_id).await,
+            _ => Ok("none, the target is a su".to_string()),
+        },
+    );
+    report.add(
+        "process format",
+        get_json(&client, &format!("{}/processes/{}", target, process_id))
+            .await
+            .and_then(|body| check_process(&body, &process_id)),
+    );
+    for message_id in &message_ids {
+        report.add(
+            "message format",
+            get_json(&client, &format!("{}/{}{}", target, message_id, query))
+                .await
+                .and_then(|body| check_message(&body, &process_id, message_id))
+                .map(|nonce| format!("{} assigned nonce {}", message_id, nonce)),
+        );
+    }
+
+    let listing = listing(&client, target, &process_id, &message_ids).await;
+    let expected = listing.as_ref().ok().cloned();
+    report.add(
+        "message listing",
+        listing.map(|ids| format!("{} messages, nonces in sequence", ids.len())),
+    );
+    if let Some(expected) = expected {
+        report.add(
+            "pagination",
+            paginate(&client, target, &process_id, &expected).await,
+        );
+    }
+    finish(report)
+}
+
+/*
+  The ids of the whole listing once it holds every
+  message sent, their nonces have to be consecutive
+*/
+async fn listing(
+    client: &Client,
+    target: &str,
+    process_id: &str,
+    message_ids: &[String],
+) -> Result<Vec<String>, String> {
+    let url = format!("{}/{}?process-id={}", target, process_id, process_id);
+    let mut attempt = 1;
+    loop {
+        let page = check_page(&get_json(client, &url).await?, process_id)?;
+        if page.has_next_page {
+            return Err("a process of a few messages has a next page".to_string());
+        }
+        let missing = message_ids.iter().find(|id| !page.ids.contains(id));
+        match missing {
+            None => {
+                check_nonces(&page.nonces)?;
+                return Ok(page.ids);
+            }
+            Some(id) if attempt == LISTING_ATTEMPTS => {
+                return Err(format!("{} is not listed", id));
+            }
+            Some(_) => {
+                attempt += 1;
+                sleep(Duration::from_secs(1)).await;
+            }
+        }
+    }
+}
+
+fn finish(report: CheckReport) -> Result<String, String> {
+    match report.passed() {
+        true => Ok(report.render()),
+        false => Err(report.render()),
+    }
+}
@@ -0,0 +1,198 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::domain::core::dal::{DeferredErrorType, DeferredItem, DeferredQueue};
+
+/*
+  Messages waiting for their Schedule-At time, one file
+  per message in DEFERRED_DIR named <schedule at>-<id>
+  so the queue survives a restart. The directory is read
+  once on start, after that the index in memory is the
+  only thing consulted to find what is due.
+*/
+impl From<io::Error> for DeferredErrorType {
+    fn from(error: io::Error) -> Self {
+        DeferredErrorType::DeferredError(format!("Deferred queue io error: {}", error))
+    }
+}
+
+struct Pending {
+    // (schedule at, id) to the file, earliest first
+    by_time: BTreeMap<(i64, String), PathBuf>,
+    by_id: HashMap<String, i64>,
+    failures: HashMap<String, u32>,
+}
+
+pub struct DeferredDir {
+    dir: PathBuf,
+    pending: Mutex<Pending>,
+}
+
+impl DeferredDir {
+    pub fn new(dir: &str) -> Result<Self, DeferredErrorType> {
+        fs::create_dir_all(dir)?;
+        let mut pending = Pending {
+            by_time: BTreeMap::new(),
+            by_id: HashMap::new(),
+            failures: HashMap::new(),
+        };
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            // anything else, like a write cut short by a crash, is left alone
+            let parsed = name
+                .split_once('-')
+                .and_then(|(at, id)| Some((at.parse::<i64>().ok()?, id.to_string())));
+            if let Some((schedule_at, id)) = parsed {
+                if !id.ends_with(".tmp") {
+                    pending.by_id.insert(id.clone(), schedule_at);
+                    pending.by_time.insert((schedule_at, id), entry.path());
+                }
+            }
+        }
+        Ok(DeferredDir {
+            dir: PathBuf::from(dir),
+            pending: Mutex::new(pending),
+        })
+    }
+
+    fn lock(&self) -> Result<std::sync::MutexGuard<'_, Pending>, DeferredErrorType> {
+        self.pending.lock().map_err(|_| {
+            DeferredErrorType::DeferredError("Deferred queue lock poisoned".to_string())
+        })
+    }
+}
+
+impl DeferredQueue for DeferredDir {
+    fn defer(&self, id: &str, schedule_at: i64, raw: &[u8]) -> Result<(), DeferredErrorType> {
+        // the id goes in a file name
+        let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+        if id.is_empty() || !id.chars().all(valid) {
+            return Err(DeferredErrorType::DeferredError(format!(
+                "Invalid message id {}",
+                id
+            )));
+        }
+        let mut pending = self.lock()?;
+        if pending.by_id.contains_key(id) {
+            return Err(DeferredErrorType::DeferredError(format!(
+                "Message {} is already deferred",
+                id
+            )));
+        }
+
+        // zero padded so the files list in the order they are due
+        let path = self.dir.join(format!("{:020}-{}", schedule_at, id));
+        let tmp_path = self.dir.join(format!("{:020}-{}.tmp", schedule_at, id));
+        fs::write(&tmp_path, raw)?;
+        fs::rename(&tmp_path, &path)?;
+
+        pending.by_id.insert(id.to_string(), schedule_at);
+        pending.by_time.insert((schedule_at, id.to_string()), path);
+        Ok(())
+    }
+
+    fn due(&self, now: i64, limit: usize) -> Result<Vec<DeferredItem>, DeferredErrorType> {
+        let pending = self.lock()?;
+        let mut items = vec![];
+        for ((schedule_at, id), path) in pending.by_time.iter().take(limit) {
+            if *schedule_at > now {
+                break;
+            }
+            items.push(DeferredItem {
+                id: id.clone(),
+                schedule_at: *schedule_at,
+                raw: fs::read(path)?,
+            });
+        }
+        Ok(items)
+    }
+
+    fn remove(&self, id: &str) -> Result<(), DeferredErrorType> {
+        let mut pending = self.lock()?;
+        pending.failures.remove(id);
+        if let Some(schedule_at) = pending.by_id.remove(id) {
+            if let Some(path) = pending.by_time.remove(&(schedule_at, id.to_string())) {
+                fs::remove_file(path)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn failed(&self, id: &str) -> u32 {
+        match self.lock() {
+            Ok(mut pending) => {
+                let failures = pending.failures.entry(id.to_string()).or_insert(0);
+                *failures += 1;
+                *failures
+            }
+            Err(_) => u32::MAX,
+        }
+    }
+
+    fn pending(&self) -> usize {
+        self.lock().map(|pending| pending.by_id.len()).unwrap_or(0)
+    }
+}
+
+pub struct NoopDeferredQueue;
+
+impl DeferredQueue for NoopDeferredQueue {
+    fn defer(&self, _id: &str, _schedule_at: i64, _raw: &[u8]) -> Result<(), DeferredErrorType> {
+        Err(DeferredErrorType::DeferredError(
+            "Deferred messages are off, set DEFERRED_DIR".to_string(),
+        ))
+    }
+
+    fn due(&self, _now: i64, _limit: usize) -> Result<Vec<DeferredItem>, DeferredErrorType> {
+        Ok(vec![])
+    }
+
+    fn remove(&self, _id: &str) -> Result<(), DeferredErrorType> {
+        Ok(())
+    }
+
+    fn failed(&self, _id: &str) -> u32 {
+        0
+    }
+
+    fn pending(&self) -> usize {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_items_come_due_in_order_and_survive_a_restart() {
+        let dir = std::env::temp_dir().join(format!("su-deferred-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let queue = DeferredDir::new(dir.to_str().unwrap()).unwrap();
+
+        queue.defer("late", 300, b"late").unwrap();
+        queue.defer("early", 100, b"early").unwrap();
+        assert!(queue.defer("early", 200, b"again").is_err());
+        assert!(queue.defer("../escape", 100, b"bad").is_err());
+        assert!(queue.due(99, 10).unwrap().is_empty());
+
+        let reopened = DeferredDir::new(dir.to_str().unwrap()).unwrap();
+        assert_eq!(reopened.pending(), 2);
+        let due = reopened.due(300, 10).unwrap();
+        let ids: Vec<&str> = due.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids, vec!["early", "late"]);
+        assert_eq!(due[0].raw, b"early".to_vec());
+        assert_eq!(reopened.due(300, 1).unwrap().len(), 1);
+
+        assert_eq!(reopened.failed("early"), 1);
+        assert_eq!(reopened.failed("early"), 2);
+        reopened.remove("early").unwrap();
+        assert_eq!(reopened.pending(), 1);
+        assert_eq!(reopened.due(300, 10).unwrap()[0].id, "late");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,393 @@
+use std::collections::{BTreeSet, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+
+use reqwest::{Client, Url};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use tokio::spawn;
+use tokio::time::{interval, Duration};
+
+use crate::domain::core::dal::{DataItem, Denylist, DenylistEntries, DenylistErrorType, Log};
+
+/*
+  Keeps a fleet wide denylist of processes and owners in
+  sync from a remote feed so abuse can be handled in one
+  place instead of on every su and router.
+
+  The feed is an ANS-104 data item signed by the wallet
+  configured as DENYLIST_OWNER, its data is json like
+
+  {
+    "version": 12,
+    "processes": ["<process id>", ...],
+    "owners": ["<owner address>", ...]
+  }
+
+  A feed that fails to download, verify or parse is
+  logged and the last good list stays in place. Feeds
+  with a lower version than the one loaded are ignored
+  so an old signed list can't be replayed.
+
+  Next to the feed each instance has its own local
+  entries, kept in the json file at DENYLIST_PATH in the
+  same shape without the version. The file is read on
+  start and rewritten whenever entries are added or
+  removed through the admin api.
+*/
+
+#[derive(Deserialize)]
+struct DenylistFeed {
+    #[serde(default)]
+    version: u64,
+    #[serde(default)]
+    processes: Vec<String>,
+    #[serde(default)]
+    owners: Vec<String>,
+}
+
+#[derive(Default)]
+struct DenylistState {
+    version: u64,
+    processes: HashSet<String>,
+    owners: HashSet<String>,
+}
+
+#[derive(Default, Clone)]
+struct LocalEntries {
+    processes: BTreeSet<String>,
+    owners: BTreeSet<String>,
+}
+
+impl LocalEntries {
+    fn to_entries(&self) -> DenylistEntries {
+        DenylistEntries {
+            processes: self.processes.iter().cloned().collect(),
+            owners: self.owners.iter().cloned().collect(),
+        }
+    }
+}
+
+pub struct DenylistClient {
+    state: Arc<RwLock<DenylistState>>,
+    local: RwLock<LocalEntries>,
+    local_path: Option<PathBuf>,
+}
+
+impl DenylistClient {
+    pub fn new(
+        denylist_url: &str,
+        denylist_owner: &str,
+        refresh_interval: u64,
+        denylist_path: &str,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, String> {
+        let state = Arc::new(RwLock::new(DenylistState::default()));
+
+        if !denylist_url.is_empty() {
+            let url =
+                Url::parse(denylist_url).map_err(|e| format!("Invalid denylist url: {}", e))?;
+            if denylist_owner.is_empty() {
+                return Err("DENYLIST_OWNER is required when DENYLIST_URL is set".to_string());
+            }
+
+            spawn(refresh(
+                url,
+                denylist_owner.to_string(),
+                refresh_interval.max(1),
+                state.clone(),
+                logger.clone(),
+            ));
+        }
+
+        let local_path = match denylist_path {
+            "" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        let local = match &local_path {
+            Some(path) => {
+                let local = load_local(path)?;
+                logger.log(format!(
+                    "Loaded local denylist, {} processes, {} owners",
+                    local.processes.len(),
+                    local.owners.len()
+                ));
+                local
+            }
+            None => LocalEntries::default(),
+        };
+
+        Ok(DenylistClient {
+            state,
+            local: RwLock::new(local),
+            local_path,
+        })
+    }
+
+    /*
+      Applies a change to a copy of the local entries and
+      only keeps it once the file has been written, so a
+      failed write leaves the file and memory in agreement
+    */
+    fn update_local<F>(&self, change: F) -> Result<DenylistEntries, DenylistErrorType>
+    where
+        F: FnOnce(&mut LocalEntries),
+    {
+        let path = self
+            .local_path
+            .as_ref()
+            .ok_or(DenylistErrorType::DenylistError(
+                "DENYLIST_PATH is not set, local entries can't be saved".to_string(),
+            ))?;
+        let mut local = self.local.write().map_err(|_| {
+            DenylistErrorType::DenylistError("Denylist state lock poisoned".to_string())
+        })?;
+
+        let mut updated = local.clone();
+        change(&mut updated);
+        save_local(path, &updated)?;
+        *local = updated;
+        Ok(local.to_entries())
+    }
+}
+
+impl Denylist for DenylistClient {
+    fn is_denied(&self, process_id: &str, owner: &str) -> bool {
+        let in_feed = match self.state.read() {
+            Ok(state) => state.processes.contains(process_id) || state.owners.contains(owner),
+            Err(_) => false,
+        };
+        let in_local = match self.local.read() {
+            Ok(local) => local.processes.contains(process_id) || local.owners.contains(owner),
+            Err(_) => false,
+        };
+        in_feed || in_local
+    }
+
+    fn local_entries(&self) -> Result<DenylistEntries, DenylistErrorType> {
+        self.local
+            .read()
+            .map(|local| local.to_entries())
+            .map_err(|_| {
+                DenylistErrorType::DenylistError("Denylist state lock poisoned".to_string())
+            })
+    }
+
+    fn add_local(&self, entries: &DenylistEntries) -> Result<DenylistEntries, DenylistErrorType> {
+        for id in entries.processes.iter().chain(entries.owners.iter()) {
+            if !is_valid_id(id) {
+                return Err(DenylistErrorType::DenylistError(format!(
+                    "Invalid denylist entry {}, expected a process id or owner address",
+                    id
+                )));
+            }
+        }
+        self.update_local(|local| {
+            local.processes.extend(entries.processes.iter().cloned());
+            local.owners.extend(entries.owners.iter().cloned());
+        })
+    }
+
+    fn remove_local(
+        &self,
+        entries: &DenylistEntries,
+    ) -> Result<DenylistEntries, DenylistErrorType> {
+        self.update_local(|local| {
+            for process_id in entries.processes.iter() {
+                local.processes.remove(process_id);
+            }
+            for owner in entries.owners.iter() {
+                local.owners.remove(owner);
+            }
+        })
+    }
+}
+
+// process ids and owner addresses are both 32 bytes in base64url
+fn is_valid_id(id: &str) -> bool {
+    id.len() == 43
+        && id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+fn load_local(path: &Path) -> Result<LocalEntries, String> {
+    if !path.exists() {
+        return Ok(LocalEntries::default());
+    }
+    let bytes = fs::read(path)
+        .map_err(|e| format!("Failed to read denylist file {}: {}", path.display(), e))?;
+    let entries: DenylistEntries = serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Invalid denylist file {}: {}", path.display(), e))?;
+    Ok(LocalEntries {
+        processes: entries.processes.into_iter().collect(),
+        owners: entries.owners.into_iter().collect(),
+    })
+}
+
+// written next to the file and renamed so a crash can't leave half of it
+fn save_local(path: &Path, local: &LocalEntries) -> Result<(), DenylistErrorType> {
+    let failed = |e: std::io::Error| {
+        DenylistErrorType::DenylistError(format!(
+            "Failed to write denylist file {}: {}",
+            path.display(),
+            e
+        ))
+    };
+    let json = serde_json::to_vec_pretty(&local.to_entries())
+        .map_err(|e| DenylistErrorType::DenylistError(e.to_string()))?;
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&tmp_path, json).map_err(failed)?;
+    fs::rename(&tmp_path, path).map_err(failed)
+}
+
+async fn refresh(
+    url: Url,
+    denylist_owner: String,
+    refresh_interval: u64,
+    state: Arc<RwLock<DenylistState>>,
+    logger: Arc<dyn Log>,
+) {
+    let client = Client::new();
+    let mut ticker = interval(Duration::from_secs(refresh_interval));
+    loop {
+        ticker.tick().await;
+        match fetch(&client, &url, &denylist_owner).await {
+            Ok(feed) => {
+                let mut current = match state.write() {
+                    Ok(s) => s,
+                    Err(_) => {
+                        logger.error("Denylist state lock poisoned".to_string());
+                        return;
+                    }
+                };
+                if feed.version < current.version {
+                    logger.error(format!(
+                        "Ignoring denylist version {}, version {} is already loaded",
+                        feed.version, current.version
+                    ));
+                    continue;
+                }
+                *current = DenylistState {
+                    version: feed.version,
+                    processes: feed.processes.into_iter().collect(),
+                    owners: feed.owners.into_iter().collect(),
+                };
+                logger.log(format!(
+                    "Loaded denylist version {}, {} processes, {} owners",
+                    current.version,
+                    current.processes.len(),
+                    current.owners.len()
+                ));
+            }
+            Err(e) => logger.error(format!("Failed to refresh denylist: {}", e)),
+        }
+    }
+}
+
+async fn fetch(client: &Client, url: &Url, denylist_owner: &str) -> Result<DenylistFeed, String> {
+    let response = client
+        .get(url.clone())
+        .send()
+        .await
+        .map_err(|e| format!("request error: {}", e))?;
+    if !response.status().is_success() {
+        return Err(format!("unexpected status {}", response.status()));
+    }
+    let bytes = response
+        .bytes()
+        .await
+        .map_err(|e| format!("request error: {}", e))?;
+
+    let item = DataItem::from_bytes_verify(bytes.to_vec())
+        .map_err(|e| format!("invalid signature: {:?}", e))?;
+
+    let owner_bytes =
+        base64_url::decode(&item.owner()).map_err(|_| "failed to parse owner".to_string())?;
+    let owner_address = base64_url::encode(&Sha256::digest(&owner_bytes));
+    if owner_address != denylist_owner {
+        return Err(format!("feed signed by unexpected owner {}", owner_address));
+    }
+
+    let data = item.data_bytes().ok_or("feed has no data".to_string())?;
+    serde_json::from_slice(&data).map_err(|e| format!("invalid feed json: {}", e))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn id(c: char) -> String {
+        std::iter::repeat(c).take(43).collect()
+    }
+
+    fn client(local_path: Option<PathBuf>) -> DenylistClient {
+        DenylistClient {
+            state: Arc::new(RwLock::new(DenylistState {
+                version: 1,
+                processes: HashSet::from(["bad-process".to_string()]),
+                owners: HashSet::from(["bad-owner".to_string()]),
+            })),
+            local: RwLock::new(LocalEntries::default()),
+            local_path,
+        }
+    }
+
+    #[test]
+    fn denies_listed_process_or_owner() {
+        let client = client(None);
+
+        assert!(client.is_denied("bad-process", "good-owner"));
+        assert!(client.is_denied("good-process", "bad-owner"));
+        assert!(!client.is_denied("good-process", "good-owner"));
+        assert!(!client.is_denied("good-process", ""));
+        assert!(client
+            .add_local(&DenylistEntries {
+                processes: vec![id('p')],
+                owners: vec![],
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn local_entries_are_saved_and_reloaded() {
+        let path = std::env::temp_dir().join(format!("su-denylist-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let client = client(Some(path.clone()));
+
+        let added = client
+            .add_local(&DenylistEntries {
+                processes: vec![id('p')],
+                owners: vec![id('o'), id('q')],
+            })
+            .unwrap();
+        assert_eq!(added.owners, vec![id('o'), id('q')]);
+        assert!(client.is_denied(&id('p'), ""));
+        assert!(client.is_denied("good-process", &id('o')));
+        assert!(client
+            .add_local(&DenylistEntries {
+                processes: vec!["not an id".to_string()],
+                owners: vec![],
+            })
+            .is_err());
+
+        client
+            .remove_local(&DenylistEntries {
+                processes: vec![],
+                owners: vec![id('o')],
+            })
+            .unwrap();
+        assert!(!client.is_denied("good-process", &id('o')));
+
+        let reloaded = load_local(&path).unwrap().to_entries();
+        assert_eq!(
+            reloaded,
+            DenylistEntries {
+                processes: vec![id('p')],
+                owners: vec![id('q')],
+            }
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
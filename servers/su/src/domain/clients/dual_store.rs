@@ -0,0 +1,401 @@
+use std::io;
+use std::sync::Arc;
+
+use async_trait::async_trait;
+
+use super::super::SuLog;
+use super::local_store::store::LocalStoreClient;
+use super::store::StoreClient;
+use crate::domain::config::AoConfig;
+use crate::domain::core::dal::{
+    ChainSnapshot, DataStore, ExportItem, InclusionProof, Log, Message, PaginatedMessages, Process,
+    ProcessStats, StoreErrorType, Tag,
+};
+
+/*
+  A DataStore used while moving an su from one
+  storage backend to another without downtime.
+
+  Every write goes to the old store first, it stays
+  the source of truth until the move is verified. The
+  write is then repeated on the new store, a failure
+  there is logged and left for the verifier to report
+  instead of failing the request.
+
+  Reads prefer the new store and fall back to the old
+  one when the new store errors or does not have the
+  record yet. Anything the scheduler relies on for
+  ordering or duplicate detection reads the old store
+  only, since the new one may still be missing history.
+*/
+pub struct DualWriteStore {
+    old: Arc<dyn DataStore>,
+    new: Arc<dyn DataStore>,
+    logger: Arc<dyn Log>,
+}
+
+impl DualWriteStore {
+    pub fn new(old: Arc<dyn DataStore>, new: Arc<dyn DataStore>, logger: Arc<dyn Log>) -> Self {
+        DualWriteStore { old, new, logger }
+    }
+
+    fn log_new_failure<T>(&self, op: &str, res: Result<T, StoreErrorType>) {
+        if let Err(e) = res {
+            self.logger
+                .error(format!("dual write to new store failed in {}: {:?}", op, e));
+        }
+    }
+}
+
+#[async_trait]
+impl DataStore for DualWriteStore {
+    fn save_process(&self, process: &Process, bundle_in: &[u8]) -> Result<String, StoreErrorType> {
+        let res = self.old.save_process(process, bundle_in)?;
+        self.log_new_failure("save_process", self.new.save_process(process, bundle_in));
+        Ok(res)
+    }
+
+    async fn get_process(&self, process_id_in: &str) -> Result<Process, StoreErrorType> {
+        match self.new.get_process(process_id_in).await {
+            Ok(process) => Ok(process),
+            Err(_) => self.old.get_process(process_id_in).await,
+        }
+    }
+
+    async fn get_process_bundle(&self, process_id_in: &str) -> Result<Vec<u8>, StoreErrorType> {
+        match self.new.get_process_bundle(process_id_in).await {
+            Ok(bundle) => Ok(bundle),
+            Err(_) => self.old.get_process_bundle(process_id_in).await,
+        }
+    }
+
+    async fn save_message(
+        &self,
+        message: &Message,
+        bundle_in: &[u8],
+        deep_hash: Option<&String>,
+    ) -> Result<String, StoreErrorType> {
+        let res = self.old.save_message(message, bundle_in, deep_hash).await?;
+        self.log_new_failure(
+            "save_message",
+            self.new.save_message(message, bundle_in, deep_hash).await,
+        );
+        Ok(res)
+    }
+
+    async fn save_messages(
+        &self,
+        messages: &[(&Message, &[u8], Option<&String>)],
+    ) -> Result<(), StoreErrorType> {
+        self.old.save_messages(messages).await?;
+        self.log_new_failure("save_messages", self.new.save_messages(messages).await);
+        Ok(())
+    }
+
+    async fn get_messages(
+        &self,
+        process: &Process,
+        from: &Option<String>,
+        to: &Option<String>,
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        match self
+            .new
+            .get_messages(process, from, to, limit, from_nonce, to_nonce)
+            .await
+        {
+            Ok(messages) => Ok(messages),
+            Err(_) => {
+                self.old
+                    .get_messages(process, from, to, limit, from_nonce, to_nonce)
+                    .await
+            }
+        }
+    }
+
+    async fn get_message_bundles(
+        &self,
+        process: &Process,
+        from: &Option<String>,
+        limit: &Option<i32>,
+    ) -> Result<(Vec<(String, Vec<u8>)>, bool), StoreErrorType> {
+        match self.new.get_message_bundles(process, from, limit).await {
+            Ok(bundles) => Ok(bundles),
+            Err(_) => self.old.get_message_bundles(process, from, limit).await,
+        }
+    }
+
+    async fn get_tagged_messages(
+        &self,
+        process: &Process,
+        tags: &[Tag],
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        match self
+            .new
+            .get_tagged_messages(process, tags, limit, from_nonce, to_nonce)
+            .await
+        {
+            Ok(messages) => Ok(messages),
+            Err(_) => {
+                self.old
+                    .get_tagged_messages(process, tags, limit, from_nonce, to_nonce)
+                    .await
+            }
+        }
+    }
+
+    fn get_message(&self, message_id_in: &str) -> Result<Message, StoreErrorType> {
+        match self.new.get_message(message_id_in) {
+            Ok(message) => Ok(message),
+            Err(_) => self.old.get_message(message_id_in),
+        }
+    }
+
+    async fn get_latest_message(
+        &self,
+        process_id_in: &str,
+    ) -> Result<Option<Message>, StoreErrorType> {
+        self.old.get_latest_message(process_id_in).await
+    }
+
+    fn check_existing_message(&self, message_id: &str) -> Result<(), StoreErrorType> {
+        self.old.check_existing_message(message_id)
+    }
+
+    async fn check_existing_deep_hash(
+        &self,
+        process_id: &str,
+        deep_hash: &str,
+    ) -> Result<(), StoreErrorType> {
+        self.old.check_existing_deep_hash(process_id, deep_hash).await
+    }
+
+    async fn get_deephash_version(&self, process_id: &str) -> Result<String, StoreErrorType> {
+        self.old.get_deephash_version(process_id).await
+    }
+
+    async fn save_deephash_version(
+        &self,
+        process_id: &str,
+        version: &str,
+    ) -> Result<(), StoreErrorType> {
+        self.old.save_deephash_version(process_id, version).await?;
+        self.log_new_failure(
+            "save_deephash_version",
+            self.new.save_deephash_version(process_id, version).await,
+        );
+        Ok(())
+    }
+
+    async fn save_deephash(&self, process_id: &str, deep_hash: &str) -> Result<(), StoreErrorType> {
+        self.old.save_deephash(process_id, deep_hash).await?;
+        self.log_new_failure(
+            "save_deephash",
+            self.new.save_deephash(process_id, deep_hash).await,
+        );
+        Ok(())
+    }
+
+    async fn get_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType> {
+        self.old.get_process_stats(process_id).await
+    }
+
+    async fn get_processes_stats(
+        &self,
+        process_ids: &[String],
+    ) -> Result<Vec<ProcessStats>, StoreErrorType> {
+        self.old.get_processes_stats(process_ids).await
+    }
+
+    async fn save_chain_snapshot(&self, snapshot: &ChainSnapshot) -> Result<(), StoreErrorType> {
+        self.old.save_chain_snapshot(snapshot).await?;
+        self.log_new_failure(
+            "save_chain_snapshot",
+            self.new.save_chain_snapshot(snapshot).await,
+        );
+        Ok(())
+    }
+
+    async fn get_chain_snapshot(
+        &self,
+        process_id: &str,
+        max_nonce: Option<i32>,
+    ) -> Result<ChainSnapshot, StoreErrorType> {
+        self.old.get_chain_snapshot(process_id, max_nonce).await
+    }
+
+    async fn save_inclusion_proofs(
+        &self,
+        proofs: &[InclusionProof],
+    ) -> Result<(), StoreErrorType> {
+        self.old.save_inclusion_proofs(proofs).await?;
+        self.log_new_failure(
+            "save_inclusion_proofs",
+            self.new.save_inclusion_proofs(proofs).await,
+        );
+        Ok(())
+    }
+
+    async fn get_inclusion_proof(&self, item_id: &str) -> Result<InclusionProof, StoreErrorType> {
+        self.old.get_inclusion_proof(item_id).await
+    }
+
+    async fn get_items_after(
+        &self,
+        after: (i64, &str),
+        limit: usize,
+    ) -> Result<Vec<ExportItem>, StoreErrorType> {
+        self.old.get_items_after(after, limit).await
+    }
+
+    async fn get_process_ids_after(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreErrorType> {
+        self.old.get_process_ids_after(after, limit).await
+    }
+
+    async fn list_processes(
+        &self,
+        spawned_after: Option<i64>,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, StoreErrorType> {
+        self.old.list_processes(spawned_after, after, limit).await
+    }
+}
+
+/*
+  Page through a process's message list in both
+  stores and return a description of the first
+  difference, None if they match.
+*/
+pub async fn compare_stores(
+    old: &dyn DataStore,
+    new: &dyn DataStore,
+    process: &Process,
+    page_size: i32,
+) -> Result<Option<String>, StoreErrorType> {
+    let mut from: Option<String> = None;
+    loop {
+        let old_page = old
+            .get_messages(process, &from, &None, &Some(page_size), &None, &None)
+            .await?;
+        let new_page = match new
+            .get_messages(process, &from, &None, &Some(page_size), &None, &None)
+            .await
+        {
+            Ok(page) => page,
+            Err(e) => return Ok(Some(format!("new store error: {:?}", e))),
+        };
+
+        let old_ids = old_page
+            .edges
+            .iter()
+            .map(|edge| edge.node.assignment_id())
+            .collect::<Result<Vec<String>, _>>()?;
+        let new_ids = new_page
+            .edges
+            .iter()
+            .map(|edge| edge.node.assignment_id())
+            .collect::<Result<Vec<String>, _>>()?;
+
+        if let Some(pos) = (0..old_ids.len().max(new_ids.len()))
+            .find(|i| old_ids.get(*i) != new_ids.get(*i))
+        {
+            return Ok(Some(format!(
+                "first difference after cursor {:?}: old {:?}, new {:?}",
+                from,
+                old_ids.get(pos),
+                new_ids.get(pos)
+            )));
+        }
+
+        if !old_page.page_info.has_next_page {
+            return Ok(None);
+        }
+
+        from = match old_page.edges.last() {
+            Some(edge) => Some(edge.cursor.clone()),
+            None => return Ok(None),
+        };
+    }
+}
+
+/*
+  Run from the cli against a dual writing su, checks
+  every process in the old store against the new
+  store and logs the ones that differ. Once it reports
+  no mismatches the su can switch to the new store.
+*/
+pub async fn verify_dual_write() -> io::Result<()> {
+    let logger = SuLog::init();
+    let config = AoConfig::new(None).expect("Failed to read configuration");
+
+    let old_store =
+        Arc::new(StoreClient::new_single_connection().expect("Failed to create StoreClient"));
+    let new_store =
+        LocalStoreClient::new_read_only(&config.su_file_db_dir, &config.su_index_db_dir)
+            .expect("Failed to create LocalStoreClient");
+
+    let batch_size = config.migration_batch_size;
+    let total_process_count = old_store
+        .get_process_count()
+        .expect("Failed to get process count");
+
+    let mut checked = 0;
+    let mut mismatched = 0;
+
+    for batch_start in (0..total_process_count).step_by(batch_size as usize) {
+        let processes = old_store
+            .get_all_processes(batch_start, Some(batch_start + batch_size))
+            .expect("Failed to fetch processes");
+
+        for bundle in processes {
+            let process = match Process::from_bytes(bundle) {
+                Ok(p) => p,
+                Err(e) => {
+                    logger.error(format!("Failed to parse process: {:?}", e));
+                    continue;
+                }
+            };
+
+            checked += 1;
+            match compare_stores(old_store.as_ref(), &new_store, &process, 100).await {
+                Ok(None) => (),
+                Ok(Some(diff)) => {
+                    mismatched += 1;
+                    logger.error(format!(
+                        "Process {} differs between stores, {}",
+                        process.process.process_id, diff
+                    ));
+                }
+                Err(e) => {
+                    mismatched += 1;
+                    logger.error(format!(
+                        "Process {} could not be read from the old store, {:?}",
+                        process.process.process_id, e
+                    ));
+                }
+            }
+        }
+
+        logger.log(format!(
+            "Verified {} of {} processes, {} mismatched",
+            checked, total_process_count, mismatched
+        ));
+    }
+
+    logger.log(format!(
+        "Verification complete, {} of {} processes mismatched",
+        mismatched, checked
+    ));
+
+    Ok(())
+}
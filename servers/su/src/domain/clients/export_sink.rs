@@ -0,0 +1,298 @@
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use async_trait::async_trait;
+use data_encoding::HEXLOWER;
+use reqwest::{Client, Url};
+use ring::hmac;
+use sha2::{Digest, Sha256};
+
+use crate::domain::core::dal::ExportSink;
+
+/*
+  The destinations of su export-parquet. A plain path is
+  a local directory, s3://bucket/prefix a bucket written
+  to with path style PUT requests signed with AWS
+  signature version 4, which also works against S3
+  compatible stores like MinIO through their endpoint.
+*/
+
+pub struct S3Settings {
+    pub endpoint: String,
+    pub region: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    // set with temporary credentials
+    pub session_token: String,
+}
+
+pub fn open_export_sink(destination: &str, s3: S3Settings) -> Result<Box<dyn ExportSink>, String> {
+    match destination.strip_prefix("s3://") {
+        Some(location) => Ok(Box::new(S3Sink::new(location, s3)?)),
+        None => {
+            std::fs::create_dir_all(destination)
+                .map_err(|e| format!("Failed to create {}: {}", destination, e))?;
+            Ok(Box::new(DirSink {
+                dir: PathBuf::from(destination),
+            }))
+        }
+    }
+}
+
+pub struct DirSink {
+    dir: PathBuf,
+}
+
+#[async_trait]
+impl ExportSink for DirSink {
+    async fn put(&self, name: &str, body: Vec<u8>) -> Result<String, String> {
+        let path = self.dir.join(name);
+        tokio::fs::write(&path, body)
+            .await
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        Ok(path.display().to_string())
+    }
+}
+
+pub struct S3Sink {
+    client: Client,
+    endpoint: Url,
+    bucket: String,
+    prefix: String,
+    settings: S3Settings,
+}
+
+impl S3Sink {
+    fn new(location: &str, settings: S3Settings) -> Result<Self, String> {
+        let (bucket, prefix) = location.split_once('/').unwrap_or((location, ""));
+        if bucket.is_empty() {
+            return Err("The s3 destination has no bucket".to_string());
+        }
+        if settings.access_key_id.is_empty() || settings.secret_access_key.is_empty() {
+            return Err(
+                "Writing to s3 needs AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY".to_string(),
+            );
+        }
+        let endpoint = match settings.endpoint.as_str() {
+            "" => format!("https://s3.{}.amazonaws.com", settings.region),
+            endpoint => endpoint.to_string(),
+        };
+        Ok(S3Sink {
+            client: Client::new(),
+            endpoint: Url::parse(&endpoint).map_err(|e| format!("Invalid s3 endpoint: {}", e))?,
+            bucket: bucket.to_string(),
+            prefix: prefix.trim_matches('/').to_string(),
+            settings,
+        })
+    }
+
+    fn key(&self, name: &str) -> String {
+        match self.prefix.as_str() {
+            "" => name.to_string(),
+            prefix => format!("{}/{}", prefix, name),
+        }
+    }
+}
+
+#[async_trait]
+impl ExportSink for S3Sink {
+    async fn put(&self, name: &str, body: Vec<u8>) -> Result<String, String> {
+        let key = self.key(name);
+        let path = format!("/{}/{}", uri_encode(&self.bucket), uri_encode(&key));
+        let mut url = self.endpoint.clone();
+        url.set_path(&path);
+
+        let host = match url.port() {
+            Some(port) => format!("{}:{}", url.host_str().unwrap_or(""), port),
+            None => url.host_str().unwrap_or("").to_string(),
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_err(|e| e.to_string())?
+            .as_secs();
+        let payload_hash = HEXLOWER.encode(&Sha256::digest(&body));
+        let headers = sign_put(&self.settings, &host, &path, &payload_hash, now);
+
+        let mut request = self.client.put(url).body(body);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+        let response = request
+            .send()
+            .await
+            .map_err(|e| format!("s3 request error: {}", e))?;
+        let status = response.status();
+        if !status.is_success() {
+            let body = response.text().await.unwrap_or_default();
+            return Err(format!("s3 returned {} for {}: {}", status, key, body));
+        }
+        Ok(format!("s3://{}/{}", self.bucket, key))
+    }
+}
+
+/*
+  The headers that sign a PUT of a payload with the
+  given hash to path, at now in unix seconds
+*/
+fn sign_put(
+    settings: &S3Settings,
+    host: &str,
+    path: &str,
+    payload_hash: &str,
+    now: u64,
+) -> Vec<(&'static str, String)> {
+    let amz_date = amz_date(now);
+    let date = &amz_date[..8];
+
+    let mut headers = vec![
+        ("host", host.to_string()),
+        ("x-amz-content-sha256", payload_hash.to_string()),
+        ("x-amz-date", amz_date.clone()),
+    ];
+    if !settings.session_token.is_empty() {
+        headers.push(("x-amz-security-token", settings.session_token.clone()));
+    }
+    let canonical_headers: String = headers
+        .iter()
+        .map(|(name, value)| format!("{}:{}\n", name, value))
+        .collect();
+    let signed_headers = headers
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(";");
+
+    let canonical_request = format!(
+        "PUT\n{}\n\n{}\n{}\n{}",
+        path, canonical_headers, signed_headers, payload_hash
+    );
+    let scope = format!("{}/{}/s3/aws4_request", date, settings.region);
+    let string_to_sign = format!(
+        "AWS4-HMAC-SHA256\n{}\n{}\n{}",
+        amz_date,
+        scope,
+        HEXLOWER.encode(&Sha256::digest(canonical_request.as_bytes()))
+    );
+
+    let key = signing_key(&settings.secret_access_key, date, &settings.region, "s3");
+    let signature = HEXLOWER.encode(
+        hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_ref()),
+            string_to_sign.as_bytes(),
+        )
+        .as_ref(),
+    );
+
+    // reqwest sets host itself
+    headers.retain(|(name, _)| *name != "host");
+    headers.push((
+        "authorization",
+        format!(
+            "AWS4-HMAC-SHA256 Credential={}/{}, SignedHeaders={}, Signature={}",
+            settings.access_key_id, scope, signed_headers, signature
+        ),
+    ));
+    headers
+}
+
+// the SigV4 key of one day, region and service, each step keyed by the one before
+fn signing_key(secret: &str, date: &str, region: &str, service: &str) -> hmac::Tag {
+    let secret = format!("AWS4{}", secret);
+    let mut key = hmac::sign(
+        &hmac::Key::new(hmac::HMAC_SHA256, secret.as_bytes()),
+        date.as_bytes(),
+    );
+    for part in [region, service, "aws4_request"] {
+        key = hmac::sign(
+            &hmac::Key::new(hmac::HMAC_SHA256, key.as_ref()),
+            part.as_bytes(),
+        );
+    }
+    key
+}
+
+// unreserved characters and the / between segments are left as they are
+fn uri_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            b => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+// YYYYMMDDTHHMMSSZ in utc
+fn amz_date(unix_seconds: u64) -> String {
+    let days = (unix_seconds / 86400) as i64;
+    let seconds = unix_seconds % 86400;
+
+    // civil date of a day count, from Howard Hinnant's date algorithms
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + (month <= 2) as i64;
+
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signing_key_and_dates() {
+        // the example in the AWS docs on deriving a signing key
+        let key = signing_key(
+            "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY",
+            "20120215",
+            "us-east-1",
+            "iam",
+        );
+        assert_eq!(
+            HEXLOWER.encode(key.as_ref()),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+        assert_eq!(amz_date(0), "19700101T000000Z");
+        assert_eq!(amz_date(1700000000), "20231114T221320Z");
+        assert_eq!(amz_date(951782400), "20000229T000000Z");
+        assert_eq!(uri_encode("a b/c+d.parquet"), "a%20b/c%2Bd.parquet");
+    }
+
+    #[test]
+    fn test_signed_put_headers() {
+        let settings = S3Settings {
+            endpoint: "".to_string(),
+            region: "us-east-1".to_string(),
+            access_key_id: "AKID".to_string(),
+            secret_access_key: "secret".to_string(),
+            session_token: "token".to_string(),
+        };
+        let headers = sign_put(&settings, "s3.us-east-1.amazonaws.com", "/b/k", "00", 0);
+        let authorization = &headers.last().unwrap().1;
+        assert_eq!(
+            authorization,
+            "AWS4-HMAC-SHA256 Credential=AKID/19700101/us-east-1/s3/aws4_request, SignedHeaders=host;x-amz-content-sha256;x-amz-date;x-amz-security-token, Signature=001afa2afc7ca02639ee3edc5dc2765cef5326b644e3b78b0bfd92ba47df883e"
+        );
+        assert!(headers
+            .iter()
+            .any(|(name, value)| *name == "x-amz-security-token" && value == "token"));
+        assert!(!headers.iter().any(|(name, _)| *name == "host"));
+    }
+}
@@ -1,5 +1,7 @@
+use crate::domain::clients::otlp::propagate;
 use crate::domain::config::AoConfig;
 use crate::domain::core::dal::{Gateway, GatewayTx, NetworkInfo, TxStatus};
+use crate::domain::core::trace::{self, SpanKind};
 use arweave_rs::network::NetworkInfoClient;
 use async_trait::async_trait;
 use reqwest::{Client, Url};
@@ -15,6 +17,7 @@ pub struct ArweaveGateway {
 }
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum GatewayErrorType {
     CheckHeadError(String),
     StatusError(String),
@@ -86,7 +89,7 @@ impl ArweaveGateway {
         for attempt in 0..5 {
             match network_client.network_info().await {
                 Ok(network_info) => {
-                    let height = network_info.height.clone();
+                    let height = network_info.height;
                     let current = network_info.current.to_string();
 
                     return Ok(NetworkInfo {
@@ -120,6 +123,7 @@ impl ArweaveGateway {
 #[async_trait]
 impl Gateway for ArweaveGateway {
     async fn check_head(&self, tx_id: String) -> Result<bool, String> {
+        let _span = trace::span("gateway check_head", SpanKind::Client);
         let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
         let arweave_urls = config.arweave_url_list;
 
@@ -134,13 +138,14 @@ impl Gateway for ArweaveGateway {
                 }
             };
 
-            let response = client
-                .head(
-                    url.join(&format!("{}", tx_id))
+            let response = propagate(
+                client.head(
+                    url.join(&tx_id.to_string())
                         .map_err(|e| GatewayErrorType::CheckHeadError(e.to_string()))?,
-                )
-                .send()
-                .await;
+                ),
+            )
+            .send()
+            .await;
 
             match response {
                 Ok(res) if res.status().is_success() => {
@@ -170,7 +175,8 @@ impl Gateway for ArweaveGateway {
         Ok(NetworkInfo { height, current })
     }
 
-    async fn status(&self, tx_id: &String) -> Result<TxStatus, String> {
+    async fn status(&self, tx_id: &str) -> Result<TxStatus, String> {
+        let _span = trace::span("gateway status", SpanKind::Client);
         let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
         let arweave_url = config.arweave_url;
 
@@ -181,14 +187,15 @@ impl Gateway for ArweaveGateway {
 
         let client = Client::new();
 
-        let response = client
-            .get(
+        let response = propagate(
+            client.get(
                 url.join(&format!("tx/{}/status", tx_id))
                     .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?,
-            )
-            .send()
-            .await
-            .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?;
+            ),
+        )
+        .send()
+        .await
+        .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?;
 
         if response.status().is_success() {
             let body: serde_json::Value = response
@@ -209,7 +216,8 @@ impl Gateway for ArweaveGateway {
         }
     }
 
-    async fn raw(&self, tx_id: &String) -> Result<Vec<u8>, String> {
+    async fn raw(&self, tx_id: &str) -> Result<Vec<u8>, String> {
+        let _span = trace::span("gateway raw", SpanKind::Client);
         let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
         let arweave_url = config.arweave_url;
 
@@ -220,14 +228,15 @@ impl Gateway for ArweaveGateway {
 
         let client = Client::new();
 
-        let response = client
-            .get(
+        let response = propagate(
+            client.get(
                 url.join(&format!("raw/{}", tx_id))
                     .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?,
-            )
-            .send()
-            .await
-            .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?;
+            ),
+        )
+        .send()
+        .await
+        .map_err(|e| GatewayErrorType::StatusError(e.to_string()))?;
 
         if response.status().is_success() {
             let body = response
@@ -243,7 +252,8 @@ impl Gateway for ArweaveGateway {
         }
     }
 
-    async fn gql_tx(&self, tx_id: &String) -> Result<GatewayTx, String> {
+    async fn gql_tx(&self, tx_id: &str) -> Result<GatewayTx, String> {
+        let _span = trace::span("gateway gql_tx", SpanKind::Client);
         let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
         let graphql_url = config.graphql_url;
         let client = Client::new();
@@ -293,8 +303,7 @@ impl Gateway for ArweaveGateway {
         let query_string = serde_json::to_string(&query)
             .map_err(|e| GatewayErrorType::GraphQLError(e.to_string()))?;
 
-        let response = client
-            .post(format!("{}/graphql", graphql_url))
+        let response = propagate(client.post(format!("{}/graphql", graphql_url)))
             .header("Content-Type", "application/json")
             .body(query_string)
             .send()
@@ -307,7 +316,7 @@ impl Gateway for ArweaveGateway {
                 .await
                 .map_err(|e| GatewayErrorType::JsonParseError(e.to_string()))?;
 
-            if let Some(edge) = body.data.transactions.edges.get(0) {
+            if let Some(edge) = body.data.transactions.edges.first() {
                 Ok(edge.node.clone())
             } else {
                 Err("Transaction not found".to_string())
@@ -166,7 +166,9 @@ pub async fn migrate_to_local() -> io::Result<()> {
                 .collect();
             join_all(save_handles).await;
         } else {
-            data_store.logger.error(format!("Error fetching messages"));
+            data_store
+                .logger
+                .error("Error fetching messages".to_string());
         }
     }
 
@@ -282,7 +284,9 @@ pub async fn migrate_to_local() -> io::Result<()> {
                 .collect();
             join_all(save_handles).await;
         } else {
-            data_store.logger.error(format!("Error fetching processes"));
+            data_store
+                .logger
+                .error("Error fetching processes".to_string());
         }
     }
 
@@ -1,14 +1,22 @@
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use async_trait::async_trait;
-use rocksdb::{Options, DB};
+use rocksdb::{Options, WriteBatch, DB};
 use tokio::time::{sleep, Duration};
 
 use super::super::super::core::dal::{
-    DataStore, Log, Message, PaginatedMessages, Process, StoreErrorType,
+    ChainSnapshot, DataStore, ExportItem, InclusionProof, Log, Message, PaginatedMessages, Process,
+    ProcessStats, StoreErrorType, Tag,
 };
 use super::super::super::SuLog;
 
+// index keys read at a time while looking for tagged messages
+const TAG_SCAN_PAGE: usize = 500;
+
+// processes recounted per write batch by backfill_process_stats
+const BACKFILL_PAGE: usize = 100;
+
 pub struct LocalStoreClient {
     _logger: Arc<dyn Log>,
     /*
@@ -54,7 +62,7 @@ impl LocalStoreClient {
 
         let cfs = LocalStoreClient::generate_cfs();
 
-        let index_db = match DB::open_cf_with_opts(&opts_index, &index_db_dir, cfs) {
+        let index_db = match DB::open_cf_with_opts(&opts_index, index_db_dir, cfs) {
             Ok(_db) => _db,
             Err(e) => panic!("failed to open cf with options: {}", e),
         };
@@ -92,7 +100,7 @@ impl LocalStoreClient {
         let cfs = LocalStoreClient::generate_cfs();
 
         let index_db =
-            match DB::open_cf_with_opts_for_read_only(&opts_index, &index_db_dir, cfs, false) {
+            match DB::open_cf_with_opts_for_read_only(&opts_index, index_db_dir, cfs, false) {
                 Ok(_db) => _db,
                 Err(e) => panic!("failed to open cf with options: {}", e),
             };
@@ -148,6 +156,9 @@ impl LocalStoreClient {
             ("message_ordering".to_string(), opts_index.clone()),
             ("deep_hash".to_string(), opts_index.clone()),
             ("deep_hash_version".to_string(), opts_index.clone()),
+            ("process_stats".to_string(), opts_index.clone()),
+            ("chain_snapshot".to_string(), opts_index.clone()),
+            ("inclusion_proof".to_string(), opts_index.clone()),
         ]
     }
 
@@ -199,18 +210,131 @@ impl LocalStoreClient {
         ))
     }
 
-    fn deep_hash_key(
-        &self,
-        process_id: &String,
-        deep_hash: &String,
-    ) -> Result<String, StoreErrorType> {
+    fn deep_hash_key(&self, process_id: &str, deep_hash: &str) -> Result<String, StoreErrorType> {
         Ok(format!("deep_hash:{}:{}", process_id, deep_hash))
     }
 
-    fn deep_hash_version_key(&self, process_id: &String) -> Result<String, StoreErrorType> {
+    fn deep_hash_version_key(&self, process_id: &str) -> Result<String, StoreErrorType> {
         Ok(format!("deep_hash_version:{}", process_id))
     }
 
+    fn process_stats_key(&self, process_id: &str) -> String {
+        format!("process_stats:{}", process_id)
+    }
+
+    fn chain_snapshot_key(&self, process_id: &str, nonce: i32) -> String {
+        format!("chain_snapshot:{}:{:010}", process_id, nonce)
+    }
+
+    fn inclusion_proof_key(&self, item_id: &str) -> String {
+        format!("inclusion_proof:{}", item_id)
+    }
+
+    /*
+      Read modify write of the per process counters, the
+      new value goes in the write batch of the save it
+      counts. Saves for a process are serialized by the
+      scheduler lock so there is no concurrent update.
+    */
+    // empty stats for a process with none saved yet
+    fn stored_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType> {
+        let cf = self.index_db.cf_handle("process_stats").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'process_stats' not found".to_string())
+        })?;
+
+        let stats_key = self.process_stats_key(process_id);
+        match self.index_db.get_cf(cf, stats_key.as_bytes())? {
+            Some(value) => Ok(serde_json::from_slice::<ProcessStats>(&value)?),
+            None => Ok(ProcessStats {
+                process_id: process_id.to_string(),
+                message_count: 0,
+                byte_count: 0,
+                last_nonce: None,
+            }),
+        }
+    }
+
+    fn put_process_stats(
+        &self,
+        batch: &mut WriteBatch,
+        stats: &ProcessStats,
+    ) -> Result<(), StoreErrorType> {
+        let cf = self.index_db.cf_handle("process_stats").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'process_stats' not found".to_string())
+        })?;
+
+        let stats_key = self.process_stats_key(&stats.process_id);
+        batch.put_cf(cf, stats_key.as_bytes(), serde_json::to_vec(stats)?);
+        Ok(())
+    }
+
+    /*
+      The stats of a process counted from its index
+      entries and the size of each bundle, rather than
+      kept up as messages are saved
+    */
+    async fn count_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType> {
+        let cf = self.index_db.cf_handle("message_ordering").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'message_ordering' not found".to_string())
+        })?;
+
+        let mut stats = ProcessStats {
+            process_id: process_id.to_string(),
+            message_count: 0,
+            byte_count: 0,
+            last_nonce: self.get_process(process_id).await?.nonce().ok(),
+        };
+
+        let prefix = format!("message_ordering:{}:", process_id);
+        for item in self.index_db.prefix_iterator_cf(cf, prefix.as_bytes()) {
+            let (key, assignment_id) = item?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let key_str = String::from_utf8(key.to_vec())?;
+            let assignment_id = String::from_utf8(assignment_id.to_vec())?;
+
+            let assignment_key = self.msg_assignment_key(&assignment_id);
+            if let Some(bundle) = self.file_db.get(assignment_key.as_bytes())? {
+                stats.byte_count += bundle.len() as i64;
+            }
+            stats.message_count += 1;
+            // keys run in epoch and nonce order, the last one holds the latest nonce
+            if let Some(nonce) = key_str.split(':').nth(3) {
+                stats.last_nonce = Some(nonce.parse::<i32>()?);
+            }
+        }
+        Ok(stats)
+    }
+
+    /*
+      Recount the stats of every process, for an index
+      written before the counters were kept or after they
+      drifted. Each page of processes is written in one
+      batch, run it with the su stopped so no save lands
+      between a count and its write. Returns how many
+      processes were counted.
+    */
+    pub async fn backfill_process_stats(&self) -> Result<usize, StoreErrorType> {
+        let mut after = String::new();
+        let mut counted = 0;
+        loop {
+            let ids = self.get_process_ids_after(&after, BACKFILL_PAGE).await?;
+            let mut batch = WriteBatch::default();
+            for process_id in ids.iter() {
+                let stats = self.count_process_stats(process_id).await?;
+                self.put_process_stats(&mut batch, &stats)?;
+            }
+            self.index_db.write(batch)?;
+            counted += ids.len();
+
+            match ids.last() {
+                Some(last) if ids.len() == BACKFILL_PAGE => after = last.clone(),
+                _ => return Ok(counted),
+            }
+        }
+    }
+
     /*
       This is the core method of this program used
       for querying message ranges for the /processid
@@ -278,14 +402,11 @@ impl LocalStoreClient {
             paginated_keys.push((key_str.clone(), assignment_id));
             count += 1;
 
-            match limit {
-                Some(actual_limit) => {
-                    if count >= *actual_limit {
-                        has_next_page = true;
-                        break;
-                    }
+            if let Some(actual_limit) = limit {
+                if count >= *actual_limit {
+                    has_next_page = true;
+                    break;
                 }
-                _ => (),
             };
         }
 
@@ -354,14 +475,11 @@ impl LocalStoreClient {
             paginated_keys.push((key_str.clone(), assignment_id));
             count += 1;
 
-            match limit {
-                Some(actual_limit) => {
-                    if count >= *actual_limit {
-                        has_next_page = true;
-                        break;
-                    }
+            if let Some(actual_limit) = limit {
+                if count >= *actual_limit {
+                    has_next_page = true;
+                    break;
                 }
-                _ => (),
             };
         }
 
@@ -375,31 +493,39 @@ impl DataStore for LocalStoreClient {
       Index and save a process, currently we dont
       use the process index for anything but building
       it here to remain consistent with how messages
-      are saved
+      are saved. The bundle is written first and the
+      index entries and stats go in one write batch.
     */
     fn save_process(&self, process: &Process, bundle: &[u8]) -> Result<String, StoreErrorType> {
         let process_id = &process.process.process_id;
         let assignment_id = process.assignment_id()?;
 
+        let assignment_key = self.proc_assignment_key(&assignment_id);
+        self.file_db.put(assignment_key.as_bytes(), bundle)?;
+
+        let mut batch = WriteBatch::default();
+
         let cf = self.index_db.cf_handle("process").ok_or_else(|| {
             StoreErrorType::DatabaseError("Column family 'process' not found".to_string())
         })?;
 
         let process_key = self.proc_composite_key(process_id, &assignment_id);
-        self.index_db
-            .put_cf(cf, process_key.as_bytes(), assignment_id.as_bytes())?;
+        batch.put_cf(cf, process_key.as_bytes(), assignment_id.as_bytes());
 
         let cf = self.index_db.cf_handle("process_ordering").ok_or_else(|| {
             StoreErrorType::DatabaseError("Column family 'process_ordering' not found".to_string())
         })?;
 
         let process_order_key = self.proc_order_key(process)?;
-        self.index_db
-            .put_cf(cf, process_order_key.as_bytes(), assignment_id.as_bytes())?;
+        batch.put_cf(cf, process_order_key.as_bytes(), assignment_id.as_bytes());
 
-        let assignment_key = self.proc_assignment_key(&assignment_id);
-        self.file_db.put(assignment_key.as_bytes(), bundle)?;
+        let mut stats = self.stored_process_stats(process_id)?;
+        if let Ok(nonce) = process.nonce() {
+            stats.last_nonce = Some(nonce);
+        }
+        self.put_process_stats(&mut batch, &stats)?;
 
+        self.index_db.write(batch)?;
         Ok("Process saved".to_string())
     }
 
@@ -407,7 +533,9 @@ impl DataStore for LocalStoreClient {
       Index and save a Message, the index is used
       for fast message list and individual message
       retrieval. The file_db key value is used
-      to store and retrieve the actual bundle.
+      to store and retrieve the actual bundle. It is
+      saved as a batch of one so its index entries and
+      the process stats are written together.
     */
     async fn save_message(
         &self,
@@ -415,54 +543,88 @@ impl DataStore for LocalStoreClient {
         bundle_in: &[u8],
         deep_hash: Option<&String>,
     ) -> Result<String, StoreErrorType> {
-        let message_id = message.message_id()?;
-        let assignment_id = message.assignment_id()?;
-
-        let cf = self.index_db.cf_handle("message").ok_or_else(|| {
-            StoreErrorType::DatabaseError("Column family 'message' not found".to_string())
-        })?;
-
-        let message_composite_key = self.msg_composite_key(&message_id, &assignment_id);
-        self.index_db.put_cf(
-            cf,
-            message_composite_key.as_bytes(),
-            assignment_id.as_bytes(),
-        )?;
-
-        let cf = self.index_db.cf_handle("message_ordering").ok_or_else(|| {
-            StoreErrorType::DatabaseError("Column family 'message_ordering' not found".to_string())
-        })?;
+        self.save_messages(&[(message, bundle_in, deep_hash)])
+            .await?;
+        Ok("Message saved".to_string())
+    }
 
-        let msg_order_key = self.msg_order_key(message)?;
-        self.index_db
-            .put_cf(cf, msg_order_key.as_bytes(), assignment_id.as_bytes())?;
+    /*
+      The bundles are written first, nothing reaches them
+      until the index entries are there. Those and the
+      process stats all go in one write batch so the whole
+      batch of messages shows up at once or not at all.
+    */
+    async fn save_messages(
+        &self,
+        messages: &[(&Message, &[u8], Option<&String>)],
+    ) -> Result<(), StoreErrorType> {
+        let cf_handle = |name: &str| {
+            self.index_db.cf_handle(name).ok_or_else(|| {
+                StoreErrorType::DatabaseError(format!("Column family '{}' not found", name))
+            })
+        };
+        let message_cf = cf_handle("message")?;
+        let ordering_cf = cf_handle("message_ordering")?;
+        let deep_hash_cf = cf_handle("deep_hash")?;
 
-        let assignment_key = self.msg_assignment_key(&assignment_id);
-        self.file_db.put(assignment_key.as_bytes(), bundle_in)?;
+        let mut batch = WriteBatch::default();
+        let mut stats: BTreeMap<String, ProcessStats> = BTreeMap::new();
+        for (message, bundle_in, deep_hash) in messages {
+            let message_id = message.message_id()?;
+            let assignment_id = message.assignment_id()?;
+            let process_id = message.process_id()?;
 
-        let cf = self.index_db.cf_handle("deep_hash").ok_or_else(|| {
-            StoreErrorType::DatabaseError("Column family 'message_ordering' not found".to_string())
-        })?;
-
-        match deep_hash {
-            Some(dh) => {
-                let deep_hash_key = self.deep_hash_key(&message.process_id()?, dh)?;
-                self.index_db.put_cf(
-                    cf,
+            let assignment_key = self.msg_assignment_key(&assignment_id);
+            self.file_db.put(assignment_key.as_bytes(), bundle_in)?;
+
+            let message_composite_key = self.msg_composite_key(&message_id, &assignment_id);
+            batch.put_cf(
+                message_cf,
+                message_composite_key.as_bytes(),
+                assignment_id.as_bytes(),
+            );
+            let msg_order_key = self.msg_order_key(message)?;
+            batch.put_cf(
+                ordering_cf,
+                msg_order_key.as_bytes(),
+                assignment_id.as_bytes(),
+            );
+            if let Some(dh) = deep_hash {
+                let deep_hash_key = self.deep_hash_key(&process_id, dh)?;
+                batch.put_cf(
+                    deep_hash_cf,
                     deep_hash_key.as_bytes(),
-                    message.process_id()?.as_bytes(),
-                )?;
+                    process_id.as_bytes(),
+                );
             }
-            None => (),
-        };
 
-        Ok("Message saved".to_string())
+            if !stats.contains_key(&process_id) {
+                let stored = self.stored_process_stats(&process_id)?;
+                stats.insert(process_id.clone(), stored);
+            }
+            if let Some(process_stats) = stats.get_mut(&process_id) {
+                process_stats.message_count += 1;
+                process_stats.byte_count += bundle_in.len() as i64;
+                process_stats.last_nonce = Some(message.nonce()?);
+            }
+        }
+        for process_stats in stats.values() {
+            self.put_process_stats(&mut batch, process_stats)?;
+        }
+
+        self.index_db.write(batch)?;
+        Ok(())
     }
 
     async fn get_process(&self, tx_id: &str) -> Result<Process, StoreErrorType> {
+        let process_bundle = self.get_process_bundle(tx_id).await?;
+        Ok(Process::from_bytes(process_bundle)?)
+    }
+
+    async fn get_process_bundle(&self, tx_id: &str) -> Result<Vec<u8>, StoreErrorType> {
         let assignment_key = self.proc_assignment_key(tx_id);
         if let Some(process_bundle) = self.file_db.get(assignment_key.as_bytes())? {
-            return Ok(Process::from_bytes(process_bundle)?);
+            return Ok(process_bundle);
         }
 
         /*
@@ -487,7 +649,7 @@ impl DataStore for LocalStoreClient {
             let assignment_id = String::from_utf8(assignment_id_bytes.to_vec())?;
             let assignment_key = self.proc_assignment_key(&assignment_id);
             if let Some(process_bundle) = self.file_db.get(assignment_key.as_bytes())? {
-                return Ok(Process::from_bytes(process_bundle)?);
+                return Ok(process_bundle);
             }
         }
 
@@ -531,7 +693,7 @@ impl DataStore for LocalStoreClient {
         Err(StoreErrorType::NotFound("Message not found".to_string()))
     }
 
-    fn check_existing_message(&self, message_id: &String) -> Result<(), StoreErrorType> {
+    fn check_existing_message(&self, message_id: &str) -> Result<(), StoreErrorType> {
         if let Ok(_message) = self.get_message(message_id) {
             Err(StoreErrorType::MessageExists(
                 "Message already exists".to_string(),
@@ -543,8 +705,8 @@ impl DataStore for LocalStoreClient {
 
     async fn check_existing_deep_hash(
         &self,
-        process_id: &String,
-        deep_hash: &String,
+        process_id: &str,
+        deep_hash: &str,
     ) -> Result<(), StoreErrorType> {
         let cf = self.index_db.cf_handle("deep_hash").ok_or_else(|| {
             StoreErrorType::DatabaseError("Column family 'deep_hash' not found".to_string())
@@ -564,7 +726,7 @@ impl DataStore for LocalStoreClient {
         }
     }
 
-    async fn get_deephash_version(&self, process_id: &String) -> Result<String, StoreErrorType> {
+    async fn get_deephash_version(&self, process_id: &str) -> Result<String, StoreErrorType> {
         let cf = self
             .index_db
             .cf_handle("deep_hash_version")
@@ -594,8 +756,8 @@ impl DataStore for LocalStoreClient {
 
     async fn save_deephash_version(
         &self,
-        process_id: &String,
-        version: &String,
+        process_id: &str,
+        version: &str,
     ) -> Result<(), StoreErrorType> {
         let cf = self
             .index_db
@@ -612,11 +774,7 @@ impl DataStore for LocalStoreClient {
         Ok(())
     }
 
-    async fn save_deephash(
-        &self,
-        process_id: &String,
-        deep_hash: &String,
-    ) -> Result<(), StoreErrorType> {
+    async fn save_deephash(&self, process_id: &str, deep_hash: &str) -> Result<(), StoreErrorType> {
         let cf = self.index_db.cf_handle("deep_hash").ok_or_else(|| {
             StoreErrorType::DatabaseError("Column family 'message_ordering' not found".to_string())
         })?;
@@ -724,13 +882,7 @@ impl DataStore for LocalStoreClient {
                 */
                 let include_process = process_in.assignment.is_some()
                     && match from_nonce {
-                        Some(ref _from_nonce) => {
-                            if _from_nonce.parse::<i32>()? == -1 {
-                                true
-                            } else {
-                                false
-                            }
-                        }
+                        Some(ref _from_nonce) => _from_nonce.parse::<i32>()? == -1,
                         /*
                           No 'from' means it's the first page
                         */
@@ -853,6 +1005,73 @@ impl DataStore for LocalStoreClient {
         Ok((bundles, has_next_page))
     }
 
+    /*
+      The index holds no tags, so the keys are read a page
+      at a time and each message checked until a page of
+      matches is found or the range runs out
+    */
+    async fn get_tagged_messages(
+        &self,
+        process: &Process,
+        tags: &[Tag],
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        let limit_val = limit.unwrap_or(100) as usize;
+        let mut messages = Vec::new();
+
+        // the process leads the first page like in get_messages, if it has the tags
+        let first_page = matches!(from_nonce.as_deref(), None | Some("-1"));
+        if first_page && process.assignment.is_some() {
+            let process_message = Message::from_process(process.clone())?;
+            if process_message.has_tags(tags) {
+                messages.push(process_message);
+            }
+        }
+
+        let mut from = from_nonce.clone();
+        loop {
+            let (paginated_keys, more) = self
+                .fetch_message_range_nonce(
+                    &process.process.process_id,
+                    &from,
+                    to_nonce,
+                    &Some(TAG_SCAN_PAGE),
+                )
+                .await?;
+
+            for (key, assignment_id) in paginated_keys {
+                // the next range starts after the zero padded nonce of this key
+                from = key.split(':').nth(3).map(|nonce| nonce.to_string());
+
+                let assignment_key = self.msg_assignment_key(&assignment_id);
+                for _ in 0..10 {
+                    if let Some(message_data) = self.file_db.get(assignment_key.as_bytes())? {
+                        let message: Message = Message::from_bytes(message_data)?;
+                        if message.has_tags(tags) {
+                            if messages.len() == limit_val {
+                                return Ok(PaginatedMessages::from_messages(
+                                    messages, true, "nonce",
+                                )?);
+                            }
+                            messages.push(message);
+                        }
+                        break;
+                    } else {
+                        sleep(Duration::from_millis(100)).await;
+                    }
+                }
+            }
+
+            if !more {
+                break;
+            }
+        }
+
+        Ok(PaginatedMessages::from_messages(messages, false, "nonce")?)
+    }
+
     /*
       Retrieve the latest message for a process.
       Currently this is only run once for a process
@@ -868,7 +1087,7 @@ impl DataStore for LocalStoreClient {
             .fetch_message_range(&process_id.to_string(), &None, &None, &None)
             .await?;
 
-        if paginated_keys.len() < 1 {
+        if paginated_keys.is_empty() {
             return Ok(None);
         }
 
@@ -881,4 +1100,257 @@ impl DataStore for LocalStoreClient {
 
         Ok(Some(latest_message))
     }
+
+    async fn get_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType> {
+        let cf = self.index_db.cf_handle("process_stats").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'process_stats' not found".to_string())
+        })?;
+
+        let stats_key = self.process_stats_key(process_id);
+        match self.index_db.get_cf(cf, stats_key.as_bytes())? {
+            Some(value) => Ok(serde_json::from_slice::<ProcessStats>(&value)?),
+            None => Err(StoreErrorType::NotFound(
+                "Process stats not found".to_string(),
+            )),
+        }
+    }
+
+    async fn get_processes_stats(
+        &self,
+        process_ids: &[String],
+    ) -> Result<Vec<ProcessStats>, StoreErrorType> {
+        let cf = self.index_db.cf_handle("process_stats").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'process_stats' not found".to_string())
+        })?;
+
+        // point reads on the same column family, there is no query to batch them into
+        let mut stats = vec![];
+        for process_id in process_ids {
+            let stats_key = self.process_stats_key(process_id);
+            if let Some(value) = self.index_db.get_cf(cf, stats_key.as_bytes())? {
+                stats.push(serde_json::from_slice::<ProcessStats>(&value)?);
+            }
+        }
+        Ok(stats)
+    }
+
+    async fn save_chain_snapshot(&self, snapshot: &ChainSnapshot) -> Result<(), StoreErrorType> {
+        let cf = self.index_db.cf_handle("chain_snapshot").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'chain_snapshot' not found".to_string())
+        })?;
+
+        let snapshot_key = self.chain_snapshot_key(&snapshot.process_id, snapshot.nonce);
+        self.index_db
+            .put_cf(cf, snapshot_key.as_bytes(), serde_json::to_vec(snapshot)?)?;
+        Ok(())
+    }
+
+    /*
+      Snapshots are sparse, one every few thousand nonces,
+      so walking the process prefix in nonce order is cheap
+    */
+    async fn get_chain_snapshot(
+        &self,
+        process_id: &str,
+        max_nonce: Option<i32>,
+    ) -> Result<ChainSnapshot, StoreErrorType> {
+        let cf = self.index_db.cf_handle("chain_snapshot").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'chain_snapshot' not found".to_string())
+        })?;
+
+        let prefix = format!("chain_snapshot:{}:", process_id);
+        let mut latest = None;
+        for item in self.index_db.prefix_iterator_cf(cf, prefix.as_bytes()) {
+            let (key, value) = item?;
+            if !key.starts_with(prefix.as_bytes()) {
+                break;
+            }
+            let snapshot = serde_json::from_slice::<ChainSnapshot>(&value)?;
+            if max_nonce.is_some_and(|max_nonce| snapshot.nonce > max_nonce) {
+                break;
+            }
+            latest = Some(snapshot);
+        }
+
+        latest.ok_or(StoreErrorType::NotFound(
+            "Chain snapshot not found".to_string(),
+        ))
+    }
+
+    async fn save_inclusion_proofs(
+        &self,
+        proofs: &[InclusionProof],
+    ) -> Result<(), StoreErrorType> {
+        let cf = self.index_db.cf_handle("inclusion_proof").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'inclusion_proof' not found".to_string())
+        })?;
+
+        for proof in proofs {
+            let proof_key = self.inclusion_proof_key(&proof.item_id);
+            self.index_db
+                .put_cf(cf, proof_key.as_bytes(), serde_json::to_vec(proof)?)?;
+        }
+        Ok(())
+    }
+
+    async fn get_inclusion_proof(&self, item_id: &str) -> Result<InclusionProof, StoreErrorType> {
+        let cf = self.index_db.cf_handle("inclusion_proof").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'inclusion_proof' not found".to_string())
+        })?;
+
+        let proof_key = self.inclusion_proof_key(item_id);
+        match self.index_db.get_cf(cf, proof_key.as_bytes())? {
+            Some(value) => Ok(serde_json::from_slice(&value)?),
+            None => Err(StoreErrorType::NotFound(
+                "Inclusion proof not found".to_string(),
+            )),
+        }
+    }
+
+    /*
+      The ordering keys are per process, so every call
+      reads all of them, keeping the first limit past the
+      position, and then the bundles of those
+    */
+    async fn get_items_after(
+        &self,
+        after: (i64, &str),
+        limit: usize,
+    ) -> Result<Vec<ExportItem>, StoreErrorType> {
+        let (after_timestamp, after_id) = after;
+        // (timestamp, id) to (type, process id, nonce, assignment id)
+        let mut page: BTreeMap<(i64, String), (&'static str, String, i32, String)> =
+            BTreeMap::new();
+
+        for (cf_name, item_type) in [
+            ("process_ordering", "Process"),
+            ("message_ordering", "Message"),
+        ] {
+            let cf = self.index_db.cf_handle(cf_name).ok_or_else(|| {
+                StoreErrorType::DatabaseError(format!("Column family '{}' not found", cf_name))
+            })?;
+            for item in self.index_db.iterator_cf(cf, rocksdb::IteratorMode::Start) {
+                let (key, assignment_id_bytes) = item?;
+                let key_str = String::from_utf8(key.to_vec())?;
+                let parts: Vec<&str> = key_str.split(':').collect();
+                if parts.len() < 6 {
+                    continue;
+                }
+                let timestamp = parts[4].parse::<i64>().unwrap_or(0);
+                let assignment_id = String::from_utf8(assignment_id_bytes.to_vec())?;
+                let id = match item_type {
+                    "Process" => parts[1].to_string(),
+                    _ => assignment_id.clone(),
+                };
+                if (timestamp, id.as_str()) <= (after_timestamp, after_id) {
+                    continue;
+                }
+                let nonce = parts[3].parse::<i32>().unwrap_or(0);
+                page.insert(
+                    (timestamp, id),
+                    (item_type, parts[1].to_string(), nonce, assignment_id),
+                );
+                if page.len() > limit {
+                    page.pop_last();
+                }
+            }
+        }
+
+        let mut items = vec![];
+        for ((timestamp, id), (item_type, process_id, nonce, assignment_id)) in page {
+            let assignment_key = match item_type {
+                "Process" => self.proc_assignment_key(&assignment_id),
+                _ => self.msg_assignment_key(&assignment_id),
+            };
+            // the ordering key is written before the bundle
+            let mut bundle = None;
+            for _ in 0..10 {
+                bundle = self.file_db.get(assignment_key.as_bytes())?;
+                if bundle.is_some() {
+                    break;
+                }
+                sleep(Duration::from_millis(100)).await;
+            }
+            let bundle = bundle.ok_or_else(|| {
+                StoreErrorType::NotFound(format!("Bundle of {} not found", assignment_id))
+            })?;
+            items.push(ExportItem {
+                item_type,
+                process_id,
+                id,
+                timestamp,
+                nonce,
+                bundle,
+            });
+        }
+        Ok(items)
+    }
+
+    /*
+      The process index is keyed process:{process_id}:{assignment_id}
+      so it is already in process id order, ';' sorts right
+      after ':' and skips every key of the after process
+    */
+    async fn get_process_ids_after(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreErrorType> {
+        let cf = self.index_db.cf_handle("process").ok_or_else(|| {
+            StoreErrorType::DatabaseError("Column family 'process' not found".to_string())
+        })?;
+        let start = match after {
+            "" => "process:".to_string(),
+            after => format!("process:{};", after),
+        };
+        let mode = rocksdb::IteratorMode::From(start.as_bytes(), rocksdb::Direction::Forward);
+
+        let mut ids: Vec<String> = vec![];
+        for item in self.index_db.iterator_cf(cf, mode) {
+            let (key, _) = item?;
+            let key_str = String::from_utf8(key.to_vec())?;
+            let process_id = match key_str.strip_prefix("process:") {
+                Some(rest) => rest.split(':').next().unwrap_or_default(),
+                None => break,
+            };
+            if ids.last().map(String::as_str) != Some(process_id) {
+                if ids.len() == limit {
+                    break;
+                }
+                ids.push(process_id.to_string());
+            }
+        }
+        Ok(ids)
+    }
+
+    /*
+      There is no index on spawn time here, a filtered page
+      reads processes in id order until it has limit of them
+    */
+    async fn list_processes(
+        &self,
+        spawned_after: Option<i64>,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, StoreErrorType> {
+        let mut listed = vec![];
+        let mut cursor = after.to_string();
+        loop {
+            let ids = self.get_process_ids_after(&cursor, limit).await?;
+            let exhausted = ids.len() < limit || ids.is_empty();
+            for process_id in ids {
+                let spawned = self.get_process(&process_id).await?.process.timestamp;
+                cursor = process_id;
+                if spawned_after.map_or(true, |after| spawned > after) {
+                    listed.push((cursor.clone(), spawned));
+                    if listed.len() == limit {
+                        return Ok(listed);
+                    }
+                }
+            }
+            if exhausted {
+                return Ok(listed);
+            }
+        }
+    }
 }
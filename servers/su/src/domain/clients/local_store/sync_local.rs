@@ -62,10 +62,11 @@ pub async fn sync_local_drives(interval: u64) -> io::Result<()> {
               in the new db so we will start from the beginning
             */
             let latest_message_synced = match process_keys_sync.contains(&process_id) {
-                true => match write_sync_store.get_latest_message(&process_id).await {
-                    Ok(m) => m,
-                    Err(_) => None,
-                },
+                true => write_sync_store
+                    .get_latest_message(&process_id)
+                    .await
+                    .ok()
+                    .flatten(),
                 false => {
                     /*
                       Read the existing process information from the running
@@ -97,10 +98,8 @@ pub async fn sync_local_drives(interval: u64) -> io::Result<()> {
 
             let process = write_sync_store.get_process(&process_id).await.unwrap();
 
-            let mut from = match latest_message_synced {
-                Some(message) => Some(message.timestamp().unwrap().to_string()),
-                None => None,
-            };
+            let mut from =
+                latest_message_synced.map(|message| message.timestamp().unwrap().to_string());
 
             let mut has_next_page = true;
 
@@ -110,7 +109,7 @@ pub async fn sync_local_drives(interval: u64) -> io::Result<()> {
                     .await
                     .unwrap();
 
-                if messages_fetch.edges.len() >= 1 {
+                if !messages_fetch.edges.is_empty() {
                     if let Some(m) = &messages_fetch.edges[0].node.message {
                         if let Some(type_tag) = m.tags.iter().find(|t| t.name == "Type") {
                             if type_tag.value == "Process" {
@@ -120,7 +119,7 @@ pub async fn sync_local_drives(interval: u64) -> io::Result<()> {
                     }
                 }
 
-                if messages_fetch.edges.len() > 0 {
+                if !messages_fetch.edges.is_empty() {
                     println!(
                         "Syncing {} messages for process {} ...",
                         messages_fetch.edges.len(),
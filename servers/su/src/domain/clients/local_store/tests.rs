@@ -1,4 +1,5 @@
 #[cfg(test)]
+#[allow(clippy::module_inception)]
 mod tests {
     use super::super::store::LocalStoreClient;
     use crate::domain::core::dal::{DataStore, Message, Process, StoreErrorType};
@@ -88,7 +89,7 @@ mod tests {
         // Save all messages
         for bundle in message_bundles.iter() {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         // Retrieve messages and check nonce order and continuity
@@ -126,7 +127,7 @@ mod tests {
 
         for bundle in message_bundles.iter() {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         // Case 1: Default parameters
@@ -220,7 +221,7 @@ mod tests {
         // Save half of the messages
         for bundle in message_bundles.iter().take(message_bundles.len() / 2) {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         let (process_bundle_2, message_bundles_2) = bundle_list_2();
@@ -230,19 +231,19 @@ mod tests {
         // Save half of the messages of next process
         for bundle in message_bundles_2.iter().take(message_bundles_2.len() / 2) {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         // Save second half of messages for the first process
         for bundle in message_bundles.iter().skip(message_bundles.len() / 2) {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         // Save second half of messages for the second process
         for bundle in message_bundles_2.iter().skip(message_bundles_2.len() / 2) {
             let test_message = Message::from_bytes(bundle.clone())?;
-            client.save_message(&test_message, &bundle, None).await?;
+            client.save_message(&test_message, bundle, None).await?;
         }
 
         // Retrieve messages and check length, nonce order, and continuity
@@ -276,17 +277,157 @@ mod tests {
         let test_db = TestDb::new(6);
         let client = LocalStoreClient::new(&test_db.file_db_path(), &test_db.index_db_path())?;
 
-        client
-            .save_deephash_version(&"pid".to_string(), &"1.0".to_string())
-            .await?;
+        client.save_deephash_version("pid", "1.0").await?;
 
-        let dhv = client.get_deephash_version(&"pid".to_string()).await?;
+        let dhv = client.get_deephash_version("pid").await?;
 
         assert_eq!(dhv, "1.0".to_string());
 
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_process_stats() -> Result<(), StoreErrorType> {
+        let test_db = TestDb::new(7);
+        let client = LocalStoreClient::new(&test_db.file_db_path(), &test_db.index_db_path())?;
+
+        let (process_bundle, message_bundles) = bundle_list();
+        let test_process = Process::from_bytes(process_bundle.clone())?;
+        client.save_process(&test_process, &process_bundle)?;
+
+        let mut total_bytes = 0;
+        let mut last_nonce = None;
+        for bundle in message_bundles.iter() {
+            let test_message = Message::from_bytes(bundle.clone())?;
+            client.save_message(&test_message, bundle, None).await?;
+            total_bytes += bundle.len() as i64;
+            last_nonce = Some(test_message.nonce()?);
+        }
+
+        let stats = client
+            .get_process_stats(&test_process.process.process_id)
+            .await?;
+
+        assert_eq!(stats.message_count, message_bundles.len() as i64);
+        assert_eq!(stats.byte_count, total_bytes);
+        assert_eq!(stats.last_nonce, last_nonce);
+
+        // an index from before the counters were kept is recounted
+        let cf = client.index_db.cf_handle("process_stats").unwrap();
+        let stats_key = format!("process_stats:{}", test_process.process.process_id);
+        client.index_db.delete_cf(cf, stats_key.as_bytes())?;
+        assert_eq!(client.backfill_process_stats().await?, 1);
+
+        let backfilled = client
+            .get_process_stats(&test_process.process.process_id)
+            .await?;
+        assert_eq!(backfilled.message_count, stats.message_count);
+        assert_eq!(backfilled.byte_count, stats.byte_count);
+        assert_eq!(backfilled.last_nonce, stats.last_nonce);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_save_messages_together() -> Result<(), StoreErrorType> {
+        let test_db = TestDb::new(10);
+        let client = LocalStoreClient::new(&test_db.file_db_path(), &test_db.index_db_path())?;
+
+        let (process_bundle, message_bundles) = bundle_list();
+        let test_process = Process::from_bytes(process_bundle.clone())?;
+        client.save_process(&test_process, &process_bundle)?;
+
+        let test_messages = message_bundles
+            .iter()
+            .map(|bundle| Message::from_bytes(bundle.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        let batch: Vec<(&Message, &[u8], Option<&String>)> = test_messages
+            .iter()
+            .zip(message_bundles.iter())
+            .map(|(message, bundle)| (message, bundle.as_slice(), None))
+            .collect();
+        client.save_messages(&batch).await?;
+
+        let result = client
+            .get_messages(&test_process, &None, &None, &None, &None, &None)
+            .await?;
+        assert_eq!(result.edges.len(), message_bundles.len());
+
+        let stats = client
+            .get_process_stats(&test_process.process.process_id)
+            .await?;
+        assert_eq!(stats.message_count, message_bundles.len() as i64);
+        assert_eq!(
+            stats.last_nonce,
+            Some(test_messages.last().unwrap().nonce()?)
+        );
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_items_after_across_procs() -> Result<(), StoreErrorType> {
+        let test_db = TestDb::new(8);
+        let client = LocalStoreClient::new(&test_db.file_db_path(), &test_db.index_db_path())?;
+
+        let mut saved = 0;
+        for (process_bundle, message_bundles) in [bundle_list(), bundle_list_2()] {
+            let test_process = Process::from_bytes(process_bundle.clone())?;
+            client.save_process(&test_process, &process_bundle)?;
+            for bundle in message_bundles.iter() {
+                let test_message = Message::from_bytes(bundle.clone())?;
+                client.save_message(&test_message, bundle, None).await?;
+            }
+            saved += message_bundles.len() + 1;
+        }
+
+        // paged through, every item comes once in (timestamp, id) order
+        let mut positions: Vec<(i64, String)> = vec![];
+        let mut after = (0, "".to_string());
+        loop {
+            let page = client.get_items_after((after.0, &after.1), 3).await?;
+            if page.is_empty() {
+                break;
+            }
+            for item in page {
+                assert!((item.timestamp, item.id.clone()) > after);
+                after = (item.timestamp, item.id);
+                positions.push(after.clone());
+            }
+        }
+        assert_eq!(positions.len(), saved);
+
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_process_ids_after() -> Result<(), StoreErrorType> {
+        let test_db = TestDb::new(9);
+        let client = LocalStoreClient::new(&test_db.file_db_path(), &test_db.index_db_path())?;
+
+        let mut process_ids = vec![];
+        for (process_bundle, _) in [bundle_list(), bundle_list_2()] {
+            let test_process = Process::from_bytes(process_bundle.clone())?;
+            client.save_process(&test_process, &process_bundle)?;
+            process_ids.push(test_process.process.process_id.clone());
+        }
+        process_ids.sort();
+
+        assert_eq!(client.get_process_ids_after("", 10).await?, process_ids);
+        let first = client.get_process_ids_after("", 1).await?;
+        assert_eq!(first, process_ids[..1]);
+        assert_eq!(
+            client.get_process_ids_after(&first[0], 10).await?,
+            process_ids[1..]
+        );
+        assert!(client
+            .get_process_ids_after(&process_ids[1], 10)
+            .await?
+            .is_empty());
+
+        Ok(())
+    }
+
     /*
       Helper functions to create test data using
       base64_url encoded bundles
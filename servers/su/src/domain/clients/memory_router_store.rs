@@ -0,0 +1,644 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Bound::{Excluded, Unbounded};
+use std::sync::Mutex;
+
+use super::admin_audit::now_millis;
+use crate::domain::core::dal::{
+    Assignment, ProcessScheduler, RouterDataStore, Scheduler, StoreErrorType,
+};
+
+/*
+  A router data store that only lives in memory, for
+  integration tests and small setups that don't want a
+  database. Everything is lost on restart, so outside of
+  tests pair it with ROUTING_STRATEGY=consistent-hash,
+  which places a process the same way again without its
+  process_schedulers row.
+*/
+pub struct MemoryRouterStore {
+    state: Mutex<MemoryRouterState>,
+}
+
+#[derive(Default)]
+struct MemoryRouterState {
+    schedulers: BTreeMap<i32, Scheduler>,
+    process_schedulers: BTreeMap<i32, ProcessScheduler>,
+    // process id to its process_schedulers row id
+    process_index: HashMap<String, i32>,
+    // process_schedulers row id to when assign_process saved it
+    spawned_at: HashMap<i32, i64>,
+    owner_schedulers: HashMap<String, i32>,
+    next_scheduler_id: i32,
+    next_process_scheduler_id: i32,
+}
+
+fn copy_row(row: &ProcessScheduler) -> ProcessScheduler {
+    ProcessScheduler {
+        row_id: row.row_id,
+        process_id: row.process_id.clone(),
+        scheduler_row_id: row.scheduler_row_id,
+    }
+}
+
+impl MemoryRouterStore {
+    pub fn new() -> Self {
+        MemoryRouterStore {
+            state: Mutex::new(MemoryRouterState::default()),
+        }
+    }
+
+    fn state(&self) -> Result<std::sync::MutexGuard<'_, MemoryRouterState>, StoreErrorType> {
+        self.state
+            .lock()
+            .map_err(|_| StoreErrorType::DatabaseError("Router store lock poisoned".to_string()))
+    }
+}
+
+impl Default for MemoryRouterStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl RouterDataStore for MemoryRouterStore {
+    fn save_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        let mut state = self.state()?;
+        if state
+            .process_index
+            .contains_key(&process_scheduler.process_id)
+        {
+            return Ok("saved".to_string());
+        }
+
+        state.next_process_scheduler_id += 1;
+        let row_id = state.next_process_scheduler_id;
+        state
+            .process_index
+            .insert(process_scheduler.process_id.clone(), row_id);
+        state.process_schedulers.insert(
+            row_id,
+            ProcessScheduler {
+                row_id: Some(row_id),
+                process_id: process_scheduler.process_id.clone(),
+                scheduler_row_id: process_scheduler.scheduler_row_id,
+            },
+        );
+        Ok("saved".to_string())
+    }
+
+    fn get_process_scheduler(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        let state = self.state()?;
+        state
+            .process_index
+            .get(process_id_in)
+            .and_then(|row_id| state.process_schedulers.get(row_id))
+            .map(copy_row)
+            .ok_or(StoreErrorType::NotFound(
+                "Process scheduler not found".to_string(),
+            ))
+    }
+
+    // there are no replicas to lag behind
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.get_process_scheduler(process_id_in)
+    }
+
+    fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        let mut state = self.state()?;
+        if state.schedulers.values().any(|saved| saved.url == scheduler.url) {
+            return Ok("saved".to_string());
+        }
+
+        state.next_scheduler_id += 1;
+        let row_id = state.next_scheduler_id;
+        let mut saved = scheduler.clone();
+        saved.row_id = Some(row_id);
+        state.schedulers.insert(row_id, saved);
+        Ok("saved".to_string())
+    }
+
+    fn update_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        let mut state = self.state()?;
+        let row_id = scheduler.row_id.ok_or(StoreErrorType::DatabaseError(
+            "Missing id on scheduler".to_string(),
+        ))?;
+        if let Some(saved) = state.schedulers.get_mut(&row_id) {
+            *saved = Scheduler {
+                process_count: saved.process_count,
+                ..scheduler.clone()
+            };
+        }
+        Ok("updated".to_string())
+    }
+
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        if let Some(saved) = self.state()?.schedulers.get_mut(scheduler_row_id_in) {
+            saved.process_count += by;
+        }
+        Ok(())
+    }
+
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        if let Some(saved) = self.state()?.schedulers.get_mut(scheduler_row_id_in) {
+            saved.process_count = (saved.process_count - by).max(0);
+        }
+        Ok(())
+    }
+
+    fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
+        self.state()?
+            .schedulers
+            .get(row_id_in)
+            .cloned()
+            .ok_or(StoreErrorType::NotFound("Scheduler not found".to_string()))
+    }
+
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType> {
+        self.state()?
+            .schedulers
+            .values()
+            .find(|scheduler| scheduler.url == url_in)
+            .cloned()
+            .ok_or(StoreErrorType::NotFound("Scheduler not found".to_string()))
+    }
+
+    fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType> {
+        Ok(self.state()?.schedulers.values().cloned().collect())
+    }
+
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        let mut state = self.state()?;
+        let row_id = process_scheduler.row_id.ok_or(StoreErrorType::DatabaseError(
+            "Missing id on process scheduler".to_string(),
+        ))?;
+        if let Some(saved) = state.process_schedulers.get_mut(&row_id) {
+            saved.scheduler_row_id = process_scheduler.scheduler_row_id;
+        }
+        Ok("updated".to_string())
+    }
+
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        let mut state = self.state()?;
+        if let Some(deleted) = state.process_schedulers.remove(row_id_in) {
+            state.process_index.remove(&deleted.process_id);
+        }
+        state.spawned_at.remove(row_id_in);
+        Ok("deleted".to_string())
+    }
+
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        match self.state()?.schedulers.remove(row_id_in) {
+            Some(_) => Ok("deleted".to_string()),
+            None => Err(StoreErrorType::NotFound("Scheduler not found".to_string())),
+        }
+    }
+
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        let state = self.state()?;
+        Ok(state
+            .process_schedulers
+            .values()
+            .filter(|row| !state.schedulers.contains_key(&row.scheduler_row_id))
+            .map(copy_row)
+            .collect())
+    }
+
+    // a process id only ever has one row here
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        Ok(vec![])
+    }
+
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        let mut counts = BTreeMap::new();
+        for row in self.state()?.process_schedulers.values() {
+            *counts.entry(row.scheduler_row_id).or_insert(0) += 1;
+        }
+        Ok(counts.into_iter().collect())
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        Ok(self
+            .state()?
+            .process_schedulers
+            .range((Excluded(*after_row_id), Unbounded))
+            .map(|(_, row)| row)
+            .filter(|row| row.scheduler_row_id == *scheduler_row_id_in)
+            .take(limit.max(0) as usize)
+            .map(copy_row)
+            .collect())
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        Ok(self
+            .state()?
+            .process_schedulers
+            .range((Excluded(*after_row_id), Unbounded))
+            .take(limit.max(0) as usize)
+            .map(|(_, row)| copy_row(row))
+            .collect())
+    }
+
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        let state = self.state()?;
+        let mut rows: Vec<(ProcessScheduler, Option<i64>)> = state
+            .process_index
+            .iter()
+            .filter(|(process_id, _)| process_id.as_str() > after_process_id)
+            .map(|(_, row_id)| {
+                let row = &state.process_schedulers[row_id];
+                (copy_row(row), state.spawned_at.get(row_id).copied())
+            })
+            .filter(|(row, spawned)| {
+                scheduler_row_id_in.map_or(true, |id| row.scheduler_row_id == id)
+                    && spawned_after.map_or(true, |after| spawned.is_some_and(|at| at > after))
+            })
+            .collect();
+        rows.sort_by(|a, b| a.0.process_id.cmp(&b.0.process_id));
+        rows.truncate(limit.max(0) as usize);
+        Ok(rows)
+    }
+
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        let mut state = self.state()?;
+        let not_found = || StoreErrorType::NotFound("Scheduler not found".to_string());
+
+        if let Some(row_id) = state.process_index.get(process_id_in) {
+            let assigned_row_id = state.process_schedulers[row_id].scheduler_row_id;
+            let scheduler = state
+                .schedulers
+                .get(&assigned_row_id)
+                .cloned()
+                .ok_or_else(not_found)?;
+            return Ok(Assignment {
+                scheduler,
+                duplicate: true,
+            });
+        }
+
+        let scheduler = state
+            .schedulers
+            .get_mut(scheduler_row_id_in)
+            .ok_or_else(not_found)?;
+        scheduler.process_count += 1;
+        let scheduler = scheduler.clone();
+
+        state.next_process_scheduler_id += 1;
+        let row_id = state.next_process_scheduler_id;
+        state
+            .process_index
+            .insert(process_id_in.to_string(), row_id);
+        state.spawned_at.insert(row_id, now_millis());
+        state.process_schedulers.insert(
+            row_id,
+            ProcessScheduler {
+                row_id: Some(row_id),
+                process_id: process_id_in.to_string(),
+                scheduler_row_id: *scheduler_row_id_in,
+            },
+        );
+        Ok(Assignment {
+            scheduler,
+            duplicate: false,
+        })
+    }
+
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        let mut state = self.state()?;
+        let row_id = state
+            .process_index
+            .get(process_id_in)
+            .copied()
+            .filter(|row_id| state.process_schedulers[row_id].scheduler_row_id == *from_row_id)
+            .ok_or_else(|| {
+                StoreErrorType::NotFound(format!(
+                    "Process {} is not on scheduler {}",
+                    process_id_in, from_row_id
+                ))
+            })?;
+        if let Some(row) = state.process_schedulers.get_mut(&row_id) {
+            row.scheduler_row_id = *to_row_id;
+        }
+        if let Some(to) = state.schedulers.get_mut(to_row_id) {
+            to.process_count += 1;
+        }
+        if let Some(from) = state.schedulers.get_mut(from_row_id) {
+            from.process_count = (from.process_count - 1).max(0);
+        }
+        Ok(())
+    }
+
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType> {
+        self.state()?
+            .owner_schedulers
+            .get(owner_in)
+            .copied()
+            .ok_or(StoreErrorType::NotFound(
+                "Owner scheduler not found".to_string(),
+            ))
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        self.state()?
+            .owner_schedulers
+            .insert(owner_in.to_string(), *scheduler_row_id_in);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn scheduler(url: &str) -> Scheduler {
+        Scheduler {
+            row_id: None,
+            url: url.to_string(),
+            process_count: 0,
+            no_route: None,
+            wallets_to_route: None,
+            wallets_only: None,
+            priority: None,
+            large_objects: None,
+            weight: None,
+            public_url: None,
+            drain: None,
+            max_processes: None,
+            region: None,
+            tags_to_route: None,
+            wallets_policy: None,
+        }
+    }
+
+    fn row(process_id: &str, scheduler_row_id: i32) -> ProcessScheduler {
+        ProcessScheduler {
+            row_id: None,
+            process_id: process_id.to_string(),
+            scheduler_row_id,
+        }
+    }
+
+    #[test]
+    fn test_saves_are_unique_like_postgres() {
+        let store = MemoryRouterStore::new();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su2")).unwrap();
+
+        let all = store.get_all_schedulers().unwrap();
+        assert_eq!(all.len(), 2);
+        assert_eq!(all[0].row_id, Some(1));
+        assert_eq!(
+            store.get_scheduler_by_url("http://su2").unwrap().row_id,
+            Some(2)
+        );
+
+        store.save_process_scheduler(&row("pid1", 1)).unwrap();
+        store.save_process_scheduler(&row("pid1", 2)).unwrap();
+        assert_eq!(
+            store.get_process_scheduler("pid1").unwrap().scheduler_row_id,
+            1
+        );
+    }
+
+    #[test]
+    fn test_moves_counts_and_orphans() {
+        let store = MemoryRouterStore::new();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su2")).unwrap();
+        store.save_process_scheduler(&row("pid1", 1)).unwrap();
+        store.save_process_scheduler(&row("pid2", 1)).unwrap();
+        store.save_process_scheduler(&row("pid3", 3)).unwrap();
+
+        let mut moved = store.get_process_scheduler("pid2").unwrap();
+        moved.scheduler_row_id = 2;
+        store.update_process_scheduler(&moved).unwrap();
+
+        assert_eq!(
+            store.get_process_scheduler_counts().unwrap(),
+            vec![(1, 1), (2, 1), (3, 1)]
+        );
+        assert_eq!(
+            store.get_process_schedulers_for(&1, &0, 10).unwrap().len(),
+            1
+        );
+        assert!(store
+            .get_process_schedulers_for(&1, &1, 10)
+            .unwrap()
+            .is_empty());
+
+        let orphans = store.get_orphaned_process_schedulers().unwrap();
+        assert_eq!(orphans.len(), 1);
+        assert_eq!(orphans[0].process_id, "pid3");
+
+        store
+            .delete_process_scheduler(&orphans[0].row_id.unwrap())
+            .unwrap();
+        assert!(matches!(
+            store.get_process_scheduler("pid3"),
+            Err(StoreErrorType::NotFound(_))
+        ));
+        assert!(store.delete_scheduler(&3).is_err());
+    }
+
+    #[test]
+    fn test_assign_process_counts_once() {
+        let store = MemoryRouterStore::new();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su2")).unwrap();
+
+        let first = store.assign_process("pid1", &1).unwrap();
+        assert_eq!(first.scheduler.process_count, 1);
+        assert!(!first.duplicate);
+        let second = store.assign_process("pid2", &1).unwrap();
+        assert_eq!(second.scheduler.process_count, 2);
+
+        // a second spawn of pid1 stays where the first one went
+        let again = store.assign_process("pid1", &2).unwrap();
+        assert!(again.duplicate);
+        assert_eq!(again.scheduler.url, "http://su1");
+        assert_eq!(again.scheduler.process_count, 2);
+        assert_eq!(store.get_scheduler(&2).unwrap().process_count, 0);
+
+        assert!(matches!(
+            store.assign_process("pid3", &9),
+            Err(StoreErrorType::NotFound(_))
+        ));
+        assert!(store.get_process_scheduler("pid3").is_err());
+
+        // a scheduler read before the spawns doesn't undo them when saved
+        let mut stale = scheduler("http://su1");
+        stale.row_id = Some(1);
+        stale.weight = Some(3);
+        store.update_scheduler(&stale).unwrap();
+        let su1 = store.get_scheduler(&1).unwrap();
+        assert_eq!((su1.process_count, su1.weight), (2, Some(3)));
+        store.decrement_process_count(&1, 5).unwrap();
+        store.increment_process_count(&2, 2).unwrap();
+        assert_eq!(store.get_scheduler(&1).unwrap().process_count, 0);
+        assert_eq!(store.get_scheduler(&2).unwrap().process_count, 2);
+    }
+
+    #[test]
+    fn test_move_process() {
+        let store = MemoryRouterStore::new();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su2")).unwrap();
+        store.assign_process("pid1", &1).unwrap();
+        store.assign_process("pid2", &1).unwrap();
+
+        store.move_process("pid1", &1, &2).unwrap();
+        let moved = store.get_process_scheduler("pid1").unwrap();
+        assert_eq!(moved.scheduler_row_id, 2);
+        assert_eq!(store.get_scheduler(&1).unwrap().process_count, 1);
+        assert_eq!(store.get_scheduler(&2).unwrap().process_count, 1);
+
+        // a process no longer on from is left alone
+        assert!(matches!(
+            store.move_process("pid1", &1, &2),
+            Err(StoreErrorType::NotFound(_))
+        ));
+        assert!(store.move_process("pid9", &1, &2).is_err());
+        assert_eq!(store.get_scheduler(&1).unwrap().process_count, 1);
+        assert_eq!(store.get_scheduler(&2).unwrap().process_count, 1);
+    }
+
+    #[test]
+    fn test_list_processes() {
+        let store = MemoryRouterStore::new();
+        store.save_scheduler(&scheduler("http://su1")).unwrap();
+        store.save_scheduler(&scheduler("http://su2")).unwrap();
+        // saved without a spawn time, like rows older than the listing
+        store.save_process_scheduler(&row("pid0", 1)).unwrap();
+        let before = now_millis() - 1;
+        store.assign_process("pid3", &1).unwrap();
+        store.assign_process("pid1", &2).unwrap();
+        store.assign_process("pid2", &1).unwrap();
+
+        let ids = |page: Vec<(ProcessScheduler, Option<i64>)>| -> Vec<String> {
+            page.into_iter().map(|(row, _)| row.process_id).collect()
+        };
+        assert_eq!(
+            ids(store.list_processes(None, None, "", 10).unwrap()),
+            vec!["pid0", "pid1", "pid2", "pid3"]
+        );
+        assert_eq!(
+            ids(store.list_processes(Some(1), None, "pid0", 10).unwrap()),
+            vec!["pid2", "pid3"]
+        );
+        let spawned = store.list_processes(None, Some(before), "", 2).unwrap();
+        assert!(spawned[0].1.unwrap() > before);
+        assert_eq!(ids(spawned), vec!["pid1", "pid2"]);
+        assert!(store
+            .list_processes(None, Some(now_millis() + 1000), "", 10)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_router_state_export_import() {
+        use crate::domain::core::router_state::{
+            export_router_state, import_router_state, StateFormat,
+        };
+
+        let source = MemoryRouterStore::new();
+        source.save_scheduler(&scheduler("http://su1")).unwrap();
+        let mut su2 = scheduler("http://su2");
+        su2.wallets_to_route = Some(vec!["a".to_string(), "b".to_string()]);
+        su2.weight = Some(4);
+        source.save_scheduler(&su2).unwrap();
+        for i in 0..2500 {
+            source
+                .save_process_scheduler(&row(&format!("pid{}", i), 1 + i % 2))
+                .unwrap();
+        }
+        source.save_process_scheduler(&row("orphan", 9)).unwrap();
+
+        // the target already has su2 at another row id and a stale pin
+        let target = MemoryRouterStore::new();
+        target.save_scheduler(&scheduler("http://su3")).unwrap();
+        target.save_scheduler(&scheduler("http://su2")).unwrap();
+        target.save_process_scheduler(&row("pid0", 2)).unwrap();
+
+        for (format, name) in [(StateFormat::Json, "json"), (StateFormat::Csv, "csv")] {
+            let path = std::env::temp_dir().join(format!(
+                "su-router-state-{}.{}",
+                std::process::id(),
+                name
+            ));
+            let path = path.to_str().unwrap();
+            let exported = export_router_state(&source, path, format).unwrap();
+            assert!(exported.contains("2 schedulers and 2500 processes"));
+            assert!(exported.contains("skipped 1"));
+            import_router_state(&target, path, format).unwrap();
+            let _ = std::fs::remove_file(path).or_else(|_| std::fs::remove_dir_all(path));
+        }
+
+        let su1 = target.get_scheduler_by_url("http://su1").unwrap();
+        let su2 = target.get_scheduler_by_url("http://su2").unwrap();
+        assert_eq!(su2.row_id, Some(2));
+        assert_eq!(
+            su2.wallets_to_route,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert_eq!(su2.weight, Some(4));
+        assert_eq!((su1.process_count, su2.process_count), (1250, 1250));
+        assert_eq!(
+            target
+                .get_process_scheduler("pid0")
+                .unwrap()
+                .scheduler_row_id,
+            su1.row_id.unwrap()
+        );
+        // untouched by the import
+        assert!(target.get_scheduler_by_url("http://su3").is_ok());
+        assert!(target.get_process_scheduler("orphan").is_err());
+    }
+}
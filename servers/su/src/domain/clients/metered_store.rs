@@ -0,0 +1,466 @@
+use std::sync::Arc;
+use std::time::Instant;
+
+use async_trait::async_trait;
+
+use crate::domain::core::dal::{
+    Assignment, ChainSnapshot, CoreMetrics, DataStore, ExportItem, InclusionProof, Message, PaginatedMessages, Process, ProcessScheduler, ProcessStats,
+    RouterDataStore, Scheduler, StoreErrorType, Tag,
+};
+use crate::domain::core::trace::{self, SpanKind};
+
+/*
+  Wraps a data store and records how long each call
+  takes in the store query histogram, labelled with
+  the method name. It sits in front of whichever store
+  is configured so the numbers are comparable between
+  postgres, the local store and a dual write. Calls
+  made for a traced request are also its spans.
+*/
+pub struct MeteredStore<S: ?Sized> {
+    inner: Arc<S>,
+    metrics: Arc<dyn CoreMetrics>,
+}
+
+impl<S: ?Sized> MeteredStore<S> {
+    pub fn new(inner: Arc<S>, metrics: Arc<dyn CoreMetrics>) -> Self {
+        MeteredStore { inner, metrics }
+    }
+
+    fn observe(&self, query: &str, start: Instant) {
+        self.metrics
+            .store_query_observe(query, start.elapsed().as_millis());
+        trace::record(
+            &format!("store {}", query),
+            SpanKind::Client,
+            start,
+            &[("db.operation", query)],
+        );
+    }
+
+    fn timed<T>(&self, query: &str, call: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = call();
+        self.observe(query, start);
+        result
+    }
+}
+
+#[async_trait]
+impl DataStore for MeteredStore<dyn DataStore> {
+    fn save_process(&self, process: &Process, bundle_in: &[u8]) -> Result<String, StoreErrorType> {
+        self.timed("save_process", || self.inner.save_process(process, bundle_in))
+    }
+
+    async fn get_process(&self, process_id_in: &str) -> Result<Process, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_process(process_id_in).await;
+        self.observe("get_process", start);
+        result
+    }
+
+    async fn get_process_bundle(&self, process_id_in: &str) -> Result<Vec<u8>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_process_bundle(process_id_in).await;
+        self.observe("get_process_bundle", start);
+        result
+    }
+
+    async fn save_message(
+        &self,
+        message: &Message,
+        bundle_in: &[u8],
+        deep_hash: Option<&String>,
+    ) -> Result<String, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_message(message, bundle_in, deep_hash).await;
+        self.observe("save_message", start);
+        result
+    }
+
+    async fn save_messages(
+        &self,
+        messages: &[(&Message, &[u8], Option<&String>)],
+    ) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_messages(messages).await;
+        self.observe("save_messages", start);
+        result
+    }
+
+    async fn get_messages(
+        &self,
+        process: &Process,
+        from: &Option<String>,
+        to: &Option<String>,
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .get_messages(process, from, to, limit, from_nonce, to_nonce)
+            .await;
+        self.observe("get_messages", start);
+        result
+    }
+
+    async fn get_message_bundles(
+        &self,
+        process: &Process,
+        from: &Option<String>,
+        limit: &Option<i32>,
+    ) -> Result<(Vec<(String, Vec<u8>)>, bool), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_message_bundles(process, from, limit).await;
+        self.observe("get_message_bundles", start);
+        result
+    }
+
+    async fn get_tagged_messages(
+        &self,
+        process: &Process,
+        tags: &[Tag],
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .get_tagged_messages(process, tags, limit, from_nonce, to_nonce)
+            .await;
+        self.observe("get_tagged_messages", start);
+        result
+    }
+
+    fn get_message(&self, message_id_in: &str) -> Result<Message, StoreErrorType> {
+        self.timed("get_message", || self.inner.get_message(message_id_in))
+    }
+
+    async fn get_latest_message(
+        &self,
+        process_id_in: &str,
+    ) -> Result<Option<Message>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_latest_message(process_id_in).await;
+        self.observe("get_latest_message", start);
+        result
+    }
+
+    fn check_existing_message(&self, message_id: &str) -> Result<(), StoreErrorType> {
+        self.timed("check_existing_message", || {
+            self.inner.check_existing_message(message_id)
+        })
+    }
+
+    async fn check_existing_deep_hash(
+        &self,
+        process_id: &str,
+        deep_hash: &str,
+    ) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self
+            .inner
+            .check_existing_deep_hash(process_id, deep_hash)
+            .await;
+        self.observe("check_existing_deep_hash", start);
+        result
+    }
+
+    async fn get_deephash_version(&self, process_id: &str) -> Result<String, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_deephash_version(process_id).await;
+        self.observe("get_deephash_version", start);
+        result
+    }
+
+    async fn save_deephash_version(
+        &self,
+        process_id: &str,
+        version: &str,
+    ) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_deephash_version(process_id, version).await;
+        self.observe("save_deephash_version", start);
+        result
+    }
+
+    async fn save_deephash(&self, process_id: &str, deep_hash: &str) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_deephash(process_id, deep_hash).await;
+        self.observe("save_deephash", start);
+        result
+    }
+
+    async fn get_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_process_stats(process_id).await;
+        self.observe("get_process_stats", start);
+        result
+    }
+
+    async fn get_processes_stats(
+        &self,
+        process_ids: &[String],
+    ) -> Result<Vec<ProcessStats>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_processes_stats(process_ids).await;
+        self.observe("get_processes_stats", start);
+        result
+    }
+
+    async fn save_chain_snapshot(&self, snapshot: &ChainSnapshot) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_chain_snapshot(snapshot).await;
+        self.observe("save_chain_snapshot", start);
+        result
+    }
+
+    async fn get_chain_snapshot(
+        &self,
+        process_id: &str,
+        max_nonce: Option<i32>,
+    ) -> Result<ChainSnapshot, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_chain_snapshot(process_id, max_nonce).await;
+        self.observe("get_chain_snapshot", start);
+        result
+    }
+
+    async fn save_inclusion_proofs(
+        &self,
+        proofs: &[InclusionProof],
+    ) -> Result<(), StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.save_inclusion_proofs(proofs).await;
+        self.observe("save_inclusion_proofs", start);
+        result
+    }
+
+    async fn get_inclusion_proof(&self, item_id: &str) -> Result<InclusionProof, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_inclusion_proof(item_id).await;
+        self.observe("get_inclusion_proof", start);
+        result
+    }
+
+    async fn get_items_after(
+        &self,
+        after: (i64, &str),
+        limit: usize,
+    ) -> Result<Vec<ExportItem>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_items_after(after, limit).await;
+        self.observe("get_items_after", start);
+        result
+    }
+
+    async fn get_process_ids_after(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.get_process_ids_after(after, limit).await;
+        self.observe("get_process_ids_after", start);
+        result
+    }
+
+    async fn list_processes(
+        &self,
+        spawned_after: Option<i64>,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, StoreErrorType> {
+        let start = Instant::now();
+        let result = self.inner.list_processes(spawned_after, after, limit).await;
+        self.observe("list_processes", start);
+        result
+    }
+}
+
+#[async_trait]
+impl RouterDataStore for MeteredStore<dyn RouterDataStore> {
+    fn save_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        self.timed("save_process_scheduler", || {
+            self.inner.save_process_scheduler(process_scheduler)
+        })
+    }
+
+    fn get_process_scheduler(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.timed("get_process_scheduler", || {
+            self.inner.get_process_scheduler(process_id_in)
+        })
+    }
+
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.timed("get_process_scheduler_from_writer", || {
+            self.inner.get_process_scheduler_from_writer(process_id_in)
+        })
+    }
+
+    fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        self.timed("save_scheduler", || self.inner.save_scheduler(scheduler))
+    }
+
+    fn update_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        self.timed("update_scheduler", || self.inner.update_scheduler(scheduler))
+    }
+
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        self.timed("increment_process_count", || {
+            self.inner.increment_process_count(scheduler_row_id_in, by)
+        })
+    }
+
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        self.timed("decrement_process_count", || {
+            self.inner.decrement_process_count(scheduler_row_id_in, by)
+        })
+    }
+
+    fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
+        self.timed("get_scheduler", || self.inner.get_scheduler(row_id_in))
+    }
+
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType> {
+        self.timed("get_scheduler_by_url", || {
+            self.inner.get_scheduler_by_url(url_in)
+        })
+    }
+
+    fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType> {
+        self.timed("get_all_schedulers", || self.inner.get_all_schedulers())
+    }
+
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        self.timed("update_process_scheduler", || {
+            self.inner.update_process_scheduler(process_scheduler)
+        })
+    }
+
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        self.timed("delete_process_scheduler", || {
+            self.inner.delete_process_scheduler(row_id_in)
+        })
+    }
+
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        self.timed("delete_scheduler", || self.inner.delete_scheduler(row_id_in))
+    }
+
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.timed("get_orphaned_process_schedulers", || {
+            self.inner.get_orphaned_process_schedulers()
+        })
+    }
+
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.timed("get_duplicate_process_schedulers", || {
+            self.inner.get_duplicate_process_schedulers()
+        })
+    }
+
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        self.timed("get_process_scheduler_counts", || {
+            self.inner.get_process_scheduler_counts()
+        })
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.timed("get_process_schedulers_for", || {
+            self.inner
+                .get_process_schedulers_for(scheduler_row_id_in, after_row_id, limit)
+        })
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.timed("get_process_schedulers_after", || {
+            self.inner.get_process_schedulers_after(after_row_id, limit)
+        })
+    }
+
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        self.timed("list_processes", || {
+            self.inner
+                .list_processes(scheduler_row_id_in, spawned_after, after_process_id, limit)
+        })
+    }
+
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        self.timed("assign_process", || {
+            self.inner
+                .assign_process(process_id_in, scheduler_row_id_in)
+        })
+    }
+
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        self.timed("move_process", || {
+            self.inner
+                .move_process(process_id_in, from_row_id, to_row_id)
+        })
+    }
+
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType> {
+        self.timed("get_owner_scheduler", || {
+            self.inner.get_owner_scheduler(owner_in)
+        })
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        self.timed("save_owner_scheduler", || {
+            self.inner
+                .save_owner_scheduler(owner_in, scheduler_row_id_in)
+        })
+    }
+}
@@ -1,6 +1,9 @@
 use super::super::config::AoConfig;
-use super::super::core::dal::CoreMetrics;
-use prometheus::{HistogramOpts, HistogramVec, IntCounter, Registry, TextEncoder};
+use super::super::core::dal::{CoreMetrics, Log};
+use prometheus::{
+    Gauge, Histogram, HistogramOpts, HistogramVec, IntCounter, IntCounterVec, IntGauge,
+    IntGaugeVec, Opts, Registry, TextEncoder,
+};
 
 /*
   Implementation of metrics
@@ -13,6 +16,30 @@ pub struct PromMetrics {
     enabled: bool,
     core_metrics: HistogramVec,
     message_save_failures: IntCounter,
+    process_scheduler_cleanups: IntCounterVec,
+    memory_rss: IntGauge,
+    memory_in_flight: IntGauge,
+    memory_shed_level: IntGauge,
+    writes_shed: IntCounter,
+    redirects: IntCounterVec,
+    items_written: IntCounterVec,
+    process_messages: Option<IntCounterVec>,
+    upload_failures: IntCounter,
+    store_queries: HistogramVec,
+    spawn_failovers: IntCounterVec,
+    duplicate_spawns: IntCounter,
+    rate_limited: IntCounterVec,
+    read_rate_limited: IntCounterVec,
+    endpoint_queued: IntGaugeVec,
+    endpoint_rejected: IntCounterVec,
+    write_batch_sizes: Histogram,
+    write_batch_limit: IntGauge,
+    write_batch_wait: IntGauge,
+    write_batch_queued: IntGauge,
+    rebalance_imbalance: Gauge,
+    rebalance_moves: IntCounterVec,
+    signing_check_failing: IntGauge,
+    write_verifications: IntCounterVec,
     registry: Registry,
 }
 
@@ -45,10 +72,262 @@ impl PromMetrics {
             .register(Box::new(message_save_failures.clone()))
             .unwrap();
 
+        let process_scheduler_cleanups = IntCounterVec::new(
+            Opts::new(
+                "process_scheduler_cleanups",
+                "process_schedulers rows found broken by the cleanup job, by action taken",
+            ),
+            &["action"],
+        )
+        .unwrap();
+
+        registry
+            .register(Box::new(process_scheduler_cleanups.clone()))
+            .unwrap();
+
+        let memory_rss =
+            IntGauge::new("memory_rss_bytes", "resident memory of the su in bytes").unwrap();
+        let memory_in_flight = IntGauge::new(
+            "memory_in_flight_bytes",
+            "bytes of writes currently being processed",
+        )
+        .unwrap();
+        let memory_shed_level = IntGauge::new(
+            "memory_shed_level",
+            "0 accepting all writes, 1 shedding large writes, 2 shedding all writes",
+        )
+        .unwrap();
+        let writes_shed =
+            IntCounter::new("writes_shed", "writes refused because of memory usage").unwrap();
+
+        registry.register(Box::new(memory_rss.clone())).unwrap();
+        registry.register(Box::new(memory_in_flight.clone())).unwrap();
+        registry.register(Box::new(memory_shed_level.clone())).unwrap();
+        registry.register(Box::new(writes_shed.clone())).unwrap();
+
+        let redirects = IntCounterVec::new(
+            Opts::new(
+                "redirects_served",
+                "router redirects by scheduler url and the rule that picked it",
+            ),
+            &["scheduler", "rule"],
+        )
+        .unwrap();
+        let items_written = IntCounterVec::new(
+            Opts::new("items_written", "processes, messages and assignments written"),
+            &["type"],
+        )
+        .unwrap();
+        let upload_failures =
+            IntCounter::new("upload_failures", "failed bundle upload attempts, each is retried").unwrap();
+
+        registry.register(Box::new(redirects.clone())).unwrap();
+        registry.register(Box::new(items_written.clone())).unwrap();
+        registry.register(Box::new(upload_failures.clone())).unwrap();
+
+        /*
+          One series per process, only when asked for since
+          a busy su can hold more processes than a
+          prometheus server is happy to scrape
+        */
+        let process_messages = match config.enable_process_metrics {
+            true => {
+                let process_messages = IntCounterVec::new(
+                    Opts::new("process_messages", "messages written per process"),
+                    &["process_id"],
+                )
+                .unwrap();
+                registry
+                    .register(Box::new(process_messages.clone()))
+                    .unwrap();
+                Some(process_messages)
+            }
+            false => None,
+        };
+
+        let store_queries = HistogramVec::new(
+            HistogramOpts::new(
+                "store_query_duration_milliseconds",
+                "Histogram of data store query durations in milliseconds",
+            )
+            .buckets(vec![
+                0.0, 1.0, 2.5, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0, 1000.0, 2500.0, 5000.0,
+            ])
+            .namespace("su"),
+            &["query"],
+        )
+        .unwrap();
+        registry.register(Box::new(store_queries.clone())).unwrap();
+
+        let spawn_failovers = IntCounterVec::new(
+            Opts::new(
+                "spawn_failovers",
+                "schedulers skipped for a new process because they were unreachable",
+            ),
+            &["scheduler"],
+        )
+        .unwrap();
+        registry.register(Box::new(spawn_failovers.clone())).unwrap();
+
+        let duplicate_spawns = IntCounter::new(
+            "duplicate_spawns",
+            "spawns of a process that was already assigned, sent to its scheduler",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(duplicate_spawns.clone()))
+            .unwrap();
+
+        let rate_limited = IntCounterVec::new(
+            Opts::new(
+                "wallet_rate_limited",
+                "writes refused because their wallet was over its limit",
+            ),
+            &["kind"],
+        )
+        .unwrap();
+        registry.register(Box::new(rate_limited.clone())).unwrap();
+
+        let read_rate_limited = IntCounterVec::new(
+            Opts::new(
+                "read_rate_limited",
+                "reads refused because their client or api key was over its limit",
+            ),
+            &["tier"],
+        )
+        .unwrap();
+        registry.register(Box::new(read_rate_limited.clone())).unwrap();
+
+        let endpoint_queued = IntGaugeVec::new(
+            Opts::new(
+                "endpoint_queue_depth",
+                "requests waiting for a slot under their endpoint class concurrency limit",
+            ),
+            &["class"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(endpoint_queued.clone()))
+            .unwrap();
+
+        let endpoint_rejected = IntCounterVec::new(
+            Opts::new(
+                "endpoint_rejected",
+                "requests refused because their endpoint class and its queue were full",
+            ),
+            &["class"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(endpoint_rejected.clone()))
+            .unwrap();
+
+        let write_batch_sizes = Histogram::with_opts(
+            HistogramOpts::new(
+                "write_batch_size",
+                "messages saved together in one write batch",
+            )
+            .buckets(vec![1.0, 2.0, 4.0, 8.0, 16.0, 32.0, 64.0, 128.0, 256.0])
+            .namespace("su"),
+        )
+        .unwrap();
+        let write_batch_limit = IntGauge::new(
+            "write_batch_limit",
+            "current size a write batch is allowed to grow to",
+        )
+        .unwrap();
+        let write_batch_wait = IntGauge::new(
+            "write_batch_wait_milliseconds",
+            "current time a write batch waits to fill up",
+        )
+        .unwrap();
+        let write_batch_queued = IntGauge::new(
+            "write_batch_queue_depth",
+            "messages waiting behind the write batch being saved",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(write_batch_sizes.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(write_batch_limit.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(write_batch_wait.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(write_batch_queued.clone()))
+            .unwrap();
+
+        let rebalance_imbalance = Gauge::new(
+            "rebalance_imbalance",
+            "spread between the most and least loaded scheduler as a share of the average load",
+        )
+        .unwrap();
+        let rebalance_moves = IntCounterVec::new(
+            Opts::new(
+                "rebalance_moves",
+                "processes the rebalancer moved, planned in a dry run or failed to move",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(rebalance_imbalance.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(rebalance_moves.clone()))
+            .unwrap();
+
+        let signing_check_failing = IntGauge::new(
+            "signing_check_failing",
+            "1 while the signing self check fails to verify the su's own signature",
+        )
+        .unwrap();
+        registry
+            .register(Box::new(signing_check_failing.clone()))
+            .unwrap();
+
+        let write_verifications = IntCounterVec::new(
+            Opts::new(
+                "write_verifications",
+                "sampled writes read back from the data store, by whether they matched what was acked",
+            ),
+            &["outcome"],
+        )
+        .unwrap();
+        registry
+            .register(Box::new(write_verifications.clone()))
+            .unwrap();
+
         PromMetrics {
             enabled: config.enable_metrics,
             core_metrics,
             message_save_failures,
+            process_scheduler_cleanups,
+            memory_rss,
+            memory_in_flight,
+            memory_shed_level,
+            writes_shed,
+            redirects,
+            items_written,
+            process_messages,
+            upload_failures,
+            store_queries,
+            spawn_failovers,
+            duplicate_spawns,
+            rate_limited,
+            read_rate_limited,
+            endpoint_queued,
+            endpoint_rejected,
+            write_batch_sizes,
+            write_batch_limit,
+            write_batch_wait,
+            write_batch_queued,
+            rebalance_imbalance,
+            rebalance_moves,
+            signing_check_failing,
+            write_verifications,
             registry,
         }
     }
@@ -114,4 +393,105 @@ impl CoreMetrics for PromMetrics {
     fn failed_message_save(&self) {
         self.message_save_failures.inc();
     }
+
+    fn process_scheduler_cleanup(&self, action: &str) {
+        self.process_scheduler_cleanups
+            .with_label_values(&[action])
+            .inc();
+    }
+
+    fn memory_observe(&self, rss: u64, in_flight: u64, shed_level: u8) {
+        self.memory_rss.set(rss as i64);
+        self.memory_in_flight.set(in_flight as i64);
+        self.memory_shed_level.set(shed_level as i64);
+    }
+
+    fn write_shed(&self) {
+        self.writes_shed.inc();
+    }
+
+    fn redirect_served(&self, scheduler: &str, rule: &str) {
+        self.redirects.with_label_values(&[scheduler, rule]).inc();
+    }
+
+    fn item_written(&self, kind: &str, process_id: &str) {
+        self.items_written.with_label_values(&[kind]).inc();
+        if kind != "process" {
+            if let Some(process_messages) = &self.process_messages {
+                process_messages.with_label_values(&[process_id]).inc();
+            }
+        }
+    }
+
+    fn upload_failed(&self) {
+        self.upload_failures.inc();
+    }
+
+    fn store_query_observe(&self, query: &str, duration: u128) {
+        if !self.enabled {
+            return;
+        }
+        self.store_queries
+            .with_label_values(&[query])
+            .observe(duration as f64);
+    }
+
+    fn spawn_failover(&self, scheduler: &str) {
+        self.spawn_failovers.with_label_values(&[scheduler]).inc();
+    }
+
+    fn duplicate_spawn(&self) {
+        self.duplicate_spawns.inc();
+    }
+
+    fn rate_limited(&self, kind: &str) {
+        self.rate_limited.with_label_values(&[kind]).inc();
+    }
+
+    fn read_rate_limited(&self, tier: &str) {
+        self.read_rate_limited.with_label_values(&[tier]).inc();
+    }
+
+    fn endpoint_queue_observe(&self, class: &str, depth: usize) {
+        self.endpoint_queued
+            .with_label_values(&[class])
+            .set(depth as i64);
+    }
+
+    fn endpoint_rejected(&self, class: &str) {
+        self.endpoint_rejected.with_label_values(&[class]).inc();
+    }
+
+    fn write_batch_observe(&self, size: usize, limit: usize, wait: u64, depth: usize) {
+        if !self.enabled {
+            return;
+        }
+        self.write_batch_sizes.observe(size as f64);
+        self.write_batch_limit.set(limit as i64);
+        self.write_batch_wait.set(wait as i64);
+        self.write_batch_queued.set(depth as i64);
+    }
+
+    fn rebalance_observe(&self, imbalance: f64) {
+        self.rebalance_imbalance.set(imbalance);
+    }
+
+    fn rebalance_move(&self, outcome: &str) {
+        self.rebalance_moves.with_label_values(&[outcome]).inc();
+    }
+
+    fn signing_check_observe(&self, failing: bool) {
+        self.signing_check_failing.set(failing as i64);
+    }
+
+    fn write_verified(&self, outcome: &str) {
+        self.write_verifications.with_label_values(&[outcome]).inc();
+    }
+
+    fn flush(&self, logger: &dyn Log) {
+        // nothing is recorded when disabled
+        if let Ok(metrics) = self.emit_metrics() {
+            logger.log(format!("final metrics:\n{}", metrics));
+        }
+    }
 }
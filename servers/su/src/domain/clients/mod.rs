@@ -14,6 +14,12 @@ pub mod store;
 // local database layer
 pub mod local_store;
 
+// writes to two data stores while migrating between them
+pub mod dual_store;
+
+// times the calls made to a data store
+pub mod metered_store;
+
 // arweave gateway
 pub mod gateway;
 
@@ -30,4 +36,56 @@ pub mod signer;
 pub mod metrics;
 
 // module for calling a router
-pub mod su_router;
\ No newline at end of file
+pub mod su_router;
+
+// publishes assignments to an external stream
+pub mod streamer;
+
+// process and owner denylist, local and from a remote feed
+pub mod denylist;
+
+// processes migrated off this su, writes to them are refused
+pub mod read_only;
+
+// cold storage for the raw bytes of accepted data items
+pub mod raw_archive;
+
+// forwards committed items to a standby su
+pub mod standby;
+
+// router data store held in memory
+pub mod memory_router_store;
+
+// streams the writes to the router tables to a standby router
+pub mod router_stream;
+
+// router data store in a sqlite file
+#[cfg(feature = "sqlite")]
+pub mod sqlite_router_store;
+
+// log of the spawns a router placed
+pub mod spawn_audit;
+
+// api keys for the read endpoints
+pub mod api_keys;
+
+// hash chained log of the changes made through the admin api
+pub mod admin_audit;
+
+// messages waiting for their Schedule-At time
+pub mod deferred;
+
+// group commit of data store writes, sized by queue depth
+pub mod write_batch;
+
+// local directory or s3 bucket su export-parquet writes to
+pub mod export_sink;
+
+// cache in redis shared by the replicas of a router
+pub mod redis_cache;
+
+// sends request traces to an OpenTelemetry collector
+pub mod otlp;
+
+// runs su-conformance against an su over http
+pub mod conformance;
@@ -0,0 +1,181 @@
+use std::sync::Arc;
+
+use reqwest::{Client, RequestBuilder};
+use serde_json::{json, Value};
+use tokio::spawn;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::time::{timeout_at, Duration, Instant};
+
+use crate::domain::core::dal::{Log, Span, SpanExporter};
+use crate::domain::core::trace;
+
+/*
+  Sends finished spans to an OpenTelemetry collector with
+  OTLP over HTTP in its json encoding, POSTed to
+  /v1/traces of OTEL_EXPORTER_OTLP_ENDPOINT. Spans are
+  queued and a background task sends them in batches of
+  up to BATCH_SIZE, at least every FLUSH_MS. A full
+  queue drops new spans rather than hold up requests,
+  and a batch the collector refuses is dropped too.
+*/
+
+const QUEUE_SIZE: usize = 4096;
+const BATCH_SIZE: usize = 512;
+const FLUSH_MS: u64 = 1000;
+
+pub struct OtlpExporter {
+    sender: Sender<Span>,
+}
+
+impl OtlpExporter {
+    pub fn new(endpoint: &str, service_name: &str, logger: Arc<dyn Log>) -> Self {
+        let (sender, receiver) = channel(QUEUE_SIZE);
+        let url = format!("{}/v1/traces", endpoint.trim_end_matches('/'));
+        spawn(send_batches(
+            receiver,
+            url,
+            service_name.to_string(),
+            logger,
+        ));
+        OtlpExporter { sender }
+    }
+}
+
+impl SpanExporter for OtlpExporter {
+    fn export(&self, span: Span) {
+        let _ = self.sender.try_send(span);
+    }
+}
+
+// a call out continuing the trace of the request it is made for
+pub fn propagate(request: RequestBuilder) -> RequestBuilder {
+    match trace::traceparent() {
+        Some(traceparent) => request.header("traceparent", traceparent),
+        None => request,
+    }
+}
+
+async fn send_batches(
+    mut receiver: Receiver<Span>,
+    url: String,
+    service_name: String,
+    logger: Arc<dyn Log>,
+) {
+    let client = Client::new();
+    let mut failing = false;
+    while let Some(first) = receiver.recv().await {
+        let mut batch = vec![first];
+        let deadline = Instant::now() + Duration::from_millis(FLUSH_MS);
+        while batch.len() < BATCH_SIZE {
+            match timeout_at(deadline, receiver.recv()).await {
+                Ok(Some(span)) => batch.push(span),
+                _ => break,
+            }
+        }
+
+        let result = client
+            .post(&url)
+            .json(&export_request(&service_name, &batch))
+            .timeout(Duration::from_secs(10))
+            .send()
+            .await
+            .map_err(|e| e.to_string())
+            .and_then(|response| match response.status().is_success() {
+                true => Ok(()),
+                false => Err(format!("collector returned {}", response.status())),
+            });
+        match result {
+            Ok(()) if failing => {
+                failing = false;
+                logger.log("trace collector reachable again".to_string());
+            }
+            Ok(()) => (),
+            Err(e) if !failing => {
+                failing = true;
+                logger.error(format!("Failed to export {} spans: {}", batch.len(), e));
+            }
+            Err(_) => (),
+        }
+    }
+}
+
+fn string_attributes(attributes: &[(String, String)]) -> Value {
+    attributes
+        .iter()
+        .map(|(key, value)| json!({ "key": key, "value": { "stringValue": value } }))
+        .collect()
+}
+
+// an ExportTraceServiceRequest, ids in hex and times as strings of nanoseconds
+fn export_request(service_name: &str, spans: &[Span]) -> Value {
+    let spans: Vec<Value> = spans
+        .iter()
+        .map(|span| {
+            let mut encoded = json!({
+                "traceId": span.trace_id,
+                "spanId": span.span_id,
+                "name": span.name,
+                "kind": span.kind.otlp(),
+                "startTimeUnixNano": span.start_unix_nanos.to_string(),
+                "endTimeUnixNano": span.end_unix_nanos.to_string(),
+                "attributes": string_attributes(&span.attributes),
+                // STATUS_CODE_UNSET or STATUS_CODE_ERROR
+                "status": match &span.error {
+                    Some(error) => json!({ "code": 2, "message": error }),
+                    None => json!({ "code": 0 }),
+                },
+            });
+            if let Some(parent) = &span.parent_span_id {
+                encoded["parentSpanId"] = json!(parent);
+            }
+            encoded
+        })
+        .collect();
+    json!({
+        "resourceSpans": [{
+            "resource": {
+                "attributes": string_attributes(&[(
+                    "service.name".to_string(),
+                    service_name.to_string()
+                )]),
+            },
+            "scopeSpans": [{
+                "scope": { "name": "ao-su", "version": env!("CARGO_PKG_VERSION") },
+                "spans": spans,
+            }],
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::core::trace::SpanKind;
+
+    #[test]
+    fn test_export_request() {
+        let span = Span {
+            trace_id: "4bf92f3577b34da6a3ce929d0e0e4736".to_string(),
+            span_id: "00f067aa0ba902b7".to_string(),
+            parent_span_id: None,
+            name: "POST /".to_string(),
+            kind: SpanKind::Server,
+            start_unix_nanos: 1,
+            end_unix_nanos: 2,
+            attributes: vec![("http.status_code".to_string(), "503".to_string())],
+            error: Some("503 Service Unavailable".to_string()),
+        };
+        let request = export_request("su", &[span]);
+        let resource = &request["resourceSpans"][0];
+        assert_eq!(
+            resource["resource"]["attributes"][0]["value"]["stringValue"],
+            "su"
+        );
+        let encoded = &resource["scopeSpans"][0]["spans"][0];
+        assert_eq!(encoded["kind"], 2);
+        assert_eq!(encoded["endTimeUnixNano"], "2");
+        assert_eq!(encoded["status"]["code"], 2);
+        assert_eq!(encoded["attributes"][0]["key"], "http.status_code");
+        assert!(encoded.get("parentSpanId").is_none());
+    }
+}
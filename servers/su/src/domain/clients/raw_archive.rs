@@ -0,0 +1,208 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use tokio::spawn;
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, Duration};
+
+use crate::domain::core::dal::{Log, RawArchive, RawArchiveErrorType};
+
+/*
+  Stores the raw bytes of every accepted data item on
+  disk, usually a mount backed by cheaper cold storage.
+
+  Files are grouped in one directory per day, named by
+  the number of days since the unix epoch, so expiring
+  old items is a matter of removing whole directories.
+  The retention period is independent of the data store,
+  pruning here never touches parsed records.
+*/
+
+const SECONDS_PER_DAY: u64 = 86400;
+const PRUNE_INTERVAL: u64 = 3600;
+
+impl From<io::Error> for RawArchiveErrorType {
+    fn from(error: io::Error) -> Self {
+        RawArchiveErrorType::ArchiveError(format!("Raw archive io error: {}", error))
+    }
+}
+
+pub struct RawArchiveClient {
+    dir: PathBuf,
+    retention_days: u64,
+}
+
+impl RawArchiveClient {
+    pub fn new(
+        dir: &str,
+        retention_days: u64,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, RawArchiveErrorType> {
+        fs::create_dir_all(dir)?;
+        let client = RawArchiveClient {
+            dir: PathBuf::from(dir),
+            retention_days,
+        };
+
+        if retention_days > 0 {
+            let prune_dir = client.dir.clone();
+            spawn(async move {
+                let mut ticker = interval(Duration::from_secs(PRUNE_INTERVAL));
+                loop {
+                    ticker.tick().await;
+                    let dir = prune_dir.clone();
+                    match spawn_blocking(move || prune(&dir, retention_days)).await {
+                        Ok(Ok(0)) => (),
+                        Ok(Ok(removed)) => {
+                            logger.log(format!("Pruned {} days of raw data items", removed))
+                        }
+                        Ok(Err(e)) => logger.error(format!("Failed to prune raw archive: {:?}", e)),
+                        Err(e) => logger.error(format!("Failed to prune raw archive: {:?}", e)),
+                    }
+                }
+            });
+        }
+
+        Ok(client)
+    }
+
+    /*
+      Only the day directories still inside the retention
+      period can hold the item, newest first
+    */
+    fn day_dirs(&self) -> Vec<PathBuf> {
+        let today = current_day();
+        let oldest = match self.retention_days {
+            0 => 0,
+            days => today.saturating_sub(days),
+        };
+        let mut days = match fs::read_dir(&self.dir) {
+            Ok(entries) => entries
+                .filter_map(|entry| entry.ok())
+                .filter_map(|entry| entry.file_name().to_str()?.parse::<u64>().ok())
+                .filter(|day| *day >= oldest)
+                .collect::<Vec<u64>>(),
+            Err(_) => vec![],
+        };
+        days.sort_unstable_by(|a, b| b.cmp(a));
+        days.into_iter()
+            .map(|day| self.dir.join(day.to_string()))
+            .collect()
+    }
+}
+
+impl RawArchive for RawArchiveClient {
+    fn retain(&self, id: &str, raw: &[u8]) -> Result<(), RawArchiveErrorType> {
+        validate_id(id)?;
+        let day_dir = self.dir.join(current_day().to_string());
+        fs::create_dir_all(&day_dir)?;
+
+        // write then rename so a crash never leaves a partial item
+        let tmp_path = day_dir.join(format!("{}.tmp", id));
+        fs::write(&tmp_path, raw)?;
+        fs::rename(&tmp_path, day_dir.join(id))?;
+        Ok(())
+    }
+
+    fn read(&self, id: &str) -> Result<Option<Vec<u8>>, RawArchiveErrorType> {
+        validate_id(id)?;
+        for day_dir in self.day_dirs() {
+            match fs::read(day_dir.join(id)) {
+                Ok(raw) => return Ok(Some(raw)),
+                Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(None)
+    }
+}
+
+/*
+  Used when no RAW_ARCHIVE_DIR is configured
+*/
+pub struct NoopRawArchive;
+
+impl RawArchive for NoopRawArchive {
+    fn retain(&self, _id: &str, _raw: &[u8]) -> Result<(), RawArchiveErrorType> {
+        Ok(())
+    }
+
+    fn read(&self, _id: &str) -> Result<Option<Vec<u8>>, RawArchiveErrorType> {
+        Err(RawArchiveErrorType::ArchiveError(
+            "Raw data item retention is not enabled".to_string(),
+        ))
+    }
+}
+
+fn current_day() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() / SECONDS_PER_DAY)
+        .unwrap_or(0)
+}
+
+// ids are base64url, anything else could escape the archive dir
+fn validate_id(id: &str) -> Result<(), RawArchiveErrorType> {
+    if id.is_empty()
+        || !id
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(RawArchiveErrorType::InvalidId(id.to_string()));
+    }
+    Ok(())
+}
+
+fn prune(dir: &PathBuf, retention_days: u64) -> Result<usize, RawArchiveErrorType> {
+    let oldest = current_day().saturating_sub(retention_days);
+    let mut removed = 0;
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let day = match entry.file_name().to_str().and_then(|n| n.parse::<u64>().ok()) {
+            Some(day) => day,
+            None => continue,
+        };
+        if day < oldest {
+            fs::remove_dir_all(entry.path())?;
+            removed += 1;
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::logger::SuLog;
+    use tempdir::TempDir;
+
+    #[tokio::test]
+    async fn retains_and_reads_raw_bytes() {
+        let tmp = TempDir::new("raw_archive").unwrap();
+        let archive =
+            RawArchiveClient::new(tmp.path().to_str().unwrap(), 30, SuLog::init()).unwrap();
+
+        archive.retain("item-id_1", b"raw bytes").unwrap();
+
+        assert_eq!(archive.read("item-id_1").unwrap(), Some(b"raw bytes".to_vec()));
+        assert_eq!(archive.read("missing").unwrap(), None);
+        assert!(archive.read("../escape").is_err());
+    }
+
+    #[test]
+    fn prune_removes_expired_days() {
+        let tmp = TempDir::new("raw_archive").unwrap();
+        let today = current_day();
+        for day in [today, today - 10, today - 40] {
+            fs::create_dir_all(tmp.path().join(day.to_string())).unwrap();
+        }
+
+        assert_eq!(prune(&tmp.path().to_path_buf(), 30).unwrap(), 1);
+        assert!(tmp.path().join(today.to_string()).exists());
+        assert!(tmp.path().join((today - 10).to_string()).exists());
+        assert!(!tmp.path().join((today - 40).to_string()).exists());
+    }
+}
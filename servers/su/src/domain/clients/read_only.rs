@@ -0,0 +1,121 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+
+use crate::domain::core::dal::ReadOnlyProcesses;
+
+/*
+  The processes the router migrated off this su, kept in
+  the json file at READ_ONLY_PATH as an object of process
+  ids to the url of the scheduler each moved to. The file
+  is read on start and rewritten on every change. Without
+  it the marks are only held in memory and a restart makes
+  the processes writable here again.
+*/
+
+pub struct ReadOnlyClient {
+    processes: RwLock<BTreeMap<String, String>>,
+    path: Option<PathBuf>,
+}
+
+impl ReadOnlyClient {
+    pub fn new(read_only_path: &str) -> Result<Self, String> {
+        let path = match read_only_path {
+            "" => None,
+            path => Some(PathBuf::from(path)),
+        };
+        let processes = match &path {
+            Some(path) => load(path)?,
+            None => BTreeMap::new(),
+        };
+        Ok(ReadOnlyClient {
+            processes: RwLock::new(processes),
+            path,
+        })
+    }
+
+    // like the denylist, memory only changes once the file is written
+    fn update<F, T>(&self, change: F) -> Result<T, String>
+    where
+        F: FnOnce(&mut BTreeMap<String, String>) -> T,
+    {
+        let mut processes = self
+            .processes
+            .write()
+            .map_err(|_| "Read only state lock poisoned".to_string())?;
+        let mut updated = processes.clone();
+        let result = change(&mut updated);
+        if let Some(path) = &self.path {
+            save(path, &updated)?;
+        }
+        *processes = updated;
+        Ok(result)
+    }
+}
+
+impl ReadOnlyProcesses for ReadOnlyClient {
+    fn moved_to(&self, process_id: &str) -> Option<String> {
+        match self.processes.read() {
+            Ok(processes) => processes.get(process_id).cloned(),
+            Err(_) => None,
+        }
+    }
+
+    fn mark(&self, process_id: &str, moved_to: &str) -> Result<(), String> {
+        self.update(|processes| {
+            processes.insert(process_id.to_string(), moved_to.to_string());
+        })
+    }
+
+    fn clear(&self, process_id: &str) -> Result<bool, String> {
+        self.update(|processes| processes.remove(process_id).is_some())
+    }
+}
+
+fn load(path: &Path) -> Result<BTreeMap<String, String>, String> {
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+    let bytes = fs::read(path)
+        .map_err(|e| format!("Failed to read read only file {}: {}", path.display(), e))?;
+    serde_json::from_slice(&bytes)
+        .map_err(|e| format!("Invalid read only file {}: {}", path.display(), e))
+}
+
+fn save(path: &Path, processes: &BTreeMap<String, String>) -> Result<(), String> {
+    let failed =
+        |e: std::io::Error| format!("Failed to write read only file {}: {}", path.display(), e);
+    let json = serde_json::to_vec_pretty(processes).map_err(|e| e.to_string())?;
+    let tmp_path = PathBuf::from(format!("{}.tmp", path.display()));
+    fs::write(&tmp_path, json).map_err(failed)?;
+    fs::rename(&tmp_path, path).map_err(failed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn marks_are_saved_and_reloaded() {
+        let path = std::env::temp_dir().join(format!("su-read-only-{}.json", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let path_str = path.display().to_string();
+
+        let client = ReadOnlyClient::new(&path_str).unwrap();
+        assert_eq!(client.moved_to("pid"), None);
+        client.mark("pid", "https://su2").unwrap();
+        client.mark("other", "https://su3").unwrap();
+        assert_eq!(client.moved_to("pid"), Some("https://su2".to_string()));
+
+        let reloaded = ReadOnlyClient::new(&path_str).unwrap();
+        assert_eq!(reloaded.moved_to("other"), Some("https://su3".to_string()));
+        assert!(reloaded.clear("other").unwrap());
+        assert!(!reloaded.clear("other").unwrap());
+
+        let reloaded = ReadOnlyClient::new(&path_str).unwrap();
+        assert_eq!(reloaded.moved_to("other"), None);
+        assert_eq!(reloaded.moved_to("pid"), Some("https://su2".to_string()));
+        let _ = fs::remove_file(&path);
+    }
+}
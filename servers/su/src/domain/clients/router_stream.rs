@@ -0,0 +1,466 @@
+use std::sync::Arc;
+
+use reqwest::{Client, Url};
+use tokio::spawn;
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+use tokio::time::{sleep, Duration};
+
+use crate::domain::core::dal::{
+    Assignment, Log, ProcessScheduler, RouterDataStore, Scheduler, StoreErrorType,
+};
+use crate::domain::core::router::RouterRole;
+use crate::domain::core::router_state::{OwnerRow, ProcessRow, RouterChange, SchedulerRow};
+
+/*
+  Wraps the router data store of a primary router and
+  streams every write it takes to a warm standby router,
+  which applies them to its own tables so it can be
+  promoted without sharing the primary's database.
+
+  Changes are queued and a background task posts them in
+  batches, in the order they were written, retrying a
+  batch until the standby accepts it. The queue is in
+  memory, changes still in it when the router stops, or
+  that do not fit in it, are missing on the standby until
+  it is seeded again with su router export and import.
+  Rows the cleanup job deletes are not streamed, the
+  standby runs the cleanup itself once it is promoted.
+
+  Nothing is streamed while this router is a standby
+  itself, so a pair can point at each other and the
+  stream follows whichever one is primary.
+*/
+
+const QUEUE_SIZE: usize = 10000;
+const BATCH_SIZE: usize = 500;
+const REQUEST_TIMEOUT: u64 = 5;
+const RETRY_DELAY: u64 = 1;
+
+pub struct StreamingRouterStore {
+    inner: Arc<dyn RouterDataStore>,
+    role: Arc<RouterRole>,
+    queue: Sender<RouterChange>,
+    logger: Arc<dyn Log>,
+}
+
+impl StreamingRouterStore {
+    pub fn new(
+        inner: Arc<dyn RouterDataStore>,
+        role: Arc<RouterRole>,
+        standby_url: &str,
+        standby_token: &str,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, String> {
+        let mut url =
+            Url::parse(standby_url).map_err(|e| format!("Invalid router standby url: {}", e))?;
+        url.set_path("/admin/router/changes");
+
+        let (queue, receiver) = channel(QUEUE_SIZE);
+        spawn(deliver(
+            url,
+            standby_token.to_string(),
+            receiver,
+            logger.clone(),
+        ));
+        Ok(StreamingRouterStore {
+            inner,
+            role,
+            queue,
+            logger,
+        })
+    }
+
+    fn stream(&self, change: RouterChange) {
+        if self.role.standby() {
+            return;
+        }
+        match self.queue.try_send(change) {
+            Ok(()) => (),
+            Err(TrySendError::Full(change)) => self.logger.error(format!(
+                "Router standby queue is full, change dropped: {:?}",
+                change
+            )),
+            Err(TrySendError::Closed(_)) => self
+                .logger
+                .error("Router standby delivery stopped".to_string()),
+        }
+    }
+
+    fn stream_process(&self, process_scheduler: &ProcessScheduler) {
+        match self
+            .inner
+            .get_scheduler(&process_scheduler.scheduler_row_id)
+        {
+            Ok(scheduler) => self.stream(RouterChange::Process(ProcessRow {
+                process_id: process_scheduler.process_id.clone(),
+                scheduler_url: scheduler.url,
+            })),
+            Err(e) => self.logger.error(format!(
+                "Failed to stream process {} to the router standby: {:?}",
+                process_scheduler.process_id, e
+            )),
+        }
+    }
+
+    fn stream_scheduler(&self, scheduler: &Scheduler) {
+        self.stream(RouterChange::Scheduler(SchedulerRow::from_scheduler(
+            scheduler,
+        )));
+    }
+}
+
+impl RouterDataStore for StreamingRouterStore {
+    fn save_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        let result = self.inner.save_process_scheduler(process_scheduler)?;
+        self.stream_process(process_scheduler);
+        Ok(result)
+    }
+
+    fn get_process_scheduler(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.inner.get_process_scheduler(process_id_in)
+    }
+
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.inner.get_process_scheduler_from_writer(process_id_in)
+    }
+
+    fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        let result = self.inner.save_scheduler(scheduler)?;
+        self.stream_scheduler(scheduler);
+        Ok(result)
+    }
+
+    fn update_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        let result = self.inner.update_scheduler(scheduler)?;
+        self.stream_scheduler(scheduler);
+        Ok(result)
+    }
+
+    // the standby counts the processes it is streamed itself
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        self.inner.increment_process_count(scheduler_row_id_in, by)
+    }
+
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        self.inner.decrement_process_count(scheduler_row_id_in, by)
+    }
+
+    fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
+        self.inner.get_scheduler(row_id_in)
+    }
+
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType> {
+        self.inner.get_scheduler_by_url(url_in)
+    }
+
+    fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType> {
+        self.inner.get_all_schedulers()
+    }
+
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        let result = self.inner.update_process_scheduler(process_scheduler)?;
+        self.stream_process(process_scheduler);
+        Ok(result)
+    }
+
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        self.inner.delete_process_scheduler(row_id_in)
+    }
+
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        let scheduler = self.inner.get_scheduler(row_id_in)?;
+        let result = self.inner.delete_scheduler(row_id_in)?;
+        self.stream(RouterChange::SchedulerRemoved { url: scheduler.url });
+        Ok(result)
+    }
+
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.inner.get_orphaned_process_schedulers()
+    }
+
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.inner.get_duplicate_process_schedulers()
+    }
+
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        self.inner.get_process_scheduler_counts()
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.inner
+            .get_process_schedulers_for(scheduler_row_id_in, after_row_id, limit)
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        self.inner.get_process_schedulers_after(after_row_id, limit)
+    }
+
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        self.inner
+            .list_processes(scheduler_row_id_in, spawned_after, after_process_id, limit)
+    }
+
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        let assignment = self
+            .inner
+            .assign_process(process_id_in, scheduler_row_id_in)?;
+        if !assignment.duplicate {
+            self.stream(RouterChange::Process(ProcessRow {
+                process_id: process_id_in.to_string(),
+                scheduler_url: assignment.scheduler.url.clone(),
+            }));
+        }
+        Ok(assignment)
+    }
+
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        self.inner
+            .move_process(process_id_in, from_row_id, to_row_id)?;
+        self.stream_process(&ProcessScheduler {
+            row_id: None,
+            process_id: process_id_in.to_string(),
+            scheduler_row_id: *to_row_id,
+        });
+        Ok(())
+    }
+
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType> {
+        self.inner.get_owner_scheduler(owner_in)
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        self.inner
+            .save_owner_scheduler(owner_in, scheduler_row_id_in)?;
+        match self.inner.get_scheduler(scheduler_row_id_in) {
+            Ok(scheduler) => self.stream(RouterChange::Owner(OwnerRow {
+                owner: owner_in.to_string(),
+                scheduler_url: scheduler.url,
+            })),
+            Err(e) => self.logger.error(format!(
+                "Failed to stream the scheduler of owner {} to the router standby: {:?}",
+                owner_in, e
+            )),
+        }
+        Ok(())
+    }
+}
+
+async fn deliver(
+    url: Url,
+    token: String,
+    mut receiver: Receiver<RouterChange>,
+    logger: Arc<dyn Log>,
+) {
+    let client = Client::new();
+    while let Some(change) = receiver.recv().await {
+        let mut batch = vec![change];
+        while batch.len() < BATCH_SIZE {
+            match receiver.try_recv() {
+                Ok(change) => batch.push(change),
+                Err(_) => break,
+            }
+        }
+
+        let mut failures = 0;
+        while let Err(e) = send(&client, &url, &token, &batch).await {
+            // log the first failure and then every minute or so
+            if failures % 60 == 0 {
+                logger.error(format!(
+                    "Failed to stream {} router changes to the standby, retrying: {}",
+                    batch.len(),
+                    e
+                ));
+            }
+            failures += 1;
+            sleep(Duration::from_secs(RETRY_DELAY)).await;
+        }
+    }
+}
+
+async fn send(
+    client: &Client,
+    url: &Url,
+    token: &str,
+    batch: &[RouterChange],
+) -> Result<(), String> {
+    let response = client
+        .post(url.clone())
+        .bearer_auth(token)
+        .json(batch)
+        .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+        .send()
+        .await
+        .map_err(|e| format!("Router standby request error: {}", e))?;
+
+    let status = response.status();
+    if status.is_success() {
+        return Ok(());
+    }
+    let body = response.text().await.unwrap_or_default();
+    Err(format!("Router standby returned {}: {}", status, body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::clients::memory_router_store::MemoryRouterStore;
+    use crate::domain::core::router_state::{apply_router_change, read_router_state};
+    use crate::domain::logger::SuLog;
+
+    fn scheduler(url: &str) -> Scheduler {
+        Scheduler {
+            row_id: None,
+            url: url.to_string(),
+            process_count: 0,
+            no_route: None,
+            wallets_to_route: None,
+            wallets_only: None,
+            priority: None,
+            large_objects: None,
+            weight: None,
+            public_url: None,
+            drain: None,
+            max_processes: None,
+            region: None,
+            tags_to_route: None,
+            wallets_policy: None,
+        }
+    }
+
+    #[test]
+    fn test_standby_follows_the_primary() {
+        let (queue, mut receiver) = channel(QUEUE_SIZE);
+        let primary = StreamingRouterStore {
+            inner: Arc::new(MemoryRouterStore::new()),
+            role: Arc::new(RouterRole::new(false)),
+            queue,
+            logger: SuLog::init(),
+        };
+
+        primary.save_scheduler(&scheduler("https://su1")).unwrap();
+        primary.save_scheduler(&scheduler("https://su2")).unwrap();
+        let mut su1 = primary.get_scheduler_by_url("https://su1").unwrap();
+        let su2 = primary.get_scheduler_by_url("https://su2").unwrap();
+        primary
+            .assign_process("pid1", &su1.row_id.unwrap())
+            .unwrap();
+        primary
+            .assign_process("pid2", &su1.row_id.unwrap())
+            .unwrap();
+        // a retried spawn changes nothing and streams nothing
+        primary
+            .assign_process("pid1", &su2.row_id.unwrap())
+            .unwrap();
+
+        // pid2 moves to su2 and both counts follow it
+        let mut moved = primary.get_process_scheduler("pid2").unwrap();
+        moved.scheduler_row_id = su2.row_id.unwrap();
+        primary.update_process_scheduler(&moved).unwrap();
+        primary
+            .increment_process_count(&su2.row_id.unwrap(), 1)
+            .unwrap();
+        primary
+            .decrement_process_count(&su1.row_id.unwrap(), 1)
+            .unwrap();
+        su1 = primary.get_scheduler(&su1.row_id.unwrap()).unwrap();
+        su1.weight = Some(2);
+        primary.update_scheduler(&su1).unwrap();
+        let su2 = primary.get_scheduler(&su2.row_id.unwrap()).unwrap();
+
+        primary.save_scheduler(&scheduler("https://su3")).unwrap();
+        let su3 = primary.get_scheduler_by_url("https://su3").unwrap();
+        primary.delete_scheduler(&su3.row_id.unwrap()).unwrap();
+        primary
+            .save_owner_scheduler("owner", &su2.row_id.unwrap())
+            .unwrap();
+
+        let standby = MemoryRouterStore::new();
+        let mut changes = vec![];
+        while let Ok(change) = receiver.try_recv() {
+            changes.push(change);
+        }
+        assert_eq!(changes.len(), 9);
+        for change in &changes {
+            apply_router_change(&standby, change).unwrap();
+        }
+        assert_eq!(
+            read_router_state(&standby).unwrap(),
+            read_router_state(&primary).unwrap()
+        );
+        let owner_scheduler = standby.get_owner_scheduler("owner").unwrap();
+        assert_eq!(
+            standby.get_scheduler(&owner_scheduler).unwrap().url,
+            "https://su2"
+        );
+
+        // applying the stream again changes nothing
+        for change in &changes {
+            apply_router_change(&standby, change).unwrap();
+        }
+        assert_eq!(
+            read_router_state(&standby).unwrap(),
+            read_router_state(&primary).unwrap()
+        );
+
+        // a standby streams nothing until it is promoted
+        let follower = StreamingRouterStore {
+            inner: Arc::new(standby),
+            role: Arc::new(RouterRole::new(true)),
+            queue: primary.queue.clone(),
+            logger: SuLog::init(),
+        };
+        follower.save_scheduler(&scheduler("https://su4")).unwrap();
+        assert!(receiver.try_recv().is_err());
+        follower.role.promote();
+        follower.save_scheduler(&scheduler("https://su5")).unwrap();
+        assert!(receiver.try_recv().is_ok());
+    }
+}
@@ -36,6 +36,15 @@ table! {
         no_route -> Nullable<Bool>,
         wallets_to_route -> Nullable<Text>,
         wallets_only -> Nullable<Bool>,
+        priority -> Nullable<Int4>,
+        large_objects -> Nullable<Bool>,
+        weight -> Nullable<Int4>,
+        public_url -> Nullable<Varchar>,
+        drain -> Nullable<Bool>,
+        max_processes -> Nullable<Int4>,
+        region -> Nullable<Varchar>,
+        tags_to_route -> Nullable<Varchar>,
+        wallets_policy -> Nullable<Varchar>,
     }
 }
 
@@ -44,7 +53,74 @@ table! {
         row_id -> Int4,
         process_id -> Varchar,
         scheduler_row_id -> Int4,
+        spawned_at -> Nullable<BigInt>,
     }
 }
 
-allow_tables_to_appear_in_same_query!(processes, messages, schedulers, process_schedulers,);
+table! {
+    owner_schedulers (owner) {
+        owner -> Varchar,
+        scheduler_row_id -> Int4,
+    }
+}
+
+table! {
+    process_stats (process_id) {
+        process_id -> Varchar,
+        message_count -> BigInt,
+        byte_count -> BigInt,
+        last_nonce -> Nullable<Int4>,
+    }
+}
+
+table! {
+    chain_snapshots (row_id) {
+        row_id -> Int4,
+        process_id -> Varchar,
+        epoch -> Int4,
+        nonce -> Int4,
+        timestamp -> BigInt,
+        hash_chain -> Text,
+        assignment_id -> Varchar,
+        signature -> Text,
+    }
+}
+
+table! {
+    inclusion_proofs (row_id) {
+        row_id -> Int4,
+        item_id -> Varchar,
+        bundle_id -> Varchar,
+        root -> Varchar,
+        leaf_index -> Int4,
+        leaf_count -> Int4,
+        path -> Text,
+    }
+}
+
+table! {
+    admin_audit (seq) {
+        seq -> BigInt,
+        timestamp -> BigInt,
+        actor -> Text,
+        client -> Text,
+        action -> Varchar,
+        target -> Text,
+        before_value -> Nullable<Jsonb>,
+        after_value -> Nullable<Jsonb>,
+        prev_hash -> Varchar,
+        hash -> Varchar,
+    }
+}
+
+allow_tables_to_appear_in_same_query!(
+    processes,
+    messages,
+    schedulers,
+    process_schedulers,
+    owner_schedulers,
+    process_stats,
+    chain_snapshots,
+    inclusion_proofs,
+    admin_audit,
+);
@@ -0,0 +1,213 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use tokio::spawn;
+use tokio::task::spawn_blocking;
+use tokio::time::{interval, Duration};
+
+use super::admin_audit::now_millis;
+use crate::domain::core::dal::{Log, SpawnAudit, SpawnAuditErrorType, SpawnEvent, SpawnQuery};
+use crate::domain::core::spawn_rollup::{compact, Compacted, Retention};
+
+/*
+  Appends each spawn as a json line to ROUTER_AUDIT_LOG,
+  the input of su simulate-routing. Outside of
+  compaction the file is only ever appended to, rotate
+  it with copytruncate.
+*/
+impl From<io::Error> for SpawnAuditErrorType {
+    fn from(error: io::Error) -> Self {
+        SpawnAuditErrorType::AuditError(format!("Spawn audit io error: {}", error))
+    }
+}
+
+const COMPACT_INTERVAL: u64 = 3600;
+
+pub struct SpawnAuditLog {
+    path: PathBuf,
+    file: Arc<Mutex<File>>,
+}
+
+impl SpawnAuditLog {
+    pub fn new(
+        path: &str,
+        retention: Retention,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, SpawnAuditErrorType> {
+        let file = Arc::new(Mutex::new(open(path)?));
+
+        if retention.enabled() {
+            let path = PathBuf::from(path);
+            let compact_file = file.clone();
+            spawn(async move {
+                let mut ticker = interval(Duration::from_secs(COMPACT_INTERVAL));
+                loop {
+                    ticker.tick().await;
+                    let (path, file, retention) =
+                        (path.clone(), compact_file.clone(), retention.clone());
+                    match spawn_blocking(move || compact_log(&path, &file, &retention)).await {
+                        Ok(Ok(Compacted {
+                            rolled_up: 0,
+                            dropped: 0,
+                            ..
+                        })) => (),
+                        Ok(Ok(compacted)) => logger.log(format!(
+                            "Compacted the router audit log, rolled up {} spawns and dropped {} lines",
+                            compacted.rolled_up, compacted.dropped
+                        )),
+                        Ok(Err(e)) => {
+                            logger.error(format!("Failed to compact the router audit log: {:?}", e))
+                        }
+                        Err(e) => {
+                            logger.error(format!("Failed to compact the router audit log: {:?}", e))
+                        }
+                    }
+                }
+            });
+        }
+
+        Ok(SpawnAuditLog {
+            path: PathBuf::from(path),
+            file,
+        })
+    }
+}
+
+fn open(path: impl AsRef<Path>) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+/*
+  Rewrite the log compacted, holding the lock so no spawn
+  is appended in between. The new file replaces the old
+  by a rename and the appends go on in the new one.
+*/
+fn compact_log(
+    path: &Path,
+    file: &Mutex<File>,
+    retention: &Retention,
+) -> Result<Compacted, SpawnAuditErrorType> {
+    let mut file = file
+        .lock()
+        .map_err(|_| SpawnAuditErrorType::AuditError("Audit log lock poisoned".to_string()))?;
+    let compacted = compact(&fs::read_to_string(path)?, now_millis() as u64, retention);
+    if compacted.rolled_up == 0 && compacted.dropped == 0 {
+        return Ok(compacted);
+    }
+
+    let tmp_path = path.with_extension("compacting");
+    let mut contents = compacted.lines.join("\n");
+    contents.push('\n');
+    fs::write(&tmp_path, contents)?;
+    fs::rename(&tmp_path, path)?;
+    *file = open(path)?;
+    Ok(compacted)
+}
+
+impl SpawnAudit for SpawnAuditLog {
+    fn record(&self, event: &SpawnEvent) -> Result<(), SpawnAuditErrorType> {
+        let mut line = serde_json::to_string(event)
+            .map_err(|e| SpawnAuditErrorType::AuditError(e.to_string()))?;
+        line.push('\n');
+        let mut file = self
+            .file
+            .lock()
+            .map_err(|_| SpawnAuditErrorType::AuditError("Audit log lock poisoned".to_string()))?;
+        file.write_all(line.as_bytes())?;
+        Ok(())
+    }
+
+    /*
+      Scans the whole log, rollups and a line still being
+      appended are not spawns and are passed over
+    */
+    fn read(&self, query: &SpawnQuery) -> Result<Vec<SpawnEvent>, SpawnAuditErrorType> {
+        let contents = fs::read_to_string(&self.path)?;
+        Ok(contents
+            .lines()
+            .rev()
+            .filter_map(|line| serde_json::from_str::<SpawnEvent>(line).ok())
+            .filter(|event| query.matches(event))
+            .take(query.limit)
+            .collect())
+    }
+}
+
+pub struct NoopSpawnAudit;
+
+impl SpawnAudit for NoopSpawnAudit {
+    fn record(&self, _event: &SpawnEvent) -> Result<(), SpawnAuditErrorType> {
+        Ok(())
+    }
+
+    fn read(&self, _query: &SpawnQuery) -> Result<Vec<SpawnEvent>, SpawnAuditErrorType> {
+        Err(SpawnAuditErrorType::AuditError(
+            "Routing decisions are only recorded with ROUTER_AUDIT_LOG set".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::logger::SuLog;
+
+    #[test]
+    fn test_decisions_are_read_newest_first() {
+        let path = std::env::temp_dir().join(format!("su-spawn-audit-{}.log", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let retention = Retention {
+            raw_hours: 0,
+            hourly_days: 0,
+            retention_days: 0,
+        };
+        let log = SpawnAuditLog::new(path.to_str().unwrap(), retention, SuLog::init()).unwrap();
+        let schedulers = ["https://su1", "https://su2", "https://su1"];
+        for (i, scheduler) in schedulers.iter().enumerate() {
+            log.record(&SpawnEvent {
+                timestamp: i as u64,
+                process_id: format!("p{}", i),
+                owner: "owner".to_string(),
+                size: 10,
+                scheduler: scheduler.to_string(),
+                rule: "least-count".to_string(),
+                region: None,
+                strategy: Some("least-count".to_string()),
+                candidates: vec!["https://su1".to_string(), "https://su2".to_string()],
+                skipped: vec![],
+            })
+            .unwrap();
+        }
+
+        let read = |query: SpawnQuery| {
+            log.read(&query)
+                .unwrap()
+                .into_iter()
+                .map(|event| event.process_id)
+                .collect::<Vec<_>>()
+        };
+        let all = SpawnQuery {
+            limit: 10,
+            ..SpawnQuery::default()
+        };
+        assert_eq!(read(all.clone()), vec!["p2", "p1", "p0"]);
+        assert_eq!(
+            read(SpawnQuery {
+                scheduler: Some("https://su1".to_string()),
+                limit: 1,
+                ..all.clone()
+            }),
+            vec!["p2"]
+        );
+        assert_eq!(
+            read(SpawnQuery {
+                process_id: Some("p1".to_string()),
+                ..all
+            }),
+            vec!["p1"]
+        );
+        fs::remove_file(&path).unwrap();
+    }
+}
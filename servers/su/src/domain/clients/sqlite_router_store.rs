@@ -0,0 +1,550 @@
+use std::sync::{Mutex, MutexGuard};
+
+use diesel::connection::SimpleConnection;
+use diesel::prelude::*;
+use diesel::result::Error as DieselError;
+use diesel::sqlite::SqliteConnection;
+
+use super::admin_audit::now_millis;
+use super::store::{
+    wallets_column, DbProcessScheduler, DbScheduler, NewProcessScheduler, NewScheduler,
+};
+use crate::domain::core::dal::{
+    Assignment, ProcessScheduler, RouterDataStore, Scheduler, StoreErrorType,
+};
+use crate::domain::core::scheduler_list::split_wallets;
+
+/*
+  The router tables kept in the same shape as the postgres
+  migrations create them, a column added to schedulers or
+  process_schedulers there needs adding here as well
+*/
+const CREATE_TABLES: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA busy_timeout = 5000;
+    CREATE TABLE IF NOT EXISTS schedulers (
+        row_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        url TEXT NOT NULL UNIQUE,
+        process_count INTEGER NOT NULL DEFAULT 0,
+        no_route BOOLEAN,
+        wallets_to_route TEXT,
+        wallets_only BOOLEAN,
+        priority INTEGER,
+        large_objects BOOLEAN,
+        weight INTEGER,
+        public_url TEXT,
+        drain BOOLEAN,
+        max_processes INTEGER,
+        region TEXT,
+        tags_to_route TEXT,
+        wallets_policy TEXT
+    );
+    CREATE TABLE IF NOT EXISTS process_schedulers (
+        row_id INTEGER PRIMARY KEY AUTOINCREMENT,
+        process_id TEXT NOT NULL UNIQUE,
+        scheduler_row_id INTEGER NOT NULL,
+        spawned_at BIGINT
+    );
+    CREATE INDEX IF NOT EXISTS process_schedulers_scheduler_row_id
+        ON process_schedulers (scheduler_row_id);
+    CREATE TABLE IF NOT EXISTS owner_schedulers (
+        owner TEXT PRIMARY KEY,
+        scheduler_row_id INTEGER NOT NULL
+    );
+";
+
+/*
+  Columns added to the router tables since a router store
+  file could first be created. sqlite has no ADD COLUMN IF NOT
+  EXISTS, so an existing file that already has one fails
+  with a duplicate column error and is left as it is.
+*/
+const ADDED_COLUMNS: &[&str] = &[
+    "ALTER TABLE schedulers ADD COLUMN max_processes INTEGER",
+    "ALTER TABLE schedulers ADD COLUMN region TEXT",
+    "ALTER TABLE schedulers ADD COLUMN tags_to_route TEXT",
+    "ALTER TABLE schedulers ADD COLUMN wallets_policy TEXT",
+    "ALTER TABLE process_schedulers ADD COLUMN spawned_at BIGINT",
+];
+
+// indexes of the listing, on columns that may only just have been added
+const LISTING_INDEXES: &str = "
+    CREATE INDEX IF NOT EXISTS process_schedulers_scheduler_process_id
+        ON process_schedulers (scheduler_row_id, process_id);
+    CREATE INDEX IF NOT EXISTS process_schedulers_spawned_at_process_id
+        ON process_schedulers (spawned_at, process_id);
+";
+
+/*
+  Rewrite the comma separated wallets_to_route of a file
+  from before the lists were json arrays, as the postgres
+  migration does
+*/
+fn migrate_wallet_lists(conn: &mut SqliteConnection) -> Result<(), StoreErrorType> {
+    use super::schema::schedulers::dsl::*;
+
+    let joined: Vec<(i32, String)> = schedulers
+        .filter(wallets_to_route.not_like("[%"))
+        .select((row_id, wallets_to_route.assume_not_null()))
+        .load(conn)?;
+    for (row_id_in, wallets) in joined {
+        diesel::update(schedulers.filter(row_id.eq(row_id_in)))
+            .set(wallets_to_route.eq(wallets_column(&Some(split_wallets(&wallets)))))
+            .execute(conn)?;
+    }
+    Ok(())
+}
+
+/*
+  A router data store in a single sqlite file, so a router
+  can run without a postgres server. There is one
+  connection behind a mutex, the router writes a row per
+  spawn and reads are served from the route cache most of
+  the time, so this is not the bottleneck.
+*/
+pub struct SqliteRouterStore {
+    conn: Mutex<SqliteConnection>,
+}
+
+impl SqliteRouterStore {
+    pub fn new(path: &str) -> Result<Self, StoreErrorType> {
+        let mut conn = SqliteConnection::establish(path).map_err(|e| {
+            StoreErrorType::DatabaseError(format!("Failed to open sqlite router store: {}", e))
+        })?;
+        conn.batch_execute(CREATE_TABLES)?;
+        for statement in ADDED_COLUMNS {
+            match conn.batch_execute(statement) {
+                Err(e) if !e.to_string().contains("duplicate column") => return Err(e.into()),
+                _ => (),
+            }
+        }
+        conn.batch_execute(LISTING_INDEXES)?;
+        migrate_wallet_lists(&mut conn)?;
+        Ok(SqliteRouterStore {
+            conn: Mutex::new(conn),
+        })
+    }
+
+    fn get_conn(&self) -> Result<MutexGuard<'_, SqliteConnection>, StoreErrorType> {
+        self.conn
+            .lock()
+            .map_err(|_| StoreErrorType::DatabaseError("Router store lock poisoned".to_string()))
+    }
+}
+
+impl RouterDataStore for SqliteRouterStore {
+    fn save_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let new_process_scheduler = NewProcessScheduler {
+            process_id: &process_scheduler.process_id,
+            scheduler_row_id: &process_scheduler.scheduler_row_id,
+            spawned_at: None,
+        };
+
+        diesel::insert_or_ignore_into(process_schedulers)
+            .values(&new_process_scheduler)
+            .execute(conn)?;
+        Ok("saved".to_string())
+    }
+
+    fn get_process_scheduler(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row: Option<DbProcessScheduler> = process_schedulers
+            .filter(process_id.eq(process_id_in))
+            .first(conn)
+            .optional()?;
+
+        row.map(ProcessScheduler::from).ok_or(StoreErrorType::NotFound(
+            "Process scheduler not found".to_string(),
+        ))
+    }
+
+    // there are no replicas to lag behind
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        self.get_process_scheduler(process_id_in)
+    }
+
+    fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let wallets = wallets_column(&scheduler.wallets_to_route);
+        let new_scheduler = NewScheduler {
+            url: &scheduler.url,
+            process_count: &scheduler.process_count,
+            no_route: scheduler.no_route.as_ref(),
+            wallets_to_route: wallets.as_deref(),
+            wallets_only: scheduler.wallets_only.as_ref(),
+            priority: scheduler.priority.as_ref(),
+            large_objects: scheduler.large_objects.as_ref(),
+            weight: scheduler.weight.as_ref(),
+            public_url: scheduler.public_url.as_deref(),
+            drain: scheduler.drain.as_ref(),
+            max_processes: scheduler.max_processes.as_ref(),
+            region: scheduler.region.as_deref(),
+            tags_to_route: scheduler.tags_to_route.as_deref(),
+            wallets_policy: scheduler.wallets_policy.as_deref(),
+        };
+
+        diesel::insert_or_ignore_into(schedulers)
+            .values(&new_scheduler)
+            .execute(conn)?;
+        Ok("saved".to_string())
+    }
+
+    fn update_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row_id_in = scheduler.row_id.ok_or(StoreErrorType::DatabaseError(
+            "Missing id on scheduler".to_string(),
+        ))?;
+        diesel::update(schedulers.filter(row_id.eq(row_id_in)))
+            .set((
+                url.eq(&scheduler.url),
+                no_route.eq(&scheduler.no_route),
+                wallets_to_route.eq(wallets_column(&scheduler.wallets_to_route)),
+                wallets_only.eq(&scheduler.wallets_only),
+                priority.eq(&scheduler.priority),
+                large_objects.eq(&scheduler.large_objects),
+                weight.eq(&scheduler.weight),
+                public_url.eq(&scheduler.public_url),
+                drain.eq(&scheduler.drain),
+                max_processes.eq(&scheduler.max_processes),
+                region.eq(&scheduler.region),
+                tags_to_route.eq(&scheduler.tags_to_route),
+                wallets_policy.eq(&scheduler.wallets_policy),
+            ))
+            .execute(conn)?;
+        Ok("updated".to_string())
+    }
+
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+            .set(process_count.eq(process_count + by))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    // a count smaller than by goes to 0 rather than below it
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        conn.transaction::<_, DieselError, _>(|conn| {
+            let updated = diesel::update(
+                schedulers.filter(row_id.eq(scheduler_row_id_in).and(process_count.ge(by))),
+            )
+            .set(process_count.eq(process_count - by))
+            .execute(conn)?;
+            if updated == 0 {
+                diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+                    .set(process_count.eq(0))
+                    .execute(conn)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
+    fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row: Option<DbScheduler> = schedulers
+            .filter(row_id.eq(row_id_in))
+            .first(conn)
+            .optional()?;
+
+        row.map(Scheduler::from)
+            .ok_or(StoreErrorType::NotFound("Scheduler not found".to_string()))
+    }
+
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row: Option<DbScheduler> = schedulers.filter(url.eq(url_in)).first(conn).optional()?;
+
+        row.map(Scheduler::from)
+            .ok_or(StoreErrorType::NotFound("Scheduler not found".to_string()))
+    }
+
+    fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let rows: Vec<DbScheduler> = schedulers.order(row_id.asc()).load(conn)?;
+        Ok(rows.into_iter().map(Scheduler::from).collect())
+    }
+
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row_id_in = process_scheduler.row_id.ok_or(StoreErrorType::DatabaseError(
+            "Missing id on process scheduler".to_string(),
+        ))?;
+        diesel::update(process_schedulers.filter(row_id.eq(row_id_in)))
+            .set(scheduler_row_id.eq(process_scheduler.scheduler_row_id))
+            .execute(conn)?;
+        Ok("updated".to_string())
+    }
+
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        diesel::delete(process_schedulers.filter(row_id.eq(row_id_in))).execute(conn)?;
+        Ok("deleted".to_string())
+    }
+
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        match diesel::delete(schedulers.filter(row_id.eq(row_id_in))).execute(conn)? {
+            0 => Err(StoreErrorType::NotFound("Scheduler not found".to_string())),
+            _ => Ok("deleted".to_string()),
+        }
+    }
+
+    // there is no foreign key on scheduler_row_id here
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        use super::schema::schedulers::dsl as scheduler_dsl;
+        let conn = &mut *self.get_conn()?;
+
+        let rows: Result<Vec<DbProcessScheduler>, DieselError> = process_schedulers
+            .filter(diesel::dsl::not(scheduler_row_id.eq_any(
+                scheduler_dsl::schedulers.select(scheduler_dsl::row_id),
+            )))
+            .order(row_id.asc())
+            .load(conn);
+
+        Ok(rows?.into_iter().map(ProcessScheduler::from).collect())
+    }
+
+    // process_id is unique from the start here
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        Ok(vec![])
+    }
+
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let counts: Vec<(i32, i64)> = process_schedulers
+            .group_by(scheduler_row_id)
+            .select((scheduler_row_id, diesel::dsl::count_star()))
+            .load(conn)?;
+
+        Ok(counts)
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let rows: Vec<DbProcessScheduler> = process_schedulers
+            .filter(scheduler_row_id.eq(scheduler_row_id_in))
+            .filter(row_id.gt(after_row_id))
+            .order(row_id.asc())
+            .limit(limit)
+            .load(conn)?;
+
+        Ok(rows.into_iter().map(ProcessScheduler::from).collect())
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let rows: Vec<DbProcessScheduler> = process_schedulers
+            .filter(row_id.gt(after_row_id))
+            .order(row_id.asc())
+            .limit(limit)
+            .load(conn)?;
+
+        Ok(rows.into_iter().map(ProcessScheduler::from).collect())
+    }
+
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let mut query = process_schedulers
+            .filter(process_id.gt(after_process_id))
+            .into_boxed();
+        if let Some(scheduler) = scheduler_row_id_in {
+            query = query.filter(scheduler_row_id.eq(scheduler));
+        }
+        if let Some(after) = spawned_after {
+            query = query.filter(spawned_at.gt(after));
+        }
+        let rows: Vec<DbProcessScheduler> =
+            query.order(process_id.asc()).limit(limit).load(conn)?;
+
+        Ok(rows
+            .into_iter()
+            .map(|row| {
+                let spawned = row.spawned_at;
+                (ProcessScheduler::from(row), spawned)
+            })
+            .collect())
+    }
+
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        use super::schema::process_schedulers::dsl as ps;
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let new_process_scheduler = NewProcessScheduler {
+            process_id: process_id_in,
+            scheduler_row_id: scheduler_row_id_in,
+            spawned_at: Some(now_millis()),
+        };
+
+        let (db_scheduler, duplicate) = conn.transaction::<_, DieselError, _>(|conn| {
+            let row_count = diesel::insert_or_ignore_into(ps::process_schedulers)
+                .values(&new_process_scheduler)
+                .execute(conn)?;
+            let assigned_row_id = match row_count {
+                0 => ps::process_schedulers
+                    .filter(ps::process_id.eq(process_id_in))
+                    .select(ps::scheduler_row_id)
+                    .first(conn)?,
+                _ => {
+                    diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+                        .set(process_count.eq(process_count + 1))
+                        .execute(conn)?;
+                    *scheduler_row_id_in
+                }
+            };
+            let db_scheduler: DbScheduler =
+                schedulers.filter(row_id.eq(assigned_row_id)).first(conn)?;
+            Ok((db_scheduler, row_count == 0))
+        })?;
+
+        Ok(Assignment {
+            scheduler: Scheduler::from(db_scheduler),
+            duplicate,
+        })
+    }
+
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::process_schedulers::dsl as ps;
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let moved = conn.transaction::<_, DieselError, _>(|conn| {
+            let row_count = diesel::update(
+                ps::process_schedulers.filter(
+                    ps::process_id
+                        .eq(process_id_in)
+                        .and(ps::scheduler_row_id.eq(from_row_id)),
+                ),
+            )
+            .set(ps::scheduler_row_id.eq(to_row_id))
+            .execute(conn)?;
+            if row_count == 0 {
+                return Ok(false);
+            }
+            diesel::update(schedulers.filter(row_id.eq(to_row_id)))
+                .set(process_count.eq(process_count + 1))
+                .execute(conn)?;
+            diesel::update(schedulers.filter(row_id.eq(from_row_id).and(process_count.gt(0))))
+                .set(process_count.eq(process_count - 1))
+                .execute(conn)?;
+            Ok(true)
+        })?;
+
+        match moved {
+            true => Ok(()),
+            false => Err(StoreErrorType::NotFound(format!(
+                "Process {} is not on scheduler {}",
+                process_id_in, from_row_id
+            ))),
+        }
+    }
+
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType> {
+        use super::schema::owner_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        let row: Option<i32> = owner_schedulers
+            .filter(owner.eq(owner_in))
+            .select(scheduler_row_id)
+            .first(conn)
+            .optional()?;
+
+        row.ok_or(StoreErrorType::NotFound(
+            "Owner scheduler not found".to_string(),
+        ))
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::owner_schedulers::dsl::*;
+        let conn = &mut *self.get_conn()?;
+
+        diesel::replace_into(owner_schedulers)
+            .values((owner.eq(owner_in), scheduler_row_id.eq(scheduler_row_id_in)))
+            .execute(conn)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,183 @@
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use tokio::spawn;
+use tokio::sync::mpsc::{channel, error::TrySendError, Receiver, Sender};
+use tokio::time::{sleep, Duration};
+
+use crate::domain::core::dal::{Log, Replica, Replicator, ReplicatorErrorType};
+
+/*
+  Forwards every committed process and message to a
+  standby su so it holds a warm copy of the schedule and
+  can take over the process set on failover.
+
+  In sync mode a write waits until the standby has stored
+  the item, so whatever a client was told is scheduled is
+  on both. Otherwise items are queued and a background task
+  sends them in order, retrying each one until the standby
+  accepts it. The queue is in memory, items still in it
+  when the su stops, or that do not fit in it, are missing
+  on the standby.
+*/
+
+const QUEUE_SIZE: usize = 10000;
+const REQUEST_TIMEOUT: u64 = 5;
+const RETRY_DELAY: u64 = 1;
+
+impl From<reqwest::Error> for ReplicatorErrorType {
+    fn from(error: reqwest::Error) -> Self {
+        ReplicatorErrorType::ReplicateError(format!("Standby request error: {}", error))
+    }
+}
+
+struct StandbyTarget {
+    client: Client,
+    url: Url,
+    token: String,
+}
+
+impl StandbyTarget {
+    async fn send(&self, replica: &Replica) -> Result<(), ReplicatorErrorType> {
+        let mut url = self.url.clone();
+        url.query_pairs_mut()
+            .append_pair("type", replica.kind.as_str());
+        if let Some(deep_hash) = &replica.deep_hash {
+            url.query_pairs_mut().append_pair("deep-hash", deep_hash);
+        }
+
+        let response = self
+            .client
+            .post(url)
+            .bearer_auth(&self.token)
+            .body(replica.binary.clone())
+            .timeout(Duration::from_secs(REQUEST_TIMEOUT))
+            .send()
+            .await?;
+
+        let status = response.status();
+        if status.is_success() {
+            return Ok(());
+        }
+        let body = response.text().await.unwrap_or_default();
+        Err(ReplicatorErrorType::ReplicateError(format!(
+            "Standby returned {}: {}",
+            status, body
+        )))
+    }
+}
+
+pub struct StandbyClient {
+    target: Arc<StandbyTarget>,
+    // None in sync mode
+    queue: Option<Sender<Replica>>,
+}
+
+impl StandbyClient {
+    pub fn new(
+        standby_url: &str,
+        standby_token: &str,
+        sync: bool,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, String> {
+        let mut url = Url::parse(standby_url).map_err(|e| format!("Invalid standby url: {}", e))?;
+        url.set_path("/admin/replica");
+
+        let target = Arc::new(StandbyTarget {
+            client: Client::new(),
+            url,
+            token: standby_token.to_string(),
+        });
+
+        let queue = match sync {
+            true => None,
+            false => {
+                let (sender, receiver) = channel(QUEUE_SIZE);
+                spawn(deliver(target.clone(), receiver, logger));
+                Some(sender)
+            }
+        };
+
+        Ok(StandbyClient { target, queue })
+    }
+}
+
+#[async_trait]
+impl Replicator for StandbyClient {
+    async fn replicate(&self, replica: Replica) -> Result<(), ReplicatorErrorType> {
+        match &self.queue {
+            None => self.target.send(&replica).await,
+            Some(queue) => match queue.try_send(replica) {
+                Ok(()) => Ok(()),
+                Err(TrySendError::Full(_)) => Err(ReplicatorErrorType::ReplicateError(
+                    "Standby queue is full, item dropped".to_string(),
+                )),
+                Err(TrySendError::Closed(_)) => Err(ReplicatorErrorType::ReplicateError(
+                    "Standby delivery stopped".to_string(),
+                )),
+            },
+        }
+    }
+}
+
+/*
+  Used when no STANDBY_URL is configured
+*/
+pub struct NoopReplicator;
+
+#[async_trait]
+impl Replicator for NoopReplicator {
+    async fn replicate(&self, _replica: Replica) -> Result<(), ReplicatorErrorType> {
+        Ok(())
+    }
+}
+
+async fn deliver(
+    target: Arc<StandbyTarget>,
+    mut receiver: Receiver<Replica>,
+    logger: Arc<dyn Log>,
+) {
+    while let Some(replica) = receiver.recv().await {
+        let mut failures = 0;
+        while let Err(e) = target.send(&replica).await {
+            // log the first failure and then every minute or so
+            if failures % 60 == 0 {
+                logger.error(format!(
+                    "Failed to replicate {} to standby, retrying: {:?}",
+                    replica.kind.as_str(),
+                    e
+                ));
+            }
+            failures += 1;
+            sleep(Duration::from_secs(RETRY_DELAY)).await;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::core::dal::ReplicaKind;
+    use crate::domain::logger::SuLog;
+
+    fn replica() -> Replica {
+        Replica {
+            kind: ReplicaKind::Message,
+            binary: vec![1, 2, 3],
+            deep_hash: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn sync_mode_reports_standby_failures() {
+        let unreachable = "http://127.0.0.1:1";
+
+        let sync = StandbyClient::new(unreachable, "token", true, SuLog::init()).unwrap();
+        assert!(sync.replicate(replica()).await.is_err());
+
+        // async mode only queues, delivery is retried in the background
+        let queued = StandbyClient::new(unreachable, "token", false, SuLog::init()).unwrap();
+        assert!(queued.replicate(replica()).await.is_ok());
+    }
+}
@@ -1,11 +1,14 @@
 use std::env::VarError;
 use std::num::NonZeroUsize;
 use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
 use std::{env, io};
 
 use async_trait::async_trait;
-use diesel::pg::PgConnection;
+use dashmap::DashMap;
+use diesel::migration::MigrationSource;
+use diesel::pg::{Pg, PgConnection};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::r2d2::Pool;
@@ -19,11 +22,17 @@ use tokio::time::interval;
 
 use super::super::SuLog;
 
+use super::super::core::audit;
 use super::super::core::dal::{
-    DataStore, JsonErrorType, Log, Message, PaginatedMessages, Process, ProcessScheduler,
-    RouterDataStore, Scheduler, StoreErrorType,
+    AdminAudit, AdminAuditErrorType, AdminChange, Assignment, AuditEntry, ChainSnapshot,
+    CoreMetrics, DataStore, ExportItem, InclusionProof, JsonErrorType, Log, Message,
+    PaginatedMessages, Process, ProcessScheduler, ProcessStats, RouterDataStore, Scheduler,
+    StoreErrorType, Tag,
 };
+use super::super::core::scheduler_list::split_wallets;
 
+use super::admin_audit::now_millis;
+use super::write_batch::WriteBatcher;
 use crate::domain::config::AoConfig;
 
 pub const MIGRATIONS: EmbeddedMigrations = embed_migrations!("./migrations");
@@ -60,6 +69,107 @@ impl From<std::num::ParseIntError> for StoreErrorType {
     }
 }
 
+/*
+  Bump the per process counters, called inside the
+  same transaction as the insert it is counting so the
+  process_stats table never drifts from messages.
+*/
+fn increment_process_stats(
+    conn: &mut PgConnection,
+    process_id_in: &str,
+    messages_in: i64,
+    bytes_in: i64,
+    nonce_in: Option<i32>,
+) -> Result<usize, DieselError> {
+    use super::schema::process_stats::dsl::*;
+
+    diesel::insert_into(process_stats)
+        .values((
+            process_id.eq(process_id_in),
+            message_count.eq(messages_in),
+            byte_count.eq(bytes_in),
+            last_nonce.eq(nonce_in),
+        ))
+        .on_conflict(process_id)
+        .do_update()
+        .set((
+            message_count.eq(message_count + messages_in),
+            byte_count.eq(byte_count + bytes_in),
+            last_nonce.eq(nonce_in),
+        ))
+        .execute(conn)
+}
+
+// the message and its process stats, 0 when nothing was inserted
+fn insert_message(conn: &mut PgConnection, new_message: &NewMessage) -> Result<usize, DieselError> {
+    use super::schema::messages::dsl::*;
+
+    let row_count = diesel::insert_into(messages)
+        .values(new_message)
+        .execute(conn)?;
+    if row_count > 0 {
+        increment_process_stats(
+            conn,
+            new_message.process_id,
+            1,
+            new_message.bundle.len() as i64,
+            Some(*new_message.nonce),
+        )?;
+    }
+    Ok(row_count)
+}
+
+fn save_message_row(
+    conn: &mut PgConnection,
+    new_message: &NewMessage,
+) -> Result<(), StoreErrorType> {
+    match conn.transaction::<_, DieselError, _>(|conn| insert_message(conn, new_message)) {
+        Ok(0) => Err(StoreErrorType::DatabaseError(
+            "Error saving message".to_string(),
+        )),
+        Ok(_) => Ok(()),
+        Err(e) => Err(StoreErrorType::from(e)),
+    }
+}
+
+/*
+  Flush for the write batcher, the whole batch is saved
+  in one transaction. When that fails the messages are
+  saved one by one so only the ones at fault fail.
+*/
+fn save_message_batch(
+    pool: &Pool<ConnectionManager<PgConnection>>,
+    batch: Vec<PendingMessage>,
+) -> Vec<Result<(), String>> {
+    let mut conn = match pool.get() {
+        Ok(conn) => conn,
+        Err(_) => {
+            return batch
+                .iter()
+                .map(|_| Err("Failed to get connection from pool.".to_string()))
+                .collect()
+        }
+    };
+
+    let rows: Vec<NewMessage> = batch.iter().map(PendingMessage::row).collect();
+    if rows.len() > 1 {
+        let saved = conn.transaction::<_, DieselError, _>(|conn| {
+            for row in &rows {
+                if insert_message(conn, row)? == 0 {
+                    return Err(DieselError::RollbackTransaction);
+                }
+            }
+            Ok(())
+        });
+        if saved.is_ok() {
+            return rows.iter().map(|_| Ok(())).collect();
+        }
+    }
+    rows.iter()
+        .map(|row| save_message_row(&mut conn, row).map_err(String::from))
+        .collect()
+}
+
 struct InMemoryCache {
     process_cache: Mutex<LruCache<String, Process>>,
 }
@@ -96,6 +206,124 @@ pub struct StoreClient {
     pub bytestore: Arc<bytestore::ByteStore>,
     in_memory_cache: InMemoryCache,
     enable_process_assignment: bool,
+
+    /*
+      Replica read settings, both are off when zero or
+      when DATABASE_READ_URL is not a separate database
+    */
+    read_hedge_delay: u64,
+    read_freshness_window: u64,
+    recent_writes: Arc<DashMap<String, RecentWrite>>,
+
+    /*
+      One pool per ROUTER_READ_URLS replica, the router's
+      process and scheduler lookups take turns over them
+    */
+    router_read_pools: Vec<Pool<ConnectionManager<PgConnection>>>,
+    next_router_read: AtomicUsize,
+
+    // message inserts go through it when WRITE_BATCH_MAX is set
+    write_batch: Option<WriteBatcher<PendingMessage>>,
+}
+
+/*
+  The first nonce and timestamp written for a process
+  in the current bucket, anything at or after them may
+  not have reached the replica yet
+*/
+struct RecentWrite {
+    nonce: i32,
+    timestamp: i64,
+    // the start of the previous bucket if it had writes inside the window
+    previous: Option<(i32, i64)>,
+    bucket_start: Instant,
+    last_write: Instant,
+    // the highest nonce written and the highest the probe found on the replica
+    last_nonce: i32,
+    replicated_nonce: Option<i32>,
+}
+
+impl RecentWrite {
+    fn replicated(&self) -> bool {
+        matches!(self.replicated_nonce, Some(n) if n >= self.last_nonce)
+    }
+}
+
+/*
+  Replication lag probe, every READ_WATERMARK_INTERVAL
+  milliseconds it reads the highest nonce the replica
+  has for each process with writes it hasn't seen yet.
+  Errors leave the watermarks where they are, reads then
+  fall back on READ_FRESHNESS_WINDOW alone.
+*/
+fn spawn_watermark_probe(
+    read_pool: Pool<ConnectionManager<PgConnection>>,
+    recent_writes: Arc<DashMap<String, RecentWrite>>,
+    interval_ms: u64,
+) {
+    let probe = move || loop {
+        std::thread::sleep(Duration::from_millis(interval_ms));
+        let lagging = recent_writes
+            .iter()
+            .filter(|recent| !recent.replicated())
+            .map(|recent| recent.key().clone())
+            .collect::<Vec<_>>();
+        if lagging.is_empty() {
+            continue;
+        }
+
+        let mut conn = match read_pool.get() {
+            Ok(conn) => conn,
+            Err(_) => continue,
+        };
+        let watermarks: Vec<(String, Option<i32>)> = {
+            use super::schema::messages::dsl::*;
+            messages
+                .filter(process_id.eq_any(&lagging))
+                .group_by(process_id)
+                .select((process_id, diesel::dsl::max(nonce)))
+                .load(&mut conn)
+                .unwrap_or_default()
+        };
+        for (process, watermark) in watermarks {
+            if let (Some(mut recent), Some(watermark)) =
+                (recent_writes.get_mut(&process), watermark)
+            {
+                recent.replicated_nonce = recent.replicated_nonce.max(Some(watermark));
+            }
+        }
+    };
+    if let Err(e) = std::thread::Builder::new()
+        .name("replica-watermark".to_string())
+        .spawn(probe)
+    {
+        SuLog::init().error(format!(
+            "Failed to start the replica watermark probe: {}",
+            e
+        ));
+    }
+}
+
+type ReadResult<T> = (bool, Result<Option<T>, StoreErrorType>);
+
+fn spawn_read<T, F>(
+    pool: Pool<ConnectionManager<PgConnection>>,
+    primary: bool,
+    query: Arc<F>,
+    tx: mpsc::Sender<ReadResult<T>>,
+) where
+    T: Send + 'static,
+    F: Fn(&mut PgConnection) -> Result<Option<T>, DieselError> + Send + Sync + 'static,
+{
+    tokio::task::spawn_blocking(move || {
+        let result = match pool.get() {
+            Ok(mut conn) => query(&mut conn).map_err(StoreErrorType::from),
+            Err(_) => Err(StoreErrorType::DatabaseError(
+                "Failed to get connection from pool.".to_string(),
+            )),
+        };
+        let _ = tx.send((primary, result));
+    });
 }
 
 /*
@@ -115,6 +343,7 @@ impl StoreClient {
     pub fn new() -> Result<Self, StoreErrorType> {
         let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
         let c_clone = config.clone();
+        let replica_enabled = config.database_read_url != config.database_url;
         let database_url = config.database_url;
         let database_read_url = config.database_read_url;
         let manager = ConnectionManager::<PgConnection>::new(database_url);
@@ -139,6 +368,37 @@ impl StoreClient {
                 )
             })?;
 
+        /*
+          Built unchecked so a replica that is down at startup
+          doesn't stop the router, its lookups go to the writer
+          until it comes up
+        */
+        let router_read_pools = config
+            .router_read_urls
+            .iter()
+            .map(|router_read_url| {
+                Pool::builder()
+                    .max_size(config.db_read_connections)
+                    .connection_timeout(Duration::from_secs(1))
+                    .test_on_check_out(true)
+                    .build_unchecked(ConnectionManager::<PgConnection>::new(router_read_url))
+            })
+            .collect();
+
+        let read_freshness_window = if replica_enabled {
+            config.read_freshness_window
+        } else {
+            0
+        };
+        let recent_writes = Arc::new(DashMap::new());
+        if read_freshness_window > 0 && config.read_watermark_interval > 0 {
+            spawn_watermark_probe(
+                read_pool.clone(),
+                recent_writes.clone(),
+                config.read_watermark_interval,
+            );
+        }
+
         Ok(StoreClient {
             pool,
             read_pool,
@@ -146,6 +406,12 @@ impl StoreClient {
             bytestore: Arc::new(bytestore::ByteStore::new(c_clone)),
             in_memory_cache: InMemoryCache::new(config.process_cache_size),
             enable_process_assignment: config.enable_process_assignment,
+            read_hedge_delay: if replica_enabled { config.read_hedge_delay } else { 0 },
+            read_freshness_window,
+            recent_writes,
+            router_read_pools,
+            next_router_read: AtomicUsize::new(0),
+            write_batch: None,
         })
     }
 
@@ -183,9 +449,38 @@ impl StoreClient {
             bytestore: Arc::new(bytestore::ByteStore::new(c_clone)),
             in_memory_cache: InMemoryCache::new(config.process_cache_size),
             enable_process_assignment: config.enable_process_assignment,
+            read_hedge_delay: 0,
+            read_freshness_window: 0,
+            recent_writes: Arc::new(DashMap::new()),
+            router_read_pools: vec![],
+            next_router_read: AtomicUsize::new(0),
+            write_batch: None,
         })
     }
 
+    /*
+      Save messages through a write batcher that grows
+      its batches under load, max_size 0 keeps every
+      message in a transaction of its own
+    */
+    pub fn with_write_batch(
+        mut self,
+        max_size: usize,
+        max_wait: u64,
+        metrics: Arc<dyn CoreMetrics>,
+    ) -> Result<Self, StoreErrorType> {
+        if max_size > 0 {
+            let pool = self.pool.clone();
+            let batcher =
+                WriteBatcher::start("write-batch", max_size, max_wait, metrics, move |batch| {
+                    save_message_batch(&pool, batch)
+                })
+                .map_err(StoreErrorType::DatabaseError)?;
+            self.write_batch = Some(batcher);
+        }
+        Ok(self)
+    }
+
     /*
       Get a connection to the writer database using
       the connection pool initialized in r2d2. This
@@ -216,6 +511,167 @@ impl StoreClient {
         })
     }
 
+    /*
+      A router table lookup on the next ROUTER_READ_URLS
+      replica. The writer answers instead when the replica
+      can't be reached, errors or hasn't got the row yet,
+      as with a process spawned a moment ago. Without
+      replicas it is an ordinary read on the read pool.
+    */
+    fn router_read<T, F>(&self, query: F) -> Result<Option<T>, StoreErrorType>
+    where
+        F: Fn(&mut PgConnection) -> Result<Option<T>, DieselError>,
+    {
+        if self.router_read_pools.is_empty() {
+            let conn = &mut self.get_read_conn()?;
+            return query(conn).map_err(StoreErrorType::from);
+        }
+
+        let at =
+            self.next_router_read.fetch_add(1, Ordering::Relaxed) % self.router_read_pools.len();
+        if let Ok(mut conn) = self.router_read_pools[at].get() {
+            if let Ok(Some(row)) = query(&mut conn) {
+                return Ok(Some(row));
+            }
+        }
+        let conn = &mut self.get_conn()?;
+        query(conn).map_err(StoreErrorType::from)
+    }
+
+    /*
+      Point lookup against the replica that falls back to
+      the writer when the replica errors or does not have
+      the row yet. If the replica has not answered within
+      READ_HEDGE_DELAY milliseconds the same query is sent
+      to the writer too and the first row found wins.
+    */
+    fn hedged_read<T, F>(&self, query: F) -> Result<Option<T>, StoreErrorType>
+    where
+        T: Send + 'static,
+        F: Fn(&mut PgConnection) -> Result<Option<T>, DieselError> + Send + Sync + 'static,
+    {
+        if self.read_hedge_delay == 0 || tokio::runtime::Handle::try_current().is_err() {
+            let conn = &mut self.get_read_conn()?;
+            return query(conn).map_err(StoreErrorType::from);
+        }
+
+        let query = Arc::new(query);
+        let (tx, rx) = mpsc::channel();
+        spawn_read(self.read_pool.clone(), false, query.clone(), tx.clone());
+
+        match rx.recv_timeout(Duration::from_millis(self.read_hedge_delay)) {
+            Ok((_, Ok(Some(row)))) => return Ok(Some(row)),
+            Ok(_) => {
+                let conn = &mut self.get_conn()?;
+                return query(conn).map_err(StoreErrorType::from);
+            }
+            Err(_) => spawn_read(self.pool.clone(), true, query, tx),
+        }
+
+        // both are running, prefer any row then the writer's answer
+        let mut primary_result = Ok(None);
+        while let Ok((primary, result)) = rx.recv() {
+            match result {
+                Ok(Some(row)) => return Ok(Some(row)),
+                result if primary => primary_result = result,
+                _ => (),
+            }
+        }
+        primary_result
+    }
+
+    /*
+      Remember the writes made in roughly the last
+      READ_FRESHNESS_WINDOW milliseconds per process, in
+      buckets of one window so the boundary errs towards
+      sending reads to the writer
+    */
+    fn record_write(&self, process_id_in: &str, nonce_in: i32, timestamp_in: i64) {
+        if self.read_freshness_window == 0 {
+            return;
+        }
+        let window = Duration::from_millis(self.read_freshness_window);
+        let now = Instant::now();
+
+        let mut recent = self
+            .recent_writes
+            .entry(process_id_in.to_string())
+            .or_insert(RecentWrite {
+                nonce: nonce_in,
+                timestamp: timestamp_in,
+                previous: None,
+                bucket_start: now,
+                last_write: now,
+                last_nonce: nonce_in,
+                replicated_nonce: None,
+            });
+        if now.duration_since(recent.bucket_start) >= window {
+            recent.previous = if now.duration_since(recent.last_write) < window {
+                Some((recent.nonce, recent.timestamp))
+            } else {
+                None
+            };
+            recent.nonce = nonce_in;
+            recent.timestamp = timestamp_in;
+            recent.bucket_start = now;
+        }
+        recent.last_write = now;
+        recent.last_nonce = recent.last_nonce.max(nonce_in);
+    }
+
+    /*
+      True when a message range read could include writes
+      the replica may not have yet, open ended ranges always
+      count while the process has recent writes. Once the
+      watermark probe has seen a nonce on the replica, ranges
+      ending at or below it are read there, and every read
+      is once it has seen the last nonce written.
+    */
+    fn reads_recent_writes(
+        &self,
+        process_id_in: &str,
+        to: &Option<String>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> bool {
+        if self.read_freshness_window == 0 {
+            return false;
+        }
+        let window = Duration::from_millis(self.read_freshness_window);
+
+        let boundary = self.recent_writes.get(process_id_in).map(|recent| {
+            if recent.last_write.elapsed() < window && !recent.replicated() {
+                Some((
+                    recent.previous.unwrap_or((recent.nonce, recent.timestamp)),
+                    recent.replicated_nonce,
+                ))
+            } else {
+                None
+            }
+        });
+        let ((boundary_nonce, boundary_timestamp), replicated_nonce) = match boundary {
+            Some(Some(b)) => b,
+            Some(None) => {
+                // nothing recent left or the replica has it all
+                self.recent_writes
+                    .remove_if(process_id_in, |_, recent| recent.last_write.elapsed() >= window);
+                return false;
+            }
+            None => return false,
+        };
+
+        match (from_nonce, to_nonce) {
+            (None, None) => match to {
+                Some(t) => t.parse::<i64>().map_or(true, |t| t >= boundary_timestamp),
+                None => true,
+            },
+            (_, Some(n)) => n.parse::<i32>().map_or(true, |n| {
+                n >= boundary_nonce && !matches!(replicated_nonce, Some(r) if n <= r)
+            }),
+            (Some(_), None) => true,
+        }
+    }
+
     /*
         Run at server startup to modify the database as needed.
         Migrations are embedded directly into the binary that
@@ -227,11 +683,134 @@ impl StoreClient {
             Ok(m) => Ok(format!("Migrations applied... {:?}", m)),
             Err(e) => Err(StoreErrorType::DatabaseError(format!(
                 "Error applying migrations: {}",
-                e.to_string()
+                e
             ))),
         }
     }
 
+    /*
+      Run at startup after run_migrations, compares the
+      migrations recorded in the database with the ones
+      embedded in this binary so a mismatch stops the
+      server with the fix instead of surfacing later as
+      sql errors mid request.
+    */
+    pub fn check_schema(&self, allow_newer: bool) -> Result<String, StoreErrorType> {
+        let conn = &mut self.get_conn()?;
+
+        let pending = conn.pending_migrations(MIGRATIONS).map_err(|e| {
+            StoreErrorType::DatabaseError(format!("Unable to read applied migrations: {}", e))
+        })?;
+        if !pending.is_empty() {
+            let names = pending
+                .iter()
+                .map(|m| m.name().to_string())
+                .collect::<Vec<String>>();
+            return Err(StoreErrorType::DatabaseError(format!(
+                "Database schema is behind this binary, missing migrations: {}. \
+                 Fix the error logged while applying them and restart, or apply them \
+                 with `diesel migration run` against DATABASE_URL",
+                names.join(", ")
+            )));
+        }
+
+        let embedded = MigrationSource::<Pg>::migrations(&MIGRATIONS)
+            .map_err(|e| {
+                StoreErrorType::DatabaseError(format!("Unable to read embedded migrations: {}", e))
+            })?
+            .iter()
+            .map(|m| m.name().version().to_string())
+            .collect::<Vec<String>>();
+        let applied = conn.applied_migrations().map_err(|e| {
+            StoreErrorType::DatabaseError(format!("Unable to read applied migrations: {}", e))
+        })?;
+        let unknown = applied
+            .iter()
+            .map(|v| v.to_string())
+            .filter(|v| !embedded.contains(v))
+            .collect::<Vec<String>>();
+
+        if unknown.is_empty() {
+            return Ok(format!(
+                "Database schema matches, {} migrations applied",
+                applied.len()
+            ));
+        }
+
+        let message = format!(
+            "Database schema is ahead of this binary, unknown migrations: {}. \
+             Deploy a build that includes them or revert them with `diesel migration revert`",
+            unknown.join(", ")
+        );
+        if allow_newer {
+            Ok(message)
+        } else {
+            Err(StoreErrorType::DatabaseError(format!(
+                "{}. Set ALLOW_NEWER_SCHEMA=true if they are known to be compatible",
+                message
+            )))
+        }
+    }
+
+    /*
+      Recount process_stats from the messages table, for a
+      database that had messages before the counters were
+      kept or whose counters drifted. Processes are counted
+      a page at a time, each page in its own statement, so
+      no transaction spans the whole table. Run it with the
+      su stopped so no message lands between a count and
+      its write. Returns how many processes were counted.
+    */
+    pub fn backfill_process_stats(&self) -> Result<usize, StoreErrorType> {
+        use super::schema::processes::dsl::*;
+        use diesel::sql_types::{Array, Text};
+        let conn = &mut self.get_conn()?;
+
+        const BACKFILL_PAGE: i64 = 1000;
+        const RECOUNT: &str =
+            "INSERT INTO process_stats (process_id, message_count, byte_count, last_nonce)
+            SELECT p.process_id,
+                   COALESCE(m.message_count, 0),
+                   COALESCE(m.byte_count, 0),
+                   COALESCE(m.last_nonce, p.nonce)
+            FROM processes p
+            LEFT JOIN (
+                SELECT process_id,
+                       COUNT(*) AS message_count,
+                       SUM(octet_length(bundle)) AS byte_count,
+                       MAX(nonce) AS last_nonce
+                FROM messages
+                WHERE process_id = ANY($1)
+                GROUP BY process_id
+            ) m ON m.process_id = p.process_id
+            WHERE p.process_id = ANY($1)
+            ON CONFLICT (process_id) DO UPDATE SET
+                message_count = EXCLUDED.message_count,
+                byte_count = EXCLUDED.byte_count,
+                last_nonce = EXCLUDED.last_nonce";
+
+        let mut after = String::new();
+        let mut counted = 0;
+        loop {
+            let ids: Vec<String> = processes
+                .filter(process_id.gt(&after))
+                .select(process_id)
+                .order(process_id.asc())
+                .limit(BACKFILL_PAGE)
+                .load(conn)?;
+            let last = match ids.last() {
+                Some(last) => last.clone(),
+                None => return Ok(counted),
+            };
+
+            diesel::sql_query(RECOUNT)
+                .bind::<Array<Text>, _>(&ids)
+                .execute(conn)?;
+            counted += ids.len();
+            after = last;
+        }
+    }
+
     /*
       Method to get the total number of messages
       in the database, this is important for the migration
@@ -307,6 +886,7 @@ impl StoreClient {
       Get all messages in the database, within a
       certain range. This is used for the migration.
     */
+    #[allow(clippy::type_complexity)]
     pub fn get_all_messages(
         &self,
         from: i64,
@@ -367,6 +947,7 @@ impl StoreClient {
     }
 
     // used by the mig_local migration
+    #[allow(clippy::type_complexity)]
     pub async fn get_all_messages_using_bytestore(
         &self,
         from: i64,
@@ -467,9 +1048,9 @@ impl StoreClient {
                                 db_message.0.clone(), // message_id
                                 db_message.1.clone(), // assignment_id
                                 db_message.2.clone(), // process_id
-                                db_message.3.clone(), // timestamp
-                                db_message.4.clone(), // epoch
-                                db_message.5.clone(), // nonce
+                                db_message.3,         // timestamp
+                                db_message.4,         // epoch
+                                db_message.5,         // nonce
                                 db_message.6.clone(), // hash_chain
                                 bytes_result.clone(), // bundle
                             ));
@@ -494,9 +1075,9 @@ impl StoreClient {
                                 db_message.0.clone(),                  // message_id
                                 db_message.1.clone(),                  // assignment_id
                                 db_message.2.clone(),                  // process_id
-                                db_message.3.clone(),                  // timestamp
-                                db_message.4.clone(),                  // epoch
-                                db_message.5.clone(),                  // nonce
+                                db_message.3,                          // timestamp
+                                db_message.4,                          // epoch
+                                db_message.5,                          // nonce
                                 db_message.6.clone(),                  // hash_chain
                                 db_message_with_bundle.bundle.clone(), // bundle
                             ));
@@ -560,6 +1141,7 @@ impl StoreClient {
       Used in the sync_bytestore function to iterate
       over the message table starting at the end.
     */
+    #[allow(clippy::type_complexity)]
     pub fn get_message_by_offset_from_end(
         &self,
         offset: i64,
@@ -684,7 +1266,7 @@ impl StoreClient {
                     synced_count += 1;
                 }
                 Ok(None) => {
-                    self.logger.log(format!("No more messages to process."));
+                    self.logger.log("No more messages to process.".to_string());
                     break;
                 }
                 Err(e) => {
@@ -737,12 +1319,21 @@ impl DataStore for StoreClient {
             timestamp: process_timestamp,
         };
 
-        match diesel::insert_into(processes)
-            .values(&new_process)
-            .on_conflict(process_id)
-            .do_nothing()
-            .execute(conn)
-        {
+        match conn.transaction::<_, DieselError, _>(|conn| {
+            let row_count = diesel::insert_into(processes)
+                .values(&new_process)
+                .on_conflict(process_id)
+                .do_nothing()
+                .execute(conn)?;
+            /*
+              Start the counters at zero, the process itself
+              is not a message but it may hold nonce 0
+            */
+            if row_count > 0 {
+                increment_process_stats(conn, new_process.process_id, 0, 0, process_nonce)?;
+            }
+            Ok(row_count)
+        }) {
             Ok(_) => Ok("saved".to_string()),
             Err(e) => Err(StoreErrorType::from(e)),
         }
@@ -758,12 +1349,15 @@ impl DataStore for StoreClient {
         }
 
         use super::schema::processes::dsl::*;
-        let conn = &mut self.get_read_conn()?;
 
-        let db_process_result: Result<Option<DbProcess>, DieselError> = processes
-            .filter(process_id.eq(process_id_in))
-            .first(conn)
-            .optional();
+        let process_id_owned = process_id_in.to_string();
+        let db_process_result: Result<Option<DbProcess>, StoreErrorType> =
+            self.hedged_read(move |conn| {
+                processes
+                    .filter(process_id.eq(&process_id_owned))
+                    .first(conn)
+                    .optional()
+            });
 
         match db_process_result {
             Ok(Some(db_process)) => {
@@ -774,7 +1368,27 @@ impl DataStore for StoreClient {
                 Ok(process)
             }
             Ok(None) => Err(StoreErrorType::NotFound("Process not found".to_string())),
-            Err(e) => Err(StoreErrorType::from(e)),
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_process_bundle(&self, process_id_in: &str) -> Result<Vec<u8>, StoreErrorType> {
+        use super::schema::processes::dsl::*;
+
+        let process_id_owned = process_id_in.to_string();
+        let bundle_result: Result<Option<Vec<u8>>, StoreErrorType> =
+            self.hedged_read(move |conn| {
+                processes
+                    .filter(process_id.eq(&process_id_owned))
+                    .select(bundle)
+                    .first(conn)
+                    .optional()
+            });
+
+        match bundle_result {
+            Ok(Some(process_bundle)) => Ok(process_bundle),
+            Ok(None) => Err(StoreErrorType::NotFound("Process not found".to_string())),
+            Err(e) => Err(e),
         }
     }
 
@@ -783,8 +1397,8 @@ impl DataStore for StoreClient {
         not just an assignment we need to check that it
         doesnt already exist.
     */
-    fn check_existing_message(&self, message_id: &String) -> Result<(), StoreErrorType> {
-        match self.get_message(&message_id) {
+    fn check_existing_message(&self, message_id: &str) -> Result<(), StoreErrorType> {
+        match self.get_message(message_id) {
             Ok(parsed) => {
                 /*
                     If the message already exists and it contains
@@ -816,8 +1430,8 @@ impl DataStore for StoreClient {
 
     async fn check_existing_deep_hash(
         &self,
-        process_id: &String,
-        deep_hash: &String,
+        process_id: &str,
+        deep_hash: &str,
     ) -> Result<(), StoreErrorType> {
         if self.bytestore.is_ready() {
             match self.bytestore.deep_hash_exists(process_id, deep_hash) {
@@ -832,7 +1446,7 @@ impl DataStore for StoreClient {
         Ok(())
     }
 
-    async fn get_deephash_version(&self, process_id: &String) -> Result<String, StoreErrorType> {
+    async fn get_deephash_version(&self, process_id: &str) -> Result<String, StoreErrorType> {
         if self.bytestore.is_ready() {
             if let Ok(dhv) = self.bytestore.get_deep_hash_version(process_id) {
                 return Ok(dhv);
@@ -845,8 +1459,8 @@ impl DataStore for StoreClient {
 
     async fn save_deephash_version(
         &self,
-        process_id: &String,
-        version: &String,
+        process_id: &str,
+        version: &str,
     ) -> Result<(), StoreErrorType> {
         if self.bytestore.is_ready() {
             self.bytestore.save_deep_hash_version(process_id, version)?;
@@ -854,11 +1468,7 @@ impl DataStore for StoreClient {
         Ok(())
     }
 
-    async fn save_deephash(
-        &self,
-        process_id: &String,
-        deep_hash: &String,
-    ) -> Result<(), StoreErrorType> {
+    async fn save_deephash(&self, process_id: &str, deep_hash: &str) -> Result<(), StoreErrorType> {
         if self.bytestore.is_ready() {
             self.bytestore.save_deep_hash(process_id, deep_hash)?;
         }
@@ -871,9 +1481,6 @@ impl DataStore for StoreClient {
         bundle_in: &[u8],
         deep_hash: Option<&String>,
     ) -> Result<String, StoreErrorType> {
-        use super::schema::messages::dsl::*;
-        let conn = &mut self.get_conn()?;
-
         let new_message = NewMessage {
             process_id: &message.process_id()?,
             message_id: &message.message_id()?,
@@ -906,32 +1513,21 @@ impl DataStore for StoreClient {
                 message.timestamp()?.to_string(),
                 bundle_in.to_vec(),
             )?;
-            match deep_hash {
-                Some(dh) => {
-                    bytestore.save_deep_hash(
-                        &message.process_id()?, 
-                        dh
-                    )?;
-                }
-                None => (),
+            if let Some(dh) = deep_hash {
+                bytestore.save_deep_hash(&message.process_id()?, dh)?;
             };
         }
 
-        let res = match diesel::insert_into(messages)
-            .values(&new_message)
-            .execute(conn)
-        {
-            Ok(row_count) => {
-                if row_count == 0 {
-                    Err(StoreErrorType::DatabaseError(
-                        "Error saving message".to_string(),
-                    )) 
-                } else {
-                    Ok("saved".to_string())
-                }
-            }
-            Err(e) => Err(StoreErrorType::from(e)),
-        };
+        let res = match &self.write_batch {
+            Some(batcher) => batcher
+                .write(PendingMessage::from(&new_message))
+                .await
+                .map_err(StoreErrorType::DatabaseError),
+            None => self
+                .get_conn()
+                .and_then(|mut conn| save_message_row(&mut conn, &new_message)),
+        }
+        .map(|_| "saved".to_string());
 
         /*
           Clean the message out of the bytestore if the
@@ -940,7 +1536,10 @@ impl DataStore for StoreClient {
           controls the schedule, but will avoid it if possible.
         */
         match res {
-          Ok(r) => Ok(r),
+          Ok(r) => {
+            self.record_write(&message.process_id()?, message.nonce()?, message.timestamp()?);
+            Ok(r)
+          }
           Err(e) => {
             if bytestore.is_ready() {
                 bytestore.delete_binary(
@@ -949,14 +1548,8 @@ impl DataStore for StoreClient {
                     message.process_id()?,
                     message.timestamp()?.to_string()
                 )?;
-                match deep_hash {
-                    Some(dh) => {
-                        bytestore.delete_deep_hash(
-                            &message.process_id()?, 
-                            dh
-                        )?;
-                    }
-                    None => (),
+                if let Some(dh) = deep_hash {
+                    bytestore.delete_deep_hash(&message.process_id()?, dh)?;
                 };
             }
             Err(e)
@@ -964,6 +1557,77 @@ impl DataStore for StoreClient {
         }
     }
 
+    /*
+      The rows go in one transaction so the whole batch is
+      saved or none of it. They go around the write batcher,
+      which could split them over its flushes. The bytestore
+      is written first and cleaned up after a failure the
+      same as in save_message.
+    */
+    async fn save_messages(
+        &self,
+        messages: &[(&Message, &[u8], Option<&String>)],
+    ) -> Result<(), StoreErrorType> {
+        let pending = messages
+            .iter()
+            .map(|(message, bundle_in, _)| PendingMessage::new(message, bundle_in))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let bytestore = self.bytestore.clone();
+        if bytestore.is_ready() {
+            for ((_, bundle_in, deep_hash), row) in messages.iter().zip(&pending) {
+                bytestore.save_binary(
+                    row.message_id.clone(),
+                    Some(row.assignment_id.clone()),
+                    row.process_id.clone(),
+                    row.timestamp.to_string(),
+                    bundle_in.to_vec(),
+                )?;
+                if let Some(dh) = deep_hash {
+                    bytestore.save_deep_hash(&row.process_id, dh)?;
+                }
+            }
+        }
+
+        let rows: Vec<NewMessage> = pending.iter().map(PendingMessage::row).collect();
+        let res = self.get_conn().and_then(|mut conn| {
+            conn.transaction::<_, DieselError, _>(|conn| {
+                for row in &rows {
+                    if insert_message(conn, row)? == 0 {
+                        return Err(DieselError::RollbackTransaction);
+                    }
+                }
+                Ok(())
+            })
+            .map_err(StoreErrorType::from)
+        });
+
+        match res {
+            Ok(()) => {
+                for row in &rows {
+                    self.record_write(row.process_id, *row.nonce, *row.timestamp);
+                }
+                Ok(())
+            }
+            Err(e) => {
+                if bytestore.is_ready() {
+                    for ((_, _, deep_hash), row) in messages.iter().zip(&pending) {
+                        bytestore.delete_binary(
+                            row.message_id.clone(),
+                            Some(row.assignment_id.clone()),
+                            row.process_id.clone(),
+                            row.timestamp.to_string(),
+                        )?;
+                        if let Some(dh) = deep_hash {
+                            bytestore.delete_deep_hash(&row.process_id, dh)?;
+                        }
+                    }
+                }
+                Err(e)
+            }
+        }
+    }
+
     async fn get_messages(
         &self,
         process_in: &Process,
@@ -974,7 +1638,20 @@ impl DataStore for StoreClient {
         to_nonce: &Option<String>,
     ) -> Result<PaginatedMessages, StoreErrorType> {
         use super::schema::messages::dsl::*;
-        let conn = &mut self.get_read_conn()?;
+        /*
+            Ranges that reach writes the replica may not
+            have yet are read from the writer
+        */
+        let conn = &mut if self.reads_recent_writes(
+            &process_in.process.process_id,
+            to,
+            from_nonce,
+            to_nonce,
+        ) {
+            self.get_conn()?
+        } else {
+            self.get_read_conn()?
+        };
         let mut query = messages
             .filter(process_id.eq(process_in.process.process_id.clone()))
             .into_boxed();
@@ -1017,24 +1694,12 @@ impl DataStore for StoreClient {
 
         let include_process = match (from_nonce, to_nonce) {
             // we are dealing with timestamps
-            (None, None) => {
-                process_in.assignment.is_some()
-                    && match from {
-                        Some(_) => false,
-                        None => true,
-                    }
-            }
+            (None, None) => process_in.assignment.is_some() && from.is_none(),
             // if we are dealing with nonce sequencing
             (_, _) => {
                 process_in.assignment.is_some()
                     && match from_nonce {
-                        Some(ref _from_nonce) => {
-                            if _from_nonce.parse::<i32>()? == -1 {
-                                true
-                            } else {
-                                false
-                            }
-                        }
+                        Some(ref _from_nonce) => _from_nonce.parse::<i32>()? == -1,
                         /*
                           No 'from' means it's the first page
                         */
@@ -1274,15 +1939,12 @@ impl DataStore for StoreClient {
                                             .first::<DbMessage>(conn)
                                     })?;
 
-                                match full_message.assignment_id.clone() {
-                                    Some(a) => {
-                                        message_bundles.push((a, full_message.bundle.clone()))
-                                    }
-                                    /*
-                                      Anything old enough that it doesnt have
-                                      an assignemnt can be ignored
-                                    */
-                                    None => (),
+                                /*
+                                  Anything old enough that it doesnt have
+                                  an assignemnt can be ignored
+                                */
+                                if let Some(a) = full_message.assignment_id.clone() {
+                                    message_bundles.push((a, full_message.bundle.clone()))
                                 }
                             }
                         }
@@ -1299,19 +1961,95 @@ impl DataStore for StoreClient {
         ))
     }
 
+    /*
+      The tag filter is a jsonb containment on the message
+      tags, served by the idx_messages_tags gin index, so
+      only the matching rows are read
+    */
+    async fn get_tagged_messages(
+        &self,
+        process_in: &Process,
+        tags: &[Tag],
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType> {
+        use super::schema::messages::dsl::*;
+        let conn = &mut if self.reads_recent_writes(
+            &process_in.process.process_id,
+            &None,
+            from_nonce,
+            to_nonce,
+        ) {
+            self.get_conn()?
+        } else {
+            self.get_read_conn()?
+        };
+
+        /*
+          Written out so the path is a constant and matches
+          the expression of the index, the json path methods
+          would bind it as parameters
+        */
+        let tag_filter = diesel::dsl::sql::<diesel::sql_types::Bool>(
+            "(message_data -> 'message' -> 'tags') @> ",
+        )
+        .bind::<diesel::sql_types::Jsonb, _>(serde_json::to_value(tags)?);
+        let mut query = messages
+            .filter(process_id.eq(process_in.process.process_id.clone()))
+            .filter(tag_filter)
+            .into_boxed();
+        if let Some(from_nonce_s) = from_nonce {
+            query = query.filter(nonce.gt(from_nonce_s.parse::<i32>()?));
+        }
+        if let Some(to_nonce_s) = to_nonce {
+            query = query.filter(nonce.le(to_nonce_s.parse::<i32>()?));
+        }
+
+        let mut messages_mapped: Vec<Message> = vec![];
+        // the process leads the first page like in get_messages, if it has the tags
+        let first_page = matches!(from_nonce.as_deref(), None | Some("-1"));
+        if first_page && process_in.assignment.is_some() {
+            let process_message = Message::from_process(process_in.clone())?;
+            if process_message.has_tags(tags) {
+                messages_mapped.push(process_message);
+            }
+        }
+
+        let limit_val = limit.unwrap_or(100) as i64 - messages_mapped.len() as i64;
+        let db_messages: Vec<DbMessage> =
+            query.order(nonce.asc()).limit(limit_val + 1).load(conn)?;
+        let has_next_page = db_messages.len() as i64 > limit_val;
+
+        for db_message in db_messages.iter().take(limit_val as usize) {
+            let json = serde_json::from_value(db_message.message_data.clone())?;
+            let mapped = Message::from_val(&json, db_message.bundle.clone())?;
+            messages_mapped.push(mapped);
+        }
+
+        Ok(PaginatedMessages::from_messages(
+            messages_mapped,
+            has_next_page,
+            "nonce",
+        )?)
+    }
+
     fn get_message(&self, tx_id: &str) -> Result<Message, StoreErrorType> {
         use super::schema::messages::dsl::*;
-        let conn = &mut self.get_read_conn()?;
 
         /*
             get the oldest match. in the case of a message that has
             later assignments, it should be the original message itself.
         */
-        let db_message_result: Result<Option<DbMessage>, DieselError> = messages
-            .filter(message_id.eq(tx_id).or(assignment_id.eq(tx_id)))
-            .order(timestamp.asc())
-            .first(conn)
-            .optional();
+        let tx_id_owned = tx_id.to_string();
+        let db_message_result: Result<Option<DbMessage>, StoreErrorType> =
+            self.hedged_read(move |conn| {
+                messages
+                    .filter(message_id.eq(&tx_id_owned).or(assignment_id.eq(&tx_id_owned)))
+                    .order(timestamp.asc())
+                    .first(conn)
+                    .optional()
+            });
 
         match db_message_result {
             Ok(Some(db_message)) => {
@@ -1321,7 +2059,7 @@ impl DataStore for StoreClient {
                 Ok(message)
             }
             Ok(None) => Err(StoreErrorType::NotFound("Message not found".to_string())), // Adjust this error type as needed
-            Err(e) => Err(StoreErrorType::from(e)),
+            Err(e) => Err(e),
         }
     }
 
@@ -1361,7 +2099,7 @@ impl DataStore for StoreClient {
                 // Deserialize the message_data into Message
                 let message_val: serde_json::Value =
                     serde_json::from_value(db_message.message_data)
-                        .map_err(|e| StoreErrorType::from(e))?;
+                        .map_err(StoreErrorType::from)?;
 
                 let message: Message = Message::from_val(&message_val, db_message.bundle.clone())?;
 
@@ -1371,6 +2109,299 @@ impl DataStore for StoreClient {
             Err(e) => Err(StoreErrorType::from(e)),
         }
     }
+
+    async fn get_process_stats(&self, process_id_in: &str) -> Result<ProcessStats, StoreErrorType> {
+        use super::schema::process_stats::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_stats_result: Result<Option<DbProcessStats>, DieselError> = process_stats
+            .filter(process_id.eq(process_id_in))
+            .first(conn)
+            .optional();
+
+        match db_stats_result {
+            Ok(Some(db_stats)) => Ok(ProcessStats {
+                process_id: db_stats.process_id,
+                message_count: db_stats.message_count,
+                byte_count: db_stats.byte_count,
+                last_nonce: db_stats.last_nonce,
+            }),
+            Ok(None) => Err(StoreErrorType::NotFound(
+                "Process stats not found".to_string(),
+            )),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    async fn get_processes_stats(
+        &self,
+        process_ids: &[String],
+    ) -> Result<Vec<ProcessStats>, StoreErrorType> {
+        use super::schema::process_stats::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_stats: Vec<DbProcessStats> = process_stats
+            .filter(process_id.eq_any(process_ids))
+            .load(conn)?;
+
+        Ok(db_stats
+            .into_iter()
+            .map(|db_stats| ProcessStats {
+                process_id: db_stats.process_id,
+                message_count: db_stats.message_count,
+                byte_count: db_stats.byte_count,
+                last_nonce: db_stats.last_nonce,
+            })
+            .collect())
+    }
+
+    async fn save_chain_snapshot(&self, snapshot: &ChainSnapshot) -> Result<(), StoreErrorType> {
+        use super::schema::chain_snapshots::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        let new_snapshot = NewChainSnapshot {
+            process_id: &snapshot.process_id,
+            epoch: &snapshot.epoch,
+            nonce: &snapshot.nonce,
+            timestamp: &snapshot.timestamp,
+            hash_chain: &snapshot.hash_chain,
+            assignment_id: &snapshot.assignment_id,
+            signature: &snapshot.signature,
+        };
+
+        diesel::insert_into(chain_snapshots)
+            .values(&new_snapshot)
+            .on_conflict((process_id, nonce))
+            .do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    async fn get_chain_snapshot(
+        &self,
+        process_id_in: &str,
+        max_nonce: Option<i32>,
+    ) -> Result<ChainSnapshot, StoreErrorType> {
+        use super::schema::chain_snapshots::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let mut query = chain_snapshots
+            .filter(process_id.eq(process_id_in))
+            .into_boxed();
+        if let Some(max_nonce) = max_nonce {
+            query = query.filter(nonce.le(max_nonce));
+        }
+
+        let db_snapshot: Option<DbChainSnapshot> = query
+            .order(nonce.desc())
+            .select(DbChainSnapshot::as_select())
+            .first(conn)
+            .optional()?;
+
+        match db_snapshot {
+            Some(db_snapshot) => Ok(ChainSnapshot {
+                process_id: db_snapshot.process_id,
+                epoch: db_snapshot.epoch,
+                nonce: db_snapshot.nonce,
+                timestamp: db_snapshot.timestamp,
+                hash_chain: db_snapshot.hash_chain,
+                assignment_id: db_snapshot.assignment_id,
+                signature: db_snapshot.signature,
+            }),
+            None => Err(StoreErrorType::NotFound(
+                "Chain snapshot not found".to_string(),
+            )),
+        }
+    }
+
+    async fn save_inclusion_proofs(
+        &self,
+        proofs: &[InclusionProof],
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::inclusion_proofs::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        let paths = proofs
+            .iter()
+            .map(|proof| serde_json::to_string(&proof.path))
+            .collect::<Result<Vec<String>, _>>()?;
+        let new_proofs: Vec<NewInclusionProof> = proofs
+            .iter()
+            .zip(paths.iter())
+            .map(|(proof, proof_path)| NewInclusionProof {
+                item_id: &proof.item_id,
+                bundle_id: &proof.bundle_id,
+                root: &proof.root,
+                leaf_index: &proof.index,
+                leaf_count: &proof.leaf_count,
+                path: proof_path,
+            })
+            .collect();
+
+        diesel::insert_into(inclusion_proofs)
+            .values(&new_proofs)
+            .on_conflict(item_id)
+            .do_nothing()
+            .execute(conn)?;
+        Ok(())
+    }
+
+    async fn get_inclusion_proof(&self, item_id_in: &str) -> Result<InclusionProof, StoreErrorType> {
+        use super::schema::inclusion_proofs::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_proof: Option<DbInclusionProof> = inclusion_proofs
+            .filter(item_id.eq(item_id_in))
+            .select(DbInclusionProof::as_select())
+            .first(conn)
+            .optional()?;
+
+        match db_proof {
+            Some(db_proof) => Ok(InclusionProof {
+                item_id: db_proof.item_id,
+                bundle_id: db_proof.bundle_id,
+                root: db_proof.root,
+                index: db_proof.leaf_index,
+                leaf_count: db_proof.leaf_count,
+                path: serde_json::from_str(&db_proof.path)?,
+            }),
+            None => Err(StoreErrorType::NotFound(
+                "Inclusion proof not found".to_string(),
+            )),
+        }
+    }
+
+    /*
+      A page of each table after the position, merged. The
+      bundle column is always written, so unlike the message
+      list this doesn't need the bytestore.
+    */
+    async fn get_items_after(
+        &self,
+        after: (i64, &str),
+        limit: usize,
+    ) -> Result<Vec<ExportItem>, StoreErrorType> {
+        use super::schema::{messages, processes};
+        let conn = &mut self.get_read_conn()?;
+        let (after_timestamp, after_id) = after;
+
+        let message_rows: Vec<(String, Option<String>, i64, i32, Vec<u8>)> = messages::table
+            .filter(messages::assignment_id.is_not_null())
+            .filter(
+                messages::timestamp
+                    .gt(after_timestamp)
+                    .or(messages::timestamp
+                        .eq(after_timestamp)
+                        .and(messages::assignment_id.gt(after_id))),
+            )
+            .order((messages::timestamp.asc(), messages::assignment_id.asc()))
+            .limit(limit as i64)
+            .select((
+                messages::process_id,
+                messages::assignment_id,
+                messages::timestamp,
+                messages::nonce,
+                messages::bundle,
+            ))
+            .load(conn)?;
+
+        let process_rows: Vec<(String, Option<i64>, Option<i32>, Vec<u8>)> = processes::table
+            .filter(
+                processes::timestamp
+                    .gt(after_timestamp)
+                    .or(processes::timestamp
+                        .eq(after_timestamp)
+                        .and(processes::process_id.gt(after_id))),
+            )
+            .order((processes::timestamp.asc(), processes::process_id.asc()))
+            .limit(limit as i64)
+            .select((
+                processes::process_id,
+                processes::timestamp,
+                processes::nonce,
+                processes::bundle,
+            ))
+            .load(conn)?;
+
+        let mut items: Vec<ExportItem> = message_rows
+            .into_iter()
+            .map(
+                |(process_id, assignment_id, timestamp, nonce, bundle)| ExportItem {
+                    item_type: "Message",
+                    process_id,
+                    id: assignment_id.unwrap_or_default(),
+                    timestamp,
+                    nonce,
+                    bundle,
+                },
+            )
+            .collect();
+        items.extend(
+            process_rows
+                .into_iter()
+                .map(|(process_id, timestamp, nonce, bundle)| ExportItem {
+                    item_type: "Process",
+                    id: process_id.clone(),
+                    process_id,
+                    timestamp: timestamp.unwrap_or(0),
+                    nonce: nonce.unwrap_or(0),
+                    bundle,
+                }),
+        );
+        items.sort_by(|a, b| (a.timestamp, &a.id).cmp(&(b.timestamp, &b.id)));
+        items.truncate(limit);
+        Ok(items)
+    }
+
+    async fn get_process_ids_after(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreErrorType> {
+        use super::schema::processes::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let ids: Vec<String> = processes
+            .filter(process_id.gt(after))
+            .order(process_id.asc())
+            .limit(limit as i64)
+            .select(process_id)
+            .load(conn)?;
+        Ok(ids)
+    }
+
+    async fn list_processes(
+        &self,
+        spawned_after: Option<i64>,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, StoreErrorType> {
+        use super::schema::processes::dsl::*;
+        use diesel::sql_types::{BigInt, Bool};
+        let conn = &mut self.get_read_conn()?;
+
+        /*
+          The timestamp column is only filled with process
+          assignment enabled, the one in the process json
+          always is. Written out to match the index.
+        */
+        const SPAWNED_AT: &str = "((process_data -> 'process' ->> 'timestamp')::BIGINT)";
+        let mut query = processes
+            .filter(process_id.gt(after))
+            .select((process_id, diesel::dsl::sql::<BigInt>(SPAWNED_AT)))
+            .into_boxed();
+        if let Some(spawned_after) = spawned_after {
+            query = query.filter(
+                diesel::dsl::sql::<Bool>(&format!("{} > ", SPAWNED_AT))
+                    .bind::<BigInt, _>(spawned_after),
+            );
+        }
+        let rows: Vec<(String, i64)> = query
+            .order(process_id.asc())
+            .limit(limit as i64)
+            .load(conn)?;
+        Ok(rows)
+    }
 }
 
 impl RouterDataStore for StoreClient {
@@ -1384,6 +2415,7 @@ impl RouterDataStore for StoreClient {
         let new_process_scheduler = NewProcessScheduler {
             process_id: &process_scheduler.process_id,
             scheduler_row_id: &process_scheduler.scheduler_row_id,
+            spawned_at: None,
         };
 
         match diesel::insert_into(process_schedulers)
@@ -1402,12 +2434,14 @@ impl RouterDataStore for StoreClient {
         process_id_in: &str,
     ) -> Result<ProcessScheduler, StoreErrorType> {
         use super::schema::process_schedulers::dsl::*;
-        let conn = &mut self.get_read_conn()?;
 
-        let db_process_result: Result<Option<DbProcessScheduler>, DieselError> = process_schedulers
-            .filter(process_id.eq(process_id_in))
-            .first(conn)
-            .optional();
+        let db_process_result: Result<Option<DbProcessScheduler>, StoreErrorType> = self
+            .router_read(|conn| {
+                process_schedulers
+                    .filter(process_id.eq(process_id_in))
+                    .first(conn)
+                    .optional()
+            });
 
         match db_process_result {
             Ok(Some(db_process_scheduler)) => {
@@ -1421,20 +2455,48 @@ impl RouterDataStore for StoreClient {
             Ok(None) => Err(StoreErrorType::NotFound(
                 "Process scheduler not found".to_string(),
             )),
-            Err(e) => Err(StoreErrorType::from(e)),
+            Err(e) => Err(e),
         }
     }
 
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        let row: Option<DbProcessScheduler> = process_schedulers
+            .filter(process_id.eq(process_id_in))
+            .first(conn)
+            .optional()?;
+
+        row.map(ProcessScheduler::from)
+            .ok_or(StoreErrorType::NotFound(
+                "Process scheduler not found".to_string(),
+            ))
+    }
+
     fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType> {
         use super::schema::schedulers::dsl::*;
         let conn = &mut self.get_conn()?;
 
+        let wallets = wallets_column(&scheduler.wallets_to_route);
         let new_scheduler = NewScheduler {
             url: &scheduler.url,
             process_count: &scheduler.process_count,
             no_route: scheduler.no_route.as_ref(),
-            wallets_to_route: scheduler.wallets_to_route.as_deref(),
+            wallets_to_route: wallets.as_deref(),
             wallets_only: scheduler.wallets_only.as_ref(),
+            priority: scheduler.priority.as_ref(),
+            large_objects: scheduler.large_objects.as_ref(),
+            weight: scheduler.weight.as_ref(),
+            public_url: scheduler.public_url.as_deref(),
+            drain: scheduler.drain.as_ref(),
+            max_processes: scheduler.max_processes.as_ref(),
+            region: scheduler.region.as_deref(),
+            tags_to_route: scheduler.tags_to_route.as_deref(),
+            wallets_policy: scheduler.wallets_policy.as_deref(),
         };
 
         match diesel::insert_into(schedulers)
@@ -1455,11 +2517,19 @@ impl RouterDataStore for StoreClient {
         // Ensure scheduler.row_id is Some(value) before calling this function
         match diesel::update(schedulers.filter(row_id.eq(scheduler.row_id.unwrap())))
             .set((
-                process_count.eq(scheduler.process_count),
                 url.eq(&scheduler.url),
                 no_route.eq(&scheduler.no_route),
-                wallets_to_route.eq(&scheduler.wallets_to_route),
+                wallets_to_route.eq(wallets_column(&scheduler.wallets_to_route)),
                 wallets_only.eq(&scheduler.wallets_only),
+                priority.eq(&scheduler.priority),
+                large_objects.eq(&scheduler.large_objects),
+                weight.eq(&scheduler.weight),
+                public_url.eq(&scheduler.public_url),
+                drain.eq(&scheduler.drain),
+                max_processes.eq(&scheduler.max_processes),
+                region.eq(&scheduler.region),
+                tags_to_route.eq(&scheduler.tags_to_route),
+                wallets_policy.eq(&scheduler.wallets_policy),
             ))
             .execute(conn)
         {
@@ -1468,14 +2538,55 @@ impl RouterDataStore for StoreClient {
         }
     }
 
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+            .set(process_count.eq(process_count + by))
+            .execute(conn)?;
+        Ok(())
+    }
+
+    // a count smaller than by goes to 0 rather than below it
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        conn.transaction::<_, DieselError, _>(|conn| {
+            let updated = diesel::update(
+                schedulers.filter(row_id.eq(scheduler_row_id_in).and(process_count.ge(by))),
+            )
+            .set(process_count.eq(process_count - by))
+            .execute(conn)?;
+            if updated == 0 {
+                diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+                    .set(process_count.eq(0))
+                    .execute(conn)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+
     fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
         use super::schema::schedulers::dsl::*;
-        let conn = &mut self.get_read_conn()?;
 
-        let db_scheduler_result: Result<Option<DbScheduler>, DieselError> = schedulers
-            .filter(row_id.eq(row_id_in))
-            .first(conn)
-            .optional();
+        let db_scheduler_result: Result<Option<DbScheduler>, StoreErrorType> =
+            self.router_read(|conn| {
+                schedulers
+                    .filter(row_id.eq(row_id_in))
+                    .first(conn)
+                    .optional()
+            });
 
         match db_scheduler_result {
             Ok(Some(db_scheduler)) => {
@@ -1484,17 +2595,26 @@ impl RouterDataStore for StoreClient {
                     url: db_scheduler.url,
                     process_count: db_scheduler.process_count,
                     no_route: db_scheduler.no_route,
-                    wallets_to_route: db_scheduler.wallets_to_route,
+                    wallets_to_route: wallets_from_column(db_scheduler.wallets_to_route),
                     wallets_only: db_scheduler.wallets_only,
+                    priority: db_scheduler.priority,
+                    large_objects: db_scheduler.large_objects,
+                    weight: db_scheduler.weight,
+                    public_url: db_scheduler.public_url,
+                    drain: db_scheduler.drain,
+                    max_processes: db_scheduler.max_processes,
+                    region: db_scheduler.region,
+                    tags_to_route: db_scheduler.tags_to_route,
+                    wallets_policy: db_scheduler.wallets_policy,
                 };
                 Ok(scheduler)
             }
             Ok(None) => Err(StoreErrorType::NotFound("Scheduler not found".to_string())),
-            Err(e) => Err(StoreErrorType::from(e)),
+            Err(e) => Err(e),
         }
     }
 
-    fn get_scheduler_by_url(&self, url_in: &String) -> Result<Scheduler, StoreErrorType> {
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType> {
         use super::schema::schedulers::dsl::*;
         let conn = &mut self.get_read_conn()?;
 
@@ -1508,8 +2628,17 @@ impl RouterDataStore for StoreClient {
                     url: db_scheduler.url,
                     process_count: db_scheduler.process_count,
                     no_route: db_scheduler.no_route,
-                    wallets_to_route: db_scheduler.wallets_to_route,
+                    wallets_to_route: wallets_from_column(db_scheduler.wallets_to_route),
                     wallets_only: db_scheduler.wallets_only,
+                    priority: db_scheduler.priority,
+                    large_objects: db_scheduler.large_objects,
+                    weight: db_scheduler.weight,
+                    public_url: db_scheduler.public_url,
+                    drain: db_scheduler.drain,
+                    max_processes: db_scheduler.max_processes,
+                    region: db_scheduler.region,
+                    tags_to_route: db_scheduler.tags_to_route,
+                    wallets_policy: db_scheduler.wallets_policy,
                 };
                 Ok(scheduler)
             }
@@ -1531,8 +2660,17 @@ impl RouterDataStore for StoreClient {
                         url: db_scheduler.url,
                         process_count: db_scheduler.process_count,
                         no_route: db_scheduler.no_route,
-                        wallets_to_route: db_scheduler.wallets_to_route,
+                        wallets_to_route: wallets_from_column(db_scheduler.wallets_to_route),
                         wallets_only: db_scheduler.wallets_only,
+                        priority: db_scheduler.priority,
+                        large_objects: db_scheduler.large_objects,
+                        weight: db_scheduler.weight,
+                        public_url: db_scheduler.public_url,
+                        drain: db_scheduler.drain,
+                        max_processes: db_scheduler.max_processes,
+                        region: db_scheduler.region,
+                        tags_to_route: db_scheduler.tags_to_route,
+                        wallets_policy: db_scheduler.wallets_policy,
                     })
                     .collect();
                 Ok(schedulers_out)
@@ -1540,11 +2678,409 @@ impl RouterDataStore for StoreClient {
             Err(e) => Err(StoreErrorType::from(e)),
         }
     }
+
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        match diesel::update(process_schedulers.filter(row_id.eq(process_scheduler.row_id.unwrap())))
+            .set(scheduler_row_id.eq(process_scheduler.scheduler_row_id))
+            .execute(conn)
+        {
+            Ok(_) => Ok("updated".to_string()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        match diesel::delete(process_schedulers.filter(row_id.eq(row_id_in))).execute(conn) {
+            Ok(_) => Ok("deleted".to_string()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType> {
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        match diesel::delete(schedulers.filter(row_id.eq(row_id_in))).execute(conn) {
+            Ok(0) => Err(StoreErrorType::NotFound("Scheduler not found".to_string())),
+            Ok(_) => Ok("deleted".to_string()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    /*
+      Rows pointing at a scheduler that no longer exists,
+      the foreign key prevents this on databases created
+      from the migrations but older deployments created
+      the table without it.
+    */
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        use super::schema::schedulers::dsl as scheduler_dsl;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_result: Result<Vec<DbProcessScheduler>, DieselError> = process_schedulers
+            .filter(diesel::dsl::not(scheduler_row_id.eq_any(
+                scheduler_dsl::schedulers.select(scheduler_dsl::row_id),
+            )))
+            .order(row_id.asc())
+            .load(conn);
+
+        match db_result {
+            Ok(rows) => Ok(rows.into_iter().map(ProcessScheduler::from).collect()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    /*
+      Every row of each process id that has more than one,
+      ordered so the oldest row for a process comes first
+    */
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let duplicate_ids: Vec<String> = process_schedulers
+            .group_by(process_id)
+            .having(diesel::dsl::count_star().gt(1))
+            .select(process_id)
+            .load(conn)?;
+
+        if duplicate_ids.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let db_result: Result<Vec<DbProcessScheduler>, DieselError> = process_schedulers
+            .filter(process_id.eq_any(&duplicate_ids))
+            .order((process_id.asc(), row_id.asc()))
+            .load(conn);
+
+        match db_result {
+            Ok(rows) => Ok(rows.into_iter().map(ProcessScheduler::from).collect()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    /*
+      Number of process_schedulers rows pointing at each
+      scheduler row id, schedulers with none are left out
+    */
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let counts: Vec<(i32, i64)> = process_schedulers
+            .group_by(scheduler_row_id)
+            .select((scheduler_row_id, diesel::dsl::count_star()))
+            .load(conn)?;
+
+        Ok(counts)
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_result: Result<Vec<DbProcessScheduler>, DieselError> = process_schedulers
+            .filter(scheduler_row_id.eq(scheduler_row_id_in))
+            .filter(row_id.gt(after_row_id))
+            .order(row_id.asc())
+            .limit(limit)
+            .load(conn);
+
+        match db_result {
+            Ok(rows) => Ok(rows.into_iter().map(ProcessScheduler::from).collect()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let db_result: Result<Vec<DbProcessScheduler>, DieselError> = process_schedulers
+            .filter(row_id.gt(after_row_id))
+            .order(row_id.asc())
+            .limit(limit)
+            .load(conn);
+
+        match db_result {
+            Ok(rows) => Ok(rows.into_iter().map(ProcessScheduler::from).collect()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        use super::schema::process_schedulers::dsl::*;
+        let conn = &mut self.get_read_conn()?;
+
+        let mut query = process_schedulers
+            .filter(process_id.gt(after_process_id))
+            .into_boxed();
+        if let Some(scheduler) = scheduler_row_id_in {
+            query = query.filter(scheduler_row_id.eq(scheduler));
+        }
+        if let Some(after) = spawned_after {
+            query = query.filter(spawned_at.gt(after));
+        }
+        let db_result: Result<Vec<DbProcessScheduler>, DieselError> =
+            query.order(process_id.asc()).limit(limit).load(conn);
+
+        match db_result {
+            Ok(rows) => Ok(rows
+                .into_iter()
+                .map(|row| {
+                    let spawned = row.spawned_at;
+                    (ProcessScheduler::from(row), spawned)
+                })
+                .collect()),
+            Err(e) => Err(StoreErrorType::from(e)),
+        }
+    }
+
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        use super::schema::process_schedulers::dsl as ps;
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        let new_process_scheduler = NewProcessScheduler {
+            process_id: process_id_in,
+            scheduler_row_id: scheduler_row_id_in,
+            spawned_at: Some(now_millis()),
+        };
+
+        let (db_scheduler, duplicate) = conn.transaction::<_, DieselError, _>(|conn| {
+            let row_count = diesel::insert_into(ps::process_schedulers)
+                .values(&new_process_scheduler)
+                .on_conflict(ps::process_id)
+                .do_nothing()
+                .execute(conn)?;
+            if row_count == 0 {
+                let assigned_row_id: i32 = ps::process_schedulers
+                    .filter(ps::process_id.eq(process_id_in))
+                    .select(ps::scheduler_row_id)
+                    .first(conn)?;
+                let db_scheduler: DbScheduler =
+                    schedulers.filter(row_id.eq(assigned_row_id)).first(conn)?;
+                return Ok((db_scheduler, true));
+            }
+            let db_scheduler: DbScheduler =
+                diesel::update(schedulers.filter(row_id.eq(scheduler_row_id_in)))
+                    .set(process_count.eq(process_count + 1))
+                    .get_result(conn)?;
+            Ok((db_scheduler, false))
+        })?;
+
+        Ok(Assignment {
+            scheduler: Scheduler::from(db_scheduler),
+            duplicate,
+        })
+    }
+
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::process_schedulers::dsl as ps;
+        use super::schema::schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        let moved = conn.transaction::<_, DieselError, _>(|conn| {
+            let row_count = diesel::update(
+                ps::process_schedulers.filter(
+                    ps::process_id
+                        .eq(process_id_in)
+                        .and(ps::scheduler_row_id.eq(from_row_id)),
+                ),
+            )
+            .set(ps::scheduler_row_id.eq(to_row_id))
+            .execute(conn)?;
+            if row_count == 0 {
+                return Ok(false);
+            }
+            diesel::update(schedulers.filter(row_id.eq(to_row_id)))
+                .set(process_count.eq(process_count + 1))
+                .execute(conn)?;
+            diesel::update(schedulers.filter(row_id.eq(from_row_id).and(process_count.gt(0))))
+                .set(process_count.eq(process_count - 1))
+                .execute(conn)?;
+            Ok(true)
+        })?;
+
+        match moved {
+            true => Ok(()),
+            false => Err(StoreErrorType::NotFound(format!(
+                "Process {} is not on scheduler {}",
+                process_id_in, from_row_id
+            ))),
+        }
+    }
+
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType> {
+        use super::schema::owner_schedulers::dsl::*;
+
+        let row: Option<i32> = self.router_read(|conn| {
+            owner_schedulers
+                .filter(owner.eq(owner_in))
+                .select(scheduler_row_id)
+                .first(conn)
+                .optional()
+        })?;
+
+        row.ok_or(StoreErrorType::NotFound(
+            "Owner scheduler not found".to_string(),
+        ))
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        use super::schema::owner_schedulers::dsl::*;
+        let conn = &mut self.get_conn()?;
+
+        diesel::insert_into(owner_schedulers)
+            .values((owner.eq(owner_in), scheduler_row_id.eq(scheduler_row_id_in)))
+            .on_conflict(owner)
+            .do_update()
+            .set(scheduler_row_id.eq(scheduler_row_id_in))
+            .execute(conn)?;
+        Ok(())
+    }
+}
+
+/*
+  Appends take a lock on the table for their transaction
+  so su instances sharing the database append one at a
+  time, reads aren't blocked. The migration refuses
+  updates and deletes on it.
+*/
+impl AdminAudit for StoreClient {
+    fn append(&self, change: AdminChange) -> Result<AuditEntry, AdminAuditErrorType> {
+        use super::schema::admin_audit::dsl::*;
+        let conn = &mut self.get_conn().map_err(audit_error)?;
+
+        conn.transaction::<_, DieselError, _>(|conn| {
+            diesel::sql_query("LOCK TABLE admin_audit IN SHARE ROW EXCLUSIVE MODE")
+                .execute(conn)?;
+            let last: Option<DbAdminAudit> = admin_audit
+                .order(seq.desc())
+                .select(DbAdminAudit::as_select())
+                .first(conn)
+                .optional()?;
+            let last = last.map(AuditEntry::from);
+            let entry = audit::seal(last.as_ref(), change, now_millis());
+            diesel::insert_into(admin_audit)
+                .values(DbAdminAudit::from(entry.clone()))
+                .execute(conn)?;
+            Ok(entry)
+        })
+        .map_err(|e| audit_error(StoreErrorType::from(e)))
+    }
+
+    fn read(&self, from: i64, limit: i64) -> Result<Vec<AuditEntry>, AdminAuditErrorType> {
+        use super::schema::admin_audit::dsl::*;
+        let conn = &mut self.get_read_conn().map_err(audit_error)?;
+
+        let rows: Vec<DbAdminAudit> = admin_audit
+            .filter(seq.ge(from))
+            .order(seq.asc())
+            .limit(limit)
+            .select(DbAdminAudit::as_select())
+            .load(conn)
+            .map_err(|e| audit_error(StoreErrorType::from(e)))?;
+        Ok(rows.into_iter().map(AuditEntry::from).collect())
+    }
+}
+
+fn audit_error(error: StoreErrorType) -> AdminAuditErrorType {
+    AdminAuditErrorType::AuditError(format!("{:?}", error))
+}
+
+#[derive(Queryable, Selectable, Insertable)]
+#[diesel(table_name = super::schema::admin_audit)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbAdminAudit {
+    pub seq: i64,
+    pub timestamp: i64,
+    pub actor: String,
+    pub client: String,
+    pub action: String,
+    pub target: String,
+    pub before_value: Option<serde_json::Value>,
+    pub after_value: Option<serde_json::Value>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+impl From<DbAdminAudit> for AuditEntry {
+    fn from(row: DbAdminAudit) -> Self {
+        AuditEntry {
+            seq: row.seq,
+            timestamp: row.timestamp,
+            actor: row.actor,
+            client: row.client,
+            action: row.action,
+            target: row.target,
+            before: row.before_value,
+            after: row.after_value,
+            prev_hash: row.prev_hash,
+            hash: row.hash,
+        }
+    }
+}
+
+impl From<AuditEntry> for DbAdminAudit {
+    fn from(entry: AuditEntry) -> Self {
+        DbAdminAudit {
+            seq: entry.seq,
+            timestamp: entry.timestamp,
+            actor: entry.actor,
+            client: entry.client,
+            action: entry.action,
+            target: entry.target,
+            before_value: entry.before,
+            after_value: entry.after,
+            prev_hash: entry.prev_hash,
+            hash: entry.hash,
+        }
+    }
 }
 
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = super::schema::processes)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
 pub struct DbProcess {
     pub row_id: i32,
     pub process_id: String,
@@ -1559,6 +3095,7 @@ pub struct DbProcess {
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = super::schema::messages)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
 pub struct DbMessage {
     pub row_id: i32,
     pub process_id: String,
@@ -1575,6 +3112,7 @@ pub struct DbMessage {
 #[derive(Queryable, Selectable)]
 #[diesel(table_name = super::schema::messages)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
+#[allow(dead_code)]
 pub struct DbMessageWithoutData {
     pub row_id: i32,
     pub process_id: String,
@@ -1600,6 +3138,123 @@ pub struct NewMessage<'a> {
     pub hash_chain: &'a str,
 }
 
+// a NewMessage owning its data, for the write batcher
+struct PendingMessage {
+    process_id: String,
+    message_id: String,
+    assignment_id: String,
+    message_data: serde_json::Value,
+    bundle: Vec<u8>,
+    epoch: i32,
+    nonce: i32,
+    timestamp: i64,
+    hash_chain: String,
+}
+
+impl From<&NewMessage<'_>> for PendingMessage {
+    fn from(new_message: &NewMessage) -> Self {
+        PendingMessage {
+            process_id: new_message.process_id.to_string(),
+            message_id: new_message.message_id.to_string(),
+            assignment_id: new_message.assignment_id.to_string(),
+            message_data: new_message.message_data.clone(),
+            bundle: new_message.bundle.to_vec(),
+            epoch: *new_message.epoch,
+            nonce: *new_message.nonce,
+            timestamp: *new_message.timestamp,
+            hash_chain: new_message.hash_chain.to_string(),
+        }
+    }
+}
+
+impl PendingMessage {
+    fn new(message: &Message, bundle_in: &[u8]) -> Result<Self, StoreErrorType> {
+        Ok(PendingMessage {
+            process_id: message.process_id()?,
+            message_id: message.message_id()?,
+            assignment_id: message.assignment_id()?,
+            message_data: serde_json::to_value(message)?,
+            bundle: bundle_in.to_vec(),
+            epoch: message.epoch()?,
+            nonce: message.nonce()?,
+            timestamp: message.timestamp()?,
+            hash_chain: message.hash_chain()?,
+        })
+    }
+
+    fn row(&self) -> NewMessage<'_> {
+        NewMessage {
+            process_id: &self.process_id,
+            message_id: &self.message_id,
+            assignment_id: &self.assignment_id,
+            message_data: self.message_data.clone(),
+            bundle: &self.bundle,
+            epoch: &self.epoch,
+            nonce: &self.nonce,
+            timestamp: &self.timestamp,
+            hash_chain: &self.hash_chain,
+        }
+    }
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = super::schema::process_stats)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbProcessStats {
+    pub process_id: String,
+    pub message_count: i64,
+    pub byte_count: i64,
+    pub last_nonce: Option<i32>,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = super::schema::chain_snapshots)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbChainSnapshot {
+    pub process_id: String,
+    pub epoch: i32,
+    pub nonce: i32,
+    pub timestamp: i64,
+    pub hash_chain: String,
+    pub assignment_id: String,
+    pub signature: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = super::schema::chain_snapshots)]
+pub struct NewChainSnapshot<'a> {
+    pub process_id: &'a str,
+    pub epoch: &'a i32,
+    pub nonce: &'a i32,
+    pub timestamp: &'a i64,
+    pub hash_chain: &'a str,
+    pub assignment_id: &'a str,
+    pub signature: &'a str,
+}
+
+#[derive(Queryable, Selectable)]
+#[diesel(table_name = super::schema::inclusion_proofs)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+pub struct DbInclusionProof {
+    pub item_id: String,
+    pub bundle_id: String,
+    pub root: String,
+    pub leaf_index: i32,
+    pub leaf_count: i32,
+    pub path: String,
+}
+
+#[derive(Insertable)]
+#[diesel(table_name = super::schema::inclusion_proofs)]
+pub struct NewInclusionProof<'a> {
+    pub item_id: &'a str,
+    pub bundle_id: &'a str,
+    pub root: &'a str,
+    pub leaf_index: &'a i32,
+    pub leaf_count: &'a i32,
+    pub path: &'a str,
+}
+
 #[derive(Insertable)]
 #[diesel(table_name = super::schema::processes)]
 pub struct NewProcess<'a> {
@@ -1622,6 +3277,53 @@ pub struct DbScheduler {
     pub no_route: Option<bool>,
     pub wallets_to_route: Option<String>,
     pub wallets_only: Option<bool>,
+    pub priority: Option<i32>,
+    pub large_objects: Option<bool>,
+    pub weight: Option<i32>,
+    pub public_url: Option<String>,
+    pub drain: Option<bool>,
+    pub max_processes: Option<i32>,
+    pub region: Option<String>,
+    pub tags_to_route: Option<String>,
+    pub wallets_policy: Option<String>,
+}
+
+/*
+  wallets_to_route is a json array in a text column, the
+  same in postgres and the sqlite router store. A row an
+  older router wrote during a rolling upgrade holds a
+  comma separated string and reads as the same wallets.
+*/
+pub fn wallets_column(wallets: &Option<Vec<String>>) -> Option<String> {
+    wallets
+        .as_ref()
+        .map(|wallets| serde_json::json!(wallets).to_string())
+}
+
+pub fn wallets_from_column(column: Option<String>) -> Option<Vec<String>> {
+    column.map(|column| serde_json::from_str(&column).unwrap_or_else(|_| split_wallets(&column)))
+}
+
+impl From<DbScheduler> for Scheduler {
+    fn from(db_scheduler: DbScheduler) -> Self {
+        Scheduler {
+            row_id: Some(db_scheduler.row_id),
+            url: db_scheduler.url,
+            process_count: db_scheduler.process_count,
+            no_route: db_scheduler.no_route,
+            wallets_to_route: wallets_from_column(db_scheduler.wallets_to_route),
+            wallets_only: db_scheduler.wallets_only,
+            priority: db_scheduler.priority,
+            large_objects: db_scheduler.large_objects,
+            weight: db_scheduler.weight,
+            public_url: db_scheduler.public_url,
+            drain: db_scheduler.drain,
+            max_processes: db_scheduler.max_processes,
+            region: db_scheduler.region,
+            tags_to_route: db_scheduler.tags_to_route,
+            wallets_policy: db_scheduler.wallets_policy,
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -1632,6 +3334,15 @@ pub struct NewScheduler<'a> {
     pub no_route: Option<&'a bool>,
     pub wallets_to_route: Option<&'a str>,
     pub wallets_only: Option<&'a bool>,
+    pub priority: Option<&'a i32>,
+    pub large_objects: Option<&'a bool>,
+    pub weight: Option<&'a i32>,
+    pub public_url: Option<&'a str>,
+    pub drain: Option<&'a bool>,
+    pub max_processes: Option<&'a i32>,
+    pub region: Option<&'a str>,
+    pub tags_to_route: Option<&'a str>,
+    pub wallets_policy: Option<&'a str>,
 }
 
 #[derive(Queryable, Selectable)]
@@ -1641,6 +3352,18 @@ pub struct DbProcessScheduler {
     pub row_id: i32,
     pub process_id: String,
     pub scheduler_row_id: i32,
+    // unset on rows saved before it was recorded
+    pub spawned_at: Option<i64>,
+}
+
+impl From<DbProcessScheduler> for ProcessScheduler {
+    fn from(db_process_scheduler: DbProcessScheduler) -> Self {
+        ProcessScheduler {
+            row_id: Some(db_process_scheduler.row_id),
+            process_id: db_process_scheduler.process_id,
+            scheduler_row_id: db_process_scheduler.scheduler_row_id,
+        }
+    }
 }
 
 #[derive(Insertable)]
@@ -1648,6 +3371,7 @@ pub struct DbProcessScheduler {
 pub struct NewProcessScheduler<'a> {
     pub process_id: &'a str,
     pub scheduler_row_id: &'a i32,
+    pub spawned_at: Option<i64>,
 }
 
 /*
@@ -1830,23 +3554,16 @@ mod bytestore {
             };
 
             if let Some(ref db) = *db {
-                match db.get(&key) {
-                    Ok(Some(_)) => true,
-                    _ => false,
-                }
+                matches!(db.get(&key), Ok(Some(_)))
             } else {
                 false
             }
         }
 
-        pub fn save_deep_hash(
-            &self,
-            process_id: &String,
-            deep_hash: &String,
-        ) -> Result<(), String> {
+        pub fn save_deep_hash(&self, process_id: &str, deep_hash: &str) -> Result<(), String> {
             let key = format!("deephash___{}___{}", process_id, deep_hash).into_bytes();
 
-            let value = format!("{}", process_id).into_bytes();
+            let value = process_id.to_string().into_bytes();
 
             let db = match self.db.read() {
                 Ok(r) => r,
@@ -1862,11 +3579,7 @@ mod bytestore {
             }
         }
 
-        pub fn delete_deep_hash(
-            &self,
-            process_id: &String,
-            deep_hash: &String,
-        ) -> Result<(), String> {
+        pub fn delete_deep_hash(&self, process_id: &str, deep_hash: &str) -> Result<(), String> {
             let key = format!("deephash___{}___{}", process_id, deep_hash).into_bytes();
         
             let db = match self.db.read() {
@@ -1886,12 +3599,12 @@ mod bytestore {
 
         pub fn save_deep_hash_version(
             &self,
-            process_id: &String,
-            version: &String,
+            process_id: &str,
+            version: &str,
         ) -> Result<(), String> {
             let key = format!("deephashversion___{}", process_id).into_bytes();
 
-            let value = format!("{}", version).into_bytes();
+            let value = version.to_string().into_bytes();
 
             let db = match self.db.read() {
                 Ok(r) => r,
@@ -1907,7 +3620,7 @@ mod bytestore {
             }
         }
 
-        pub fn get_deep_hash_version(&self, process_id: &String) -> Result<String, String> {
+        pub fn get_deep_hash_version(&self, process_id: &str) -> Result<String, String> {
             let key = format!("deephashversion___{}", process_id).into_bytes();
 
             let db = match self.db.read() {
@@ -1921,14 +3634,14 @@ mod bytestore {
                         Ok(vs) => Ok(vs),
                         Err(_) => Err("Error parsing deep hash version".to_string()),
                     },
-                    _ => return Err("No deephash version found".to_string()),
+                    _ => Err("No deephash version found".to_string()),
                 }
             } else {
-                return Err("Cannot acquire read lock, deep hash version, match".to_string());
+                Err("Cannot acquire read lock, deep hash version, match".to_string())
             }
         }
 
-        pub fn deep_hash_exists(&self, process_id: &String, deep_hash: &String) -> bool {
+        pub fn deep_hash_exists(&self, process_id: &str, deep_hash: &str) -> bool {
             let key = format!("deephash___{}___{}", process_id, deep_hash).into_bytes();
 
             let db = match self.db.read() {
@@ -1937,10 +3650,7 @@ mod bytestore {
             };
 
             if let Some(ref db) = *db {
-                match db.get(&key) {
-                    Ok(Some(_)) => true,
-                    _ => false,
-                }
+                matches!(db.get(&key), Ok(Some(_)))
             } else {
                 false
             }
@@ -1994,10 +3704,12 @@ pub async fn migrate_to_disk() -> io::Result<()> {
         }
     };
 
-    format!("Total messages to process: {}", total_count);
+    data_store
+        .logger
+        .log(format!("Total messages to process: {}", total_count));
 
     let config = AoConfig::new(Some("su".to_string())).expect("Failed to read configuration");
-    let batch_size = config.migration_batch_size.clone() as usize;
+    let batch_size = config.migration_batch_size as usize;
 
     let processed_count = Arc::new(AtomicUsize::new(0));
 
@@ -0,0 +1,605 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+
+use async_trait::async_trait;
+use reqwest::{Client, Url};
+use serde_json::json;
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader as AsyncBufReader};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::spawn;
+use tokio::sync::mpsc::{self, Receiver, Sender};
+use tokio::sync::{oneshot, Notify};
+use tokio::task::spawn_blocking;
+use tokio::time::{sleep, timeout, Duration};
+
+use crate::domain::core::dal::{AssignmentEvent, Log, Streamer, StreamerErrorType};
+
+/*
+  Publishes every new assignment to NATS or Kafka so
+  indexers and CUs can consume the schedule as a stream
+  instead of polling the SU.
+
+  Events are queued for a writer that appends them to a
+  local spool file, syncing once for everything queued
+  since its last write, and a background task delivers
+  them in order. publish only returns once its event is
+  synced, so an assignment is never answered before its
+  event is on disk. The checkpoint
+  (a byte offset into the spool) is only moved forward
+  after the broker acknowledges an event, so a crash in
+  between means it is sent again on restart. Delivery is
+  at-least-once, consumers should dedupe on assignment_id.
+*/
+
+const BATCH_SIZE: usize = 100;
+
+// events waiting for the spool writer, publish waits for room once this many are queued
+const QUEUE_SIZE: usize = 10_000;
+
+// told the result of the spool write of one event
+type Ack = oneshot::Sender<Result<(), String>>;
+
+enum StreamTarget {
+    // plain NATS core publish, addr is host:port
+    Nats { addr: String, subject: String },
+    // Kafka through a Confluent compatible REST proxy
+    Kafka { rest_url: Url, topic: String },
+}
+
+impl From<io::Error> for StreamerErrorType {
+    fn from(error: io::Error) -> Self {
+        StreamerErrorType::StreamError(format!("Stream io error: {}", error))
+    }
+}
+
+impl From<serde_json::Error> for StreamerErrorType {
+    fn from(error: serde_json::Error) -> Self {
+        StreamerErrorType::StreamError(format!("Stream json error: {}", error))
+    }
+}
+
+impl From<reqwest::Error> for StreamerErrorType {
+    fn from(error: reqwest::Error) -> Self {
+        StreamerErrorType::StreamError(format!("Stream request error: {}", error))
+    }
+}
+
+/*
+  The spool is an append only file of newline separated
+  json events plus a checkpoint file holding the offset
+  of the first undelivered event.
+*/
+struct Spool {
+    spool_path: PathBuf,
+    checkpoint_path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl Spool {
+    fn new(dir: &str) -> Result<Self, StreamerErrorType> {
+        fs::create_dir_all(dir)?;
+        let mut spool_path = PathBuf::from(dir);
+        spool_path.push("assignments.spool");
+        let mut checkpoint_path = PathBuf::from(dir);
+        checkpoint_path.push("assignments.checkpoint");
+
+        Ok(Spool {
+            spool_path,
+            checkpoint_path,
+            lock: Mutex::new(()),
+        })
+    }
+
+    // blocking, writes every line and syncs once
+    fn append(&self, lines: &[String]) -> Result<(), StreamerErrorType> {
+        let _l = self.lock.lock().unwrap();
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.spool_path)?;
+        let mut buf = Vec::new();
+        for line in lines {
+            buf.extend_from_slice(line.as_bytes());
+            buf.push(b'\n');
+        }
+        file.write_all(&buf)?;
+        file.sync_data()?;
+        Ok(())
+    }
+
+    // events appended after the checkpoint, ones not delivered yet
+    fn pending(&self) -> usize {
+        let _l = self.lock.lock().unwrap();
+        let mut file = match File::open(&self.spool_path) {
+            Ok(f) => f,
+            Err(_) => return 0,
+        };
+        let mut rest = Vec::new();
+        if file.seek(SeekFrom::Start(self.checkpoint())).is_err()
+            || file.read_to_end(&mut rest).is_err()
+        {
+            return 0;
+        }
+        rest.iter().filter(|b| **b == b'\n').count()
+    }
+
+    fn checkpoint(&self) -> u64 {
+        match fs::read_to_string(&self.checkpoint_path) {
+            Ok(c) => c.trim().parse().unwrap_or(0),
+            Err(_) => 0,
+        }
+    }
+
+    /*
+      Read up to BATCH_SIZE events starting at offset, each
+      event is returned with the offset right after it which
+      is what gets committed once it is delivered.
+    */
+    fn read_from(&self, offset: u64) -> Result<Vec<(u64, String)>, StreamerErrorType> {
+        let _l = self.lock.lock().unwrap();
+        let mut file = match File::open(&self.spool_path) {
+            Ok(f) => f,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(StreamerErrorType::from(e)),
+        };
+        file.seek(SeekFrom::Start(offset))?;
+
+        let mut reader = BufReader::new(file);
+        let mut events = vec![];
+        let mut position = offset;
+        while events.len() < BATCH_SIZE {
+            let mut line = String::new();
+            let read = reader.read_line(&mut line)?;
+            /*
+              A line without a newline is a partial write, leave
+              it for the next pass
+            */
+            if read == 0 || !line.ends_with('\n') {
+                break;
+            }
+            position += read as u64;
+            let trimmed = line.trim_end();
+            if !trimmed.is_empty() {
+                events.push((position, trimmed.to_string()));
+            }
+        }
+
+        Ok(events)
+    }
+
+    /*
+      Persist the new checkpoint, once everything in the
+      spool is delivered both files are reset so the spool
+      does not grow forever.
+    */
+    fn commit(&self, offset: u64) -> Result<(), StreamerErrorType> {
+        let _l = self.lock.lock().unwrap();
+        let spool_len = match fs::metadata(&self.spool_path) {
+            Ok(m) => m.len(),
+            Err(_) => 0,
+        };
+
+        let next = if offset >= spool_len {
+            File::create(&self.spool_path)?;
+            0
+        } else {
+            offset
+        };
+
+        let tmp_path = self.checkpoint_path.with_extension("tmp");
+        fs::write(&tmp_path, next.to_string())?;
+        fs::rename(&tmp_path, &self.checkpoint_path)?;
+        Ok(())
+    }
+}
+
+struct NatsConn {
+    reader: AsyncBufReader<OwnedReadHalf>,
+    writer: OwnedWriteHalf,
+}
+
+impl NatsConn {
+    async fn connect(addr: &str) -> Result<Self, StreamerErrorType> {
+        let stream = TcpStream::connect(addr).await?;
+        let (read_half, mut writer) = stream.into_split();
+        let mut reader = AsyncBufReader::new(read_half);
+
+        let mut info = String::new();
+        reader.read_line(&mut info).await?;
+        if !info.starts_with("INFO") {
+            return Err(StreamerErrorType::StreamError(format!(
+                "Unexpected NATS greeting: {}",
+                info.trim_end()
+            )));
+        }
+
+        writer
+            .write_all(b"CONNECT {\"verbose\":false,\"pedantic\":false,\"name\":\"ao-su\"}\r\n")
+            .await?;
+
+        Ok(NatsConn { reader, writer })
+    }
+
+    /*
+      NATS core publishing has no per message ack so a
+      PING is sent after the PUB, the server answers PONG
+      only after it has processed everything before it.
+    */
+    async fn publish(&mut self, subject: &str, payload: &str) -> Result<(), StreamerErrorType> {
+        let frame = format!(
+            "PUB {} {}\r\n{}\r\nPING\r\n",
+            subject,
+            payload.len(),
+            payload
+        );
+        self.writer.write_all(frame.as_bytes()).await?;
+        self.writer.flush().await?;
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line).await? == 0 {
+                return Err(StreamerErrorType::StreamError(
+                    "NATS connection closed".to_string(),
+                ));
+            }
+            match line.trim_end() {
+                "PONG" => return Ok(()),
+                "PING" => self.writer.write_all(b"PONG\r\n").await?,
+                l if l.starts_with("-ERR") => {
+                    return Err(StreamerErrorType::StreamError(format!("NATS error: {}", l)))
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+pub struct StreamClient {
+    queue: Sender<(String, Ack)>,
+    spool: Arc<Spool>,
+}
+
+impl StreamClient {
+    pub fn new(
+        stream_url: &str,
+        topic: &str,
+        spool_dir: &str,
+        logger: Arc<dyn Log>,
+    ) -> Result<Self, StreamerErrorType> {
+        let url = Url::parse(stream_url)
+            .map_err(|e| StreamerErrorType::StreamError(format!("Invalid stream url: {}", e)))?;
+
+        let target = match url.scheme() {
+            "nats" => {
+                let host = url.host_str().ok_or(StreamerErrorType::StreamError(
+                    "Missing host in stream url".to_string(),
+                ))?;
+                StreamTarget::Nats {
+                    addr: format!("{}:{}", host, url.port().unwrap_or(4222)),
+                    subject: topic.to_string(),
+                }
+            }
+            "http" | "https" => StreamTarget::Kafka {
+                rest_url: url,
+                topic: topic.to_string(),
+            },
+            s => {
+                return Err(StreamerErrorType::StreamError(format!(
+                    "Unsupported stream url scheme: {}",
+                    s
+                )))
+            }
+        };
+
+        let spool = Arc::new(Spool::new(spool_dir)?);
+        let notify = Arc::new(Notify::new());
+        let (queue, receiver) = mpsc::channel(QUEUE_SIZE);
+
+        spawn_blocking({
+            let (spool, notify, logger) = (spool.clone(), notify.clone(), logger.clone());
+            move || write_spool(receiver, spool, notify, logger)
+        });
+        spawn(deliver(target, spool.clone(), notify, logger));
+
+        Ok(StreamClient { queue, spool })
+    }
+}
+
+fn writer_stopped() -> StreamerErrorType {
+    StreamerErrorType::StreamError("Stream spool writer stopped".to_string())
+}
+
+#[async_trait]
+impl Streamer for StreamClient {
+    async fn publish(&self, event: &AssignmentEvent) -> Result<(), StreamerErrorType> {
+        let line = serde_json::to_string(event)?;
+        let (ack, written) = oneshot::channel();
+        self.queue
+            .send((line, ack))
+            .await
+            .map_err(|_| writer_stopped())?;
+        written
+            .await
+            .map_err(|_| writer_stopped())?
+            .map_err(StreamerErrorType::StreamError)
+    }
+
+    // still queued for the writer plus written but not delivered
+    fn pending(&self) -> usize {
+        let queued = self.queue.max_capacity() - self.queue.capacity();
+        queued + self.spool.pending()
+    }
+}
+
+// the next event and whatever else is already queued, None once publishing stops
+fn next_batch<I>(receiver: &mut Receiver<I>) -> Option<Vec<I>> {
+    let mut lines = vec![receiver.blocking_recv()?];
+    while lines.len() < BATCH_SIZE {
+        match receiver.try_recv() {
+            Ok(line) => lines.push(line),
+            Err(_) => break,
+        }
+    }
+    Some(lines)
+}
+
+/*
+  Runs on a blocking thread for the life of the server,
+  appending each batch of queued events to the spool and
+  waking the delivery task once they are synced. Every
+  publisher in the batch is told the result, a batch that
+  can't be written fails all of them.
+*/
+fn write_spool(
+    mut receiver: Receiver<(String, Ack)>,
+    spool: Arc<Spool>,
+    notify: Arc<Notify>,
+    logger: Arc<dyn Log>,
+) {
+    while let Some(batch) = next_batch(&mut receiver) {
+        let (lines, acks): (Vec<String>, Vec<Ack>) = batch.into_iter().unzip();
+        let result = spool.append(&lines).map_err(|e| {
+            logger.error(format!(
+                "Failed to write {} events to the stream spool: {:?}",
+                lines.len(),
+                e
+            ));
+            format!("Failed to write the stream spool: {:?}", e)
+        });
+        if result.is_ok() {
+            notify.notify_one();
+        }
+        for ack in acks {
+            let _ = ack.send(result.clone());
+        }
+    }
+}
+
+/*
+  Used when no STREAM_URL is configured
+*/
+pub struct NoopStreamer;
+
+#[async_trait]
+impl Streamer for NoopStreamer {
+    async fn publish(&self, _event: &AssignmentEvent) -> Result<(), StreamerErrorType> {
+        Ok(())
+    }
+}
+
+async fn send(
+    target: &StreamTarget,
+    nats: &mut Option<NatsConn>,
+    client: &Client,
+    line: &str,
+) -> Result<(), StreamerErrorType> {
+    match target {
+        StreamTarget::Nats { addr, subject } => {
+            if nats.is_none() {
+                *nats = Some(NatsConn::connect(addr).await?);
+            }
+            let conn = nats.as_mut().unwrap();
+            let result = timeout(Duration::from_secs(10), conn.publish(subject, line))
+                .await
+                .unwrap_or(Err(StreamerErrorType::StreamError(
+                    "NATS publish timed out".to_string(),
+                )));
+            // reconnect on the next attempt
+            if result.is_err() {
+                *nats = None;
+            }
+            result
+        }
+        StreamTarget::Kafka { rest_url, topic } => {
+            let value: serde_json::Value = serde_json::from_str(line)?;
+            let body = json!({
+                "records": [{ "key": value["process_id"], "value": value }]
+            });
+            let url = rest_url
+                .join(&format!("topics/{}", topic))
+                .map_err(|e| StreamerErrorType::StreamError(e.to_string()))?;
+            let response = client
+                .post(url)
+                .header("Content-Type", "application/vnd.kafka.json.v2+json")
+                .body(body.to_string())
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await?;
+            if response.status().is_success() {
+                Ok(())
+            } else {
+                Err(StreamerErrorType::StreamError(format!(
+                    "Kafka rest proxy returned {}",
+                    response.status()
+                )))
+            }
+        }
+    }
+}
+
+/*
+  Runs for the life of the server, resumes from the
+  stored checkpoint and retries each event with backoff
+  until it is acknowledged so ordering is preserved.
+*/
+async fn deliver(target: StreamTarget, spool: Arc<Spool>, notify: Arc<Notify>, logger: Arc<dyn Log>) {
+    let client = Client::new();
+    let mut nats: Option<NatsConn> = None;
+    let max_delay = Duration::from_secs(32);
+
+    loop {
+        let events = match spool.read_from(spool.checkpoint()) {
+            Ok(e) => e,
+            Err(e) => {
+                logger.error(format!("Failed to read stream spool: {:?}", e));
+                sleep(Duration::from_secs(5)).await;
+                continue;
+            }
+        };
+
+        if events.is_empty() {
+            let _ = timeout(Duration::from_secs(5), notify.notified()).await;
+            continue;
+        }
+
+        for (next_offset, line) in events {
+            let mut delay = Duration::from_secs(1);
+            while let Err(e) = send(&target, &mut nats, &client, &line).await {
+                logger.error(format!("Stream publish failed, retrying in {:?}: {:?}", delay, e));
+                sleep(delay).await;
+                delay = (delay * 2).min(max_delay);
+            }
+
+            if let Err(e) = spool.commit(next_offset) {
+                logger.error(format!("Failed to commit stream checkpoint: {:?}", e));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::logger::SuLog;
+    use tempdir::TempDir;
+
+    fn event(nonce: i32) -> AssignmentEvent {
+        AssignmentEvent {
+            process_id: "pid".to_string(),
+            assignment_id: format!("aid{}", nonce),
+            message_id: format!("mid{}", nonce),
+            epoch: 0,
+            nonce,
+            timestamp: 0,
+            hash_chain: "chain".to_string(),
+            block_height: "1".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_spool_resumes_from_checkpoint() {
+        let dir = TempDir::new("spool").unwrap();
+        let spool = Spool::new(dir.path().to_str().unwrap()).unwrap();
+
+        spool
+            .append(&["{\"nonce\":1}".to_string(), "{\"nonce\":2}".to_string()])
+            .unwrap();
+
+        let events = spool.read_from(spool.checkpoint()).unwrap();
+        assert_eq!(events.len(), 2);
+
+        spool.commit(events[0].0).unwrap();
+
+        let remaining = spool.read_from(spool.checkpoint()).unwrap();
+        assert_eq!(remaining.len(), 1);
+        assert_eq!(remaining[0].1, "{\"nonce\":2}");
+    }
+
+    #[test]
+    fn test_spool_resets_when_drained() {
+        let dir = TempDir::new("spool").unwrap();
+        let spool = Spool::new(dir.path().to_str().unwrap()).unwrap();
+
+        spool.append(&["{\"nonce\":1}".to_string()]).unwrap();
+        let events = spool.read_from(spool.checkpoint()).unwrap();
+        spool.commit(events[0].0).unwrap();
+
+        assert_eq!(spool.checkpoint(), 0);
+        assert!(spool.read_from(0).unwrap().is_empty());
+
+        spool.append(&["{\"nonce\":2}".to_string()]).unwrap();
+        let events = spool.read_from(spool.checkpoint()).unwrap();
+        assert_eq!(events[0].1, "{\"nonce\":2}");
+    }
+
+    #[test]
+    fn test_pending_counts_undelivered_events() {
+        let dir = TempDir::new("spool").unwrap();
+        let spool = Spool::new(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(spool.pending(), 0);
+
+        spool
+            .append(&["{\"nonce\":1}".to_string(), "{\"nonce\":2}".to_string()])
+            .unwrap();
+        assert_eq!(spool.pending(), 2);
+
+        let events = spool.read_from(spool.checkpoint()).unwrap();
+        spool.commit(events[0].0).unwrap();
+        assert_eq!(spool.pending(), 1);
+
+        spool.commit(events[1].0).unwrap();
+        assert_eq!(spool.pending(), 0);
+    }
+
+    #[test]
+    fn test_queued_events_are_written_together() {
+        let dir = TempDir::new("spool").unwrap();
+        let spool = Spool::new(dir.path().to_str().unwrap()).unwrap();
+        let (queue, mut receiver) = mpsc::channel(QUEUE_SIZE);
+        for nonce in 1..=3 {
+            queue.try_send(format!("{{\"nonce\":{}}}", nonce)).unwrap();
+        }
+        drop(queue);
+
+        let lines = next_batch(&mut receiver).unwrap();
+        assert_eq!(lines.len(), 3);
+        spool.append(&lines).unwrap();
+        assert!(next_batch(&mut receiver).is_none());
+
+        let events = spool.read_from(0).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[2].1, "{\"nonce\":3}");
+    }
+
+    #[tokio::test]
+    async fn test_publish_waits_for_the_spool() {
+        let dir = TempDir::new("spool").unwrap();
+        let spool = Arc::new(Spool::new(dir.path().to_str().unwrap()).unwrap());
+        let (queue, receiver) = mpsc::channel(QUEUE_SIZE);
+        let writer = spawn_blocking({
+            let spool = spool.clone();
+            move || write_spool(receiver, spool, Arc::new(Notify::new()), SuLog::init())
+        });
+        let client = StreamClient {
+            queue,
+            spool: spool.clone(),
+        };
+
+        client.publish(&event(1)).await.unwrap();
+        let events = spool.read_from(0).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(client.pending(), 1);
+        assert!(events[0].1.contains("\"aid1\""));
+
+        // a spool that can't be written fails the publish
+        fs::remove_file(&spool.spool_path).unwrap();
+        fs::create_dir(&spool.spool_path).unwrap();
+        assert!(client.publish(&event(2)).await.is_err());
+
+        drop(client);
+        writer.await.unwrap();
+    }
+}
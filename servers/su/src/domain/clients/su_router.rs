@@ -1,8 +1,15 @@
+use std::time::Duration;
+
 use crate::domain::config::AoConfig;
 use reqwest::{Client, Url};
 use async_trait::async_trait;
 
-use crate::domain::core::dal::{ ExtRouter, ExtRouterErrorType };
+use crate::domain::clients::otlp::propagate;
+use crate::domain::core::dal::{ ExtRouter, ExtRouterErrorType, ReplicaKind };
+use crate::domain::core::trace::{self, SpanKind};
+
+const PROBE_TIMEOUT: u64 = 5;
+const COPY_TIMEOUT: u64 = 30;
 
 pub struct SuRouter;
 
@@ -12,6 +19,7 @@ impl ExtRouter for SuRouter {
         &self, 
         process_id: String
     ) -> Result<String, ExtRouterErrorType> {
+        let _span = trace::span("router get_routed_assignment", SpanKind::Client);
         let config = AoConfig::new(
             Some("su".to_string())
         ).expect("Failed to read configuration");
@@ -31,11 +39,11 @@ impl ExtRouter for SuRouter {
             )
         };
 
-        let response = client
+        let response = propagate(client
             .get(
               url.join(&format!("/{}?process-id={}", process_id, process_id))
                     .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?,
-            )
+            ))
             .send()
             .await;
 
@@ -68,4 +76,186 @@ impl ExtRouter for SuRouter {
 
         Err(ExtRouterErrorType::NotFound("Process not found on the router".to_string()))
     }
+
+    /*
+        Used by the router health check, any 2xx from the
+        su health endpoint within the timeout is healthy
+    */
+    async fn probe_scheduler(&self, url: String) -> Result<(), ExtRouterErrorType> {
+        let client = Client::builder()
+            .timeout(Duration::from_secs(PROBE_TIMEOUT))
+            .build()
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+
+        let health_url = Url::parse(&url)
+            .and_then(|u| u.join("/health"))
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+
+        match client.get(health_url).send().await {
+            Ok(res) if res.status().is_success() => Ok(()),
+            Ok(res) => Err(ExtRouterErrorType::NetworkError(format!(
+                "Health check returned {}",
+                res.status()
+            ))),
+            Err(e) => Err(ExtRouterErrorType::NetworkError(e.to_string())),
+        }
+    }
+
+    /*
+        Used by the drain job to read a page of a process
+        history from the su it is leaving
+    */
+    async fn export_process(
+        &self,
+        url: String,
+        process_id: String,
+        from: Option<String>,
+        token: String,
+    ) -> Result<String, ExtRouterErrorType> {
+        let mut export_url = Url::parse(&url)
+            .and_then(|u| u.join(&format!("/admin/export/{}", process_id)))
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+        if let Some(from) = from {
+            export_url.query_pairs_mut().append_pair("from", &from);
+        }
+
+        let response = Client::new()
+            .get(export_url)
+            .bearer_auth(token)
+            .timeout(Duration::from_secs(COPY_TIMEOUT))
+            .send()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+        match status.is_success() {
+            true => Ok(body),
+            false => Err(ExtRouterErrorType::NetworkError(format!(
+                "Export returned {}: {}",
+                status, body
+            ))),
+        }
+    }
+
+    /*
+        Stores an exported process or message on the su it
+        is moving to, through the same endpoint a standby
+        su is fed from
+    */
+    async fn push_replica(
+        &self,
+        url: String,
+        kind: ReplicaKind,
+        binary: Vec<u8>,
+        token: String,
+    ) -> Result<(), ExtRouterErrorType> {
+        let mut replica_url = Url::parse(&url)
+            .and_then(|u| u.join("/admin/replica"))
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+        replica_url.query_pairs_mut().append_pair("type", kind.as_str());
+
+        let response = Client::new()
+            .post(replica_url)
+            .bearer_auth(token)
+            .body(binary)
+            .timeout(Duration::from_secs(COPY_TIMEOUT))
+            .send()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+
+        let status = response.status();
+        match status.is_success() {
+            true => Ok(()),
+            false => Err(ExtRouterErrorType::NetworkError(format!(
+                "Replica returned {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ))),
+        }
+    }
+
+    /*
+        Used by the reconcile task to read a page of the
+        process ids an su holds
+    */
+    async fn list_processes(
+        &self,
+        url: String,
+        after: Option<String>,
+        token: String,
+    ) -> Result<String, ExtRouterErrorType> {
+        let mut list_url = Url::parse(&url)
+            .and_then(|u| u.join("/admin/processes"))
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+        if let Some(after) = after {
+            list_url.query_pairs_mut().append_pair("after", &after);
+        }
+
+        let response = Client::new()
+            .get(list_url)
+            .bearer_auth(token)
+            .timeout(Duration::from_secs(COPY_TIMEOUT))
+            .send()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+
+        let status = response.status();
+        let body = response
+            .text()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+        match status.is_success() {
+            true => Ok(body),
+            false => Err(ExtRouterErrorType::NetworkError(format!(
+                "Process list returned {}: {}",
+                status, body
+            ))),
+        }
+    }
+
+    /*
+        Used by a process migration to stop the su it is
+        leaving from taking writes for it, or to let it
+        take them again when the migration fails
+    */
+    async fn set_read_only(
+        &self,
+        url: String,
+        process_id: String,
+        moved_to: Option<String>,
+        token: String,
+    ) -> Result<(), ExtRouterErrorType> {
+        let mut read_only_url = Url::parse(&url)
+            .and_then(|u| u.join(&format!("/admin/processes/{}/read-only", process_id)))
+            .map_err(|e| ExtRouterErrorType::ConfigError(e.to_string()))?;
+
+        let client = Client::new();
+        let request = match moved_to {
+            Some(moved_to) => {
+                read_only_url.query_pairs_mut().append_pair("to", &moved_to);
+                client.post(read_only_url)
+            }
+            None => client.delete(read_only_url),
+        };
+        let response = request
+            .bearer_auth(token)
+            .timeout(Duration::from_secs(COPY_TIMEOUT))
+            .send()
+            .await
+            .map_err(|e| ExtRouterErrorType::NetworkError(e.to_string()))?;
+
+        let status = response.status();
+        match status.is_success() {
+            true => Ok(()),
+            false => Err(ExtRouterErrorType::NetworkError(format!(
+                "Read only returned {}: {}",
+                status,
+                response.text().await.unwrap_or_default()
+            ))),
+        }
+    }
 }
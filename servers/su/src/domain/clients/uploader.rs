@@ -1,28 +1,21 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
 
 use reqwest::{Client, Url};
 
 extern crate serde;
-use serde::{Deserialize, Serialize};
 
 use tokio::spawn;
 use tokio::time::{sleep, Duration};
 
-use crate::domain::core::dal::{Uploader, UploaderErrorType};
+use crate::domain::core::dal::{CoreMetrics, Uploader, UploaderErrorType};
 use crate::domain::Log;
 
 pub struct UploaderClient {
     node_url: Url,
     logger: Arc<dyn Log>,
-}
-
-#[derive(Debug, Deserialize, Serialize)]
-struct IrysResponse {
-    id: String,
-    timestamp: u64,
-    version: String,
-    public: String,
-    signature: String,
+    metrics: Arc<dyn CoreMetrics>,
+    pending: Arc<AtomicUsize>,
 }
 
 impl From<reqwest::Error> for UploaderErrorType {
@@ -38,7 +31,11 @@ impl From<serde_json::Error> for UploaderErrorType {
 }
 
 impl UploaderClient {
-    pub fn new(node_url: &str, logger: Arc<dyn Log>) -> Result<Self, UploaderErrorType> {
+    pub fn new(
+        node_url: &str,
+        logger: Arc<dyn Log>,
+        metrics: Arc<dyn CoreMetrics>,
+    ) -> Result<Self, UploaderErrorType> {
         let url = match Url::parse(node_url) {
             Ok(u) => u,
             Err(e) => return Err(UploaderErrorType::UploadError(format!("{}", e))),
@@ -47,6 +44,8 @@ impl UploaderClient {
         Ok(UploaderClient {
             node_url: url,
             logger,
+            metrics,
+            pending: Arc::new(AtomicUsize::new(0)),
         })
     }
 }
@@ -56,6 +55,9 @@ impl Uploader for UploaderClient {
         let node_url_clone = self.node_url.clone();
         let tx_clone = tx.clone();
         let logger_clone = Arc::clone(&self.logger);
+        let metrics_clone = Arc::clone(&self.metrics);
+        let pending = Arc::clone(&self.pending);
+        pending.fetch_add(1, Ordering::SeqCst);
 
         spawn(async move {
             let client = Client::new();
@@ -66,7 +68,7 @@ impl Uploader for UploaderClient {
                 let response = client
                     .post(
                         node_url_clone
-                            .join(&format!("tx/{}", "arweave".to_string()))
+                            .join(&format!("tx/{}", "arweave"))
                             .expect("Failed to join URL"),
                     )
                     .header("Content-Type", "application/octet-stream")
@@ -86,6 +88,7 @@ impl Uploader for UploaderClient {
                         logger_clone.error(format!("Request error: {}", e));
                     }
                 }
+                metrics_clone.upload_failed();
 
                 // Exponential backoff logic
                 logger_clone.log(format!(
@@ -98,8 +101,13 @@ impl Uploader for UploaderClient {
                 // Double the delay for the next attempt, but don't exceed the max delay
                 delay = (delay * 2).min(max_delay);
             }
+            pending.fetch_sub(1, Ordering::SeqCst);
         });
 
         Ok(())
     }
+
+    fn pending(&self) -> usize {
+        self.pending.load(Ordering::SeqCst)
+    }
 }
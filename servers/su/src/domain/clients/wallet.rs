@@ -20,10 +20,10 @@ impl Wallet for FileWallet {
             Err(_) => return Err("failed to read wallet file".to_string()),
         };
         let mut key_json = String::new();
-        if let Ok(_) = file.read_to_string(&mut key_json) {
-            return Ok(key_json);
+        if file.read_to_string(&mut key_json).is_ok() {
+            Ok(key_json)
         } else {
-            return Err("Failed to read wallet from file system".to_string());
+            Err("Failed to read wallet from file system".to_string())
         }
     }
 
@@ -35,7 +35,7 @@ impl Wallet for FileWallet {
             Err(_) => return Err("failed to read wallet file".to_string()),
         };
         let mut key_json = String::new();
-        if let Ok(_) = file.read_to_string(&mut key_json) {
+        if file.read_to_string(&mut key_json).is_ok() {
             let jwk: JsonWebKey = match serde_json::from_str(&key_json) {
                 Ok(s) => s,
                 Err(_) => return Err("failed to parse the wallet file".to_string()),
@@ -46,9 +46,9 @@ impl Wallet for FileWallet {
             let keypair_modulus = modulus.to_vec();
             let mut context = sha2::Sha256::new();
             context.update(&keypair_modulus);
-            return Ok(base64_url::encode(&context.finalize().to_vec()));
+            Ok(base64_url::encode(&context.finalize().to_vec()))
         } else {
-            return Err("Failed to read wallet from file system".to_string());
+            Err("Failed to read wallet from file system".to_string())
         }
     }
 }
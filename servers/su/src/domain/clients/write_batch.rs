@@ -0,0 +1,197 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError, Sender};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use tokio::sync::oneshot;
+
+use crate::domain::core::dal::CoreMetrics;
+
+/*
+  Group commit for data store writes. Writes are queued
+  for a thread that takes them off the queue in batches
+  and hands each batch to a flush function in one go,
+  every caller waits for the result of its own item.
+
+  The batch size and the window the thread waits for a
+  batch to fill follow the queue. They start at a single
+  item written straight away, so a quiet su pays no
+  latency for batching, and grow while writes pile up
+  behind the batch being flushed.
+*/
+
+/*
+  Sizes the next batch from the depth of the queue the
+  last one left behind. A backlog of at least a full
+  batch doubles the size and the window, up to their
+  limits, and an empty queue halves them again. A
+  backlog in between keeps them where they are.
+*/
+#[derive(Debug)]
+pub struct AdaptiveBatch {
+    max_size: usize,
+    max_wait: u64,
+    size: usize,
+    wait: u64,
+}
+
+impl AdaptiveBatch {
+    pub fn new(max_size: usize, max_wait: u64) -> Self {
+        AdaptiveBatch {
+            max_size: max_size.max(1),
+            max_wait,
+            size: 1,
+            wait: 0,
+        }
+    }
+
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    // milliseconds
+    pub fn wait(&self) -> u64 {
+        self.wait
+    }
+
+    pub fn adjust(&mut self, depth: usize) {
+        if depth >= self.size {
+            self.size = (self.size * 2).min(self.max_size);
+            self.wait = self.max_wait.min((self.wait * 2).max(1));
+        } else if depth == 0 {
+            self.size = (self.size / 2).max(1);
+            self.wait /= 2;
+        }
+    }
+}
+
+type Reply = oneshot::Sender<Result<(), String>>;
+
+pub struct WriteBatcher<T> {
+    sender: Sender<(T, Reply)>,
+    // items written and not flushed yet
+    depth: Arc<AtomicUsize>,
+}
+
+impl<T: Send + 'static> WriteBatcher<T> {
+    /*
+      Start the writer thread. flush gets the items of a
+      batch in the order they were written and returns a
+      result for each of them, in the same order.
+    */
+    pub fn start<F>(
+        name: &str,
+        max_size: usize,
+        max_wait: u64,
+        metrics: Arc<dyn CoreMetrics>,
+        mut flush: F,
+    ) -> Result<Self, String>
+    where
+        F: FnMut(Vec<T>) -> Vec<Result<(), String>> + Send + 'static,
+    {
+        let (sender, receiver) = channel::<(T, Reply)>();
+        let depth = Arc::new(AtomicUsize::new(0));
+        let queued = depth.clone();
+
+        std::thread::Builder::new()
+            .name(name.to_string())
+            .spawn(move || {
+                let mut batch = AdaptiveBatch::new(max_size, max_wait);
+                while let Some(items) = collect(&receiver, &batch) {
+                    let size = items.len();
+                    let (items, replies): (Vec<T>, Vec<Reply>) = items.into_iter().unzip();
+                    for (reply, result) in replies.into_iter().zip(flush(items)) {
+                        let _ = reply.send(result);
+                    }
+
+                    let left = queued.fetch_sub(size, Ordering::SeqCst) - size;
+                    batch.adjust(left);
+                    metrics.write_batch_observe(size, batch.size(), batch.wait(), left);
+                }
+            })
+            .map_err(|e| format!("Failed to start the {} thread: {}", name, e))?;
+
+        Ok(WriteBatcher { sender, depth })
+    }
+
+    pub async fn write(&self, item: T) -> Result<(), String> {
+        let (reply, result) = oneshot::channel();
+        self.depth.fetch_add(1, Ordering::SeqCst);
+        if self.sender.send((item, reply)).is_err() {
+            self.depth.fetch_sub(1, Ordering::SeqCst);
+            return Err("Write batcher stopped".to_string());
+        }
+        result
+            .await
+            .map_err(|_| "Write batcher dropped the write".to_string())?
+    }
+}
+
+/*
+  The next batch, waiting as long as it takes for its
+  first item and at most the window for the rest. None
+  once every sender is gone.
+*/
+fn collect<I>(receiver: &Receiver<I>, batch: &AdaptiveBatch) -> Option<Vec<I>> {
+    let mut items = vec![receiver.recv().ok()?];
+    let deadline = Instant::now() + Duration::from_millis(batch.wait());
+    while items.len() < batch.size() {
+        match receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+            Ok(item) => items.push(item),
+            Err(RecvTimeoutError::Timeout) | Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+    Some(items)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_batch_follows_the_queue() {
+        let mut batch = AdaptiveBatch::new(8, 4);
+        assert_eq!((batch.size(), batch.wait()), (1, 0));
+
+        // a backlog grows the batch up to its limits
+        for _ in 0..5 {
+            batch.adjust(100);
+        }
+        assert_eq!((batch.size(), batch.wait()), (8, 4));
+
+        // a partial backlog holds it, an empty queue shrinks it
+        batch.adjust(3);
+        assert_eq!(batch.size(), 8);
+        batch.adjust(0);
+        assert_eq!((batch.size(), batch.wait()), (4, 2));
+        for _ in 0..5 {
+            batch.adjust(0);
+        }
+        assert_eq!((batch.size(), batch.wait()), (1, 0));
+    }
+
+    #[test]
+    fn test_collect_takes_what_is_queued() {
+        let (sender, receiver) = channel();
+        for i in 0..5 {
+            sender.send(i).unwrap();
+        }
+
+        // a single item batch never waits for more
+        let mut batch = AdaptiveBatch::new(4, 50);
+        assert_eq!(collect(&receiver, &batch), Some(vec![0]));
+
+        batch.adjust(4);
+        batch.adjust(4);
+        assert_eq!(collect(&receiver, &batch), Some(vec![1, 2, 3, 4]));
+
+        // a short batch is flushed when the window closes
+        sender.send(5).unwrap();
+        let start = Instant::now();
+        assert_eq!(collect(&receiver, &batch), Some(vec![5]));
+        assert!(start.elapsed() >= Duration::from_millis(batch.wait()));
+
+        drop(sender);
+        assert_eq!(collect(&receiver, &batch), None);
+    }
+}
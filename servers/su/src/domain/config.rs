@@ -1,8 +1,15 @@
+use std::collections::{HashMap, HashSet};
 use std::env;
+use std::fs;
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex, OnceLock, RwLock};
 
 use dotenv::dotenv;
+use sha2::{Digest, Sha256};
 
+use crate::domain::config_file::read_config_file;
+use crate::domain::core::dal::Mode;
+use crate::domain::core::ip_access::{parse_cidrs, Cidr, IpAccess, IpRules};
 use crate::domain::Config;
 
 #[derive(Debug, Clone)]
@@ -11,9 +18,10 @@ pub struct AoConfig {
     pub graphql_url: String,
     pub arweave_url: String,
     pub upload_node_url: String,
-    pub mode: String,
+    pub mode: Mode,
     pub scheduler_list_path: String,
     pub enable_metrics: bool,
+    pub enable_process_metrics: bool,
     pub enable_process_assignment: bool,
     pub arweave_url_list: Vec<String>,
 
@@ -31,12 +39,15 @@ pub struct AoConfig {
     pub database_read_url: String,
     pub max_read_memory: usize,
     pub process_cache_size: usize,
+    pub signature_cache_size: usize,
+    pub owner_cache_size: usize,
 
     /*
       These configurations are for the new local_store
       implementation that runs on only RocksDB
     */
     pub use_local_store: bool,
+    pub dual_write: bool,
     pub su_file_db_dir: String,
     pub su_index_db_dir: String,
 
@@ -51,19 +62,309 @@ pub struct AoConfig {
 
     pub enable_router_check: bool,
     pub router_url: String,
-    pub assignment: String
+    pub assignment: String,
+
+    /*
+      Optional assignment streaming, nats://host:port for
+      NATS or an http(s) url of a Kafka REST proxy
+    */
+    pub stream_url: String,
+    pub stream_topic: String,
+    pub stream_spool_dir: String,
+
+    pub cursor_secret: String,
+    pub allow_legacy_cursors: bool,
+
+    pub enable_router_decision_header: bool,
+
+    pub process_scheduler_cleanup_interval: u64,
+    pub process_scheduler_cleanup_policy: String,
+
+    pub admin_token: String,
+
+    /*
+      Optional remote denylist, a data item signed by
+      denylist_owner listing processes and owners to
+      refuse on the write path
+    */
+    pub denylist_url: String,
+    pub denylist_owner: String,
+    pub denylist_refresh_interval: u64,
+
+    // json file of this instance's own denylist entries
+    pub denylist_path: String,
+
+    // json file of the processes migrated off this su
+    pub read_only_path: String,
+
+    /*
+      Optional directory to keep the raw bytes of accepted
+      data items in, with its own retention period
+    */
+    pub raw_archive_dir: String,
+    pub raw_retention_days: u64,
+
+    /*
+      Optional standby su every committed item is
+      forwarded to, standby_token is its ADMIN_TOKEN
+    */
+    pub standby_url: String,
+    pub standby_token: String,
+    pub standby_sync: bool,
+
+    pub allow_newer_schema: bool,
+
+    pub large_process_threshold: usize,
+
+    pub scheduler_health_interval: u64,
+    pub scheduler_unhealthy_after: u32,
+
+    pub scheduler_list_reload_interval: u64,
+
+    pub routing_strategy: String,
+
+    pub route_cache_size: usize,
+    pub route_cache_ttl: u64,
+
+    // redis://[user:password@]host[:port][/db] shared by the router replicas
+    pub router_redis_url: String,
+
+    // postgres, sqlite or memory, where the router keeps its tables
+    pub router_store: String,
+    pub router_sqlite_path: String,
+    // postgres replicas the router's process and scheduler lookups are spread over
+    pub router_read_urls: Vec<String>,
+
+    // file every spawn the router places is appended to, empty is off
+    pub router_audit_log: String,
+
+    /*
+      How long the router audit log keeps each spawn before
+      rolling it into hourly counts, how long it keeps those
+      before rolling them into daily ones and how long it
+      keeps anything at all, 0 keeps them forever
+    */
+    pub router_audit_raw_hours: u64,
+    pub router_audit_hourly_days: u64,
+    pub router_audit_retention_days: u64,
+
+    /*
+      While the router tables can't be reached, redirect
+      from the route cache and the last scheduler list
+      instead of failing, the store is probed every
+      router_store_probe_interval seconds until it is back
+    */
+    pub router_degraded_reads: bool,
+    pub router_store_probe_interval: u64,
+
+    // probe the scheduler picked for a spawn, moving on when it is down
+    pub spawn_failover: bool,
+
+    // only redirect Messages to processes with a process_schedulers row
+    pub router_strict_messages: bool,
+
+    // send an owner's new processes to the scheduler of its first one
+    pub router_owner_sticky: bool,
+
+    /*
+      Request header naming the client's region for
+      spawns, and value=region pairs it is mapped through
+    */
+    pub router_region_header: String,
+    pub router_region_map: String,
+
+    /*
+      Forward requests to the scheduler the router picked
+      and relay its response instead of redirecting, with
+      at most router_proxy_pool_size idle connections kept
+      open to each scheduler
+    */
+    pub router_proxy: bool,
+    pub router_proxy_pool_size: usize,
+
+    // check the spawn of a process before its first message here
+    pub verify_process_genesis: bool,
+
+    /*
+      The url published in this su's Scheduler-Location
+      record, empty publishes none, and the record's
+      Time-To-Live in milliseconds
+    */
+    pub scheduler_location_url: String,
+    pub scheduler_location_ttl: u64,
+
+    // nonces between signed hash chain snapshots, 0 turns them off
+    pub chain_snapshot_interval: i32,
+
+    // most data items accepted in one POST /batch
+    pub batch_max_items: usize,
+
+    // largest data item body accepted, bigger ones are refused before they are read in full
+    pub max_data_item_size: usize,
+
+    // per wallet write limits, 0 turns each off
+    pub wallet_spawns_per_hour: u32,
+    pub wallet_messages_per_minute: u32,
+
+    /*
+      Read limits per minute, by client address without an
+      api key and per key with one, 0 turns each off. The
+      address comes from X-Forwarded-For only when
+      trust_forwarded_for is set.
+    */
+    pub anonymous_reads_per_minute: u32,
+    pub api_key_reads_per_minute: u32,
+    pub api_keys_path: String,
+    pub trust_forwarded_for: bool,
+
+    /*
+      TRUSTED_PROXIES and the allowed and denied addresses
+      of each endpoint class, parsed once here rather than
+      on every request
+    */
+    pub ip_access: Arc<IpAccess>,
+
+    /*
+      Requests handled at once per endpoint class, writes,
+      big reads and admin, 0 leaves a class unlimited. Up
+      to concurrency_queue_size more wait for a slot, past
+      that they are refused with a 503.
+    */
+    pub write_concurrency: usize,
+    pub read_concurrency: usize,
+    pub admin_concurrency: usize,
+    pub concurrency_queue_size: usize,
+
+    // json lines admin audit log, instead of the database
+    pub admin_audit_path: String,
+
+    /*
+      Messages with a Schedule-At tag in the future are
+      held in deferred_dir and assigned when it comes, at
+      most deferred_max_delay seconds ahead. Empty is off.
+    */
+    pub deferred_dir: String,
+    pub deferred_max_delay: u64,
+
+    /*
+      The drain job, processes on draining schedulers are
+      moved in batches and their history copied when a
+      copy token is set
+    */
+    pub drain_interval: u64,
+    pub drain_batch_size: i64,
+    pub drain_copy_token: String,
+
+    /*
+      The rebalancer, every rebalance_interval seconds up
+      to rebalance_max_moves idle processes are migrated
+      from the most to the least loaded scheduler while
+      their loads differ by more than rebalance_threshold
+      of the average. 0 is off.
+    */
+    pub rebalance_interval: u64,
+    pub rebalance_max_moves: usize,
+    pub rebalance_threshold: f64,
+    // seconds without a message before a process counts as idle
+    pub rebalance_idle_secs: u64,
+    pub rebalance_dry_run: bool,
+
+    // Name=Value pairs added to every assignment
+    pub assignment_tags: String,
+
+    /*
+      Resident memory in bytes at which writes start
+      being shed, largest first, and at which all are
+    */
+    pub memory_soft_limit: u64,
+    pub memory_hard_limit: u64,
+
+    /*
+      Replica reads, only used when DATABASE_READ_URL
+      points at a separate database
+    */
+    pub read_hedge_delay: u64,
+    pub read_freshness_window: u64,
+    pub read_watermark_interval: u64,
+
+    // sign the body of message and process reads with the su wallet
+    pub sign_read_responses: bool,
+
+    /*
+      Every signing_check_interval seconds the su signs and
+      verifies a canary assignment, 0 turns the check off.
+      With signing_check_read_only writes are refused while
+      it fails.
+    */
+    pub signing_check_interval: u64,
+    pub signing_check_read_only: bool,
+
+    /*
+      Percent of saved assignments read straight back and
+      compared with the one acked, 0 is off
+    */
+    pub write_verify_percent: f64,
+
+    /*
+      Group commit of message inserts, batches grow with
+      the queue up to write_batch_max messages and wait up
+      to write_batch_max_wait milliseconds to fill. A max
+      of 0 saves every message on its own.
+    */
+    pub write_batch_max: usize,
+    pub write_batch_max_wait: u64,
+
+    /*
+      Router mode only. A primary streams every change to
+      its router tables to the router at router_standby_url,
+      router_standby_token is that router's ADMIN_TOKEN. A
+      router started with router_standby set applies the
+      stream and assigns no processes until it is promoted.
+    */
+    pub router_standby: bool,
+    pub router_standby_url: String,
+    pub router_standby_token: String,
+
+    /*
+      Credentials and region for su export-parquet to an
+      s3:// destination, aws_endpoint_url points it at an
+      S3 compatible store instead of AWS
+    */
+    pub aws_endpoint_url: String,
+    pub aws_region: String,
+    pub aws_access_key_id: String,
+    pub aws_secret_access_key: String,
+    pub aws_session_token: String,
+
+    /*
+      OTLP/HTTP collector request traces are sent to, like
+      http://localhost:4318, tracing is off without one.
+      otel_sample_ratio of the requests that arrive without
+      a traceparent are traced, the others follow the
+      sampled flag of theirs.
+    */
+    pub otel_endpoint: String,
+    pub otel_service_name: String,
+    pub otel_sample_ratio: f64,
+
+    /*
+      Seconds a stopping server waits for the requests in
+      flight, and then again for the bundle uploads still
+      pending, before it exits anyway
+    */
+    pub shutdown_timeout: u64,
 }
 
-fn get_db_dirs() -> (String, String, String, String) {
+fn get_db_dirs(var: &dyn Fn(&str) -> Result<String, String>) -> (String, String, String, String) {
     // Get the user's home directory based on platform
     let home_dir = if cfg!(target_os = "windows") {
-        env::var("USERPROFILE").unwrap_or_else(|_| ".".to_string())
+        var("USERPROFILE").unwrap_or_else(|_| ".".to_string())
     } else {
-        env::var("HOME").unwrap_or_else(|_| ".".to_string())
+        var("HOME").unwrap_or_else(|_| ".".to_string())
     };
 
     // Check for SU_FILE_DB_DIR environment variable, default to ~/sudata
-    let su_file_db_dir = match env::var("SU_FILE_DB_DIR") {
+    let su_file_db_dir = match var("SU_FILE_DB_DIR") {
         Ok(val) => val,
         Err(_) => {
             let mut path = PathBuf::from(&home_dir);
@@ -73,7 +374,7 @@ fn get_db_dirs() -> (String, String, String, String) {
     };
 
     // Check for SU_INDEX_DB_DIR environment variable, default to ~/suindex
-    let su_index_db_dir = match env::var("SU_INDEX_DB_DIR") {
+    let su_index_db_dir = match var("SU_INDEX_DB_DIR") {
         Ok(val) => val,
         Err(_) => {
             let mut path = PathBuf::from(&home_dir);
@@ -82,7 +383,7 @@ fn get_db_dirs() -> (String, String, String, String) {
         }
     };
 
-    let su_file_sync_db_dir = match env::var("SU_FILE_SYNC_DB_DIR") {
+    let su_file_sync_db_dir = match var("SU_FILE_SYNC_DB_DIR") {
         Ok(val) => val,
         Err(_) => {
             let mut path = PathBuf::from(&home_dir);
@@ -91,7 +392,7 @@ fn get_db_dirs() -> (String, String, String, String) {
         }
     };
 
-    let su_index_sync_db_dir = match env::var("SU_INDEX_SYNC_DB_DIR") {
+    let su_index_sync_db_dir = match var("SU_INDEX_SYNC_DB_DIR") {
         Ok(val) => val,
         Err(_) => {
             let mut path = PathBuf::from(&home_dir);
@@ -108,129 +409,781 @@ fn get_db_dirs() -> (String, String, String, String) {
     )
 }
 
+// a set variable that doesn't parse is an error naming it
+fn parse_var<T: std::str::FromStr>(key: &str, val: &str) -> Result<T, String> {
+    val.parse()
+        .map_err(|_| format!("Invalid value for {}: {}", key, val))
+}
+
+// an http or https url with a host, kept as it was written
+fn parse_url(key: &str, val: &str) -> Result<String, String> {
+    match reqwest::Url::parse(val) {
+        Ok(url) if matches!(url.scheme(), "http" | "https") && url.host().is_some() => {
+            Ok(val.to_string())
+        }
+        Ok(_) => Err(format!(
+            "Invalid value for {}: {}, expected an http or https url",
+            key, val
+        )),
+        Err(e) => Err(format!("Invalid value for {}: {}, {}", key, val, e)),
+    }
+}
+
+// like parse_url where an empty value leaves the setting off
+fn parse_optional_url(key: &str, val: String) -> Result<String, String> {
+    match val.is_empty() {
+        true => Ok(val),
+        false => parse_url(key, &val),
+    }
+}
+
+/*
+  The variables set before .env was first loaded, as on
+  startup they win over .env when it is read again
+*/
+fn startup_vars() -> &'static HashSet<String> {
+    static STARTUP_VARS: OnceLock<HashSet<String>> = OnceLock::new();
+    STARTUP_VARS.get_or_init(|| {
+        env::vars_os()
+            .filter_map(|(key, _)| key.into_string().ok())
+            .collect()
+    })
+}
+
+// the settings of the CONFIG_PATH file, none without one
+fn config_file_vars() -> Result<Vec<(String, String)>, String> {
+    match env::var("CONFIG_PATH") {
+        Ok(path) if !path.is_empty() => read_config_file(&path),
+        _ => Ok(vec![]),
+    }
+}
+
+/*
+  The file goes under the environment and .env, its
+  settings are set in the environment where they aren't
+  already so the clients reading variables see them too
+*/
+fn load_config_file() -> Result<(), String> {
+    for (key, val) in config_file_vars()? {
+        if env::var(&key).is_err() {
+            env::set_var(key, val);
+        }
+    }
+    Ok(())
+}
+
+/*
+  The values .env and the CONFIG_PATH file now hold that
+  differ from the environment, a setting of the file only
+  where .env doesn't have it. dotenv only reads a file
+  without loading it through its deprecated iterators.
+*/
+#[allow(deprecated)]
+fn dotenv_changes() -> Result<Vec<(String, String)>, String> {
+    let lines = match dotenv::dotenv_iter() {
+        Ok(lines) => lines.collect::<Result<Vec<_>, _>>(),
+        Err(dotenv::Error::Io(e)) if e.kind() == std::io::ErrorKind::NotFound => Ok(vec![]),
+        Err(e) => return Err(format!("Failed to read .env: {}", e)),
+    };
+    let dotenv_vars = lines.map_err(|e| format!("Invalid .env: {}", e))?;
+    let in_dotenv: HashSet<String> = dotenv_vars.iter().map(|(key, _)| key.clone()).collect();
+    let file_vars = config_file_vars()?
+        .into_iter()
+        .filter(|(key, _)| !in_dotenv.contains(key));
+
+    let mut changes = vec![];
+    for (key, val) in dotenv_vars.into_iter().chain(file_vars) {
+        if !startup_vars().contains(&key) && env::var(&key).ok().as_ref() != Some(&val) {
+            changes.push((key, val));
+        }
+    }
+    Ok(changes)
+}
+
 impl AoConfig {
-    pub fn new(mode: Option<String>) -> Result<Self, env::VarError> {
+    pub fn new(mode: Option<String>) -> Result<Self, String> {
+        startup_vars();
         dotenv().ok();
-        let mode_out = match mode {
-            Some(m) => m,
-            None => env::var("MODE")?,
+        load_config_file()?;
+        Self::from_vars(mode, &|key| {
+            env::var(key).map_err(|_| {
+                format!(
+                    "{} is not set in the environment, .env or the CONFIG_PATH file",
+                    key
+                )
+            })
+        })
+    }
+
+    pub(crate) fn from_vars(
+        mode: Option<String>,
+        var: &dyn Fn(&str) -> Result<String, String>,
+    ) -> Result<Self, String> {
+        let mode_out: Mode = match mode {
+            Some(m) => m.parse()?,
+            None => var("MODE")?.parse()?,
         };
-        let database_read_url = match env::var("DATABASE_READ_URL") {
+        let database_read_url = match var("DATABASE_READ_URL") {
             Ok(val) => val,
-            Err(_e) => env::var("DATABASE_URL")?,
+            Err(_e) => var("DATABASE_URL")?,
         };
-        let use_disk = match env::var("USE_DISK") {
+        let use_disk = match var("USE_DISK") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
         let su_data_dir = match use_disk {
-            true => env::var("SU_DATA_DIR")?,
+            true => var("SU_DATA_DIR")?,
             false => "".to_string(),
         };
-        let migration_batch_size = match env::var("MIGRATION_BATCH_SIZE") {
-            Ok(val) => val.parse().unwrap(),
+        let migration_batch_size = match var("MIGRATION_BATCH_SIZE") {
+            Ok(val) => parse_var("MIGRATION_BATCH_SIZE", &val)?,
             Err(_e) => 1000,
         };
-        let db_write_connections = match env::var("DB_WRITE_CONNECTIONS") {
-            Ok(val) => val.parse().unwrap(),
+        let db_write_connections = match var("DB_WRITE_CONNECTIONS") {
+            Ok(val) => parse_var("DB_WRITE_CONNECTIONS", &val)?,
             Err(_e) => 10,
         };
-        let db_read_connections = match env::var("DB_READ_CONNECTIONS") {
-            Ok(val) => val.parse().unwrap(),
+        let db_read_connections = match var("DB_READ_CONNECTIONS") {
+            Ok(val) => parse_var("DB_READ_CONNECTIONS", &val)?,
             Err(_e) => 10,
         };
-        let graphql_url = match env::var("GRAPHQL_URL") {
-            Ok(val) => val,
-            Err(_e) => env::var("GATEWAY_URL")?,
+        let graphql_url = match var("GRAPHQL_URL") {
+            Ok(val) => parse_url("GRAPHQL_URL", &val)?,
+            Err(_e) => parse_url("GATEWAY_URL", &var("GATEWAY_URL")?)?,
         };
-        let arweave_url = match env::var("ARWEAVE_URL") {
-            Ok(val) => val,
-            Err(_e) => env::var("GATEWAY_URL")?,
+        let arweave_url = match var("ARWEAVE_URL") {
+            Ok(val) => parse_url("ARWEAVE_URL", &val)?,
+            Err(_e) => parse_url("GATEWAY_URL", &var("GATEWAY_URL")?)?,
         };
-        let enable_metrics = match env::var("ENABLE_METRICS") {
+        let enable_metrics = match var("ENABLE_METRICS") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
-        let max_read_memory = match env::var("MAX_READ_MEMORY") {
-            Ok(val) => val.parse().unwrap(),
+        let enable_process_metrics = match var("ENABLE_PROCESS_METRICS") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+        let max_read_memory = match var("MAX_READ_MEMORY") {
+            Ok(val) => parse_var("MAX_READ_MEMORY", &val)?,
             Err(_e) => 1_073_741_824,
         };
-        let process_cache_size = match env::var("PROCESS_CACHE_SIZE") {
-            Ok(val) => val.parse().unwrap(),
+        let process_cache_size = match var("PROCESS_CACHE_SIZE") {
+            Ok(val) => parse_var("PROCESS_CACHE_SIZE", &val)?,
             Err(_e) => 20000,
         };
-        let enable_process_assignment = match env::var("ENABLE_PROCESS_ASSIGNMENT") {
+        let signature_cache_size = match var("SIGNATURE_CACHE_SIZE") {
+            Ok(val) => parse_var("SIGNATURE_CACHE_SIZE", &val)?,
+            Err(_e) => 10000,
+        };
+        let owner_cache_size = match var("OWNER_CACHE_SIZE") {
+            Ok(val) => parse_var("OWNER_CACHE_SIZE", &val)?,
+            Err(_e) => 10000,
+        };
+        let enable_process_assignment = match var("ENABLE_PROCESS_ASSIGNMENT") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
-        let arweave_url_list: Vec<String> = match env::var("ARWEAVE_URL_LIST") {
-            Ok(val) => val.split(',').map(|s| s.trim().to_string()).collect(),
+        let arweave_url_list: Vec<String> = match var("ARWEAVE_URL_LIST") {
+            Ok(val) => val
+                .split(',')
+                .map(|s| parse_url("ARWEAVE_URL_LIST", s.trim()))
+                .collect::<Result<_, _>>()?,
             Err(_e) => vec![
                 "https://arweave.net".to_string(),
                 "https://g8way.io".to_string(),
             ],
         };
-        let use_local_store = match env::var("USE_LOCAL_STORE") {
+        let use_local_store = match var("USE_LOCAL_STORE") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+        let dual_write = match var("DUAL_WRITE") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
         let (su_file_db_dir, su_index_db_dir, su_file_sync_db_dir, su_index_sync_db_dir) =
-            get_db_dirs();
-        let enable_deep_hash_checks = match env::var("ENABLE_DEEP_HASH_CHECKS") {
+            get_db_dirs(var);
+        let enable_deep_hash_checks = match var("ENABLE_DEEP_HASH_CHECKS") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
 
-        let current_deephash_version = match env::var("CURRENT_DEEPHASH_VERSION") {
+        let current_deephash_version = match var("CURRENT_DEEPHASH_VERSION") {
             Ok(val) => val,
             Err(_e) => "1.0".to_string(),
         };
 
-        let deephash_recalc_limit = match env::var("DEEPHASH_RECALC_LIMIT") {
-            Ok(val) => val.parse().unwrap(),
+        let deephash_recalc_limit = match var("DEEPHASH_RECALC_LIMIT") {
+            Ok(val) => parse_var("DEEPHASH_RECALC_LIMIT", &val)?,
             Err(_e) => 400,
         };
 
-        let warmup_delay = match env::var("WARMUP_DELAY") {
-            Ok(val) => val.parse().unwrap(),
+        let warmup_delay = match var("WARMUP_DELAY") {
+            Ok(val) => parse_var("WARMUP_DELAY", &val)?,
             Err(_e) => 30,
         };
 
-        let enable_router_check = match env::var("ENABLE_ROUTER_CHECK") {
+        let enable_router_check = match var("ENABLE_ROUTER_CHECK") {
             Ok(val) => val == "true",
             Err(_e) => false,
         };
 
-        let router_url = match env::var("ROUTER_URL") {
-            Ok(val) => val,
+        let router_url = match var("ROUTER_URL") {
+            Ok(val) => parse_url("ROUTER_URL", &val)?,
             Err(_e) => "https://su-router.ao-testnet.xyz".to_string(),
         };
 
-        let assignment = match env::var("ASSIGNMENT") {
+        let assignment = match var("ASSIGNMENT") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let stream_url = match var("STREAM_URL") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let stream_topic = match var("STREAM_TOPIC") {
+            Ok(val) => val,
+            Err(_e) => "ao.assignments".to_string(),
+        };
+
+        let stream_spool_dir = match var("STREAM_SPOOL_DIR") {
+            Ok(val) => val,
+            Err(_e) => "stream_spool".to_string(),
+        };
+
+        /*
+          Secret used to sign pagination cursors, if it is
+          not set derive one from the wallet so cursors stay
+          valid across restarts and between SU instances
+          sharing a wallet. Without either the su refuses
+          to start rather than sign with an empty key.
+        */
+        let cursor_secret = match var("CURSOR_SECRET") {
+            Ok(val) if !val.is_empty() => val,
+            _ => fs::read(var("SU_WALLET_PATH")?)
+                .map(|wallet| base64_url::encode(&Sha256::digest(&wallet)))
+                .map_err(|e| {
+                    format!(
+                        "CURSOR_SECRET is not set and the wallet can't be read: {}",
+                        e
+                    )
+                })?,
+        };
+
+        let allow_legacy_cursors = match var("ALLOW_LEGACY_CURSORS") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let enable_router_decision_header = match var("ENABLE_ROUTER_DECISION_HEADER") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let process_scheduler_cleanup_interval = match var("PROCESS_SCHEDULER_CLEANUP_INTERVAL") {
+            Ok(val) => parse_var("PROCESS_SCHEDULER_CLEANUP_INTERVAL", &val)?,
+            Err(_e) => 3600,
+        };
+
+        let process_scheduler_cleanup_policy = match var("PROCESS_SCHEDULER_CLEANUP_POLICY") {
+            Ok(val) => val,
+            Err(_e) => "alert".to_string(),
+        };
+
+        let admin_token = match var("ADMIN_TOKEN") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let denylist_url = match var("DENYLIST_URL") {
+            Ok(val) => parse_optional_url("DENYLIST_URL", val)?,
+            Err(_e) => "".to_string(),
+        };
+
+        let denylist_owner = match var("DENYLIST_OWNER") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let denylist_refresh_interval = match var("DENYLIST_REFRESH_INTERVAL") {
+            Ok(val) => parse_var("DENYLIST_REFRESH_INTERVAL", &val)?,
+            Err(_e) => 300,
+        };
+
+        let denylist_path = match var("DENYLIST_PATH") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let read_only_path = match var("READ_ONLY_PATH") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let raw_archive_dir = match var("RAW_ARCHIVE_DIR") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let raw_retention_days = match var("RAW_RETENTION_DAYS") {
+            Ok(val) => parse_var("RAW_RETENTION_DAYS", &val)?,
+            Err(_e) => 365,
+        };
+
+        let standby_url = match var("STANDBY_URL") {
+            Ok(val) => parse_optional_url("STANDBY_URL", val)?,
+            Err(_e) => "".to_string(),
+        };
+
+        let standby_token = match var("STANDBY_TOKEN") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let standby_sync = match var("STANDBY_SYNC") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let allow_newer_schema = match var("ALLOW_NEWER_SCHEMA") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let large_process_threshold = match var("LARGE_PROCESS_THRESHOLD") {
+            Ok(val) => parse_var("LARGE_PROCESS_THRESHOLD", &val)?,
+            Err(_e) => 0,
+        };
+
+        let route_cache_size = match var("ROUTE_CACHE_SIZE") {
+            Ok(val) => parse_var("ROUTE_CACHE_SIZE", &val)?,
+            Err(_e) => 100000,
+        };
+
+        let route_cache_ttl = match var("ROUTE_CACHE_TTL") {
+            Ok(val) => parse_var("ROUTE_CACHE_TTL", &val)?,
+            Err(_e) => 60,
+        };
+
+        let router_redis_url = match var("ROUTER_REDIS_URL") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let router_store = match var("ROUTER_STORE") {
+            Ok(val) => val,
+            Err(_e) => "postgres".to_string(),
+        };
+
+        let router_sqlite_path = match var("ROUTER_SQLITE_PATH") {
+            Ok(val) => val,
+            Err(_e) => "router.sqlite".to_string(),
+        };
+
+        let router_read_urls: Vec<String> = match var("ROUTER_READ_URLS") {
+            Ok(val) => val
+                .split(',')
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+                .collect(),
+            Err(_e) => vec![],
+        };
+
+        let router_audit_log = match var("ROUTER_AUDIT_LOG") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+        let router_audit_raw_hours = match var("ROUTER_AUDIT_RAW_HOURS") {
+            Ok(val) => parse_var("ROUTER_AUDIT_RAW_HOURS", &val)?,
+            Err(_e) => 0,
+        };
+        let router_audit_hourly_days = match var("ROUTER_AUDIT_HOURLY_DAYS") {
+            Ok(val) => parse_var("ROUTER_AUDIT_HOURLY_DAYS", &val)?,
+            Err(_e) => 7,
+        };
+        let router_audit_retention_days = match var("ROUTER_AUDIT_RETENTION_DAYS") {
+            Ok(val) => parse_var("ROUTER_AUDIT_RETENTION_DAYS", &val)?,
+            Err(_e) => 0,
+        };
+
+        let scheduler_health_interval = match var("SCHEDULER_HEALTH_INTERVAL") {
+            Ok(val) => parse_var("SCHEDULER_HEALTH_INTERVAL", &val)?,
+            Err(_e) => 30,
+        };
+
+        let scheduler_unhealthy_after = match var("SCHEDULER_UNHEALTHY_AFTER") {
+            Ok(val) => parse_var("SCHEDULER_UNHEALTHY_AFTER", &val)?,
+            Err(_e) => 3,
+        };
+
+        let scheduler_list_reload_interval = match var("SCHEDULER_LIST_RELOAD_INTERVAL") {
+            Ok(val) => parse_var("SCHEDULER_LIST_RELOAD_INTERVAL", &val)?,
+            Err(_e) => 60,
+        };
+
+        let routing_strategy = match var("ROUTING_STRATEGY") {
+            Ok(val) => val,
+            Err(_e) => "least-count".to_string(),
+        };
+
+        let chain_snapshot_interval = match var("CHAIN_SNAPSHOT_INTERVAL") {
+            Ok(val) => parse_var("CHAIN_SNAPSHOT_INTERVAL", &val)?,
+            Err(_e) => 1000,
+        };
+
+        let max_data_item_size = match var("MAX_DATA_ITEM_SIZE") {
+            Ok(val) => parse_var("MAX_DATA_ITEM_SIZE", &val)?,
+            Err(_e) => 10485760,
+        };
+
+        let batch_max_items = match var("BATCH_MAX_ITEMS") {
+            Ok(val) => parse_var("BATCH_MAX_ITEMS", &val)?,
+            Err(_e) => 100,
+        };
+
+        let wallet_spawns_per_hour = match var("WALLET_SPAWNS_PER_HOUR") {
+            Ok(val) => parse_var("WALLET_SPAWNS_PER_HOUR", &val)?,
+            Err(_e) => 0,
+        };
+
+        let wallet_messages_per_minute = match var("WALLET_MESSAGES_PER_MINUTE") {
+            Ok(val) => parse_var("WALLET_MESSAGES_PER_MINUTE", &val)?,
+            Err(_e) => 0,
+        };
+
+        let anonymous_reads_per_minute = match var("ANONYMOUS_READS_PER_MINUTE") {
+            Ok(val) => parse_var("ANONYMOUS_READS_PER_MINUTE", &val)?,
+            Err(_e) => 0,
+        };
+
+        let api_key_reads_per_minute = match var("API_KEY_READS_PER_MINUTE") {
+            Ok(val) => parse_var("API_KEY_READS_PER_MINUTE", &val)?,
+            Err(_e) => 0,
+        };
+
+        let write_concurrency = match var("WRITE_CONCURRENCY") {
+            Ok(val) => parse_var("WRITE_CONCURRENCY", &val)?,
+            Err(_e) => 0,
+        };
+
+        let read_concurrency = match var("READ_CONCURRENCY") {
+            Ok(val) => parse_var("READ_CONCURRENCY", &val)?,
+            Err(_e) => 0,
+        };
+
+        let admin_concurrency = match var("ADMIN_CONCURRENCY") {
+            Ok(val) => parse_var("ADMIN_CONCURRENCY", &val)?,
+            Err(_e) => 0,
+        };
+
+        let concurrency_queue_size = match var("CONCURRENCY_QUEUE_SIZE") {
+            Ok(val) => parse_var("CONCURRENCY_QUEUE_SIZE", &val)?,
+            Err(_e) => 0,
+        };
+
+        let api_keys_path = match var("API_KEYS_PATH") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let admin_audit_path = match var("ADMIN_AUDIT_PATH") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let deferred_dir = match var("DEFERRED_DIR") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let deferred_max_delay = match var("DEFERRED_MAX_DELAY") {
+            Ok(val) => parse_var("DEFERRED_MAX_DELAY", &val)?,
+            Err(_e) => 604800,
+        };
+
+        let router_degraded_reads = match var("ROUTER_DEGRADED_READS") {
+            Ok(val) => val == "true",
+            Err(_e) => true,
+        };
+
+        let router_store_probe_interval = match var("ROUTER_STORE_PROBE_INTERVAL") {
+            Ok(val) => parse_var("ROUTER_STORE_PROBE_INTERVAL", &val)?,
+            Err(_e) => 5,
+        };
+
+        let trust_forwarded_for = match var("TRUST_FORWARDED_FOR") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let cidrs = |key: &str| -> Result<Vec<Cidr>, String> {
+            match var(key) {
+                Ok(val) => {
+                    parse_cidrs(&val).map_err(|e| format!("Invalid value for {}: {}", key, e))
+                }
+                Err(_e) => Ok(vec![]),
+            }
+        };
+        let ip_access = Arc::new(IpAccess {
+            trusted_proxies: cidrs("TRUSTED_PROXIES")?,
+            writes: IpRules {
+                allow: cidrs("WRITE_ALLOW_CIDRS")?,
+                deny: cidrs("WRITE_DENY_CIDRS")?,
+            },
+            reads: IpRules {
+                allow: cidrs("READ_ALLOW_CIDRS")?,
+                deny: cidrs("READ_DENY_CIDRS")?,
+            },
+            admin: IpRules {
+                allow: cidrs("ADMIN_ALLOW_CIDRS")?,
+                deny: cidrs("ADMIN_DENY_CIDRS")?,
+            },
+        });
+
+        let spawn_failover = match var("SPAWN_FAILOVER") {
+            Ok(val) => val == "true",
+            Err(_e) => true,
+        };
+
+        let router_strict_messages = match var("ROUTER_STRICT_MESSAGES") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let router_owner_sticky = match var("ROUTER_OWNER_STICKY") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let router_region_header = match var("ROUTER_REGION_HEADER") {
             Ok(val) => val,
             Err(_e) => "".to_string(),
         };
 
+        let router_region_map = match var("ROUTER_REGION_MAP") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let router_proxy = match var("ROUTER_PROXY") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let router_proxy_pool_size = match var("ROUTER_PROXY_POOL_SIZE") {
+            Ok(val) => parse_var("ROUTER_PROXY_POOL_SIZE", &val)?,
+            Err(_e) => 32,
+        };
+
+        let verify_process_genesis = match var("VERIFY_PROCESS_GENESIS") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let scheduler_location_url = match var("SCHEDULER_LOCATION_URL") {
+            Ok(val) => parse_optional_url("SCHEDULER_LOCATION_URL", val)?
+                .trim_end_matches('/')
+                .to_string(),
+            Err(_e) => "".to_string(),
+        };
+
+        let scheduler_location_ttl = match var("SCHEDULER_LOCATION_TTL") {
+            Ok(val) if val == "0" => {
+                return Err("Invalid value for SCHEDULER_LOCATION_TTL: 0".to_string())
+            }
+            Ok(val) => parse_var("SCHEDULER_LOCATION_TTL", &val)?,
+            Err(_e) => 86400000,
+        };
+
+        let drain_interval = match var("DRAIN_INTERVAL") {
+            Ok(val) => parse_var("DRAIN_INTERVAL", &val)?,
+            Err(_e) => 60,
+        };
+
+        let drain_batch_size = match var("DRAIN_BATCH_SIZE") {
+            Ok(val) => parse_var("DRAIN_BATCH_SIZE", &val)?,
+            Err(_e) => 100,
+        };
+
+        let drain_copy_token = match var("DRAIN_COPY_TOKEN") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let rebalance_interval = match var("REBALANCE_INTERVAL") {
+            Ok(val) => parse_var("REBALANCE_INTERVAL", &val)?,
+            Err(_e) => 0,
+        };
+
+        let rebalance_max_moves = match var("REBALANCE_MAX_MOVES") {
+            Ok(val) => parse_var("REBALANCE_MAX_MOVES", &val)?,
+            Err(_e) => 10,
+        };
+
+        let rebalance_threshold = match var("REBALANCE_THRESHOLD") {
+            Ok(val) => parse_var("REBALANCE_THRESHOLD", &val)?,
+            Err(_e) => 0.2,
+        };
+
+        let rebalance_idle_secs = match var("REBALANCE_IDLE_SECS") {
+            Ok(val) => parse_var("REBALANCE_IDLE_SECS", &val)?,
+            Err(_e) => 3600,
+        };
+
+        let rebalance_dry_run = match var("REBALANCE_DRY_RUN") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let assignment_tags = match var("ASSIGNMENT_TAGS") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let memory_soft_limit = match var("MEMORY_SOFT_LIMIT") {
+            Ok(val) => parse_var("MEMORY_SOFT_LIMIT", &val)?,
+            Err(_e) => 0,
+        };
+
+        let memory_hard_limit = match var("MEMORY_HARD_LIMIT") {
+            Ok(val) => parse_var("MEMORY_HARD_LIMIT", &val)?,
+            Err(_e) => 0,
+        };
+
+        let read_hedge_delay = match var("READ_HEDGE_DELAY") {
+            Ok(val) => parse_var("READ_HEDGE_DELAY", &val)?,
+            Err(_e) => 0,
+        };
+
+        let read_freshness_window = match var("READ_FRESHNESS_WINDOW") {
+            Ok(val) => parse_var("READ_FRESHNESS_WINDOW", &val)?,
+            Err(_e) => 0,
+        };
+
+        let read_watermark_interval = match var("READ_WATERMARK_INTERVAL") {
+            Ok(val) => parse_var("READ_WATERMARK_INTERVAL", &val)?,
+            Err(_e) => 0,
+        };
+
+        let sign_read_responses = match var("SIGN_READ_RESPONSES") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let signing_check_interval = match var("SIGNING_CHECK_INTERVAL") {
+            Ok(val) => parse_var("SIGNING_CHECK_INTERVAL", &val)?,
+            Err(_e) => 300,
+        };
+
+        let signing_check_read_only = match var("SIGNING_CHECK_READ_ONLY") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let write_verify_percent = match var("WRITE_VERIFY_PERCENT") {
+            Ok(val) => parse_var("WRITE_VERIFY_PERCENT", &val)?,
+            Err(_e) => 0.0,
+        };
+
+        let write_batch_max = match var("WRITE_BATCH_MAX") {
+            Ok(val) => parse_var("WRITE_BATCH_MAX", &val)?,
+            Err(_e) => 0,
+        };
+
+        let write_batch_max_wait = match var("WRITE_BATCH_MAX_WAIT") {
+            Ok(val) => parse_var("WRITE_BATCH_MAX_WAIT", &val)?,
+            Err(_e) => 5,
+        };
+
+        let router_standby = match var("ROUTER_STANDBY") {
+            Ok(val) => val == "true",
+            Err(_e) => false,
+        };
+
+        let router_standby_url = match var("ROUTER_STANDBY_URL") {
+            Ok(val) => parse_optional_url("ROUTER_STANDBY_URL", val)?,
+            Err(_e) => "".to_string(),
+        };
+
+        let router_standby_token = match var("ROUTER_STANDBY_TOKEN") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let aws_endpoint_url = match var("AWS_ENDPOINT_URL") {
+            Ok(val) => parse_optional_url("AWS_ENDPOINT_URL", val)?,
+            Err(_e) => "".to_string(),
+        };
+
+        let aws_region = match var("AWS_REGION") {
+            Ok(val) => val,
+            Err(_e) => "us-east-1".to_string(),
+        };
+
+        let aws_access_key_id = match var("AWS_ACCESS_KEY_ID") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let aws_secret_access_key = match var("AWS_SECRET_ACCESS_KEY") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let aws_session_token = match var("AWS_SESSION_TOKEN") {
+            Ok(val) => val,
+            Err(_e) => "".to_string(),
+        };
+
+        let otel_endpoint = match var("OTEL_EXPORTER_OTLP_ENDPOINT") {
+            Ok(val) => parse_optional_url("OTEL_EXPORTER_OTLP_ENDPOINT", val)?,
+            Err(_e) => "".to_string(),
+        };
+
+        let otel_service_name = match var("OTEL_SERVICE_NAME") {
+            Ok(val) => val,
+            Err(_e) => match mode_out {
+                Mode::Router => "su-router".to_string(),
+                Mode::Su => "su".to_string(),
+            },
+        };
+
+        let otel_sample_ratio = match var("OTEL_TRACES_SAMPLER_ARG") {
+            Ok(val) => parse_var("OTEL_TRACES_SAMPLER_ARG", &val)?,
+            Err(_e) => 1.0,
+        };
+
+        let shutdown_timeout = match var("SHUTDOWN_TIMEOUT") {
+            Ok(val) => parse_var("SHUTDOWN_TIMEOUT", &val)?,
+            Err(_e) => 30,
+        };
+
         Ok(AoConfig {
-            database_url: env::var("DATABASE_URL")?,
+            database_url: var("DATABASE_URL")?,
             database_read_url,
-            su_wallet_path: env::var("SU_WALLET_PATH")?,
+            su_wallet_path: var("SU_WALLET_PATH")?,
             graphql_url,
             arweave_url,
-            upload_node_url: env::var("UPLOAD_NODE_URL")?,
+            upload_node_url: parse_url("UPLOAD_NODE_URL", &var("UPLOAD_NODE_URL")?)?,
             mode: mode_out,
-            scheduler_list_path: env::var("SCHEDULER_LIST_PATH")?,
+            scheduler_list_path: var("SCHEDULER_LIST_PATH")?,
             use_disk,
             su_data_dir,
             migration_batch_size,
             db_write_connections,
             db_read_connections,
             enable_metrics,
+            enable_process_metrics,
             max_read_memory,
             process_cache_size,
+            signature_cache_size,
+            owner_cache_size,
             enable_process_assignment,
             arweave_url_list,
             use_local_store,
+            dual_write,
             su_file_db_dir,
             su_index_db_dir,
             enable_deep_hash_checks,
@@ -241,41 +1194,137 @@ impl AoConfig {
             warmup_delay,
             enable_router_check,
             router_url,
-            assignment
+            assignment,
+            stream_url,
+            stream_topic,
+            stream_spool_dir,
+            cursor_secret,
+            allow_legacy_cursors,
+            enable_router_decision_header,
+            process_scheduler_cleanup_interval,
+            process_scheduler_cleanup_policy,
+            admin_token,
+            denylist_url,
+            denylist_owner,
+            denylist_refresh_interval,
+            denylist_path,
+            read_only_path,
+            raw_archive_dir,
+            raw_retention_days,
+            standby_url,
+            standby_token,
+            standby_sync,
+            allow_newer_schema,
+            large_process_threshold,
+            scheduler_health_interval,
+            scheduler_unhealthy_after,
+            scheduler_list_reload_interval,
+            routing_strategy,
+            route_cache_size,
+            route_cache_ttl,
+            router_redis_url,
+            router_store,
+            router_sqlite_path,
+            router_read_urls,
+            router_audit_log,
+            router_audit_raw_hours,
+            router_audit_hourly_days,
+            router_audit_retention_days,
+            router_degraded_reads,
+            router_store_probe_interval,
+            spawn_failover,
+            router_strict_messages,
+            router_owner_sticky,
+            router_region_header,
+            router_region_map,
+            router_proxy,
+            router_proxy_pool_size,
+            verify_process_genesis,
+            scheduler_location_url,
+            scheduler_location_ttl,
+            chain_snapshot_interval,
+            batch_max_items,
+            max_data_item_size,
+            wallet_spawns_per_hour,
+            wallet_messages_per_minute,
+            anonymous_reads_per_minute,
+            api_key_reads_per_minute,
+            api_keys_path,
+            trust_forwarded_for,
+            ip_access,
+            write_concurrency,
+            read_concurrency,
+            admin_concurrency,
+            concurrency_queue_size,
+            admin_audit_path,
+            deferred_dir,
+            deferred_max_delay,
+            drain_interval,
+            drain_batch_size,
+            drain_copy_token,
+            rebalance_interval,
+            rebalance_max_moves,
+            rebalance_threshold,
+            rebalance_idle_secs,
+            rebalance_dry_run,
+            assignment_tags,
+            memory_soft_limit,
+            memory_hard_limit,
+            read_hedge_delay,
+            read_freshness_window,
+            read_watermark_interval,
+            sign_read_responses,
+            signing_check_interval,
+            signing_check_read_only,
+            write_verify_percent,
+            write_batch_max,
+            write_batch_max_wait,
+            router_standby,
+            router_standby_url,
+            router_standby_token,
+            aws_endpoint_url,
+            aws_region,
+            aws_access_key_id,
+            aws_secret_access_key,
+            aws_session_token,
+            otel_endpoint,
+            otel_service_name,
+            otel_sample_ratio,
+            shutdown_timeout,
         })
     }
 }
 
 impl Config for AoConfig {
-    fn mode(&self) -> String {
-        self.mode.clone()
+    fn mode(&self) -> Mode {
+        self.mode
     }
     fn scheduler_list_path(&self) -> String {
         self.scheduler_list_path.clone()
     }
     fn enable_process_assignment(&self) -> bool {
-        self.enable_process_assignment.clone()
+        self.enable_process_assignment
     }
     fn enable_deep_hash_checks(&self) -> bool {
-        self.enable_deep_hash_checks.clone()
+        self.enable_deep_hash_checks
     }
     fn current_deephash_version(&self) -> String {
         self.current_deephash_version.clone()
     }
     fn deephash_recalc_limit(&self) -> i32 {
-        self.deephash_recalc_limit.clone()
+        self.deephash_recalc_limit
     }
     fn use_local_store(&self) -> bool {
-        self.use_local_store.clone()
+        self.use_local_store
     }
     fn use_disk(&self) -> bool {
-        self.use_disk.clone()
+        self.use_disk
     }
     fn warmup_delay(&self) -> u64 {
-        self.warmup_delay.clone()
+        self.warmup_delay
     }
     fn enable_router_check(&self) -> bool {
-        self.enable_router_check.clone()
+        self.enable_router_check
     }
     fn router_url(&self) -> String {
         self.router_url.clone()
@@ -283,4 +1332,348 @@ impl Config for AoConfig {
     fn assignment(&self) -> String {
         self.assignment.clone()
     }
+    fn cursor_secret(&self) -> String {
+        self.cursor_secret.clone()
+    }
+    fn allow_legacy_cursors(&self) -> bool {
+        self.allow_legacy_cursors
+    }
+    fn enable_router_decision_header(&self) -> bool {
+        self.enable_router_decision_header
+    }
+    fn max_read_memory(&self) -> usize {
+        self.max_read_memory
+    }
+    fn process_scheduler_cleanup_interval(&self) -> u64 {
+        self.process_scheduler_cleanup_interval
+    }
+    fn process_scheduler_cleanup_policy(&self) -> String {
+        self.process_scheduler_cleanup_policy.clone()
+    }
+    fn admin_token(&self) -> String {
+        self.admin_token.clone()
+    }
+    fn large_process_threshold(&self) -> usize {
+        self.large_process_threshold
+    }
+    fn scheduler_health_interval(&self) -> u64 {
+        self.scheduler_health_interval
+    }
+    fn scheduler_unhealthy_after(&self) -> u32 {
+        self.scheduler_unhealthy_after
+    }
+    fn scheduler_list_reload_interval(&self) -> u64 {
+        self.scheduler_list_reload_interval
+    }
+    fn routing_strategy(&self) -> String {
+        self.routing_strategy.clone()
+    }
+    fn drain_interval(&self) -> u64 {
+        self.drain_interval
+    }
+    fn drain_batch_size(&self) -> i64 {
+        self.drain_batch_size
+    }
+    fn drain_copy_token(&self) -> String {
+        self.drain_copy_token.clone()
+    }
+    fn rebalance_interval(&self) -> u64 {
+        self.rebalance_interval
+    }
+    fn rebalance_max_moves(&self) -> usize {
+        self.rebalance_max_moves
+    }
+    fn rebalance_threshold(&self) -> f64 {
+        self.rebalance_threshold
+    }
+    fn rebalance_idle_secs(&self) -> u64 {
+        self.rebalance_idle_secs
+    }
+    fn rebalance_dry_run(&self) -> bool {
+        self.rebalance_dry_run
+    }
+    fn assignment_tags(&self) -> String {
+        self.assignment_tags.clone()
+    }
+    fn spawn_failover(&self) -> bool {
+        self.spawn_failover
+    }
+    fn router_strict_messages(&self) -> bool {
+        self.router_strict_messages
+    }
+    fn router_owner_sticky(&self) -> bool {
+        self.router_owner_sticky
+    }
+    fn router_region_header(&self) -> String {
+        self.router_region_header.clone()
+    }
+    fn router_region_map(&self) -> String {
+        self.router_region_map.clone()
+    }
+    fn verify_process_genesis(&self) -> bool {
+        self.verify_process_genesis
+    }
+    fn scheduler_location_url(&self) -> String {
+        self.scheduler_location_url.clone()
+    }
+    fn scheduler_location_ttl(&self) -> u64 {
+        self.scheduler_location_ttl
+    }
+    fn chain_snapshot_interval(&self) -> i32 {
+        self.chain_snapshot_interval
+    }
+    fn batch_max_items(&self) -> usize {
+        self.batch_max_items
+    }
+    fn max_data_item_size(&self) -> usize {
+        self.max_data_item_size
+    }
+    fn trust_forwarded_for(&self) -> bool {
+        self.trust_forwarded_for
+    }
+    fn ip_access(&self) -> Arc<IpAccess> {
+        self.ip_access.clone()
+    }
+    fn router_degraded_reads(&self) -> bool {
+        self.router_degraded_reads
+    }
+    fn router_store_probe_interval(&self) -> u64 {
+        self.router_store_probe_interval
+    }
+    fn deferred_messages(&self) -> bool {
+        !self.deferred_dir.is_empty()
+    }
+    fn deferred_max_delay(&self) -> u64 {
+        self.deferred_max_delay
+    }
+    fn wallet_spawns_per_hour(&self) -> u32 {
+        self.wallet_spawns_per_hour
+    }
+    fn wallet_messages_per_minute(&self) -> u32 {
+        self.wallet_messages_per_minute
+    }
+    fn anonymous_reads_per_minute(&self) -> u32 {
+        self.anonymous_reads_per_minute
+    }
+    fn api_key_reads_per_minute(&self) -> u32 {
+        self.api_key_reads_per_minute
+    }
+    fn sign_read_responses(&self) -> bool {
+        self.sign_read_responses
+    }
+    fn signing_check_interval(&self) -> u64 {
+        self.signing_check_interval
+    }
+    fn signing_check_read_only(&self) -> bool {
+        self.signing_check_read_only
+    }
+    fn write_verify_percent(&self) -> f64 {
+        self.write_verify_percent
+    }
+}
+
+/*
+  The configuration the deps read, swapped whole on a
+  reload so each getter sees either the old or the new
+  values. What was built from the startup values, like
+  the stores and connection pools, keeps them.
+*/
+pub struct LiveConfig {
+    current: RwLock<Arc<AoConfig>>,
+    reloading: Mutex<()>,
+}
+
+impl LiveConfig {
+    pub fn new(config: Arc<AoConfig>) -> Self {
+        LiveConfig {
+            current: RwLock::new(config),
+            reloading: Mutex::new(()),
+        }
+    }
+
+    pub fn current(&self) -> Arc<AoConfig> {
+        self.current.read().unwrap().clone()
+    }
+}
+
+macro_rules! from_current {
+    ($($getter:ident -> $type:ty),* $(,)?) => {
+        $(
+            fn $getter(&self) -> $type {
+                self.current().$getter()
+            }
+        )*
+    };
+}
+
+impl Config for LiveConfig {
+    from_current! {
+        mode -> Mode,
+        scheduler_list_path -> String,
+        enable_process_assignment -> bool,
+        enable_deep_hash_checks -> bool,
+        current_deephash_version -> String,
+        deephash_recalc_limit -> i32,
+        use_local_store -> bool,
+        use_disk -> bool,
+        warmup_delay -> u64,
+        enable_router_check -> bool,
+        router_url -> String,
+        assignment -> String,
+        cursor_secret -> String,
+        allow_legacy_cursors -> bool,
+        enable_router_decision_header -> bool,
+        max_read_memory -> usize,
+        process_scheduler_cleanup_interval -> u64,
+        process_scheduler_cleanup_policy -> String,
+        admin_token -> String,
+        large_process_threshold -> usize,
+        scheduler_health_interval -> u64,
+        scheduler_unhealthy_after -> u32,
+        scheduler_list_reload_interval -> u64,
+        routing_strategy -> String,
+        drain_interval -> u64,
+        drain_batch_size -> i64,
+        drain_copy_token -> String,
+        rebalance_interval -> u64,
+        rebalance_max_moves -> usize,
+        rebalance_threshold -> f64,
+        rebalance_idle_secs -> u64,
+        rebalance_dry_run -> bool,
+        assignment_tags -> String,
+        spawn_failover -> bool,
+        router_strict_messages -> bool,
+        router_owner_sticky -> bool,
+        router_region_header -> String,
+        router_region_map -> String,
+        verify_process_genesis -> bool,
+        scheduler_location_url -> String,
+        scheduler_location_ttl -> u64,
+        chain_snapshot_interval -> i32,
+        batch_max_items -> usize,
+        max_data_item_size -> usize,
+        trust_forwarded_for -> bool,
+        ip_access -> Arc<IpAccess>,
+        router_degraded_reads -> bool,
+        router_store_probe_interval -> u64,
+        deferred_messages -> bool,
+        deferred_max_delay -> u64,
+        wallet_spawns_per_hour -> u32,
+        wallet_messages_per_minute -> u32,
+        anonymous_reads_per_minute -> u32,
+        api_key_reads_per_minute -> u32,
+        sign_read_responses -> bool,
+        signing_check_interval -> u64,
+        signing_check_read_only -> bool,
+        write_verify_percent -> f64,
+    }
+
+    /*
+      .env and the CONFIG_PATH file are read again over
+      the current environment, the mode stays what it was
+      started with. The new
+      values are only set in the environment, where the
+      clients reading it on each call find them, once
+      the configuration they make is accepted.
+    */
+    fn reload(
+        &self,
+        check: &dyn Fn(&dyn Config) -> Result<(), String>,
+    ) -> Result<Vec<String>, String> {
+        let _reloading = self.reloading.lock().unwrap();
+        let changes = dotenv_changes()?;
+        let changed = changes.iter().cloned().collect::<HashMap<_, _>>();
+        let mode = Some(self.current().mode.to_string());
+        let config = AoConfig::from_vars(mode, &|key| match changed.get(key) {
+            Some(val) => Ok(val.clone()),
+            None => env::var(key).map_err(|_| format!("{} is not set", key)),
+        })?;
+        check(&config)?;
+
+        for (key, val) in &changes {
+            env::set_var(key, val);
+        }
+        *self.current.write().unwrap() = Arc::new(config);
+        Ok(changes.into_iter().map(|(key, _)| key).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_map(vars: &[(&str, &str)]) -> Result<AoConfig, String> {
+        let vars = vars.iter().cloned().collect::<HashMap<_, _>>();
+        AoConfig::from_vars(Some("su".to_string()), &|key| {
+            vars.get(key)
+                .map(|val| val.to_string())
+                .ok_or(format!("{} is not set", key))
+        })
+    }
+
+    #[test]
+    fn test_invalid_values_are_refused() {
+        let required = [
+            ("DATABASE_URL", "postgres://su"),
+            ("SU_WALLET_PATH", "wallet.json"),
+            ("GATEWAY_URL", "https://arweave.net"),
+            ("UPLOAD_NODE_URL", "https://upload"),
+            ("SCHEDULER_LIST_PATH", "schedulers.json"),
+            ("CURSOR_SECRET", "secret"),
+        ];
+        let config = from_map(&required).unwrap();
+        assert_eq!(config.batch_max_items(), 100);
+
+        let mut vars = required.to_vec();
+        vars.push(("BATCH_MAX_ITEMS", "many"));
+        assert_eq!(
+            from_map(&vars).unwrap_err(),
+            "Invalid value for BATCH_MAX_ITEMS: many"
+        );
+        assert_eq!(
+            from_map(&required[1..]).unwrap_err(),
+            "DATABASE_URL is not set"
+        );
+
+        let mut vars = required.to_vec();
+        vars.push(("SCHEDULER_LOCATION_URL", "su.example.com"));
+        assert!(from_map(&vars).is_err());
+        vars.pop();
+        vars.push(("SCHEDULER_LOCATION_URL", "https://su.example.com/"));
+        let located = from_map(&vars).unwrap();
+        assert_eq!(located.scheduler_location_url(), "https://su.example.com");
+
+        let mut vars = required.to_vec();
+        vars.push(("UPLOAD_NODE_URL", "ftp://upload"));
+        assert!(from_map(&vars).is_err());
+        let vars = required.to_vec();
+        assert_eq!(
+            AoConfig::from_vars(Some("standby".to_string()), &|key| {
+                vars.iter()
+                    .find(|(set, _)| *set == key)
+                    .map(|(_, val)| val.to_string())
+                    .ok_or(format!("{} is not set", key))
+            })
+            .unwrap_err(),
+            "Invalid value for MODE: standby, expected su or router"
+        );
+
+        assert!(config.router_read_urls.is_empty());
+        let mut vars = required.to_vec();
+        vars.push(("ROUTER_READ_URLS", "postgres://r1, ,postgres://r2"));
+        assert_eq!(
+            from_map(&vars).unwrap().router_read_urls,
+            vec!["postgres://r1", "postgres://r2"]
+        );
+
+        // no secret to sign cursors with
+        assert!(!config.allow_legacy_cursors());
+        assert!(from_map(&required[..5])
+            .unwrap_err()
+            .starts_with("CURSOR_SECRET is not set"));
+
+        let live = LiveConfig::new(Arc::new(config));
+        assert_eq!(live.batch_max_items(), 100);
+        assert_eq!(live.mode(), Mode::Su);
+    }
 }
@@ -0,0 +1,249 @@
+use std::fs;
+
+/*
+  The settings of a CONFIG_PATH file, written in a
+  subset of toml. Keys are the environment variable
+  names in lower case and a table prefixes the keys
+  under it, so read_urls under [router] is
+  ROUTER_READ_URLS. Values are strings, numbers,
+  booleans or arrays of those, an array is joined with
+  commas like the variables that take a list.
+*/
+pub fn read_config_file(path: &str) -> Result<Vec<(String, String)>, String> {
+    let contents = fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read CONFIG_PATH {}: {}", path, e))?;
+    parse_config(&contents).map_err(|e| format!("Invalid CONFIG_PATH {}, {}", path, e))
+}
+
+pub fn parse_config(contents: &str) -> Result<Vec<(String, String)>, String> {
+    let mut vars: Vec<(String, String)> = vec![];
+    let mut table = String::new();
+    let mut lines = contents.lines().enumerate();
+    while let Some((index, line)) = lines.next() {
+        let at = |e: String| format!("line {}: {}", index + 1, e);
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        if let Some(header) = line.strip_prefix('[') {
+            let name = header
+                .strip_suffix(']')
+                .ok_or_else(|| at("unclosed table header".to_string()))?;
+            let parts = name
+                .split('.')
+                .map(|part| env_key(part.trim()))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(at)?;
+            table = format!("{}_", parts.join("_"));
+            continue;
+        }
+
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| at(format!("expected key = value, found {}", line)))?;
+        let key = format!("{}{}", table, env_key(key.trim()).map_err(at)?);
+        let mut value = value.trim().to_string();
+        // an array may go on over the lines after
+        while value.starts_with('[') && depth(&value) > 0 {
+            let (_, next) = lines
+                .next()
+                .ok_or_else(|| at(format!("unclosed array for {}", key)))?;
+            value.push(' ');
+            value.push_str(strip_comment(next).trim());
+        }
+        let value = parse_value(&value).map_err(|e| at(format!("{} {}", key, e)))?;
+        if vars.iter().any(|(set, _)| *set == key) {
+            return Err(at(format!("{} is set twice", key)));
+        }
+        vars.push((key, value));
+    }
+    Ok(vars)
+}
+
+// a bare key as the variable it sets
+fn env_key(key: &str) -> Result<String, String> {
+    let bare = key
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    match bare && !key.is_empty() {
+        true => Ok(key.replace('-', "_").to_ascii_uppercase()),
+        false => Err(format!("invalid key {:?}", key)),
+    }
+}
+
+/*
+  Where each character of a line is, in a string or
+  not, for finding comments, array ends and separators
+*/
+fn outside_strings(line: &str) -> Vec<(usize, char)> {
+    let mut outside = vec![];
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (at, c) in line.char_indices() {
+        match quote {
+            Some('"') if escaped => escaped = false,
+            Some('"') if c == '\\' => escaped = true,
+            Some(open) if c == open => quote = None,
+            Some(_) => (),
+            None if c == '"' || c == '\'' => quote = Some(c),
+            None => outside.push((at, c)),
+        }
+    }
+    outside
+}
+
+fn strip_comment(line: &str) -> &str {
+    match outside_strings(line).iter().find(|(_, c)| *c == '#') {
+        Some((at, _)) => &line[..*at],
+        None => line,
+    }
+}
+
+fn depth(value: &str) -> i32 {
+    outside_strings(value)
+        .iter()
+        .map(|(_, c)| match c {
+            '[' => 1,
+            ']' => -1,
+            _ => 0,
+        })
+        .sum()
+}
+
+fn parse_value(value: &str) -> Result<String, String> {
+    if let Some(inner) = value.strip_prefix('[') {
+        let inner = inner
+            .strip_suffix(']')
+            .ok_or_else(|| "has text after its array".to_string())?;
+        let mut items = vec![];
+        let mut start = 0;
+        let separators = outside_strings(inner)
+            .into_iter()
+            .filter(|(_, c)| *c == ',')
+            .map(|(at, _)| at)
+            .chain([inner.len()]);
+        for end in separators {
+            let item = inner[start..end].trim();
+            start = end + 1;
+            // a trailing comma is allowed
+            if item.is_empty() && start > inner.len() {
+                continue;
+            }
+            if item.starts_with('[') {
+                return Err("has an array in an array".to_string());
+            }
+            items.push(parse_value(item)?);
+        }
+        return Ok(items.join(","));
+    }
+    if let Some(inner) = value.strip_prefix('"') {
+        let inner = inner
+            .strip_suffix('"')
+            .filter(|inner| !inner.ends_with('\\') || inner.ends_with("\\\\"))
+            .ok_or_else(|| "has an unclosed string".to_string())?;
+        return unescape(inner);
+    }
+    if let Some(inner) = value.strip_prefix('\'') {
+        return inner
+            .strip_suffix('\'')
+            .filter(|inner| !inner.contains('\''))
+            .map(str::to_string)
+            .ok_or_else(|| "has an unclosed string".to_string());
+    }
+    if value == "true" || value == "false" {
+        return Ok(value.to_string());
+    }
+    let number = value.replace('_', "");
+    match number.parse::<i64>().is_ok() || number.parse::<f64>().is_ok() {
+        true => Ok(number),
+        false => Err(format!("has {}, strings have to be quoted", value)),
+    }
+}
+
+fn unescape(inner: &str) -> Result<String, String> {
+    let mut unescaped = String::new();
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            unescaped.push(c);
+            continue;
+        }
+        unescaped.push(match chars.next() {
+            Some('"') => '"',
+            Some('\\') => '\\',
+            Some('n') => '\n',
+            Some('t') => '\t',
+            Some('r') => '\r',
+            other => return Err(format!("has an unknown escape \\{}", other.unwrap_or(' '))),
+        });
+    }
+    Ok(unescaped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_config() {
+        let vars = parse_config(
+            r#"
+            # written like the environment, in lower case
+            mode = "router"
+            database_url = 'postgres://su:pass@db/su'
+            batch_max_items = 1_000
+            enable_metrics = true # inline comments are fine
+            scheduler_location_url = "https://su.example/#frag"
+
+            [router]
+            read_urls = [
+                "postgres://r1",
+                "postgres://r2",
+            ]
+            owner-sticky = false
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            vars,
+            vec![
+                ("MODE".to_string(), "router".to_string()),
+                (
+                    "DATABASE_URL".to_string(),
+                    "postgres://su:pass@db/su".to_string()
+                ),
+                ("BATCH_MAX_ITEMS".to_string(), "1000".to_string()),
+                ("ENABLE_METRICS".to_string(), "true".to_string()),
+                (
+                    "SCHEDULER_LOCATION_URL".to_string(),
+                    "https://su.example/#frag".to_string()
+                ),
+                (
+                    "ROUTER_READ_URLS".to_string(),
+                    "postgres://r1,postgres://r2".to_string()
+                ),
+                ("ROUTER_OWNER_STICKY".to_string(), "false".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_line() {
+        assert_eq!(
+            parse_config("mode = router").unwrap_err(),
+            "line 1: MODE has router, strings have to be quoted"
+        );
+        assert_eq!(
+            parse_config("mode = \"su\"\n\nMODE = \"router\"").unwrap_err(),
+            "line 3: MODE is set twice"
+        );
+        assert_eq!(
+            parse_config("[router\nurl = \"x\"").unwrap_err(),
+            "line 1: unclosed table header"
+        );
+        assert!(parse_config("read_urls = [\"a\",").is_err());
+        assert!(parse_config("wallet = \"open").is_err());
+        assert!(parse_config("just some text").is_err());
+    }
+}
@@ -0,0 +1,196 @@
+use sha2::{Digest, Sha256};
+use sha3::Keccak256;
+
+use super::bytes::SignerMap;
+
+/*
+  The address of an owner key in the format its signature
+  type's wallets use: an EIP-55 checksummed 0x address for
+  Ethereum, base58 for Solana and the base64url sha256 of
+  the key, the Arweave address, for everything else. An
+  Ethereum key that isn't an uncompressed point falls back
+  to the Arweave form.
+*/
+pub fn owner_address(signature_type: &SignerMap, owner: &[u8]) -> String {
+    match signature_type {
+        SignerMap::Ethereum if owner.len() == 65 && owner[0] == 4 => {
+            let hash = Keccak256::digest(&owner[1..]);
+            eip55(&hash[12..])
+        }
+        SignerMap::Solana => base58(owner),
+        _ => arweave_address(owner),
+    }
+}
+
+pub fn arweave_address(owner: &[u8]) -> String {
+    base64_url::encode(&Sha256::digest(owner))
+}
+
+/*
+  One spelling per wallet, 0x addresses lose their
+  checksum casing so a list can hold them lowercase
+*/
+pub fn normalize_address(address: &str) -> String {
+    match address.starts_with("0x") {
+        true => address.to_ascii_lowercase(),
+        false => address.to_string(),
+    }
+}
+
+/*
+  How closely a wallets_to_route wallet takes an
+  address, None when it doesn't. A wallet ending in * is
+  a prefix of the addresses it takes and * alone takes
+  every one. An exact match is closer than any prefix and
+  a longer prefix closer than a shorter one.
+*/
+pub fn wallet_match(listed: &str, address: &str) -> Option<usize> {
+    let address = normalize_address(address);
+    match listed.strip_suffix('*') {
+        Some(prefix) => {
+            let prefix = normalize_address(prefix);
+            address.starts_with(&prefix).then_some(prefix.len())
+        }
+        None => (normalize_address(listed) == address).then_some(usize::MAX),
+    }
+}
+
+// only characters one of the address formats uses
+pub fn is_wallet_prefix(prefix: &str) -> bool {
+    prefix
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+/*
+  Whether a value is written like the address of one of
+  the signature types, not that any wallet holds it
+*/
+pub fn is_wallet_address(address: &str) -> bool {
+    let arweave = address.len() == 43
+        && address
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    let ethereum = address
+        .strip_prefix("0x")
+        .is_some_and(|hex| hex.len() == 40 && hex.chars().all(|c| c.is_ascii_hexdigit()));
+    let solana =
+        (32..=44).contains(&address.len()) && address.bytes().all(|b| BASE58_ALPHABET.contains(&b));
+    arweave || ethereum || solana
+}
+
+fn eip55(address: &[u8]) -> String {
+    let hex: String = address.iter().map(|b| format!("{:02x}", b)).collect();
+    let hash = Keccak256::digest(hex.as_bytes());
+    let checksummed: String = hex
+        .chars()
+        .enumerate()
+        .map(|(i, c)| {
+            let nibble = (hash[i / 2] >> (if i % 2 == 0 { 4 } else { 0 })) & 0x0f;
+            if c.is_ascii_alphabetic() && nibble >= 8 {
+                c.to_ascii_uppercase()
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!("0x{}", checksummed)
+}
+
+const BASE58_ALPHABET: &[u8] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+fn base58(bytes: &[u8]) -> String {
+    // base 58 digits, least significant first
+    let mut digits: Vec<u8> = vec![];
+    for byte in bytes {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+    // every leading zero byte is a leading 1
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+    let mut encoded = "1".repeat(zeros);
+    for digit in digits.iter().rev() {
+        encoded.push(BASE58_ALPHABET[*digit as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn test_addresses_per_signature_type() {
+        // the public key of private key 1, the secp256k1 generator point
+        let ethereum_key = unhex(concat!(
+            "04",
+            "79be667ef9dcbbac55a06295ce870b07029bfcdb2dce28d959f2815b16f81798",
+            "483ada7726a3c4655da4fbfc0e1108a8fd17b448a68554199c47d08ffb10d4b8"
+        ));
+        assert_eq!(
+            owner_address(&SignerMap::Ethereum, &ethereum_key),
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+        );
+
+        assert_eq!(base58(&[0, 0, 1]), "112");
+        assert_eq!(base58(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(owner_address(&SignerMap::Solana, &[0; 32]), "1".repeat(32));
+
+        let arweave_key = vec![7; 512];
+        assert_eq!(
+            owner_address(&SignerMap::Arweave, &arweave_key),
+            arweave_address(&arweave_key)
+        );
+        // not an uncompressed point, addressed like any other key
+        assert_eq!(
+            owner_address(&SignerMap::Ethereum, &[1; 33]),
+            arweave_address(&[1; 33])
+        );
+
+        assert!(is_wallet_address(&arweave_address(&arweave_key)));
+        assert!(is_wallet_address(
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+        ));
+        assert!(is_wallet_address(&"1".repeat(32)));
+        assert!(!is_wallet_address("wallet1"));
+        assert!(!is_wallet_address(
+            "0x7E5F4552091A69125d5DfCb7b8C2659029395Bd"
+        ));
+    }
+
+    #[test]
+    fn test_wallet_match() {
+        let address = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+        assert_eq!(
+            wallet_match("0x7e5f4552091a69125d5dfcb7b8c2659029395bdf", address),
+            Some(usize::MAX)
+        );
+        assert_eq!(wallet_match("0x7E5F*", address), Some(6));
+        assert_eq!(wallet_match("*", address), Some(0));
+        assert_eq!(wallet_match("0x8*", address), None);
+        // base64url and base58 addresses are case sensitive
+        assert_eq!(
+            wallet_match("vh-*", "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI"),
+            Some(3)
+        );
+        assert_eq!(
+            wallet_match("VH-*", "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI"),
+            None
+        );
+    }
+}
@@ -0,0 +1,216 @@
+use serde_json::json;
+
+use super::dal::{DataStore, ExportItem, ExportSink, Message, Process};
+use super::parquet::{write_parquet, Column, ColumnType, Value};
+
+/*
+  su export-parquet, the assignments and messages written
+  in a time range as Parquet files for analytics, so
+  queries over the schedule run in Spark or DuckDB
+  instead of against the production database. Items are
+  read in the (timestamp, id) order of the export index,
+  a page at a time, from the read database when one is
+  configured.
+
+  Each run writes assignments-<since>-<until>-<part>.parquet
+  and messages-<since>-<until>-<part>.parquet, a new part
+  every PART_ROWS rows. An assignment of an existing
+  transaction carries no message so it only has an
+  assignments row.
+*/
+
+const PAGE_SIZE: usize = 100;
+const PART_ROWS: usize = 50000;
+
+fn assignment_columns() -> Vec<Column> {
+    vec![
+        Column::required("assignment_id", ColumnType::String),
+        Column::required("process_id", ColumnType::String),
+        Column::required("message_id", ColumnType::String),
+        Column::required("type", ColumnType::String),
+        Column::required("timestamp", ColumnType::Int64),
+        Column::required("nonce", ColumnType::Int32),
+        Column::optional("epoch", ColumnType::Int32),
+        Column::optional("block_height", ColumnType::Int64),
+        Column::optional("hash_chain", ColumnType::String),
+    ]
+}
+
+fn message_columns() -> Vec<Column> {
+    vec![
+        Column::required("id", ColumnType::String),
+        Column::required("assignment_id", ColumnType::String),
+        Column::required("process_id", ColumnType::String),
+        Column::required("type", ColumnType::String),
+        Column::required("owner", ColumnType::String),
+        Column::optional("target", ColumnType::String),
+        Column::optional("anchor", ColumnType::String),
+        // name and value objects as json
+        Column::required("tags", ColumnType::String),
+        Column::required("timestamp", ColumnType::Int64),
+        Column::required("nonce", ColumnType::Int32),
+    ]
+}
+
+fn optional_string(value: &Option<String>) -> Value {
+    match value {
+        Some(value) => Value::String(value.clone()),
+        None => Value::Null,
+    }
+}
+
+fn parse_item(item: &ExportItem) -> Result<Message, String> {
+    let bundle = item.bundle.clone();
+    let message = match item.item_type {
+        "Process" => Message::from_process(Process::from_bytes(bundle)?)?,
+        _ => Message::from_bytes(bundle)?,
+    };
+    Ok(message)
+}
+
+fn assignment_row(item: &ExportItem, message: &Message) -> Vec<Value> {
+    vec![
+        Value::String(message.assignment.id.clone()),
+        Value::String(item.process_id.clone()),
+        Value::String(message.message_id().unwrap_or_default()),
+        Value::String(item.item_type.to_string()),
+        Value::Int64(item.timestamp),
+        Value::Int32(item.nonce),
+        message.epoch().map(Value::Int32).unwrap_or(Value::Null),
+        message
+            .block_height()
+            .ok()
+            .and_then(|height| height.parse::<i64>().ok())
+            .map(Value::Int64)
+            .unwrap_or(Value::Null),
+        message
+            .hash_chain()
+            .map(Value::String)
+            .unwrap_or(Value::Null),
+    ]
+}
+
+fn message_row(item: &ExportItem, message: &Message) -> Option<Vec<Value>> {
+    let inner = message.message.as_ref()?;
+    Some(vec![
+        Value::String(inner.id.clone()),
+        Value::String(message.assignment.id.clone()),
+        Value::String(item.process_id.clone()),
+        Value::String(item.item_type.to_string()),
+        Value::String(inner.owner.address.clone()),
+        optional_string(&inner.target),
+        optional_string(&inner.anchor),
+        Value::String(json!(inner.tags).to_string()),
+        Value::Int64(item.timestamp),
+        Value::Int32(item.nonce),
+    ])
+}
+
+// the rows of one table and the parts of it written so far
+struct Table {
+    name: &'static str,
+    columns: Vec<Column>,
+    rows: Vec<Vec<Value>>,
+    parts: usize,
+    written: usize,
+}
+
+impl Table {
+    fn new(name: &'static str, columns: Vec<Column>) -> Self {
+        Table {
+            name,
+            columns,
+            rows: vec![],
+            parts: 0,
+            written: 0,
+        }
+    }
+
+    async fn flush(
+        &mut self,
+        sink: &dyn ExportSink,
+        since: i64,
+        until: i64,
+        locations: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if self.rows.is_empty() {
+            return Ok(());
+        }
+        let file = write_parquet(&self.columns, &self.rows)?;
+        let name = format!(
+            "{}-{}-{}-{:05}.parquet",
+            self.name, since, until, self.parts
+        );
+        locations.push(sink.put(&name, file).await?);
+        self.parts += 1;
+        self.written += self.rows.len();
+        self.rows.clear();
+        Ok(())
+    }
+}
+
+/*
+  Export every item with an assignment timestamp from
+  since up to until, until excluded, both in milliseconds.
+  Items whose bundle can't be read are skipped and
+  counted in the report.
+*/
+pub async fn export_parquet(
+    store: &dyn DataStore,
+    sink: &dyn ExportSink,
+    since: i64,
+    until: i64,
+) -> Result<String, String> {
+    if until <= since {
+        return Err(format!("until {} is not after since {}", until, since));
+    }
+
+    let mut assignments = Table::new("assignments", assignment_columns());
+    let mut messages = Table::new("messages", message_columns());
+    let mut locations = vec![];
+    let mut skipped = 0;
+
+    // every id sorts after the empty one, so since itself is included
+    let mut after = (since, String::new());
+    'pages: loop {
+        let items = store
+            .get_items_after((after.0, &after.1), PAGE_SIZE)
+            .await?;
+        let last_page = items.len() < PAGE_SIZE;
+        for item in items {
+            if item.timestamp >= until {
+                break 'pages;
+            }
+            match parse_item(&item) {
+                Ok(message) => {
+                    assignments.rows.push(assignment_row(&item, &message));
+                    if let Some(row) = message_row(&item, &message) {
+                        messages.rows.push(row);
+                    }
+                }
+                Err(_) => skipped += 1,
+            }
+            after = (item.timestamp, item.id);
+        }
+
+        for table in [&mut assignments, &mut messages] {
+            if table.rows.len() >= PART_ROWS {
+                table.flush(sink, since, until, &mut locations).await?;
+            }
+        }
+        if last_page {
+            break;
+        }
+    }
+    for table in [&mut assignments, &mut messages] {
+        table.flush(sink, since, until, &mut locations).await?;
+    }
+
+    Ok(json!({
+        "assignments": assignments.written,
+        "messages": messages.written,
+        "skipped": skipped,
+        "files": locations,
+    })
+    .to_string())
+}
@@ -0,0 +1,226 @@
+use serde_json::{json, Value};
+
+use super::concurrency::CONCURRENCY_LIMITED;
+use super::flows::{
+    DATA_ITEM_TOO_LARGE, HASH_CHAIN_MISMATCH, INVALID_API_KEY, INVALID_TARGET, PROCESS_READ_ONLY,
+    RATE_LIMITED,
+};
+use super::ip_access::IP_FORBIDDEN;
+use super::router::{
+    ROUTER_AT_CAPACITY, ROUTER_DEGRADED, ROUTER_STANDBY, WALLET_PINNED_UNAVAILABLE,
+};
+use super::signing_check::SIGNING_UNHEALTHY;
+
+/*
+  The codes of the json body every error response has,
+  {"code", "message", "details", "retryable"}, so clients
+  branch on the code instead of the message text. The
+  codes are part of the api and don't change once added,
+  the messages may. "error" repeats the message for the
+  clients written before the codes existed.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorCode {
+    BadRequest,
+    InvalidTarget,
+    DataItemTooLarge,
+    Unauthorized,
+    InvalidApiKey,
+    Forbidden,
+    NotFound,
+    ProcessReadOnly,
+    HashChainMismatch,
+    RateLimited,
+    Internal,
+    SchedulerUnreachable,
+    WarmingUp,
+    Overloaded,
+    ConcurrencyLimited,
+    RouterDegraded,
+    RouterStandby,
+    RouterAtCapacity,
+    WalletPinnedUnavailable,
+    SigningUnavailable,
+}
+
+impl ErrorCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorCode::BadRequest => "bad_request",
+            ErrorCode::InvalidTarget => "invalid_target",
+            ErrorCode::DataItemTooLarge => "data_item_too_large",
+            ErrorCode::Unauthorized => "unauthorized",
+            ErrorCode::InvalidApiKey => "invalid_api_key",
+            ErrorCode::Forbidden => "forbidden",
+            ErrorCode::NotFound => "not_found",
+            ErrorCode::ProcessReadOnly => "process_read_only",
+            ErrorCode::HashChainMismatch => "hash_chain_mismatch",
+            ErrorCode::RateLimited => "rate_limited",
+            ErrorCode::Internal => "internal",
+            ErrorCode::SchedulerUnreachable => "scheduler_unreachable",
+            ErrorCode::WarmingUp => "warming_up",
+            ErrorCode::Overloaded => "overloaded",
+            ErrorCode::ConcurrencyLimited => "concurrency_limited",
+            ErrorCode::RouterDegraded => "router_degraded",
+            ErrorCode::RouterStandby => "router_standby",
+            ErrorCode::RouterAtCapacity => "router_at_capacity",
+            ErrorCode::WalletPinnedUnavailable => "wallet_pinned_unavailable",
+            ErrorCode::SigningUnavailable => "signing_unavailable",
+        }
+    }
+
+    pub fn status(&self) -> u16 {
+        match self {
+            ErrorCode::BadRequest | ErrorCode::InvalidTarget => 400,
+            ErrorCode::Unauthorized | ErrorCode::InvalidApiKey => 401,
+            ErrorCode::Forbidden => 403,
+            ErrorCode::NotFound => 404,
+            ErrorCode::ProcessReadOnly => 409,
+            ErrorCode::HashChainMismatch => 412,
+            ErrorCode::DataItemTooLarge => 413,
+            ErrorCode::RateLimited => 429,
+            ErrorCode::Internal => 500,
+            ErrorCode::SchedulerUnreachable => 502,
+            ErrorCode::WarmingUp
+            | ErrorCode::Overloaded
+            | ErrorCode::ConcurrencyLimited
+            | ErrorCode::RouterDegraded
+            | ErrorCode::RouterStandby
+            | ErrorCode::RouterAtCapacity
+            | ErrorCode::WalletPinnedUnavailable
+            | ErrorCode::SigningUnavailable => 503,
+        }
+    }
+
+    // whether the same request can succeed if sent again later
+    pub fn retryable(&self) -> bool {
+        matches!(
+            self,
+            ErrorCode::RateLimited
+                | ErrorCode::SchedulerUnreachable
+                | ErrorCode::WarmingUp
+                | ErrorCode::Overloaded
+                | ErrorCode::ConcurrencyLimited
+                | ErrorCode::RouterDegraded
+                | ErrorCode::RouterStandby
+                | ErrorCode::RouterAtCapacity
+                | ErrorCode::WalletPinnedUnavailable
+                | ErrorCode::SigningUnavailable
+        )
+    }
+
+    // seconds sent in Retry-After
+    pub fn retry_after(&self) -> Option<u64> {
+        match self {
+            ErrorCode::ConcurrencyLimited => Some(1),
+            ErrorCode::RouterDegraded | ErrorCode::RouterStandby => Some(5),
+            ErrorCode::RouterAtCapacity | ErrorCode::WalletPinnedUnavailable => Some(30),
+            _ => None,
+        }
+    }
+}
+
+/*
+  The code of an error returned by the flows or the
+  router, from the prefix constant its message carries.
+  A batch names the item before it so the message is
+  searched, not just its start. Anything else is a
+  bad_request like before the codes.
+*/
+pub fn classify(err: &str) -> ErrorCode {
+    let codes = [
+        (ROUTER_DEGRADED, ErrorCode::RouterDegraded),
+        (ROUTER_STANDBY, ErrorCode::RouterStandby),
+        (ROUTER_AT_CAPACITY, ErrorCode::RouterAtCapacity),
+        (
+            WALLET_PINNED_UNAVAILABLE,
+            ErrorCode::WalletPinnedUnavailable,
+        ),
+        (INVALID_TARGET, ErrorCode::InvalidTarget),
+        (RATE_LIMITED, ErrorCode::RateLimited),
+        (DATA_ITEM_TOO_LARGE, ErrorCode::DataItemTooLarge),
+        (PROCESS_READ_ONLY, ErrorCode::ProcessReadOnly),
+        (HASH_CHAIN_MISMATCH, ErrorCode::HashChainMismatch),
+        (INVALID_API_KEY, ErrorCode::InvalidApiKey),
+        (CONCURRENCY_LIMITED, ErrorCode::ConcurrencyLimited),
+        (SIGNING_UNHEALTHY, ErrorCode::SigningUnavailable),
+        (IP_FORBIDDEN, ErrorCode::Forbidden),
+    ];
+    codes
+        .iter()
+        .find(|(prefix, _)| err.contains(prefix))
+        .map(|(_, code)| *code)
+        .unwrap_or(ErrorCode::BadRequest)
+}
+
+/*
+  The body of an error response, details is an object
+  of whatever the code has to add, Retry-After included
+*/
+pub fn error_body(code: ErrorCode, message: &str, details: Value) -> Value {
+    let mut details = match details {
+        Value::Object(details) => details,
+        _ => serde_json::Map::new(),
+    };
+    if let Some(seconds) = code.retry_after() {
+        details.insert("retry_after".to_string(), json!(seconds));
+    }
+    json!({
+        "code": code.as_str(),
+        "message": message,
+        "details": details,
+        "retryable": code.retryable(),
+        "error": message,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_errors_are_classified_by_prefix() {
+        assert_eq!(
+            classify("Router degraded, the router database is unreachable and no route"),
+            ErrorCode::RouterDegraded
+        );
+        assert_eq!(
+            classify("item 3: Invalid message target, the Message has no target"),
+            ErrorCode::InvalidTarget
+        );
+        assert_eq!(
+            classify("Rate limit exceeded for anonymous reads"),
+            ErrorCode::RateLimited
+        );
+        assert_eq!(
+            classify("Hash chain has advanced, latest is abc"),
+            ErrorCode::HashChainMismatch
+        );
+        assert_eq!(
+            classify("Message or Process not found"),
+            ErrorCode::BadRequest
+        );
+    }
+
+    #[test]
+    fn test_error_body() {
+        let body = error_body(
+            ErrorCode::RouterAtCapacity,
+            "Every scheduler is at capacity, try again later",
+            json!({}),
+        );
+        assert_eq!(body["code"], "router_at_capacity");
+        assert_eq!(body["retryable"], true);
+        assert_eq!(body["details"]["retry_after"], 30);
+        assert_eq!(body["message"], body["error"]);
+
+        let body = error_body(
+            ErrorCode::DataItemTooLarge,
+            "too large",
+            json!({"limit": 10}),
+        );
+        assert_eq!(body["retryable"], false);
+        assert_eq!(body["details"], json!({"limit": 10}));
+        assert_eq!(ErrorCode::DataItemTooLarge.status(), 413);
+    }
+}
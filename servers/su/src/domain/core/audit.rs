@@ -0,0 +1,148 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use sha2::{Digest, Sha256};
+
+/*
+  The hash chain of the admin audit log. Every entry
+  carries the hash of the one before it and its own hash
+  over its fields and that previous hash, so an entry
+  edited or removed after the fact breaks the chain from
+  there on. The first entry's previous hash is empty.
+*/
+
+// a change made through the admin api, before it is sealed
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AdminChange {
+    // the X-Admin-Actor the request declared
+    pub actor: String,
+    pub client: String,
+    pub action: String,
+    pub target: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    pub seq: i64,
+    pub timestamp: i64,
+    pub actor: String,
+    pub client: String,
+    pub action: String,
+    pub target: String,
+    pub before: Option<Value>,
+    pub after: Option<Value>,
+    pub prev_hash: String,
+    pub hash: String,
+}
+
+// object keys sorted at every level, the stores don't keep their order
+fn canonical(value: &Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut keys: Vec<&String> = map.keys().collect();
+            keys.sort();
+            let mut sorted = Map::new();
+            for key in keys {
+                sorted.insert(key.clone(), canonical(&map[key]));
+            }
+            Value::Object(sorted)
+        }
+        Value::Array(values) => Value::Array(values.iter().map(canonical).collect()),
+        other => other.clone(),
+    }
+}
+
+fn entry_hash(entry: &AuditEntry) -> String {
+    let fields = json!([
+        entry.seq,
+        entry.timestamp,
+        entry.actor,
+        entry.client,
+        entry.action,
+        entry.target,
+        entry.before.as_ref().map(canonical),
+        entry.after.as_ref().map(canonical),
+        entry.prev_hash,
+    ]);
+    base64_url::encode(&Sha256::digest(fields.to_string().as_bytes()))
+}
+
+// the entry that follows previous, None for the first one
+pub fn seal(previous: Option<&AuditEntry>, change: AdminChange, timestamp: i64) -> AuditEntry {
+    let mut entry = AuditEntry {
+        seq: previous.map(|p| p.seq + 1).unwrap_or(1),
+        timestamp,
+        actor: change.actor,
+        client: change.client,
+        action: change.action,
+        target: change.target,
+        before: change.before,
+        after: change.after,
+        prev_hash: previous.map(|p| p.hash.clone()).unwrap_or_default(),
+        hash: String::new(),
+    };
+    entry.hash = entry_hash(&entry);
+    entry
+}
+
+/*
+  Checks a run of consecutive entries, previous is the
+  entry before the first one if it isn't the start of
+  the log. Returns the seq of the first broken entry.
+*/
+pub fn verify_chain(previous: Option<&AuditEntry>, entries: &[AuditEntry]) -> Result<(), i64> {
+    let mut previous = previous;
+    for entry in entries {
+        let (seq, prev_hash) = match previous {
+            Some(p) => (p.seq + 1, p.hash.as_str()),
+            None => (1, ""),
+        };
+        if entry.seq != seq || entry.prev_hash != prev_hash || entry.hash != entry_hash(entry) {
+            return Err(entry.seq);
+        }
+        previous = Some(entry);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn change(action: &str, after: Value) -> AdminChange {
+        AdminChange {
+            actor: "ops".to_string(),
+            client: "10.0.0.1".to_string(),
+            action: action.to_string(),
+            target: "target".to_string(),
+            before: None,
+            after: Some(after),
+        }
+    }
+
+    #[test]
+    fn test_chain_detects_edits_and_removals() {
+        let first = seal(None, change("denylist.add", json!({"a": 1, "b": 2})), 1);
+        let second = seal(Some(&first), change("denylist.remove", json!({})), 2);
+        let third = seal(Some(&second), change("api_key.issue", json!(null)), 3);
+        assert_eq!((first.seq, first.prev_hash.as_str()), (1, ""));
+        assert_eq!(third.prev_hash, second.hash);
+
+        let entries = vec![first.clone(), second.clone(), third.clone()];
+        assert_eq!(verify_chain(None, &entries), Ok(()));
+        assert_eq!(verify_chain(Some(&first), &entries[1..]), Ok(()));
+
+        // key order doesn't change the hash
+        let mut reordered = first.clone();
+        reordered.after = serde_json::from_str(r#"{"b": 2, "a": 1}"#).ok();
+        assert_eq!(verify_chain(None, &[reordered]), Ok(()));
+
+        let mut edited = entries.clone();
+        edited[1].actor = "someone else".to_string();
+        assert_eq!(verify_chain(None, &edited), Err(2));
+
+        let removed = vec![first, third];
+        assert_eq!(verify_chain(None, &removed), Err(3));
+    }
+}
@@ -1,21 +1,82 @@
+use std::env;
 use std::sync::Arc;
 
+use bytes::Bytes;
+
 use super::tags::Tag;
 
 use super::bytes::{ByteErrorType, DataBundle, DataItem};
 use super::dal::{Gateway, GatewayTx, Log, ScheduleProvider, Signer, TxStatus};
 use super::json::Process;
+use super::merkle::{inclusion_proofs, merkle_root, InclusionProof, MERKLE_ROOT_TAG};
+use super::variant::Variant;
+use super::verify_cache::VerifyCache;
 
 pub struct Builder<'a> {
     gateway: Arc<dyn Gateway>,
     signer: Arc<dyn Signer>,
     logger: &'a Arc<dyn Log>,
+    assignment_tags: Vec<Tag>,
+}
+
+// tags the su sets itself, operator tags may not reuse them
+const RESERVED_TAGS: [&str; 11] = [
+    "Process",
+    "Epoch",
+    "Nonce",
+    "Hash-Chain",
+    "Block-Height",
+    "Timestamp",
+    "Data-Protocol",
+    "Type",
+    "Variant",
+    "Message",
+    "Exclude",
+];
+
+/*
+  Parse ASSIGNMENT_TAGS, a comma separated list of
+  Name=Value pairs appended to every assignment. A value
+  of {version} is replaced with the su version and
+  {hostname} with the HOSTNAME environment variable.
+*/
+pub fn parse_assignment_tags(spec: &str) -> Result<Vec<Tag>, BuilderErrorType> {
+    let mut tags = vec![];
+    for pair in spec.split(',').map(str::trim).filter(|pair| !pair.is_empty()) {
+        let (name, value) = pair.split_once('=').ok_or(BuilderErrorType::BuilderError(
+            format!("Assignment tag {} is not Name=Value", pair),
+        ))?;
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return Err(BuilderErrorType::BuilderError(format!(
+                "Assignment tag {} has no name",
+                pair
+            )));
+        }
+        if RESERVED_TAGS
+            .iter()
+            .any(|reserved| reserved.eq_ignore_ascii_case(name))
+        {
+            return Err(BuilderErrorType::BuilderError(format!(
+                "Assignment tag {} is set by the su and cannot be overridden",
+                name
+            )));
+        }
+
+        let value = value
+            .replace("{version}", env!("CARGO_PKG_VERSION"))
+            .replace("{hostname}", &env::var("HOSTNAME").unwrap_or_default());
+        tags.push(Tag::new(name, &value));
+    }
+    Ok(tags)
 }
 
 pub struct BuildResult {
     pub binary: Vec<u8>,
     pub bundle: DataBundle,
     pub bundle_data_item: DataItem,
+    // one per bundled item, against the bundle's Merkle-Root tag
+    pub proofs: Vec<InclusionProof>,
 }
 
 #[derive(Debug, Clone)]
@@ -51,48 +112,54 @@ impl<'a> Builder<'a> {
             gateway,
             signer,
             logger,
+            assignment_tags: vec![],
         })
     }
 
+    // operator tags added to every assignment after the su's own
+    pub fn with_assignment_tags(mut self, assignment_tags: Vec<Tag>) -> Self {
+        self.assignment_tags = assignment_tags;
+        self
+    }
+
     pub async fn gen_assignment(
         &self,
         message_id: Option<String>,
         process_id: String,
         schedule_info: &dyn ScheduleProvider,
         exclude: &Option<String>,
+        variant: Variant,
     ) -> Result<DataItem, BuilderErrorType> {
         let network_info = self.gateway.network_info().await?;
         let height = network_info.height.clone();
         let mut tags = vec![
-            Tag::new(&"Process".to_string(), &process_id),
-            Tag::new(&"Epoch".to_string(), &schedule_info.epoch()),
-            Tag::new(&"Nonce".to_string(), &schedule_info.nonce()),
-            Tag::new(&"Hash-Chain".to_string(), &schedule_info.hash_chain()),
-            Tag::new(&"Block-Height".to_string(), &height.to_string()),
-            Tag::new(&"Timestamp".to_string(), &schedule_info.timestamp()),
-            Tag::new(&"Data-Protocol".to_string(), &"ao".to_string()),
-            Tag::new(&"Type".to_string(), &"Assignment".to_string()),
-            Tag::new(&"Variant".to_string(), &"ao.TN.1".to_string()),
+            Tag::new("Process", &process_id),
+            Tag::new("Epoch", &schedule_info.epoch()),
+            Tag::new("Nonce", &schedule_info.nonce()),
+            Tag::new("Hash-Chain", &schedule_info.hash_chain()),
+            Tag::new("Block-Height", &height.to_string()),
+            Tag::new("Timestamp", &schedule_info.timestamp()),
+            Tag::new("Data-Protocol", "ao"),
+            Tag::new("Type", "Assignment"),
+            Tag::new("Variant", variant.as_str()),
         ];
 
-        match message_id {
-            Some(id) => tags.push(Tag::new(&"Message".to_string(), &id)),
-            None => (),
+        if let Some(id) = message_id {
+            tags.push(Tag::new("Message", &id))
         };
 
         /*
             exclude is a comma seperated value fed in as a query
             param. We add an Exclude tag for each value set.
         */
-        match exclude {
-            Some(csv) => {
-                for val in csv.split(',') {
-                    tags.push(Tag::new(&"Exclude".to_string(), &val))
-                }
+        if let Some(csv) = exclude {
+            for val in csv.split(',') {
+                tags.push(Tag::new("Exclude", val))
             }
-            None => (),
         }
 
+        tags.extend(self.assignment_tags.iter().cloned());
+
         let mut assignment = DataItem::new(vec![], vec![], tags, self.signer.get_public_key())?;
         let assignment_message = assignment.get_message()?.to_vec();
         let assignment_signature = self.signer.sign_tx(assignment_message).await?;
@@ -105,13 +172,42 @@ impl<'a> Builder<'a> {
         Ok(assignment)
     }
 
+    /*
+        The Scheduler-Location record announcing where the
+        su's wallet schedules, for ttl milliseconds
+    */
+    pub async fn build_scheduler_location(
+        &self,
+        url: &str,
+        ttl: u64,
+    ) -> Result<DataItem, BuilderErrorType> {
+        let tags = vec![
+            Tag::new("Data-Protocol", "ao"),
+            Tag::new("Variant", Variant::DEFAULT.as_str()),
+            Tag::new("Type", "Scheduler-Location"),
+            Tag::new("Url", url),
+            Tag::new("Time-To-Live", &ttl.to_string()),
+        ];
+
+        let mut location = DataItem::new(vec![], vec![], tags, self.signer.get_public_key())?;
+        let location_message = location.get_message()?.to_vec();
+        location.signature = self.signer.sign_tx(location_message).await?;
+
+        self.logger
+            .log(format!("built scheduler location {}", location.id()));
+
+        Ok(location)
+    }
+
     pub async fn bundle_items(
         &self,
         items: Vec<DataItem>,
     ) -> Result<BuildResult, BuilderErrorType> {
+        let item_ids: Vec<String> = items.iter().map(|item| item.id()).collect();
         let bundle_tags = vec![
-            Tag::new(&"Bundle-Format".to_string(), &"binary".to_string()),
-            Tag::new(&"Bundle-Version".to_string(), &"2.0.0".to_string()),
+            Tag::new("Bundle-Format", "binary"),
+            Tag::new("Bundle-Version", "2.0.0"),
+            Tag::new(MERKLE_ROOT_TAG, &merkle_root(&item_ids)?),
         ];
 
         let mut data_bundle = DataBundle::new();
@@ -135,6 +231,7 @@ impl<'a> Builder<'a> {
         Ok(BuildResult {
             binary: bundle_data_item.as_bytes()?,
             bundle: data_bundle,
+            proofs: inclusion_proofs(&bundle_data_item.id(), &item_ids)?,
             bundle_data_item,
         })
     }
@@ -165,10 +262,11 @@ impl<'a> Builder<'a> {
         let height = network_info.height.clone();
 
         let tags = vec![
-            Tag::new(&"Bundle-Format".to_string(), &"binary".to_string()),
-            Tag::new(&"Bundle-Version".to_string(), &"2.0.0".to_string()),
-            Tag::new(&"Block-Height".to_string(), &height.to_string()),
-            Tag::new(&"Timestamp".to_string(), &schedule_info.timestamp()),
+            Tag::new("Bundle-Format", "binary"),
+            Tag::new("Bundle-Version", "2.0.0"),
+            Tag::new("Block-Height", &height.to_string()),
+            Tag::new("Timestamp", &schedule_info.timestamp()),
+            Tag::new(MERKLE_ROOT_TAG, &merkle_root(&[item.id()])?),
         ];
         self.logger.log(format!("generated tags - {:?}", &tags));
 
@@ -188,25 +286,46 @@ impl<'a> Builder<'a> {
 
         Ok(BuildResult {
             binary: new_data_item.as_bytes()?,
+            proofs: inclusion_proofs(&new_data_item.id(), &[data_bundle.items[0].id()])?,
             bundle: data_bundle,
             bundle_data_item: new_data_item,
         })
     }
 
-    pub fn parse_data_item(tx: Vec<u8>) -> Result<DataItem, BuilderErrorType> {
-        Ok(DataItem::from_bytes_verify(tx)?)
+    pub fn parse_data_item(
+        tx: Vec<u8>,
+        verify_cache: &VerifyCache,
+    ) -> Result<DataItem, BuilderErrorType> {
+        let mut item = DataItem::from_bytes(tx)?;
+        verify_cache.verify(&mut item)?;
+        Ok(item)
+    }
+
+    /*
+      Parses only the header of a data item and verifies
+      its signature over the data in place, the returned
+      item has no data. For callers that only need the id,
+      owner, target and tags.
+    */
+    pub fn parse_data_item_header(
+        tx: &Bytes,
+        verify_cache: &VerifyCache,
+    ) -> Result<DataItem, BuilderErrorType> {
+        let (item, data_start) = DataItem::parse_header(tx)?;
+        verify_cache.verify_detached(&item, tx.slice(data_start..))?;
+        Ok(item)
     }
 
     pub async fn verify_assignment(
         &self,
-        tx_id: &String,
+        tx_id: &str,
         process: &Process,
         base_layer: &Option<String>,
     ) -> Result<Option<GatewayTx>, BuilderErrorType> {
         // Process the assignment verification
         let result = match base_layer {
             Some(_) => {
-                let status: TxStatus = self.gateway.status(&tx_id).await?;
+                let status: TxStatus = self.gateway.status(tx_id).await?;
 
                 let threshold = match process
                     .process
@@ -214,10 +333,7 @@ impl<'a> Builder<'a> {
                     .iter()
                     .find(|tag| tag.name == "Settlement-Depth")
                 {
-                    Some(t) => match t.value.parse::<i32>() {
-                        Ok(parsed_value) => parsed_value,
-                        Err(_) => 20,
-                    },
+                    Some(t) => t.value.parse::<i32>().unwrap_or(20),
                     None => 20,
                 };
 
@@ -228,7 +344,7 @@ impl<'a> Builder<'a> {
                     )),
                 }
             }
-            None => Ok(Some(self.gateway.gql_tx(&tx_id).await?)),
+            None => Ok(Some(self.gateway.gql_tx(tx_id).await?)),
         };
 
         result
@@ -242,6 +358,22 @@ mod tests {
     use async_trait::async_trait;
     // use std::sync::Arc;
 
+    #[test]
+    fn test_parse_assignment_tags() {
+        let tags = parse_assignment_tags(" Region=eu-west , SU-Version={version},Empty=").unwrap();
+        assert_eq!(tags.len(), 3);
+        assert_eq!(tags[0].name, "Region");
+        assert_eq!(tags[0].value, "eu-west");
+        assert_eq!(tags[1].value, env!("CARGO_PKG_VERSION"));
+        assert_eq!(tags[2].value, "");
+
+        assert!(parse_assignment_tags("").unwrap().is_empty());
+        assert!(parse_assignment_tags("Region").is_err());
+        assert!(parse_assignment_tags("=eu-west").is_err());
+        assert!(parse_assignment_tags("nonce=1").is_err());
+    }
+
+    #[allow(dead_code)]
     struct MockGateway;
     #[async_trait]
     impl Gateway for MockGateway {
@@ -256,14 +388,14 @@ mod tests {
             })
         }
 
-        async fn status(&self, _tx_id: &String) -> Result<TxStatus, String> {
+        async fn status(&self, _tx_id: &str) -> Result<TxStatus, String> {
             Ok(TxStatus {
                 block_height: 0,
                 number_of_confirmations: 0,
             })
         }
 
-        async fn gql_tx(&self, _tx_id: &String) -> Result<GatewayTx, String> {
+        async fn gql_tx(&self, _tx_id: &str) -> Result<GatewayTx, String> {
             Ok(GatewayTx {
                 id: "id".to_string(),
                 signature: "sig".to_string(),
@@ -273,11 +405,12 @@ mod tests {
             })
         }
 
-        async fn raw(&self, _tx_id: &String) -> Result<Vec<u8>, String> {
+        async fn raw(&self, _tx_id: &str) -> Result<Vec<u8>, String> {
             Ok(vec![])
         }
     }
 
+    #[allow(dead_code)]
     struct MockSigner;
     #[async_trait]
     impl Signer for MockSigner {
@@ -290,6 +423,7 @@ mod tests {
         }
     }
 
+    #[allow(dead_code)]
     struct MockLogger;
     #[async_trait]
     impl Log for MockLogger {
@@ -301,6 +435,7 @@ mod tests {
         }
     }
 
+    #[allow(dead_code)]
     struct MockScheduler;
     impl ScheduleProvider for MockScheduler {
         fn epoch(&self) -> String {
@@ -8,6 +8,7 @@ use base64_url;
 use sha2::{Digest as Sha2Digest, Sha256, Sha384};
 
 use ring::rand::SecureRandom;
+use ring::signature::{UnparsedPublicKey, ED25519};
 
 use jsonwebkey::JsonWebKey;
 use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
@@ -30,7 +31,7 @@ impl From<TagError> for ByteErrorType {
 
 impl From<&str> for ByteErrorType {
     fn from(error: &str) -> Self {
-        ByteErrorType::ByteError(format!("Byte error: {}", error.to_string()))
+        ByteErrorType::ByteError(format!("Byte error: {}", error))
     }
 }
 
@@ -122,13 +123,39 @@ impl DataBundle {
     }
 }
 
+/*
+  The raw bytes of each item in a bundle, in order. The
+  bundle comes from a client so every length is checked
+  against the input instead of trusted.
+*/
+pub fn split_bundle(bytes: &[u8]) -> Result<Vec<Vec<u8>>, ByteErrorType> {
+    let truncated = || ByteErrorType::ByteError("Bundle is truncated".to_string());
+
+    let items_len = _32_byte_array_to_long(bytes.get(0..32).ok_or_else(truncated)?)? as usize;
+    let headers_end = items_len
+        .checked_mul(64)
+        .and_then(|size| size.checked_add(32))
+        .ok_or_else(truncated)?;
+    let headers = bytes.get(32..headers_end).ok_or_else(truncated)?;
+
+    let mut offset = headers_end;
+    let mut items = Vec::with_capacity(items_len);
+    for header in headers.chunks(64) {
+        let item_len = _32_byte_array_to_long(&header[0..32])? as usize;
+        let end = offset.checked_add(item_len).ok_or_else(truncated)?;
+        items.push(bytes.get(offset..end).ok_or_else(truncated)?.to_vec());
+        offset = end;
+    }
+
+    Ok(items)
+}
+
 fn long_to_n_byte_array(n: usize, long: u64) -> Result<Vec<u8>, ByteErrorType> {
     let mut byte_array = vec![0u8; n];
     let mut value = long;
 
-    for index in 0..n {
-        let byte = (value & 0xFF) as u8;
-        byte_array[index] = byte;
+    for byte in byte_array.iter_mut() {
+        *byte = (value & 0xFF) as u8;
         value >>= 8;
     }
 
@@ -349,36 +376,47 @@ impl DataItem {
     }
 
     pub fn get_message(&mut self) -> Result<Bytes, ByteErrorType> {
+        match &self.data {
+            Data::None => Ok(Bytes::new()),
+            Data::Bytes(data) => self.message_with(data.clone().into()),
+        }
+    }
+
+    /// The signed message of this item's header over data held elsewhere
+    pub fn message_with(&self, data: Bytes) -> Result<Bytes, ByteErrorType> {
         let encoded_tags = if !self.tags.is_empty() {
             self.tags.encode()?
         } else {
             Bytes::default()
         };
 
-        match &mut self.data {
-            Data::None => Ok(Bytes::new()),
-            Data::Bytes(data) => {
-                let data_chunk = DeepHashChunk::Chunk(data.clone().into());
-                let sig_type = &self.signature_type;
-                let sig_type_bytes = sig_type.as_u16().to_string().as_bytes().to_vec();
-                deep_hash_sync(DeepHashChunk::Chunks(vec![
-                    DeepHashChunk::Chunk(DATAITEM_AS_BUFFER.into()),
-                    DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
-                    DeepHashChunk::Chunk(sig_type_bytes.to_vec().into()),
-                    DeepHashChunk::Chunk(self.owner.to_vec().into()),
-                    DeepHashChunk::Chunk(self.target.to_vec().into()),
-                    DeepHashChunk::Chunk(self.anchor.to_vec().into()),
-                    DeepHashChunk::Chunk(encoded_tags.clone()),
-                    data_chunk,
-                ]))
-            }
-        }
+        let sig_type_bytes = self.signature_type.as_u16().to_string().as_bytes().to_vec();
+        deep_hash_sync(DeepHashChunk::Chunks(vec![
+            DeepHashChunk::Chunk(DATAITEM_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(ONE_AS_BUFFER.into()),
+            DeepHashChunk::Chunk(sig_type_bytes.into()),
+            DeepHashChunk::Chunk(self.owner.to_vec().into()),
+            DeepHashChunk::Chunk(self.target.to_vec().into()),
+            DeepHashChunk::Chunk(self.anchor.to_vec().into()),
+            DeepHashChunk::Chunk(encoded_tags),
+            DeepHashChunk::Chunk(data),
+        ]))
     }
 
     pub fn is_signed(&self) -> bool {
         !self.signature.is_empty() && self.signature_type != SignerMap::None
     }
 
+    /*
+      Parses only the header of an ANS-104 data item, the
+      signature, owner, target, anchor and tags, without
+      reading or copying the data after it. Returns the
+      item without data and the offset its data starts at.
+    */
+    pub fn parse_header(buffer: &[u8]) -> Result<(Self, usize), ByteErrorType> {
+        DataItem::from_info_bytes(buffer)
+    }
+
     fn from_info_bytes(buffer: &[u8]) -> Result<(Self, usize), ByteErrorType> {
         if buffer.len() < 2 {
             return Err(ByteErrorType::ByteError(
@@ -408,50 +446,58 @@ impl DataItem {
         let signature = &buffer[2..2 + sig_length];
         let owner = &buffer[2 + sig_length..2 + sig_length + pub_length];
 
+        /*
+          Every read below is bounds checked, a truncated
+          or lying header is an error rather than a panic
+        */
+        let too_short =
+            |field: &str| ByteErrorType::ByteError(format!("Buffer too short for {}", field));
+
         let target_start = 2 + sig_length + pub_length;
-        let target_present = u8::from_le_bytes(
-            <[u8; 1]>::try_from(&buffer[target_start..target_start + 1]).map_err(|err| {
-                ByteErrorType::ByteError(format!("target bytes error - {}", err.to_string()))
-            })?,
-        );
-        let target = match target_present {
-            0 => &[],
-            1 => &buffer[target_start + 1..target_start + 33],
-            _b => return Err(ByteErrorType::ByteError("target bytes error".to_string())),
+        let target = match buffer.get(target_start) {
+            Some(0) => &[][..],
+            Some(1) => buffer
+                .get(target_start + 1..target_start + 33)
+                .ok_or_else(|| too_short("target"))?,
+            Some(_) => return Err(ByteErrorType::ByteError("target bytes error".to_string())),
+            None => return Err(too_short("target")),
         };
         let anchor_start = target_start + 1 + target.len();
-        let anchor_present = u8::from_le_bytes(
-            <[u8; 1]>::try_from(&buffer[anchor_start..anchor_start + 1]).map_err(|err| {
-                ByteErrorType::ByteError(format!("anchor bytes error - {}", err.to_string()))
-            })?,
-        );
-        let anchor = match anchor_present {
-            0 => &[],
-            1 => &buffer[anchor_start + 1..anchor_start + 33],
-            b => {
+        let anchor = match buffer.get(anchor_start) {
+            Some(0) => &[][..],
+            Some(1) => buffer
+                .get(anchor_start + 1..anchor_start + 33)
+                .ok_or_else(|| too_short("anchor"))?,
+            Some(b) => {
                 return Err(ByteErrorType::ByteError(format!(
                     "anchor bytes error - {}",
-                    b.to_string()
+                    b
                 )))
             }
+            None => return Err(too_short("anchor")),
         };
 
         let tags_start = anchor_start + 1 + anchor.len();
+        let tag_counts = buffer
+            .get(tags_start..tags_start + 16)
+            .ok_or_else(|| too_short("tags"))?;
         let number_of_tags = u64::from_le_bytes(
-            <[u8; 8]>::try_from(&buffer[tags_start..tags_start + 8]).map_err(|err| {
-                ByteErrorType::ByteError(format!("tag bytes error - {}", err.to_string()))
-            })?,
+            <[u8; 8]>::try_from(&tag_counts[..8])
+                .map_err(|err| ByteErrorType::ByteError(format!("tag bytes error - {}", err)))?,
         );
-
         let number_of_tags_bytes = u64::from_le_bytes(
-            <[u8; 8]>::try_from(&buffer[tags_start + 8..tags_start + 16]).map_err(|err| {
-                ByteErrorType::ByteError(format!("tag bytes error - {}", err.to_string()))
-            })?,
+            <[u8; 8]>::try_from(&tag_counts[8..])
+                .map_err(|err| ByteErrorType::ByteError(format!("tag bytes error - {}", err)))?,
         );
 
-        let mut b = buffer.to_vec();
-        let mut tags_bytes =
-            &mut b[tags_start + 16..tags_start + 16 + number_of_tags_bytes as usize];
+        let tags_end = usize::try_from(number_of_tags_bytes)
+            .ok()
+            .and_then(|len| (tags_start + 16).checked_add(len))
+            .filter(|end| *end <= buffer.len())
+            .ok_or_else(|| too_short("tags"))?;
+        // only the tags are copied, the decoder needs them mutable
+        let mut b = buffer[tags_start + 16..tags_end].to_vec();
+        let mut tags_bytes = b.as_mut_slice();
 
         let tags = if number_of_tags_bytes > 0 {
             tags_bytes.decode()?
@@ -473,7 +519,7 @@ impl DataItem {
             data: Data::None,
         };
 
-        Ok((data_item, tags_start + 16 + number_of_tags_bytes as usize))
+        Ok((data_item, tags_end))
     }
 
     pub fn from_bytes(buffer: Vec<u8>) -> Result<Self, ByteErrorType> {
@@ -505,17 +551,33 @@ impl DataItem {
     }
 
     pub fn verify(&mut self) -> Result<(), ByteErrorType> {
+        let message = self.get_message()?;
+        self.verify_message(&message)
+    }
+
+    /// Verify the signature over a message already built with get_message
+    pub fn verify_message(&self, message: &[u8]) -> Result<(), ByteErrorType> {
         match self.signature_type {
-            SignerMap::Ethereum | SignerMap::TypedEthereum => self.verify_ethereum(),
-            _ => self.verify_rsa(),
+            SignerMap::Arweave => self.verify_rsa(message),
+            SignerMap::Ethereum => self.verify_ethereum(message),
+            SignerMap::Ed25519 | SignerMap::Solana => self.verify_ed25519(message),
+            _ => Err(ByteErrorType::ByteError(format!(
+                "Unsupported signature type {}",
+                self.signature_type.get_config().sig_name
+            ))),
         }
     }
 
-    /// Ethereum (0x MetaMask) Signature Verification
-    fn verify_ethereum(&mut self) -> Result<(), ByteErrorType> {
-        // Get the message to verify
-        let message = self.get_message()?;
+    /// Ed25519 and Solana Signature Verification, the key signs the message as is
+    fn verify_ed25519(&self, message: &[u8]) -> Result<(), ByteErrorType> {
+        let public_key = UnparsedPublicKey::new(&ED25519, self.owner.as_slice());
+        public_key
+            .verify(message, self.signature.as_slice())
+            .map_err(|_| ByteErrorType::ByteError("Signature verification failed".to_string()))
+    }
 
+    /// Ethereum (0x MetaMask) Signature Verification
+    fn verify_ethereum(&self, message: &[u8]) -> Result<(), ByteErrorType> {
         // Extract r, s, and v from the signature - standard Ethereum format
         let r = &self.signature[0..32];
         let s = &self.signature[32..64];
@@ -551,7 +613,7 @@ impl DataItem {
         // Full signed message
         let mut eth_msg = Vec::new();
         eth_msg.extend_from_slice(prefix.as_bytes());
-        eth_msg.extend_from_slice(&message);
+        eth_msg.extend_from_slice(message);
 
         // Now create the keccak256 hash that was signed
         let mut msg_hasher = Keccak256::new();
@@ -560,8 +622,8 @@ impl DataItem {
 
         // Adjust v to a recovery id (0 or 1)
         let recovery_id = match v {
-            27 => 0,
-            28 => 1,
+            0 | 27 => 0,
+            1 | 28 => 1,
             _ => {
                 return Err(ByteErrorType::ByteError(
                     "Invalid recovery ID for Ethereum signature".to_string(),
@@ -625,7 +687,7 @@ impl DataItem {
     }
 
     /// RSA Signature Verification
-    fn verify_rsa(&mut self) -> Result<(), ByteErrorType> {
+    fn verify_rsa(&self, message: &[u8]) -> Result<(), ByteErrorType> {
         let jwt_str = format!(
             "{{\"kty\":\"RSA\",\"e\":\"AQAB\",\"n\":\"{}\"}}",
             base64_url::encode(self.owner.as_slice())
@@ -648,13 +710,7 @@ impl DataItem {
         };
 
         let mut hasher = sha2::Sha256::new();
-        let message = match self.get_message() {
-            Ok(msg) => msg,
-            Err(e) => {
-                return Err(e);
-            }
-        };
-        hasher.update(&message);
+        hasher.update(message);
         let hashed = &hasher.finalize();
 
         let rng = thread_rng();
@@ -702,9 +758,10 @@ impl DataItem {
             + encoded_tags.len() as u64
             + data.len() as u64;
 
-        let mut b = Vec::with_capacity(TryInto::<usize>::try_into(length).map_err(|err| {
-            ByteErrorType::ByteError(format!("data length error - {} ", err.to_string()))
-        })?);
+        let mut b =
+            Vec::with_capacity(TryInto::<usize>::try_into(length).map_err(|err| {
+                ByteErrorType::ByteError(format!("data length error - {} ", err))
+            })?);
 
         let sig_type: [u8; 2] = (self.signature_type.clone() as u16).to_le_bytes();
         let target_presence_byte = if self.target.is_empty() {
@@ -830,13 +887,20 @@ impl DataItem {
     }
 
     pub fn owner(&self) -> String {
-        let owner_base64 = base64_url::encode(&self.owner);
-        owner_base64
+        base64_url::encode(&self.owner)
+    }
+
+    pub fn signature_type(&self) -> &SignerMap {
+        &self.signature_type
+    }
+
+    /// The owner's address in its signature type's own format
+    pub fn owner_address(&self) -> String {
+        super::address::owner_address(&self.signature_type, &self.owner)
     }
 
     pub fn target(&self) -> String {
-        let target_base64 = base64_url::encode(&self.target);
-        target_base64
+        base64_url::encode(&self.target)
     }
 
     pub fn tags(&self) -> Vec<Tag> {
@@ -856,10 +920,7 @@ impl DataItem {
     */
     pub fn data_if_string(&self) -> Option<String> {
         match &self.data {
-            Data::Bytes(d) => match String::from_utf8(d.clone()) {
-                Ok(s) => Some(s),
-                Err(_) => None,
-            },
+            Data::Bytes(d) => String::from_utf8(d.clone()).ok(),
             Data::None => None,
         }
     }
@@ -872,8 +933,7 @@ impl DataItem {
     }
 
     pub fn signature(&self) -> String {
-        let sig_base64 = base64_url::encode(&self.signature);
-        sig_base64
+        base64_url::encode(&self.signature)
     }
 
     pub fn anchor(&self) -> String {
@@ -916,12 +976,73 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_parse_header_leaves_data() {
+        let mut item = DataItem::new(vec![3; 32], b"data".to_vec(), vec![], vec![1; 512]).unwrap();
+        item.signature = vec![2; 512];
+        let item_bytes = item.as_bytes().unwrap();
+
+        let mut full = DataItem::from_bytes(item_bytes.clone()).expect("failed to build data item");
+        let (header, data_start) =
+            DataItem::parse_header(&item_bytes).expect("failed to parse header");
+        assert_eq!(header.id(), full.id());
+        assert_eq!(header.owner(), full.owner());
+        assert_eq!(header.target(), full.target());
+        assert_eq!(header.tags(), full.tags());
+        assert_eq!(
+            &item_bytes[data_start..],
+            full.data_bytes().unwrap().as_slice()
+        );
+
+        let shared = Bytes::from(item_bytes);
+        assert_eq!(
+            header.message_with(shared.slice(data_start..)).unwrap(),
+            full.get_message().unwrap()
+        );
+
+        // a truncated header is an error, not a panic
+        for len in 0..data_start {
+            assert!(DataItem::parse_header(&shared[..len]).is_err(), "{}", len);
+        }
+    }
+
+    #[test]
+    fn test_verify_ed25519_and_solana() {
+        use ring::signature::{Ed25519KeyPair, KeyPair};
+
+        let rng = ring::rand::SystemRandom::new();
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&rng).unwrap();
+        let key = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+
+        for signature_type in [SignerMap::Ed25519, SignerMap::Solana] {
+            let owner = key.public_key().as_ref().to_vec();
+            let mut item = DataItem::new(vec![], b"data".to_vec(), vec![], owner).unwrap();
+            item.signature_type = signature_type;
+            let message = item.get_message().unwrap();
+            item.signature = key.sign(&message).as_ref().to_vec();
+
+            let parsed = DataItem::from_bytes_verify(item.as_bytes().unwrap());
+            assert!(parsed.is_ok(), "{:?}", parsed.err());
+
+            item.signature[0] ^= 1;
+            assert!(DataItem::from_bytes_verify(item.as_bytes().unwrap()).is_err());
+        }
+
+        let mut aptos = DataItem::new(vec![], b"data".to_vec(), vec![], vec![1; 32]).unwrap();
+        aptos.signature_type = SignerMap::InjectedAptos;
+        aptos.signature = vec![2; 64];
+        match DataItem::from_bytes_verify(aptos.as_bytes().unwrap()) {
+            Err(ByteErrorType::ByteError(e)) => assert!(e.contains("injectedAptos"), "{}", e),
+            Ok(_) => panic!("an unsupported signature type verified"),
+        }
+    }
+
     #[test]
     fn test_is_signed() {
         let d_item_string = ITEM_STR.to_string();
         let item_bytes = base64_url::decode(&d_item_string).expect("failed to encode data item");
         let data_item = DataItem::from_bytes(item_bytes).expect("failed to build data item");
-        assert_eq!(data_item.is_signed(), true);
+        assert!(data_item.is_signed());
     }
 
     #[test]
@@ -935,4 +1056,21 @@ mod tests {
         let bundle_bytes = data_bundle.to_bytes();
         assert!(bundle_bytes.is_ok(), "Bundling failed");
     }
+
+    #[test]
+    fn test_split_bundle() {
+        let mut bundle = long_to_32_byte_array(2).unwrap();
+        for item in [&[1u8, 2, 3][..], &[4u8, 5][..]] {
+            bundle.extend(long_to_32_byte_array(item.len() as u64).unwrap());
+            bundle.extend([0u8; 32]);
+        }
+        bundle.extend([1u8, 2, 3, 4, 5]);
+
+        let items = split_bundle(&bundle).unwrap();
+        assert_eq!(items, vec![vec![1, 2, 3], vec![4, 5]]);
+
+        assert!(split_bundle(&bundle[..bundle.len() - 1]).is_err());
+        assert!(split_bundle(&bundle[..40]).is_err());
+        assert!(split_bundle(&[]).is_err());
+    }
 }
@@ -0,0 +1,225 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+use super::dal::CoreMetrics;
+
+/*
+  Prefix of the error a request gets when its endpoint
+  class has every slot taken and its queue is full
+*/
+pub const CONCURRENCY_LIMITED: &str = "Too many concurrent requests";
+
+/*
+  The classes of endpoints that are limited separately,
+  so a flood of one kind can't starve the others
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EndpointClass {
+    // data items posted to / and /batch
+    Write,
+    // message listings, graphql, stats and replays
+    Read,
+    // everything under /admin
+    Admin,
+}
+
+impl EndpointClass {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EndpointClass::Write => "writes",
+            EndpointClass::Read => "reads",
+            EndpointClass::Admin => "admin",
+        }
+    }
+}
+
+/*
+  At most limit requests of a class run at once and up
+  to queue_size more wait for one of them to finish, a
+  limit of 0 lets everything through
+*/
+pub struct EndpointLimit {
+    class: EndpointClass,
+    limit: usize,
+    semaphore: Arc<Semaphore>,
+    queue_size: usize,
+    queued: AtomicUsize,
+}
+
+/*
+  A place in the queue, given up when the request gets
+  its slot, is refused or goes away while waiting
+*/
+struct Queued<'a> {
+    limit: &'a EndpointLimit,
+    metrics: &'a dyn CoreMetrics,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        let depth = self.limit.queued.fetch_sub(1, Ordering::SeqCst) - 1;
+        self.metrics
+            .endpoint_queue_observe(self.limit.class.as_str(), depth);
+    }
+}
+
+impl EndpointLimit {
+    pub fn new(class: EndpointClass, limit: usize, queue_size: usize) -> Self {
+        EndpointLimit {
+            class,
+            limit,
+            semaphore: Arc::new(Semaphore::new(limit)),
+            queue_size,
+            queued: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.limit > 0
+    }
+
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::SeqCst)
+    }
+
+    /*
+      Wait for a slot, the returned permit must be held
+      until the request is done. None when the class is
+      unlimited.
+    */
+    pub async fn acquire(
+        &self,
+        metrics: &dyn CoreMetrics,
+    ) -> Result<Option<OwnedSemaphorePermit>, String> {
+        if !self.enabled() {
+            return Ok(None);
+        }
+        if let Ok(permit) = self.semaphore.clone().try_acquire_owned() {
+            return Ok(Some(permit));
+        }
+
+        let depth = self.queued.fetch_add(1, Ordering::SeqCst) + 1;
+        let queued = Queued {
+            limit: self,
+            metrics,
+        };
+        if depth > self.queue_size {
+            metrics.endpoint_rejected(self.class.as_str());
+            return Err(format!(
+                "{} for {}, {} in progress and {} waiting",
+                CONCURRENCY_LIMITED,
+                self.class.as_str(),
+                self.limit,
+                self.queue_size
+            ));
+        }
+        metrics.endpoint_queue_observe(self.class.as_str(), depth);
+
+        let permit = self
+            .semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .map_err(|e| format!("{:?}", e))?;
+        drop(queued);
+        Ok(Some(permit))
+    }
+}
+
+pub struct ConcurrencyLimits {
+    pub writes: EndpointLimit,
+    pub reads: EndpointLimit,
+    pub admin: EndpointLimit,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(writes: usize, reads: usize, admin: usize, queue_size: usize) -> Self {
+        ConcurrencyLimits {
+            writes: EndpointLimit::new(EndpointClass::Write, writes, queue_size),
+            reads: EndpointLimit::new(EndpointClass::Read, reads, queue_size),
+            admin: EndpointLimit::new(EndpointClass::Admin, admin, queue_size),
+        }
+    }
+
+    pub fn get(&self, class: EndpointClass) -> &EndpointLimit {
+        match class {
+            EndpointClass::Write => &self.writes,
+            EndpointClass::Read => &self.reads,
+            EndpointClass::Admin => &self.admin,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    #[derive(Default)]
+    struct Recorded {
+        depths: Mutex<Vec<usize>>,
+        rejected: Mutex<usize>,
+    }
+
+    impl CoreMetrics for Recorded {
+        fn get_process_observe(&self, _duration: u128) {}
+        fn get_message_observe(&self, _duration: u128) {}
+        fn get_messages_observe(&self, _duration: u128) {}
+        fn read_message_data_observe(&self, _duration: u128) {}
+        fn write_item_observe(&self, _duration: u128) {}
+        fn write_assignment_observe(&self, _duration: u128) {}
+        fn acquire_write_lock_observe(&self, _duration: u128) {}
+        fn failed_message_save(&self) {}
+        fn process_scheduler_cleanup(&self, _action: &str) {}
+        fn memory_observe(&self, _rss: u64, _in_flight: u64, _shed_level: u8) {}
+        fn write_shed(&self) {}
+        fn redirect_served(&self, _scheduler: &str, _rule: &str) {}
+        fn item_written(&self, _kind: &str, _process_id: &str) {}
+        fn upload_failed(&self) {}
+        fn store_query_observe(&self, _query: &str, _duration: u128) {}
+        fn spawn_failover(&self, _scheduler: &str) {}
+        fn duplicate_spawn(&self) {}
+        fn rate_limited(&self, _kind: &str) {}
+        fn read_rate_limited(&self, _tier: &str) {}
+        fn endpoint_queue_observe(&self, _class: &str, depth: usize) {
+            self.depths.lock().unwrap().push(depth);
+        }
+        fn endpoint_rejected(&self, _class: &str) {
+            *self.rejected.lock().unwrap() += 1;
+        }
+        fn write_batch_observe(&self, _size: usize, _limit: usize, _wait: u64, _depth: usize) {}
+        fn rebalance_observe(&self, _imbalance: f64) {}
+        fn rebalance_move(&self, _outcome: &str) {}
+        fn signing_check_observe(&self, _failing: bool) {}
+        fn write_verified(&self, _outcome: &str) {}
+    }
+
+    #[tokio::test]
+    async fn test_queue_then_reject() {
+        let metrics = Recorded::default();
+        let limit = EndpointLimit::new(EndpointClass::Write, 1, 1);
+
+        let first = limit.acquire(&metrics).await.unwrap();
+        assert!(first.is_some());
+
+        // the second waits for the first, the third finds the queue full
+        let second = limit.acquire(&metrics);
+        tokio::pin!(second);
+        assert!(futures::poll!(second.as_mut()).is_pending());
+        assert_eq!(limit.queued(), 1);
+
+        let third = limit.acquire(&metrics).await;
+        assert!(third.unwrap_err().starts_with(CONCURRENCY_LIMITED));
+        assert_eq!(*metrics.rejected.lock().unwrap(), 1);
+
+        drop(first);
+        assert!(second.await.unwrap().is_some());
+        assert_eq!(limit.queued(), 0);
+        assert_eq!(*metrics.depths.lock().unwrap(), vec![1, 1, 0]);
+
+        let unlimited = EndpointLimit::new(EndpointClass::Read, 0, 0);
+        assert!(unlimited.acquire(&metrics).await.unwrap().is_none());
+    }
+}
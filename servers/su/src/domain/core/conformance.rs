@@ -0,0 +1,381 @@
+use serde_json::Value;
+
+use super::bytes::{ByteErrorType, DataItem};
+use super::dal::Signer;
+use super::router::hash;
+use super::tags::Tag;
+use super::variant::Variant;
+
+/*
+  The Module of the processes su-conformance spawns, an
+  su never loads it so any id will do
+*/
+pub const UNUSED_MODULE: &str = "AAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAAA";
+
+// tags every assignment carries, Message only when it assigns one
+const ASSIGNMENT_TAGS: [&str; 6] = [
+    "Process",
+    "Epoch",
+    "Nonce",
+    "Hash-Chain",
+    "Block-Height",
+    "Timestamp",
+];
+
+fn byte_error(e: ByteErrorType) -> String {
+    let ByteErrorType::ByteError(e) = e;
+    e
+}
+
+async fn signed_item(
+    signer: &dyn Signer,
+    target: Vec<u8>,
+    tags: Vec<Tag>,
+) -> Result<(String, Vec<u8>), String> {
+    let public_key = signer.get_public_key();
+    let mut item =
+        DataItem::new(target, b"su-conformance".to_vec(), tags, public_key).map_err(byte_error)?;
+    let message = item.get_message().map_err(byte_error)?.to_vec();
+    item.signature = signer.sign_tx(message).await?;
+    Ok((item.id(), item.as_bytes().map_err(byte_error)?))
+}
+
+// the id and bytes of a throwaway process scheduled on scheduler
+pub async fn spawn_item(signer: &dyn Signer, scheduler: &str) -> Result<(String, Vec<u8>), String> {
+    let tags = vec![
+        Tag::new("Data-Protocol", "ao"),
+        Tag::new("Variant", Variant::DEFAULT.as_str()),
+        Tag::new("Type", "Process"),
+        Tag::new("Module", UNUSED_MODULE),
+        Tag::new("Scheduler", scheduler),
+        Tag::new("Name", "su-conformance"),
+    ];
+    signed_item(signer, vec![], tags).await
+}
+
+pub async fn message_item(
+    signer: &dyn Signer,
+    process_id: &str,
+    sequence: usize,
+) -> Result<(String, Vec<u8>), String> {
+    let target = base64_url::decode(process_id)
+        .map_err(|_| format!("{} is not a base64url id", process_id))?;
+    let tags = vec![
+        Tag::new("Data-Protocol", "ao"),
+        Tag::new("Variant", Variant::DEFAULT.as_str()),
+        Tag::new("Type", "Message"),
+        Tag::new("Action", "Conformance"),
+        Tag::new("Sequence", &sequence.to_string()),
+    ];
+    signed_item(signer, target, tags).await
+}
+
+fn field<'a>(value: &'a Value, name: &str) -> Result<&'a Value, String> {
+    value
+        .get(name)
+        .filter(|field| !field.is_null())
+        .ok_or(format!("{} is missing", name))
+}
+
+fn string_field<'a>(value: &'a Value, name: &str) -> Result<&'a str, String> {
+    field(value, name)?
+        .as_str()
+        .ok_or(format!("{} is not a string", name))
+}
+
+fn number_field(value: &Value, name: &str) -> Result<i64, String> {
+    field(value, name)?
+        .as_i64()
+        .ok_or(format!("{} is not a number", name))
+}
+
+fn is_id(value: &str) -> bool {
+    value.len() == 43
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+}
+
+// the value of a tag in a {"name", "value"} list
+fn tag_value<'a>(item: &'a Value, name: &str) -> Result<Option<&'a str>, String> {
+    let tags = field(item, "tags")?
+        .as_array()
+        .ok_or("tags is not a list".to_string())?;
+    Ok(tags
+        .iter()
+        .find(|tag| tag.get("name").and_then(Value::as_str) == Some(name))
+        .and_then(|tag| tag.get("value").and_then(Value::as_str)))
+}
+
+pub struct TargetInfo {
+    pub address: String,
+    pub mode: String,
+}
+
+/*
+  GET /info, the signature has to verify over the exact
+  info string with the public key the address is the
+  sha256 of
+*/
+pub fn check_info(body: &Value) -> Result<TargetInfo, String> {
+    let signed = string_field(body, "info")?;
+    let info: Value =
+        serde_json::from_str(signed).map_err(|e| format!("info is not json: {}", e))?;
+    let address = string_field(&info, "address")?;
+    let mode = string_field(&info, "mode")?;
+    if mode != "su" && mode != "router" {
+        return Err(format!("mode is {}, expected su or router", mode));
+    }
+    let protocol = field(&info, "protocol")?;
+    if string_field(protocol, "data_protocol")? != "ao" {
+        return Err("protocol.data_protocol is not ao".to_string());
+    }
+    number_field(&info, "timestamp")?;
+
+    let public_key = base64_url::decode(string_field(body, "public_key")?)
+        .map_err(|_| "public_key is not base64url".to_string())?;
+    let signature = base64_url::decode(string_field(body, "signature")?)
+        .map_err(|_| "signature is not base64url".to_string())?;
+    if base64_url::encode(&hash(&public_key)) != address {
+        return Err(format!("address {} is not the public key's", address));
+    }
+    let mut signer = DataItem::new(vec![], vec![], vec![], public_key).map_err(byte_error)?;
+    signer.signature = signature;
+    signer
+        .verify_message(signed.as_bytes())
+        .map_err(|e| format!("info signature: {}", byte_error(e)))?;
+
+    Ok(TargetInfo {
+        address: address.to_string(),
+        mode: mode.to_string(),
+    })
+}
+
+// GET /timestamp, the block height zero padded to 12 digits
+pub fn check_timestamp(body: &Value) -> Result<String, String> {
+    let timestamp = number_field(body, "timestamp")?;
+    let height = string_field(body, "block_height")?;
+    if height.len() != 12 || !height.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!("block_height {} is not 12 digits", height));
+    }
+    Ok(format!("timestamp {}, block height {}", timestamp, height))
+}
+
+// the answer to POST /, the id of the item written
+pub fn check_write(body: &Value, id: &str) -> Result<i64, String> {
+    if string_field(body, "id")? != id {
+        return Err(format!("answered id {}, sent {}", body["id"], id));
+    }
+    number_field(body, "timestamp")
+}
+
+// returns the nonce of an assignment of process_id
+pub fn check_assignment(
+    assignment: &Value,
+    process_id: &str,
+    message_id: Option<&str>,
+) -> Result<i64, String> {
+    for name in ASSIGNMENT_TAGS {
+        if tag_value(assignment, name)?.is_none() {
+            return Err(format!("assignment has no {} tag", name));
+        }
+    }
+    let tag = |name| tag_value(assignment, name).map(|value| value.unwrap_or_default());
+    if tag("Process")? != process_id {
+        return Err(format!("assignment is for process {}", tag("Process")?));
+    }
+    if let Some(message_id) = message_id {
+        if tag("Message")? != message_id {
+            return Err(format!("assignment is for message {}", tag("Message")?));
+        }
+    }
+    for name in ["Epoch", "Nonce", "Timestamp"] {
+        if tag(name)?.parse::<i64>().is_err() {
+            return Err(format!(
+                "assignment {} {} is not a number",
+                name,
+                tag(name)?
+            ));
+        }
+    }
+    if !tag("Block-Height")?.chars().all(|c| c.is_ascii_digit()) {
+        return Err(format!(
+            "assignment Block-Height {} is not a number",
+            tag("Block-Height")?
+        ));
+    }
+    if tag("Hash-Chain")?.is_empty() {
+        return Err("assignment Hash-Chain is empty".to_string());
+    }
+    string_field(assignment, "signature")?;
+    Ok(tag("Nonce")?.parse().unwrap_or_default())
+}
+
+// GET /processes/{process_id}
+pub fn check_process(body: &Value, process_id: &str) -> Result<String, String> {
+    let process = field(body, "process")?;
+    if string_field(process, "process_id")? != process_id {
+        return Err(format!("process_id is {}", process["process_id"]));
+    }
+    let owner = string_field(field(process, "owner")?, "address")?;
+    if !is_id(owner) {
+        return Err(format!("owner address {} is not an address", owner));
+    }
+    for name in ["Type", "Module", "Scheduler"] {
+        if tag_value(process, name)?.is_none() {
+            return Err(format!("process has no {} tag", name));
+        }
+    }
+    // processes spawned before assignments existed have none
+    match body.get("assignment").filter(|a| !a.is_null()) {
+        Some(assignment) => {
+            let nonce = check_assignment(assignment, process_id, None)?;
+            Ok(format!("owned by {}, assigned nonce {}", owner, nonce))
+        }
+        None => Ok(format!("owned by {}, without an assignment", owner)),
+    }
+}
+
+// GET /{message_id}, returns its nonce
+pub fn check_message(body: &Value, process_id: &str, message_id: &str) -> Result<i64, String> {
+    let message = field(body, "message")?;
+    if string_field(message, "id")? != message_id {
+        return Err(format!("message id is {}", message["id"]));
+    }
+    if string_field(message, "target")? != process_id {
+        return Err(format!("message target is {}", message["target"]));
+    }
+    check_assignment(field(body, "assignment")?, process_id, Some(message_id))
+}
+
+// one page of GET /{process_id}
+pub struct MessagePage {
+    pub ids: Vec<String>,
+    pub nonces: Vec<i64>,
+    pub last_cursor: Option<String>,
+    pub has_next_page: bool,
+}
+
+pub fn check_page(body: &Value, process_id: &str) -> Result<MessagePage, String> {
+    let has_next_page = field(field(body, "page_info")?, "has_next_page")?
+        .as_bool()
+        .ok_or("page_info.has_next_page is not a boolean".to_string())?;
+    let edges = field(body, "edges")?
+        .as_array()
+        .ok_or("edges is not a list".to_string())?;
+
+    let mut page = MessagePage {
+        ids: vec![],
+        nonces: vec![],
+        last_cursor: None,
+        has_next_page,
+    };
+    for (index, edge) in edges.iter().enumerate() {
+        let at = |e: String| format!("edge {}: {}", index, e);
+        let cursor = string_field(edge, "cursor").map_err(at)?;
+        if cursor.is_empty() {
+            return Err(at("cursor is empty".to_string()));
+        }
+        let node = field(edge, "node").map_err(at)?;
+        let assignment = field(node, "assignment").map_err(at)?;
+        let id = match node.get("message").filter(|m| !m.is_null()) {
+            Some(message) => string_field(message, "id").map_err(at)?.to_string(),
+            None => tag_value(assignment, "Message")
+                .map_err(at)?
+                .unwrap_or(process_id)
+                .to_string(),
+        };
+        page.nonces
+            .push(check_assignment(assignment, process_id, None).map_err(at)?);
+        page.ids.push(id);
+        page.last_cursor = Some(cursor.to_string());
+    }
+    Ok(page)
+}
+
+// nonces of a listing have to go up one at a time
+pub fn check_nonces(nonces: &[i64]) -> Result<(), String> {
+    match nonces.windows(2).find(|pair| pair[1] != pair[0] + 1) {
+        Some(pair) => Err(format!("nonce {} follows {}", pair[1], pair[0])),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const PROCESS: &str = "V4iZaXaryQ3eeCDB_2ZS4WmET6DTm35QukI6dNEhQPm";
+    const MESSAGE: &str = "FRlw0hbk1-nlAbJ2DbMmwqGV8u6rWgrFYJWjm1mBMjk";
+
+    fn assignment(nonce: i64, message: &str) -> Value {
+        json!({
+            "id": "assignment",
+            "signature": "sig",
+            "tags": [
+                {"name": "Process", "value": PROCESS},
+                {"name": "Message", "value": message},
+                {"name": "Epoch", "value": "0"},
+                {"name": "Nonce", "value": nonce.to_string()},
+                {"name": "Hash-Chain", "value": "V-ICzfwIXwt1H3hWVwe3D8YQNT2lU99WMMZBr_Qa53P"},
+                {"name": "Block-Height", "value": "000001393008"},
+                {"name": "Timestamp", "value": "1711676638471"},
+            ]
+        })
+    }
+
+    #[test]
+    fn test_message_pages() {
+        let body = json!({
+            "page_info": {"has_next_page": true},
+            "edges": [
+                {"cursor": "c1", "node": {"message": {"id": MESSAGE}, "assignment": assignment(1, MESSAGE)}},
+                {"cursor": "c2", "node": {"message": null, "assignment": assignment(2, "other")}},
+            ]
+        });
+        let page = check_page(&body, PROCESS).unwrap();
+        assert_eq!(page.ids, vec![MESSAGE, "other"]);
+        assert_eq!(page.nonces, vec![1, 2]);
+        assert_eq!(page.last_cursor, Some("c2".to_string()));
+        assert!(page.has_next_page);
+        assert!(check_nonces(&page.nonces).is_ok());
+        assert_eq!(check_nonces(&[1, 2, 2]).unwrap_err(), "nonce 2 follows 2");
+
+        let message = json!({
+            "message": {"id": MESSAGE, "target": PROCESS},
+            "assignment": assignment(1, MESSAGE),
+        });
+        assert_eq!(check_message(&message, PROCESS, MESSAGE), Ok(1));
+        assert!(check_message(&message, PROCESS, "another").is_err());
+    }
+
+    #[test]
+    fn test_malformed_answers_are_reported() {
+        let mut unnumbered = assignment(1, MESSAGE);
+        unnumbered["tags"][3]["value"] = json!("one");
+        assert_eq!(
+            check_assignment(&unnumbered, PROCESS, None).unwrap_err(),
+            "assignment Nonce one is not a number"
+        );
+        let body = json!({"page_info": {}, "edges": []});
+        assert_eq!(
+            check_page(&body, PROCESS).err(),
+            Some("has_next_page is missing".to_string())
+        );
+        let body = json!({
+            "page_info": {"has_next_page": false},
+            "edges": [{"cursor": "", "node": {}}]
+        });
+        assert_eq!(
+            check_page(&body, PROCESS).err(),
+            Some("edge 0: cursor is empty".to_string())
+        );
+        assert!(check_timestamp(&json!({"timestamp": 1, "block_height": "000001393008"})).is_ok());
+        assert!(check_timestamp(&json!({"timestamp": 1, "block_height": "1393008"})).is_err());
+        assert_eq!(
+            check_write(&json!({"id": "a", "timestamp": 5}), "b").unwrap_err(),
+            "answered id \"a\", sent b"
+        );
+    }
+}
@@ -0,0 +1,257 @@
+use ring::hmac;
+
+use super::json::{JsonErrorType, Message, PaginatedMessages};
+
+/*
+  Opaque pagination cursors handed out on message
+  listings. A cursor carries the nonce, timestamp and
+  assignment id of the edge it was generated for and an
+  HMAC so clients cannot forge arbitrary positions.
+
+  The stores keep working with plain timestamps and
+  nonces, cursors are only encoded and decoded at the
+  edge in flows.rs so they stay valid no matter which
+  data store implementation is serving the request.
+*/
+
+const CURSOR_VERSION: &str = "v1";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cursor {
+    pub nonce: i32,
+    pub timestamp: i64,
+    pub id: String,
+}
+
+// which part of the cursor a query parameter refers to
+pub enum CursorField {
+    Timestamp,
+    Nonce,
+}
+
+impl Cursor {
+    pub fn from_message(message: &Message) -> Result<Self, JsonErrorType> {
+        Ok(Cursor {
+            nonce: message.nonce()?,
+            timestamp: message.timestamp()?,
+            id: message.assignment_id()?,
+        })
+    }
+
+    pub fn encode(&self, secret: &[u8]) -> String {
+        let payload = format!(
+            "{}:{}:{}:{}",
+            CURSOR_VERSION, self.nonce, self.timestamp, self.id
+        );
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        let signature = hmac::sign(&key, payload.as_bytes());
+        format!(
+            "{}.{}",
+            base64_url::encode(&payload),
+            base64_url::encode(signature.as_ref())
+        )
+    }
+
+    pub fn decode(cursor: &str, secret: &[u8]) -> Result<Self, String> {
+        let (payload_b64, signature_b64) = cursor
+            .split_once('.')
+            .ok_or("Invalid cursor".to_string())?;
+        let payload = base64_url::decode(payload_b64).map_err(|_| "Invalid cursor".to_string())?;
+        let signature =
+            base64_url::decode(signature_b64).map_err(|_| "Invalid cursor".to_string())?;
+
+        // constant time so the signature can't be guessed byte by byte
+        let key = hmac::Key::new(hmac::HMAC_SHA256, secret);
+        hmac::verify(&key, &payload, &signature)
+            .map_err(|_| "Invalid cursor signature".to_string())?;
+
+        let payload = String::from_utf8(payload).map_err(|_| "Invalid cursor".to_string())?;
+        let parts: Vec<&str> = payload.splitn(4, ':').collect();
+        if parts.len() != 4 || parts[0] != CURSOR_VERSION {
+            return Err("Unsupported cursor version".to_string());
+        }
+
+        Ok(Cursor {
+            nonce: parts[1].parse().map_err(|_| "Invalid cursor".to_string())?,
+            timestamp: parts[2].parse().map_err(|_| "Invalid cursor".to_string())?,
+            id: parts[3].to_string(),
+        })
+    }
+}
+
+/*
+  Replace the raw cursors the store generated with
+  signed ones before the page goes back to a client
+*/
+pub fn sign_page(page: &mut PaginatedMessages, secret: &[u8]) -> Result<(), JsonErrorType> {
+    for edge in page.edges.iter_mut() {
+        edge.cursor = Cursor::from_message(&edge.node)?.encode(secret);
+    }
+    Ok(())
+}
+
+/*
+  Turn a from/from-nonce query parameter back into the
+  plain value the stores understand. Plain numbers are
+  the old cursor format and are only accepted when
+  allow_legacy is set, a from-nonce of -1 always is
+  because it just means start at the process.
+*/
+pub fn resolve(
+    param: &Option<String>,
+    field: CursorField,
+    secret: &[u8],
+    allow_legacy: bool,
+) -> Result<Option<String>, String> {
+    let value = match param {
+        Some(v) => v,
+        None => return Ok(None),
+    };
+
+    if value.parse::<i64>().is_ok() {
+        if allow_legacy || (matches!(field, CursorField::Nonce) && value == "-1") {
+            return Ok(Some(value.clone()));
+        }
+        return Err("Numeric cursors are not accepted, use the cursor from a previous page".to_string());
+    }
+
+    let cursor = Cursor::decode(value, secret)?;
+    Ok(Some(match field {
+        CursorField::Timestamp => cursor.timestamp.to_string(),
+        CursorField::Nonce => cursor.nonce.to_string(),
+    }))
+}
+
+/*
+  Turn a to/to-nonce query parameter into a plain value.
+  An upper bound can't be used to skip ahead, so plain
+  numbers are always accepted, a cursor ends the range
+  at the edge it was handed out for.
+*/
+pub fn resolve_bound(
+    param: &Option<String>,
+    field: CursorField,
+    secret: &[u8],
+) -> Result<Option<String>, String> {
+    match param {
+        Some(value) if value.parse::<i64>().is_ok() => Ok(Some(value.clone())),
+        _ => resolve(param, field, secret, false),
+    }
+}
+
+/*
+  The next of a listing in process id order, a cursor
+  holding only the id of the last process on the page
+*/
+pub fn encode_after(id: &str, secret: &[u8]) -> String {
+    Cursor {
+        nonce: 0,
+        timestamp: 0,
+        id: id.to_string(),
+    }
+    .encode(secret)
+}
+
+/*
+  Turn an after query parameter back into the process id
+  the stores page from, the empty one when it is not set.
+  Plain process ids are the old format and are only
+  accepted when allow_legacy is set, a signed cursor
+  always has a '.' which an id never does.
+*/
+pub fn resolve_after(
+    param: &Option<String>,
+    secret: &[u8],
+    allow_legacy: bool,
+) -> Result<String, String> {
+    match param {
+        None => Ok(String::new()),
+        Some(value) if value.is_empty() => Ok(String::new()),
+        Some(value) if !value.contains('.') => match allow_legacy {
+            true => Ok(value.clone()),
+            false => Err(
+                "Process ids are not accepted as after, use the next of a previous page"
+                    .to_string(),
+            ),
+        },
+        Some(value) => Ok(Cursor::decode(value, secret)?.id),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cursor() -> Cursor {
+        Cursor {
+            nonce: 42,
+            timestamp: 1712345678901,
+            id: "Nq0dL6a8Dk5cQPR4f2aGzTGJ9t3sYSxkK1n0qQ8wbHw".to_string(),
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let encoded = cursor().encode(b"secret");
+        assert_eq!(Cursor::decode(&encoded, b"secret").unwrap(), cursor());
+    }
+
+    #[test]
+    fn test_rejects_other_secret() {
+        let encoded = cursor().encode(b"secret");
+        assert!(Cursor::decode(&encoded, b"other").is_err());
+    }
+
+    #[test]
+    fn test_rejects_tampered_payload() {
+        let encoded = cursor().encode(b"secret");
+        let signature = encoded.split_once('.').unwrap().1;
+        let forged = format!(
+            "{}.{}",
+            base64_url::encode("v1:0:0:Nq0dL6a8Dk5cQPR4f2aGzTGJ9t3sYSxkK1n0qQ8wbHw"),
+            signature
+        );
+        assert!(Cursor::decode(&forged, b"secret").is_err());
+    }
+
+    #[test]
+    fn test_resolve() {
+        let encoded = Some(cursor().encode(b"secret"));
+        assert_eq!(
+            resolve(&encoded, CursorField::Nonce, b"secret", false).unwrap(),
+            Some("42".to_string())
+        );
+        assert_eq!(
+            resolve(&encoded, CursorField::Timestamp, b"secret", false).unwrap(),
+            Some("1712345678901".to_string())
+        );
+        assert!(resolve(&Some("100".to_string()), CursorField::Timestamp, b"secret", false).is_err());
+        assert!(resolve(&Some("100".to_string()), CursorField::Timestamp, b"secret", true).is_ok());
+        assert!(resolve(&Some("-1".to_string()), CursorField::Nonce, b"secret", false).is_ok());
+
+        assert_eq!(
+            resolve_bound(&encoded, CursorField::Nonce, b"secret").unwrap(),
+            Some("42".to_string())
+        );
+        assert_eq!(
+            resolve_bound(&Some("100".to_string()), CursorField::Timestamp, b"secret").unwrap(),
+            Some("100".to_string())
+        );
+        assert!(resolve_bound(&Some("x".to_string()), CursorField::Nonce, b"secret").is_err());
+    }
+
+    #[test]
+    fn test_resolve_after() {
+        let id = "Nq0dL6a8Dk5cQPR4f2aGzTGJ9t3sYSxkK1n0qQ8wbHw".to_string();
+        let next = Some(encode_after(&id, b"secret"));
+        assert_eq!(resolve_after(&next, b"secret", false).unwrap(), id);
+        assert!(resolve_after(&next, b"other", false).is_err());
+
+        assert_eq!(resolve_after(&None, b"secret", false).unwrap(), "");
+        assert!(resolve_after(&Some(id.clone()), b"secret", false).is_err());
+        assert_eq!(
+            resolve_after(&Some(id.clone()), b"secret", true).unwrap(),
+            id
+        );
+    }
+}
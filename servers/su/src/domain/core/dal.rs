@@ -1,10 +1,21 @@
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
 use async_trait::async_trait;
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
 
+pub use super::audit::{AdminChange, AuditEntry};
 pub use super::bytes::DataItem;
-pub use super::json::{JsonErrorType, Message, PaginatedMessages, Process};
-pub use super::router::{ProcessScheduler, Scheduler};
+pub use super::ip_access::IpAccess;
+pub use super::json::{
+    AssignmentEvent, ChainSnapshot, ExportItem, JsonErrorType, Message, PaginatedMessages, Process,
+    ProcessStats,
+};
+pub use super::merkle::InclusionProof;
+pub use super::router::{Assignment, ProcessScheduler, Scheduler, SpawnEvent, SpawnQuery};
 pub use super::tags::Tag;
+pub use super::trace::Span;
 
 /*
 Interfaces for core dependencies. Implement these traits
@@ -36,9 +47,9 @@ pub struct GatewayTx {
 pub trait Gateway: Send + Sync {
     async fn check_head(&self, tx_id: String) -> Result<bool, String>;
     async fn network_info(&self) -> Result<NetworkInfo, String>;
-    async fn status(&self, tx_id: &String) -> Result<TxStatus, String>;
-    async fn gql_tx(&self, tx_id: &String) -> Result<GatewayTx, String>;
-    async fn raw(&self, tx_id: &String) -> Result<Vec<u8>, String>;
+    async fn status(&self, tx_id: &str) -> Result<TxStatus, String>;
+    async fn gql_tx(&self, tx_id: &str) -> Result<GatewayTx, String>;
+    async fn raw(&self, tx_id: &str) -> Result<Vec<u8>, String>;
 }
 
 pub trait Wallet: Send + Sync {
@@ -52,20 +63,134 @@ pub trait Signer: Send + Sync {
     fn get_public_key(&self) -> Vec<u8>;
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+/*
+  Context attached to a structured log event, only the
+  fields that are set are emitted
+*/
+#[derive(Debug, Clone, Default)]
+pub struct LogFields {
+    pub process_id: Option<String>,
+    pub scheduler_url: Option<String>,
+    pub latency_ms: Option<u128>,
+}
+
+impl LogFields {
+    pub fn process(process_id: &str) -> Self {
+        LogFields {
+            process_id: Some(process_id.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn scheduler(scheduler_url: &str) -> Self {
+        LogFields {
+            scheduler_url: Some(scheduler_url.to_string()),
+            ..Default::default()
+        }
+    }
+
+    pub fn latency(mut self, latency_ms: u128) -> Self {
+        self.latency_ms = Some(latency_ms);
+        self
+    }
+
+    // key=value pairs for plain text logs
+    pub fn to_text(&self) -> String {
+        let mut pairs = vec![];
+        if let Some(process_id) = &self.process_id {
+            pairs.push(format!("process_id={}", process_id));
+        }
+        if let Some(scheduler_url) = &self.scheduler_url {
+            pairs.push(format!("scheduler_url={}", scheduler_url));
+        }
+        if let Some(latency_ms) = self.latency_ms {
+            pairs.push(format!("latency_ms={}", latency_ms));
+        }
+        pairs.join(" ")
+    }
+}
+
 pub trait Log: Send + Sync {
     fn log(&self, message: String);
     fn error(&self, message: String);
+
+    // picks up a new log filter after a configuration reload
+    fn reload(&self) {}
+
+    // writes out anything buffered, before the process exits
+    fn flush(&self) {}
+
+    /*
+      A leveled event from a named module with context
+      fields, loggers without structured output fall back
+      to log and error with the fields appended
+    */
+    fn event(&self, level: LogLevel, module: &str, message: String, fields: LogFields) {
+        let line = format!("{}: {} {}", module, message, fields.to_text());
+        match level {
+            LogLevel::Error => self.error(line.trim_end().to_string()),
+            _ => self.log(line.trim_end().to_string()),
+        }
+    }
 }
 
-pub trait ScheduleProvider {
+pub trait ScheduleProvider: Sync {
     fn epoch(&self) -> String;
     fn nonce(&self) -> String;
     fn timestamp(&self) -> String;
     fn hash_chain(&self) -> String;
 }
 
+/*
+  What the server runs as, a scheduler unit that holds
+  processes or a router in front of several of them
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    Su,
+    Router,
+}
+
+impl Mode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Su => "su",
+            Mode::Router => "router",
+        }
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "su" => Ok(Mode::Su),
+            "router" => Ok(Mode::Router),
+            other => Err(format!(
+                "Invalid value for MODE: {}, expected su or router",
+                other
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
 pub trait Config: Send + Sync {
-    fn mode(&self) -> String;
+    fn mode(&self) -> Mode;
     fn scheduler_list_path(&self) -> String;
     fn enable_process_assignment(&self) -> bool;
     fn enable_deep_hash_checks(&self) -> bool;
@@ -77,6 +202,64 @@ pub trait Config: Send + Sync {
     fn enable_router_check(&self) -> bool;
     fn router_url(&self) -> String;
     fn assignment(&self) -> String;
+    fn cursor_secret(&self) -> String;
+    fn allow_legacy_cursors(&self) -> bool;
+    fn enable_router_decision_header(&self) -> bool;
+    fn max_read_memory(&self) -> usize;
+    fn process_scheduler_cleanup_interval(&self) -> u64;
+    fn process_scheduler_cleanup_policy(&self) -> String;
+    fn admin_token(&self) -> String;
+    fn large_process_threshold(&self) -> usize;
+    fn scheduler_health_interval(&self) -> u64;
+    fn scheduler_unhealthy_after(&self) -> u32;
+    fn scheduler_list_reload_interval(&self) -> u64;
+    fn routing_strategy(&self) -> String;
+    fn drain_interval(&self) -> u64;
+    fn drain_batch_size(&self) -> i64;
+    fn drain_copy_token(&self) -> String;
+    fn rebalance_interval(&self) -> u64;
+    fn rebalance_max_moves(&self) -> usize;
+    fn rebalance_threshold(&self) -> f64;
+    fn rebalance_idle_secs(&self) -> u64;
+    fn rebalance_dry_run(&self) -> bool;
+    fn assignment_tags(&self) -> String;
+    fn spawn_failover(&self) -> bool;
+    fn router_strict_messages(&self) -> bool;
+    fn router_owner_sticky(&self) -> bool;
+    fn router_region_header(&self) -> String;
+    fn router_region_map(&self) -> String;
+    fn verify_process_genesis(&self) -> bool;
+    fn scheduler_location_url(&self) -> String;
+    fn scheduler_location_ttl(&self) -> u64;
+    fn chain_snapshot_interval(&self) -> i32;
+    fn batch_max_items(&self) -> usize;
+    fn max_data_item_size(&self) -> usize;
+    fn trust_forwarded_for(&self) -> bool;
+    fn ip_access(&self) -> Arc<IpAccess>;
+    fn router_degraded_reads(&self) -> bool;
+    fn router_store_probe_interval(&self) -> u64;
+    fn deferred_messages(&self) -> bool;
+    fn deferred_max_delay(&self) -> u64;
+    fn wallet_spawns_per_hour(&self) -> u32;
+    fn wallet_messages_per_minute(&self) -> u32;
+    fn anonymous_reads_per_minute(&self) -> u32;
+    fn api_key_reads_per_minute(&self) -> u32;
+    fn sign_read_responses(&self) -> bool;
+    fn signing_check_interval(&self) -> u64;
+    fn signing_check_read_only(&self) -> bool;
+    fn write_verify_percent(&self) -> f64;
+
+    /*
+      Reads the configuration again and swaps it in once
+      check accepts it, returning the variables that
+      changed. Only a live configuration can be reloaded.
+    */
+    fn reload(
+        &self,
+        _check: &dyn Fn(&dyn Config) -> Result<(), String>,
+    ) -> Result<Vec<String>, String> {
+        Err("This configuration can't be reloaded".to_string())
+    }
 }
 
 #[derive(Debug)]
@@ -92,6 +275,304 @@ impl From<UploaderErrorType> for String {
 
 pub trait Uploader: Send + Sync {
     fn upload(&self, tx: Vec<u8>) -> Result<(), UploaderErrorType>;
+    // uploads started that have neither succeeded nor given up
+    fn pending(&self) -> usize;
+}
+
+#[derive(Debug)]
+pub enum StreamerErrorType {
+    StreamError(String),
+}
+
+impl From<StreamerErrorType> for String {
+    fn from(error: StreamerErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+/*
+  Publishes assignments to an external stream, publish
+  returns once the event is stored durably, delivery to
+  the broker happens in the background.
+*/
+#[async_trait]
+pub trait Streamer: Send + Sync {
+    async fn publish(&self, event: &AssignmentEvent) -> Result<(), StreamerErrorType>;
+    // events published that the broker has not acknowledged yet
+    fn pending(&self) -> usize {
+        0
+    }
+}
+
+#[derive(Debug)]
+pub enum RawArchiveErrorType {
+    ArchiveError(String),
+    InvalidId(String),
+}
+
+impl From<RawArchiveErrorType> for String {
+    fn from(error: RawArchiveErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+/*
+  Keeps the exact bytes of accepted data items, as they
+  were posted, for longer than or separately from the
+  parsed records so disputes can be settled later.
+*/
+pub trait RawArchive: Send + Sync {
+    fn retain(&self, id: &str, raw: &[u8]) -> Result<(), RawArchiveErrorType>;
+    fn read(&self, id: &str) -> Result<Option<Vec<u8>>, RawArchiveErrorType>;
+}
+
+#[derive(Debug)]
+pub enum DenylistErrorType {
+    DenylistError(String),
+}
+
+impl From<DenylistErrorType> for String {
+    fn from(error: DenylistErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct DenylistEntries {
+    #[serde(default)]
+    pub processes: Vec<String>,
+    #[serde(default)]
+    pub owners: Vec<String>,
+}
+
+/*
+  Process ids and owner addresses refused on the write
+  path. An empty owner never matches, assignments only
+  carry the process id. The local entries are the ones
+  managed on this instance, the remote feed is not
+  part of them.
+*/
+pub trait Denylist: Send + Sync {
+    fn is_denied(&self, process_id: &str, owner: &str) -> bool;
+    fn local_entries(&self) -> Result<DenylistEntries, DenylistErrorType>;
+    fn add_local(&self, entries: &DenylistEntries) -> Result<DenylistEntries, DenylistErrorType>;
+    fn remove_local(&self, entries: &DenylistEntries)
+        -> Result<DenylistEntries, DenylistErrorType>;
+}
+
+/*
+  Processes migrated off this su, each with the url of
+  the scheduler it moved to. Writes to them are refused,
+  their history is still served. clear returns whether
+  the process was marked.
+*/
+pub trait ReadOnlyProcesses: Send + Sync {
+    fn moved_to(&self, process_id: &str) -> Option<String>;
+    fn mark(&self, process_id: &str, moved_to: &str) -> Result<(), String>;
+    fn clear(&self, process_id: &str) -> Result<bool, String>;
+}
+
+#[derive(Debug)]
+pub enum ApiKeyErrorType {
+    ApiKeyError(String),
+    NotFound(String),
+}
+
+impl From<ApiKeyErrorType> for String {
+    fn from(error: ApiKeyErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+/*
+  A client's api key, the key itself is only shown once
+  when it is issued, id is the hash it is kept under
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiKey {
+    pub id: String,
+    pub name: String,
+    // overrides API_KEY_READS_PER_MINUTE for this key
+    pub reads_per_minute: Option<u32>,
+    pub created: i64,
+}
+
+pub trait ApiKeys: Send + Sync {
+    fn validate(&self, key: &str) -> Option<ApiKey>;
+    fn list(&self) -> Result<Vec<ApiKey>, ApiKeyErrorType>;
+    fn issue(
+        &self,
+        name: &str,
+        reads_per_minute: Option<u32>,
+    ) -> Result<(String, ApiKey), ApiKeyErrorType>;
+    fn revoke(&self, id: &str) -> Result<ApiKey, ApiKeyErrorType>;
+}
+
+#[derive(Debug)]
+pub enum SpawnAuditErrorType {
+    AuditError(String),
+}
+
+impl From<SpawnAuditErrorType> for String {
+    fn from(error: SpawnAuditErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+/*
+  Router mode only, keeps every spawn the router placed
+  so a change to the scheduler list or strategy can be
+  replayed against real traffic before it is deployed.
+  read returns up to limit of the spawns matching the
+  query, newest first.
+*/
+pub trait SpawnAudit: Send + Sync {
+    fn record(&self, event: &SpawnEvent) -> Result<(), SpawnAuditErrorType>;
+    fn read(&self, query: &SpawnQuery) -> Result<Vec<SpawnEvent>, SpawnAuditErrorType>;
+}
+
+#[derive(Debug)]
+pub enum AdminAuditErrorType {
+    AuditError(String),
+}
+
+impl From<AdminAuditErrorType> for String {
+    fn from(error: AdminAuditErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+/*
+  Append only log of the changes made through the admin
+  api. append seals a change onto the end of the chain,
+  one append at a time, and returns the entry written.
+  read returns up to limit entries from seq from on.
+*/
+pub trait AdminAudit: Send + Sync {
+    fn append(&self, change: AdminChange) -> Result<AuditEntry, AdminAuditErrorType>;
+    fn read(&self, from: i64, limit: i64) -> Result<Vec<AuditEntry>, AdminAuditErrorType>;
+}
+
+#[derive(Debug)]
+pub enum DeferredErrorType {
+    DeferredError(String),
+}
+
+impl From<DeferredErrorType> for String {
+    fn from(error: DeferredErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+// a message held back until its Schedule-At time
+pub struct DeferredItem {
+    pub id: String,
+    pub schedule_at: i64,
+    pub raw: Vec<u8>,
+}
+
+/*
+  Messages accepted with a Schedule-At in the future and
+  not yet assigned. due returns up to limit items whose
+  time is at or before now, earliest first, and they stay
+  queued until removed. failed counts a release attempt
+  that didn't go through and returns the count so far.
+*/
+pub trait DeferredQueue: Send + Sync {
+    fn defer(&self, id: &str, schedule_at: i64, raw: &[u8]) -> Result<(), DeferredErrorType>;
+    fn due(&self, now: i64, limit: usize) -> Result<Vec<DeferredItem>, DeferredErrorType>;
+    fn remove(&self, id: &str) -> Result<(), DeferredErrorType>;
+    fn failed(&self, id: &str) -> u32;
+    fn pending(&self) -> usize;
+}
+
+#[derive(Debug)]
+pub enum ReplicatorErrorType {
+    ReplicateError(String),
+}
+
+impl From<ReplicatorErrorType> for String {
+    fn from(error: ReplicatorErrorType) -> Self {
+        format!("{:?}", error)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ReplicaKind {
+    Process,
+    Message,
+}
+
+impl ReplicaKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ReplicaKind::Process => "process",
+            ReplicaKind::Message => "message",
+        }
+    }
+
+    pub fn parse(kind: &str) -> Result<Self, ReplicatorErrorType> {
+        match kind {
+            "process" => Ok(ReplicaKind::Process),
+            "message" => Ok(ReplicaKind::Message),
+            _ => Err(ReplicatorErrorType::ReplicateError(format!(
+                "Invalid replica type: {}",
+                kind
+            ))),
+        }
+    }
+}
+
+/*
+  A committed process or message as the su stored it,
+  binary is the bundle saved to the data store
+*/
+#[derive(Debug, Clone)]
+pub struct Replica {
+    pub kind: ReplicaKind,
+    pub binary: Vec<u8>,
+    pub deep_hash: Option<String>,
+}
+
+/*
+  Forwards committed items to a standby su. Depending on
+  configuration replicate either waits for the standby to
+  store the item or only queues it for delivery.
+*/
+#[async_trait]
+pub trait Replicator: Send + Sync {
+    async fn replicate(&self, replica: Replica) -> Result<(), ReplicatorErrorType>;
+}
+
+/*
+  String entries shared by the router replicas behind one
+  load balancer, each expiring on its own ttl seconds
+  after it was written. incr counts up the integer at a
+  key and returns the new count.
+*/
+pub trait SharedCache: Send + Sync {
+    fn get(&self, key: &str) -> Result<Option<String>, String>;
+    fn set(&self, key: &str, value: &str, ttl: u64) -> Result<(), String>;
+    fn delete(&self, key: &str) -> Result<(), String>;
+    fn incr(&self, key: &str, ttl: u64) -> Result<i64, String>;
+}
+
+/*
+  Where finished spans go, export is called on the
+  request path so it must not block, spans it can't
+  keep up with are dropped
+*/
+pub trait SpanExporter: Send + Sync {
+    fn export(&self, span: Span);
+}
+
+/*
+  Where su export-parquet puts the files it writes, put
+  returns the location the file ended up at
+*/
+#[async_trait]
+pub trait ExportSink: Send + Sync {
+    async fn put(&self, name: &str, body: Vec<u8>) -> Result<String, String>;
 }
 
 #[derive(Debug)]
@@ -132,12 +613,18 @@ impl From<std::string::FromUtf8Error> for StoreErrorType {
 pub trait DataStore: Send + Sync {
     fn save_process(&self, process: &Process, bundle_in: &[u8]) -> Result<String, StoreErrorType>;
     async fn get_process(&self, process_id_in: &str) -> Result<Process, StoreErrorType>;
+    async fn get_process_bundle(&self, process_id_in: &str) -> Result<Vec<u8>, StoreErrorType>;
     async fn save_message(
         &self,
         message: &Message,
         bundle_in: &[u8],
         deep_hash: Option<&String>,
     ) -> Result<String, StoreErrorType>;
+    // the messages of a batch together, all of them are saved or none are
+    async fn save_messages(
+        &self,
+        messages: &[(&Message, &[u8], Option<&String>)],
+    ) -> Result<(), StoreErrorType>;
     async fn get_messages(
         &self,
         process: &Process,
@@ -153,28 +640,75 @@ pub trait DataStore: Send + Sync {
         from: &Option<String>,
         limit: &Option<i32>,
     ) -> Result<(Vec<(String, Vec<u8>)>, bool), StoreErrorType>;
+    // like get_messages by nonce, only the messages carrying every one of tags
+    async fn get_tagged_messages(
+        &self,
+        process: &Process,
+        tags: &[Tag],
+        limit: &Option<i32>,
+        from_nonce: &Option<String>,
+        to_nonce: &Option<String>,
+    ) -> Result<PaginatedMessages, StoreErrorType>;
     fn get_message(&self, message_id_in: &str) -> Result<Message, StoreErrorType>;
     async fn get_latest_message(
         &self,
         process_id_in: &str,
     ) -> Result<Option<Message>, StoreErrorType>;
-    fn check_existing_message(&self, message_id: &String) -> Result<(), StoreErrorType>;
+    fn check_existing_message(&self, message_id: &str) -> Result<(), StoreErrorType>;
     async fn check_existing_deep_hash(
         &self,
-        process_id: &String,
-        deep_hash: &String,
+        process_id: &str,
+        deep_hash: &str,
     ) -> Result<(), StoreErrorType>;
-    async fn get_deephash_version(&self, process_id: &String) -> Result<String, StoreErrorType>;
+    async fn get_deephash_version(&self, process_id: &str) -> Result<String, StoreErrorType>;
     async fn save_deephash_version(
         &self,
-        process_id: &String,
-        version: &String,
+        process_id: &str,
+        version: &str,
     ) -> Result<(), StoreErrorType>;
-    async fn save_deephash(
+    async fn save_deephash(&self, process_id: &str, deep_hash: &str) -> Result<(), StoreErrorType>;
+    async fn get_process_stats(&self, process_id: &str) -> Result<ProcessStats, StoreErrorType>;
+    // the stats of those processes that have them, in one read
+    async fn get_processes_stats(
         &self,
-        process_id: &String,
-        deep_hash: &String,
-    ) -> Result<(), StoreErrorType>;
+        process_ids: &[String],
+    ) -> Result<Vec<ProcessStats>, StoreErrorType>;
+    async fn save_chain_snapshot(&self, snapshot: &ChainSnapshot) -> Result<(), StoreErrorType>;
+    // the latest snapshot, or the latest at or below max_nonce
+    async fn get_chain_snapshot(
+        &self,
+        process_id: &str,
+        max_nonce: Option<i32>,
+    ) -> Result<ChainSnapshot, StoreErrorType>;
+    async fn save_inclusion_proofs(&self, proofs: &[InclusionProof])
+        -> Result<(), StoreErrorType>;
+    async fn get_inclusion_proof(&self, item_id: &str) -> Result<InclusionProof, StoreErrorType>;
+    /*
+      Up to limit items of every process after the
+      (timestamp, id) position, processes only when they
+      have an assignment
+    */
+    async fn get_items_after(
+        &self,
+        after: (i64, &str),
+        limit: usize,
+    ) -> Result<Vec<ExportItem>, StoreErrorType>;
+    // ids of the processes stored here in order, after "" starts at the first
+    async fn get_process_ids_after(
+        &self,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<String>, StoreErrorType>;
+    /*
+      Like get_process_ids_after with the timestamp of each
+      spawn, only those spawned after spawned_after when set
+    */
+    async fn list_processes(
+        &self,
+        spawned_after: Option<i64>,
+        after: &str,
+        limit: usize,
+    ) -> Result<Vec<(String, i64)>, StoreErrorType>;
 }
 
 #[async_trait]
@@ -187,11 +721,103 @@ pub trait RouterDataStore: Send + Sync {
         &self,
         process_id_in: &str,
     ) -> Result<ProcessScheduler, StoreErrorType>;
+    // get_process_scheduler without the replicas, for a read that must see the latest move
+    fn get_process_scheduler_from_writer(
+        &self,
+        process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType>;
     fn save_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType>;
+    // every field but process_count, which only changes through the calls below
     fn update_scheduler(&self, scheduler: &Scheduler) -> Result<String, StoreErrorType>;
+    /*
+      Add to or take from a scheduler's process_count
+      relative to the value in the table, so a count
+      changed since the scheduler was read isn't lost.
+      A count never goes below 0.
+    */
+    fn increment_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType>;
+    fn decrement_process_count(
+        &self,
+        scheduler_row_id_in: &i32,
+        by: i32,
+    ) -> Result<(), StoreErrorType>;
     fn get_scheduler(&self, row_id_in: &i32) -> Result<Scheduler, StoreErrorType>;
-    fn get_scheduler_by_url(&self, url_in: &String) -> Result<Scheduler, StoreErrorType>;
+    fn get_scheduler_by_url(&self, url_in: &str) -> Result<Scheduler, StoreErrorType>;
     fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType>;
+    fn update_process_scheduler(
+        &self,
+        process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType>;
+    fn delete_process_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType>;
+    fn delete_scheduler(&self, row_id_in: &i32) -> Result<String, StoreErrorType>;
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType>;
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType>;
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType>;
+    // a scheduler's rows in row_id order, a page at a time after the after_row_id cursor
+    fn get_process_schedulers_for(
+        &self,
+        scheduler_row_id_in: &i32,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType>;
+    // every row in row_id order, a page at a time for su router export
+    fn get_process_schedulers_after(
+        &self,
+        after_row_id: &i32,
+        limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType>;
+    /*
+      Up to limit rows in process id order after the
+      after_process_id cursor, with when each was spawned
+      here. Either filter narrows the page, rows saved
+      before spawn times were recorded have none and never
+      match spawned_after.
+    */
+    fn list_processes(
+        &self,
+        scheduler_row_id_in: Option<i32>,
+        spawned_after: Option<i64>,
+        after_process_id: &str,
+        limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType>;
+    /*
+      Save the row of a new process and count it on its
+      scheduler in one transaction, so concurrent spawns
+      can't lose an increment. A process that already has
+      a row keeps it and is not counted again, it comes
+      back as a duplicate with the scheduler it is on.
+    */
+    fn assign_process(
+        &self,
+        process_id_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType>;
+    /*
+      Move a process from one scheduler to another, updating
+      its row and both process counts in one transaction.
+      NotFound when the process is no longer on from_row_id,
+      in which case nothing is changed.
+    */
+    fn move_process(
+        &self,
+        process_id_in: &str,
+        from_row_id: &i32,
+        to_row_id: &i32,
+    ) -> Result<(), StoreErrorType>;
+    /*
+      The scheduler of an owner's first process, for
+      ROUTER_OWNER_STICKY. NotFound when the owner has none.
+    */
+    fn get_owner_scheduler(&self, owner_in: &str) -> Result<i32, StoreErrorType>;
+    fn save_owner_scheduler(
+        &self,
+        owner_in: &str,
+        scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType>;
 }
 
 pub struct MockRouterDataStore;
@@ -212,6 +838,13 @@ impl RouterDataStore for MockRouterDataStore {
         unreachable!("get_process_scheduler is not implemented in MockRouterDataStore");
     }
 
+    fn get_process_scheduler_from_writer(
+        &self,
+        _process_id_in: &str,
+    ) -> Result<ProcessScheduler, StoreErrorType> {
+        unreachable!("get_process_scheduler_from_writer is not implemented in MockRouterDataStore");
+    }
+
     fn save_scheduler(&self, _scheduler: &Scheduler) -> Result<String, StoreErrorType> {
         unreachable!("save_scheduler is not implemented in MockRouterDataStore");
     }
@@ -220,17 +853,116 @@ impl RouterDataStore for MockRouterDataStore {
         unreachable!("update_scheduler is not implemented in MockRouterDataStore");
     }
 
+    fn increment_process_count(
+        &self,
+        _scheduler_row_id_in: &i32,
+        _by: i32,
+    ) -> Result<(), StoreErrorType> {
+        unreachable!("increment_process_count is not implemented in MockRouterDataStore");
+    }
+
+    fn decrement_process_count(
+        &self,
+        _scheduler_row_id_in: &i32,
+        _by: i32,
+    ) -> Result<(), StoreErrorType> {
+        unreachable!("decrement_process_count is not implemented in MockRouterDataStore");
+    }
+
     fn get_scheduler(&self, _row_id_in: &i32) -> Result<Scheduler, StoreErrorType> {
         unreachable!("get_scheduler is not implemented in MockRouterDataStore");
     }
 
-    fn get_scheduler_by_url(&self, _url_in: &String) -> Result<Scheduler, StoreErrorType> {
+    fn get_scheduler_by_url(&self, _url_in: &str) -> Result<Scheduler, StoreErrorType> {
         unreachable!("get_scheduler_by_url is not implemented in MockRouterDataStore");
     }
 
     fn get_all_schedulers(&self) -> Result<Vec<Scheduler>, StoreErrorType> {
         unreachable!("get_all_schedulers is not implemented in MockRouterDataStore");
     }
+
+    fn update_process_scheduler(
+        &self,
+        _process_scheduler: &ProcessScheduler,
+    ) -> Result<String, StoreErrorType> {
+        unreachable!("update_process_scheduler is not implemented in MockRouterDataStore");
+    }
+
+    fn delete_process_scheduler(&self, _row_id_in: &i32) -> Result<String, StoreErrorType> {
+        unreachable!("delete_process_scheduler is not implemented in MockRouterDataStore");
+    }
+
+    fn delete_scheduler(&self, _row_id_in: &i32) -> Result<String, StoreErrorType> {
+        unreachable!("delete_scheduler is not implemented in MockRouterDataStore");
+    }
+
+    fn get_orphaned_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        unreachable!("get_orphaned_process_schedulers is not implemented in MockRouterDataStore");
+    }
+
+    fn get_duplicate_process_schedulers(&self) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        unreachable!("get_duplicate_process_schedulers is not implemented in MockRouterDataStore");
+    }
+
+    fn get_process_scheduler_counts(&self) -> Result<Vec<(i32, i64)>, StoreErrorType> {
+        unreachable!("get_process_scheduler_counts is not implemented in MockRouterDataStore");
+    }
+
+    fn get_process_schedulers_for(
+        &self,
+        _scheduler_row_id_in: &i32,
+        _after_row_id: &i32,
+        _limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        unreachable!("get_process_schedulers_for is not implemented in MockRouterDataStore");
+    }
+
+    fn get_process_schedulers_after(
+        &self,
+        _after_row_id: &i32,
+        _limit: i64,
+    ) -> Result<Vec<ProcessScheduler>, StoreErrorType> {
+        unreachable!("get_process_schedulers_after is not implemented in MockRouterDataStore");
+    }
+
+    fn list_processes(
+        &self,
+        _scheduler_row_id_in: Option<i32>,
+        _spawned_after: Option<i64>,
+        _after_process_id: &str,
+        _limit: i64,
+    ) -> Result<Vec<(ProcessScheduler, Option<i64>)>, StoreErrorType> {
+        unreachable!("list_processes is not implemented in MockRouterDataStore");
+    }
+
+    fn assign_process(
+        &self,
+        _process_id_in: &str,
+        _scheduler_row_id_in: &i32,
+    ) -> Result<Assignment, StoreErrorType> {
+        unreachable!("assign_process is not implemented in MockRouterDataStore");
+    }
+
+    fn move_process(
+        &self,
+        _process_id_in: &str,
+        _from_row_id: &i32,
+        _to_row_id: &i32,
+    ) -> Result<(), StoreErrorType> {
+        unreachable!("move_process is not implemented in MockRouterDataStore");
+    }
+
+    fn get_owner_scheduler(&self, _owner_in: &str) -> Result<i32, StoreErrorType> {
+        unreachable!("get_owner_scheduler is not implemented in MockRouterDataStore");
+    }
+
+    fn save_owner_scheduler(
+        &self,
+        _owner_in: &str,
+        _scheduler_row_id_in: &i32,
+    ) -> Result<(), StoreErrorType> {
+        unreachable!("save_owner_scheduler is not implemented in MockRouterDataStore");
+    }
 }
 
 pub trait CoreMetrics: Send + Sync {
@@ -242,15 +974,76 @@ pub trait CoreMetrics: Send + Sync {
     fn write_assignment_observe(&self, duration: u128);
     fn acquire_write_lock_observe(&self, duration: u128);
     fn failed_message_save(&self);
+    fn process_scheduler_cleanup(&self, action: &str);
+    fn memory_observe(&self, rss: u64, in_flight: u64, shed_level: u8);
+    fn write_shed(&self);
+    fn redirect_served(&self, scheduler: &str, rule: &str);
+    fn item_written(&self, kind: &str, process_id: &str);
+    fn upload_failed(&self);
+    fn store_query_observe(&self, query: &str, duration: u128);
+    fn spawn_failover(&self, scheduler: &str);
+    fn duplicate_spawn(&self);
+    fn rate_limited(&self, kind: &str);
+    fn read_rate_limited(&self, tier: &str);
+    fn endpoint_queue_observe(&self, class: &str, depth: usize);
+    fn endpoint_rejected(&self, class: &str);
+    fn write_batch_observe(&self, size: usize, limit: usize, wait: u64, depth: usize);
+    fn rebalance_observe(&self, imbalance: f64);
+    fn rebalance_move(&self, outcome: &str);
+    fn signing_check_observe(&self, failing: bool);
+    fn write_verified(&self, outcome: &str);
+
+    /*
+      Writes the current values to the log before the
+      process exits, a scrape only sees them while the
+      server is up so the last changes would be lost
+    */
+    fn flush(&self, _logger: &dyn Log) {}
 }
 
 #[async_trait]
 pub trait ExtRouter: Send + Sync {
     async fn get_routed_assignment(&self, process_id: String) -> Result<String, ExtRouterErrorType>;
+    async fn probe_scheduler(&self, url: String) -> Result<(), ExtRouterErrorType>;
+    async fn export_process(
+        &self,
+        url: String,
+        process_id: String,
+        from: Option<String>,
+        token: String,
+    ) -> Result<String, ExtRouterErrorType>;
+    async fn push_replica(
+        &self,
+        url: String,
+        kind: ReplicaKind,
+        binary: Vec<u8>,
+        token: String,
+    ) -> Result<(), ExtRouterErrorType>;
+    async fn list_processes(
+        &self,
+        url: String,
+        after: Option<String>,
+        token: String,
+    ) -> Result<String, ExtRouterErrorType>;
+    // marks the process read only on the su at url, or clears it with None
+    async fn set_read_only(
+        &self,
+        url: String,
+        process_id: String,
+        moved_to: Option<String>,
+        token: String,
+    ) -> Result<(), ExtRouterErrorType>;
 }
 
+#[derive(Debug)]
 pub enum ExtRouterErrorType {
     NotFound(String),
     NetworkError(String),
     ConfigError(String)
+}
+
+impl From<ExtRouterErrorType> for String {
+    fn from(error: ExtRouterErrorType) -> Self {
+        format!("{:?}", error)
+    }
 }
\ No newline at end of file
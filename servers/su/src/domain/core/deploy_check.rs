@@ -0,0 +1,78 @@
+/*
+  The report of su check, one line per check in the
+  order they ran, each passing with what it found or
+  failing with why
+*/
+pub struct CheckReport {
+    checks: Vec<(String, Result<String, String>)>,
+}
+
+impl CheckReport {
+    pub fn new() -> Self {
+        CheckReport { checks: vec![] }
+    }
+
+    pub fn add(&mut self, name: &str, result: Result<String, String>) {
+        self.checks.push((name.to_string(), result));
+    }
+
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|(_, result)| result.is_ok())
+    }
+
+    pub fn render(&self) -> String {
+        let width = self
+            .checks
+            .iter()
+            .map(|(name, _)| name.len())
+            .max()
+            .unwrap_or(0);
+        let mut lines: Vec<String> = self
+            .checks
+            .iter()
+            .map(|(name, result)| match result {
+                Ok(found) => format!("ok    {:width$}  {}", name, found, width = width),
+                Err(e) => format!("FAIL  {:width$}  {}", name, e, width = width),
+            })
+            .collect();
+        let failed = self.checks.iter().filter(|(_, r)| r.is_err()).count();
+        lines.push(match failed {
+            0 => format!("{} checks passed", self.checks.len()),
+            _ => format!("{} of {} checks failed", failed, self.checks.len()),
+        });
+        lines.join("\n")
+    }
+}
+
+impl Default for CheckReport {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_report() {
+        let mut report = CheckReport::new();
+        report.add("configuration", Ok("mode su".to_string()));
+        report.add(
+            "gateway",
+            Ok("https://arweave.net answered 200".to_string()),
+        );
+        assert!(report.passed());
+        assert!(report.render().ends_with("2 checks passed"));
+
+        report.add("wallet", Err("failed to read wallet file".to_string()));
+        assert!(!report.passed());
+        assert_eq!(
+            report.render(),
+            "ok    configuration  mode su\n\
+             ok    gateway        https://arweave.net answered 200\n\
+             FAIL  wallet         failed to read wallet file\n\
+             1 of 3 checks failed"
+        );
+    }
+}
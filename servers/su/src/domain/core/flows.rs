@@ -1,20 +1,41 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use std::time::{SystemTime, SystemTimeError, UNIX_EPOCH};
 
+use bytes::Bytes;
 use dashmap::DashMap;
 use dotenv::dotenv;
+use futures::future::join_all;
+use futures::stream::{self, BoxStream, StreamExt};
+use rand::{thread_rng, Rng};
+use serde::Deserialize;
 use serde_json::json;
 use simd_json::to_string as simd_to_string;
-use tokio::sync::Mutex;
-
-use super::builder::Builder;
-use super::bytes::{DataBundle, DataItem};
-use super::json::{Message, Process};
+use tokio::sync::broadcast::error::RecvError;
+use tokio::sync::{Mutex, OwnedSemaphorePermit};
+use tokio::time::timeout;
+
+use super::builder::{parse_assignment_tags, Builder};
+use super::concurrency::{ConcurrencyLimits, EndpointClass};
+use super::cursor::{self, Cursor, CursorField};
+use super::bytes::{split_bundle, DataBundle, DataItem};
+use super::json::{hash, ChainSnapshot, Message, Process, ProcessStats};
+use super::memory::{InFlightWrite, MemoryGuard, ShedLevel};
+use super::owner_cache::OwnerCache;
+use super::rate_limit::RateLimits;
+use super::registration::Registration;
+use super::router::{self, RouteCache, RouterHealth, RouterRole, SharedHealth};
 use super::scheduler;
+use super::signing_check::{sign_canary, SigningHealth, SIGNING_UNHEALTHY};
+use super::subscriptions::Subscriptions;
+use super::trace::{self, SpanKind, Tracer};
+use super::variant::{check_message_variant, Variant};
+use super::verify_cache::VerifyCache;
 
+use super::audit;
 use super::dal::{
-    Config, CoreMetrics, DataStore, ExtRouter, ExtRouterErrorType, Gateway, Log, RouterDataStore, Signer, Uploader, Wallet
+    AdminAudit, AdminChange, ApiKeys, AssignmentEvent, Config, CoreMetrics, DataStore, DeferredQueue, Denylist, DenylistEntries, ExtRouter, ExtRouterErrorType, Gateway, InclusionProof, Log, LogFields, LogLevel, Mode, RawArchive, ReadOnlyProcesses, Replica, ReplicaKind, Replicator, RouterDataStore, SharedCache, Signer, SpawnAudit, StoreErrorType, Streamer, Tag, Uploader, Wallet
 };
 
 pub struct Deps {
@@ -28,6 +49,22 @@ pub struct Deps {
     pub uploader: Arc<dyn Uploader>,
     pub metrics: Arc<dyn CoreMetrics>,
     pub ext_router: Arc<dyn ExtRouter>,
+    pub streamer: Arc<dyn Streamer>,
+    pub denylist: Arc<dyn Denylist>,
+    pub read_only: Arc<dyn ReadOnlyProcesses>,
+    pub raw_archive: Arc<dyn RawArchive>,
+    pub replicator: Arc<dyn Replicator>,
+    pub memory: Arc<MemoryGuard>,
+    pub verify_cache: Arc<VerifyCache>,
+    pub owner_cache: Arc<OwnerCache>,
+    pub spawn_audit: Arc<dyn SpawnAudit>,
+    pub rate_limits: Arc<RateLimits>,
+    pub concurrency: Arc<ConcurrencyLimits>,
+    pub api_keys: Arc<dyn ApiKeys>,
+    pub subscriptions: Arc<Subscriptions>,
+    pub registration: Arc<Registration>,
+    pub admin_audit: Arc<dyn AdminAudit>,
+    pub deferred: Arc<dyn DeferredQueue>,
 
     /*
         scheduler is part of the core but we initialize
@@ -42,16 +79,293 @@ pub struct Deps {
       given process
     */
     pub deephash_locks: Arc<DashMap<String, Arc<Mutex<String>>>>,
+
+    /*
+      Router mode only, consecutive failed health checks
+      per scheduler url, cleared when a check succeeds
+    */
+    pub scheduler_failures: Arc<DashMap<String, u32>>,
+
+    // router mode only, the scheduler of recently redirected processes
+    pub route_cache: Arc<RouteCache>,
+
+    // router mode only, state shared with the other replicas of the router
+    pub shared_cache: Arc<dyn SharedCache>,
+
+    // router mode only, the scheduler health the other replicas found
+    pub shared_health: Arc<SharedHealth>,
+
+    // whether the last signing self check passed
+    pub signing_health: Arc<SigningHealth>,
+
+    // starts the spans of the requests that are traced
+    pub tracer: Arc<Tracer>,
+
+    // router mode only, whether the router tables can be reached
+    pub router_health: Arc<RouterHealth>,
+
+    // router mode only, whether this router is a warm standby
+    pub router_role: Arc<RouterRole>,
 }
 
 /*
     flows.rs is the main business logic of the su
 */
 
-pub fn init_builder(deps: &Arc<Deps>) -> Result<Builder, String> {
+pub fn init_builder(deps: &Arc<Deps>) -> Result<Builder<'_>, String> {
     dotenv().ok();
-    let builder = Builder::new(deps.gateway.clone(), deps.signer.clone(), &deps.logger)?;
-    return Ok(builder);
+    let assignment_tags = parse_assignment_tags(&deps.config.assignment_tags())?;
+    let builder = Builder::new(deps.gateway.clone(), deps.signer.clone(), &deps.logger)?
+        .with_assignment_tags(assignment_tags);
+    Ok(builder)
+}
+
+/*
+  Refuse writes for a denylisted process or owner
+  before any scheduling work is done
+*/
+pub fn check_denylist(deps: &Arc<Deps>, process_id: &str, owner: &str) -> Result<(), String> {
+    if deps.denylist.is_denied(process_id, "") {
+        return Err(format!("Process {} is on the denylist", process_id));
+    }
+    if deps.denylist.is_denied("", owner) {
+        return Err(format!("Owner {} is on the denylist", owner));
+    }
+    Ok(())
+}
+
+/*
+  Prefix of the error a write to a process gets once the
+  router has migrated it off this su, the message names
+  the scheduler it moved to
+*/
+pub const PROCESS_READ_ONLY: &str = "Process is read only on this scheduler";
+
+pub fn check_read_only(deps: &Arc<Deps>, process_id: &str) -> Result<(), String> {
+    match deps.read_only.moved_to(process_id) {
+        Some(url) => Err(format!(
+            "{}, {} moved to {}",
+            PROCESS_READ_ONLY, process_id, url
+        )),
+        None => Ok(()),
+    }
+}
+
+/*
+  Prefix of the error returned when a wallet is over
+  WALLET_SPAWNS_PER_HOUR or WALLET_MESSAGES_PER_MINUTE,
+  or a reader over its read limit
+*/
+pub const RATE_LIMITED: &str = "Rate limit exceeded";
+
+/*
+  Count a spawn or Message against its owner's limit,
+  kind is the value of the item's Type tag
+*/
+pub fn check_rate_limit(deps: &Arc<Deps>, kind: &str, owner: &str) -> Result<(), String> {
+    let (limiter, label) = match kind {
+        "Process" => (&deps.rate_limits.spawns, "spawn"),
+        _ => (&deps.rate_limits.messages, "message"),
+    };
+    if !limiter.allow(owner) {
+        deps.metrics.rate_limited(label);
+        return Err(format!("{} for {} on wallet {}", RATE_LIMITED, label, owner));
+    }
+    Ok(())
+}
+
+/*
+  Prefix of the error returned for a read with an api
+  key that was never issued or has been revoked
+*/
+pub const INVALID_API_KEY: &str = "Invalid api key";
+
+/*
+  Count a read against its api key, or against the
+  client address when it was sent without one
+*/
+pub fn check_read_limit(deps: &Arc<Deps>, api_key: Option<&str>, client: &str) -> Result<(), String> {
+    let limits = &deps.rate_limits;
+    match api_key {
+        Some(key) => {
+            let api_key = deps
+                .api_keys
+                .validate(key)
+                .ok_or(INVALID_API_KEY.to_string())?;
+            let limit = api_key
+                .reads_per_minute
+                .unwrap_or(limits.keyed_reads.limit());
+            if !limits.keyed_reads.allow_with(&api_key.id, limit) {
+                deps.metrics.read_rate_limited("keyed");
+                return Err(format!(
+                    "{} for reads on api key {}",
+                    RATE_LIMITED, api_key.name
+                ));
+            }
+        }
+        None => {
+            if !limits.anonymous_reads.allow(client) {
+                deps.metrics.read_rate_limited("anonymous");
+                return Err(format!(
+                    "{} for anonymous reads, send an api key for a higher limit",
+                    RATE_LIMITED
+                ));
+            }
+        }
+    }
+    Ok(())
+}
+
+/*
+  Called before a write is processed, the returned
+  guard must be held until the write is done
+*/
+pub fn admit_write(deps: &Arc<Deps>, size: usize) -> Result<InFlightWrite, String> {
+    if deps.config.signing_check_read_only() {
+        if let Some(failure) = deps.signing_health.failure() {
+            return Err(format!(
+                "{}, writes are paused: {}",
+                SIGNING_UNHEALTHY, failure
+            ));
+        }
+    }
+    deps.memory.admit(size).map_err(|e| {
+        deps.metrics.write_shed();
+        e
+    })
+}
+
+/*
+  Wait for a slot under the concurrency limit of a
+  request's endpoint class, the permit must be held
+  until the request is done
+*/
+pub async fn admit_request(
+    deps: &Arc<Deps>,
+    class: EndpointClass,
+) -> Result<Option<OwnedSemaphorePermit>, String> {
+    deps.concurrency
+        .get(class)
+        .acquire(deps.metrics.as_ref())
+        .await
+}
+
+/*
+  Runs on an interval when memory guardrails are on,
+  alerts whenever writes start or stop being shed
+*/
+pub fn check_memory(deps: &Arc<Deps>) {
+    if let Some(level) = deps.memory.sample() {
+        let message = format!(
+            "memory at {} bytes resident, {} bytes in flight, shedding {}",
+            deps.memory.rss(),
+            deps.memory.in_flight(),
+            level.as_str()
+        );
+        match level {
+            ShedLevel::None => deps.logger.log(message),
+            _ => deps.logger.error(message),
+        }
+    }
+    deps.metrics.memory_observe(
+        deps.memory.rss(),
+        deps.memory.in_flight(),
+        deps.memory.level() as u8,
+    );
+}
+
+/*
+  Runs every SIGNING_CHECK_INTERVAL seconds, alerts when
+  the wallet's signatures stop verifying and again once
+  they do
+*/
+pub async fn check_signing(deps: &Arc<Deps>) {
+    let result = sign_canary(deps.signer.as_ref(), deps.wallet.as_ref()).await;
+    deps.metrics.signing_check_observe(result.is_err());
+    let failure = result.clone().err();
+    if deps.signing_health.update(result) {
+        match failure {
+            Some(e) => deps.logger.error(format!(
+                "signing self check failed{}: {}",
+                match deps.config.signing_check_read_only() {
+                    true => ", refusing writes until it passes",
+                    false => "",
+                },
+                e
+            )),
+            None => deps
+                .logger
+                .log("signing self check passing again".to_string()),
+        }
+    }
+}
+
+/*
+  Runs after the item is saved, a failure here is
+  logged but does not fail a write that already has
+  its nonce
+*/
+fn retain_raw(deps: &Arc<Deps>, id: &str, raw: &[u8]) {
+    if let Err(e) = deps.raw_archive.retain(id, raw) {
+        deps.logger
+            .error(format!("Failed to retain raw data item {}: {:?}", id, e));
+    }
+}
+
+/*
+  Count a saved write, assignment latency runs from
+  taking the next nonce until the item is stored
+*/
+fn record_write(deps: &Arc<Deps>, kind: &str, process_id: &str, start_assignment: Instant) {
+    let latency = start_assignment.elapsed().as_millis();
+    deps.metrics.write_assignment_observe(latency);
+    deps.metrics.item_written(kind, process_id);
+    deps.logger.event(
+        LogLevel::Info,
+        "flows",
+        format!("saved {}", kind),
+        LogFields::process(process_id).latency(latency),
+    );
+}
+
+/*
+  Reads WRITE_VERIFY_PERCENT of saved assignments back
+  from the data store and compares them byte for byte,
+  as json, with the one that was acked. Runs once the
+  process lock is released and only reports, the write
+  already succeeded.
+*/
+fn verify_write(deps: &Arc<Deps>, message: &Message) {
+    let percent = deps.config.write_verify_percent();
+    if percent <= 0.0 || thread_rng().gen::<f64>() * 100.0 >= percent {
+        return;
+    }
+    let acked = serde_json::to_vec(&message.assignment).map_err(|e| e.to_string());
+    let read = deps
+        .data_store
+        .get_message(&message.assignment.id)
+        .map_err(|e| format!("{:?}", e))
+        .and_then(|read| serde_json::to_vec(&read.assignment).map_err(|e| e.to_string()));
+    let outcome = match (acked, read) {
+        (Ok(acked), Ok(read)) if acked == read => "match",
+        (Ok(acked), Ok(read)) => {
+            deps.logger.error(format!(
+                "assignment {} reads back different from what was acked, acked {} read {}",
+                message.assignment.id,
+                String::from_utf8_lossy(&acked),
+                String::from_utf8_lossy(&read)
+            ));
+            "mismatch"
+        }
+        (Err(e), _) | (_, Err(e)) => {
+            deps.logger.error(format!(
+                "assignment {} could not be read back to verify it: {}",
+                message.assignment.id, e
+            ));
+            "unreadable"
+        }
+    };
+    deps.metrics.write_verified(outcome);
 }
 
 async fn upload(deps: &Arc<Deps>, build_result: Vec<u8>) -> Result<String, String> {
@@ -63,6 +377,135 @@ async fn upload(deps: &Arc<Deps>, build_result: Vec<u8>) -> Result<String, Strin
     Ok(result)
 }
 
+/*
+  Hand a newly scheduled assignment to the streamer. The
+  message is already saved at this point so a failure is
+  logged rather than failing the write.
+*/
+/*
+  Spool the assignment for the external stream. It is
+  called before the process lock is let go so the spool
+  stays in nonce order, and a failure fails the request
+  once the rest of the write is done rather than losing
+  the event.
+*/
+async fn stream_assignment(deps: &Arc<Deps>, message: &Message) -> Result<(), String> {
+    let event = AssignmentEvent::from_message(message)?;
+    deps.streamer.publish(&event).await.map_err(|e| {
+        deps.logger
+            .error(format!("Failed to stream assignment: {:?}", e));
+        format!(
+            "Assignment {} saved but not spooled for streaming: {}",
+            event.assignment_id,
+            String::from(e)
+        )
+    })
+}
+
+/*
+  Send a newly scheduled message to the clients
+  subscribed to its process, as a server sent event
+  with the nonce as its id and the message json, the
+  same as a node of the paginated messages, as data
+*/
+fn notify_subscribers(deps: &Arc<Deps>, message: &Message) {
+    let process_id = match message.process_id() {
+        Ok(process_id) => process_id,
+        Err(_) => return,
+    };
+    if !deps.subscriptions.is_watched(&process_id) {
+        return;
+    }
+
+    match (message.nonce(), serde_json::to_string(message)) {
+        (Ok(nonce), Ok(data)) => deps.subscriptions.publish(
+            &process_id,
+            format!("id: {}\nevent: message\ndata: {}\n\n", nonce, data),
+        ),
+        _ => deps
+            .logger
+            .error(format!("Failed to notify subscribers of {}", process_id)),
+    }
+}
+
+/*
+  Every CHAIN_SNAPSHOT_INTERVAL nonces the assignment's
+  place in the hash chain is signed and stored. It runs
+  in the background, the write already has its nonce and
+  a missing snapshot only means verifiers start earlier.
+*/
+fn snapshot_chain(deps: &Arc<Deps>, message: &Message) {
+    let interval = deps.config.chain_snapshot_interval();
+    match message.nonce() {
+        Ok(nonce) if interval > 0 && nonce > 0 && nonce % interval == 0 => (),
+        _ => return,
+    }
+
+    let snapshot = match ChainSnapshot::from_message(message) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            deps.logger
+                .error(format!("Failed to snapshot hash chain: {:?}", e));
+            return;
+        }
+    };
+
+    let deps = deps.clone();
+    tokio::spawn(async move {
+        if let Err(e) = save_chain_snapshot(&deps, snapshot).await {
+            deps.logger
+                .error(format!("Failed to snapshot hash chain: {}", e));
+        }
+    });
+}
+
+/*
+  Store the inclusion proofs of a bundle's items, in the
+  background like the chain snapshots, an item without
+  one can still be checked against the whole bundle
+*/
+fn store_proofs(deps: &Arc<Deps>, proofs: Vec<InclusionProof>) {
+    let deps = deps.clone();
+    tokio::spawn(async move {
+        if let Err(e) = deps.data_store.save_inclusion_proofs(&proofs).await {
+            deps.logger
+                .error(format!("Failed to store inclusion proofs: {:?}", e));
+        }
+    });
+}
+
+async fn save_chain_snapshot(deps: &Arc<Deps>, mut snapshot: ChainSnapshot) -> Result<(), String> {
+    let signature = deps.signer.sign_tx(snapshot.signed_payload()).await?;
+    snapshot.signature = base64_url::encode(&signature);
+    deps.data_store.save_chain_snapshot(&snapshot).await?;
+    Ok(())
+}
+
+/*
+  Forward a committed item to the standby su. Like
+  streaming, a failure is logged rather than failing a
+  write that already has its nonce.
+*/
+async fn replicate(
+    deps: &Arc<Deps>,
+    kind: ReplicaKind,
+    binary: &[u8],
+    deep_hash: Option<&String>,
+) {
+    let replica = Replica {
+        kind,
+        binary: binary.to_vec(),
+        deep_hash: deep_hash.cloned(),
+    };
+    if let Err(e) = deps.replicator.replicate(replica).await {
+        deps.logger.error(format!(
+            "Failed to replicate {} to standby: {:?}",
+            kind.as_str(),
+            e
+        ));
+    }
+}
+
 fn id_res(deps: &Arc<Deps>, id: String, start_top_level: Instant) -> Result<String, String> {
     match system_time_u64() {
         Ok(timestamp) => {
@@ -114,7 +557,7 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
     */
     if !deps.config.use_local_store() && !deps.config.use_disk() {
         deps.logger
-          .log(format!("Skipping deephash recalc, no deep hash checks available on this SU"));
+            .log("Skipping deephash recalc, no deep hash checks available on this SU".to_string());
 
         return Ok(())
     }
@@ -128,15 +571,12 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
     let limit = Some(deps.config.deephash_recalc_limit());
     let mut total = 0;
 
-    match deps.data_store.get_deephash_version(process_id).await {
-        Ok(d) => {
-            if d == deps.config.current_deephash_version() {
-                deps.logger
-                    .log(format!("Deephash version up to date for {}", process_id));
-                return Ok(());
-            }
+    if let Ok(d) = deps.data_store.get_deephash_version(process_id).await {
+        if d == deps.config.current_deephash_version() {
+            deps.logger
+                .log(format!("Deephash version up to date for {}", process_id));
+            return Ok(());
         }
-        _ => (),
     };
 
     deps.logger.log(format!(
@@ -144,7 +584,7 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
         process_id
     ));
 
-    let process = match deps.data_store.get_process(&process_id).await {
+    let process = match deps.data_store.get_process(process_id).await {
         Ok(p) => p,
         /*
           the process just hasnt been created yet, do nothing
@@ -158,11 +598,11 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
             .get_message_bundles(&process, &from, &limit)
             .await?;
 
-        if bundles.0.len() < 1 {
+        if bundles.0.is_empty() {
             break;
         }
 
-        total = total + bundles.0.len();
+        total += bundles.0.len();
 
         deps.logger.log(format!(
             "Total bundles retrieved in deephash calc for process {}: {}",
@@ -183,14 +623,14 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
             */
             match msg_deephash(deps.gateway.clone(), &msg, bundle).await {
                 Ok(Some(dh)) => {
-                    deps.data_store.save_deephash(&process_id, &dh).await?;
+                    deps.data_store.save_deephash(process_id, &dh).await?;
                 }
                 Ok(None) => (),
                 Err(_) => (),
             };
         }
 
-        if bundles.1 == false {
+        if !bundles.1 {
             break;
         }
     }
@@ -220,6 +660,102 @@ async fn maybe_recalc_deephashes(deps: Arc<Deps>, process_id: &String) -> Result
     follows the Assignment flow instead. If one is
     set both must be set.
 */
+/*
+  The variant a Message is scheduled under, its process's.
+  A message to a process this su does not have is still
+  scheduled, under the default variant, as it was before
+  variants were tracked, once verify_genesis lets it.
+*/
+async fn message_variant(
+    deps: &Arc<Deps>,
+    process_id: &str,
+    message_tags: &[Tag],
+) -> Result<Variant, String> {
+    match deps.data_store.get_process(process_id).await {
+        Ok(process) => check_message_variant(&process.process.tags, message_tags),
+        Err(StoreErrorType::NotFound(_)) => {
+            verify_genesis(deps, process_id).await?;
+            check_message_variant(&[], message_tags)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// prefix of the errors verify_genesis refuses a Message with
+const GENESIS_UNVERIFIED: &str = "Process genesis not verified";
+
+/*
+  With VERIFY_PROCESS_GENESIS on, the first Message to a
+  process this su has neither the spawn nor any messages
+  of is only assigned once the spawn is known to name this
+  su as its Scheduler. The spawn's tags are read from the
+  gateway, a spawn it hasn't indexed yet is accepted when
+  the router placed the process on this su. Processes
+  that already have messages here are left alone.
+*/
+async fn verify_genesis(deps: &Arc<Deps>, process_id: &str) -> Result<(), String> {
+    if !deps.config.verify_process_genesis() {
+        return Ok(());
+    }
+    let latest = deps.data_store.get_latest_message(process_id).await?;
+    if latest.is_some() {
+        return Ok(());
+    }
+
+    let su_address = deps.wallet.wallet_address()?;
+    let gateway_error = match deps.gateway.gql_tx(process_id).await {
+        Ok(spawn) => return check_genesis(process_id, &spawn.tags, &su_address),
+        Err(e) => e,
+    };
+    if deps.config.enable_router_check() {
+        if let Ok(assignment) = deps
+            .ext_router
+            .get_routed_assignment(process_id.to_string())
+            .await
+        {
+            if assignment == deps.config.assignment() {
+                return Ok(());
+            }
+        }
+    }
+    Err(format!(
+        "{}, unable to verify the spawn of process {}: {}",
+        GENESIS_UNVERIFIED, process_id, gateway_error
+    ))
+}
+
+fn check_genesis(process_id: &str, spawn_tags: &[Tag], su_address: &str) -> Result<(), String> {
+    let tag = |name: &str| {
+        spawn_tags
+            .iter()
+            .find(|tag| tag.name.eq_ignore_ascii_case(name))
+            .map(|tag| tag.value.as_ref())
+    };
+    if tag("Type") != Some("Process") {
+        return Err(format!(
+            "{}, {} is not a Process",
+            GENESIS_UNVERIFIED, process_id
+        ));
+    }
+    match tag("Scheduler") {
+        Some(scheduler) if scheduler == su_address => Ok(()),
+        Some(scheduler) => Err(format!(
+            "{}, process {} is scheduled on {}, not this su",
+            GENESIS_UNVERIFIED, process_id, scheduler
+        )),
+        None => Err(format!(
+            "{}, process {} has no Scheduler tag",
+            GENESIS_UNVERIFIED, process_id
+        )),
+    }
+}
+
+/*
+  Prefix of the error write_item returns when an If-Match
+  hash chain is no longer the latest one
+*/
+pub const HASH_CHAIN_MISMATCH: &str = "Hash chain has advanced";
+
 pub async fn write_item(
     deps: Arc<Deps>,
     input: Vec<u8>,
@@ -227,23 +763,105 @@ pub async fn write_item(
     assign: Option<String>,
     base_layer: Option<String>,
     exclude: Option<String>,
+    if_match: Option<String>,
+) -> Result<String, String> {
+    let _span = trace::span("flows write_item", SpanKind::Internal);
+    schedule_item(
+        deps, input, process_id, assign, base_layer, exclude, if_match, false,
+    )
+    .await
+}
+
+// the tag a Message asks to be assigned later with, unix milliseconds
+pub const SCHEDULE_AT_TAG: &str = "Schedule-At";
+
+/*
+  When the Message should be assigned, if deferred
+  messages are on and its Schedule-At is still ahead.
+  A time further out than DEFERRED_MAX_DELAY is refused
+  rather than held on to.
+*/
+fn deferred_until(config: &dyn Config, item: &DataItem) -> Result<Option<i64>, String> {
+    if !config.deferred_messages() {
+        return Ok(None);
+    }
+    let value = match item.tags().iter().find(|tag| tag.name == SCHEDULE_AT_TAG) {
+        Some(tag) => tag.value.clone(),
+        None => return Ok(None),
+    };
+    let schedule_at: i64 = value.trim().parse().map_err(|_| {
+        format!(
+            "Invalid {} tag {}, expected unix milliseconds",
+            SCHEDULE_AT_TAG, value
+        )
+    })?;
+    let now = system_time_u64().map_err(|e| format!("{:?}", e))? as i64;
+    if schedule_at <= now {
+        return Ok(None);
+    }
+    let max_delay = config.deferred_max_delay() as i64 * 1000;
+    if schedule_at - now > max_delay {
+        return Err(format!(
+            "{} is more than {} seconds ahead",
+            SCHEDULE_AT_TAG,
+            config.deferred_max_delay()
+        ));
+    }
+    Ok(Some(schedule_at))
+}
+
+/*
+  A batch is scheduled in one go under the locks of its
+  processes, so an item asking to be assigned later is
+  refused rather than held back from the rest
+*/
+fn check_batch_deferral(config: &dyn Config, item: &DataItem) -> Result<(), String> {
+    match deferred_until(config, item)? {
+        Some(_) => Err(format!(
+            "{} is not supported in a batch, send the Message on its own",
+            SCHEDULE_AT_TAG
+        )),
+        None => Ok(()),
+    }
+}
+
+/*
+  released is set for a deferred message whose time has
+  come, it was counted against its wallet's limit when
+  it was accepted and is assigned now whatever its tag says
+*/
+#[allow(clippy::too_many_arguments)]
+async fn schedule_item(
+    deps: Arc<Deps>,
+    input: Vec<u8>,
+    process_id: Option<String>,
+    assign: Option<String>,
+    base_layer: Option<String>,
+    exclude: Option<String>,
+    if_match: Option<String>,
+    released: bool,
 ) -> Result<String, String> {
-    deps.logger.log(format!("write item called"));
+    deps.logger.log("write item called".to_string());
     let start_top_level = Instant::now();
     let builder = init_builder(&deps)?;
 
-    let (target_id, data_item) = if let (Some(ref process_id), Some(_)) = (&process_id, &assign) {
-        (process_id.clone(), None)
+    let (target_id, data_item, kind) = if let (Some(ref process_id), Some(_)) =
+        (&process_id, &assign)
+    {
+        (process_id.clone(), None, "Assignment")
     } else {
-        let data_item = Builder::parse_data_item(input.clone())?;
+        let data_item = Builder::parse_data_item(input.clone(), &deps.verify_cache)?;
         match data_item
             .tags()
             .iter()
             .find(|tag| tag.name == "Type" || tag.name == "type")
         {
-            Some(type_tag) => match type_tag.value.as_str() {
-                "Process" => (data_item.id(), Some(data_item)),
-                "Message" => (data_item.target(), Some(data_item)),
+            Some(type_tag) => match type_tag.value.as_ref() {
+                "Process" => (data_item.id(), Some(data_item), "Process"),
+                "Message" => {
+                    check_message_target(&data_item.target())?;
+                    (data_item.target(), Some(data_item), "Message")
+                }
                 _ => return Err("Unsupported Type tag value".to_string()),
             },
             None => return Err("Type tag not present".to_string()),
@@ -255,6 +873,49 @@ pub async fn write_item(
         &target_id
     ));
 
+    let owner_address = match data_item {
+        Some(ref item) => {
+            let owner_bytes = base64_url::decode(&item.owner())
+                .map_err(|_| "Failed to parse owner".to_string())?;
+            base64_url::encode(&hash(&owner_bytes))
+        }
+        None => "".to_string(),
+    };
+    check_denylist(&deps, &target_id, &owner_address)?;
+    check_read_only(&deps, &target_id)?;
+    if !released {
+        check_rate_limit(&deps, kind, &owner_address)?;
+    }
+
+    /*
+      A Message for later is only held on to once it could
+      be assigned, the rest of the checks run when it is.
+      Nothing is locked or incremented until then.
+    */
+    if let (Some(item), false) = (&data_item, released) {
+        let schedule_at = match kind {
+            "Message" => deferred_until(deps.config.as_ref(), item)?,
+            _ => None,
+        };
+        if let Some(schedule_at) = schedule_at {
+            deps.data_store.get_process(&target_id).await?;
+            deps.data_store.check_existing_message(&item.id())?;
+            deps.deferred.defer(&item.id(), schedule_at, &input)?;
+            deps.logger.event(
+                LogLevel::Info,
+                "flows",
+                format!("message {} deferred until {}", item.id(), schedule_at),
+                LogFields::process(&target_id),
+            );
+            return Ok(json!({
+                "id": item.id(),
+                "schedule_at": schedule_at,
+                "deferred": true
+            })
+            .to_string());
+        }
+    }
+
     /*
       Acquire the lock for a given process id. After acquiring the lock
       we can safely increment it and start building/writing data
@@ -270,12 +931,38 @@ pub async fn write_item(
     let elapsed_acquire_lock = start_acquire_lock.elapsed();
     deps.metrics
         .acquire_write_lock_observe(elapsed_acquire_lock.as_millis());
+    trace::record(
+        "scheduler acquire_lock",
+        SpanKind::Internal,
+        start_acquire_lock,
+        &[("ao.process_id", &target_id)],
+    );
+
+    deps.logger.event(
+        LogLevel::Info,
+        "flows",
+        "lock acquired".to_string(),
+        LogFields::process(&target_id).latency(elapsed_acquire_lock.as_millis()),
+    );
 
-    deps.logger.log(format!(
-        "lock acquired - {} - {}",
-        &target_id,
-        elapsed_acquire_lock.as_millis()
-    ));
+    /*
+      A conditional write only goes ahead while the latest
+      hash chain is still the one the client sent, checked
+      under the lock so no other write can land in between
+    */
+    if let Some(expected) = if_match {
+        let latest = deps
+            .scheduler
+            .latest_hash_chain(&mut schedule_info, &target_id)
+            .await?;
+        if latest.as_deref() != Some(expected.trim().trim_matches('"')) {
+            return Err(format!(
+                "{}, latest is {}",
+                HASH_CHAIN_MISMATCH,
+                latest.unwrap_or_else(|| "none".to_string())
+            ));
+        }
+    }
 
     /*
       Check to see if the message already exists, this
@@ -309,9 +996,10 @@ pub async fn write_item(
       Increment the scheduling info using the locked mutable reference
       to schedule_info
     */
+    let start_assignment = Instant::now();
     let next_schedule_info = deps
         .scheduler
-        .increment(&mut *schedule_info, target_id.clone())
+        .increment(&mut schedule_info, target_id.clone())
         .await?;
 
     deps.logger
@@ -325,17 +1013,19 @@ pub async fn write_item(
     if process_id.is_some() ^ assign.is_some() {
         return Err("If sending assign or process-id, you must send both.".to_string());
     } else if let (Some(process_id), Some(assign)) = (process_id.clone(), assign.clone()) {
+        let process = deps.data_store.get_process(&process_id).await?;
+        let variant = Variant::from_tags(&process.process.tags)?.unwrap_or(Variant::DEFAULT);
+
         let assignment = builder
             .gen_assignment(
                 Some(assign.clone()),
                 process_id.clone(),
                 &next_schedule_info,
                 &exclude,
+                variant,
             )
             .await?;
 
-        let process = deps.data_store.get_process(&process_id).await?;
-
         let gateway_tx = match builder
             .verify_assignment(&assign, &process, &base_layer)
             .await?
@@ -378,7 +1068,7 @@ pub async fn write_item(
         deps.data_store
             .save_message(&message, &build_result.binary, deep_hash.as_ref())
             .await?;
-        deps.logger.log(format!("saved message"));
+        record_write(&deps, "assignment", &process_id, start_assignment);
 
         /*
           we set the id of the previous assignment
@@ -386,14 +1076,23 @@ pub async fn write_item(
           in its Hash Chain
         */
         deps.scheduler.commit(
-            &mut *schedule_info,
+            &mut schedule_info,
             &next_schedule_info,
             process_id.clone(),
             aid,
         );
+        let streamed = stream_assignment(&deps, &message).await;
         drop(schedule_info);
 
+        verify_write(&deps, &message);
+        notify_subscribers(&deps, &message);
+        snapshot_chain(&deps, &message);
+        store_proofs(&deps, build_result.proofs);
+
+        replicate(&deps, ReplicaKind::Message, &build_result.binary, deep_hash.as_ref()).await;
+
         upload(&deps, build_result.binary.to_vec()).await?;
+        streamed?;
         return id_res(&deps, return_aid, start_top_level);
     }
 
@@ -432,6 +1131,7 @@ pub async fn write_item(
                 "Required Module and Scheduler tags for Process type not present".to_string(),
             );
         }
+        let variant = Variant::from_tags(&tags)?.unwrap_or(Variant::DEFAULT);
 
         /*
           If we dont enable_process_assignment, the
@@ -443,9 +1143,8 @@ pub async fn write_item(
           will start at 0 for the first message
         */
         if deps.config.enable_process_assignment() {
-            match deps.config.enable_router_check() {
-              true => {
-                  match deps.ext_router.get_routed_assignment(data_item.id()).await {
+            if deps.config.enable_router_check() {
+                match deps.ext_router.get_routed_assignment(data_item.id()).await {
                     Ok(a) => {
                         /*
                           This process was spawned on another SU through
@@ -453,9 +1152,9 @@ pub async fn write_item(
                           here as well.
                         */
                         if a != deps.config.assignment() {
-                            return Err("Process does not belong on this SU".to_string())
+                            return Err("Process does not belong on this SU".to_string());
                         }
-                    },
+                    }
                     Err(e) => {
                         match e {
                             /*
@@ -468,28 +1167,25 @@ pub async fn write_item(
                               check the router for a process id we cant determine
                               if it is safe so throw an error.
                             */
-                            _ => return Err("Unable to check router".to_string())
+                            _ => return Err("Unable to check router".to_string()),
                         }
                     }
-                  }
-              },
-              false => ()
+                }
             };
 
-            match data_item.tags().iter().find(|tag| tag.name == "On-Boot") {
-                Some(boot_tag) => match boot_tag.value.as_str() {
+            if let Some(boot_tag) = data_item.tags().iter().find(|tag| tag.name == "On-Boot") {
+                match boot_tag.value.as_ref() {
                     "Data" => (),
                     tx_id => {
                         if !deps.gateway.check_head(tx_id.to_string()).await? {
                             return Err("Invalid tx id for On-Boot tag".to_string());
                         }
                     }
-                },
-                None => (),
+                }
             };
 
             let assignment = builder
-                .gen_assignment(None, data_item.id(), &next_schedule_info, &None)
+                .gen_assignment(None, data_item.id(), &next_schedule_info, &None, variant)
                 .await?;
 
             let aid = assignment.id();
@@ -499,23 +1195,36 @@ pub async fn write_item(
             let process = Process::from_bundle(&build_result.bundle)?;
             deps.data_store
                 .save_process(&process, &build_result.binary)?;
+            record_write(&deps, "process", &did, start_assignment);
 
             deps.scheduler
-                .commit(&mut *schedule_info, &next_schedule_info, did, aid);
+                .commit(&mut schedule_info, &next_schedule_info, did, aid);
+            let streamed = match Message::from_process(process.clone()) {
+                Ok(process_message) => stream_assignment(&deps, &process_message).await,
+                Err(e) => Err(format!("Failed to stream assignment: {:?}", e)),
+            };
             drop(schedule_info);
 
+            retain_raw(&deps, &process.process.process_id, &input);
+
+            store_proofs(&deps, build_result.proofs);
+            replicate(&deps, ReplicaKind::Process, &build_result.binary, None).await;
+
             upload(&deps, build_result.binary.to_vec()).await?;
+            streamed?;
 
-            return id_res(&deps, process.process.process_id.clone(), start_top_level);
+            id_res(&deps, process.process.process_id.clone(), start_top_level)
         } else {
-            let build_result = builder.build_process(input, &next_schedule_info).await?;
+            let build_result = builder
+                .build_process(input.clone(), &next_schedule_info)
+                .await?;
             let process = Process::from_bundle_no_assign(
                 &build_result.bundle,
                 &build_result.bundle_data_item,
             )?;
             deps.data_store
                 .save_process(&process, &build_result.binary)?;
-            deps.logger.log(format!("saved process"));
+            record_write(&deps, "process", &process.process.process_id, start_assignment);
 
             /*
               We dont commit and schedule info change here
@@ -525,16 +1234,23 @@ pub async fn write_item(
             */
             drop(schedule_info);
 
+            retain_raw(&deps, &process.process.process_id, &input);
+
+            store_proofs(&deps, build_result.proofs);
+            replicate(&deps, ReplicaKind::Process, &build_result.binary, None).await;
+
             upload(&deps, build_result.binary.to_vec()).await?;
-            return id_res(&deps, process.process.process_id.clone(), start_top_level);
+            id_res(&deps, process.process.process_id.clone(), start_top_level)
         }
     } else if type_tag.value == "Message" {
+        let variant = message_variant(&deps, &data_item.target(), &tags).await?;
         let assignment = builder
             .gen_assignment(
                 Some(data_item.id()),
                 data_item.target(),
                 &next_schedule_info,
                 &None,
+                variant,
             )
             .await?;
 
@@ -547,7 +1263,7 @@ pub async fn write_item(
               a pushed message so we should dedupe it, otherwise
               it is a user message and we should not
             */
-            Some(_) => {
+            Some(_) if variant.dedupes_pushed_messages() => {
                 let mut mutable_item = data_item.clone();
                 let deep_hash = match mutable_item.deep_hash() {
                     Ok(d) => d,
@@ -566,7 +1282,7 @@ pub async fn write_item(
 
                 Some(deep_hash)
             }
-            None => None,
+            _ => None,
         };
 
         let build_result = builder.bundle_items(vec![assignment, data_item]).await?;
@@ -576,7 +1292,7 @@ pub async fn write_item(
             .save_message(&message, &build_result.binary, deep_hash.as_ref())
             .await?;
 
-        deps.logger.log(format!("saved message"));
+        record_write(&deps, "message", &dtarget, start_assignment);
 
         /*
           we set the id of the previous assignment
@@ -584,65 +1300,1358 @@ pub async fn write_item(
           in its Hash Chain
         */
         deps.scheduler
-            .commit(&mut *schedule_info, &next_schedule_info, dtarget, aid);
+            .commit(&mut schedule_info, &next_schedule_info, dtarget, aid);
+        let streamed = stream_assignment(&deps, &message).await;
         drop(schedule_info);
 
+        retain_raw(&deps, &message.message_id()?, &input);
+
+        verify_write(&deps, &message);
+        notify_subscribers(&deps, &message);
+        snapshot_chain(&deps, &message);
+        store_proofs(&deps, build_result.proofs);
+
+        replicate(&deps, ReplicaKind::Message, &build_result.binary, deep_hash.as_ref()).await;
+
         upload(&deps, build_result.binary.to_vec()).await?;
-        return id_res(&deps, message.message_id()?, start_top_level);
+        streamed?;
+        id_res(&deps, message.message_id()?, start_top_level)
     } else {
-        return Err("Type tag not present".to_string());
+        Err("Type tag not present".to_string())
     }
 }
 
-pub async fn read_message_data(
-    deps: Arc<Deps>,
-    tx_id: String,
-    from: Option<String>,
-    to: Option<String>,
-    limit: Option<i32>,
-    from_nonce: Option<String>,
-    to_nonce: Option<String>,
-) -> Result<String, String> {
-    let start_top_level = Instant::now();
-    let start_get_message = Instant::now();
-    if let Ok(message) = deps.data_store.get_message(&tx_id) {
-        if message.message.is_some()
-            || ((message.message_id()? != message.process_id()?)
-                && (message.assignment_id()? == tx_id))
-        {
-            let elapsed_get_message = start_get_message.elapsed();
-            deps.metrics
-                .get_message_observe(elapsed_get_message.as_millis());
-            return serde_json::to_string(&message).map_err(|e| format!("{:?}", e));
-        }
+/*
+  The data items of a POST /batch body, an ANS-104
+  bundle, or with ndjson one base64url encoded item per
+  line, optionally quoted as a json string
+*/
+pub fn split_batch(input: &[u8], ndjson: bool) -> Result<Vec<Vec<u8>>, String> {
+    if !ndjson {
+        return split_bundle(input).map_err(|e| format!("Invalid bundle: {:?}", e));
     }
 
-    if let Ok(process) = deps.data_store.get_process(&tx_id).await {
-        let start = Instant::now();
-        let messages = deps
-            .data_store
-            .get_messages(&process, &from, &to, &limit, &from_nonce, &to_nonce)
-            .await?;
-        let duration = start.elapsed();
+    let text = std::str::from_utf8(input).map_err(|_| "Batch is not utf-8".to_string())?;
+    text.lines()
+        .map(|line| line.trim().trim_matches('"'))
+        .filter(|line| !line.is_empty())
+        .enumerate()
+        .map(|(index, line)| {
+            base64_url::decode(line).map_err(|_| format!("Batch item {}: invalid base64url", index))
+        })
+        .collect()
+}
+
+struct BatchItem {
+    raw: Vec<u8>,
+    item: DataItem,
+    target: String,
+    deep_hash: Option<String>,
+    // set from the target process once its lock is held
+    variant: Variant,
+}
+
+struct ScheduledItem {
+    raw: Vec<u8>,
+    target: String,
+    message: Message,
+    binary: Vec<u8>,
+    proofs: Vec<InclusionProof>,
+    deep_hash: Option<String>,
+    // committed to the scheduler once the whole batch is saved
+    next_schedule_info: scheduler::ScheduleInfo,
+}
+
+// the checks write_item makes on a Message that need no lock
+fn parse_batch_item(deps: &Arc<Deps>, raw: Vec<u8>) -> Result<BatchItem, String> {
+    let item = Builder::parse_data_item(raw.clone(), &deps.verify_cache)?;
+    let tags = item.tags();
+    let is_message = tags
+        .iter()
+        .any(|tag| (tag.name == "Type" || tag.name == "type") && tag.value == "Message");
+    if !is_message {
+        return Err("only Messages can be batched".to_string());
+    }
+    if !tags
+        .iter()
+        .any(|tag| tag.name == "Data-Protocol" || tag.name == "data-protocol")
+    {
+        return Err("Data-Protocol tag not present".to_string());
+    }
+    check_batch_deferral(deps.config.as_ref(), &item)?;
+
+    let target = item.target();
+    check_message_target(&target)?;
+    let owner_bytes =
+        base64_url::decode(&item.owner()).map_err(|_| "Failed to parse owner".to_string())?;
+    let owner_address = base64_url::encode(&hash(&owner_bytes));
+    check_denylist(deps, &target, &owner_address)?;
+    check_read_only(deps, &target)?;
+    check_rate_limit(deps, "Message", &owner_address)?;
+
+    // pushed messages are deduped by deep hash like in write_item
+    let deep_hash = match tags.iter().any(|tag| tag.name == "From-Process") {
+        true => Some(
+            item.clone()
+                .deep_hash()
+                .map_err(|_| "Unable to calculate deep hash".to_string())?,
+        ),
+        false => None,
+    };
+
+    Ok(BatchItem {
+        raw,
+        item,
+        target,
+        deep_hash,
+        variant: Variant::DEFAULT,
+    })
+}
+
+/*
+  Build the assignment of a batch item without saving
+  it. An item after another one on the same process
+  follows on from that one, which isn't committed yet.
+*/
+async fn schedule_batch_item(
+    deps: &Arc<Deps>,
+    builder: &Builder<'_>,
+    schedule_info: &mut scheduler::ScheduleInfo,
+    previous: Option<&Message>,
+    batch_item: BatchItem,
+) -> Result<ScheduledItem, String> {
+    let next_schedule_info = match previous {
+        Some(previous) => {
+            let now = system_time_u64().map_err(|e| format!("{:?}", e))? as i64;
+            scheduler::schedule_after(previous, now.max(previous.timestamp()?))?
+        }
+        None => {
+            deps.scheduler
+                .increment(schedule_info, batch_item.target.clone())
+                .await?
+        }
+    };
+
+    let assignment = builder
+        .gen_assignment(
+            Some(batch_item.item.id()),
+            batch_item.target.clone(),
+            &next_schedule_info,
+            &None,
+            batch_item.variant,
+        )
+        .await?;
+
+    let build_result = builder
+        .bundle_items(vec![assignment, batch_item.item])
+        .await?;
+    let message = Message::from_bundle(&build_result.bundle)?;
+
+    Ok(ScheduledItem {
+        raw: batch_item.raw,
+        target: batch_item.target,
+        message,
+        binary: build_result.binary,
+        proofs: build_result.proofs,
+        deep_hash: batch_item.deep_hash,
+        next_schedule_info,
+    })
+}
+
+// release attempts before a deferred message is given up on
+const DEFERRED_MAX_ATTEMPTS: u32 = 10;
+
+/*
+  Assign the deferred messages whose time has come, the
+  earliest first. One that fails stays queued and is
+  tried again on the next run, up to DEFERRED_MAX_ATTEMPTS.
+  Returns how many were assigned.
+*/
+pub async fn release_deferred(deps: Arc<Deps>) -> Result<usize, String> {
+    let now = system_time_u64().map_err(|e| format!("{:?}", e))? as i64;
+    let mut released = 0;
+    for item in deps.deferred.due(now, 100)? {
+        let result =
+            schedule_item(deps.clone(), item.raw, None, None, None, None, None, true).await;
+        match result {
+            Ok(_) => {
+                released += 1;
+                deps.deferred.remove(&item.id)?;
+            }
+            Err(e) if deps.deferred.failed(&item.id) >= DEFERRED_MAX_ATTEMPTS => {
+                deps.logger.error(format!(
+                    "Dropping deferred message {} due at {}: {}",
+                    item.id, item.schedule_at, e
+                ));
+                deps.deferred.remove(&item.id)?;
+            }
+            Err(e) => {
+                deps.logger.error(format!(
+                    "Failed to release deferred message {}: {}",
+                    item.id, e
+                ));
+            }
+        }
+    }
+    Ok(released)
+}
+
+/*
+  Prefix of the error for a Message without a target
+  or with one that can't be a process id, refused
+  before the process is looked up anywhere
+*/
+pub const INVALID_TARGET: &str = "Invalid message target";
+
+pub fn check_message_target(target: &str) -> Result<(), String> {
+    if target.is_empty() {
+        return Err(format!("{}, the Message has no target", INVALID_TARGET));
+    }
+    // a process id is a 32 byte id in base64url
+    let valid = target.len() == 43
+        && target
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    match valid {
+        true => Ok(()),
+        false => Err(format!(
+            "{}, {} is not a 43 character base64url id",
+            INVALID_TARGET, target
+        )),
+    }
+}
+
+/*
+  Prefix of the error for a data item over
+  MAX_DATA_ITEM_SIZE, the server refuses a body with it
+  before reading past the limit
+*/
+pub const DATA_ITEM_TOO_LARGE: &str = "Data item too large";
+
+// size is None when the body was cut off at the limit
+pub fn data_item_too_large(limit: usize, size: Option<usize>) -> String {
+    match size {
+        Some(size) => format!(
+            "{}, {} bytes where MAX_DATA_ITEM_SIZE is {}",
+            DATA_ITEM_TOO_LARGE, size, limit
+        ),
+        None => format!(
+            "{}, over the {} bytes of MAX_DATA_ITEM_SIZE",
+            DATA_ITEM_TOO_LARGE, limit
+        ),
+    }
+}
+
+/*
+  Schedule a batch of Messages in order. Every item is
+  checked before any of them gets a nonce, so one bad
+  item rejects the whole batch, and the locks of all the
+  target processes are held until the last one is saved
+  so nothing is scheduled in between. The assignments
+  are saved in one go and only then does the schedule of
+  each process move on, so if saving fails none of the
+  items is scheduled.
+*/
+pub async fn write_batch(deps: Arc<Deps>, items: Vec<Vec<u8>>) -> Result<String, String> {
+    let _span = trace::span("flows write_batch", SpanKind::Internal);
+    let start_top_level = Instant::now();
+    let total = items.len();
+    if total == 0 {
+        return Err("Batch is empty".to_string());
+    }
+    if total > deps.config.batch_max_items() {
+        return Err(format!(
+            "Batch has {} items, the most allowed is {}",
+            total,
+            deps.config.batch_max_items()
+        ));
+    }
+    let builder = init_builder(&deps)?;
+
+    let mut batch = Vec::with_capacity(total);
+    let mut ids = HashSet::new();
+    for (index, raw) in items.into_iter().enumerate() {
+        if raw.len() > deps.config.max_data_item_size() {
+            return Err(format!(
+                "Batch item {}: {}",
+                index,
+                data_item_too_large(deps.config.max_data_item_size(), Some(raw.len()))
+            ));
+        }
+        let batch_item =
+            parse_batch_item(&deps, raw).map_err(|e| format!("Batch item {}: {}", index, e))?;
+        if !ids.insert(batch_item.item.id()) {
+            return Err(format!("Batch item {}: duplicate of an earlier item", index));
+        }
+        batch.push(batch_item);
+    }
+
+    /*
+      Locks are always taken in process id order so two
+      batches sharing processes can't deadlock each other
+    */
+    let targets: BTreeSet<String> = batch.iter().map(|item| item.target.clone()).collect();
+    let mut locks = HashMap::new();
+    for target in targets {
+        let locked_schedule_info = deps.scheduler.acquire_lock(target.clone()).await?;
+        locks.insert(target, locked_schedule_info.lock_owned().await);
+    }
+
+    let mut deep_hashes = HashSet::new();
+    for (index, batch_item) in batch.iter_mut().enumerate() {
+        deps.data_store
+            .check_existing_message(&batch_item.item.id())
+            .map_err(|e| format!("Batch item {}: {}", index, String::from(e)))?;
+        batch_item.variant = message_variant(&deps, &batch_item.target, &batch_item.item.tags())
+            .await
+            .map_err(|e| format!("Batch item {}: {}", index, e))?;
+        if !batch_item.variant.dedupes_pushed_messages() {
+            batch_item.deep_hash = None;
+        }
+        if let Some(deep_hash) = &batch_item.deep_hash {
+            if !deep_hashes.insert((batch_item.target.clone(), deep_hash.clone())) {
+                return Err(format!("Batch item {}: duplicate of an earlier item", index));
+            }
+            if deps.config.enable_deep_hash_checks() {
+                deps.data_store
+                    .check_existing_deep_hash(&batch_item.target, deep_hash)
+                    .await
+                    .map_err(|e| format!("Batch item {}: {}", index, String::from(e)))?;
+            }
+        }
+    }
+
+    for target in locks.keys() {
+        let t_clone = target.clone();
+        let d_clone = deps.clone();
+        tokio::task::spawn(async move {
+            if let Err(e) = maybe_recalc_deephashes(d_clone.clone(), &t_clone).await {
+                d_clone
+                    .logger
+                    .log(format!("Deep hash recalculation failed: {:?}", e));
+            }
+        });
+    }
+
+    let start_assignment = Instant::now();
+    let mut scheduled: Vec<ScheduledItem> = Vec::with_capacity(total);
+    for (index, batch_item) in batch.into_iter().enumerate() {
+        let schedule_info = locks
+            .get_mut(&batch_item.target)
+            .ok_or("Missing lock for batch item".to_string())?;
+        let previous = scheduled
+            .iter()
+            .rev()
+            .find(|item| item.target == batch_item.target)
+            .map(|item| &item.message);
+        let item = schedule_batch_item(&deps, &builder, schedule_info, previous, batch_item)
+            .await
+            .map_err(|e| format!("Batch item {}: {}", index, e))?;
+        scheduled.push(item);
+    }
+
+    let messages: Vec<(&Message, &[u8], Option<&String>)> = scheduled
+        .iter()
+        .map(|item| {
+            (
+                &item.message,
+                item.binary.as_slice(),
+                item.deep_hash.as_ref(),
+            )
+        })
+        .collect();
+    deps.data_store
+        .save_messages(&messages)
+        .await
+        .map_err(|e| {
+            format!(
+                "Batch not saved, none of it was scheduled: {}",
+                String::from(e)
+            )
+        })?;
+
+    for item in scheduled.iter() {
+        let schedule_info = locks
+            .get_mut(&item.target)
+            .ok_or("Missing lock for batch item".to_string())?;
+        deps.scheduler.commit(
+            schedule_info,
+            &item.next_schedule_info,
+            item.target.clone(),
+            item.message.assignment_id()?,
+        );
+        record_write(&deps, "message", &item.target, start_assignment);
+    }
+    // spooled together so they share a sync
+    let streamed = join_all(
+        scheduled
+            .iter()
+            .map(|item| stream_assignment(&deps, &item.message)),
+    )
+    .await
+    .into_iter()
+    .collect::<Result<Vec<()>, String>>();
+    drop(locks);
+
+    let mut assignments = Vec::with_capacity(scheduled.len());
+    for item in scheduled {
+        let message_id = item.message.message_id()?;
+        retain_raw(&deps, &message_id, &item.raw);
+        verify_write(&deps, &item.message);
+        notify_subscribers(&deps, &item.message);
+        snapshot_chain(&deps, &item.message);
+        store_proofs(&deps, item.proofs);
+        replicate(&deps, ReplicaKind::Message, &item.binary, item.deep_hash.as_ref()).await;
+        if let Err(e) = upload(&deps, item.binary).await {
+            deps.logger
+                .error(format!("Failed to upload batch item {}: {}", message_id, e));
+        }
+        assignments.push(json!({
+            "id": message_id,
+            "process_id": item.message.process_id()?,
+            "assignment": item.message.assignment_id()?,
+            "nonce": item.message.nonce()?,
+            "timestamp": item.message.timestamp()?,
+        }));
+    }
+    streamed?;
+
+    deps.metrics
+        .write_item_observe(start_top_level.elapsed().as_millis());
+    let timestamp = system_time_u64().map_err(|e| format!("{:?}", e))?;
+    Ok(json!({
+        "timestamp": timestamp,
+        "items": assignments
+    })
+    .to_string())
+}
+
+/*
+  Admin endpoints are off unless ADMIN_TOKEN is set.
+  Both tokens are hashed before comparing so the time
+  taken does not depend on how much of it matched.
+*/
+pub fn authorize_admin(deps: &Arc<Deps>, token: Option<String>) -> Result<(), String> {
+    let expected = deps.config.admin_token();
+    if expected.is_empty() {
+        return Err("Admin endpoints are disabled".to_string());
+    }
+    let provided = token.ok_or("Missing admin token".to_string())?;
+
+    let difference = hash(expected.as_bytes())
+        .iter()
+        .zip(hash(provided.as_bytes()).iter())
+        .fold(0u8, |acc, (a, b)| acc | (a ^ b));
+    if difference != 0 {
+        return Err("Invalid admin token".to_string());
+    }
+    Ok(())
+}
+
+/*
+  Disaster recovery flow for an su that lost part of
+  its schedule. Writes an assignment at an explicit
+  nonce for either the Message data item in the body
+  or, if assign is set, an existing transaction.
+
+  The nonce must not be assigned yet and the nonce
+  before it must exist, the epoch and hash chain carry
+  on from it. This also means it can never be past the
+  current tip. A forced assignment gets a new id so when
+  it fills a gap the item after it no longer chains from
+  it, the recovered schedule is only consistent up to
+  the forced nonce.
+*/
+pub struct ForcedAssignment {
+    pub process_id: String,
+    pub assign: Option<String>,
+    pub base_layer: Option<String>,
+    pub exclude: Option<String>,
+    pub nonce: i32,
+    pub timestamp: Option<i64>,
+}
+
+pub async fn force_assignment(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    input: Vec<u8>,
+    forced: ForcedAssignment,
+) -> Result<String, String> {
+    let ForcedAssignment {
+        process_id,
+        assign,
+        base_layer,
+        exclude,
+        nonce,
+        timestamp,
+    } = forced;
+    let start_top_level = Instant::now();
+    let builder = init_builder(&deps)?;
+
+    if nonce < 1 {
+        return Err("Nonce must be at least 1, nonce 0 cannot be forced".to_string());
+    }
+
+    let data_item = match assign {
+        Some(_) => None,
+        None => {
+            let item = Builder::parse_data_item(input, &deps.verify_cache)?;
+            let is_message = item
+                .tags()
+                .iter()
+                .any(|tag| (tag.name == "Type" || tag.name == "type") && tag.value == "Message");
+            if !is_message {
+                return Err("Only Message data items can be force assigned".to_string());
+            }
+            if item.target() != process_id {
+                return Err("Message target does not match process-id".to_string());
+            }
+            Some(item)
+        }
+    };
+
+    let locked_schedule_info = deps.scheduler.acquire_lock(process_id.clone()).await?;
+    let mut schedule_info = locked_schedule_info.lock().await;
+    let start_assignment = Instant::now();
+
+    if let Some(ref item) = data_item {
+        deps.data_store.check_existing_message(&item.id())?;
+    };
+
+    /*
+      Load the nonces either side of the requested one,
+      refusing to overwrite anything already committed
+    */
+    let process = deps.data_store.get_process(&process_id).await?;
+    let neighbours = deps
+        .data_store
+        .get_messages(
+            &process,
+            &None,
+            &None,
+            &Some(3),
+            &Some((nonce - 2).to_string()),
+            &Some((nonce + 1).to_string()),
+        )
+        .await?;
+
+    let mut previous = None;
+    let mut next = None;
+    for edge in neighbours.edges {
+        let edge_nonce = edge.node.nonce()?;
+        if edge_nonce == nonce {
+            return Err(format!("Nonce {} is already assigned", nonce));
+        } else if edge_nonce == nonce - 1 {
+            previous = Some(edge.node);
+        } else if edge_nonce == nonce + 1 {
+            next = Some(edge.node);
+        }
+    }
+    let previous = previous.ok_or(format!(
+        "Nonce {} does not exist, recover the schedule in order",
+        nonce - 1
+    ))?;
+
+    let at_tip = match deps.data_store.get_latest_message(&process_id).await? {
+        Some(latest) => latest.nonce()? == nonce - 1,
+        None => true,
+    };
+
+    /*
+      Timestamps must stay ordered with the neighbours,
+      a gap fill defaults to the previous timestamp
+    */
+    let timestamp = match (timestamp, &next) {
+        (Some(t), _) => t,
+        (None, Some(_)) => previous.timestamp()?,
+        (None, None) => system_time_u64().map_err(|e| format!("{:?}", e))? as i64,
+    };
+    if timestamp < previous.timestamp()? {
+        return Err("Timestamp is before the previous nonce".to_string());
+    }
+    if let Some(ref next) = next {
+        if timestamp > next.timestamp()? {
+            return Err("Timestamp is after the next nonce".to_string());
+        }
+    }
+
+    let forced_schedule_info = scheduler::schedule_after(&previous, timestamp)?;
+
+    deps.logger.log(format!(
+        "forcing assignment at nonce {} for process {}",
+        nonce, &process_id
+    ));
+
+    let kind = match data_item {
+        None => "assignment",
+        Some(_) => "message",
+    };
+    let (build_result, deep_hash) = match data_item {
+        None => {
+            let assign = assign.ok_or("Missing assign".to_string())?;
+            let assignment = builder
+                .gen_assignment(
+                    Some(assign.clone()),
+                    process_id.clone(),
+                    &forced_schedule_info,
+                    &exclude,
+                    Variant::from_tags(&process.process.tags)?.unwrap_or(Variant::DEFAULT),
+                )
+                .await?;
+
+            let gateway_tx = match builder
+                .verify_assignment(&assign, &process, &base_layer)
+                .await?
+            {
+                Some(g) => g,
+                None => return Err("Invalid gateway tx for assignming".to_string()),
+            };
+
+            let deep_hash = match &base_layer {
+                Some(_) => None,
+                None => {
+                    let tx_data = deps.gateway.raw(&assign).await?;
+                    let dh = DataItem::deep_hash_fields(
+                        gateway_tx.recipient,
+                        gateway_tx.anchor,
+                        gateway_tx.tags,
+                        tx_data,
+                    )
+                    .map_err(|_| "Unable to calculate deep hash".to_string())?;
+
+                    if deps.config.enable_deep_hash_checks() {
+                        deps.data_store
+                            .check_existing_deep_hash(&process_id, &dh)
+                            .await?;
+                    }
+
+                    Some(dh)
+                }
+            };
+
+            (builder.bundle_items(vec![assignment]).await?, deep_hash)
+        }
+        Some(data_item) => {
+            let variant = check_message_variant(&process.process.tags, &data_item.tags())?;
+            let assignment = builder
+                .gen_assignment(
+                    Some(data_item.id()),
+                    process_id.clone(),
+                    &forced_schedule_info,
+                    &exclude,
+                    variant,
+                )
+                .await?;
+
+            let deep_hash = match data_item.tags().iter().find(|tag| tag.name == "From-Process") {
+                Some(_) if variant.dedupes_pushed_messages() => {
+                    let mut mutable_item = data_item.clone();
+                    let dh = mutable_item
+                        .deep_hash()
+                        .map_err(|_| "Unable to calculate deep hash".to_string())?;
+
+                    if deps.config.enable_deep_hash_checks() {
+                        deps.data_store
+                            .check_existing_deep_hash(&process_id, &dh)
+                            .await?;
+                    }
+
+                    Some(dh)
+                }
+                _ => None,
+            };
+
+            (
+                builder.bundle_items(vec![assignment, data_item]).await?,
+                deep_hash,
+            )
+        }
+    };
+
+    let message = Message::from_bundle(&build_result.bundle)?;
+    let aid = message.assignment_id()?;
+    deps.data_store
+        .save_message(&message, &build_result.binary, deep_hash.as_ref())
+        .await?;
+    record_write(&deps, kind, &process_id, start_assignment);
+    let audited = audit_admin(
+        &deps,
+        actor,
+        "assignment.force",
+        &process_id,
+        None,
+        Some(json!({
+            "kind": kind,
+            "assignment": aid,
+            "message": message.message_id()?,
+            "nonce": nonce,
+            "timestamp": timestamp,
+        })),
+    );
+
+    /*
+      Only move the scheduler forward when the forced
+      nonce is the new tip, filling a gap leaves the
+      next nonce to hand out unchanged
+    */
+    if at_tip {
+        deps.scheduler.commit(
+            &mut schedule_info,
+            &forced_schedule_info,
+            process_id.clone(),
+            aid.clone(),
+        );
+    }
+    let streamed = stream_assignment(&deps, &message).await;
+    drop(schedule_info);
+
+    verify_write(&deps, &message);
+    notify_subscribers(&deps, &message);
+    snapshot_chain(&deps, &message);
+    store_proofs(&deps, build_result.proofs);
+
+    replicate(&deps, ReplicaKind::Message, &build_result.binary, deep_hash.as_ref()).await;
+
+    upload(&deps, build_result.binary.to_vec()).await?;
+    audited?;
+    streamed?;
+    id_res(&deps, aid, start_top_level)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn read_message_data(
+    deps: Arc<Deps>,
+    tx_id: String,
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i32>,
+    from_nonce: Option<String>,
+    to_nonce: Option<String>,
+    tags: Option<String>,
+) -> Result<String, String> {
+    let _span = trace::span("flows read_message_data", SpanKind::Internal);
+    let start_top_level = Instant::now();
+    let start_get_message = Instant::now();
+    if let Ok(message) = deps.data_store.get_message(&tx_id) {
+        if message.message.is_some()
+            || ((message.message_id()? != message.process_id()?)
+                && (message.assignment_id()? == tx_id))
+        {
+            let elapsed_get_message = start_get_message.elapsed();
+            deps.metrics
+                .get_message_observe(elapsed_get_message.as_millis());
+            return serde_json::to_string(&message).map_err(|e| format!("{:?}", e));
+        }
+    }
+
+    if let Ok(process) = deps.data_store.get_process(&tx_id).await {
+        let secret = deps.config.cursor_secret();
+        let allow_legacy = deps.config.allow_legacy_cursors();
+
+        let start = Instant::now();
+        let mut messages = match tags {
+            Some(tags) => {
+                let tags = parse_tag_filter(&tags)?;
+                /*
+                  Tagged pages are in nonce order, from and to are
+                  taken as cursors, which carry a nonce too
+                */
+                let numeric = |param: &Option<String>| {
+                    param.as_ref().is_some_and(|v| v.parse::<i64>().is_ok())
+                };
+                if numeric(&from) || numeric(&to) {
+                    return Err(
+                        "Tag filters page by nonce, use from-nonce and to-nonce or cursors"
+                            .to_string(),
+                    );
+                }
+                let from_nonce = cursor::resolve(
+                    &from_nonce.or(from),
+                    CursorField::Nonce,
+                    secret.as_bytes(),
+                    allow_legacy,
+                )?;
+                let to_nonce =
+                    cursor::resolve_bound(&to_nonce.or(to), CursorField::Nonce, secret.as_bytes())?;
+                deps.data_store
+                    .get_tagged_messages(&process, &tags, &limit, &from_nonce, &to_nonce)
+                    .await?
+            }
+            None => {
+                let from = cursor::resolve(
+                    &from,
+                    CursorField::Timestamp,
+                    secret.as_bytes(),
+                    allow_legacy,
+                )?;
+                let from_nonce = cursor::resolve(
+                    &from_nonce,
+                    CursorField::Nonce,
+                    secret.as_bytes(),
+                    allow_legacy,
+                )?;
+                let to = cursor::resolve_bound(&to, CursorField::Timestamp, secret.as_bytes())?;
+                let to_nonce =
+                    cursor::resolve_bound(&to_nonce, CursorField::Nonce, secret.as_bytes())?;
+                deps.data_store
+                    .get_messages(&process, &from, &to, &limit, &from_nonce, &to_nonce)
+                    .await?
+            }
+        };
+        cursor::sign_page(&mut messages, secret.as_bytes())?;
+        let duration = start.elapsed();
         deps.logger
             .log(format!("Time elapsed in get_messages() is: {:?}", duration));
         deps.metrics.get_messages_observe(duration.as_millis());
 
-        let result = simd_to_string(&messages).map_err(|e| format!("{:?}", e))?;
+        let result = simd_to_string(&messages).map_err(|e| format!("{:?}", e))?;
+
+        let elapsed_top_level = start_top_level.elapsed();
+        deps.metrics
+            .read_message_data_observe(elapsed_top_level.as_millis());
+
+        return Ok(result);
+    }
+
+    Err("Message or Process not found".to_string())
+}
+
+// most tags one listing can filter by
+const MAX_TAG_FILTERS: usize = 10;
+
+/*
+  The tags query parameter, Name:Value pairs separated
+  by commas that a message has to carry all of, for
+  example Action:Transfer,Data-Protocol:ao
+*/
+fn parse_tag_filter(param: &str) -> Result<Vec<Tag>, String> {
+    let tags = param
+        .split(',')
+        .map(|pair| match pair.split_once(':') {
+            Some((name, value)) if !name.is_empty() => Ok(Tag::new(name, value)),
+            _ => Err(format!("Invalid tag filter {}, expected Name:Value", pair)),
+        })
+        .collect::<Result<Vec<Tag>, String>>()?;
+    if tags.len() > MAX_TAG_FILTERS {
+        return Err(format!("At most {} tag filters", MAX_TAG_FILTERS));
+    }
+    Ok(tags)
+}
+
+/*
+  The exact bytes a data item was posted with, only
+  available while RAW_ARCHIVE_DIR retention keeps them
+*/
+pub fn read_raw_item(deps: Arc<Deps>, tx_id: String) -> Result<Vec<u8>, String> {
+    match deps.raw_archive.read(&tx_id)? {
+        Some(raw) => Ok(raw),
+        None => Err(format!("Raw data item {} not found", tx_id)),
+    }
+}
+
+// who made an admin change, as the request declared it
+pub struct AdminActor {
+    pub name: String,
+    pub client: String,
+}
+
+/*
+  Reads the configuration again, on SIGHUP or through
+  the admin api, and applies it to the rate limits and
+  the log filter as well. One that doesn't parse or that
+  the router couldn't route with is refused and the
+  running one stays. Requests in flight keep the values
+  they already read.
+*/
+pub fn reload_config(deps: Arc<Deps>, actor: Option<&AdminActor>) -> Result<String, String> {
+    let changed = deps.config.reload(&router::check_config).map_err(|e| {
+        let err = format!(
+            "Configuration reload refused, keeping the running one: {}",
+            e
+        );
+        deps.logger.error(err.clone());
+        err
+    })?;
+
+    let limits = &deps.rate_limits;
+    limits
+        .spawns
+        .set_limit(deps.config.wallet_spawns_per_hour());
+    limits
+        .messages
+        .set_limit(deps.config.wallet_messages_per_minute());
+    limits
+        .anonymous_reads
+        .set_limit(deps.config.anonymous_reads_per_minute());
+    limits
+        .keyed_reads
+        .set_limit(deps.config.api_key_reads_per_minute());
+    deps.logger.reload();
+
+    // only the names, values like ADMIN_TOKEN don't belong in the logs
+    deps.logger.log(format!(
+        "configuration reloaded, changed: [{}]",
+        changed.join(", ")
+    ));
+    if let Some(actor) = actor {
+        audit_admin(
+            &deps,
+            actor,
+            "config.reload",
+            "config",
+            None,
+            Some(json!({ "changed": changed })),
+        )?;
+    }
+    Ok(json!({ "changed": changed }).to_string())
+}
+
+/*
+  Records a change the admin api made. It has already
+  been applied when this runs, a failure to record it is
+  logged and reported back so it can be noticed.
+*/
+pub fn audit_admin(
+    deps: &Arc<Deps>,
+    actor: &AdminActor,
+    action: &str,
+    target: &str,
+    before: Option<serde_json::Value>,
+    after: Option<serde_json::Value>,
+) -> Result<(), String> {
+    let change = AdminChange {
+        actor: actor.name.clone(),
+        client: actor.client.clone(),
+        action: action.to_string(),
+        target: target.to_string(),
+        before,
+        after,
+    };
+    deps.admin_audit.append(change).map(|_| ()).map_err(|e| {
+        let err = format!(
+            "{} on {} was applied but not recorded in the admin audit log: {:?}",
+            action, target, e
+        );
+        deps.logger.error(err.clone());
+        err
+    })
+}
+
+const AUDIT_DEFAULT_LIMIT: i64 = 100;
+const AUDIT_MAX_LIMIT: i64 = 1000;
+
+/*
+  Admin audit entries from seq from on, the chain is
+  checked from the entry before them and verified is
+  false with broken_at set if it doesn't hold
+*/
+pub fn read_admin_audit(
+    deps: Arc<Deps>,
+    from: Option<i64>,
+    limit: Option<i64>,
+) -> Result<String, String> {
+    let from = from.unwrap_or(1).max(1);
+    let limit = limit
+        .unwrap_or(AUDIT_DEFAULT_LIMIT)
+        .clamp(1, AUDIT_MAX_LIMIT);
+
+    let mut entries = deps.admin_audit.read(from - 1, limit + 1)?;
+    let previous = match entries.first() {
+        Some(first) if first.seq == from - 1 => Some(entries.remove(0)),
+        _ => None,
+    };
+    entries.truncate(limit as usize);
+
+    let broken_at = match (from, &previous) {
+        (1, _) | (_, Some(_)) => audit::verify_chain(previous.as_ref(), &entries).err(),
+        // the entry before from is missing, so is the link to it
+        (_, None) => entries.first().map(|entry| entry.seq),
+    };
+    Ok(json!({
+        "entries": entries,
+        "verified": broken_at.is_none(),
+        "broken_at": broken_at,
+    })
+    .to_string())
+}
+
+/*
+  The local denylist entries of this instance, the admin
+  api changes them with a json body of the same shape
+  and gets the full list back
+*/
+pub fn list_denylist(deps: Arc<Deps>) -> Result<String, String> {
+    let entries = deps.denylist.local_entries()?;
+    serde_json::to_string(&entries).map_err(|e| e.to_string())
+}
+
+pub fn add_denylist(deps: Arc<Deps>, actor: &AdminActor, body: Vec<u8>) -> Result<String, String> {
+    let change: DenylistEntries =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid denylist entries: {}", e))?;
+    let before = deps.denylist.local_entries()?;
+    let entries = deps.denylist.add_local(&change)?;
+    deps.logger.log(format!(
+        "added to denylist through admin api: processes {:?}, owners {:?}",
+        change.processes, change.owners
+    ));
+    audit_admin(
+        &deps,
+        actor,
+        "denylist.add",
+        "denylist",
+        Some(json!(before)),
+        Some(json!(entries)),
+    )?;
+    serde_json::to_string(&entries).map_err(|e| e.to_string())
+}
+
+pub fn remove_denylist(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    body: Vec<u8>,
+) -> Result<String, String> {
+    let change: DenylistEntries =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid denylist entries: {}", e))?;
+    let before = deps.denylist.local_entries()?;
+    let entries = deps.denylist.remove_local(&change)?;
+    deps.logger.log(format!(
+        "removed from denylist through admin api: processes {:?}, owners {:?}",
+        change.processes, change.owners
+    ));
+    audit_admin(
+        &deps,
+        actor,
+        "denylist.remove",
+        "denylist",
+        Some(json!(before)),
+        Some(json!(entries)),
+    )?;
+    serde_json::to_string(&entries).map_err(|e| e.to_string())
+}
+
+/*
+  Mark a process read only on this su, the router does
+  this once it has copied the process to the scheduler
+  moved_to and clears it again when the migration fails
+*/
+pub fn mark_read_only(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    process_id: String,
+    moved_to: String,
+) -> Result<String, String> {
+    if moved_to.is_empty() {
+        return Err("to is required, the url the process moved to".to_string());
+    }
+    let before = deps.read_only.moved_to(&process_id);
+    deps.read_only.mark(&process_id, &moved_to)?;
+    audit_admin(
+        &deps,
+        actor,
+        "process.read_only",
+        &process_id,
+        Some(json!({ "moved_to": before })),
+        Some(json!({ "moved_to": moved_to })),
+    )?;
+    Ok(json!({ "process_id": process_id, "moved_to": moved_to }).to_string())
+}
+
+pub fn clear_read_only(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    process_id: String,
+) -> Result<String, String> {
+    let before = deps.read_only.moved_to(&process_id);
+    let cleared = deps.read_only.clear(&process_id)?;
+    if cleared {
+        audit_admin(
+            &deps,
+            actor,
+            "process.writable",
+            &process_id,
+            Some(json!({ "moved_to": before })),
+            None,
+        )?;
+    }
+    Ok(json!({ "process_id": process_id, "cleared": cleared }).to_string())
+}
+
+#[derive(Deserialize)]
+struct NewApiKey {
+    name: String,
+    reads_per_minute: Option<u32>,
+}
+
+/*
+  Api keys are listed without the key, it is only in
+  the response of the request that issued it
+*/
+pub fn list_api_keys(deps: Arc<Deps>) -> Result<String, String> {
+    let api_keys = deps.api_keys.list()?;
+    serde_json::to_string(&api_keys).map_err(|e| e.to_string())
+}
+
+pub fn issue_api_key(deps: Arc<Deps>, actor: &AdminActor, body: Vec<u8>) -> Result<String, String> {
+    let new_key: NewApiKey =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid api key request: {}", e))?;
+    let (key, api_key) = deps
+        .api_keys
+        .issue(&new_key.name, new_key.reads_per_minute)?;
+    deps.logger.log(format!(
+        "issued api key {} through admin api: {}",
+        api_key.id, api_key.name
+    ));
+    // the key itself is never recorded, only its id
+    audit_admin(
+        &deps,
+        actor,
+        "api_key.issue",
+        &api_key.id,
+        None,
+        Some(json!(api_key)),
+    )?;
+    Ok(json!({
+        "key": key,
+        "id": api_key.id,
+        "name": api_key.name,
+        "reads_per_minute": api_key.reads_per_minute,
+        "created": api_key.created,
+    })
+    .to_string())
+}
+
+pub fn revoke_api_key(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    key_id: String,
+) -> Result<String, String> {
+    let api_key = deps.api_keys.revoke(&key_id)?;
+    deps.logger.log(format!(
+        "revoked api key {} through admin api: {}",
+        api_key.id, api_key.name
+    ));
+    audit_admin(
+        &deps,
+        actor,
+        "api_key.revoke",
+        &api_key.id,
+        Some(json!(api_key)),
+        None,
+    )?;
+    Ok(json!({ "revoked": api_key.id }).to_string())
+}
+
+/*
+  Runs on a standby su, stores an item the primary has
+  committed and forwarded. Items already stored are
+  accepted again so the primary can safely retry. The
+  router's drain job uses it to copy a process history
+  too, it sends no deep hash so one is computed here.
+*/
+pub async fn apply_replica(
+    deps: Arc<Deps>,
+    kind: String,
+    deep_hash: Option<String>,
+    binary: Vec<u8>,
+) -> Result<String, String> {
+    match ReplicaKind::parse(&kind)? {
+        ReplicaKind::Process => {
+            let process = Process::from_bytes(binary.clone())?;
+            deps.data_store.save_process(&process, &binary)?;
+            Ok(json!({ "id": process.process.process_id }).to_string())
+        }
+        ReplicaKind::Message => {
+            let message = Message::from_bytes(binary.clone())?;
+            let assignment_id = message.assignment_id()?;
+            match deps.data_store.get_message(&assignment_id) {
+                Ok(_) => return Ok(json!({ "id": assignment_id }).to_string()),
+                Err(StoreErrorType::NotFound(_)) => (),
+                Err(e) => return Err(e.into()),
+            }
+            let deep_hash = match deep_hash {
+                Some(deep_hash) => Some(deep_hash),
+                None => msg_deephash(deps.gateway.clone(), &message, &binary)
+                    .await
+                    .unwrap_or(None),
+            };
+            deps.data_store
+                .save_message(&message, &binary, deep_hash.as_ref())
+                .await?;
+            Ok(json!({ "id": assignment_id }).to_string())
+        }
+    }
+}
+
+/*
+  One page of a process history as the raw bundles,
+  used by the router to copy a process to another su
+  when draining this one. The process itself is only
+  sent with the first page.
+
+  next_from steps back one millisecond from the last
+  timestamp so messages sharing it with the next page
+  are not skipped, the copies they cause are ignored
+  by apply_replica.
+*/
+pub async fn export_process(
+    deps: Arc<Deps>,
+    process_id: String,
+    from: Option<String>,
+    limit: Option<i32>,
+) -> Result<String, String> {
+    let process = deps.data_store.get_process(&process_id).await?;
+    let process_bundle = match from {
+        None => Some(base64_url::encode(
+            &deps.data_store.get_process_bundle(&process_id).await?,
+        )),
+        Some(_) => None,
+    };
+
+    let (bundles, has_next) = deps
+        .data_store
+        .get_message_bundles(&process, &from, &limit)
+        .await?;
 
-        let elapsed_top_level = start_top_level.elapsed();
-        deps.metrics
-            .read_message_data_observe(elapsed_top_level.as_millis());
+    let next_from = match bundles.last() {
+        Some((_, bundle)) => {
+            let last = Message::from_bytes(bundle.clone())?.timestamp()?;
+            let first = Message::from_bytes(bundles[0].1.clone())?.timestamp()?;
+            // a page that is all one timestamp has to move past it
+            match first == last {
+                true => Some(last.to_string()),
+                false => Some((last - 1).to_string()),
+            }
+        }
+        None => from,
+    };
 
-        return Ok(result);
-    }
+    Ok(json!({
+        "process": process_bundle,
+        "messages": bundles
+            .iter()
+            .map(|(_, bundle)| base64_url::encode(bundle))
+            .collect::<Vec<_>>(),
+        "has_next": has_next,
+        "next_from": next_from,
+    })
+    .to_string())
+}
 
-    Err("Message or Process not found".to_string())
+const PROCESS_LIST_LIMIT: usize = 1000;
+
+/*
+  A page of the ids of the processes this su holds the
+  spawn of, what a router reconciles its routing rows
+  against. next is the signed after of the following
+  page and null on the last one.
+*/
+pub async fn list_process_ids(
+    deps: Arc<Deps>,
+    after: Option<String>,
+    limit: Option<usize>,
+) -> Result<String, String> {
+    let limit = limit
+        .unwrap_or(PROCESS_LIST_LIMIT)
+        .clamp(1, PROCESS_LIST_LIMIT);
+    let secret = deps.config.cursor_secret();
+    let after = cursor::resolve_after(
+        &after,
+        secret.as_bytes(),
+        deps.config.allow_legacy_cursors(),
+    )?;
+    let ids = deps.data_store.get_process_ids_after(&after, limit).await?;
+    let next = match ids.len() == limit {
+        true => ids
+            .last()
+            .map(|id| cursor::encode_after(id, secret.as_bytes())),
+        false => None,
+    };
+    Ok(json!({ "processes": ids, "next": next }).to_string())
+}
+
+/*
+  A page of GET /processes in process id order. A router
+  lists its routing rows, scheduler being the row id or
+  url of one of its schedulers, and spawned_at is when
+  it routed the spawn, null on rows routed before that
+  was recorded. A scheduler unit lists the processes it
+  holds, scheduler being its own wallet address, and
+  spawned_at is the timestamp of the spawn's assignment.
+*/
+pub async fn list_processes(
+    deps: Arc<Deps>,
+    scheduler: Option<String>,
+    spawned_after: Option<i64>,
+    after: Option<String>,
+    limit: Option<usize>,
+) -> Result<String, String> {
+    let limit = limit
+        .unwrap_or(PROCESS_LIST_LIMIT)
+        .clamp(1, PROCESS_LIST_LIMIT);
+    let secret = deps.config.cursor_secret();
+    let after = cursor::resolve_after(
+        &after,
+        secret.as_bytes(),
+        deps.config.allow_legacy_cursors(),
+    )?;
+
+    let processes: Vec<serde_json::Value> = if deps.config.mode() == Mode::Router {
+        let store = &deps.router_data_store;
+        let scheduler_row_id = match scheduler {
+            Some(scheduler) => match scheduler.parse::<i32>() {
+                Ok(row_id) => Some(row_id),
+                Err(_) => store.get_scheduler_by_url(&scheduler)?.row_id,
+            },
+            None => None,
+        };
+        let urls: HashMap<i32, String> = store
+            .get_all_schedulers()?
+            .into_iter()
+            .filter_map(|scheduler| scheduler.row_id.map(|row_id| (row_id, scheduler.url)))
+            .collect();
+        store
+            .list_processes(scheduler_row_id, spawned_after, &after, limit as i64)?
+            .into_iter()
+            .map(|(row, spawned_at)| {
+                json!({
+                    "process_id": row.process_id,
+                    "scheduler": urls.get(&row.scheduler_row_id),
+                    "spawned_at": spawned_at,
+                })
+            })
+            .collect()
+    } else {
+        let su_address = deps.wallet.wallet_address()?;
+        if scheduler.is_some_and(|scheduler| scheduler != su_address) {
+            return Ok(json!({ "processes": [], "next": null }).to_string());
+        }
+        deps.data_store
+            .list_processes(spawned_after, &after, limit)
+            .await?
+            .into_iter()
+            .map(|(process_id, spawned_at)| {
+                json!({
+                    "process_id": process_id,
+                    "scheduler": su_address,
+                    "spawned_at": spawned_at,
+                })
+            })
+            .collect()
+    };
+
+    let next = match processes.len() == limit {
+        true => processes
+            .last()
+            .and_then(|process| process["process_id"].as_str())
+            .map(|id| cursor::encode_after(id, secret.as_bytes())),
+        false => None,
+    };
+    Ok(json!({ "processes": processes, "next": next }).to_string())
 }
 
 pub async fn read_latest_message(deps: Arc<Deps>, process_id: String) -> Result<String, String> {
     if let Ok(Some(message)) = deps.data_store.get_latest_message(&process_id).await {
-        return serde_json::to_string(&message).map_err(|e| format!("{:?}", e));
+        serde_json::to_string(&message).map_err(|e| format!("{:?}", e))
     } else {
         Err("Latest message not available".to_string())
     }
@@ -660,6 +2669,723 @@ pub async fn read_process(deps: Arc<Deps>, process_id: String) -> Result<String,
     Ok(result)
 }
 
+/*
+  Served from the counters the data store maintains
+  on write, never from a count over the messages
+*/
+pub async fn read_process_stats(deps: Arc<Deps>, process_id: String) -> Result<String, String> {
+    let stats = deps.data_store.get_process_stats(&process_id).await?;
+    serde_json::to_string(&stats).map_err(|e| format!("{:?}", e))
+}
+
+// most processes one POST /processes/stats may ask for
+pub const PROCESS_STATS_MAX: usize = 100;
+
+#[derive(Deserialize)]
+struct ProcessStatsRequest {
+    process_ids: Vec<String>,
+}
+
+/*
+  The process ids of a POST /processes/stats body, in
+  the order given without repeats
+*/
+pub fn parse_stats_request(body: &[u8]) -> Result<Vec<String>, String> {
+    let request: ProcessStatsRequest =
+        serde_json::from_slice(body).map_err(|e| format!("Invalid stats request: {}", e))?;
+    let mut seen = HashSet::new();
+    let process_ids: Vec<String> = request
+        .process_ids
+        .into_iter()
+        .filter(|process_id| seen.insert(process_id.clone()))
+        .collect();
+    if process_ids.is_empty() {
+        return Err("No process_ids given".to_string());
+    }
+    if process_ids.len() > PROCESS_STATS_MAX {
+        return Err(format!(
+            "{} process_ids given, the most allowed is {}",
+            process_ids.len(),
+            PROCESS_STATS_MAX
+        ));
+    }
+    Ok(process_ids)
+}
+
+/*
+  The stats of several processes from one read of the
+  data store, in the order asked for. Processes without
+  stats are listed under missing rather than failing the
+  rest.
+*/
+pub async fn read_processes_stats(
+    deps: Arc<Deps>,
+    process_ids: Vec<String>,
+) -> Result<String, String> {
+    let mut found: HashMap<String, ProcessStats> = deps
+        .data_store
+        .get_processes_stats(&process_ids)
+        .await?
+        .into_iter()
+        .map(|stats| (stats.process_id.clone(), stats))
+        .collect();
+    let mut stats = vec![];
+    let mut missing = vec![];
+    for process_id in process_ids {
+        match found.remove(&process_id) {
+            Some(process_stats) => stats.push(process_stats),
+            None => missing.push(process_id),
+        }
+    }
+    Ok(json!({ "stats": stats, "missing": missing }).to_string())
+}
+
+/*
+  The latest signed chain snapshot of a process, or the
+  latest at or below nonce. owner is the su public key
+  the signature verifies against, payload the exact bytes
+  that were signed.
+*/
+pub async fn read_chain_snapshot(
+    deps: Arc<Deps>,
+    process_id: String,
+    nonce: Option<i32>,
+) -> Result<String, String> {
+    let snapshot = deps
+        .data_store
+        .get_chain_snapshot(&process_id, nonce)
+        .await?;
+    let payload = String::from_utf8(snapshot.signed_payload()).map_err(|e| e.to_string())?;
+    let mut result = serde_json::to_value(&snapshot).map_err(|e| format!("{:?}", e))?;
+    result["payload"] = json!(payload);
+    result["owner"] = json!(base64_url::encode(&deps.signer.get_public_key()));
+    Ok(result.to_string())
+}
+
+/*
+  The inclusion proof of a scheduled item, a Message or
+  assignment, in the bundle the su uploaded it in. The
+  proof's root is the bundle's signed Merkle-Root tag.
+*/
+pub async fn read_inclusion_proof(deps: Arc<Deps>, item_id: String) -> Result<String, String> {
+    let proof = deps.data_store.get_inclusion_proof(&item_id).await?;
+    serde_json::to_string(&proof).map_err(|e| format!("{:?}", e))
+}
+
+// most transactions returned by one query, like a gateway
+const QUERY_MAX_FIRST: usize = 100;
+const QUERY_DEFAULT_FIRST: usize = 10;
+// messages read per store page while a query filters them
+const QUERY_PAGE_SIZE: i32 = 100;
+// messages one query reads before it returns what it found
+const QUERY_SCAN_LIMIT: usize = 5000;
+
+pub struct TagQuery {
+    pub name: String,
+    pub values: Vec<String>,
+    // the gateway's NEQ operator
+    pub negate: bool,
+}
+
+/*
+  A gateway style transactions query over the messages
+  scheduled here. Messages are only indexed by process,
+  so a query names its processes as recipients or its
+  messages as ids, the other filters are applied to the
+  messages read.
+*/
+#[derive(Default)]
+pub struct TransactionQuery {
+    pub ids: Option<Vec<String>>,
+    pub owners: Option<Vec<String>>,
+    pub recipients: Option<Vec<String>>,
+    pub tags: Vec<TagQuery>,
+    pub first: Option<usize>,
+    pub after: Option<String>,
+    pub descending: bool,
+}
+
+// a scheduled message in the shape of a gateway transaction
+pub struct QueriedTransaction {
+    pub cursor: String,
+    pub id: String,
+    pub owner_address: String,
+    pub owner_key: String,
+    pub recipient: String,
+    pub anchor: String,
+    pub signature: String,
+    pub tags: Vec<(String, String)>,
+    pub data_size: usize,
+    pub content_type: Option<String>,
+    pub block_height: Option<i64>,
+    // seconds, from the assignment
+    pub timestamp: Option<i64>,
+}
+
+pub struct TransactionPage {
+    pub edges: Vec<QueriedTransaction>,
+    pub has_next_page: bool,
+    // where the next page starts, also when the scan limit stopped it with no edges
+    pub end_cursor: Option<String>,
+}
+
+/*
+  Cursors are the process, or "ids", and a position in
+  it, signed like the cursors of the message listings so
+  a query can only carry on where one of its pages ended.
+  Unsigned cursors are refused.
+*/
+fn query_cursor(key: &str, position: i64, secret: &[u8]) -> String {
+    Cursor {
+        nonce: position as i32,
+        timestamp: 0,
+        id: key.to_string(),
+    }
+    .encode(secret)
+}
+
+fn parse_query_cursor(cursor: &str, secret: &[u8]) -> Result<(String, i64), String> {
+    let cursor = Cursor::decode(cursor, secret)?;
+    Ok((cursor.id, cursor.nonce as i64))
+}
+
+impl TransactionQuery {
+    fn matches(&self, message: &Message) -> bool {
+        let (id, owner, tags) = match &message.message {
+            Some(inner) => (&inner.id, &inner.owner.address, &inner.tags),
+            None => (
+                &message.assignment.id,
+                &message.assignment.owner.address,
+                &message.assignment.tags,
+            ),
+        };
+        let listed = |list: &Option<Vec<String>>, value: &String| match list {
+            Some(list) => list.contains(value),
+            None => true,
+        };
+
+        listed(&self.ids, id)
+            && listed(&self.owners, owner)
+            && self.tags.iter().all(|filter| {
+                let found = tags.iter().any(|tag| {
+                    tag.name == filter.name && filter.values.iter().any(|v| *v == tag.value)
+                });
+                found != filter.negate
+            })
+    }
+}
+
+impl QueriedTransaction {
+    fn from_message(cursor: String, message: &Message) -> QueriedTransaction {
+        let assignment_tag = |name: &str| {
+            message
+                .assignment
+                .tags
+                .iter()
+                .find(|tag| tag.name == name)
+                .and_then(|tag| tag.value.parse::<i64>().ok())
+        };
+        let block_height = assignment_tag("Block-Height");
+        let timestamp = assignment_tag("Timestamp").map(|ms| ms / 1000);
+
+        match &message.message {
+            Some(inner) => QueriedTransaction {
+                cursor,
+                id: inner.id.clone(),
+                owner_address: inner.owner.address.clone(),
+                owner_key: inner.owner.key.clone(),
+                recipient: inner.target.clone().unwrap_or_default(),
+                anchor: inner.anchor.clone().unwrap_or_default(),
+                signature: inner.signature.clone(),
+                tags: inner
+                    .tags
+                    .iter()
+                    .map(|tag| (tag.name.to_string(), tag.value.to_string()))
+                    .collect(),
+                data_size: inner.data.as_ref().map(|data| data.len()).unwrap_or(0),
+                content_type: inner
+                    .tags
+                    .iter()
+                    .find(|tag| tag.name == "Content-Type")
+                    .map(|tag| tag.value.to_string()),
+                block_height,
+                timestamp,
+            },
+            // an assignment of an existing transaction
+            None => QueriedTransaction {
+                cursor,
+                id: message.assignment.id.clone(),
+                owner_address: message.assignment.owner.address.clone(),
+                owner_key: message.assignment.owner.key.clone(),
+                recipient: message.assignment.target.clone().unwrap_or_default(),
+                anchor: message.assignment.anchor.clone().unwrap_or_default(),
+                signature: message.assignment.signature.clone(),
+                tags: message
+                    .assignment
+                    .tags
+                    .iter()
+                    .map(|tag| (tag.name.to_string(), tag.value.to_string()))
+                    .collect(),
+                data_size: 0,
+                content_type: None,
+                block_height,
+                timestamp,
+            },
+        }
+    }
+}
+
+/*
+  Runs a transactions query against the data store. The
+  recipients are read one after another in nonce order,
+  newest first when descending, and the filters applied
+  to each message read. A query that reads
+  QUERY_SCAN_LIMIT messages returns what it has found so
+  far with a cursor to carry on from.
+*/
+pub async fn query_transactions(
+    deps: Arc<Deps>,
+    query: TransactionQuery,
+) -> Result<TransactionPage, String> {
+    if deps.config.mode() == Mode::Router {
+        return Err("The router holds no messages, query the scheduler of the process".to_string());
+    }
+    let first = query
+        .first
+        .unwrap_or(QUERY_DEFAULT_FIRST)
+        .clamp(1, QUERY_MAX_FIRST);
+    let secret = deps.config.cursor_secret();
+    let after = match &query.after {
+        Some(cursor) => Some(parse_query_cursor(cursor, secret.as_bytes())?),
+        None => None,
+    };
+
+    if let Some(ids) = &query.recipients {
+        return query_recipients(&deps, &query, ids, first, after, secret.as_bytes()).await;
+    }
+    if let Some(ids) = &query.ids {
+        return query_ids(&deps, &query, ids, first, after, secret.as_bytes());
+    }
+    Err("Filter transactions by ids or recipients, the processes they were sent to".to_string())
+}
+
+fn query_ids(
+    deps: &Arc<Deps>,
+    query: &TransactionQuery,
+    ids: &[String],
+    first: usize,
+    after: Option<(String, i64)>,
+    secret: &[u8],
+) -> Result<TransactionPage, String> {
+    if ids.len() > QUERY_SCAN_LIMIT {
+        return Err(format!("At most {} ids per query", QUERY_SCAN_LIMIT));
+    }
+    let start = match after {
+        Some((key, position)) if key == "ids" => position + 1,
+        Some(_) => return Err("Cursor is not from this query".to_string()),
+        None => 0,
+    };
+
+    let mut edges = vec![];
+    for (index, id) in ids.iter().enumerate().skip(start.max(0) as usize) {
+        let message = match deps.data_store.get_message(id) {
+            Ok(message) => message,
+            Err(StoreErrorType::NotFound(_)) => continue,
+            Err(e) => return Err(e.into()),
+        };
+        if !query.matches(&message) {
+            continue;
+        }
+        if edges.len() == first {
+            return Ok(page(edges, true));
+        }
+        edges.push(QueriedTransaction::from_message(
+            query_cursor("ids", index as i64, secret),
+            &message,
+        ));
+    }
+    Ok(page(edges, false))
+}
+
+fn page(edges: Vec<QueriedTransaction>, has_next_page: bool) -> TransactionPage {
+    let end_cursor = edges.last().map(|edge| edge.cursor.clone());
+    TransactionPage {
+        edges,
+        has_next_page,
+        end_cursor,
+    }
+}
+
+async fn query_recipients(
+    deps: &Arc<Deps>,
+    query: &TransactionQuery,
+    recipients: &[String],
+    first: usize,
+    after: Option<(String, i64)>,
+    secret: &[u8],
+) -> Result<TransactionPage, String> {
+    let (start_index, mut position) = match after {
+        Some((process_id, nonce)) => match recipients.iter().position(|r| *r == process_id) {
+            Some(index) => (index, Some(nonce)),
+            None => return Err("Cursor is not from this query".to_string()),
+        },
+        None => (0, None),
+    };
+
+    let mut edges = vec![];
+    let mut scanned = 0;
+    for process_id in recipients.iter().skip(start_index) {
+        let process = match deps.data_store.get_process(process_id).await {
+            Ok(process) => process,
+            Err(StoreErrorType::NotFound(_)) => {
+                position = None;
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+
+        loop {
+            let (messages, more) =
+                read_query_page(deps, &process, position, query.descending).await?;
+            for message in messages.iter() {
+                scanned += 1;
+                let nonce = message.nonce()? as i64;
+                position = Some(nonce);
+                if query.matches(message) {
+                    if edges.len() == first {
+                        return Ok(page(edges, true));
+                    }
+                    edges.push(QueriedTransaction::from_message(
+                        query_cursor(process_id, nonce, secret),
+                        message,
+                    ));
+                }
+                if scanned >= QUERY_SCAN_LIMIT {
+                    return Ok(TransactionPage {
+                        edges,
+                        has_next_page: true,
+                        end_cursor: Some(query_cursor(process_id, nonce, secret)),
+                    });
+                }
+            }
+            if !more {
+                break;
+            }
+        }
+        position = None;
+    }
+    Ok(page(edges, false))
+}
+
+/*
+  The next page of a process after nonce, in the query's
+  order, and whether there are more. The process itself
+  is nonce 0, the store adds it to the page that starts
+  from -1.
+*/
+async fn read_query_page(
+    deps: &Arc<Deps>,
+    process: &Process,
+    after: Option<i64>,
+    descending: bool,
+) -> Result<(Vec<Message>, bool), String> {
+    let process_id = &process.process.process_id;
+    if !descending {
+        let from_nonce = after.unwrap_or(-1);
+        let page = deps
+            .data_store
+            .get_messages(
+                process,
+                &None,
+                &None,
+                &Some(QUERY_PAGE_SIZE),
+                &Some(from_nonce.to_string()),
+                &None,
+            )
+            .await?;
+        let messages: Vec<Message> = page.edges.into_iter().map(|edge| edge.node).collect();
+        let more = page.page_info.has_next_page && !messages.is_empty();
+        return Ok((messages, more));
+    }
+
+    let to_nonce = match after {
+        Some(nonce) => nonce - 1,
+        None => match deps.data_store.get_latest_message(process_id).await? {
+            Some(latest) => latest.nonce()? as i64,
+            None => 0,
+        },
+    };
+    if to_nonce < 0 {
+        return Ok((vec![], false));
+    }
+    let from_nonce = (to_nonce - QUERY_PAGE_SIZE as i64).max(-1);
+    let page = deps
+        .data_store
+        .get_messages(
+            process,
+            &None,
+            &None,
+            &Some((to_nonce - from_nonce + 1) as i32),
+            &Some(from_nonce.to_string()),
+            &Some(to_nonce.to_string()),
+        )
+        .await?;
+    let mut messages: Vec<Message> = page.edges.into_iter().map(|edge| edge.node).collect();
+    messages.reverse();
+    Ok((messages, from_nonce > -1))
+}
+
+// a comment is sent this often so idle connections stay open
+const SUBSCRIBE_KEEPALIVE: Duration = Duration::from_secs(15);
+
+/*
+  Server sent events for the messages scheduled on a
+  process from now on. A subscriber that falls too far
+  behind gets a lagged event with the number of messages
+  it missed, it can read them from the paginated
+  messages starting after the last id it saw.
+*/
+pub async fn subscribe_messages(
+    deps: Arc<Deps>,
+    process_id: String,
+) -> Result<BoxStream<'static, Result<Bytes, String>>, String> {
+    deps.data_store.get_process(&process_id).await?;
+
+    let receiver = deps.subscriptions.subscribe(&process_id);
+    let events = stream::unfold(receiver, |mut receiver| async move {
+        let event = match timeout(SUBSCRIBE_KEEPALIVE, receiver.recv()).await {
+            Ok(Ok(event)) => event.to_string(),
+            Ok(Err(RecvError::Lagged(missed))) => {
+                format!("event: lagged\ndata: {}\n\n", missed)
+            }
+            Ok(Err(RecvError::Closed)) => return None,
+            Err(_) => ": keepalive\n\n".to_string(),
+        };
+        Some((Ok(Bytes::from(event)), receiver))
+    });
+
+    Ok(events.boxed())
+}
+
+const REPLAY_CHUNK_SIZE: i32 = 500;
+const REPLAY_MAX_CHUNK_SIZE: i32 = 5000;
+const REPLAY_WORKERS: usize = 4;
+const REPLAY_MAX_WORKERS: usize = 16;
+
+/*
+  Fetch every message in the nonce range (from_nonce, to_nonce]
+  for a full history replay. The range is split into chunks
+  that up to `workers` tasks read from the store at the same
+  time, the chunks are still emitted strictly in nonce order
+  as newline delimited json, one message per line.
+
+  from_nonce defaults to -1 so the process itself is the
+  first line, to_nonce defaults to the latest nonce. At most
+  `workers` chunks are held in memory at once. A failed chunk
+  ends the stream early so a client must check it received
+  every nonce it asked for.
+*/
+pub async fn replay_messages(
+    deps: Arc<Deps>,
+    process_id: String,
+    from_nonce: Option<i32>,
+    to_nonce: Option<i32>,
+    chunk_size: Option<i32>,
+    workers: Option<usize>,
+) -> Result<BoxStream<'static, Result<Bytes, String>>, String> {
+    let process = deps.data_store.get_process(&process_id).await?;
+
+    let from_nonce = from_nonce.unwrap_or(-1);
+    let to_nonce = match to_nonce {
+        Some(n) => n,
+        None => match deps.data_store.get_latest_message(&process_id).await? {
+            Some(latest) => latest.nonce()?,
+            None => 0,
+        },
+    };
+    if from_nonce < -1 || to_nonce < from_nonce {
+        return Err("Invalid nonce range".to_string());
+    }
+
+    let chunk_size = chunk_size
+        .unwrap_or(REPLAY_CHUNK_SIZE)
+        .clamp(1, REPLAY_MAX_CHUNK_SIZE);
+    let workers = workers
+        .unwrap_or(REPLAY_WORKERS)
+        .clamp(1, REPLAY_MAX_WORKERS);
+
+    let mut chunks = vec![];
+    let mut start = from_nonce;
+    while start < to_nonce {
+        let end = start.saturating_add(chunk_size).min(to_nonce);
+        chunks.push((start, end));
+        start = end;
+    }
+
+    deps.logger.log(format!(
+        "replaying {} nonces ({}, {}] in {} chunks with {} workers",
+        process_id,
+        from_nonce,
+        to_nonce,
+        chunks.len(),
+        workers
+    ));
+
+    let replay = stream::iter(chunks)
+        .map(move |(start, end)| read_replay_chunk(deps.clone(), process.clone(), start, end))
+        .buffered(workers);
+
+    Ok(replay.boxed())
+}
+
+async fn read_replay_chunk(
+    deps: Arc<Deps>,
+    process: Process,
+    start: i32,
+    end: i32,
+) -> Result<Bytes, String> {
+    let mut lines = Vec::new();
+    let mut from = start;
+    loop {
+        /*
+          A chunk holds at most end - start messages plus the
+          process on the first chunk, page anyway in case the
+          store returns less than asked for
+        */
+        let page = deps
+            .data_store
+            .get_messages(
+                &process,
+                &None,
+                &None,
+                &Some(end - from + 1),
+                &Some(from.to_string()),
+                &Some(end.to_string()),
+            )
+            .await?;
+
+        for edge in page.edges.iter() {
+            lines.extend(serde_json::to_vec(&edge.node).map_err(|e| format!("{:?}", e))?);
+            lines.push(b'\n');
+        }
+
+        match page.edges.last() {
+            Some(edge) if page.page_info.has_next_page => from = edge.node.nonce()?,
+            _ => break,
+        }
+    }
+    Ok(Bytes::from(lines))
+}
+
+const EXPORT_PAGE_SIZE: usize = 100;
+
+// timestamp:id of the last item exported, where the next export resumes
+fn export_cursor(timestamp: i64, id: &str) -> String {
+    format!("{}:{}", timestamp, id)
+}
+
+fn parse_export_cursor(cursor: &str) -> Result<(i64, String), String> {
+    cursor
+        .split_once(':')
+        .and_then(|(timestamp, id)| Some((timestamp.parse::<i64>().ok()?, id.to_string())))
+        .ok_or(format!("Invalid export cursor {}", cursor))
+}
+
+/*
+  Every process and message of every process written
+  from since, or after cursor, up to when the export
+  started, as newline delimited json ordered by
+  timestamp. Each line carries the base64url bundle and
+  the cursor to resume after it, and a last line of type
+  End the cursor for the next incremental export. A
+  stream without the End line was cut short, resume
+  from the last cursor received.
+*/
+pub async fn export_items(
+    deps: Arc<Deps>,
+    since: Option<i64>,
+    cursor: Option<String>,
+    format: Option<String>,
+) -> Result<BoxStream<'static, Result<Bytes, String>>, String> {
+    match format.as_deref() {
+        None | Some("ndjson") => (),
+        Some(other) => return Err(format!("Unsupported export format {}", other)),
+    }
+    let after = match (cursor, since) {
+        (Some(cursor), _) => parse_export_cursor(&cursor)?,
+        // every id sorts after the empty one, so since itself is included
+        (None, since) => (since.unwrap_or(0), "".to_string()),
+    };
+    let until = system_time_u64().map_err(|e| format!("{:?}", e))? as i64;
+
+    deps.logger.log(format!(
+        "exporting items after {} until {}",
+        export_cursor(after.0, &after.1),
+        until
+    ));
+
+    let pages = stream::unfold(Some(after), move |after| {
+        let deps = deps.clone();
+        async move {
+            match read_export_page(&deps, after?, until).await {
+                Ok((lines, last, false)) => Some((Ok(lines), Some(last))),
+                Ok((mut lines, last, true)) => {
+                    let end = json!({
+                        "type": "End",
+                        "cursor": export_cursor(last.0, &last.1),
+                    });
+                    lines.extend(end.to_string().into_bytes());
+                    lines.push(b'\n');
+                    Some((Ok(lines), None))
+                }
+                Err(e) => Some((Err(e), None)),
+            }
+        }
+    });
+
+    Ok(pages.map(|lines| lines.map(Bytes::from)).boxed())
+}
+
+/*
+  The lines of one page, the position of its last item
+  and whether the export is done, because the page
+  reached until or the end of the store
+*/
+async fn read_export_page(
+    deps: &Arc<Deps>,
+    after: (i64, String),
+    until: i64,
+) -> Result<(Vec<u8>, (i64, String), bool), String> {
+    let items = deps
+        .data_store
+        .get_items_after((after.0, &after.1), EXPORT_PAGE_SIZE)
+        .await?;
+    let mut done = items.len() < EXPORT_PAGE_SIZE;
+
+    let mut lines = Vec::new();
+    let mut last = after;
+    for item in items {
+        if item.timestamp > until {
+            done = true;
+            break;
+        }
+        let cursor = export_cursor(item.timestamp, &item.id);
+        let line = json!({
+            "type": item.item_type,
+            "process_id": item.process_id,
+            "id": item.id,
+            "timestamp": item.timestamp,
+            "nonce": item.nonce,
+            "cursor": cursor,
+            "bundle": base64_url::encode(&item.bundle),
+        });
+        lines.extend(line.to_string().into_bytes());
+        lines.push(b'\n');
+        last = (item.timestamp, item.id);
+    }
+    Ok((lines, last, done))
+}
+
 fn system_time() -> Result<String, SystemTimeError> {
     let start_time = SystemTime::now();
     let duration = start_time.duration_since(UNIX_EPOCH)?;
@@ -708,14 +3434,80 @@ pub async fn health(deps: Arc<Deps>) -> Result<String, String> {
     }
 }
 
+/*
+  Identity of this su for routers and clients. The
+  info object is signed with the su wallet, the
+  signature covers the exact bytes of the compact
+  json in "info" so a client verifies it against
+  that string with the returned public key and checks
+  the address is the sha256 of the key. The timestamp
+  lets them reject a stale replayed response.
+*/
+pub async fn info(deps: Arc<Deps>) -> Result<String, String> {
+    let timestamp = system_time().map_err(|e| format!("{:?}", e))?;
+    let wallet_address = deps.wallet.wallet_address()?;
+
+    let info = json!({
+        "address": wallet_address,
+        "mode": deps.config.mode().as_str(),
+        "version": env!("CARGO_PKG_VERSION"),
+        "protocol": {
+            "data_protocol": "ao",
+            "variants": Variant::SUPPORTED.iter().map(Variant::as_str).collect::<Vec<_>>(),
+        },
+        "limits": {
+            "max_read_memory": deps.config.max_read_memory(),
+            "default_page_size": 100,
+        },
+        "timestamp": timestamp,
+    })
+    .to_string();
+
+    let signature = deps.signer.sign_tx(info.as_bytes().to_vec()).await?;
+
+    let response_json = json!({
+        "info": info,
+        "public_key": base64_url::encode(&deps.signer.get_public_key()),
+        "signature": base64_url::encode(&signature),
+    });
+    Ok(response_json.to_string())
+}
+
+/*
+  Detached signature of a read response. With
+  SIGN_READ_RESPONSES on, message and process reads carry
+  it in headers so a cache or mirror serving the body can
+  be checked against this su. signature covers the exact
+  body bytes and verifies against the public key in /info,
+  signer is the su address, the sha256 of that key.
+*/
+pub struct ResponseSignature {
+    pub signature: String,
+    pub signer: String,
+}
+
+pub async fn sign_response(
+    deps: &Arc<Deps>,
+    body: &str,
+) -> Result<Option<ResponseSignature>, String> {
+    if !deps.config.sign_read_responses() {
+        return Ok(None);
+    }
+    let signature = deps.signer.sign_tx(body.as_bytes().to_vec()).await?;
+    Ok(Some(ResponseSignature {
+        signature: base64_url::encode(&signature),
+        signer: base64_url::encode(&router::hash(&deps.signer.get_public_key())),
+    }))
+}
+
 pub async fn msg_deephash(
     gateway: Arc<dyn Gateway>,
     message: &Message,
-    bundle_bytes: &Vec<u8>,
+    bundle_bytes: &[u8],
 ) -> Result<Option<String>, String> {
     match &message.message {
         Some(m) => {
-            let bundle_data_item = match DataItem::from_bytes(bundle_bytes.clone()) {
+            let bundle_data_item = match DataItem::from_bytes(bundle_bytes.to_vec()) {
                 Ok(b) => b,
                 Err(_) => {
                     return Err("Error parsing bundle for message deephash".to_string());
@@ -751,13 +3543,12 @@ pub async fn msg_deephash(
             /*
               filter out base layer tx's
             */
-            match gateway.status(&message_id).await {
-                Ok(_) => return Ok(None),
-                Err(_) => ()
+            if gateway.status(message_id).await.is_ok() {
+                return Ok(None);
             };
 
-            let gateway_tx = gateway.gql_tx(&message_id).await?;
-            let tx_data = gateway.raw(&message_id).await?;
+            let gateway_tx = gateway.gql_tx(message_id).await?;
+            let tx_data = gateway.raw(message_id).await?;
             
             let dh = DataItem::deep_hash_fields(
                 gateway_tx.recipient,
@@ -771,3 +3562,62 @@ pub async fn msg_deephash(
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::config::AoConfig;
+    use crate::domain::core::api_error::{classify, ErrorCode};
+
+    fn config(deferred_dir: &str) -> AoConfig {
+        let vars = [
+            ("DATABASE_URL", "postgres://su"),
+            ("SU_WALLET_PATH", "wallet.json"),
+            ("GATEWAY_URL", "https://arweave.net"),
+            ("UPLOAD_NODE_URL", "https://upload"),
+            ("SCHEDULER_LIST_PATH", "schedulers.json"),
+            ("CURSOR_SECRET", "secret"),
+            ("DEFERRED_DIR", deferred_dir),
+        ];
+        AoConfig::from_vars(Some("su".to_string()), &|key| {
+            vars.iter()
+                .find(|(set, val)| *set == key && !val.is_empty())
+                .map(|(_, val)| val.to_string())
+                .ok_or(format!("{} is not set", key))
+        })
+        .unwrap()
+    }
+
+    fn message(schedule_at: i64) -> DataItem {
+        let tags = vec![
+            Tag::new("Type", "Message"),
+            Tag::new("Data-Protocol", "ao"),
+            Tag::new(SCHEDULE_AT_TAG, &schedule_at.to_string()),
+        ];
+        DataItem::new(vec![3; 32], b"data".to_vec(), tags, vec![1; 512]).unwrap()
+    }
+
+    #[test]
+    fn test_batch_refuses_schedule_at() {
+        let now = system_time_u64().unwrap() as i64;
+        let deferred = config("deferred");
+
+        let later = check_batch_deferral(&deferred, &message(now + 60_000)).unwrap_err();
+        assert!(
+            later.starts_with("Schedule-At is not supported in a batch"),
+            "{}",
+            later
+        );
+        assert_eq!(classify(&later), ErrorCode::BadRequest);
+
+        // too far ahead is refused as it is on its own
+        let too_far = message(now + 30 * 24 * 3600 * 1000);
+        assert!(check_batch_deferral(&deferred, &too_far)
+            .unwrap_err()
+            .contains("seconds ahead"));
+
+        // a time that has come, or deferral being off, schedules it with the rest
+        assert!(check_batch_deferral(&deferred, &message(now - 1000)).is_ok());
+        assert!(check_batch_deferral(&config(""), &message(now + 60_000)).is_ok());
+    }
+}
@@ -0,0 +1,211 @@
+use std::net::{IpAddr, SocketAddr};
+
+use super::concurrency::EndpointClass;
+
+/*
+  Prefix of the error a request gets when its client
+  address is denied, or not allowed, on its endpoint class
+*/
+pub const IP_FORBIDDEN: &str = "Client address not allowed";
+
+// a block of addresses, a bare address is a block of one
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cidr {
+    network: IpAddr,
+    prefix: u8,
+}
+
+impl Cidr {
+    pub fn parse(value: &str) -> Result<Self, String> {
+        let (address, prefix) = match value.split_once('/') {
+            Some((address, prefix)) => (address, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = address
+            .trim()
+            .parse()
+            .map_err(|_| format!("{} is not an address", address.trim()))?;
+        let network = network.to_canonical();
+        let bits = match network {
+            IpAddr::V4(_) => 32,
+            IpAddr::V6(_) => 128,
+        };
+        let prefix = match prefix {
+            Some(prefix) => prefix
+                .trim()
+                .parse::<u8>()
+                .ok()
+                .filter(|prefix| *prefix <= bits)
+                .ok_or_else(|| format!("{} has an invalid prefix length", value))?,
+            None => bits,
+        };
+        Ok(Cidr { network, prefix })
+    }
+
+    pub fn contains(&self, ip: &IpAddr) -> bool {
+        let (network, ip, bits) = match (self.network, ip.to_canonical()) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                (u32::from(network) as u128, u32::from(ip) as u128, 32)
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => (u128::from(network), u128::from(ip), 128),
+            _ => return false,
+        };
+        self.prefix == 0 || (network ^ ip) >> (bits - self.prefix) == 0
+    }
+}
+
+// a comma separated list of blocks, empty entries are skipped
+pub fn parse_cidrs(value: &str) -> Result<Vec<Cidr>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .map(Cidr::parse)
+        .collect()
+}
+
+/*
+  Who may call one class of endpoints. A denied address
+  is refused, and when allow lists anything only the
+  addresses in it get through.
+*/
+#[derive(Debug, Clone, Default)]
+pub struct IpRules {
+    pub allow: Vec<Cidr>,
+    pub deny: Vec<Cidr>,
+}
+
+impl IpRules {
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn admits(&self, ip: &IpAddr) -> bool {
+        !self.deny.iter().any(|cidr| cidr.contains(ip))
+            && (self.allow.is_empty() || self.allow.iter().any(|cidr| cidr.contains(ip)))
+    }
+}
+
+/*
+  The proxies whose X-Forwarded-For is believed and the
+  rules of each endpoint class, from TRUSTED_PROXIES and
+  the *_ALLOW_CIDRS and *_DENY_CIDRS variables
+*/
+#[derive(Debug, Clone, Default)]
+pub struct IpAccess {
+    pub trusted_proxies: Vec<Cidr>,
+    pub writes: IpRules,
+    pub reads: IpRules,
+    pub admin: IpRules,
+}
+
+impl IpAccess {
+    pub fn rules(&self, class: EndpointClass) -> &IpRules {
+        match class {
+            EndpointClass::Write => &self.writes,
+            EndpointClass::Read => &self.reads,
+            EndpointClass::Admin => &self.admin,
+        }
+    }
+
+    pub fn trusts(&self, ip: &IpAddr) -> bool {
+        self.trusted_proxies.iter().any(|cidr| cidr.contains(ip))
+    }
+
+    /*
+      The address of the client behind the peer that
+      connected. Every proxy appends the address it got
+      the request from to X-Forwarded-For, so reading the
+      list from the end, each entry is believed only while
+      the hop that added it is a trusted proxy. The first
+      address that isn't one is the client, whatever comes
+      before it could have been sent by the client itself.
+    */
+    pub fn client(&self, peer: IpAddr, forwarded_for: &[&str]) -> IpAddr {
+        let hops: Vec<&str> = forwarded_for
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        let mut client = peer.to_canonical();
+        for hop in hops.iter().rev() {
+            if !self.trusts(&client) {
+                break;
+            }
+            match parse_hop(hop) {
+                Some(ip) => client = ip,
+                None => break,
+            }
+        }
+        client
+    }
+}
+
+// an X-Forwarded-For entry, which some proxies write with a port
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    let ip = match hop.parse::<IpAddr>() {
+        Ok(ip) => ip,
+        Err(_) => hop.parse::<SocketAddr>().ok()?.ip(),
+    };
+    Some(ip.to_canonical())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ip(value: &str) -> IpAddr {
+        value.parse().unwrap()
+    }
+
+    #[test]
+    fn test_cidrs() {
+        let private = Cidr::parse("10.0.0.0/8").unwrap();
+        assert!(private.contains(&ip("10.1.2.3")));
+        assert!(private.contains(&ip("::ffff:10.1.2.3")));
+        assert!(!private.contains(&ip("11.0.0.1")));
+        assert!(Cidr::parse("0.0.0.0/0").unwrap().contains(&ip("8.8.8.8")));
+        assert!(Cidr::parse("::/0").unwrap().contains(&ip("2001:db8::1")));
+        assert!(Cidr::parse("2001:db8::/32")
+            .unwrap()
+            .contains(&ip("2001:db8:ffff::1")));
+        assert!(!Cidr::parse("192.168.1.7")
+            .unwrap()
+            .contains(&ip("192.168.1.8")));
+        assert!(Cidr::parse("10.0.0.0/33").is_err());
+        assert!(parse_cidrs("10.0.0.0/8, bogus").is_err());
+        assert_eq!(parse_cidrs(" ,10.0.0.0/8,").unwrap().len(), 1);
+
+        let rules = IpRules {
+            allow: parse_cidrs("10.0.0.0/8").unwrap(),
+            deny: parse_cidrs("10.9.0.0/16").unwrap(),
+        };
+        assert!(rules.admits(&ip("10.1.0.1")));
+        assert!(!rules.admits(&ip("10.9.0.1")));
+        assert!(!rules.admits(&ip("8.8.8.8")));
+        assert!(IpRules::default().admits(&ip("8.8.8.8")));
+    }
+
+    #[test]
+    fn test_client_behind_trusted_proxies() {
+        let access = IpAccess {
+            trusted_proxies: parse_cidrs("10.0.0.0/8").unwrap(),
+            ..IpAccess::default()
+        };
+
+        // a direct client can't pick its address
+        assert_eq!(access.client(ip("8.8.8.8"), &["1.1.1.1"]), ip("8.8.8.8"));
+        assert_eq!(
+            access.client(ip("10.0.0.2"), &["1.1.1.1, 9.9.9.9", "10.0.0.1"]),
+            ip("9.9.9.9")
+        );
+        assert_eq!(
+            access.client(ip("10.0.0.2"), &["9.9.9.9:4000"]),
+            ip("9.9.9.9")
+        );
+        // only proxies all the way, the first one is the client
+        assert_eq!(access.client(ip("10.0.0.2"), &["10.0.0.3"]), ip("10.0.0.3"));
+        assert_eq!(access.client(ip("10.0.0.2"), &["garbage"]), ip("10.0.0.2"));
+        assert_eq!(access.client(ip("10.0.0.2"), &[]), ip("10.0.0.2"));
+    }
+}
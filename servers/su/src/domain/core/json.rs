@@ -118,6 +118,98 @@ pub struct Edge {
     pub cursor: String,
 }
 
+/*
+  Compact description of a single assignment, this is
+  what gets published to external streams so consumers
+  can follow the schedule without fetching bundles.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AssignmentEvent {
+    pub process_id: String,
+    pub assignment_id: String,
+    pub message_id: String,
+    pub epoch: i32,
+    pub nonce: i32,
+    pub timestamp: i64,
+    pub hash_chain: String,
+    pub block_height: String,
+}
+
+/*
+  Running totals kept per process by the data store
+  so stats can be served without counting messages
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ProcessStats {
+    pub process_id: String,
+    pub message_count: i64,
+    pub byte_count: i64,
+    pub last_nonce: Option<i32>,
+}
+
+/*
+  A signed checkpoint of a process's assignment chain.
+  The next assignment's chain is derived from hash_chain
+  and assignment_id, so a verifier can check the history
+  after nonce from here instead of replaying from genesis.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChainSnapshot {
+    pub process_id: String,
+    pub epoch: i32,
+    pub nonce: i32,
+    pub timestamp: i64,
+    pub hash_chain: String,
+    pub assignment_id: String,
+    // base64url su signature over signed_payload, empty until signed
+    pub signature: String,
+}
+
+impl ChainSnapshot {
+    pub fn from_message(message: &Message) -> Result<Self, JsonErrorType> {
+        Ok(ChainSnapshot {
+            process_id: message.process_id()?,
+            epoch: message.epoch()?,
+            nonce: message.nonce()?,
+            timestamp: message.timestamp()?,
+            hash_chain: message.hash_chain()?,
+            assignment_id: message.assignment_id()?,
+            signature: String::new(),
+        })
+    }
+
+    // the fields in a fixed order, colon separated
+    pub fn signed_payload(&self) -> Vec<u8> {
+        format!(
+            "{}:{}:{}:{}:{}:{}",
+            self.process_id,
+            self.epoch,
+            self.nonce,
+            self.timestamp,
+            self.hash_chain,
+            self.assignment_id
+        )
+        .into_bytes()
+    }
+}
+
+/*
+  A process or message bundle in the export across
+  processes, which is ordered by the timestamp of the
+  assignment and then id, the assignment id of a message
+  or the process id of a process
+*/
+#[derive(Debug, Clone)]
+pub struct ExportItem {
+    // Process or Message
+    pub item_type: &'static str,
+    pub process_id: String,
+    pub id: String,
+    pub timestamp: i64,
+    pub nonce: i32,
+    pub bundle: Vec<u8>,
+}
+
 pub fn hash(data: &[u8]) -> Vec<u8> {
     let mut hasher = Sha256::new();
     hasher.update(data);
@@ -154,11 +246,9 @@ impl Process {
                 */
                 Ok(Process::from_bundle_no_assign(&bundle_data, &data_item)?)
             }
-            _ => {
-                return Err(JsonErrorType::JsonError(
-                    "Invalid Process Bundle".to_string(),
-                ))
-            }
+            _ => Err(JsonErrorType::JsonError(
+                "Invalid Process Bundle".to_string(),
+            )),
         }
     }
 
@@ -221,11 +311,11 @@ impl Process {
             .find(|tag| tag.name == "Timestamp")
             .ok_or("Timestamp tag not found")?;
 
-        let block = block_tag.value.clone();
-        let timestamp = timestamp_tag.value.clone().parse::<i64>()?;
+        let block = block_tag.value.to_string();
+        let timestamp = timestamp_tag.value.parse::<i64>()?;
 
         let owner = Owner {
-            address: address,
+            address,
             key: owner,
         };
 
@@ -237,14 +327,14 @@ impl Process {
 
         let process_inner = ProcessInner {
             process_id: id,
-            block: block,
-            timestamp: timestamp,
-            owner: owner,
-            tags: tags,
+            block,
+            timestamp,
+            owner,
+            tags,
             target,
             signature: Some(signature),
             anchor: anchor_r,
-            data: data,
+            data,
         };
 
         Ok(Process {
@@ -290,11 +380,11 @@ impl Process {
             .find(|tag| tag.name == "Timestamp")
             .ok_or("Timestamp tag not found")?;
 
-        let block = block_tag.value.clone();
-        let timestamp = timestamp_tag.value.clone().parse::<i64>()?;
+        let block = block_tag.value.to_string();
+        let timestamp = timestamp_tag.value.parse::<i64>()?;
 
         let owner = Owner {
-            address: address,
+            address,
             key: owner,
         };
 
@@ -306,10 +396,10 @@ impl Process {
 
         let process_inner = ProcessInner {
             process_id: id,
-            block: block,
-            timestamp: timestamp,
-            owner: owner,
-            tags: tags,
+            block,
+            timestamp,
+            owner,
+            tags,
             signature: Some(signature),
             anchor: anchor_r,
             data: None,
@@ -378,7 +468,7 @@ impl Process {
                     .iter()
                     .find(|tag| tag.name == "Hash-Chain")
                     .ok_or("Timestamp tag not found")?;
-                Ok(hash_chain_tag.value.clone())
+                Ok(hash_chain_tag.value.to_string())
             }
             None => Err(JsonErrorType::JsonError(
                 "No Assignment on Process".to_string(),
@@ -469,7 +559,7 @@ impl Message {
                 let assignment: AssignmentInner = AssignmentInner {
                     id: data_item.id(),
                     owner: Owner {
-                        address: address,
+                        address,
                         key: owner,
                     },
                     tags: data_item.tags(),
@@ -544,7 +634,7 @@ impl Message {
         let address = base64_url::encode(&address_hash);
 
         let owner = Owner {
-            address: address,
+            address,
             key: owner,
         };
 
@@ -581,7 +671,7 @@ impl Message {
                 let address = base64_url::encode(&address_hash);
 
                 let owner = Owner {
-                    address: address,
+                    address,
                     key: owner,
                 };
 
@@ -656,6 +746,18 @@ impl Message {
         Ok(nonce_tag.value.parse::<i32>()?)
     }
 
+    /*
+      Whether the message carries every one of tags, an
+      assignment of an existing transaction has no message
+      tags so it never does
+    */
+    pub fn has_tags(&self, tags: &[Tag]) -> bool {
+        match &self.message {
+            Some(message) => tags.iter().all(|tag| message.tags.contains(tag)),
+            None => false,
+        }
+    }
+
     pub fn timestamp(&self) -> Result<i64, JsonErrorType> {
         let timestamp_tag = self
             .assignment
@@ -673,7 +775,7 @@ impl Message {
             .iter()
             .find(|tag| tag.name == "Hash-Chain")
             .ok_or("Timestamp tag not found")?;
-        Ok(hash_chain_tag.value.clone())
+        Ok(hash_chain_tag.value.to_string())
     }
 
     pub fn block_height(&self) -> Result<String, JsonErrorType> {
@@ -683,7 +785,7 @@ impl Message {
             .iter()
             .find(|tag| tag.name == "Block-Height")
             .ok_or("Block-Height tag not found")?;
-        Ok(block_height_tag.value.clone())
+        Ok(block_height_tag.value.to_string())
     }
 
     pub fn message_id(&self) -> Result<String, JsonErrorType> {
@@ -696,7 +798,7 @@ impl Message {
             .iter()
             .find(|tag| tag.name == "Message")
             .ok_or("Message tag not found")?;
-        Ok(message_tag.value.clone())
+        Ok(message_tag.value.to_string())
     }
 
     pub fn assignment_id(&self) -> Result<String, JsonErrorType> {
@@ -711,7 +813,7 @@ impl Message {
             .iter()
             .find(|tag| tag.name == "Process")
             .ok_or("Process tag not found")?;
-        Ok(process_tag.value.clone())
+        Ok(process_tag.value.to_string())
     }
 
     /*
@@ -735,10 +837,10 @@ impl Message {
                     old message structure so we have to break
                     down the json by field
                 */
-                let old_message = extract_val(&value, "message")?;
-                let message_owner = serde_json::from_value(extract_val(&value, "owner")?)?;
-                let message_data = extract_option_str(&value, "data");
-                let message_target = extract_option_str(&value, "process_id");
+                let old_message = extract_val(value, "message")?;
+                let message_owner = serde_json::from_value(extract_val(value, "owner")?)?;
+                let message_data = extract_option_str(value, "data");
+                let message_target = extract_option_str(value, "process_id");
 
                 let message_id = str_val(&extract_val(&old_message, "id")?)?;
                 let message_tags = to_tags(&extract_val(&old_message, "tags")?)?;
@@ -776,7 +878,7 @@ impl Message {
                 let assignment: AssignmentInner = AssignmentInner {
                     id: bundle_data_item.id(),
                     owner: Owner {
-                        address: address,
+                        address,
                         key: owner,
                     },
                     tags: bundle_data_item.tags(),
@@ -821,7 +923,7 @@ fn to_tags(val: &serde_json::Value) -> Result<Vec<Tag>, JsonErrorType> {
         for tag in tags_array {
             if let (Some(name), Some(value)) = (tag.get("name"), tag.get("value")) {
                 if let (Some(name_str), Some(value_str)) = (name.as_str(), value.as_str()) {
-                    tags.push(Tag::new(&name_str, &value_str));
+                    tags.push(Tag::new(name_str, value_str));
                 }
             }
         }
@@ -831,6 +933,21 @@ fn to_tags(val: &serde_json::Value) -> Result<Vec<Tag>, JsonErrorType> {
     }
 }
 
+impl AssignmentEvent {
+    pub fn from_message(message: &Message) -> Result<Self, JsonErrorType> {
+        Ok(AssignmentEvent {
+            process_id: message.process_id()?,
+            assignment_id: message.assignment_id()?,
+            message_id: message.message_id()?,
+            epoch: message.epoch()?,
+            nonce: message.nonce()?,
+            timestamp: message.timestamp()?,
+            hash_chain: message.hash_chain()?,
+            block_height: message.block_height()?,
+        })
+    }
+}
+
 impl PaginatedMessages {
     pub fn from_messages(
         messages: Vec<Message>,
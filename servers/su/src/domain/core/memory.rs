@@ -0,0 +1,223 @@
+use std::fs;
+use std::sync::atomic::{AtomicU64, AtomicU8, Ordering};
+use std::sync::Arc;
+
+/*
+  Memory guardrails for the write path. Resident memory
+  is sampled on an interval and the bytes of writes still
+  being processed are counted on top of it, so a burst of
+  large writes between samples is caught too.
+
+  Past the soft limit the largest write accepted shrinks
+  in proportion to the room left before the hard limit,
+  so the biggest writes are shed first. At the hard limit
+  every write is refused until usage drops again. Reads
+  are never shed.
+*/
+
+// the largest body the server accepts at all
+const MAX_WRITE_SIZE: u64 = 10485760;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ShedLevel {
+    None = 0,
+    Large = 1,
+    All = 2,
+}
+
+impl ShedLevel {
+    fn from_u8(level: u8) -> Self {
+        match level {
+            0 => ShedLevel::None,
+            1 => ShedLevel::Large,
+            _ => ShedLevel::All,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ShedLevel::None => "none",
+            ShedLevel::Large => "large writes",
+            ShedLevel::All => "all writes",
+        }
+    }
+}
+
+pub struct MemoryGuard {
+    soft_limit: u64,
+    hard_limit: u64,
+    rss: AtomicU64,
+    in_flight: Arc<AtomicU64>,
+    level: AtomicU8,
+}
+
+/*
+  Held for as long as a write is being processed, its
+  bytes stop counting against the limits when dropped
+*/
+pub struct InFlightWrite {
+    in_flight: Arc<AtomicU64>,
+    size: u64,
+}
+
+impl Drop for InFlightWrite {
+    fn drop(&mut self) {
+        self.in_flight.fetch_sub(self.size, Ordering::SeqCst);
+    }
+}
+
+impl MemoryGuard {
+    /*
+      A hard limit of 0 turns shedding off, a soft limit
+      of 0 or above the hard limit jumps straight from
+      accepting everything to refusing every write
+    */
+    pub fn new(soft_limit: u64, hard_limit: u64) -> Self {
+        let soft_limit = match soft_limit {
+            0 => hard_limit,
+            soft => soft.min(hard_limit),
+        };
+        MemoryGuard {
+            soft_limit,
+            hard_limit,
+            rss: AtomicU64::new(0),
+            in_flight: Arc::new(AtomicU64::new(0)),
+            level: AtomicU8::new(ShedLevel::None as u8),
+        }
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.hard_limit > 0
+    }
+
+    pub fn rss(&self) -> u64 {
+        self.rss.load(Ordering::SeqCst)
+    }
+
+    pub fn in_flight(&self) -> u64 {
+        self.in_flight.load(Ordering::SeqCst)
+    }
+
+    pub fn level(&self) -> ShedLevel {
+        ShedLevel::from_u8(self.level.load(Ordering::SeqCst))
+    }
+
+    /*
+      Take a new sample of resident memory, returns the
+      shed level when it changed since the last sample
+    */
+    pub fn sample(&self) -> Option<ShedLevel> {
+        if !self.enabled() {
+            return None;
+        }
+        if let Some(rss) = read_rss() {
+            self.rss.store(rss, Ordering::SeqCst);
+        }
+
+        let level = self.level_at(self.rss() + self.in_flight());
+        let previous = self.level.swap(level as u8, Ordering::SeqCst);
+        match previous == level as u8 {
+            true => None,
+            false => Some(level),
+        }
+    }
+
+    /*
+      Count a write of size bytes against the limits or
+      refuse it if it is over the size still accepted
+    */
+    pub fn admit(&self, size: usize) -> Result<InFlightWrite, String> {
+        let size = size as u64;
+        if self.enabled() {
+            let usage = self.rss() + self.in_flight() + size;
+            let limit = self.write_limit(usage);
+            if size > limit {
+                return Err(match limit {
+                    0 => "Memory usage is at its limit, writes are paused".to_string(),
+                    _ => format!(
+                        "Memory usage is high, only writes up to {} bytes are accepted",
+                        limit
+                    ),
+                });
+            }
+        }
+
+        self.in_flight.fetch_add(size, Ordering::SeqCst);
+        Ok(InFlightWrite {
+            in_flight: self.in_flight.clone(),
+            size,
+        })
+    }
+
+    fn level_at(&self, usage: u64) -> ShedLevel {
+        if usage >= self.hard_limit {
+            ShedLevel::All
+        } else if usage >= self.soft_limit {
+            ShedLevel::Large
+        } else {
+            ShedLevel::None
+        }
+    }
+
+    // the largest write accepted at the given usage
+    fn write_limit(&self, usage: u64) -> u64 {
+        match self.level_at(usage) {
+            ShedLevel::None => MAX_WRITE_SIZE,
+            ShedLevel::All => 0,
+            ShedLevel::Large => {
+                let room = (self.hard_limit - usage) as u128;
+                let range = (self.hard_limit - self.soft_limit).max(1) as u128;
+                (MAX_WRITE_SIZE as u128 * room / range) as u64
+            }
+        }
+    }
+}
+
+/*
+  Resident set size from /proc, None where it is not
+  available so the last sample is kept
+*/
+fn read_rss() -> Option<u64> {
+    let status = fs::read_to_string("/proc/self/status").ok()?;
+    let line = status.lines().find(|line| line.starts_with("VmRSS:"))?;
+    let kb = line.split_whitespace().nth(1)?.parse::<u64>().ok()?;
+    Some(kb * 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sheds_largest_writes_first() {
+        let guard = MemoryGuard::new(1000, 2000);
+
+        assert_eq!(guard.write_limit(500), MAX_WRITE_SIZE);
+        assert_eq!(guard.write_limit(1500), MAX_WRITE_SIZE / 2);
+        assert_eq!(guard.write_limit(2000), 0);
+        assert!(guard.write_limit(1900) < guard.write_limit(1100));
+    }
+
+    #[test]
+    fn counts_in_flight_writes() {
+        let guard = MemoryGuard::new(0, 1000);
+        guard.rss.store(400, Ordering::SeqCst);
+
+        let first = guard.admit(300).unwrap();
+        assert_eq!(guard.in_flight(), 300);
+        // 400 resident plus 300 in flight leaves no room for 300 more
+        assert!(guard.admit(300).is_err());
+
+        drop(first);
+        assert_eq!(guard.in_flight(), 0);
+        assert!(guard.admit(300).is_ok());
+    }
+
+    #[test]
+    fn disabled_without_hard_limit() {
+        let guard = MemoryGuard::new(0, 0);
+        assert!(!guard.enabled());
+        assert!(guard.admit(MAX_WRITE_SIZE as usize).is_ok());
+        assert_eq!(guard.sample(), None);
+    }
+}
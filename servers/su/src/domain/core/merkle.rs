@@ -0,0 +1,189 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/*
+  Merkle trees over the items of the bundles the su
+  uploads. The root is signed into each bundle as its
+  Merkle-Root tag, so a light client holding an item id,
+  its proof and the bundle's signed header can check the
+  item is in the bundle without downloading the bundle.
+
+  Leaves are sha256(0x00 || item id bytes) and nodes
+  sha256(0x01 || left || right). A node without a right
+  sibling is carried up a level unchanged, leaves are
+  never duplicated.
+*/
+
+pub const MERKLE_ROOT_TAG: &str = "Merkle-Root";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InclusionProof {
+    pub item_id: String,
+    pub bundle_id: String,
+    pub root: String,
+    pub index: i32,
+    pub leaf_count: i32,
+    // base64url sibling hashes from the leaf up
+    pub path: Vec<String>,
+}
+
+type Hash = [u8; 32];
+
+fn leaf_hash(item_id: &str) -> Result<Hash, String> {
+    let id_bytes =
+        base64_url::decode(item_id).map_err(|_| format!("Invalid item id {}", item_id))?;
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    hasher.update(&id_bytes);
+    Ok(hasher.finalize().into())
+}
+
+fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+// every level of the tree, the leaves first and the root last
+fn levels(item_ids: &[String]) -> Result<Vec<Vec<Hash>>, String> {
+    if item_ids.is_empty() {
+        return Err("Can't build a merkle tree without items".to_string());
+    }
+    let leaves = item_ids
+        .iter()
+        .map(|id| leaf_hash(id))
+        .collect::<Result<Vec<Hash>, String>>()?;
+
+    let mut levels = vec![leaves];
+    while levels[levels.len() - 1].len() > 1 {
+        let next = levels[levels.len() - 1]
+            .chunks(2)
+            .map(|pair| match pair {
+                [left, right] => node_hash(left, right),
+                [single] => *single,
+                _ => unreachable!(),
+            })
+            .collect();
+        levels.push(next);
+    }
+    Ok(levels)
+}
+
+pub fn merkle_root(item_ids: &[String]) -> Result<String, String> {
+    let levels = levels(item_ids)?;
+    Ok(base64_url::encode(&levels[levels.len() - 1][0]))
+}
+
+// one proof per item, in bundle order
+pub fn inclusion_proofs(bundle_id: &str, item_ids: &[String]) -> Result<Vec<InclusionProof>, String> {
+    let levels = levels(item_ids)?;
+    let root = base64_url::encode(&levels[levels.len() - 1][0]);
+
+    Ok(item_ids
+        .iter()
+        .enumerate()
+        .map(|(index, item_id)| {
+            let mut path = vec![];
+            let mut position = index;
+            for level in &levels[..levels.len() - 1] {
+                let sibling = position ^ 1;
+                if sibling < level.len() {
+                    path.push(base64_url::encode(&level[sibling]));
+                }
+                position /= 2;
+            }
+            InclusionProof {
+                item_id: item_id.clone(),
+                bundle_id: bundle_id.to_string(),
+                root: root.clone(),
+                index: index as i32,
+                leaf_count: item_ids.len() as i32,
+                path,
+            }
+        })
+        .collect())
+}
+
+impl InclusionProof {
+    // recomputes the root from item_id and path
+    pub fn verify(&self) -> bool {
+        let mut hash = match leaf_hash(&self.item_id) {
+            Ok(hash) => hash,
+            Err(_) => return false,
+        };
+        if self.index < 0 || self.index >= self.leaf_count {
+            return false;
+        }
+
+        let mut position = self.index as usize;
+        let mut width = self.leaf_count as usize;
+        let mut path = self.path.iter();
+        while width > 1 {
+            let has_sibling = position % 2 == 1 || position + 1 < width;
+            if has_sibling {
+                let sibling: Hash = match path
+                    .next()
+                    .and_then(|s| base64_url::decode(s).ok())
+                    .and_then(|bytes| bytes.try_into().ok())
+                {
+                    Some(sibling) => sibling,
+                    None => return false,
+                };
+                hash = match position % 2 {
+                    1 => node_hash(&sibling, &hash),
+                    _ => node_hash(&hash, &sibling),
+                };
+            }
+            position /= 2;
+            width = width.div_ceil(2);
+        }
+
+        path.next().is_none() && base64_url::encode(&hash) == self.root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn ids(count: u8) -> Vec<String> {
+        (0..count)
+            .map(|i| base64_url::encode(&Sha256::digest([i])))
+            .collect()
+    }
+
+    #[test]
+    fn test_every_item_proves_against_the_root() {
+        for count in 1..=7 {
+            let item_ids = ids(count);
+            let root = merkle_root(&item_ids).unwrap();
+            let proofs = inclusion_proofs("bundle", &item_ids).unwrap();
+            assert_eq!(proofs.len(), count as usize);
+            for proof in proofs {
+                assert_eq!(proof.root, root);
+                assert!(proof.verify(), "{} items, index {}", count, proof.index);
+            }
+        }
+        assert!(merkle_root(&[]).is_err());
+    }
+
+    #[test]
+    fn test_tampered_proofs_fail() {
+        let item_ids = ids(5);
+        let proofs = inclusion_proofs("bundle", &item_ids).unwrap();
+
+        let mut wrong_item = proofs[0].clone();
+        wrong_item.item_id = item_ids[1].clone();
+        assert!(!wrong_item.verify());
+
+        let mut wrong_index = proofs[2].clone();
+        wrong_index.index = 3;
+        assert!(!wrong_index.verify());
+
+        let mut short_path = proofs[4].clone();
+        short_path.path.pop();
+        assert!(!short_path.verify());
+    }
+}
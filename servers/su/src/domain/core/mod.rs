@@ -10,14 +10,83 @@ mod json;
 // tags impl
 mod tags;
 
+// wallet addresses per signature type
+mod address;
+
+// signed pagination cursors
+mod cursor;
+
 // traits for injecting dependencies
 pub mod dal;
 
+// merkle inclusion proofs for uploaded bundles
+pub mod merkle;
+
+// hash chained log of admin changes
+pub mod audit;
+
+// protocol variants a process can be scheduled under
+pub mod variant;
+
 // mutex locked scheduling data
 pub mod scheduler;
 
+// memory guardrails for the write path
+pub mod memory;
+
+// per wallet write limits
+pub mod rate_limit;
+
+// per endpoint class concurrency limits
+pub mod concurrency;
+
+// verified signature cache for the write path
+pub mod verify_cache;
+
+// addresses of the owners a router already hashed
+pub mod owner_cache;
+
+// server sent events for newly scheduled messages
+pub mod subscriptions;
+
+// the su's Scheduler-Location record on chain
+pub mod registration;
+
 // main business logic
 pub mod flows;
 
 // router logic
 pub mod router;
+
+// reading the scheduler list file in its versioned formats
+pub mod scheduler_list;
+
+// su router export and import of the routing tables
+pub mod router_state;
+
+// rolling the spawn audit log up into hours and days
+pub mod spawn_rollup;
+
+// writing flat tables in the parquet format
+pub mod parquet;
+
+// su export-parquet of assignments and messages for analytics
+pub mod analytics_export;
+
+// periodic self check of the su's signing
+pub mod signing_check;
+
+// spans of traced requests and their propagation
+pub mod trace;
+
+// stable codes of the json errors the api returns
+pub mod api_error;
+
+// the report of su check
+pub mod deploy_check;
+
+// client address resolution and per class address rules
+pub mod ip_access;
+
+// the test items and response checks of su-conformance
+pub mod conformance;
@@ -0,0 +1,81 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use lru::LruCache;
+
+use super::address::arweave_address;
+use super::bytes::DataItem;
+
+/*
+  The addresses of the owners a router has already
+  hashed. The same wallets spawn and send over and over,
+  so most items skip decoding the owner and hashing it.
+  An owner has two: the Arweave style address the
+  denylist and rate limits use, and the address in its
+  signature type's format that wallets_to_route lists.
+  The key is the owner with its signature type, since
+  an Ed25519 and a Solana key of the same bytes are
+  written differently.
+*/
+pub struct OwnerCache {
+    addresses: Option<Mutex<LruCache<(u16, String), OwnerAddresses>>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnerAddresses {
+    pub owner_address: String,
+    pub route_address: String,
+}
+
+impl OwnerCache {
+    // a size of 0 turns the cache off
+    pub fn new(size: usize) -> Self {
+        OwnerCache {
+            addresses: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+        }
+    }
+
+    pub fn addresses(&self, item: &DataItem) -> Result<OwnerAddresses, String> {
+        let owner = item.owner();
+        let key = (item.signature_type().as_u16(), owner);
+        if let Some(addresses) = &self.addresses {
+            if let Some(found) = addresses.lock().unwrap().get(&key) {
+                return Ok(found.clone());
+            }
+        }
+
+        let owner_bytes =
+            base64_url::decode(&key.1).map_err(|_| "Failed to parse owner".to_string())?;
+        let found = OwnerAddresses {
+            owner_address: arweave_address(&owner_bytes),
+            route_address: item.owner_address(),
+        };
+        if let Some(addresses) = &self.addresses {
+            addresses.lock().unwrap().put(key, found.clone());
+        }
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cached_addresses_match_computed_ones() {
+        let item = DataItem::new(vec![], vec![], vec![], vec![7; 512]).unwrap();
+        let expected = OwnerCache::new(0).addresses(&item).unwrap();
+        assert_eq!(expected.owner_address, arweave_address(&[7; 512]));
+
+        let cache = OwnerCache::new(1);
+        assert_eq!(cache.addresses(&item).unwrap(), expected);
+        assert_eq!(cache.addresses(&item).unwrap(), expected);
+
+        // the least recently used owner makes room
+        let other = DataItem::new(vec![], vec![], vec![], vec![8; 512]).unwrap();
+        cache.addresses(&other).unwrap();
+        let cached = cache.addresses.as_ref().unwrap().lock().unwrap();
+        assert_eq!(cached.len(), 1);
+        assert!(cached.contains(&(1, other.owner())));
+    }
+}
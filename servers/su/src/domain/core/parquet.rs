@@ -0,0 +1,410 @@
+/*
+  Just enough of the Parquet format to write flat tables
+  for analytics tools like Spark and DuckDB. A file holds
+  a single row group, every column chunk a single data
+  page, values are PLAIN encoded and uncompressed. The
+  metadata is thrift in its compact protocol.
+*/
+
+const MAGIC: &[u8] = b"PAR1";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColumnType {
+    Int32,
+    Int64,
+    // a utf8 BYTE_ARRAY
+    String,
+}
+
+impl ColumnType {
+    // the physical type in the file
+    fn physical(&self) -> i32 {
+        match self {
+            ColumnType::Int32 => 1,
+            ColumnType::Int64 => 2,
+            ColumnType::String => 6,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Column {
+    pub name: &'static str,
+    pub kind: ColumnType,
+    pub optional: bool,
+}
+
+impl Column {
+    pub fn required(name: &'static str, kind: ColumnType) -> Self {
+        Column {
+            name,
+            kind,
+            optional: false,
+        }
+    }
+
+    pub fn optional(name: &'static str, kind: ColumnType) -> Self {
+        Column {
+            name,
+            kind,
+            optional: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Int32(i32),
+    Int64(i64),
+    String(String),
+    Null,
+}
+
+/*
+  A file of rows with the columns given, every row has a
+  value for each column in order
+*/
+pub fn write_parquet(columns: &[Column], rows: &[Vec<Value>]) -> Result<Vec<u8>, String> {
+    let mut out = MAGIC.to_vec();
+    let mut chunks = vec![];
+    let mut total_size = 0;
+    for (i, column) in columns.iter().enumerate() {
+        let page = column_page(column, rows.iter().map(|row| &row[i]))?;
+        let header = Thrift::Struct(vec![
+            // DATA_PAGE
+            (1, Thrift::I32(0)),
+            (2, Thrift::I32(page.len() as i32)),
+            (3, Thrift::I32(page.len() as i32)),
+            (
+                5,
+                Thrift::Struct(vec![
+                    (1, Thrift::I32(rows.len() as i32)),
+                    // PLAIN values, RLE levels
+                    (2, Thrift::I32(0)),
+                    (3, Thrift::I32(3)),
+                    (4, Thrift::I32(3)),
+                ]),
+            ),
+        ])
+        .encode();
+
+        let offset = out.len() as i64;
+        let size = (header.len() + page.len()) as i64;
+        out.extend(header);
+        out.extend(page);
+        total_size += size;
+
+        chunks.push(Thrift::Struct(vec![
+            (2, Thrift::I64(offset)),
+            (
+                3,
+                Thrift::Struct(vec![
+                    (1, Thrift::I32(column.kind.physical())),
+                    (2, Thrift::List(5, vec![Thrift::I32(0), Thrift::I32(3)])),
+                    (
+                        3,
+                        Thrift::List(8, vec![Thrift::Binary(column.name.as_bytes().to_vec())]),
+                    ),
+                    // UNCOMPRESSED
+                    (4, Thrift::I32(0)),
+                    (5, Thrift::I64(rows.len() as i64)),
+                    (6, Thrift::I64(size)),
+                    (7, Thrift::I64(size)),
+                    (9, Thrift::I64(offset)),
+                ]),
+            ),
+        ]));
+    }
+
+    let mut schema = vec![Thrift::Struct(vec![
+        (4, Thrift::Binary(b"schema".to_vec())),
+        (5, Thrift::I32(columns.len() as i32)),
+    ])];
+    for column in columns {
+        let mut element = vec![
+            (1, Thrift::I32(column.kind.physical())),
+            // REQUIRED or OPTIONAL
+            (3, Thrift::I32(column.optional as i32)),
+            (4, Thrift::Binary(column.name.as_bytes().to_vec())),
+        ];
+        if column.kind == ColumnType::String {
+            // converted type UTF8
+            element.push((6, Thrift::I32(0)));
+        }
+        schema.push(Thrift::Struct(element));
+    }
+
+    let footer = Thrift::Struct(vec![
+        (1, Thrift::I32(1)),
+        (2, Thrift::List(12, schema)),
+        (3, Thrift::I64(rows.len() as i64)),
+        (
+            4,
+            Thrift::List(
+                12,
+                vec![Thrift::Struct(vec![
+                    (1, Thrift::List(12, chunks)),
+                    (2, Thrift::I64(total_size)),
+                    (3, Thrift::I64(rows.len() as i64)),
+                ])],
+            ),
+        ),
+        (6, Thrift::Binary(b"ao su".to_vec())),
+    ])
+    .encode();
+
+    out.extend(&footer);
+    out.extend((footer.len() as u32).to_le_bytes());
+    out.extend(MAGIC);
+    Ok(out)
+}
+
+/*
+  The page body of one column, the definition levels of
+  an optional column followed by its non null values
+*/
+fn column_page<'a>(
+    column: &Column,
+    values: impl Iterator<Item = &'a Value>,
+) -> Result<Vec<u8>, String> {
+    let mut defined = vec![];
+    let mut encoded = vec![];
+    for value in values {
+        match (value, column.kind) {
+            (Value::Null, _) if column.optional => {
+                defined.push(false);
+                continue;
+            }
+            (Value::Int32(v), ColumnType::Int32) => encoded.extend(v.to_le_bytes()),
+            (Value::Int64(v), ColumnType::Int64) => encoded.extend(v.to_le_bytes()),
+            (Value::String(v), ColumnType::String) => {
+                encoded.extend((v.len() as u32).to_le_bytes());
+                encoded.extend(v.as_bytes());
+            }
+            (value, kind) => {
+                return Err(format!(
+                    "Column {} is {:?}, got {:?}",
+                    column.name, kind, value
+                ))
+            }
+        }
+        defined.push(true);
+    }
+
+    let mut page = vec![];
+    if column.optional {
+        let levels = definition_levels(&defined);
+        page.extend((levels.len() as u32).to_le_bytes());
+        page.extend(levels);
+    }
+    page.extend(encoded);
+    Ok(page)
+}
+
+/*
+  Definition levels of a top level optional column, 1
+  for a value and 0 for a null, in the RLE runs of the
+  hybrid encoding with a bit width of 1
+*/
+fn definition_levels(defined: &[bool]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+    while i < defined.len() {
+        let run = defined[i..]
+            .iter()
+            .take_while(|value| **value == defined[i])
+            .count();
+        varint(&mut out, (run as u64) << 1);
+        out.push(defined[i] as u8);
+        i += run;
+    }
+    out
+}
+
+fn varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn zigzag(value: i64) -> u64 {
+    ((value << 1) ^ (value >> 63)) as u64
+}
+
+// the thrift values the metadata is made of
+enum Thrift {
+    I32(i32),
+    I64(i64),
+    Binary(Vec<u8>),
+    // fields in increasing id order
+    Struct(Vec<(i16, Thrift)>),
+    // element type and elements
+    List(u8, Vec<Thrift>),
+}
+
+impl Thrift {
+    fn compact_type(&self) -> u8 {
+        match self {
+            Thrift::I32(_) => 5,
+            Thrift::I64(_) => 6,
+            Thrift::Binary(_) => 8,
+            Thrift::List(_, _) => 9,
+            Thrift::Struct(_) => 12,
+        }
+    }
+
+    fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        self.write(&mut out);
+        out
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Thrift::I32(value) => varint(out, zigzag(*value as i64)),
+            Thrift::I64(value) => varint(out, zigzag(*value)),
+            Thrift::Binary(bytes) => {
+                varint(out, bytes.len() as u64);
+                out.extend(bytes);
+            }
+            Thrift::Struct(fields) => {
+                let mut last = 0;
+                for (id, value) in fields {
+                    let delta = id - last;
+                    if (1..=15).contains(&delta) {
+                        out.push(((delta as u8) << 4) | value.compact_type());
+                    } else {
+                        out.push(value.compact_type());
+                        varint(out, zigzag(*id as i64));
+                    }
+                    value.write(out);
+                    last = *id;
+                }
+                out.push(0);
+            }
+            Thrift::List(kind, elements) => {
+                if elements.len() < 15 {
+                    out.push(((elements.len() as u8) << 4) | kind);
+                } else {
+                    out.push(0xf0 | kind);
+                    varint(out, elements.len() as u64);
+                }
+                for element in elements {
+                    element.write(out);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_layout() {
+        let columns = [
+            Column::required("id", ColumnType::String),
+            Column::required("nonce", ColumnType::Int32),
+            Column::optional("target", ColumnType::String),
+        ];
+        let rows = vec![
+            vec![Value::String("a".to_string()), Value::Int32(1), Value::Null],
+            vec![
+                Value::String("b".to_string()),
+                Value::Int32(2),
+                Value::String("pid".to_string()),
+            ],
+        ];
+        let file = write_parquet(&columns, &rows).unwrap();
+        assert_eq!(&file[..4], MAGIC);
+        assert_eq!(&file[file.len() - 4..], MAGIC);
+
+        // the footer length points back at the end of the last page
+        let length = u32::from_le_bytes(file[file.len() - 8..file.len() - 4].try_into().unwrap());
+        let footer_start = file.len() - 8 - length as usize;
+        assert!(file[..footer_start].ends_with(b"\x03\x00\x00\x00pid"));
+
+        // a value of the wrong type is refused
+        let bad = vec![vec![Value::Int32(1), Value::Int32(1), Value::Null]];
+        assert!(write_parquet(&columns, &bad).is_err());
+    }
+
+    fn read_back_table() -> (Vec<Column>, Vec<Vec<Value>>) {
+        let columns = vec![
+            Column::required("id", ColumnType::String),
+            Column::required("nonce", ColumnType::Int32),
+            Column::required("timestamp", ColumnType::Int64),
+            Column::optional("epoch", ColumnType::Int32),
+            Column::optional("target", ColumnType::String),
+        ];
+        let rows = vec![
+            vec![
+                Value::String("a".to_string()),
+                Value::Int32(0),
+                Value::Int64(1712345678901),
+                Value::Int32(0),
+                Value::Null,
+            ],
+            vec![
+                Value::String("bé".to_string()),
+                Value::Int32(-1),
+                Value::Int64(-5),
+                Value::Null,
+                Value::String("pid".to_string()),
+            ],
+            vec![
+                Value::String("".to_string()),
+                Value::Int32(i32::MAX),
+                Value::Int64(i64::MAX),
+                Value::Int32(7),
+                Value::String("".to_string()),
+            ],
+        ];
+        (columns, rows)
+    }
+
+    /*
+      testdata/read_back.parquet was written from this table
+      and read back with the parquet crate (53.4.1), which
+      returned the same rows and a schema of BYTE_ARRAY,
+      INT32 and INT64 columns, the last two optional. A
+      change to the bytes needs the file read back again
+      with a real reader before it is replaced.
+    */
+    #[test]
+    fn test_matches_file_read_back() {
+        let (columns, rows) = read_back_table();
+        let expected = include_bytes!(concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/testdata/read_back.parquet"
+        ));
+        assert_eq!(write_parquet(&columns, &rows).unwrap(), expected.to_vec());
+    }
+
+    #[test]
+    fn test_definition_levels_are_runs() {
+        assert_eq!(
+            definition_levels(&[true, true, true, false, true]),
+            vec![6, 1, 2, 0, 2, 1]
+        );
+        assert_eq!(definition_levels(&[true; 200]), vec![0x90, 0x03, 1]);
+    }
+
+    #[test]
+    fn test_thrift_compact_encoding() {
+        let encoded = Thrift::Struct(vec![
+            (1, Thrift::I32(-1)),
+            (2, Thrift::List(8, vec![Thrift::Binary(b"x".to_vec())])),
+            // a jump of more than 15 ids writes the id itself
+            (20, Thrift::I64(300)),
+        ])
+        .encode();
+        assert_eq!(
+            encoded,
+            vec![0x15, 0x01, 0x19, 0x18, 0x01, b'x', 0x06, 0x28, 0xd8, 0x04, 0x00]
+        );
+    }
+}
@@ -0,0 +1,122 @@
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+
+// windows are swept once this many keys are tracked
+const PRUNE_AT: usize = 100000;
+
+/*
+  Counts the requests of each key, a wallet, an api key
+  or a client address, in fixed windows and refuses them
+  once the window's limit is reached. A limit of 0 turns
+  it off. Writes without an owner, the assignments of
+  existing transactions, are not counted.
+*/
+pub struct WindowLimiter {
+    limit: AtomicU32,
+    window: Duration,
+    windows: DashMap<String, (Instant, u32)>,
+}
+
+impl WindowLimiter {
+    pub fn new(limit: u32, window: Duration) -> Self {
+        WindowLimiter {
+            limit: AtomicU32::new(limit),
+            window,
+            windows: DashMap::new(),
+        }
+    }
+
+    pub fn limit(&self) -> u32 {
+        self.limit.load(Ordering::Relaxed)
+    }
+
+    // a configuration reload, the counts of open windows carry over
+    pub fn set_limit(&self, limit: u32) {
+        self.limit.store(limit, Ordering::Relaxed);
+    }
+
+    // counts the request and returns false when it is over the limit
+    pub fn allow(&self, key: &str) -> bool {
+        self.allow_with(key, self.limit())
+    }
+
+    // like allow with a limit of its own for this key
+    pub fn allow_with(&self, key: &str, limit: u32) -> bool {
+        if limit == 0 || key.is_empty() {
+            return true;
+        }
+
+        let now = Instant::now();
+        if self.windows.len() >= PRUNE_AT {
+            self.windows
+                .retain(|_, (start, _)| now.duration_since(*start) < self.window);
+        }
+
+        let mut entry = self.windows.entry(key.to_string()).or_insert((now, 0));
+        let (start, count) = entry.value_mut();
+        if now.duration_since(*start) >= self.window {
+            *start = now;
+            *count = 0;
+        }
+        if *count >= limit {
+            return false;
+        }
+        *count += 1;
+        true
+    }
+}
+
+pub struct RateLimits {
+    pub spawns: WindowLimiter,
+    pub messages: WindowLimiter,
+    // reads without an api key, by client address
+    pub anonymous_reads: WindowLimiter,
+    // reads with an api key, by key id
+    pub keyed_reads: WindowLimiter,
+}
+
+impl RateLimits {
+    pub fn new(
+        spawns_per_hour: u32,
+        messages_per_minute: u32,
+        anonymous_reads_per_minute: u32,
+        keyed_reads_per_minute: u32,
+    ) -> Self {
+        let minute = Duration::from_secs(60);
+        RateLimits {
+            spawns: WindowLimiter::new(spawns_per_hour, Duration::from_secs(3600)),
+            messages: WindowLimiter::new(messages_per_minute, minute),
+            anonymous_reads: WindowLimiter::new(anonymous_reads_per_minute, minute),
+            keyed_reads: WindowLimiter::new(keyed_reads_per_minute, minute),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_limits_each_key_per_window() {
+        let limiter = WindowLimiter::new(2, Duration::from_millis(50));
+        assert!(limiter.allow("a"));
+        assert!(limiter.allow("a"));
+        assert!(!limiter.allow("a"));
+        assert!(limiter.allow("b"));
+        assert!(limiter.allow(""));
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(limiter.allow("a"));
+        limiter.set_limit(3);
+        assert!(limiter.allow("a"));
+        assert!(limiter.allow("a"));
+        assert!(!limiter.allow("a"));
+
+        let off = WindowLimiter::new(0, Duration::from_secs(60));
+        assert!((0..10).all(|_| off.allow("a")));
+        assert!(off.allow_with("b", 1));
+        assert!(!off.allow_with("b", 1));
+    }
+}
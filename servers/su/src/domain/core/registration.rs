@@ -0,0 +1,185 @@
+use std::sync::{Arc, Mutex};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+use serde_json::json;
+
+use super::builder::BuilderErrorType;
+use super::flows::{init_builder, Deps};
+
+/*
+  The Scheduler-Location record clients find this su's
+  url by, a data item with the Url and Time-To-Live tags
+  signed by the su wallet. With SCHEDULER_LOCATION_URL set
+  it is uploaded on startup and again once half its ttl
+  has passed, so the record on chain never expires.
+*/
+#[derive(Serialize, Clone, Debug)]
+pub struct SchedulerLocation {
+    pub id: String,
+    pub url: String,
+    // milliseconds, as in the Time-To-Live tag
+    pub ttl: u64,
+    pub wallet: String,
+    pub published_at: u64,
+}
+
+pub struct Registration {
+    current: Mutex<Option<SchedulerLocation>>,
+    last_error: Mutex<Option<String>>,
+}
+
+impl Registration {
+    pub fn new() -> Self {
+        Registration {
+            current: Mutex::new(None),
+            last_error: Mutex::new(None),
+        }
+    }
+
+    pub fn current(&self) -> Option<SchedulerLocation> {
+        self.current.lock().unwrap().clone()
+    }
+}
+
+impl Default for Registration {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0)
+}
+
+/*
+  A record is republished at half its ttl, and at once
+  when a reload changed the url or ttl it was made with
+*/
+fn refresh_due(current: Option<&SchedulerLocation>, url: &str, ttl: u64, now: u64) -> bool {
+    match current {
+        None => true,
+        Some(location) => {
+            location.url != url
+                || location.ttl != ttl
+                || now.saturating_sub(location.published_at) >= location.ttl / 2
+        }
+    }
+}
+
+/*
+  Called on startup and every minute after, returns a
+  message when a record was uploaded. A failed attempt
+  is retried on the next call.
+*/
+pub async fn refresh_scheduler_location(deps: Arc<Deps>) -> Result<Option<String>, String> {
+    let url = deps.config.scheduler_location_url();
+    let ttl = deps.config.scheduler_location_ttl();
+    let current = deps.registration.current();
+    if url.is_empty() || !refresh_due(current.as_ref(), &url, ttl, now_millis()) {
+        return Ok(None);
+    }
+
+    match publish(&deps, &url, ttl).await {
+        Ok(location) => {
+            let message = format!(
+                "Published scheduler location {} for {} with a ttl of {}ms",
+                location.id, location.url, location.ttl
+            );
+            *deps.registration.current.lock().unwrap() = Some(location);
+            *deps.registration.last_error.lock().unwrap() = None;
+            Ok(Some(message))
+        }
+        Err(e) => {
+            let e = format!("Failed to publish the scheduler location: {}", e);
+            *deps.registration.last_error.lock().unwrap() = Some(e.clone());
+            Err(e)
+        }
+    }
+}
+
+async fn publish(deps: &Arc<Deps>, url: &str, ttl: u64) -> Result<SchedulerLocation, String> {
+    let wallet = deps.wallet.wallet_address()?;
+    let builder = init_builder(deps)?;
+    let location = builder.build_scheduler_location(url, ttl).await?;
+    let binary = location.as_bytes().map_err(BuilderErrorType::from)?;
+    // the uploader retries in the background until the bundler takes it
+    deps.uploader.upload(binary)?;
+    Ok(SchedulerLocation {
+        id: location.id(),
+        url: url.to_string(),
+        ttl,
+        wallet,
+        published_at: now_millis(),
+    })
+}
+
+// the record last published, for GET /admin/registration
+pub fn registration_status(deps: &Arc<Deps>) -> String {
+    let url = deps.config.scheduler_location_url();
+    let current = deps.registration.current();
+    let refresh_at = current
+        .as_ref()
+        .map(|location| location.published_at + location.ttl / 2);
+    json!({
+        "enabled": !url.is_empty(),
+        "url": url,
+        "ttl": deps.config.scheduler_location_ttl(),
+        "wallet": deps.wallet.wallet_address().ok(),
+        "record": current,
+        "refresh_at": refresh_at,
+        "last_error": deps.registration.last_error.lock().unwrap().clone(),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_refresh_due_at_half_the_ttl_or_on_change() {
+        let location = SchedulerLocation {
+            id: "id".to_string(),
+            url: "https://su.example".to_string(),
+            ttl: 1000,
+            wallet: "wallet".to_string(),
+            published_at: 10_000,
+        };
+        assert!(refresh_due(None, "https://su.example", 1000, 10_000));
+        assert!(!refresh_due(
+            Some(&location),
+            "https://su.example",
+            1000,
+            10_499
+        ));
+        assert!(refresh_due(
+            Some(&location),
+            "https://su.example",
+            1000,
+            10_500
+        ));
+        assert!(refresh_due(
+            Some(&location),
+            "https://other.example",
+            1000,
+            10_001
+        ));
+        assert!(refresh_due(
+            Some(&location),
+            "https://su.example",
+            2000,
+            10_001
+        ));
+        // a clock that went back does not trigger one
+        assert!(!refresh_due(
+            Some(&location),
+            "https://su.example",
+            1000,
+            9_000
+        ));
+    }
+}
@@ -1,11 +1,44 @@
-use serde::Deserialize;
+use bytes::Bytes;
+use futures::future::join_all;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
 use sha2::{Digest, Sha256};
-use std::{fmt::Debug, sync::Arc};
+use std::{
+    cmp::{self, Reverse},
+    collections::{BTreeMap, HashMap, HashSet},
+    env,
+    fmt::Debug,
+    fs,
+    num::NonZeroUsize,
+    path::Path,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use lru::LruCache;
 use tokio::{fs::File, io::AsyncReadExt};
 
+use super::address::{normalize_address, wallet_match};
 use super::builder::Builder;
-use crate::domain::core::dal::StoreErrorType;
-use crate::domain::flows::Deps;
+use super::json::{Message, Process};
+use super::owner_cache::OwnerAddresses;
+use super::router_state::{apply_router_change, RouterChange};
+use super::scheduler::gen_hash_chain;
+use super::scheduler_list::{
+    check_wallets, check_wallets_policy, deserialize_wallets, parse_scheduler_list,
+    parse_tag_rules, split_wallets, SchedulerEntry, WALLETS_STRICT,
+};
+use super::spawn_rollup::is_rollup;
+use super::trace::{self, SpanKind};
+use crate::domain::core::dal::{
+    Config, ExtRouterErrorType, LogFields, LogLevel, Mode, ReplicaKind, SharedCache,
+    StoreErrorType, Tag,
+};
+use crate::domain::flows::{
+    audit_admin, check_denylist, check_message_target, check_rate_limit, AdminActor, Deps,
+};
 
 /*
     The code in this file only runs on a su that is
@@ -16,14 +49,99 @@ use crate::domain::flows::Deps;
     a file. It is a basic load balancer implementation
 */
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Scheduler {
     pub row_id: Option<i32>,
     pub url: String,
     pub process_count: i32,
     pub no_route: Option<bool>,
-    pub wallets_to_route: Option<String>,
+    // shared cache entries from before the list was structured hold a string
+    #[serde(default, deserialize_with = "deserialize_wallets")]
+    pub wallets_to_route: Option<Vec<String>>,
     pub wallets_only: Option<bool>,
+    /*
+        Decides which scheduler gets a wallet listed
+        by more than one, lowest wins and unset sorts
+        after every set value
+    */
+    pub priority: Option<i32>,
+    /*
+        Takes the Process spawns larger than
+        LARGE_PROCESS_THRESHOLD, standard spawns are kept
+        off it while any other scheduler is available
+    */
+    pub large_objects: Option<bool>,
+    /*
+        Relative capacity for new processes, a scheduler
+        with weight 4 is given about four times as many
+        as one with weight 1. Unset counts as 1.
+    */
+    pub weight: Option<i32>,
+    /*
+        Base url clients are redirected to, for a scheduler
+        behind a CDN or vanity domain. url stays the
+        internal address used for health checks.
+    */
+    pub public_url: Option<String>,
+    /*
+        Like no_route it takes no new processes, and the
+        drain job also moves its existing processes to
+        other schedulers so it can be decommissioned
+    */
+    pub drain: Option<bool>,
+    /*
+        Quota of processes, once process_count reaches it
+        the scheduler takes no new ones. Unset is no limit.
+    */
+    pub max_processes: Option<i32>,
+    /*
+        Where the scheduler runs, spawns from clients in
+        the same region go to it ahead of the others
+    */
+    pub region: Option<String>,
+    /*
+        Comma separated rules on the tags of a spawn, each
+        one or more Name=Value conditions joined by &. A
+        spawn with every tag of any rule is sent here.
+    */
+    pub tags_to_route: Option<String>,
+    /*
+        strict or prefer, whether the wallets listed here
+        are refused or sent to the other schedulers while
+        the ones listing them can't take a spawn. Unset is
+        prefer.
+    */
+    pub wallets_policy: Option<String>,
+}
+
+impl Scheduler {
+    // whether new processes may be assigned here
+    fn routable(&self) -> bool {
+        !self.no_route.unwrap_or(false) && !self.drain.unwrap_or(false) && !self.full()
+    }
+
+    fn strict_wallets(&self) -> bool {
+        self.wallets_policy.as_deref() == Some(WALLETS_STRICT)
+    }
+
+    fn full(&self) -> bool {
+        matches!(self.max_processes, Some(max) if self.process_count >= max)
+    }
+
+    // share of max_processes in use, None without a quota
+    fn utilization(&self) -> Option<f64> {
+        self.max_processes
+            .map(|max| self.process_count as f64 / max.max(1) as f64)
+    }
+
+    fn route(&self, rule: RouteRule) -> RouteDecision {
+        let url = self.public_url.as_ref().unwrap_or(&self.url);
+        RouteDecision {
+            url: url.trim_end_matches('/').to_string(),
+            upstream: self.url.trim_end_matches('/').to_string(),
+            rule,
+        }
+    }
 }
 
 pub struct ProcessScheduler {
@@ -32,12 +150,195 @@ pub struct ProcessScheduler {
     pub scheduler_row_id: i32,
 }
 
-#[derive(Deserialize, Debug)]
-struct SchedulerEntry {
-    url: String,
-    no_route: Option<bool>,
-    wallets_to_route: Option<String>,
-    wallets_only: Option<bool>,
+/*
+    The scheduler a spawn ended up on. duplicate is set
+    when the process already had a row, from a retried
+    or concurrent spawn, and scheduler is the one it was
+    given the first time.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Assignment {
+    pub scheduler: Scheduler,
+    pub duplicate: bool,
+}
+
+/*
+    Which rule picked the scheduler, this is surfaced
+    to clients in the x-su-router header when enabled
+    to help debug misrouted traffic
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub enum RouteRule {
+    // the owner matched a scheduler's wallets_to_route
+    Wallet,
+    // new process sent to the scheduler with the fewest processes
+    LeastCount,
+    // the process already has a scheduler assigned
+    Pinned,
+    // a large spawn sent to a large object scheduler
+    Size,
+    // placed by the hash ring of the consistent-hash strategy
+    Hash,
+    // new process sent to the least loaded scheduler in the client's region
+    Region,
+    // the spawn's tags matched a scheduler's tags_to_route
+    Tag,
+    // sent after the owner's first process by ROUTER_OWNER_STICKY
+    Owner,
+}
+
+impl RouteRule {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteRule::Wallet => "wallet",
+            RouteRule::LeastCount => "least-count",
+            RouteRule::Pinned => "pinned",
+            RouteRule::Size => "size",
+            RouteRule::Hash => "consistent-hash",
+            RouteRule::Region => "region",
+            RouteRule::Tag => "tag",
+            RouteRule::Owner => "owner",
+        }
+    }
+}
+
+/*
+    A spawn as the router placed it, one line of the
+    spawn audit log. owner is the wallet address and
+    size the length of the posted data item.
+    candidates are the schedulers it was placed among
+    and skipped the ones picked first that failed the
+    SPAWN_FAILOVER probe, lines written before they
+    were recorded have neither.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpawnEvent {
+    pub timestamp: u64,
+    pub process_id: String,
+    pub owner: String,
+    pub size: usize,
+    pub scheduler: String,
+    pub rule: String,
+    // the client's region, when ROUTER_REGION_HEADER gave one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub strategy: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub candidates: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub skipped: Vec<String>,
+}
+
+// which spawns of the audit log to read, every field set has to match
+#[derive(Debug, Clone, Default)]
+pub struct SpawnQuery {
+    pub process_id: Option<String>,
+    pub owner: Option<String>,
+    pub scheduler: Option<String>,
+    pub limit: usize,
+}
+
+impl SpawnQuery {
+    pub fn matches(&self, event: &SpawnEvent) -> bool {
+        self.process_id
+            .as_ref()
+            .map_or(true, |process_id| *process_id == event.process_id)
+            && self.owner.as_ref().map_or(true, |owner| {
+                normalize_address(owner) == normalize_address(&event.owner)
+            })
+            && self
+                .scheduler
+                .as_ref()
+                .map_or(true, |scheduler| *scheduler == event.scheduler)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RouteDecision {
+    pub url: String,
+    // the internal url, where a proxying router sends the request
+    pub upstream: String,
+    pub rule: RouteRule,
+}
+
+impl RouteDecision {
+    pub fn header_value(&self) -> String {
+        format!("scheduler={}; rule={}", self.url, self.rule.as_str())
+    }
+}
+
+/*
+    Resolve a scheduler list field that may be given
+    inline, as a file path or as an environment variable
+    name. At most one of them may be set. Relative file
+    paths are relative to the scheduler list.
+*/
+fn resolve_entry_field(
+    field: &str,
+    url: &str,
+    inline: &Option<String>,
+    file: &Option<String>,
+    env_var: &Option<String>,
+    list_dir: &Path,
+) -> Result<Option<String>, String> {
+    match (inline, file, env_var) {
+        (value, None, None) => Ok(value.clone()),
+        (None, Some(path), None) => {
+            let contents = fs::read_to_string(list_dir.join(path)).map_err(|e| {
+                format!("Failed to read {}_file {} for {}: {}", field, path, url, e)
+            })?;
+            Ok(Some(join_list_file(&contents)))
+        }
+        (None, None, Some(name)) => env::var(name)
+            .map(Some)
+            .map_err(|_| format!("{}_env {} for {} is not set", field, name, url)),
+        _ => Err(format!(
+            "Only one of {}, {}_file and {}_env may be set for {}",
+            field, field, field, url
+        )),
+    }
+}
+
+/*
+    The wallets_to_route of an entry wherever they are
+    kept, parsing the list only checks them when inline
+*/
+fn resolve_wallets(entry: &SchedulerEntry, list_dir: &Path) -> Result<Option<Vec<String>>, String> {
+    let wallets = resolve_entry_field(
+        "wallets_to_route",
+        &entry.url,
+        &entry
+            .wallets_to_route
+            .as_ref()
+            .map(|wallets| wallets.join(",")),
+        &entry.wallets_to_route_file,
+        &entry.wallets_to_route_env,
+        list_dir,
+    )?
+    .map(|joined| split_wallets(&joined));
+    if let Some(wallets) = &wallets {
+        check_wallets(wallets)
+            .map_err(|e| format!("Invalid wallets_to_route for {}: {}", entry.url, e))?;
+    }
+    Ok(wallets)
+}
+
+/*
+    List files hold one or more comma separated values
+    per line, blank lines and lines starting with # are
+    skipped
+*/
+fn join_list_file(contents: &str) -> String {
+    contents
+        .lines()
+        .map(|line| line.trim())
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(|line| line.split(','))
+        .map(|value| value.trim())
+        .filter(|value| !value.is_empty())
+        .collect::<Vec<_>>()
+        .join(",")
 }
 
 pub fn hash(data: &[u8]) -> Vec<u8> {
@@ -47,12 +348,67 @@ pub fn hash(data: &[u8]) -> Vec<u8> {
     result.to_vec()
 }
 
+/*
+    Refuses a configuration the router couldn't route
+    with, checked before a reload swaps it in
+*/
+pub fn check_config(config: &dyn Config) -> Result<(), String> {
+    RoutingStrategy::from_config(&config.routing_strategy())?;
+    CleanupPolicy::from_config(&config.process_scheduler_cleanup_policy())?;
+    Ok(())
+}
+
 /*
     this runs at server startup in router mode to
     initialize the schedulers if they dont exist
 */
 pub async fn init_schedulers(deps: Arc<Deps>) -> Result<String, String> {
-    let mut file = File::open(&deps.config.scheduler_list_path())
+    RoutingStrategy::from_config(&deps.config.routing_strategy())?;
+    // a standby takes its schedulers from the primary's stream
+    if !deps.router_role.standby() {
+        apply_scheduler_list(&deps).await?;
+    }
+    let schedulers = observe(&deps, deps.router_data_store.get_all_schedulers())?;
+    deps.router_health.remember_schedulers(&schedulers);
+    log_wallet_overlaps(&deps)?;
+    Ok("schedulers initialized".to_string())
+}
+
+/*
+    Runs on an interval in router mode so edits to the
+    scheduler list take effect without a restart. Only
+    schedulers whose entry changed are written, returns
+    None when nothing did. Schedulers removed from the
+    file are left as they are, set no_route to drain one.
+*/
+pub async fn reload_schedulers(deps: Arc<Deps>) -> Result<Option<String>, String> {
+    if deps.router_role.standby() {
+        return Ok(None);
+    }
+    let (added, updated) = apply_scheduler_list(&deps).await?;
+    if added == 0 && updated == 0 {
+        return Ok(None);
+    }
+    log_wallet_overlaps(&deps)?;
+    Ok(Some(format!(
+        "scheduler list reloaded, {} added, {} updated",
+        added, updated
+    )))
+}
+
+/*
+    Create the schedulers in the list that dont exist
+    yet and bring the rest in line with their entry,
+    returns how many were added and updated
+*/
+async fn apply_scheduler_list(deps: &Arc<Deps>) -> Result<(usize, usize), String> {
+    let list_path = deps.config.scheduler_list_path();
+    let list_dir = Path::new(&list_path)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+
+    let mut file = File::open(&list_path)
         .await
         .map_err(|e| format!("Failed to open file: {}", e))?;
 
@@ -61,14 +417,18 @@ pub async fn init_schedulers(deps: Arc<Deps>) -> Result<String, String> {
         .await
         .map_err(|e| format!("Failed to read file: {}", e))?;
 
-    let urls: Vec<SchedulerEntry> =
-        serde_json::from_str(&contents).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let urls = parse_scheduler_list(&contents)?;
+
+    let mut added = 0;
+    let mut updated = 0;
 
     /*
         Iterate over the URLs and check each one
         if the scheduler doesnt exist yet create it
     */
     for entry in urls {
+        let wallets_to_route = resolve_wallets(&entry, &list_dir)?;
+
         if let Err(StoreErrorType::NotFound(_)) =
             deps.router_data_store.get_scheduler_by_url(&entry.url)
         {
@@ -77,12 +437,23 @@ pub async fn init_schedulers(deps: Arc<Deps>) -> Result<String, String> {
                 url: entry.url.clone(),
                 process_count: 0,
                 no_route: entry.no_route,
-                wallets_to_route: entry.wallets_to_route.clone(),
+                wallets_to_route: wallets_to_route.clone(),
                 wallets_only: entry.wallets_only,
+                priority: entry.priority,
+                large_objects: entry.large_objects,
+                weight: entry.weight,
+                public_url: entry.public_url.clone(),
+                drain: entry.drain,
+                max_processes: entry.max_processes,
+                region: entry.region.clone(),
+                tags_to_route: entry.tags_to_route.clone(),
+                wallets_policy: entry.wallets_policy.clone(),
             };
             deps.router_data_store.save_scheduler(&scheduler)?;
             deps.logger
                 .log(format!("saved new scheduler: {}", entry.url));
+            added += 1;
+            continue;
         }
 
         /*
@@ -90,206 +461,4156 @@ pub async fn init_schedulers(deps: Arc<Deps>) -> Result<String, String> {
           we can set no_route to true.
         */
         let mut sched = deps.router_data_store.get_scheduler_by_url(&entry.url)?;
-        sched.no_route = entry.no_route;
-        sched.wallets_to_route = entry.wallets_to_route.clone();
-        sched.wallets_only = entry.wallets_only;
-        deps.router_data_store.update_scheduler(&sched)?;
+        if apply_entry(&mut sched, &entry, wallets_to_route) {
+            deps.router_data_store.update_scheduler(&sched)?;
+            deps.route_cache.clear();
+            deps.logger
+                .log(format!("updated scheduler: {}", entry.url));
+            updated += 1;
+        }
     }
 
-    Ok("schedulers initialized".to_string())
+    Ok((added, updated))
 }
 
-// if this returns Ok(Some(String)) then the server should return a redirect to the String
-pub async fn redirect_process_id(
-    deps: Arc<Deps>,
-    process_id: Option<String>,
-) -> Result<Option<String>, String> {
-    if deps.config.mode() != "router" {
-        return Ok(None);
-    }
+// copy the list fields onto a scheduler, true if any changed
+fn apply_entry(
+    scheduler: &mut Scheduler,
+    entry: &SchedulerEntry,
+    wallets_to_route: Option<Vec<String>>,
+) -> bool {
+    let changed = scheduler.no_route != entry.no_route
+        || scheduler.wallets_to_route != wallets_to_route
+        || scheduler.wallets_only != entry.wallets_only
+        || scheduler.priority != entry.priority
+        || scheduler.large_objects != entry.large_objects
+        || scheduler.weight != entry.weight
+        || scheduler.public_url != entry.public_url
+        || scheduler.drain != entry.drain
+        || scheduler.max_processes != entry.max_processes
+        || scheduler.region != entry.region
+        || scheduler.tags_to_route != entry.tags_to_route
+        || scheduler.wallets_policy != entry.wallets_policy;
 
-    let pid = process_id.ok_or("No process-id query parameter provided")?;
+    scheduler.no_route = entry.no_route;
+    scheduler.wallets_to_route = wallets_to_route;
+    scheduler.wallets_only = entry.wallets_only;
+    scheduler.priority = entry.priority;
+    scheduler.large_objects = entry.large_objects;
+    scheduler.weight = entry.weight;
+    scheduler.public_url = entry.public_url.clone();
+    scheduler.drain = entry.drain;
+    scheduler.max_processes = entry.max_processes;
+    scheduler.region = entry.region.clone();
+    scheduler.tags_to_route = entry.tags_to_route.clone();
+    scheduler.wallets_policy = entry.wallets_policy.clone();
+    changed
+}
 
-    // every other process_id, redirect
-    let process_scheduler = deps.router_data_store.get_process_scheduler(&pid)?;
-    let scheduler = deps
+/*
+    A wallet in more than one scheduler's list is
+    routed by priority, report each one so the
+    overlap is a visible choice and not an accident
+*/
+fn log_wallet_overlaps(deps: &Arc<Deps>) -> Result<(), String> {
+    let schedulers = deps
         .router_data_store
-        .get_scheduler(&process_scheduler.scheduler_row_id)?;
-    Ok(Some(scheduler.url))
+        .get_all_schedulers()?
+        .into_iter()
+        .filter(|scheduler| scheduler.routable())
+        .collect::<Vec<_>>();
+    for (wallet, urls) in wallet_overlaps(&schedulers) {
+        deps.logger.error(format!(
+            "wallet {} is routed by {} schedulers, {} wins by priority over {}",
+            wallet,
+            urls.len(),
+            urls[0],
+            urls[1..].join(", ")
+        ));
+    }
+    Ok(())
 }
 
-// if this returns Ok(Some(String)) then the server should return a redirect to the String
-pub async fn redirect_tx_id(
+/*
+    Fields an operator can set on a scheduler through the
+    admin api, anything left out keeps its current value.
+    Schedulers that are also in the scheduler list get
+    their entry back on the next reload, so change those
+    in the file instead.
+*/
+#[derive(Deserialize, Debug)]
+pub struct SchedulerChange {
+    url: Option<String>,
+    no_route: Option<bool>,
+    #[serde(default, deserialize_with = "deserialize_wallets")]
+    wallets_to_route: Option<Vec<String>>,
+    wallets_only: Option<bool>,
+    priority: Option<i32>,
+    large_objects: Option<bool>,
+    weight: Option<i32>,
+    public_url: Option<String>,
+    drain: Option<bool>,
+    max_processes: Option<i32>,
+    region: Option<String>,
+    tags_to_route: Option<String>,
+    wallets_policy: Option<String>,
+}
+
+impl SchedulerChange {
+    fn from_body(body: &[u8]) -> Result<Self, String> {
+        let change: SchedulerChange =
+            serde_json::from_slice(body).map_err(|e| format!("Invalid scheduler json: {}", e))?;
+        if let Some(policy) = &change.wallets_policy {
+            check_wallets_policy(policy).map_err(|e| format!("Invalid wallets_policy: {}", e))?;
+        }
+        Ok(change)
+    }
+
+    fn apply(self, scheduler: &mut Scheduler) {
+        if self.no_route.is_some() {
+            scheduler.no_route = self.no_route;
+        }
+        if self.wallets_to_route.is_some() {
+            scheduler.wallets_to_route = self.wallets_to_route;
+        }
+        if self.wallets_only.is_some() {
+            scheduler.wallets_only = self.wallets_only;
+        }
+        if self.priority.is_some() {
+            scheduler.priority = self.priority;
+        }
+        if self.large_objects.is_some() {
+            scheduler.large_objects = self.large_objects;
+        }
+        if self.weight.is_some() {
+            scheduler.weight = self.weight;
+        }
+        if self.public_url.is_some() {
+            scheduler.public_url = self.public_url;
+        }
+        if self.drain.is_some() {
+            scheduler.drain = self.drain;
+        }
+        if self.max_processes.is_some() {
+            scheduler.max_processes = self.max_processes;
+        }
+        if self.region.is_some() {
+            scheduler.region = self.region.clone();
+        }
+        if self.tags_to_route.is_some() {
+            scheduler.tags_to_route = self.tags_to_route.clone();
+        }
+        if self.wallets_policy.is_some() {
+            scheduler.wallets_policy = self.wallets_policy.clone();
+        }
+    }
+}
+
+fn require_router(deps: &Arc<Deps>) -> Result<(), String> {
+    if deps.config.mode() != Mode::Router {
+        return Err("Schedulers can only be managed in router mode".to_string());
+    }
+    Ok(())
+}
+
+pub async fn list_schedulers(deps: Arc<Deps>) -> Result<String, String> {
+    require_router(&deps)?;
+    let schedulers = deps.router_data_store.get_all_schedulers()?;
+    let mut listed = vec![];
+    for scheduler in schedulers {
+        let mut value = serde_json::to_value(&scheduler).map_err(|e| e.to_string())?;
+        value["utilization"] = json!(scheduler.utilization());
+        value["full"] = json!(scheduler.full());
+        listed.push(value);
+    }
+    serde_json::to_string(&listed).map_err(|e| e.to_string())
+}
+
+pub async fn add_scheduler(
     deps: Arc<Deps>,
-    tx_id: String,
-    process_id: Option<String>,
-) -> Result<Option<String>, String> {
-    if deps.config.mode() != "router" {
-        return Ok(None);
+    actor: &AdminActor,
+    body: Vec<u8>,
+) -> Result<String, String> {
+    require_router(&deps)?;
+    require_primary(&deps, "schedulers are changed on its primary")?;
+    let change = SchedulerChange::from_body(&body)?;
+    let url = change.url.clone().ok_or("url is required")?;
+    if !url.starts_with("http://") && !url.starts_with("https://") {
+        return Err(format!("Invalid scheduler url: {}", url));
     }
 
-    let process_to_query = match deps.router_data_store.get_process_scheduler(&tx_id) {
-        Ok(_) => tx_id,
-        /*
-            we didn't find a process scheduler based on the tx_id
-            so we need to try and find one based on process_id query param
-        */
-        Err(_) => process_id.ok_or("Unable to locate process, if this is a message id query be sure to pass the process-id query parameter")?,
+    match deps.router_data_store.get_scheduler_by_url(&url) {
+        Ok(_) => return Err(format!("Scheduler {} already exists", url)),
+        Err(StoreErrorType::NotFound(_)) => (),
+        Err(e) => return Err(e.into()),
+    }
+
+    let mut scheduler = Scheduler {
+        row_id: None,
+        url: url.clone(),
+        process_count: 0,
+        no_route: None,
+        wallets_to_route: None,
+        wallets_only: None,
+        priority: None,
+        large_objects: None,
+        weight: None,
+        public_url: None,
+        drain: None,
+        max_processes: None,
+        region: None,
+        tags_to_route: None,
+        wallets_policy: None,
     };
+    change.apply(&mut scheduler);
+    deps.router_data_store.save_scheduler(&scheduler)?;
+    deps.logger
+        .log(format!("saved new scheduler through admin api: {}", url));
 
-    let process_scheduler = deps
-        .router_data_store
-        .get_process_scheduler(&process_to_query)?;
-    let scheduler = deps
-        .router_data_store
-        .get_scheduler(&process_scheduler.scheduler_row_id)?;
-    Ok(Some(scheduler.url))
+    let saved = deps.router_data_store.get_scheduler_by_url(&url)?;
+    audit_admin(
+        &deps,
+        actor,
+        "scheduler.add",
+        &url,
+        None,
+        Some(json!(saved)),
+    )?;
+    serde_json::to_string(&saved).map_err(|e| e.to_string())
 }
 
-// if this returns Ok(Some(String)) then the server should return a redirect to the String
-pub async fn redirect_data_item(
+pub async fn update_scheduler(
     deps: Arc<Deps>,
-    input: Vec<u8>,
-    process_id: Option<String>,
-    assign: Option<String>,
-) -> Result<Option<String>, String> {
-    if deps.config.mode() != "router" {
-        return Ok(None);
+    actor: &AdminActor,
+    scheduler_id: i32,
+    body: Vec<u8>,
+) -> Result<String, String> {
+    require_router(&deps)?;
+    require_primary(&deps, "schedulers are changed on its primary")?;
+    let change = SchedulerChange::from_body(&body)?;
+    if change.url.is_some() {
+        return Err("A scheduler's url can't be changed".to_string());
     }
 
-    // XOR, if we have one of these, we must have both.
-    if process_id.is_some() ^ assign.is_some() {
-        return Err("If sending assign or process-id, you must send both.".to_string());
-    } else if let (Some(process_id), Some(_assign)) = (process_id, assign) {
-        match deps.router_data_store.get_process_scheduler(&process_id) {
-            Ok(process_scheduler) => {
-                let scheduler = deps
-                    .router_data_store
-                    .get_scheduler(&process_scheduler.scheduler_row_id)?;
-                return Ok(Some(scheduler.url));
+    let mut scheduler = deps.router_data_store.get_scheduler(&scheduler_id)?;
+    let before = json!(scheduler);
+    change.apply(&mut scheduler);
+    deps.router_data_store.update_scheduler(&scheduler)?;
+    deps.route_cache.clear();
+    deps.logger
+        .log(format!("updated scheduler through admin api: {}", scheduler.url));
+    audit_admin(
+        &deps,
+        actor,
+        "scheduler.update",
+        &scheduler.url,
+        Some(before),
+        Some(json!(scheduler)),
+    )?;
+
+    serde_json::to_string(&scheduler).map_err(|e| e.to_string())
+}
+
+/*
+    Only a scheduler with no processes assigned can be
+    removed, otherwise they would have nowhere to route.
+    With evacuate its processes are first moved to the
+    other schedulers the way the drain job moves them.
+*/
+pub async fn remove_scheduler(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    scheduler_id: i32,
+    evacuate: bool,
+) -> Result<String, String> {
+    require_router(&deps)?;
+    require_primary(&deps, "schedulers are changed on its primary")?;
+    let mut scheduler = deps.router_data_store.get_scheduler(&scheduler_id)?;
+    let mut assigned = assigned_processes(&deps, scheduler_id)?;
+    let mut evacuated = 0;
+    if assigned > 0 && evacuate {
+        evacuated = evacuate_scheduler(&deps, &mut scheduler).await?;
+        assigned = assigned_processes(&deps, scheduler_id)?;
+    }
+    if assigned > 0 {
+        return Err(format!(
+            "Scheduler {} still has {} processes assigned, remove it with evacuate=true to move them first",
+            scheduler.url, assigned
+        ));
+    }
+
+    deps.router_data_store.delete_scheduler(&scheduler_id)?;
+    deps.scheduler_failures.remove(&scheduler.url);
+    deps.shared_health.forget(&scheduler.url);
+    deps.route_cache.clear();
+    deps.logger
+        .log(format!("deleted scheduler through admin api: {}", scheduler.url));
+    audit_admin(
+        &deps,
+        actor,
+        "scheduler.remove",
+        &scheduler.url,
+        Some(json!(scheduler)),
+        None,
+    )?;
+
+    Ok(json!({ "deleted": scheduler.url, "evacuated": evacuated }).to_string())
+}
+
+fn assigned_processes(deps: &Arc<Deps>, scheduler_id: i32) -> Result<i64, String> {
+    Ok(deps
+        .router_data_store
+        .get_process_scheduler_counts()?
+        .into_iter()
+        .find(|(row_id, _)| *row_id == scheduler_id)
+        .map(|(_, count)| count)
+        .unwrap_or(0))
+}
+
+/*
+    Move every process off a scheduler before it is
+    removed. It is set to drain first so no new process
+    lands on it, and stays draining if a move fails so the
+    drain job carries on with the rest. Each pass pages
+    through the rows by row_id and a process the drain job
+    already moved, going by the writer, is skipped. Passes
+    repeat until one finds no rows, and a pass that finds
+    rows but moves none of them is an error rather than
+    going round again.
+*/
+async fn evacuate_scheduler(deps: &Arc<Deps>, scheduler: &mut Scheduler) -> Result<usize, String> {
+    let store = &deps.router_data_store;
+    let row_id = scheduler.row_id.ok_or("Missing id on scheduler")?;
+    if scheduler.drain != Some(true) {
+        scheduler.drain = Some(true);
+        store.update_scheduler(scheduler)?;
+        deps.route_cache.clear();
+    }
+
+    let batch_size = deps.config.drain_batch_size();
+    let mut moved = 0;
+    loop {
+        let (moved_before, mut found, mut last_seen) = (moved, 0, 0);
+        loop {
+            let rows = store.get_process_schedulers_for(&row_id, &last_seen, batch_size)?;
+            let Some(last) = rows.last() else {
+                break;
+            };
+            last_seen = last.row_id.ok_or("Missing id on process scheduler")?;
+            found += rows.len();
+            for row in rows {
+                let current = match store.get_process_scheduler_from_writer(&row.process_id) {
+                    Ok(current) if current.scheduler_row_id == row_id => current,
+                    Ok(_) | Err(StoreErrorType::NotFound(_)) => continue,
+                    Err(e) => return Err(e.into()),
+                };
+                let url = drain_process(deps, scheduler, &current)
+                    .await
+                    .map_err(|e| {
+                        format!(
+                            "Evacuating {} stopped after moving {} processes, {} failed: {}, \
+                             it is left draining",
+                            scheduler.url, moved, row.process_id, e
+                        )
+                    })?;
+                moved += 1;
+                deps.logger.event(
+                    LogLevel::Info,
+                    "router",
+                    format!("evacuated process to {}", url),
+                    LogFields {
+                        process_id: Some(row.process_id.clone()),
+                        scheduler_url: Some(scheduler.url.clone()),
+                        latency_ms: None,
+                    },
+                );
             }
-            Err(_) => return Err("Unable to locate scheduler for process-id".to_string()),
+        }
+        if found == 0 {
+            return Ok(moved);
+        }
+        if moved == moved_before {
+            return Err(format!(
+                "Evacuating {} stopped after moving {} processes, {} are still listed on it \
+                 but none could be moved, it is left draining",
+                scheduler.url, moved, found
+            ));
         }
     }
+}
 
-    let item = Builder::parse_data_item(input.clone())?;
-    let tags = item.tags().clone();
-    let id = item.id().clone();
-    let target = item.target().clone();
-    let type_tag = tags
-        .iter()
-        .find(|tag| tag.name == "Type" || tag.name == "type")
-        .ok_or("Cannot redirect data item, invalid Type Tag")?;
-    let owner = item.owner().clone();
-
-    let owner_bytes = match base64_url::decode(&owner) {
-        Ok(h) => h,
-        Err(_) => return Err("Failed to parse owner".to_string()),
-    };
-    let address_hash = hash(&owner_bytes);
-    let owner_address = base64_url::encode(&address_hash);
+/*
+    Runs on an interval in router mode. Every scheduler is
+    probed and its consecutive failures counted, once they
+    reach SCHEDULER_UNHEALTHY_AFTER new processes are no
+    longer routed to it until a probe succeeds again.
+    Processes already pinned to it are still redirected.
+    With a shared cache the count is kept there, failed
+    probes from every replica add up and a successful one
+    from any of them resets it.
+*/
+pub async fn check_scheduler_health(deps: Arc<Deps>) -> Result<String, String> {
+    let schedulers = deps.router_data_store.get_all_schedulers()?;
+    let threshold = deps.config.scheduler_unhealthy_after();
+    let health_ttl = health_ttl(&deps);
 
-    match type_tag.value.as_str() {
-        "Process" => {
-            /*
-                new process so we need to generate a
-                process_schedulers record and return the url
-            */
-            let mut schedulers = deps
-                .router_data_store
-                .get_all_schedulers()?
-                .into_iter()
-                .filter(|scheduler| scheduler.no_route.unwrap_or(false) == false)
-                .collect::<Vec<_>>();
+    let results = join_all(
+        schedulers
+            .iter()
+            .map(|scheduler| deps.ext_router.probe_scheduler(scheduler.url.clone())),
+    )
+    .await;
 
-            /*
-                This logic is added for routing wallet addresses to
-                specific schedulers. It will find the first scheduler
-                with a wallet matching the owner and route the new spawn
-                there.
-            */
-            for scheduler in schedulers.iter_mut() {
-                match &scheduler.wallets_to_route {
-                    Some(w) => {
-                        let wallets: Vec<String> =
-                            w.split(',').map(|s| s.trim().to_string()).collect();
-
-                        for wallet in wallets {
-                            if owner_address == wallet {
-                                scheduler.process_count += 1;
-                                deps.router_data_store.update_scheduler(scheduler)?;
-
-                                let scheduler_row_id =
-                                    if let Some(m_scheduler_row_id) = scheduler.row_id {
-                                        m_scheduler_row_id
-                                    } else {
-                                        /*
-                                            this should be unreachable but return an error
-                                            just in case so the router doesn't crash
-                                        */
-                                        return Err("Missing id on scheduler".to_string());
-                                    };
-
-                                let process_scheduler = ProcessScheduler {
-                                    row_id: None,
-                                    scheduler_row_id,
-                                    process_id: id,
-                                };
-                                deps.router_data_store
-                                    .save_process_scheduler(&process_scheduler)?;
-
-                                return Ok(Some(scheduler.url.clone()));
-                            }
-                        }
+    let mut unhealthy = 0;
+    for (scheduler, result) in schedulers.iter().zip(results) {
+        match result {
+            Ok(()) => {
+                deps.shared_health.mark_up(&scheduler.url);
+                if let Some((_, failures)) = deps.scheduler_failures.remove(&scheduler.url) {
+                    if failures >= threshold {
+                        deps.logger.event(
+                            LogLevel::Info,
+                            "router",
+                            "scheduler is healthy again".to_string(),
+                            LogFields::scheduler(&scheduler.url),
+                        );
                     }
-                    None => {}
                 }
             }
+            Err(e) => {
+                let shared = deps
+                    .shared_cache
+                    .incr(&health_key(&scheduler.url), health_ttl);
+                let mut failures = deps
+                    .scheduler_failures
+                    .entry(scheduler.url.clone())
+                    .or_insert(0);
+                let before = *failures;
+                *failures = match shared {
+                    Ok(count) => count.clamp(0, u32::MAX as i64) as u32,
+                    Err(_) => before + 1,
+                };
+                if before < threshold && *failures >= threshold {
+                    deps.logger.event(
+                        LogLevel::Error,
+                        "router",
+                        format!(
+                            "scheduler failed {} health checks, no longer routing new processes to it: {:?}",
+                            threshold, e
+                        ),
+                        LogFields::scheduler(&scheduler.url),
+                    );
+                }
+                if *failures >= threshold {
+                    unhealthy += 1;
+                }
+            }
+        }
+    }
 
-            schedulers.retain(|scheduler| scheduler.wallets_only.unwrap_or(false) == false);
+    Ok(format!(
+        "checked health of {} schedulers, {} unhealthy",
+        schedulers.len(),
+        unhealthy
+    ))
+}
 
-            if let Some(min_scheduler) = schedulers.iter_mut().min_by_key(|s| s.process_count) {
-                min_scheduler.process_count += 1;
-                deps.router_data_store.update_scheduler(min_scheduler)?;
+fn health_key(url: &str) -> String {
+    format!("health:{}", url)
+}
 
-                let scheduler_row_id = if let Some(min_scheduler_row_id) = min_scheduler.row_id {
-                    min_scheduler_row_id
-                } else {
-                    /*
-                        this should be unreachable but return an error
-                        just in case so the router doesn't crash
-                    */
-                    return Err("Missing id on scheduler".to_string());
-                };
+// a count left by a replica that stopped probing runs out after a few intervals
+fn health_ttl(deps: &Arc<Deps>) -> u64 {
+    deps.config.scheduler_health_interval().max(1) * 3
+}
 
-                let process_scheduler = ProcessScheduler {
-                    row_id: None,
-                    scheduler_row_id,
-                    process_id: id,
-                };
-                deps.router_data_store
-                    .save_process_scheduler(&process_scheduler)?;
+// how long a count read from the shared cache is used before reading it again
+const SHARED_HEALTH_TTL: Duration = Duration::from_secs(2);
 
-                Ok(Some(min_scheduler.url.clone()))
-            } else {
-                Err("Could not find a scheduler to assign".to_string())
-            }
+/*
+    The failure counts the other replicas of the router
+    keep in the shared cache, so a scheduler one of them
+    found down is skipped here too before this router's
+    own checks catch up. A count is read at most once per
+    ttl for each scheduler, keeping the shared cache off
+    the spawn path. One that can't be read is taken as no
+    failures, the local count still applies.
+*/
+pub struct SharedHealth {
+    shared: Arc<dyn SharedCache>,
+    ttl: Duration,
+    counts: Mutex<HashMap<String, (u32, Instant)>>,
+}
+
+impl SharedHealth {
+    pub fn new(shared: Arc<dyn SharedCache>) -> Self {
+        SharedHealth {
+            shared,
+            ttl: SHARED_HEALTH_TTL,
+            counts: Mutex::new(HashMap::new()),
         }
-        "Message" => {
-            /*
-                otherwise, fetch the correct scheduler based
-                on the messages's target
-            */
-            match deps.router_data_store.get_process_scheduler(&target) {
-                Ok(process_scheduler) => {
-                    let scheduler = deps
-                        .router_data_store
-                        .get_scheduler(&process_scheduler.scheduler_row_id)?;
-                    Ok(Some(scheduler.url))
-                }
-                Err(_) => Err("Unable to locate scheduler for message target".to_string()),
-            }
+    }
+
+    fn failures(&self, url: &str) -> u32 {
+        let cached = self.counts.lock().ok().and_then(|counts| {
+            counts
+                .get(url)
+                .filter(|(_, read_at)| read_at.elapsed() < self.ttl)
+                .map(|(failures, _)| *failures)
+        });
+        if let Some(failures) = cached {
+            return failures;
         }
-        _ => Err("Cannot redirect data item, invalid Type Tag".to_string()),
+
+        let failures = self
+            .shared
+            .get(&health_key(url))
+            .ok()
+            .flatten()
+            .and_then(|count| count.parse::<i64>().ok())
+            .map_or(0, |count| count.clamp(0, u32::MAX as i64) as u32);
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.insert(url.to_string(), (failures, Instant::now()));
+        }
+        failures
+    }
+
+    // this router's own verdict, which the other replicas read within their ttl
+    fn mark_down(&self, url: &str, failures: u32, ttl: u64) {
+        let _ = self
+            .shared
+            .set(&health_key(url), &failures.to_string(), ttl);
+        self.forget(url);
+    }
+
+    fn mark_up(&self, url: &str) {
+        let _ = self.shared.delete(&health_key(url));
+        self.forget(url);
+    }
+
+    fn forget(&self, url: &str) {
+        if let Ok(mut counts) = self.counts.lock() {
+            counts.remove(url);
+        }
+    }
+}
+
+fn is_healthy(deps: &Arc<Deps>, scheduler: &Scheduler) -> bool {
+    let local = deps
+        .scheduler_failures
+        .get(&scheduler.url)
+        .map_or(0, |failures| *failures);
+    let failures = local.max(deps.shared_health.failures(&scheduler.url));
+    failures < deps.config.scheduler_unhealthy_after()
+}
+
+/*
+    Choose the scheduler for a new process spawned by
+    owner_address. Draining schedulers are never picked
+    and unhealthy ones only when nothing else is up. The
+    first scheduler in priority order listing the owner
+    wins, or when it lists the owner with a share the
+    process id picks among the schedulers sharing it.
+    Next the least loaded scheduler whose tags_to_route
+    matches the spawn's tags. Then owner_scheduler, the
+    one the owner's first process went to, when it can
+    still take a process of this size. Otherwise the least
+    loaded of the rest after size and region routing.
+    wallets_only schedulers take nothing but their own
+    wallets and tags. A wallet listed with a strict
+    wallets_policy goes nowhere else, so it gets None
+    when every scheduler listing it is left out.
+*/
+#[allow(clippy::too_many_arguments)]
+fn select_scheduler<H>(
+    schedulers: Vec<Scheduler>,
+    process_id: &str,
+    owner_address: &str,
+    size: usize,
+    threshold: usize,
+    region: Option<&str>,
+    tags: &[Tag],
+    owner_scheduler: Option<i32>,
+    healthy: H,
+) -> Option<(Scheduler, RouteRule)>
+where
+    H: Fn(&Scheduler) -> bool,
+{
+    let pinned = strict_pin(&schedulers, owner_address);
+    let mut schedulers = candidates(schedulers, healthy);
+
+    /*
+        This logic is added for routing wallet addresses to
+        specific schedulers. It will find the first scheduler
+        with a wallet matching the owner and route the new spawn
+        there. Only the schedulers matching the owner most
+        closely count, so a wallet listed by itself wins over
+        a prefix taking it. Schedulers are checked in priority
+        order so a wallet listed more than once always lands
+        on the same one.
+    */
+    let (closest, listing) = closest_listing(&schedulers, owner_address);
+    if pinned.is_some() && closest != pinned {
+        return None;
+    }
+    if let Some(i) = split_share(&listing, process_id) {
+        return Some((schedulers.swap_remove(i), RouteRule::Wallet));
+    }
+
+    let mut tagged = schedulers
+        .iter()
+        .filter(|scheduler| match &scheduler.tags_to_route {
+            Some(rules) => matches_tag_rules(rules, tags),
+            None => false,
+        })
+        .cloned()
+        .collect::<Vec<_>>();
+    if let Some(scheduler) = least_loaded(&mut tagged) {
+        return Some((scheduler.clone(), RouteRule::Tag));
+    }
+
+    schedulers.retain(|scheduler| !scheduler.wallets_only.unwrap_or(false));
+
+    let rule = route_by_size(&mut schedulers, size, threshold);
+    let sticky = schedulers
+        .iter()
+        .position(|scheduler| owner_scheduler.is_some() && scheduler.row_id == owner_scheduler);
+    if let Some(i) = sticky {
+        return Some((schedulers.swap_remove(i), RouteRule::Owner));
+    }
+    let rule = route_by_region(&mut schedulers, region, rule);
+    least_loaded(&mut schedulers).map(|scheduler| (scheduler.clone(), rule))
+}
+
+/*
+    The schedulers a new process is placed among, the
+    routable ones. Schedulers failing their health checks
+    are skipped, if every one of them is down they are
+    all kept rather than refuse the spawn.
+*/
+fn candidates<H>(schedulers: Vec<Scheduler>, healthy: H) -> Vec<Scheduler>
+where
+    H: Fn(&Scheduler) -> bool,
+{
+    let mut schedulers = schedulers
+        .into_iter()
+        .filter(|scheduler| scheduler.routable())
+        .collect::<Vec<_>>();
+    if schedulers.iter().any(&healthy) {
+        schedulers.retain(&healthy);
+    }
+    schedulers
+}
+
+/*
+    The schedulers listing owner_address most closely, in
+    precedence order with their shares, and how closely
+*/
+fn closest_listing(
+    schedulers: &[Scheduler],
+    owner_address: &str,
+) -> (Option<usize>, Vec<(usize, Option<u32>)>) {
+    let mut wallet_order = (0..schedulers.len()).collect::<Vec<_>>();
+    wallet_order.sort_by_key(|i| wallet_precedence(&schedulers[*i]));
+
+    let mut listing = vec![];
+    let mut closest = None;
+    for i in wallet_order {
+        let matched = schedulers[i]
+            .wallets_to_route
+            .iter()
+            .flatten()
+            .map(|entry| wallet_share(entry))
+            .filter_map(|(wallet, share)| {
+                wallet_match(wallet, owner_address).map(|closeness| (closeness, share))
+            })
+            .min_by_key(|(closeness, _)| Reverse(*closeness));
+        if let Some((closeness, share)) = matched {
+            match closest.cmp(&Some(closeness)) {
+                cmp::Ordering::Less => {
+                    closest = Some(closeness);
+                    listing = vec![(i, share)];
+                }
+                cmp::Ordering::Equal => listing.push((i, share)),
+                cmp::Ordering::Greater => (),
+            }
+        }
+    }
+    (closest, listing)
+}
+
+/*
+    How closely owner_address is pinned when a scheduler
+    listing it most closely has a strict wallets_policy,
+    counting every scheduler whether or not it can take
+    a spawn now
+*/
+fn strict_pin(schedulers: &[Scheduler], owner_address: &str) -> Option<usize> {
+    let (closest, listing) = closest_listing(schedulers, owner_address);
+    match listing.iter().any(|(i, _)| schedulers[*i].strict_wallets()) {
+        true => closest,
+        false => None,
+    }
+}
+
+/*
+    Narrow the candidates for a new process by its size.
+    Spawns over the threshold go to the large object
+    schedulers and the rest stay off them, either side
+    falls back to every candidate when its group is empty.
+    A threshold of 0 turns the rule off.
+*/
+fn route_by_size(schedulers: &mut Vec<Scheduler>, size: usize, threshold: usize) -> RouteRule {
+    if threshold == 0 {
+        return RouteRule::LeastCount;
+    }
+
+    let large = size > threshold;
+    let in_group = |s: &Scheduler| s.large_objects.unwrap_or(false) == large;
+    if !schedulers.iter().any(in_group) {
+        return RouteRule::LeastCount;
+    }
+
+    schedulers.retain(in_group);
+    if large {
+        RouteRule::Size
+    } else {
+        RouteRule::LeastCount
+    }
+}
+
+// a list that doesn't parse matches nothing, the scheduler list is checked when read
+fn matches_tag_rules(rules: &str, tags: &[Tag]) -> bool {
+    let rules = match parse_tag_rules(rules) {
+        Ok(rules) => rules,
+        Err(_) => return false,
+    };
+    rules.iter().any(|rule| {
+        rule.iter().all(|(name, value)| {
+            tags.iter()
+                .any(|tag| tag.name == name.as_str() && tag.value == value.as_str())
+        })
+    })
+}
+
+/*
+    Keep the candidates in the client's region, when none
+    of them is there every region stays a candidate. A
+    large spawn keeps its size rule.
+*/
+fn route_by_region(
+    schedulers: &mut Vec<Scheduler>,
+    region: Option<&str>,
+    rule: RouteRule,
+) -> RouteRule {
+    let region = match region {
+        Some(region) => region,
+        None => return rule,
+    };
+    let in_region = |s: &Scheduler| matches!(&s.region, Some(r) if r.eq_ignore_ascii_case(region));
+    if !schedulers.iter().any(in_region) {
+        return rule;
+    }
+
+    schedulers.retain(in_region);
+    match rule {
+        RouteRule::LeastCount => RouteRule::Region,
+        rule => rule,
+    }
+}
+
+/*
+    The region of a client from the value of its
+    ROUTER_REGION_HEADER, looked up in a comma separated
+    list of value=region pairs. A value the list doesn't
+    have is the region itself, so the header can carry
+    either a region or, from a CDN, a country code.
+*/
+pub fn client_region(region_map: &str, value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return None;
+    }
+    let region = region_map
+        .split(',')
+        .filter_map(|pair| pair.split_once('='))
+        .find(|(key, _)| key.trim().eq_ignore_ascii_case(value))
+        .map(|(_, region)| region.trim())
+        .unwrap_or(value);
+    Some(region.to_string())
+}
+
+/*
+    Pick the scheduler whose load per unit of weight is
+    lowest once it takes one more process, ties go to
+    the earliest one. Weights below 1 count as 1.
+*/
+fn least_loaded(schedulers: &mut [Scheduler]) -> Option<&mut Scheduler> {
+    let load = |s: &Scheduler| (s.process_count as i64 + 1, s.weight.unwrap_or(1).max(1) as i64);
+    schedulers.iter_mut().min_by(|a, b| {
+        let (a_count, a_weight) = load(a);
+        let (b_count, b_weight) = load(b);
+        (a_count * b_weight).cmp(&(b_count * a_weight))
+    })
+}
+
+/*
+    A wallets_to_route entry is a wallet, optionally
+    followed by :<percent>, the share of the wallet's new
+    processes that scheduler takes. An entry whose share
+    isn't a number is returned whole and matches nothing.
+*/
+fn wallet_share(entry: &str) -> (&str, Option<u32>) {
+    match entry.rsplit_once(':') {
+        Some((wallet, share)) => match share.trim().parse() {
+            Ok(share) => (wallet.trim(), Some(share)),
+            Err(_) => (entry, None),
+        },
+        None => (entry, None),
+    }
+}
+
+/*
+    Of the schedulers listing a wallet, in precedence
+    order with their shares, the one a process goes to.
+    The first wins unless it has a share, then the hash
+    of the process id falls in one of the shares of all
+    the schedulers that have one, so the same process
+    always lands on the same scheduler and shares need
+    not add up to 100.
+*/
+fn split_share(listing: &[(usize, Option<u32>)], process_id: &str) -> Option<usize> {
+    let (first, _) = listing.first()?;
+    let shares = listing
+        .iter()
+        .filter_map(|(i, share)| share.filter(|share| *share > 0).map(|share| (*i, share)))
+        .collect::<Vec<_>>();
+    let total: u64 = shares.iter().map(|(_, share)| *share as u64).sum();
+    if listing[0].1.is_none() || total == 0 {
+        return Some(*first);
+    }
+
+    let mut point = ring_hash(process_id) % total;
+    for (i, share) in shares {
+        if point < share as u64 {
+            return Some(i);
+        }
+        point -= share as u64;
+    }
+    Some(*first)
+}
+
+// sort key for which scheduler a wallet routes to first
+fn wallet_precedence(scheduler: &Scheduler) -> (i32, i32) {
+    (
+        scheduler.priority.unwrap_or(i32::MAX),
+        scheduler.row_id.unwrap_or(i32::MAX),
+    )
+}
+
+/*
+    Wallets listed by more than one scheduler, with
+    the urls of those schedulers in precedence order.
+    A wallet split by shares is meant to overlap and
+    is left out.
+*/
+fn wallet_overlaps(schedulers: &[Scheduler]) -> Vec<(String, Vec<String>)> {
+    let mut ordered = schedulers.iter().collect::<Vec<_>>();
+    ordered.sort_by_key(|scheduler| wallet_precedence(scheduler));
+
+    let mut by_wallet: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut split = HashSet::new();
+    for scheduler in ordered {
+        if let Some(w) = &scheduler.wallets_to_route {
+            for entry in w {
+                let (wallet, share) = wallet_share(entry);
+                let wallet = normalize_address(wallet);
+                let urls = by_wallet.entry(wallet.clone()).or_default();
+                if urls.is_empty() && share.is_some() {
+                    split.insert(wallet);
+                }
+                if !urls.contains(&scheduler.url) {
+                    urls.push(scheduler.url.clone());
+                }
+            }
+        }
+    }
+
+    by_wallet
+        .into_iter()
+        .filter(|(wallet, urls)| urls.len() > 1 && !split.contains(wallet))
+        .collect()
+}
+
+/*
+    How new processes are spread over the schedulers.
+    least-count applies the wallet, size and load rules.
+    consistent-hash places a process by its id on a hash
+    ring of the routable schedulers only, so where it
+    lives can be worked out again without its
+    process_schedulers row, for example after losing the
+    router database. Rows are still written and win over
+    the ring when present.
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum RoutingStrategy {
+    LeastCount,
+    ConsistentHash,
+}
+
+impl RoutingStrategy {
+    fn from_config(strategy: &str) -> Result<Self, String> {
+        match strategy {
+            "least-count" => Ok(RoutingStrategy::LeastCount),
+            "consistent-hash" => Ok(RoutingStrategy::ConsistentHash),
+            _ => Err(format!("Invalid routing strategy: {}", strategy)),
+        }
+    }
+}
+
+// points on the ring per unit of scheduler weight
+const RING_POINTS: i32 = 100;
+
+fn ring_hash(key: &str) -> u64 {
+    let digest = hash(key.as_bytes());
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/*
+    The first point on the ring at or after the hash of
+    the process id. Draining schedulers have no points,
+    adding or removing a scheduler only moves the
+    processes that hash next to its points.
+*/
+fn ring_scheduler<'a>(schedulers: &'a [Scheduler], process_id: &str) -> Option<&'a Scheduler> {
+    let mut ring = vec![];
+    for (i, scheduler) in schedulers.iter().enumerate() {
+        if !scheduler.routable() {
+            continue;
+        }
+        for point in 0..RING_POINTS * scheduler.weight.unwrap_or(1).max(1) {
+            ring.push((ring_hash(&format!("{}#{}", scheduler.url, point)), i));
+        }
+    }
+    if ring.is_empty() {
+        return None;
+    }
+    ring.sort_unstable();
+
+    let key = ring_hash(process_id);
+    let at = ring.partition_point(|(point, _)| *point < key) % ring.len();
+    Some(&schedulers[ring[at].1])
+}
+
+/*
+    Process id to the scheduler it is pinned to, so the
+    redirects for a busy process skip the process_schedulers
+    and schedulers lookups. Entries expire after the ttl so
+    a move made by another router is picked up, moves and
+    scheduler changes made by this one drop them straight
+    away. Only pinned processes are cached, never the
+    hash ring's placement of a process without a row.
+    Entries for the same scheduler share one copy of it,
+    so a hit clones an Arc rather than the url and
+    wallets_to_route list.
+
+    With a shared cache every entry is also written there
+    for the other replicas of the router, a miss here
+    looks in it before the database and an invalidation
+    drops the entry in both. Clearing only clears this
+    router's entries, the shared ones run out their ttl.
+*/
+pub struct RouteCache {
+    entries: Option<Mutex<CachedRoutes>>,
+    ttl: Duration,
+    shared: Option<Arc<dyn SharedCache>>,
+}
+
+struct CachedRoutes {
+    routes: LruCache<String, (Arc<Scheduler>, Instant)>,
+    // by url, the copy new entries for that scheduler point at
+    schedulers: HashMap<String, Arc<Scheduler>>,
+}
+
+impl CachedRoutes {
+    fn shared(&mut self, scheduler: &Scheduler) -> Arc<Scheduler> {
+        match self.schedulers.get(&scheduler.url) {
+            Some(shared) if **shared == *scheduler => shared.clone(),
+            _ => {
+                let shared = Arc::new(scheduler.clone());
+                self.schedulers
+                    .insert(scheduler.url.clone(), shared.clone());
+                shared
+            }
+        }
+    }
+}
+
+impl RouteCache {
+    // a size or ttl of 0 turns the cache off
+    pub fn new(size: usize, ttl: Duration) -> Self {
+        let entries = match ttl.is_zero() {
+            true => None,
+            false => NonZeroUsize::new(size).map(|size| {
+                Mutex::new(CachedRoutes {
+                    routes: LruCache::new(size),
+                    schedulers: HashMap::new(),
+                })
+            }),
+        };
+        RouteCache {
+            entries,
+            ttl,
+            shared: None,
+        }
+    }
+
+    pub fn with_shared(mut self, shared: Arc<dyn SharedCache>) -> Self {
+        self.shared = Some(shared);
+        self
+    }
+
+    // failures of the shared cache are misses, the database still has the route
+    fn get_shared(&self, process_id: &str) -> Option<Scheduler> {
+        let value = self
+            .shared
+            .as_ref()?
+            .get(&route_key(process_id))
+            .ok()
+            .flatten()?;
+        serde_json::from_str(&value).ok()
+    }
+
+    /*
+        Expired entries are left in place, they are
+        overwritten by the next lookup or evicted, so
+        get_stale can still redirect while the router
+        tables are unreachable
+    */
+    fn get(&self, process_id: &str) -> Option<Arc<Scheduler>> {
+        let entries = self.entries.as_ref()?;
+        match entries.lock().unwrap().routes.get(process_id) {
+            Some((scheduler, cached_at)) if cached_at.elapsed() < self.ttl => {
+                return Some(scheduler.clone())
+            }
+            _ => (),
+        }
+
+        let scheduler = self.get_shared(process_id)?;
+        let mut entries = entries.lock().unwrap();
+        let shared = entries.shared(&scheduler);
+        entries
+            .routes
+            .put(process_id.to_string(), (shared.clone(), Instant::now()));
+        Some(shared)
+    }
+
+    fn get_stale(&self, process_id: &str) -> Option<Arc<Scheduler>> {
+        let mut entries = self.entries.as_ref()?.lock().unwrap();
+        entries
+            .routes
+            .get(process_id)
+            .map(|(scheduler, _)| scheduler.clone())
+    }
+
+    fn insert(&self, process_id: &str, scheduler: &Scheduler) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap();
+            let shared = entries.shared(scheduler);
+            entries
+                .routes
+                .put(process_id.to_string(), (shared, Instant::now()));
+        }
+        if let (Some(_), Some(shared)) = (&self.entries, &self.shared) {
+            if let Ok(value) = serde_json::to_string(scheduler) {
+                let _ = shared.set(&route_key(process_id), &value, self.ttl.as_secs());
+            }
+        }
+    }
+
+    // the process moved or lost its row
+    fn invalidate(&self, process_id: &str) {
+        if let Some(entries) = &self.entries {
+            entries.lock().unwrap().routes.pop(process_id);
+        }
+        if let Some(shared) = &self.shared {
+            let _ = shared.delete(&route_key(process_id));
+        }
+    }
+
+    // a scheduler changed, its url or public_url may be different now
+    fn clear(&self) {
+        if let Some(entries) = &self.entries {
+            let mut entries = entries.lock().unwrap();
+            entries.routes.clear();
+            entries.schedulers.clear();
+        }
+    }
+}
+
+fn route_key(process_id: &str) -> String {
+    format!("route:{}", process_id)
+}
+
+// prefix of the errors returned while the router tables are unreachable
+pub const ROUTER_DEGRADED: &str = "Router degraded";
+
+// prefix of the error a spawn gets when only full schedulers could take it
+pub const ROUTER_AT_CAPACITY: &str = "Every scheduler is at capacity";
+
+// prefix of the error a spawn gets when the schedulers its wallet is strictly pinned to can't take it
+pub const WALLET_PINNED_UNAVAILABLE: &str = "The schedulers pinned for this wallet can't take it";
+
+// prefix of the errors a standby router returns for what only a primary does
+pub const ROUTER_STANDBY: &str = "Router is a standby";
+
+/*
+    Whether the router tables are reachable, and the last
+    scheduler list read from them. A database error from
+    the router store marks it down, the probe job or any
+    later successful lookup marks it up again. While down
+    and ROUTER_DEGRADED_READS is on, redirects are served
+    from the route cache, including expired entries, and
+    the hash ring over the last scheduler list, and new
+    processes are refused since they can't be recorded.
+*/
+pub struct RouterHealth {
+    down_since: Mutex<Option<SystemTime>>,
+    schedulers: Mutex<Vec<Scheduler>>,
+}
+
+impl RouterHealth {
+    pub fn new() -> Self {
+        RouterHealth {
+            down_since: Mutex::new(None),
+            schedulers: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn down_since(&self) -> Option<SystemTime> {
+        *self.down_since.lock().unwrap()
+    }
+
+    // returns true when this call is the one that marked it down
+    fn mark_down(&self) -> bool {
+        let mut down_since = self.down_since.lock().unwrap();
+        let changed = down_since.is_none();
+        if changed {
+            *down_since = Some(SystemTime::now());
+        }
+        changed
+    }
+
+    // returns true when this call is the one that marked it up
+    fn mark_up(&self) -> bool {
+        self.down_since.lock().unwrap().take().is_some()
+    }
+
+    fn remember_schedulers(&self, schedulers: &[Scheduler]) {
+        *self.schedulers.lock().unwrap() = schedulers.to_vec();
+    }
+
+    fn schedulers(&self) -> Vec<Scheduler> {
+        self.schedulers.lock().unwrap().clone()
+    }
+}
+
+impl Default for RouterHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/*
+    Whether this router is a warm standby. A standby keeps
+    its tables from the change stream of its primary and
+    redirects from them, but assigns no new processes,
+    takes no scheduler changes and runs none of the jobs
+    that write to the tables until it is promoted.
+*/
+pub struct RouterRole {
+    standby: AtomicBool,
+}
+
+impl RouterRole {
+    pub fn new(standby: bool) -> Self {
+        RouterRole {
+            standby: AtomicBool::new(standby),
+        }
+    }
+
+    pub fn standby(&self) -> bool {
+        self.standby.load(Ordering::SeqCst)
+    }
+
+    // returns true when this call is the one that promoted it
+    pub fn promote(&self) -> bool {
+        self.standby.swap(false, Ordering::SeqCst)
+    }
+}
+
+fn require_primary(deps: &Arc<Deps>, what: &str) -> Result<(), String> {
+    if deps.router_role.standby() {
+        return Err(format!("{}, {} until it is promoted", ROUTER_STANDBY, what));
+    }
+    Ok(())
+}
+
+/*
+    Runs on a standby router for each batch of changes its
+    primary streams, in the order they were made. A router
+    that is not a standby refuses them, so a primary that
+    was failed over can't overwrite the one promoted in
+    its place.
+*/
+pub async fn apply_router_changes(deps: Arc<Deps>, body: Vec<u8>) -> Result<String, String> {
+    require_router(&deps)?;
+    if !deps.router_role.standby() {
+        return Err("Router is not a standby, streamed changes are refused".to_string());
+    }
+    let changes: Vec<RouterChange> =
+        serde_json::from_slice(&body).map_err(|e| format!("Invalid router changes: {}", e))?;
+
+    for change in &changes {
+        if let Some(process_id) = apply_router_change(deps.router_data_store.as_ref(), change)? {
+            deps.route_cache.invalidate(&process_id);
+        }
+    }
+    if changes
+        .iter()
+        .any(|change| !matches!(change, RouterChange::Process(_) | RouterChange::Owner(_)))
+    {
+        deps.route_cache.clear();
+        let schedulers = deps.router_data_store.get_all_schedulers()?;
+        deps.router_health.remember_schedulers(&schedulers);
+    }
+    Ok(json!({ "applied": changes.len() }).to_string())
+}
+
+/*
+    Make a standby router the primary, from then on it
+    assigns new processes and runs the router jobs on the
+    tables it was streamed. Promoting a primary does
+    nothing.
+*/
+pub async fn promote_router(deps: Arc<Deps>, actor: &AdminActor) -> Result<String, String> {
+    require_router(&deps)?;
+    if !deps.router_role.promote() {
+        return Ok(json!({ "promoted": false }).to_string());
+    }
+    deps.logger
+        .log("standby router promoted to primary through admin api".to_string());
+    audit_admin(
+        &deps,
+        actor,
+        "router.promote",
+        "router",
+        Some(json!({ "standby": true })),
+        Some(json!({ "standby": false })),
+    )?;
+    Ok(json!({ "promoted": true }).to_string())
+}
+
+/*
+    Records what a router store call says about the
+    store, only database errors count as it being
+    unreachable, a missing row means it answered
+*/
+fn observe<T>(deps: &Arc<Deps>, result: Result<T, StoreErrorType>) -> Result<T, StoreErrorType> {
+    match &result {
+        Err(StoreErrorType::DatabaseError(e)) => {
+            if deps.router_health.mark_down() {
+                deps.logger.error(format!(
+                    "router database unreachable, redirecting from cache: {}",
+                    e
+                ));
+            }
+        }
+        _ => {
+            if deps.router_health.mark_up() {
+                deps.logger
+                    .log("router database reachable again".to_string());
+            }
+        }
+    }
+    result
+}
+
+fn degraded(deps: &Arc<Deps>) -> bool {
+    deps.config.router_degraded_reads() && deps.router_health.down_since().is_some()
+}
+
+fn degraded_error(what: &str) -> String {
+    format!(
+        "{}, the router database is unreachable and {}",
+        ROUTER_DEGRADED, what
+    )
+}
+
+/*
+    Where a process goes while the router tables are
+    unreachable, its cached scheduler or its place on
+    the hash ring over the last scheduler list read
+*/
+fn locate_degraded(deps: &Arc<Deps>, process_id: &str) -> Result<RouteDecision, String> {
+    if let Some(scheduler) = deps.route_cache.get_stale(process_id) {
+        return Ok(scheduler.route(RouteRule::Pinned));
+    }
+    if hashing(deps)? {
+        if let Some(scheduler) = ring_scheduler(&deps.router_health.schedulers(), process_id) {
+            return Ok(scheduler.route(RouteRule::Hash));
+        }
+    }
+    Err(degraded_error(&format!(
+        "process {} is not in the route cache",
+        process_id
+    )))
+}
+
+/*
+    Runs on an interval in router mode, while the store
+    is marked down it is read again so routing goes back
+    to it once it answers. Returns None when nothing
+    changed.
+*/
+pub async fn probe_router_store(deps: Arc<Deps>) -> Result<Option<String>, String> {
+    if deps.router_health.down_since().is_none() {
+        return Ok(None);
+    }
+    let schedulers = observe(&deps, deps.router_data_store.get_all_schedulers())?;
+    deps.router_health.remember_schedulers(&schedulers);
+    Ok(Some("router database probe succeeded".to_string()))
+}
+
+/*
+    The status /health reports, degraded while the
+    router tables are unreachable
+*/
+pub fn health_status(deps: &Arc<Deps>) -> serde_json::Value {
+    match deps.router_health.down_since() {
+        Some(since) => json!({
+            "status": "degraded",
+            "router_store_down_for": since.elapsed().map(|d| d.as_secs()).unwrap_or(0),
+            "degraded_reads": deps.config.router_degraded_reads(),
+        }),
+        None => json!({ "status": "ok" }),
+    }
+}
+
+/*
+    The scheduler a process is assigned to. Under the
+    consistent-hash strategy a process without a row is
+    placed by the ring instead of failing.
+*/
+fn locate_process(deps: &Arc<Deps>, process_id: &str) -> Result<RouteDecision, String> {
+    if let Some(scheduler) = deps.route_cache.get(process_id) {
+        return Ok(scheduler.route(RouteRule::Pinned));
+    }
+    if degraded(deps) {
+        return locate_degraded(deps, process_id);
+    }
+    match observe(
+        deps,
+        deps.router_data_store.get_process_scheduler(process_id),
+    ) {
+        Ok(process_scheduler) => {
+            let scheduler = observe(
+                deps,
+                deps.router_data_store
+                    .get_scheduler(&process_scheduler.scheduler_row_id),
+            )?;
+            deps.route_cache.insert(process_id, &scheduler);
+            Ok(scheduler.route(RouteRule::Pinned))
+        }
+        Err(StoreErrorType::NotFound(_)) if hashing(deps)? => {
+            let schedulers = observe(deps, deps.router_data_store.get_all_schedulers())?;
+            deps.router_health.remember_schedulers(&schedulers);
+            let scheduler = ring_scheduler(&schedulers, process_id)
+                .ok_or("Could not find a scheduler on the hash ring")?;
+            Ok(scheduler.route(RouteRule::Hash))
+        }
+        Err(StoreErrorType::DatabaseError(_)) if degraded(deps) => {
+            locate_degraded(deps, process_id)
+        }
+        Err(e) => Err(e.into()),
+    }
+}
+
+// where a process that already has a row is routed, None if it has none
+fn assigned_route(deps: &Arc<Deps>, process_id: &str) -> Result<Option<RouteDecision>, String> {
+    if let Some(scheduler) = deps.route_cache.get(process_id) {
+        return Ok(Some(scheduler.route(RouteRule::Pinned)));
+    }
+    match observe(
+        deps,
+        deps.router_data_store.get_process_scheduler(process_id),
+    ) {
+        Ok(process_scheduler) => {
+            let scheduler = observe(
+                deps,
+                deps.router_data_store
+                    .get_scheduler(&process_scheduler.scheduler_row_id),
+            )?;
+            deps.route_cache.insert(process_id, &scheduler);
+            Ok(Some(scheduler.route(RouteRule::Pinned)))
+        }
+        Err(StoreErrorType::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/*
+    A spawn of a process that is already assigned is
+    redirected like a message to it, it is left out of
+    the spawn audit log so a replay doesn't place it twice
+*/
+fn duplicate_spawn(deps: &Arc<Deps>, process_id: &str, decision: RouteDecision) -> RouteDecision {
+    deps.metrics.duplicate_spawn();
+    deps.logger.event(
+        LogLevel::Info,
+        "router",
+        format!("duplicate spawn sent to {}", decision.url),
+        LogFields::process(process_id),
+    );
+    decision
+}
+
+fn hashing(deps: &Arc<Deps>) -> Result<bool, String> {
+    Ok(RoutingStrategy::from_config(&deps.config.routing_strategy())?
+        == RoutingStrategy::ConsistentHash)
+}
+
+/*
+    What the cleanup job does with a broken
+    process_schedulers row. Alert only logs and
+    counts it, reassign moves an orphaned process to
+    the least loaded scheduler, delete removes the row
+    so the process is routed again like a new one.
+*/
+#[derive(Debug, Clone, PartialEq)]
+enum CleanupPolicy {
+    Reassign,
+    Delete,
+    Alert,
+}
+
+impl CleanupPolicy {
+    fn from_config(policy: &str) -> Result<Self, String> {
+        match policy {
+            "reassign" => Ok(CleanupPolicy::Reassign),
+            "delete" => Ok(CleanupPolicy::Delete),
+            "alert" => Ok(CleanupPolicy::Alert),
+            _ => Err(format!("Invalid process scheduler cleanup policy: {}", policy)),
+        }
+    }
+}
+
+/*
+    Find process_schedulers rows whose scheduler was
+    deleted, or extra rows for a process id that is
+    already assigned, and fix them per the configured
+    policy. For duplicates the oldest row is kept since
+    that is where the process was first routed, reassign
+    and delete both drop the newer rows.
+*/
+pub async fn cleanup_process_schedulers(deps: Arc<Deps>) -> Result<String, String> {
+    let policy = CleanupPolicy::from_config(&deps.config.process_scheduler_cleanup_policy())?;
+    let store = &deps.router_data_store;
+
+    let orphans = store.get_orphaned_process_schedulers()?;
+    for orphan in orphans.iter() {
+        let action = match policy {
+            CleanupPolicy::Reassign => {
+                let mut schedulers = store
+                    .get_all_schedulers()?
+                    .into_iter()
+                    .filter(|scheduler| {
+                        scheduler.routable() && !scheduler.wallets_only.unwrap_or(false)
+                    })
+                    .collect::<Vec<_>>();
+                let target = least_loaded(&mut schedulers)
+                    .ok_or("Could not find a scheduler to reassign to")?;
+                let scheduler_row_id = target.row_id.ok_or("Missing id on scheduler")?;
+
+                store.update_process_scheduler(&ProcessScheduler {
+                    row_id: orphan.row_id,
+                    process_id: orphan.process_id.clone(),
+                    scheduler_row_id,
+                })?;
+
+                store.increment_process_count(&scheduler_row_id, 1)?;
+                target.process_count += 1;
+                deps.route_cache.invalidate(&orphan.process_id);
+                "orphan_reassigned"
+            }
+            CleanupPolicy::Delete => {
+                store.delete_process_scheduler(&orphan.row_id.ok_or("Missing id on row")?)?;
+                deps.route_cache.invalidate(&orphan.process_id);
+                "orphan_deleted"
+            }
+            CleanupPolicy::Alert => "orphan_alerted",
+        };
+        deps.logger.error(format!(
+            "process {} pointed at missing scheduler {}, {}",
+            orphan.process_id, orphan.scheduler_row_id, action
+        ));
+        deps.metrics.process_scheduler_cleanup(action);
+    }
+
+    let duplicates = store.get_duplicate_process_schedulers()?;
+    let mut duplicate_count = 0;
+    for (i, duplicate) in duplicates.iter().enumerate() {
+        // rows come ordered oldest first per process id, keep the first
+        if i == 0 || duplicates[i - 1].process_id != duplicate.process_id {
+            continue;
+        }
+        duplicate_count += 1;
+
+        let action = match policy {
+            CleanupPolicy::Reassign | CleanupPolicy::Delete => {
+                store.delete_process_scheduler(&duplicate.row_id.ok_or("Missing id on row")?)?;
+                deps.route_cache.invalidate(&duplicate.process_id);
+                "duplicate_deleted"
+            }
+            CleanupPolicy::Alert => "duplicate_alerted",
+        };
+        deps.logger.error(format!(
+            "process {} has a duplicate row for scheduler {}, {}",
+            duplicate.process_id, duplicate.scheduler_row_id, action
+        ));
+        deps.metrics.process_scheduler_cleanup(action);
+    }
+
+    Ok(format!(
+        "process scheduler cleanup found {} orphaned and {} duplicate rows",
+        orphans.len(),
+        duplicate_count
+    ))
+}
+
+/*
+    Runs on an interval in router mode. Processes on a
+    scheduler with drain set are moved a batch per run
+    to the least loaded healthy scheduler, once none are
+    left the scheduler can be removed.
+
+    With DRAIN_COPY_TOKEN set the process history is
+    copied to the new scheduler before its row moves and
+    again after, to pick up messages the old scheduler
+    took while the move was in progress. A process that
+    fails to copy stays where it is until the next run.
+*/
+pub async fn drain_schedulers(deps: Arc<Deps>) -> Result<Option<String>, String> {
+    if deps.router_role.standby() {
+        return Ok(None);
+    }
+    let store = &deps.router_data_store;
+    let draining = store
+        .get_all_schedulers()?
+        .into_iter()
+        .filter(|scheduler| scheduler.drain.unwrap_or(false))
+        .collect::<Vec<_>>();
+    if draining.is_empty() {
+        return Ok(None);
+    }
+
+    let batch_size = deps.config.drain_batch_size();
+    let mut moved = 0;
+    for source in draining.iter() {
+        let source_row_id = source.row_id.ok_or("Missing id on scheduler")?;
+        let rows = store.get_process_schedulers_for(&source_row_id, &0, batch_size)?;
+        if rows.is_empty() {
+            continue;
+        }
+
+        let mut failed = 0;
+        for row in rows.iter() {
+            match drain_process(&deps, source, row).await {
+                Ok(url) => {
+                    moved += 1;
+                    deps.logger.event(
+                        LogLevel::Info,
+                        "router",
+                        format!("moved process off draining scheduler to {}", url),
+                        LogFields {
+                            process_id: Some(row.process_id.clone()),
+                            scheduler_url: Some(source.url.clone()),
+                            latency_ms: None,
+                        },
+                    );
+                }
+                Err(e) => {
+                    failed += 1;
+                    deps.logger.event(
+                        LogLevel::Error,
+                        "router",
+                        format!("failed to move process off draining scheduler: {}", e),
+                        LogFields {
+                            process_id: Some(row.process_id.clone()),
+                            scheduler_url: Some(source.url.clone()),
+                            latency_ms: None,
+                        },
+                    );
+                }
+            }
+        }
+
+        if failed == 0 && (rows.len() as i64) < batch_size {
+            deps.logger.log(format!(
+                "scheduler {} is drained, it can be removed",
+                source.url
+            ));
+        }
+    }
+
+    Ok(Some(format!(
+        "moved {} processes off {} draining schedulers",
+        moved,
+        draining.len()
+    )))
+}
+
+async fn drain_process(
+    deps: &Arc<Deps>,
+    source: &Scheduler,
+    row: &ProcessScheduler,
+) -> Result<String, String> {
+    let store = &deps.router_data_store;
+    let mut targets = store
+        .get_all_schedulers()?
+        .into_iter()
+        .filter(|scheduler| {
+            scheduler.routable()
+                && !scheduler.wallets_only.unwrap_or(false)
+                && is_healthy(deps, scheduler)
+        })
+        .collect::<Vec<_>>();
+    let target =
+        least_loaded(&mut targets).ok_or("Could not find a healthy scheduler to move to")?;
+
+    let copy_token = deps.config.drain_copy_token();
+    let copy = !copy_token.is_empty();
+    let mut copied_to = None;
+    if copy {
+        copied_to = copy_history(deps, &source.url, &target.url, &row.process_id, None).await?;
+    }
+
+    move_process_row(deps, row, target)?;
+
+    if copy {
+        copy_history(deps, &source.url, &target.url, &row.process_id, copied_to)
+            .await
+            .map_err(|e| format!("moved but the catch up copy failed: {}", e))?;
+    }
+
+    Ok(target.url.clone())
+}
+
+// point a process at target and move it from one process_count to the other
+fn move_process_row(
+    deps: &Arc<Deps>,
+    row: &ProcessScheduler,
+    target: &mut Scheduler,
+) -> Result<(), String> {
+    let target_row_id = target.row_id.ok_or("Missing id on scheduler")?;
+    deps.router_data_store
+        .move_process(&row.process_id, &row.scheduler_row_id, &target_row_id)?;
+    deps.route_cache.invalidate(&row.process_id);
+    target.process_count += 1;
+    Ok(())
+}
+
+#[derive(Deserialize)]
+struct ExportPage {
+    process: Option<String>,
+    messages: Vec<String>,
+    has_next: bool,
+    next_from: Option<String>,
+}
+
+/*
+    Copy a process history from one su to another page by
+    page, starting after the from timestamp. Returns where
+    the copy ended so a later pass can continue from it.
+*/
+async fn copy_history(
+    deps: &Arc<Deps>,
+    from_url: &str,
+    to_url: &str,
+    process_id: &str,
+    mut from: Option<String>,
+) -> Result<Option<String>, String> {
+    let token = deps.config.drain_copy_token();
+    loop {
+        let page = deps
+            .ext_router
+            .export_process(
+                from_url.to_string(),
+                process_id.to_string(),
+                from.clone(),
+                token.clone(),
+            )
+            .await?;
+        let page: ExportPage = serde_json::from_str(&page).map_err(|e| format!("{:?}", e))?;
+
+        let items = page
+            .process
+            .iter()
+            .map(|process| (ReplicaKind::Process, process))
+            .chain(
+                page.messages
+                    .iter()
+                    .map(|message| (ReplicaKind::Message, message)),
+            );
+        for (kind, item) in items {
+            let binary = base64_url::decode(item).map_err(|e| format!("{:?}", e))?;
+            deps.ext_router
+                .push_replica(to_url.to_string(), kind, binary, token.clone())
+                .await?;
+        }
+
+        from = page.next_from;
+        if !page.has_next {
+            return Ok(from);
+        }
+    }
+}
+
+/*
+    Move one process to another scheduler, to is its url
+    or row id, so an operator can take a hot process off a
+    busy su. The history is copied while the old scheduler
+    still takes writes, then the process is made read only
+    there and the rest is copied. Both copies have to be
+    an unbroken hash chain ending on the same assignment
+    before the route moves, otherwise the old scheduler
+    takes writes again and the process stays on it.
+*/
+pub async fn migrate_process(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    process_id: String,
+    to: String,
+) -> Result<String, String> {
+    require_router(&deps)?;
+    require_primary(&deps, "processes are migrated on its primary")?;
+    let token = deps.config.drain_copy_token();
+    if token.is_empty() {
+        return Err(
+            "Migrating needs DRAIN_COPY_TOKEN, the admin token of the schedulers".to_string(),
+        );
+    }
+
+    let store = &deps.router_data_store;
+    let row = store.get_process_scheduler(&process_id)?;
+    let source = store.get_scheduler(&row.scheduler_row_id)?;
+    let mut target = match to.parse::<i32>() {
+        Ok(row_id) => store.get_scheduler(&row_id)?,
+        Err(_) => store.get_scheduler_by_url(&to)?,
+    };
+    if target.row_id == source.row_id {
+        return Err(format!(
+            "Process {} is already on {}",
+            process_id, source.url
+        ));
+    }
+    if !is_healthy(&deps, &target) {
+        return Err(format!("Scheduler {} is not healthy", target.url));
+    }
+
+    let (last, messages) = migrate(&deps, &row, &source, &mut target, &token).await?;
+    deps.logger.event(
+        LogLevel::Info,
+        "router",
+        format!("migrated process to {}", target.url),
+        LogFields {
+            process_id: Some(process_id.clone()),
+            scheduler_url: Some(source.url.clone()),
+            latency_ms: None,
+        },
+    );
+    audit_admin(
+        &deps,
+        actor,
+        "process.migrate",
+        &process_id,
+        Some(json!({ "scheduler": source.url })),
+        Some(json!({ "scheduler": target.url })),
+    )?;
+
+    Ok(json!({
+        "process_id": process_id,
+        "from": source.url,
+        "to": target.url,
+        "messages": messages,
+        "nonce": last.as_ref().map(|link| link.nonce),
+        "hash_chain": last.map(|link| link.hash_chain),
+    })
+    .to_string())
+}
+
+/*
+    The steps of a migration, from the first copy to the
+    row moving, shared by migrate_process and the
+    rebalancer. Returns the last link of the chain and
+    the number of messages.
+*/
+async fn migrate(
+    deps: &Arc<Deps>,
+    row: &ProcessScheduler,
+    source: &Scheduler,
+    target: &mut Scheduler,
+    token: &str,
+) -> Result<(Option<ChainLink>, usize), String> {
+    let process_id = &row.process_id;
+    let copied_to = copy_history(deps, &source.url, &target.url, process_id, None).await?;
+    deps.ext_router
+        .set_read_only(
+            source.url.clone(),
+            process_id.clone(),
+            Some(target.url.clone()),
+            token.to_string(),
+        )
+        .await?;
+
+    let copied = match finish_copy(deps, &source.url, &target.url, process_id, copied_to).await {
+        Ok(copied) => copied,
+        Err(e) => {
+            if let Err(undo) = deps
+                .ext_router
+                .set_read_only(
+                    source.url.clone(),
+                    process_id.clone(),
+                    None,
+                    token.to_string(),
+                )
+                .await
+            {
+                deps.logger.error(format!(
+                    "process {} is still read only on {} after a failed migration: {:?}",
+                    process_id, source.url, undo
+                ));
+            }
+            return Err(format!(
+                "Migrating {} to {} failed, it stays on {}: {}",
+                process_id, target.url, source.url, e
+            ));
+        }
+    };
+
+    move_process_row(deps, row, target)?;
+    Ok(copied)
+}
+
+/*
+    The catch up copy of a migration once the old
+    scheduler is read only, then the check of both copies.
+    Returns the last link of the chain and the number of
+    messages.
+*/
+async fn finish_copy(
+    deps: &Arc<Deps>,
+    from_url: &str,
+    to_url: &str,
+    process_id: &str,
+    copied_to: Option<String>,
+) -> Result<(Option<ChainLink>, usize), String> {
+    copy_history(deps, from_url, to_url, process_id, copied_to).await?;
+    let (source_last, messages) = read_chain(deps, from_url, process_id).await?;
+    let (target_last, copied) = read_chain(deps, to_url, process_id).await?;
+    if source_last != target_last || messages != copied {
+        return Err(format!(
+            "the copy has {} messages ending at {:?}, the original {} ending at {:?}",
+            copied, target_last, messages, source_last
+        ));
+    }
+    Ok((source_last, messages))
+}
+
+// one assignment of a process history, as far as its hash chain goes
+#[derive(Debug, Clone, PartialEq)]
+struct ChainLink {
+    nonce: i32,
+    hash_chain: String,
+    assignment_id: String,
+}
+
+/*
+    Read a whole process history from an su and check its
+    hash chain, returning the last link and the number of
+    messages. Messages repeated across pages are counted
+    once, two assignments at one nonce are a fork.
+*/
+async fn read_chain(
+    deps: &Arc<Deps>,
+    url: &str,
+    process_id: &str,
+) -> Result<(Option<ChainLink>, usize), String> {
+    let token = deps.config.drain_copy_token();
+    let mut start = None;
+    let mut links = BTreeMap::new();
+    let mut from = None;
+    loop {
+        let page = deps
+            .ext_router
+            .export_process(
+                url.to_string(),
+                process_id.to_string(),
+                from.clone(),
+                token.clone(),
+            )
+            .await?;
+        let page: ExportPage = serde_json::from_str(&page).map_err(|e| format!("{:?}", e))?;
+
+        if let Some(process) = page.process {
+            let binary = base64_url::decode(&process).map_err(|e| format!("{:?}", e))?;
+            let process = Process::from_bytes(binary)?;
+            if process.assignment.is_some() {
+                start = Some(ChainLink {
+                    nonce: process.nonce()?,
+                    hash_chain: process.hash_chain()?,
+                    assignment_id: process.assignment_id()?,
+                });
+            }
+        }
+        for message in page.messages {
+            let binary = base64_url::decode(&message).map_err(|e| format!("{:?}", e))?;
+            let message = Message::from_bytes(binary)?;
+            let link = ChainLink {
+                nonce: message.nonce()?,
+                hash_chain: message.hash_chain()?,
+                assignment_id: message.assignment_id()?,
+            };
+            match links.get(&link.nonce) {
+                Some(ChainLink { assignment_id, .. }) if *assignment_id != link.assignment_id => {
+                    return Err(format!(
+                        "{} has two assignments at nonce {} on {}",
+                        process_id, link.nonce, url
+                    ))
+                }
+                Some(_) => (),
+                None => {
+                    links.insert(link.nonce, link);
+                }
+            }
+        }
+
+        from = page.next_from;
+        if !page.has_next {
+            break;
+        }
+    }
+
+    let last =
+        verify_chain(process_id, start, links.values()).map_err(|e| format!("{} on {}", e, url))?;
+    Ok((last, links.len()))
+}
+
+/*
+    Check links in nonce order follow on from start, the
+    process assignment, or from the process id itself for
+    a process spawned before processes had one. Each link
+    takes the next nonce and the chain derived from the
+    link before it.
+*/
+fn verify_chain<'a>(
+    process_id: &str,
+    start: Option<ChainLink>,
+    links: impl Iterator<Item = &'a ChainLink>,
+) -> Result<Option<ChainLink>, String> {
+    let mut previous = start;
+    for link in links {
+        let (nonce, hash_chain) = match &previous {
+            Some(previous) => (
+                previous.nonce + 1,
+                gen_hash_chain(&previous.hash_chain, Some(&previous.assignment_id))?,
+            ),
+            None => (0, gen_hash_chain(process_id, None)?),
+        };
+        if link.nonce != nonce {
+            return Err(format!(
+                "the history of {} has no message at nonce {}",
+                process_id, nonce
+            ));
+        }
+        if link.hash_chain != hash_chain {
+            return Err(format!(
+                "the hash chain of {} breaks at nonce {}",
+                process_id, nonce
+            ));
+        }
+        previous = Some(link.clone());
+    }
+    Ok(previous)
+}
+
+// processes of a scheduler checked for one that is idle
+const REBALANCE_CANDIDATES: i64 = 100;
+
+/*
+    Runs on an interval in router mode. Processes never
+    leave the scheduler they were spawned on, so one added
+    late stays underloaded. Each run moves up to
+    REBALANCE_MAX_MOVES processes from the most to the
+    least loaded scheduler, as migrate_process moves one,
+    while their loads differ by more than
+    REBALANCE_THRESHOLD of the average. Only schedulers
+    open to any spawn take part, no_route, draining and
+    wallets_only ones keep their processes. Processes
+    with a message in the last REBALANCE_IDLE_SECS are
+    left alone rather than made read only while they
+    are busy. With REBALANCE_DRY_RUN the moves are only
+    logged and counted.
+*/
+pub async fn rebalance_schedulers(deps: Arc<Deps>) -> Result<Option<String>, String> {
+    if deps.router_role.standby() {
+        return Ok(None);
+    }
+    let token = deps.config.drain_copy_token();
+    if token.is_empty() {
+        return Err(
+            "Rebalancing needs DRAIN_COPY_TOKEN, the admin token of the schedulers".to_string(),
+        );
+    }
+
+    let store = &deps.router_data_store;
+    let pool = store
+        .get_all_schedulers()?
+        .into_iter()
+        .filter(|scheduler| {
+            !scheduler.no_route.unwrap_or(false)
+                && !scheduler.drain.unwrap_or(false)
+                && !scheduler.wallets_only.unwrap_or(false)
+                && is_healthy(&deps, scheduler)
+        })
+        .collect::<Vec<_>>();
+    let spread = imbalance(&pool);
+    deps.metrics.rebalance_observe(spread);
+
+    let plan = plan_rebalance(
+        &pool,
+        deps.config.rebalance_threshold(),
+        deps.config.rebalance_max_moves(),
+    );
+    if plan.is_empty() {
+        return Ok(None);
+    }
+
+    let dry_run = deps.config.rebalance_dry_run();
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis() as u64;
+    let idle_since = now.saturating_sub(deps.config.rebalance_idle_secs() * 1000);
+
+    // processes picked or found busy in this run
+    let mut checked = HashSet::new();
+    let (mut moved, mut failed) = (0, 0);
+    for (from, to) in plan {
+        let source = &pool[from];
+        let row = match find_idle_process(&deps, source, idle_since, &token, &mut checked).await? {
+            Some(row) => row,
+            None => continue,
+        };
+        let target_row_id = pool[to].row_id.ok_or("Missing id on scheduler")?;
+        let mut target = store.get_scheduler(&target_row_id)?;
+        let fields = LogFields {
+            process_id: Some(row.process_id.clone()),
+            scheduler_url: Some(source.url.clone()),
+            latency_ms: None,
+        };
+
+        if dry_run {
+            moved += 1;
+            deps.metrics.rebalance_move("planned");
+            deps.logger.event(
+                LogLevel::Info,
+                "router",
+                format!("rebalance dry run would move process to {}", target.url),
+                fields,
+            );
+            continue;
+        }
+        match migrate(&deps, &row, source, &mut target, &token).await {
+            Ok(_) => {
+                moved += 1;
+                deps.metrics.rebalance_move("moved");
+                deps.logger.event(
+                    LogLevel::Info,
+                    "router",
+                    format!("rebalanced process to {}", target.url),
+                    fields,
+                );
+            }
+            Err(e) => {
+                failed += 1;
+                deps.metrics.rebalance_move("failed");
+                deps.logger.event(
+                    LogLevel::Error,
+                    "router",
+                    format!("failed to rebalance: {}", e),
+                    fields,
+                );
+            }
+        }
+    }
+
+    Ok(Some(format!(
+        "rebalancer {} {} processes, {} failed, imbalance was {:.2}",
+        if dry_run { "would move" } else { "moved" },
+        moved,
+        failed,
+        spread
+    )))
+}
+
+/*
+    The first process on source with no message after
+    idle_since that isn't in checked, every process looked
+    at is added to it
+*/
+async fn find_idle_process(
+    deps: &Arc<Deps>,
+    source: &Scheduler,
+    idle_since: u64,
+    token: &str,
+    checked: &mut HashSet<String>,
+) -> Result<Option<ProcessScheduler>, String> {
+    let source_row_id = source.row_id.ok_or("Missing id on scheduler")?;
+    let rows = deps.router_data_store.get_process_schedulers_for(
+        &source_row_id,
+        &0,
+        REBALANCE_CANDIDATES,
+    )?;
+    for row in rows {
+        if !checked.insert(row.process_id.clone()) {
+            continue;
+        }
+        let page = deps
+            .ext_router
+            .export_process(
+                source.url.clone(),
+                row.process_id.clone(),
+                Some(idle_since.to_string()),
+                token.to_string(),
+            )
+            .await?;
+        let page: ExportPage = serde_json::from_str(&page).map_err(|e| format!("{:?}", e))?;
+        if page.messages.is_empty() {
+            return Ok(Some(row));
+        }
+    }
+    Ok(None)
+}
+
+// a scheduler's processes per unit of weight
+fn load(count: i32, scheduler: &Scheduler) -> f64 {
+    count as f64 / scheduler.weight.unwrap_or(1).max(1) as f64
+}
+
+/*
+    The gap between the most and least loaded scheduler
+    as a share of their average load, 0 for a fleet with
+    no processes
+*/
+fn imbalance(schedulers: &[Scheduler]) -> f64 {
+    let loads = schedulers
+        .iter()
+        .map(|scheduler| load(scheduler.process_count, scheduler))
+        .collect::<Vec<_>>();
+    spread(&loads)
+}
+
+fn spread(loads: &[f64]) -> f64 {
+    let mean = loads.iter().sum::<f64>() / loads.len().max(1) as f64;
+    if mean <= 0.0 {
+        return 0.0;
+    }
+    let max = loads.iter().cloned().fold(f64::MIN, f64::max);
+    let min = loads.iter().cloned().fold(f64::MAX, f64::min);
+    (max - min) / mean
+}
+
+/*
+    Moves, as (from, to) indexes into schedulers, each
+    taking a process from the most loaded scheduler to the
+    least loaded one with room for it. Planning stops at
+    max_moves, once the spread is within threshold or when
+    a move would leave the pair the other way round.
+*/
+fn plan_rebalance(
+    schedulers: &[Scheduler],
+    threshold: f64,
+    max_moves: usize,
+) -> Vec<(usize, usize)> {
+    let mut counts = schedulers
+        .iter()
+        .map(|scheduler| scheduler.process_count)
+        .collect::<Vec<_>>();
+    let loads = |counts: &[i32]| {
+        counts
+            .iter()
+            .zip(schedulers)
+            .map(|(count, scheduler)| load(*count, scheduler))
+            .collect::<Vec<_>>()
+    };
+
+    let mut plan = vec![];
+    while plan.len() < max_moves {
+        let current = loads(&counts);
+        if spread(&current) <= threshold {
+            break;
+        }
+        let from = (0..counts.len()).max_by(|a, b| current[*a].total_cmp(&current[*b]));
+        let to = (0..counts.len())
+            .filter(|i| match schedulers[*i].max_processes {
+                Some(max) => counts[*i] < max,
+                None => true,
+            })
+            .min_by(|a, b| current[*a].total_cmp(&current[*b]));
+        let (from, to) = match (from, to) {
+            (Some(from), Some(to)) if from != to && counts[from] > 0 => (from, to),
+            _ => break,
+        };
+        if load(counts[from] - 1, &schedulers[from]) < load(counts[to] + 1, &schedulers[to]) {
+            break;
+        }
+        counts[from] -= 1;
+        counts[to] += 1;
+        plan.push((from, to));
+    }
+    plan
+}
+
+/*
+    Compare live routing data against the invariants the
+    router relies on. Every process maps to exactly one
+    existing scheduler and every scheduler's process_count
+    equals the processes assigned to it. That draining
+    schedulers never gain processes is enforced by
+    select_scheduler, a draining scheduler whose assigned
+    processes outgrow its count shows up as a mismatch.
+*/
+pub async fn check_routing_invariants(deps: Arc<Deps>) -> Result<String, String> {
+    if deps.config.mode() != Mode::Router {
+        return Err("Routing invariants can only be checked in router mode".to_string());
+    }
+
+    let store = &deps.router_data_store;
+    let schedulers = store.get_all_schedulers()?;
+    let assigned = store
+        .get_process_scheduler_counts()?
+        .into_iter()
+        .collect::<HashMap<i32, i64>>();
+    let orphans = store.get_orphaned_process_schedulers()?;
+    let duplicates = store.get_duplicate_process_schedulers()?;
+
+    let violations = routing_violations(&schedulers, &assigned, &orphans, &duplicates);
+    for violation in violations.iter() {
+        deps.logger.error(format!("routing invariant violated: {}", violation));
+    }
+
+    Ok(json!({
+        "ok": violations.is_empty(),
+        "schedulers": schedulers.len(),
+        "assigned_processes": assigned.values().sum::<i64>(),
+        "violations": violations,
+    })
+    .to_string())
+}
+
+fn routing_violations(
+    schedulers: &[Scheduler],
+    assigned: &HashMap<i32, i64>,
+    orphans: &[ProcessScheduler],
+    duplicates: &[ProcessScheduler],
+) -> Vec<String> {
+    let mut violations = vec![];
+
+    for orphan in orphans {
+        violations.push(format!(
+            "process {} is assigned to missing scheduler {}",
+            orphan.process_id, orphan.scheduler_row_id
+        ));
+    }
+
+    let mut by_process: BTreeMap<&str, Vec<i32>> = BTreeMap::new();
+    for duplicate in duplicates {
+        by_process
+            .entry(&duplicate.process_id)
+            .or_default()
+            .push(duplicate.scheduler_row_id);
+    }
+    for (process_id, row_ids) in by_process {
+        violations.push(format!(
+            "process {} is assigned {} times, to schedulers {:?}",
+            process_id,
+            row_ids.len(),
+            row_ids
+        ));
+    }
+
+    for scheduler in schedulers {
+        let count = scheduler
+            .row_id
+            .and_then(|row_id| assigned.get(&row_id).copied())
+            .unwrap_or(0);
+        if scheduler.process_count as i64 != count {
+            violations.push(format!(
+                "scheduler {} has process_count {} but {} assigned processes",
+                scheduler.url, scheduler.process_count, count
+            ));
+        }
+    }
+
+    violations
+}
+
+#[derive(Deserialize)]
+struct ProcessIdPage {
+    processes: Vec<String>,
+    next: Option<String>,
+}
+
+/*
+    Rebuild missing process_schedulers rows from the
+    schedulers themselves, for a router whose tables were
+    restored from an old backup and 404s the processes
+    spawned since. Each scheduler is asked for the ids of
+    the processes it holds the spawn of, a process with no
+    row is assigned to the scheduler holding it. A process
+    whose row points at another scheduler, or that more
+    than one scheduler holds, is a conflict and is only
+    reported. With dry_run nothing is written.
+*/
+pub async fn reconcile_process_schedulers(
+    deps: Arc<Deps>,
+    actor: &AdminActor,
+    dry_run: bool,
+) -> Result<String, String> {
+    require_router(&deps)?;
+    if !dry_run {
+        require_primary(&deps, "its tables are reconciled on its primary")?;
+    }
+    let token = deps.config.drain_copy_token();
+    if token.is_empty() {
+        return Err(
+            "Reconciling needs DRAIN_COPY_TOKEN, the admin token of the schedulers".to_string(),
+        );
+    }
+
+    let store = &deps.router_data_store;
+    let schedulers = store.get_all_schedulers()?;
+    let urls: HashMap<i32, String> = schedulers
+        .iter()
+        .filter_map(|scheduler| scheduler.row_id.map(|id| (id, scheduler.url.clone())))
+        .collect();
+
+    // processes found without a row in this run, to the scheduler they were given
+    let mut assigned: HashMap<String, String> = HashMap::new();
+    let mut conflicts = vec![];
+    let mut reports = vec![];
+    for scheduler in schedulers.iter() {
+        let row_id = scheduler.row_id.ok_or("Missing id on scheduler")?;
+        let (mut held, mut missing) = (0, 0);
+        let mut after = None;
+        let error = loop {
+            let page = match deps
+                .ext_router
+                .list_processes(scheduler.url.clone(), after.clone(), token.clone())
+                .await
+            {
+                Ok(page) => page,
+                Err(e) => break Some(format!("{:?}", e)),
+            };
+            let page: ProcessIdPage = match serde_json::from_str(&page) {
+                Ok(page) => page,
+                Err(e) => break Some(format!("Invalid process list: {}", e)),
+            };
+
+            for process_id in page.processes {
+                held += 1;
+                if let Some(other) = assigned.get(&process_id) {
+                    conflicts.push(json!({
+                        "process_id": process_id,
+                        "held_by": [other, &scheduler.url],
+                        "routed_to": other,
+                    }));
+                    continue;
+                }
+                match store.get_process_scheduler(&process_id) {
+                    Ok(row) if row.scheduler_row_id == row_id => (),
+                    Ok(row) => conflicts.push(json!({
+                        "process_id": process_id,
+                        "held_by": [&scheduler.url],
+                        "routed_to": urls.get(&row.scheduler_row_id),
+                    })),
+                    Err(StoreErrorType::NotFound(_)) => {
+                        if !dry_run {
+                            store.assign_process(&process_id, &row_id)?;
+                            deps.route_cache.invalidate(&process_id);
+                        }
+                        assigned.insert(process_id, scheduler.url.clone());
+                        missing += 1;
+                    }
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            match page.next {
+                Some(next) => after = Some(next),
+                None => break None,
+            }
+        };
+
+        reports.push(json!({
+            "url": scheduler.url,
+            "processes": held,
+            "missing": missing,
+            "error": error,
+        }));
+    }
+
+    deps.logger.log(format!(
+        "reconciled process schedulers{}, {} missing rows and {} conflicts",
+        if dry_run { " (dry run)" } else { "" },
+        assigned.len(),
+        conflicts.len()
+    ));
+    let summary = json!({
+        "dry_run": dry_run,
+        "missing": assigned.len(),
+        "conflicts": conflicts,
+        "schedulers": reports,
+    });
+    if !dry_run {
+        audit_admin(
+            &deps,
+            actor,
+            "process_schedulers.reconcile",
+            "process_schedulers",
+            None,
+            Some(json!({ "inserted": assigned.len(), "conflicts": conflicts.len() })),
+        )?;
+    }
+    Ok(summary.to_string())
+}
+
+// if this returns Ok(Some(RouteDecision)) then the server should return a redirect to its url
+pub async fn redirect_process_id(
+    deps: Arc<Deps>,
+    process_id: Option<String>,
+) -> Result<Option<RouteDecision>, String> {
+    if deps.config.mode() != Mode::Router {
+        return Ok(None);
+    }
+    let _span = trace::span("router redirect_process_id", SpanKind::Internal);
+
+    let pid = process_id.ok_or("No process-id query parameter provided")?;
+
+    // every other process_id, redirect
+    Ok(Some(locate_process(&deps, &pid)?))
+}
+
+// if this returns Ok(Some(RouteDecision)) then the server should return a redirect to its url
+pub async fn redirect_tx_id(
+    deps: Arc<Deps>,
+    tx_id: String,
+    process_id: Option<String>,
+) -> Result<Option<RouteDecision>, String> {
+    if deps.config.mode() != Mode::Router {
+        return Ok(None);
+    }
+    let _span = trace::span("router redirect_tx_id", SpanKind::Internal);
+
+    if let Some(scheduler) = deps.route_cache.get(&tx_id) {
+        return Ok(Some(scheduler.route(RouteRule::Pinned)));
+    }
+
+    if degraded(&deps) {
+        if let Ok(decision) = locate_degraded(&deps, &tx_id) {
+            return Ok(Some(decision));
+        }
+        let process_id = process_id.ok_or(degraded_error(
+            "the process-id query parameter is needed to locate a message",
+        ))?;
+        return Ok(Some(locate_degraded(&deps, &process_id)?));
+    }
+
+    let lookup = observe(&deps, deps.router_data_store.get_process_scheduler(&tx_id));
+    let process_to_query = match lookup {
+        Ok(_) => tx_id,
+        /*
+            we didn't find a process scheduler based on the tx_id
+            so we need to try and find one based on process_id query param,
+            the hash ring can only place it as a process id
+        */
+        Err(_) => match process_id {
+            Some(process_id) => process_id,
+            None if hashing(&deps)? => tx_id,
+            None => return Err("Unable to locate process, if this is a message id query be sure to pass the process-id query parameter".to_string()),
+        },
+    };
+
+    Ok(Some(locate_process(&deps, &process_to_query)?))
+}
+
+// if this returns Ok(Some(RouteDecision)) then the server should return a redirect to its url
+pub async fn redirect_data_item(
+    deps: Arc<Deps>,
+    input: Bytes,
+    process_id: Option<String>,
+    assign: Option<String>,
+    region: Option<String>,
+) -> Result<Option<RouteDecision>, String> {
+    if deps.config.mode() != Mode::Router {
+        return Ok(None);
+    }
+    let _span = trace::span("router redirect_data_item", SpanKind::Internal);
+
+    // XOR, if we have one of these, we must have both.
+    if process_id.is_some() ^ assign.is_some() {
+        return Err("If sending assign or process-id, you must send both.".to_string());
+    } else if let (Some(process_id), Some(_assign)) = (process_id, assign) {
+        check_denylist(&deps, &process_id, "")?;
+        return match locate_process(&deps, &process_id) {
+            Ok(decision) => Ok(Some(decision)),
+            Err(e) if e.starts_with(ROUTER_DEGRADED) => Err(e),
+            Err(_) => Err("Unable to locate scheduler for process-id".to_string()),
+        };
+    }
+
+    // routing only needs the header, the data is left where it is
+    let item = Builder::parse_data_item_header(&input, &deps.verify_cache)?;
+    let tags = item.tags().clone();
+    let id = item.id().clone();
+    let target = item.target().clone();
+    let type_tag = tags
+        .iter()
+        .find(|tag| tag.name == "Type" || tag.name == "type")
+        .ok_or("Cannot redirect data item, invalid Type Tag")?;
+    // wallets_to_route lists wallets the way they write their address, 0x... for Ethereum
+    let OwnerAddresses {
+        owner_address,
+        route_address,
+    } = deps.owner_cache.addresses(&item)?;
+
+    let target_process = match type_tag.value.as_ref() {
+        "Process" => &id,
+        _ => &target,
+    };
+    check_denylist(&deps, target_process, &owner_address)?;
+    check_rate_limit(&deps, &type_tag.value, &owner_address)?;
+
+    match type_tag.value.as_ref() {
+        "Process" => {
+            /*
+                new process so we need to generate a
+                process_schedulers record and return the url
+            */
+            if degraded(&deps) {
+                return Err(degraded_error("new processes can't be assigned"));
+            }
+            require_primary(&deps, "new processes are assigned by its primary")?;
+            /*
+                a client retrying a spawn after a timeout is
+                sent to the scheduler the first attempt got,
+                without picking or counting it again
+            */
+            if let Some(decision) = assigned_route(&deps, &id)? {
+                return Ok(Some(duplicate_spawn(&deps, &id, decision)));
+            }
+            let mut schedulers = match observe(&deps, deps.router_data_store.get_all_schedulers()) {
+                Err(StoreErrorType::DatabaseError(_)) if degraded(&deps) => {
+                    return Err(degraded_error("new processes can't be assigned"))
+                }
+                result => result?,
+            };
+            deps.router_health.remember_schedulers(&schedulers);
+            let region = region
+                .as_deref()
+                .and_then(|value| client_region(&deps.config.router_region_map(), value));
+            let sticky = owner_scheduler(&deps, &route_address, &schedulers);
+            let mut skipped = vec![];
+            let (scheduler, rule) = loop {
+                let (scheduler, rule) = pick_scheduler(
+                    &deps,
+                    schedulers.clone(),
+                    &id,
+                    &route_address,
+                    input.len(),
+                    region.as_deref(),
+                    &tags,
+                    sticky,
+                )?;
+                if !deps.config.spawn_failover() {
+                    break (scheduler, rule);
+                }
+                match deps.ext_router.probe_scheduler(scheduler.url.clone()).await {
+                    Ok(()) => break (scheduler, rule),
+                    Err(e) => {
+                        skip_scheduler(&deps, &scheduler, e);
+                        skipped.push(scheduler.url.clone());
+                        /*
+                            kept out of this spawn rather than removed,
+                            a wallet strictly pinned to it still is
+                        */
+                        for candidate in schedulers.iter_mut() {
+                            if candidate.url == scheduler.url {
+                                candidate.no_route = Some(true);
+                            }
+                        }
+                    }
+                }
+            };
+
+            let scheduler_row_id = if let Some(m_scheduler_row_id) = scheduler.row_id {
+                m_scheduler_row_id
+            } else {
+                /*
+                    this should be unreachable but return an error
+                    just in case so the router doesn't crash
+                */
+                return Err("Missing id on scheduler".to_string());
+            };
+
+            /*
+                the row and the count are written together, a
+                spawn of this process that got there first keeps
+                its scheduler and this one is sent there too
+            */
+            let assignment = observe(
+                &deps,
+                deps.router_data_store
+                    .assign_process(&id, &scheduler_row_id),
+            )?;
+            let scheduler = assignment.scheduler;
+            deps.route_cache.insert(&id, &scheduler);
+            if assignment.duplicate {
+                let decision = scheduler.route(RouteRule::Pinned);
+                return Ok(Some(duplicate_spawn(&deps, &id, decision)));
+            }
+            if sticky.is_none() {
+                remember_owner(&deps, &route_address, &scheduler, &rule);
+            }
+            // health isn't considered when hashing, the ring holds every routable scheduler
+            let hashing = hashing(&deps)?;
+            let candidates = candidates(schedulers, |candidate| {
+                hashing || is_healthy(&deps, candidate)
+            });
+            record_spawn(
+                &deps,
+                SpawnEvent {
+                    timestamp: 0,
+                    process_id: id.clone(),
+                    owner: route_address.clone(),
+                    size: input.len(),
+                    scheduler: scheduler.url.clone(),
+                    rule: rule.as_str().to_string(),
+                    region,
+                    strategy: Some(deps.config.routing_strategy()),
+                    candidates: candidates.into_iter().map(|c| c.url).collect(),
+                    skipped,
+                },
+            );
+
+            Ok(Some(scheduler.route(rule)))
+        }
+        "Message" => {
+            /*
+                otherwise, fetch the correct scheduler based
+                on the messages's target
+            */
+            check_message_target(&target)?;
+            let strict = deps.config.router_strict_messages();
+            let unregistered = || {
+                format!(
+                    "Cannot redirect message, target {} is not a registered process",
+                    target
+                )
+            };
+            match locate_process(&deps, &target) {
+                /*
+                    the signature was verified parsing the header,
+                    strict mode also refuses a target the hash ring
+                    would place but that was never spawned here
+                */
+                Ok(decision) if strict && decision.rule != RouteRule::Pinned => Err(unregistered()),
+                Ok(decision) => Ok(Some(decision)),
+                Err(e) if e.starts_with(ROUTER_DEGRADED) => Err(e),
+                Err(_) if strict => Err(unregistered()),
+                Err(_) => Err("Unable to locate scheduler for message target".to_string()),
+            }
+        }
+        _ => Err("Cannot redirect data item, invalid Type Tag".to_string()),
+    }
+}
+
+/*
+    A batch is redirected as a whole, so every item has
+    to be a Message to a process on the same scheduler
+*/
+pub async fn redirect_batch(
+    deps: Arc<Deps>,
+    items: &[Vec<u8>],
+) -> Result<Option<RouteDecision>, String> {
+    if deps.config.mode() != Mode::Router {
+        return Ok(None);
+    }
+    let _span = trace::span("router redirect_batch", SpanKind::Internal);
+
+    let mut decision: Option<RouteDecision> = None;
+    for (index, input) in items.iter().enumerate() {
+        let item = Builder::parse_data_item(input.clone(), &deps.verify_cache)
+            .map_err(|e| format!("Batch item {}: {}", index, String::from(e)))?;
+        let is_message = item
+            .tags()
+            .iter()
+            .any(|tag| (tag.name == "Type" || tag.name == "type") && tag.value == "Message");
+        if !is_message {
+            return Err(format!("Batch item {}: only Messages can be batched", index));
+        }
+
+        check_message_target(&item.target()).map_err(|e| format!("Batch item {}: {}", index, e))?;
+
+        let owner_bytes = base64_url::decode(&item.owner())
+            .map_err(|_| "Failed to parse owner".to_string())?;
+        let owner_address = base64_url::encode(&hash(&owner_bytes));
+        check_denylist(&deps, &item.target(), &owner_address)?;
+        check_rate_limit(&deps, "Message", &owner_address)?;
+
+        let located = locate_process(&deps, &item.target()).map_err(|e| match e {
+            e if e.starts_with(ROUTER_DEGRADED) => e,
+            _ => format!(
+                "Batch item {}: unable to locate scheduler for message target",
+                index
+            ),
+        })?;
+        if deps.config.router_strict_messages() && located.rule != RouteRule::Pinned {
+            return Err(format!(
+                "Batch item {}: target {} is not a registered process",
+                index,
+                item.target()
+            ));
+        }
+        match &decision {
+            Some(first) if first.url != located.url => {
+                return Err("Batch targets processes on more than one scheduler".to_string())
+            }
+            Some(_) => (),
+            None => decision = Some(located),
+        }
+    }
+
+    Ok(decision)
+}
+
+/*
+    A read naming several processes is redirected when they
+    are all on one scheduler, like a batch, the router has
+    nothing to answer it from
+*/
+pub async fn redirect_process_ids(
+    deps: Arc<Deps>,
+    process_ids: &[String],
+) -> Result<Option<RouteDecision>, String> {
+    if deps.config.mode() != Mode::Router {
+        return Ok(None);
+    }
+    let _span = trace::span("router redirect_process_ids", SpanKind::Internal);
+
+    let mut decision: Option<RouteDecision> = None;
+    for process_id in process_ids {
+        let located = locate_process(&deps, process_id).map_err(|e| match e {
+            e if e.starts_with(ROUTER_DEGRADED) => e,
+            _ => format!("Unable to locate scheduler for process {}", process_id),
+        })?;
+        match &decision {
+            Some(first) if first.url != located.url => {
+                return Err(
+                    "Processes are on more than one scheduler, ask each for its own".to_string(),
+                )
+            }
+            Some(_) => (),
+            None => decision = Some(located),
+        }
+    }
+
+    Ok(decision)
+}
+
+/*
+    Where a new process would go under the configured
+    strategy, among the schedulers given
+*/
+#[allow(clippy::too_many_arguments)]
+fn pick_scheduler(
+    deps: &Arc<Deps>,
+    schedulers: Vec<Scheduler>,
+    process_id: &str,
+    owner_address: &str,
+    size: usize,
+    region: Option<&str>,
+    tags: &[Tag],
+    owner_scheduler: Option<i32>,
+) -> Result<(Scheduler, RouteRule), String> {
+    let hashing = hashing(deps)?;
+    let place = |schedulers: Vec<Scheduler>| match hashing {
+        true => ring_scheduler(&schedulers, process_id)
+            .map(|scheduler| (scheduler.clone(), RouteRule::Hash)),
+        false => select_scheduler(
+            schedulers,
+            process_id,
+            owner_address,
+            size,
+            deps.config.large_process_threshold(),
+            region,
+            tags,
+            owner_scheduler,
+            |scheduler| is_healthy(deps, scheduler),
+        ),
+    };
+    if let Some(placed) = place(schedulers.clone()) {
+        return Ok(placed);
+    }
+    if !hashing && strict_pin(&schedulers, owner_address).is_some() {
+        return Err(format!("{}, try again later", WALLET_PINNED_UNAVAILABLE));
+    }
+
+    /*
+        When the spawn would have been placed if the quotas
+        were lifted it is refused as a retryable 503, so
+        clients back off instead of the router piling more
+        processes onto full schedulers
+    */
+    let unlimited = schedulers
+        .into_iter()
+        .map(|scheduler| Scheduler {
+            max_processes: None,
+            ..scheduler
+        })
+        .collect::<Vec<_>>();
+    match place(unlimited) {
+        Some(_) => Err(format!("{}, try again later", ROUTER_AT_CAPACITY)),
+        None => Err("Could not find a scheduler to assign".to_string()),
+    }
+}
+
+/*
+    The scheduler ROUTER_OWNER_STICKY keeps the owner's new
+    processes on, None when the owner has none yet or its
+    scheduler was removed since. A failed lookup only
+    costs the spawn its locality.
+*/
+fn owner_scheduler(deps: &Arc<Deps>, owner_address: &str, schedulers: &[Scheduler]) -> Option<i32> {
+    if !deps.config.router_owner_sticky() {
+        return None;
+    }
+    match observe(
+        deps,
+        deps.router_data_store.get_owner_scheduler(owner_address),
+    ) {
+        Ok(row_id) => schedulers
+            .iter()
+            .find(|scheduler| scheduler.row_id == Some(row_id))
+            .and(Some(row_id)),
+        Err(StoreErrorType::NotFound(_)) => None,
+        Err(e) => {
+            deps.logger.error(format!(
+                "failed to read the scheduler of owner {}: {:?}",
+                owner_address, e
+            ));
+            None
+        }
+    }
+}
+
+/*
+    Keep an owner's first process placed by load as its
+    scheduler for ROUTER_OWNER_STICKY. Processes sent by a
+    wallet or tag listing, or by the hash ring, don't set
+    it. A failure is logged, the spawn is already placed.
+*/
+fn remember_owner(deps: &Arc<Deps>, owner_address: &str, scheduler: &Scheduler, rule: &RouteRule) {
+    if !deps.config.router_owner_sticky()
+        || !matches!(
+            rule,
+            RouteRule::LeastCount | RouteRule::Size | RouteRule::Region
+        )
+    {
+        return;
+    }
+    let row_id = match scheduler.row_id {
+        Some(row_id) => row_id,
+        None => return,
+    };
+    if let Err(e) = deps
+        .router_data_store
+        .save_owner_scheduler(owner_address, &row_id)
+    {
+        deps.logger.error(format!(
+            "failed to save the scheduler of owner {}: {:?}",
+            owner_address, e
+        ));
+    }
+}
+
+// stamped with the time it is recorded, a failure is logged as the spawn is already placed
+fn record_spawn(deps: &Arc<Deps>, mut event: SpawnEvent) {
+    event.timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    if let Err(e) = deps.spawn_audit.record(&event) {
+        deps.logger.event(
+            LogLevel::Error,
+            "router",
+            format!("failed to record spawn in the audit log: {:?}", e),
+            LogFields::process(&event.process_id),
+        );
+    }
+}
+
+const DECISIONS_DEFAULT_LIMIT: usize = 100;
+const DECISIONS_MAX_LIMIT: usize = 1000;
+
+/*
+    The routing decisions of the spawn audit log matching
+    the query, newest first, for finding out why a process
+    landed where it did
+*/
+pub fn routing_decisions(deps: Arc<Deps>, mut query: SpawnQuery) -> Result<String, String> {
+    require_router(&deps)?;
+    query.limit = match query.limit {
+        0 => DECISIONS_DEFAULT_LIMIT,
+        limit => limit.min(DECISIONS_MAX_LIMIT),
+    };
+    let decisions = deps.spawn_audit.read(&query)?;
+    Ok(json!({ "decisions": decisions }).to_string())
+}
+
+/*
+    Replay the spawns of an audit log against a proposed
+    scheduler list and strategy, without touching any
+    database. The schedulers start out empty and every
+    one is taken to be healthy, so the report shows how
+    the policy alone would have spread the logged spawns
+    compared with where they actually went.
+*/
+pub fn simulate_routing(
+    audit_log: &str,
+    scheduler_list: &str,
+    strategy: &str,
+    large_process_threshold: usize,
+) -> Result<String, String> {
+    let strategy = RoutingStrategy::from_config(strategy)?;
+    let list_dir = Path::new(scheduler_list)
+        .parent()
+        .unwrap_or(Path::new("."))
+        .to_path_buf();
+    let contents = fs::read_to_string(scheduler_list)
+        .map_err(|e| format!("Failed to read {}: {}", scheduler_list, e))?;
+    let mut schedulers = proposed_schedulers(&contents, &list_dir)?;
+    let events = fs::read_to_string(audit_log)
+        .map_err(|e| format!("Failed to read {}: {}", audit_log, e))?;
+
+    let mut spawns = 0;
+    let mut moved = 0;
+    let mut actual: BTreeMap<String, u64> = BTreeMap::new();
+    let mut rules: BTreeMap<&'static str, u64> = BTreeMap::new();
+    for (line_number, line) in events.lines().enumerate() {
+        // spawns rolled up into counts can't be replayed
+        if line.trim().is_empty() || is_rollup(line) {
+            continue;
+        }
+        let event: SpawnEvent = serde_json::from_str(line)
+            .map_err(|e| format!("Invalid audit log line {}: {}", line_number + 1, e))?;
+
+        let placed = match strategy {
+            RoutingStrategy::ConsistentHash => ring_scheduler(&schedulers, &event.process_id)
+                .map(|scheduler| (scheduler.url.clone(), RouteRule::Hash)),
+            RoutingStrategy::LeastCount => select_scheduler(
+                schedulers.clone(),
+                &event.process_id,
+                &event.owner,
+                event.size,
+                large_process_threshold,
+                event.region.as_deref(),
+                &[],
+                None,
+                |_| true,
+            )
+            .map(|(scheduler, rule)| (scheduler.url, rule)),
+        };
+        let (url, rule) = placed.ok_or(format!(
+            "No scheduler in the list could take the spawn of {}",
+            event.process_id
+        ))?;
+
+        if let Some(scheduler) = schedulers.iter_mut().find(|s| s.url == url) {
+            scheduler.process_count += 1;
+        }
+        spawns += 1;
+        if url != event.scheduler {
+            moved += 1;
+        }
+        *actual.entry(event.scheduler).or_insert(0) += 1;
+        *rules.entry(rule.as_str()).or_insert(0) += 1;
+    }
+
+    let mut report = vec![];
+    for scheduler in &schedulers {
+        report.push(json!({
+            "url": scheduler.url,
+            "actual": actual.remove(&scheduler.url).unwrap_or(0),
+            "simulated": scheduler.process_count,
+        }));
+    }
+    // schedulers the log sent spawns to that are not in the proposed list
+    for (url, count) in actual {
+        report.push(json!({ "url": url, "actual": count, "simulated": 0 }));
+    }
+
+    serde_json::to_string_pretty(&json!({
+        "spawns": spawns,
+        "moved": moved,
+        "rules": rules,
+        "schedulers": report,
+    }))
+    .map_err(|e| e.to_string())
+}
+
+// the schedulers of a scheduler list as new rows with no processes
+fn proposed_schedulers(contents: &str, list_dir: &Path) -> Result<Vec<Scheduler>, String> {
+    let entries = parse_scheduler_list(contents)?;
+
+    let mut schedulers = vec![];
+    for (i, entry) in entries.into_iter().enumerate() {
+        let wallets_to_route = resolve_wallets(&entry, list_dir)?;
+        let mut scheduler = Scheduler {
+            row_id: Some(i as i32 + 1),
+            url: entry.url.clone(),
+            process_count: 0,
+            no_route: None,
+            wallets_to_route: None,
+            wallets_only: None,
+            priority: None,
+            large_objects: None,
+            weight: None,
+            public_url: None,
+            drain: None,
+            max_processes: None,
+            region: None,
+            tags_to_route: None,
+            wallets_policy: None,
+        };
+        apply_entry(&mut scheduler, &entry, wallets_to_route);
+        schedulers.push(scheduler);
+    }
+    Ok(schedulers)
+}
+
+/*
+    A scheduler picked for a spawn did not answer its
+    probe. It is counted as unhealthy straight away so
+    the next spawns skip it too, until a health check
+    sees it up again.
+*/
+fn skip_scheduler(deps: &Arc<Deps>, scheduler: &Scheduler, error: ExtRouterErrorType) {
+    deps.logger.event(
+        LogLevel::Warn,
+        "router",
+        format!("scheduler unreachable for a spawn, trying the next one: {:?}", error),
+        LogFields::scheduler(&scheduler.url),
+    );
+    deps.metrics.spawn_failover(&scheduler.url);
+    if deps.config.scheduler_health_interval() > 0 {
+        let threshold = deps.config.scheduler_unhealthy_after();
+        deps.scheduler_failures
+            .insert(scheduler.url.clone(), threshold);
+        deps.shared_health
+            .mark_down(&scheduler.url, threshold, health_ttl(deps));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    fn scheduler(row_id: i32, url: &str, wallets: &str, priority: Option<i32>) -> Scheduler {
+        Scheduler {
+            row_id: Some(row_id),
+            url: url.to_string(),
+            process_count: 0,
+            no_route: None,
+            wallets_to_route: Some(split_wallets(wallets)),
+            wallets_only: None,
+            priority,
+            large_objects: None,
+            weight: None,
+            public_url: None,
+            drain: None,
+            max_processes: None,
+            region: None,
+            tags_to_route: None,
+            wallets_policy: None,
+        }
+    }
+
+    #[test]
+    fn test_wallet_overlaps_ordered_by_priority() {
+        let schedulers = vec![
+            scheduler(1, "https://su1", "a, b", None),
+            scheduler(2, "https://su2", "b,c", Some(5)),
+            scheduler(3, "https://su3", "c", Some(1)),
+            scheduler(4, "https://su4", "b", None),
+        ];
+
+        let overlaps = wallet_overlaps(&schedulers);
+
+        assert_eq!(
+            overlaps,
+            vec![
+                (
+                    "b".to_string(),
+                    vec![
+                        "https://su2".to_string(),
+                        "https://su1".to_string(),
+                        "https://su4".to_string()
+                    ]
+                ),
+                (
+                    "c".to_string(),
+                    vec!["https://su3".to_string(), "https://su2".to_string()]
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_no_overlaps() {
+        let schedulers = vec![
+            scheduler(1, "https://su1", "a", None),
+            scheduler(2, "https://su2", "b", None),
+        ];
+        assert!(wallet_overlaps(&schedulers).is_empty());
+    }
+
+    #[test]
+    fn test_wallet_split_by_share() {
+        let fleet = vec![
+            scheduler(1, "https://su1", "a:80", Some(1)),
+            scheduler(2, "https://su2", "b, a:20", Some(2)),
+            scheduler(3, "https://su3", "a", Some(3)),
+        ];
+        assert!(wallet_overlaps(&fleet).is_empty());
+
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..1000 {
+            let process_id = format!("process-{}", i);
+            let pick = || {
+                select_scheduler(
+                    fleet.clone(),
+                    &process_id,
+                    "a",
+                    0,
+                    1000,
+                    None,
+                    &[],
+                    None,
+                    |_| true,
+                )
+                .unwrap()
+            };
+            let (picked, rule) = pick();
+            assert_eq!(rule, RouteRule::Wallet);
+            let (again, _) = pick();
+            assert_eq!(picked.url, again.url);
+            *counts.entry(picked.url).or_insert(0) += 1;
+        }
+        // the listing without a share takes none of the split
+        assert_eq!(counts.get("https://su3"), None);
+        assert!((700..900).contains(&counts["https://su1"]));
+        assert!((100..300).contains(&counts["https://su2"]));
+
+        // a plain listing first in precedence wins outright
+        let fleet = vec![
+            scheduler(1, "https://su1", "a", Some(1)),
+            scheduler(2, "https://su2", "a:50", Some(2)),
+        ];
+        for i in 0..50 {
+            let (picked, _) = select_scheduler(
+                fleet.clone(),
+                &format!("p{}", i),
+                "a",
+                0,
+                1000,
+                None,
+                &[],
+                None,
+                |_| true,
+            )
+            .unwrap();
+            assert_eq!(picked.url, "https://su1");
+        }
+        assert_eq!(wallet_overlaps(&fleet).len(), 1);
+        assert_eq!(wallet_share("a:x"), ("a:x", None));
+    }
+
+    #[test]
+    fn test_closest_wallet_match_wins() {
+        let fleet = vec![
+            scheduler(1, "https://su1", "*", Some(1)),
+            scheduler(2, "https://su2", "0x7e*", Some(2)),
+            scheduler(
+                3,
+                "https://su3",
+                "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf",
+                Some(3),
+            ),
+            scheduler(4, "https://su4", "0x7e5f*:50", Some(4)),
+            scheduler(5, "https://su5", "0x7e5f*:50", Some(5)),
+        ];
+        let route = |fleet: &[Scheduler], owner: &str, process_id: &str| {
+            let (picked, rule) = select_scheduler(
+                fleet.to_vec(),
+                process_id,
+                owner,
+                0,
+                1000,
+                None,
+                &[],
+                None,
+                |_| true,
+            )
+            .unwrap();
+            assert_eq!(rule, RouteRule::Wallet);
+            picked.url
+        };
+
+        // the wallet listed by itself beats every prefix taking it
+        let owner = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+        assert_eq!(route(&fleet, owner, "p"), "https://su3");
+
+        // then the longest prefix, split by shares between equals
+        let mut counts: HashMap<String, usize> = HashMap::new();
+        for i in 0..200 {
+            let url = route(
+                &fleet,
+                "0x7e5f0000000000000000000000000000000000",
+                &format!("p{}", i),
+            );
+            *counts.entry(url).or_insert(0) += 1;
+        }
+        assert_eq!(counts.len(), 2);
+        assert!(counts["https://su4"] > 50 && counts["https://su5"] > 50);
+        assert_eq!(
+            route(&fleet, "0x7e00000000000000000000000000000000000000", "p"),
+            "https://su2"
+        );
+
+        // and * takes everyone else
+        assert_eq!(
+            route(&fleet, "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI", "p"),
+            "https://su1"
+        );
+    }
+
+    #[test]
+    fn test_strict_wallets_are_refused_instead_of_spilling() {
+        let owner = "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf";
+        let mut fleet = vec![
+            scheduler(1, "https://su1", owner, Some(1)),
+            scheduler(2, "https://su2", owner, Some(2)),
+            scheduler(3, "https://su3", "", None),
+        ];
+        fleet[1].max_processes = Some(0);
+        let route = |fleet: &[Scheduler], healthy: fn(&Scheduler) -> bool| {
+            select_scheduler(
+                fleet.to_vec(),
+                "p",
+                owner,
+                0,
+                1000,
+                None,
+                &[],
+                None,
+                healthy,
+            )
+            .map(|(picked, rule)| (picked.url, rule))
+        };
+
+        // prefer falls back to the pool once the pinned schedulers are out
+        fleet[0].drain = Some(true);
+        assert_eq!(
+            route(&fleet, |_| true),
+            Some(("https://su3".to_string(), RouteRule::LeastCount))
+        );
+
+        // strict on either of them refuses it
+        fleet[1].wallets_policy = Some("strict".to_string());
+        assert_eq!(route(&fleet, |_| true), None);
+
+        // while any pinned one can take it the spawn goes there
+        fleet[0].drain = None;
+        assert_eq!(
+            route(&fleet, |_| true),
+            Some(("https://su1".to_string(), RouteRule::Wallet))
+        );
+        assert_eq!(route(&fleet, |s| s.url != "https://su1"), None);
+
+        // other wallets still use the pool
+        let other = select_scheduler(fleet.clone(), "p", "a", 0, 1000, None, &[], None, |_| true);
+        assert!(other.is_some());
+    }
+
+    fn size_candidates() -> Vec<Scheduler> {
+        let mut large = scheduler(2, "https://su2", "", None);
+        large.large_objects = Some(true);
+        vec![scheduler(1, "https://su1", "", None), large]
+    }
+
+    #[test]
+    fn test_route_by_size() {
+        let mut schedulers = size_candidates();
+        assert_eq!(route_by_size(&mut schedulers, 2000, 1000), RouteRule::Size);
+        assert_eq!(schedulers.len(), 1);
+        assert_eq!(schedulers[0].url, "https://su2");
+
+        let mut schedulers = size_candidates();
+        assert_eq!(route_by_size(&mut schedulers, 500, 1000), RouteRule::LeastCount);
+        assert_eq!(schedulers.len(), 1);
+        assert_eq!(schedulers[0].url, "https://su1");
+
+        let mut schedulers = size_candidates();
+        assert_eq!(route_by_size(&mut schedulers, 2000, 0), RouteRule::LeastCount);
+        assert_eq!(schedulers.len(), 2);
+
+        // no large object scheduler, fall back to every candidate
+        let mut schedulers = vec![scheduler(1, "https://su1", "", None)];
+        assert_eq!(route_by_size(&mut schedulers, 2000, 1000), RouteRule::LeastCount);
+        assert_eq!(schedulers.len(), 1);
+    }
+
+    #[test]
+    fn test_least_loaded_by_weight() {
+        let mut small = scheduler(1, "https://su1", "", None);
+        let mut large = scheduler(2, "https://su2", "", None);
+        large.weight = Some(4);
+
+        // the weight 4 scheduler takes 4 processes for every 1 on the other
+        let mut picks = vec![];
+        let mut schedulers = vec![small, large];
+        for _ in 0..10 {
+            let picked = least_loaded(&mut schedulers).unwrap();
+            picked.process_count += 1;
+            picks.push(picked.url.clone());
+        }
+        assert_eq!(schedulers[0].process_count, 2);
+        assert_eq!(schedulers[1].process_count, 8);
+        assert_eq!(picks[0], "https://su2");
+
+        // without weights it is the plain lowest count
+        small = scheduler(1, "https://su1", "", None);
+        small.process_count = 3;
+        large = scheduler(2, "https://su2", "", None);
+        large.process_count = 2;
+        let mut schedulers = vec![small, large];
+        assert_eq!(least_loaded(&mut schedulers).unwrap().url, "https://su2");
+    }
+
+    /*
+        Randomized checks of the routing invariants. Each
+        case builds a fleet and a run of spawns from a fixed
+        seed so a failing case can be replayed by its seed.
+    */
+    const PROPERTY_CASES: u64 = 500;
+
+    fn random_flag(rng: &mut StdRng) -> Option<bool> {
+        match rng.gen_range(0..3) {
+            0 => None,
+            1 => Some(false),
+            _ => Some(true),
+        }
+    }
+
+    fn random_fleet(rng: &mut StdRng) -> Vec<Scheduler> {
+        let mut fleet = vec![];
+        for i in 1..=rng.gen_range(1..8) {
+            let wallets = (0..rng.gen_range(0..3))
+                .map(|_| format!("w{}", rng.gen_range(0..6)))
+                .collect::<Vec<_>>();
+            fleet.push(Scheduler {
+                row_id: Some(i),
+                url: format!("https://su{}", i),
+                process_count: rng.gen_range(0..20),
+                no_route: random_flag(rng),
+                wallets_to_route: (!wallets.is_empty()).then_some(wallets),
+                wallets_only: random_flag(rng),
+                priority: rng.gen_bool(0.5).then(|| rng.gen_range(0..4)),
+                large_objects: random_flag(rng),
+                weight: rng.gen_bool(0.5).then(|| rng.gen_range(0..5)),
+                public_url: None,
+                drain: random_flag(rng),
+                max_processes: None,
+                region: None,
+                tags_to_route: None,
+                wallets_policy: None,
+            });
+        }
+        fleet
+    }
+
+    fn lists_wallet(scheduler: &Scheduler, owner: &str) -> bool {
+        scheduler
+            .wallets_to_route
+            .iter()
+            .flatten()
+            .any(|entry| wallet_match(wallet_share(entry).0, owner).is_some())
+    }
+
+    #[test]
+    fn test_routing_invariants_hold_for_random_fleets() {
+        for seed in 0..PROPERTY_CASES {
+            let mut rng = StdRng::seed_from_u64(seed);
+            let mut fleet = random_fleet(&mut rng);
+            let down = fleet
+                .iter()
+                .map(|s| (s.url.clone(), rng.gen_bool(0.3)))
+                .collect::<HashMap<_, _>>();
+            let threshold = rng.gen_range(0..3) * 1000;
+            let initial_counts = fleet
+                .iter()
+                .map(|s| (s.url.clone(), s.process_count))
+                .collect::<HashMap<_, _>>();
+
+            // the processes each scheduler held before the run count as assigned
+            let mut assigned = fleet
+                .iter()
+                .map(|s| (s.row_id.unwrap(), s.process_count as i64))
+                .collect::<HashMap<_, _>>();
+            let mut assignments: HashMap<String, i32> = HashMap::new();
+
+            for p in 0..rng.gen_range(1..50) {
+                let owner = format!("w{}", rng.gen_range(0..8));
+                let size = rng.gen_range(0..3000);
+                let process_id = format!("p{}", p);
+                let picked = select_scheduler(
+                    fleet.clone(),
+                    &process_id,
+                    &owner,
+                    size,
+                    threshold,
+                    None,
+                    &[],
+                    None,
+                    |s| !down[&s.url],
+                );
+
+                let mut candidates = fleet
+                    .iter()
+                    .filter(|s| s.routable())
+                    .collect::<Vec<_>>();
+                if candidates.iter().any(|s| !down[&s.url]) {
+                    candidates.retain(|s| !down[&s.url]);
+                }
+
+                match picked {
+                    Some((scheduler, rule)) => {
+                        assert!(scheduler.routable(), "seed {}", seed);
+                        if rule == RouteRule::Wallet {
+                            assert!(lists_wallet(&scheduler, &owner), "seed {}", seed);
+                        } else {
+                            assert_ne!(scheduler.wallets_only, Some(true), "seed {}", seed);
+                            assert!(
+                                !candidates.iter().any(|s| lists_wallet(s, &owner)),
+                                "seed {}",
+                                seed
+                            );
+                        }
+
+                        let row_id = scheduler.row_id.unwrap();
+                        let target = fleet.iter_mut().find(|s| s.row_id == Some(row_id)).unwrap();
+                        target.process_count += 1;
+                        *assigned.entry(row_id).or_insert(0) += 1;
+                        assert!(
+                            assignments.insert(process_id, row_id).is_none(),
+                            "seed {}",
+                            seed
+                        );
+                    }
+                    None => {
+                        // only when every candidate is wallets_only and none lists the owner
+                        assert!(
+                            candidates
+                                .iter()
+                                .all(|s| s.wallets_only == Some(true) && !lists_wallet(s, &owner)),
+                            "seed {}",
+                            seed
+                        );
+                    }
+                }
+            }
+
+            for scheduler in fleet.iter().filter(|s| !s.routable()) {
+                assert_eq!(
+                    scheduler.process_count, initial_counts[&scheduler.url],
+                    "seed {}: draining scheduler {} gained processes",
+                    seed, scheduler.url
+                );
+            }
+            assert_eq!(
+                routing_violations(&fleet, &assigned, &[], &[]),
+                Vec::<String>::new(),
+                "seed {}",
+                seed
+            );
+        }
+    }
+
+    #[test]
+    fn test_routing_violations() {
+        let mut su1 = scheduler(1, "https://su1", "", None);
+        su1.process_count = 2;
+        let mut su2 = scheduler(2, "https://su2", "", None);
+        su2.process_count = 1;
+        let schedulers = vec![su1, su2];
+        let assigned = HashMap::from([(1, 2), (2, 1)]);
+        assert!(routing_violations(&schedulers, &assigned, &[], &[]).is_empty());
+
+        let row = |row_id: i32, process_id: &str, scheduler_row_id: i32| ProcessScheduler {
+            row_id: Some(row_id),
+            process_id: process_id.to_string(),
+            scheduler_row_id,
+        };
+        let assigned = HashMap::from([(1, 3), (2, 1), (9, 1)]);
+        let violations = routing_violations(
+            &schedulers,
+            &assigned,
+            &[row(5, "orphan", 9)],
+            &[row(1, "dup", 1), row(4, "dup", 2)],
+        );
+        assert_eq!(
+            violations,
+            vec![
+                "process orphan is assigned to missing scheduler 9".to_string(),
+                "process dup is assigned 2 times, to schedulers [1, 2]".to_string(),
+                "scheduler https://su1 has process_count 2 but 3 assigned processes".to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_apply_entry_reports_changes() {
+        let entry: SchedulerEntry = serde_json::from_str(
+            r#"{"url": "https://su1", "no_route": true, "wallets_to_route": "a", "weight": 2}"#,
+        )
+        .unwrap();
+
+        let mut sched = scheduler(1, "https://su1", "a", None);
+        assert!(apply_entry(&mut sched, &entry, Some(split_wallets("a"))));
+        assert_eq!(sched.no_route, Some(true));
+        assert_eq!(sched.weight, Some(2));
+
+        // applying the same entry again is not a change
+        assert!(!apply_entry(&mut sched, &entry, Some(split_wallets("a"))));
+        assert!(apply_entry(&mut sched, &entry, Some(split_wallets("a,b"))));
+    }
+
+    #[test]
+    fn test_scheduler_change_keeps_unset_fields() {
+        let mut sched = scheduler(1, "https://su1", "a", Some(2));
+        sched.weight = Some(3);
+
+        let change = SchedulerChange::from_body(br#"{"no_route": true, "weight": 1}"#).unwrap();
+        change.apply(&mut sched);
+
+        assert_eq!(sched.no_route, Some(true));
+        assert_eq!(sched.weight, Some(1));
+        assert_eq!(sched.wallets_to_route, Some(split_wallets("a")));
+        assert_eq!(sched.priority, Some(2));
+        assert!(SchedulerChange::from_body(b"not json").is_err());
+
+        let change = SchedulerChange::from_body(br#"{"wallets_to_route": ["a", "b*"]}"#).unwrap();
+        change.apply(&mut sched);
+        assert_eq!(sched.wallets_to_route, Some(split_wallets("a,b*")));
+    }
+
+    #[test]
+    fn test_drain_takes_no_new_processes() {
+        let mut fleet = vec![
+            scheduler(1, "https://su1", "a", None),
+            scheduler(2, "https://su2", "", None),
+        ];
+        fleet[0].drain = Some(true);
+        fleet[1].process_count = 50;
+
+        // not even the wallet it lists
+        let (picked, rule) = select_scheduler(
+            fleet.clone(),
+            "process",
+            "a",
+            0,
+            1000,
+            None,
+            &[],
+            None,
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(picked.url, "https://su2");
+        assert_eq!(rule, RouteRule::LeastCount);
+        assert_eq!(ring_scheduler(&fleet, "process").unwrap().url, "https://su2");
+
+        let change = SchedulerChange::from_body(br#"{"drain": false}"#).unwrap();
+        change.apply(&mut fleet[0]);
+        assert!(fleet[0].routable());
+    }
+
+    #[test]
+    fn test_full_scheduler_takes_no_new_processes() {
+        let mut fleet = vec![
+            scheduler(1, "https://su1", "", None),
+            scheduler(2, "https://su2", "", None),
+        ];
+        fleet[0].max_processes = Some(10);
+        fleet[0].process_count = 9;
+        fleet[1].process_count = 50;
+
+        let (picked, _) = select_scheduler(
+            fleet.clone(),
+            "process",
+            "a",
+            0,
+            1000,
+            None,
+            &[],
+            None,
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(picked.url, "https://su1");
+
+        fleet[0].process_count = 10;
+        let (picked, _) = select_scheduler(
+            fleet.clone(),
+            "process",
+            "a",
+            0,
+            1000,
+            None,
+            &[],
+            None,
+            |_| true,
+        )
+        .unwrap();
+        assert_eq!(picked.url, "https://su2");
+        assert!(!fleet[0].routable());
+    }
+
+    #[test]
+    fn test_tag_rules_pin_spawns() {
+        let mut fleet = vec![
+            scheduler(1, "https://su1", "a", None),
+            scheduler(2, "https://su2", "", None),
+            scheduler(3, "https://su3", "", None),
+        ];
+        fleet[1].tags_to_route = Some("Module=m1, App-Name=x&Variant=v2".to_string());
+        fleet[1].wallets_only = Some(true);
+        fleet[0].process_count = 10;
+        fleet[1].process_count = 100;
+        let pick = |owner: &str, tags: &[Tag]| {
+            select_scheduler(fleet.clone(), "p", owner, 0, 1000, None, tags, None, |_| {
+                true
+            })
+            .unwrap()
+        };
+
+        let (picked, rule) = pick("b", &[Tag::new("Module", "m1")]);
+        assert_eq!(picked.url, "https://su2");
+        assert_eq!(rule, RouteRule::Tag);
+        let (picked, _) = pick("b", &[Tag::new("App-Name", "x"), Tag::new("Variant", "v2")]);
+        assert_eq!(picked.url, "https://su2");
+
+        // every condition of a rule has to match, and wallets come first
+        let (picked, rule) = pick("b", &[Tag::new("App-Name", "x")]);
+        assert_eq!(picked.url, "https://su3");
+        assert_eq!(rule, RouteRule::LeastCount);
+        let (picked, rule) = pick("a", &[Tag::new("Module", "m1")]);
+        assert_eq!(picked.url, "https://su1");
+        assert_eq!(rule, RouteRule::Wallet);
+    }
+
+    #[test]
+    fn test_region_is_preferred_over_load() {
+        let mut fleet = vec![
+            scheduler(1, "https://su1", "", None),
+            scheduler(2, "https://su2", "", None),
+            scheduler(3, "https://su3", "", None),
+        ];
+        fleet[0].region = Some("us-east".to_string());
+        fleet[1].region = Some("eu-west".to_string());
+        fleet[1].process_count = 50;
+        fleet[2].region = Some("eu-west".to_string());
+        fleet[2].process_count = 20;
+
+        let pick = |fleet: &Vec<Scheduler>, region: Option<String>| {
+            let region = region.as_deref();
+            select_scheduler(fleet.clone(), "p", "a", 0, 1000, region, &[], None, |_| {
+                true
+            })
+            .unwrap()
+        };
+
+        let region = client_region("DE=eu-west, FR=eu-west", "de");
+        assert_eq!(region.as_deref(), Some("eu-west"));
+        let (picked, rule) = pick(&fleet, region);
+        assert_eq!(picked.url, "https://su3");
+        assert_eq!(rule, RouteRule::Region);
+
+        // a region with no schedulers, or none given, falls back to the least loaded
+        let region = client_region("DE=eu-west", "ap-south");
+        assert_eq!(region.as_deref(), Some("ap-south"));
+        let (picked, rule) = pick(&fleet, region);
+        assert_eq!(picked.url, "https://su1");
+        assert_eq!(rule, RouteRule::LeastCount);
+        assert_eq!(client_region("DE=eu-west", " "), None);
+
+        // full schedulers in the region send it elsewhere too
+        fleet[1].max_processes = Some(50);
+        fleet[2].max_processes = Some(20);
+        let (picked, _) = pick(&fleet, Some("eu-west".to_string()));
+        assert_eq!(picked.url, "https://su1");
+    }
+
+    #[test]
+    fn test_owner_scheduler_is_kept_until_full() {
+        let mut fleet = vec![
+            scheduler(1, "https://su1", "", None),
+            scheduler(2, "https://su2", "", None),
+            scheduler(3, "https://su3", "a", None),
+        ];
+        fleet[0].process_count = 5;
+        fleet[0].max_processes = Some(10);
+        fleet[0].region = Some("eu-west".to_string());
+        fleet[1].region = Some("us-east".to_string());
+        fleet[2].wallets_only = Some(true);
+        let pick = |fleet: &Vec<Scheduler>, owner: &str, sticky: Option<i32>| {
+            select_scheduler(
+                fleet.clone(),
+                "p",
+                owner,
+                0,
+                1000,
+                Some("us-east"),
+                &[],
+                sticky,
+                |_| true,
+            )
+            .unwrap()
+        };
+
+        // the owner's scheduler wins over load and region
+        let (picked, rule) = pick(&fleet, "b", Some(1));
+        assert_eq!(picked.url, "https://su1");
+        assert_eq!(rule, RouteRule::Owner);
+        let (picked, rule) = pick(&fleet, "b", None);
+        assert_eq!(picked.url, "https://su2");
+        assert_eq!(rule, RouteRule::Region);
+
+        // but not over a wallet listing, nor onto a wallets_only scheduler
+        let (_, rule) = pick(&fleet, "a", Some(1));
+        assert_eq!(rule, RouteRule::Wallet);
+        let (picked, _) = pick(&fleet, "b", Some(3));
+        assert_eq!(picked.url, "https://su2");
+
+        // once it is full the spawn is placed by load again
+        fleet[0].process_count = 10;
+        let (picked, rule) = pick(&fleet, "b", Some(1));
+        assert_eq!(picked.url, "https://su2");
+        assert_eq!(rule, RouteRule::Region);
+    }
+
+    #[test]
+    fn test_utilization_of_quota() {
+        let mut sched = scheduler(1, "https://su1", "", None);
+        sched.process_count = 30;
+        assert_eq!(sched.utilization(), None);
+        assert!(!sched.full());
+
+        sched.max_processes = Some(40);
+        assert_eq!(sched.utilization(), Some(0.75));
+        sched.process_count = 40;
+        assert!(sched.full());
+        assert_eq!(sched.utilization(), Some(1.0));
+    }
+
+    #[test]
+    fn test_ring_scheduler_is_stable() {
+        let fleet = (1..=5)
+            .map(|i| scheduler(i, &format!("https://su{}", i), "", None))
+            .collect::<Vec<_>>();
+        let ids = (0..200).map(|i| format!("process-{}", i)).collect::<Vec<_>>();
+        let place = |fleet: &[Scheduler], id: &str| ring_scheduler(fleet, id).unwrap().url.clone();
+
+        let before = ids.iter().map(|id| place(&fleet, id)).collect::<Vec<_>>();
+        assert_eq!(before, ids.iter().map(|id| place(&fleet, id)).collect::<Vec<_>>());
+
+        // every scheduler gets a share
+        for s in fleet.iter() {
+            let share = before.iter().filter(|url| **url == s.url).count();
+            assert!(share > 15 && share < 80, "{} got {}", s.url, share);
+        }
+
+        // draining one only moves the processes that were on it
+        let mut drained = fleet.clone();
+        drained[2].no_route = Some(true);
+        for (id, url) in ids.iter().zip(before.iter()) {
+            let after = place(&drained, id);
+            if *url != "https://su3" {
+                assert_eq!(after, *url);
+            } else {
+                assert_ne!(after, "https://su3");
+            }
+        }
+
+        assert!(ring_scheduler(&[], "process").is_none());
+    }
+
+    #[test]
+    fn test_route_prefers_public_url() {
+        let mut sched = scheduler(1, "http://10.0.0.5:9000", "", None);
+        assert_eq!(sched.route(RouteRule::Pinned).url, "http://10.0.0.5:9000");
+
+        sched.public_url = Some("https://su1.example.com/".to_string());
+        let decision = sched.route(RouteRule::Pinned);
+        assert_eq!(decision.url, "https://su1.example.com");
+        assert_eq!(decision.rule, RouteRule::Pinned);
+    }
+
+    #[test]
+    fn test_route_cache_expires_and_invalidates() {
+        let cache = RouteCache::new(10, Duration::from_millis(50));
+        let sched = scheduler(1, "http://su1", "", None);
+
+        cache.insert("pid1", &sched);
+        cache.insert("pid2", &sched);
+        assert_eq!(cache.get("pid1").unwrap().url, "http://su1");
+        assert!(Arc::ptr_eq(
+            &cache.get("pid1").unwrap(),
+            &cache.get("pid2").unwrap()
+        ));
+
+        // a changed scheduler gets its own copy
+        let mut moved = sched.clone();
+        moved.public_url = Some("https://su1.example".to_string());
+        cache.insert("pid3", &moved);
+        assert_eq!(
+            cache.get("pid3").unwrap().public_url.as_deref(),
+            Some("https://su1.example")
+        );
+        assert!(cache.get("pid2").unwrap().public_url.is_none());
+        cache.invalidate("pid3");
+
+        cache.invalidate("pid1");
+        assert!(cache.get("pid1").is_none());
+        assert!(cache.get("pid2").is_some());
+
+        std::thread::sleep(Duration::from_millis(60));
+        assert!(cache.get("pid2").is_none());
+        // kept for redirects while the router tables are down
+        assert_eq!(cache.get_stale("pid2").unwrap().url, "http://su1");
+        assert!(cache.get_stale("pid1").is_none());
+
+        let disabled = RouteCache::new(10, Duration::ZERO);
+        disabled.insert("pid1", &sched);
+        assert!(disabled.get("pid1").is_none());
+    }
+
+    #[derive(Default)]
+    struct MapCache(Mutex<HashMap<String, String>>);
+
+    impl SharedCache for MapCache {
+        fn get(&self, key: &str) -> Result<Option<String>, String> {
+            Ok(self.0.lock().unwrap().get(key).cloned())
+        }
+
+        fn set(&self, key: &str, value: &str, _ttl: u64) -> Result<(), String> {
+            self.0
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), value.to_string());
+            Ok(())
+        }
+
+        fn delete(&self, key: &str) -> Result<(), String> {
+            self.0.lock().unwrap().remove(key);
+            Ok(())
+        }
+
+        fn incr(&self, _key: &str, _ttl: u64) -> Result<i64, String> {
+            Err("not counted".to_string())
+        }
+    }
+
+    #[test]
+    fn test_route_cache_shares_routes_between_replicas() {
+        let shared: Arc<dyn SharedCache> = Arc::new(MapCache::default());
+        let first = RouteCache::new(10, Duration::from_secs(60)).with_shared(shared.clone());
+        let second = RouteCache::new(10, Duration::from_secs(60)).with_shared(shared.clone());
+        let mut sched = scheduler(1, "http://su1", "", None);
+        sched.public_url = Some("https://su1.example".to_string());
+
+        first.insert("pid1", &sched);
+        let found = second.get("pid1").unwrap();
+        assert_eq!(*found, sched);
+        assert!(second.get_stale("pid1").is_some());
+
+        // a move seen by one replica drops the shared entry too
+        first.invalidate("pid1");
+        let third = RouteCache::new(10, Duration::from_secs(60)).with_shared(shared);
+        assert!(third.get("pid1").is_none());
+    }
+
+    #[test]
+    fn test_shared_health_between_replicas() {
+        let shared: Arc<dyn SharedCache> = Arc::new(MapCache::default());
+        let first = SharedHealth::new(shared.clone());
+        let second = SharedHealth::new(shared);
+
+        // a scheduler one router gave up on is skipped by the other
+        first.mark_down("http://su1", 3, 60);
+        assert_eq!(second.failures("http://su1"), 3);
+        assert_eq!(second.failures("http://su2"), 0);
+
+        // the other keeps its read for the ttl, then sees it up again
+        first.mark_up("http://su1");
+        assert_eq!(first.failures("http://su1"), 0);
+        assert_eq!(second.failures("http://su1"), 3);
+        if let Some((_, read_at)) = second.counts.lock().unwrap().get_mut("http://su1") {
+            *read_at -= SHARED_HEALTH_TTL;
+        }
+        assert_eq!(second.failures("http://su1"), 0);
+    }
+
+    #[test]
+    fn test_router_health_reports_transitions() {
+        let health = RouterHealth::new();
+        assert!(health.down_since().is_none());
+        assert!(!health.mark_up());
+
+        assert!(health.mark_down());
+        assert!(!health.mark_down());
+        assert!(health.down_since().is_some());
+
+        health.remember_schedulers(&[scheduler(1, "http://su1", "", None)]);
+        assert_eq!(health.schedulers()[0].url, "http://su1");
+
+        assert!(health.mark_up());
+        assert!(health.down_since().is_none());
+    }
+
+    #[test]
+    fn test_resolve_entry_field_sources() {
+        let dir = tempdir::TempDir::new("scheduler_list").unwrap();
+        fs::write(dir.path().join("team_a.txt"), "# team a\na, b\n\nc\n").unwrap();
+        env::set_var("TEST_RESOLVE_WALLETS", "d,e");
+
+        let inline = resolve_entry_field(
+            "wallets_to_route",
+            "https://su1",
+            &Some("x".to_string()),
+            &None,
+            &None,
+            dir.path(),
+        );
+        assert_eq!(inline, Ok(Some("x".to_string())));
+
+        let from_file = resolve_entry_field(
+            "wallets_to_route",
+            "https://su1",
+            &None,
+            &Some("team_a.txt".to_string()),
+            &None,
+            dir.path(),
+        );
+        assert_eq!(from_file, Ok(Some("a,b,c".to_string())));
+
+        let from_env = resolve_entry_field(
+            "wallets_to_route",
+            "https://su1",
+            &None,
+            &None,
+            &Some("TEST_RESOLVE_WALLETS".to_string()),
+            dir.path(),
+        );
+        assert_eq!(from_env, Ok(Some("d,e".to_string())));
+
+        let both = resolve_entry_field(
+            "wallets_to_route",
+            "https://su1",
+            &Some("x".to_string()),
+            &Some("team_a.txt".to_string()),
+            &None,
+            dir.path(),
+        );
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn test_simulate_routing_against_a_new_list() {
+        let dir = tempdir::TempDir::new("simulate").unwrap();
+        let list = dir.path().join("schedulers.json");
+        let w1 = "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI";
+        fs::write(
+            &list,
+            format!(
+                r#"[{{"url": "https://su1"}}, {{"url": "https://su2", "wallets_to_route": "{}"}}]"#,
+                w1
+            ),
+        )
+        .unwrap();
+
+        let audit_log = dir.path().join("audit.log");
+        let mut events = [w1, "x", "y", "z"]
+            .iter()
+            .enumerate()
+            .map(|(i, owner)| {
+                serde_json::to_string(&SpawnEvent {
+                    timestamp: i as u64,
+                    process_id: format!("pid{}", i),
+                    owner: owner.to_string(),
+                    size: 10,
+                    scheduler: "https://su1".to_string(),
+                    rule: "least-count".to_string(),
+                    region: None,
+                    strategy: None,
+                    candidates: vec![],
+                    skipped: vec![],
+                })
+                .unwrap()
+            })
+            .collect::<Vec<_>>();
+        // older spawns compacted into counts are skipped
+        events.insert(
+            0,
+            r#"{"period":"day","start":0,"scheduler":"https://su1","rule":"wallet","spawns":9,"bytes":90}"#
+                .to_string(),
+        );
+        fs::write(&audit_log, events.join("\n")).unwrap();
+
+        let report = simulate_routing(
+            audit_log.to_str().unwrap(),
+            list.to_str().unwrap(),
+            "least-count",
+            0,
+        )
+        .unwrap();
+        let report: serde_json::Value = serde_json::from_str(&report).unwrap();
+
+        assert_eq!(report["spawns"], 4);
+        assert_eq!(report["moved"], 2);
+        assert_eq!(report["rules"]["wallet"], 1);
+        assert_eq!(report["rules"]["least-count"], 3);
+        assert_eq!(report["schedulers"][0]["actual"], 4);
+        assert_eq!(report["schedulers"][0]["simulated"], 2);
+        assert_eq!(report["schedulers"][1]["simulated"], 2);
+
+        assert!(simulate_routing(
+            audit_log.to_str().unwrap(),
+            list.to_str().unwrap(),
+            "random",
+            0
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_verify_chain_follows_links() {
+        let id = |c: char| base64_url::encode(&[c as u8; 32]);
+        let process_id = id('p');
+        let mut links = vec![];
+        let mut previous: Option<ChainLink> = None;
+        for nonce in 0..4 {
+            let hash_chain = match &previous {
+                Some(p) => gen_hash_chain(&p.hash_chain, Some(&p.assignment_id)).unwrap(),
+                None => gen_hash_chain(&process_id, None).unwrap(),
+            };
+            let link = ChainLink {
+                nonce,
+                hash_chain,
+                assignment_id: id((b'a' + nonce as u8) as char),
+            };
+            links.push(link.clone());
+            previous = Some(link);
+        }
+
+        // from the process id, or from a process assignment at nonce 0
+        let last = verify_chain(&process_id, None, links.iter()).unwrap();
+        assert_eq!(last, links.last().cloned());
+        assert_eq!(
+            verify_chain(&process_id, Some(links[0].clone()), links[1..].iter()).unwrap(),
+            links.last().cloned()
+        );
+        assert_eq!(
+            verify_chain(&process_id, Some(links[3].clone()), [].iter()).unwrap(),
+            Some(links[3].clone())
+        );
+
+        // a missing message or an edited chain is refused
+        let gap = [&links[0], &links[1], &links[3]];
+        assert!(verify_chain(&process_id, None, gap.into_iter())
+            .unwrap_err()
+            .contains("no message at nonce 2"));
+        let mut edited = links.clone();
+        edited[2].hash_chain = id('x');
+        assert!(verify_chain(&process_id, None, edited.iter())
+            .unwrap_err()
+            .contains("breaks at nonce 2"));
+    }
+
+    #[test]
+    fn test_rebalance_evens_out_load() {
+        let mut fleet = (1..=3)
+            .map(|i| scheduler(i, &format!("https://su{}", i), "", None))
+            .collect::<Vec<_>>();
+        fleet[0].process_count = 10;
+        fleet[1].process_count = 8;
+        // added late
+        fleet[2].process_count = 0;
+        assert_eq!(imbalance(&fleet), 10.0 / 6.0);
+
+        let plan = plan_rebalance(&fleet, 0.2, 100);
+        assert_eq!(plan.len(), 6);
+        assert!(plan.iter().all(|(_, to)| *to == 2));
+        for (from, to) in plan {
+            fleet[from].process_count -= 1;
+            fleet[to].process_count += 1;
+        }
+        assert_eq!(
+            fleet.iter().map(|s| s.process_count).collect::<Vec<_>>(),
+            vec![6, 6, 6]
+        );
+        assert!(plan_rebalance(&fleet, 0.2, 100).is_empty());
+
+        // bounded per run, and weight and quotas are respected
+        fleet[0].process_count = 12;
+        assert_eq!(plan_rebalance(&fleet, 0.0, 2).len(), 2);
+        fleet[2].weight = Some(2);
+        fleet[2].max_processes = Some(7);
+        let plan = plan_rebalance(&fleet, 0.0, 100);
+        assert_eq!(plan[0], (0, 2));
+        assert_eq!(plan.iter().filter(|(_, to)| *to == 2).count(), 1);
+
+        // a move that only swaps which one is ahead is not made
+        let mut pair = vec![
+            scheduler(1, "https://su1", "", None),
+            scheduler(2, "https://su2", "", None),
+        ];
+        pair[0].process_count = 1;
+        assert!(plan_rebalance(&pair, 0.0, 100).is_empty());
     }
 }
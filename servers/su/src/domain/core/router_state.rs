@@ -0,0 +1,689 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::fs;
+use std::path::Path;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::dal::{ProcessScheduler, RouterDataStore, Scheduler, StoreErrorType};
+use super::scheduler_list::{deserialize_wallets, split_wallets};
+
+/*
+  The schedulers and process_schedulers tables as su router
+  export writes them and su router import reads them back,
+  for moving a router to another database or keeping a
+  snapshot of it. Row ids only mean something in the
+  database they came from, so a process names its
+  scheduler by url.
+*/
+#[derive(Serialize, Deserialize, Debug, Default, PartialEq)]
+pub struct RouterState {
+    pub schedulers: Vec<SchedulerRow>,
+    pub process_schedulers: Vec<ProcessRow>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SchedulerRow {
+    pub url: String,
+    pub process_count: i32,
+    pub no_route: Option<bool>,
+    // a comma separated string in exports made before it was a list
+    #[serde(default, deserialize_with = "deserialize_wallets")]
+    pub wallets_to_route: Option<Vec<String>>,
+    pub wallets_only: Option<bool>,
+    pub priority: Option<i32>,
+    pub large_objects: Option<bool>,
+    pub weight: Option<i32>,
+    pub public_url: Option<String>,
+    pub drain: Option<bool>,
+    // missing from exports made before scheduler quotas
+    #[serde(default)]
+    pub max_processes: Option<i32>,
+    #[serde(default)]
+    pub region: Option<String>,
+    #[serde(default)]
+    pub tags_to_route: Option<String>,
+    #[serde(default)]
+    pub wallets_policy: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ProcessRow {
+    pub process_id: String,
+    pub scheduler_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct OwnerRow {
+    pub owner: String,
+    pub scheduler_url: String,
+}
+
+impl SchedulerRow {
+    pub fn from_scheduler(scheduler: &Scheduler) -> Self {
+        SchedulerRow {
+            url: scheduler.url.clone(),
+            process_count: scheduler.process_count,
+            no_route: scheduler.no_route,
+            wallets_to_route: scheduler.wallets_to_route.clone(),
+            wallets_only: scheduler.wallets_only,
+            priority: scheduler.priority,
+            large_objects: scheduler.large_objects,
+            weight: scheduler.weight,
+            public_url: scheduler.public_url.clone(),
+            drain: scheduler.drain,
+            max_processes: scheduler.max_processes,
+            region: scheduler.region.clone(),
+            tags_to_route: scheduler.tags_to_route.clone(),
+            wallets_policy: scheduler.wallets_policy.clone(),
+        }
+    }
+
+    fn to_scheduler(&self, row_id: Option<i32>) -> Scheduler {
+        Scheduler {
+            row_id,
+            url: self.url.clone(),
+            process_count: self.process_count,
+            no_route: self.no_route,
+            wallets_to_route: self.wallets_to_route.clone(),
+            wallets_only: self.wallets_only,
+            priority: self.priority,
+            large_objects: self.large_objects,
+            weight: self.weight,
+            public_url: self.public_url.clone(),
+            drain: self.drain,
+            max_processes: self.max_processes,
+            region: self.region.clone(),
+            tags_to_route: self.tags_to_route.clone(),
+            wallets_policy: self.wallets_policy.clone(),
+        }
+    }
+}
+
+/*
+  One write to the router tables as a primary router
+  streams it to its standby, named by url and process id
+  for the same reason as the export. A scheduler change
+  carries the whole row as it was written, an owner
+  change the scheduler ROUTER_OWNER_STICKY keeps an
+  owner's processes on.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "change", rename_all = "snake_case")]
+pub enum RouterChange {
+    Scheduler(SchedulerRow),
+    SchedulerRemoved { url: String },
+    Process(ProcessRow),
+    Owner(OwnerRow),
+}
+
+/*
+  Apply a streamed change to the standby's tables. A new
+  process is assigned so it is counted on its scheduler
+  the way it was on the primary, a moved one only has
+  its row changed since the primary writes the counts of
+  both schedulers after a move. Returns the process id
+  whose route changed.
+*/
+pub fn apply_router_change(
+    store: &dyn RouterDataStore,
+    change: &RouterChange,
+) -> Result<Option<String>, String> {
+    match change {
+        RouterChange::Scheduler(row) => {
+            match store.get_scheduler_by_url(&row.url) {
+                Ok(existing) => store.update_scheduler(&row.to_scheduler(existing.row_id))?,
+                Err(StoreErrorType::NotFound(_)) => {
+                    store.save_scheduler(&row.to_scheduler(None))?
+                }
+                Err(e) => return Err(e.into()),
+            };
+            Ok(None)
+        }
+        RouterChange::SchedulerRemoved { url } => {
+            match store.get_scheduler_by_url(url) {
+                Ok(existing) => {
+                    let row_id = existing
+                        .row_id
+                        .ok_or(format!("Scheduler {} has no row id", url))?;
+                    store.delete_scheduler(&row_id)?;
+                }
+                Err(StoreErrorType::NotFound(_)) => (),
+                Err(e) => return Err(e.into()),
+            };
+            Ok(None)
+        }
+        RouterChange::Process(row) => {
+            let scheduler_row_id = store
+                .get_scheduler_by_url(&row.scheduler_url)?
+                .row_id
+                .ok_or(format!("Scheduler {} has no row id", row.scheduler_url))?;
+            match store.get_process_scheduler(&row.process_id) {
+                Ok(existing) if existing.scheduler_row_id == scheduler_row_id => return Ok(None),
+                Ok(existing) => {
+                    store.move_process(
+                        &row.process_id,
+                        &existing.scheduler_row_id,
+                        &scheduler_row_id,
+                    )?;
+                }
+                Err(StoreErrorType::NotFound(_)) => {
+                    store.assign_process(&row.process_id, &scheduler_row_id)?;
+                }
+                Err(e) => return Err(e.into()),
+            };
+            Ok(Some(row.process_id.clone()))
+        }
+        RouterChange::Owner(row) => {
+            let scheduler_row_id = store
+                .get_scheduler_by_url(&row.scheduler_url)?
+                .row_id
+                .ok_or(format!("Scheduler {} has no row id", row.scheduler_url))?;
+            store.save_owner_scheduler(&row.owner, &scheduler_row_id)?;
+            Ok(None)
+        }
+    }
+}
+
+/*
+  json is a single file, csv a directory holding
+  schedulers.csv and process_schedulers.csv
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StateFormat {
+    Json,
+    Csv,
+}
+
+impl StateFormat {
+    pub fn parse(format: &str) -> Result<Self, String> {
+        match format {
+            "json" => Ok(StateFormat::Json),
+            "csv" => Ok(StateFormat::Csv),
+            other => Err(format!("Unknown format {}, expected json or csv", other)),
+        }
+    }
+}
+
+const PAGE_SIZE: i64 = 1000;
+
+const SCHEDULER_COLUMNS: [&str; 14] = [
+    "url",
+    "process_count",
+    "no_route",
+    "wallets_to_route",
+    "wallets_only",
+    "priority",
+    "large_objects",
+    "weight",
+    "public_url",
+    "drain",
+    "max_processes",
+    "region",
+    "tags_to_route",
+    "wallets_policy",
+];
+
+const PROCESS_COLUMNS: [&str; 2] = ["process_id", "scheduler_url"];
+
+/*
+  Read both tables, process_schedulers a page at a time.
+  Rows whose scheduler no longer exists can't be named
+  by url, they are left out and counted instead.
+*/
+pub fn read_router_state(store: &dyn RouterDataStore) -> Result<(RouterState, usize), String> {
+    let schedulers = store.get_all_schedulers()?;
+    let urls: HashMap<i32, &str> = schedulers
+        .iter()
+        .filter_map(|scheduler| scheduler.row_id.map(|id| (id, scheduler.url.as_str())))
+        .collect();
+
+    let mut state = RouterState {
+        schedulers: schedulers
+            .iter()
+            .map(SchedulerRow::from_scheduler)
+            .collect(),
+        process_schedulers: vec![],
+    };
+    let mut orphans = 0;
+    let mut after = 0;
+    loop {
+        let page = store.get_process_schedulers_after(&after, PAGE_SIZE)?;
+        for row in &page {
+            match urls.get(&row.scheduler_row_id) {
+                Some(url) => state.process_schedulers.push(ProcessRow {
+                    process_id: row.process_id.clone(),
+                    scheduler_url: url.to_string(),
+                }),
+                None => orphans += 1,
+            }
+        }
+        match page.last().and_then(|row| row.row_id) {
+            Some(row_id) if page.len() as i64 == PAGE_SIZE => after = row_id,
+            _ => break,
+        }
+    }
+    Ok((state, orphans))
+}
+
+pub fn export_router_state(
+    store: &dyn RouterDataStore,
+    path: &str,
+    format: StateFormat,
+) -> Result<String, String> {
+    let (state, orphans) = read_router_state(store)?;
+    write_state(&state, Path::new(path), format)?;
+    let mut message = format!(
+        "Exported {} schedulers and {} processes to {}",
+        state.schedulers.len(),
+        state.process_schedulers.len(),
+        path
+    );
+    if orphans > 0 {
+        message.push_str(&format!(
+            ", skipped {} processes assigned to a missing scheduler",
+            orphans
+        ));
+    }
+    Ok(message)
+}
+
+pub fn import_router_state(
+    store: &dyn RouterDataStore,
+    path: &str,
+    format: StateFormat,
+) -> Result<String, String> {
+    let state = read_state(Path::new(path), format)?;
+    apply_router_state(store, &state)
+}
+
+/*
+  Write a state into a router store. A scheduler that
+  already exists by url takes the exported settings and a
+  process already assigned elsewhere is moved, so the
+  store ends up routing the way the export did. Rows not
+  in the export are left alone. process_count is counted
+  again from the rows afterwards rather than taken from
+  the file. Nothing is written unless the whole state
+  checks out.
+*/
+pub fn apply_router_state(
+    store: &dyn RouterDataStore,
+    state: &RouterState,
+) -> Result<String, String> {
+    check_router_state(state)?;
+
+    let (mut added, mut updated) = (0, 0);
+    let mut row_ids: HashMap<&str, i32> = HashMap::new();
+    for row in &state.schedulers {
+        match store.get_scheduler_by_url(&row.url) {
+            Ok(existing) => {
+                store.update_scheduler(&row.to_scheduler(existing.row_id))?;
+                updated += 1;
+            }
+            Err(StoreErrorType::NotFound(_)) => {
+                store.save_scheduler(&row.to_scheduler(None))?;
+                added += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let saved = store.get_scheduler_by_url(&row.url)?;
+        let row_id = saved
+            .row_id
+            .ok_or(format!("Scheduler {} was saved without a row id", row.url))?;
+        row_ids.insert(row.url.as_str(), row_id);
+    }
+
+    let (mut assigned, mut moved, mut unchanged) = (0, 0, 0);
+    for row in &state.process_schedulers {
+        let scheduler_row_id = row_ids[row.scheduler_url.as_str()];
+        match store.get_process_scheduler(&row.process_id) {
+            Ok(existing) if existing.scheduler_row_id == scheduler_row_id => unchanged += 1,
+            Ok(existing) => {
+                store.update_process_scheduler(&ProcessScheduler {
+                    row_id: existing.row_id,
+                    process_id: row.process_id.clone(),
+                    scheduler_row_id,
+                })?;
+                moved += 1;
+            }
+            Err(StoreErrorType::NotFound(_)) => {
+                store.save_process_scheduler(&ProcessScheduler {
+                    row_id: None,
+                    process_id: row.process_id.clone(),
+                    scheduler_row_id,
+                })?;
+                assigned += 1;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+
+    let counts: HashMap<i32, i64> = store.get_process_scheduler_counts()?.into_iter().collect();
+    for scheduler in store.get_all_schedulers()? {
+        let row_id = match scheduler.row_id {
+            Some(row_id) => row_id,
+            None => continue,
+        };
+        let count = counts.get(&row_id).copied().unwrap_or(0) as i32;
+        match count - scheduler.process_count {
+            0 => (),
+            more if more > 0 => store.increment_process_count(&row_id, more)?,
+            fewer => store.decrement_process_count(&row_id, -fewer)?,
+        }
+    }
+
+    Ok(format!(
+        "Imported {} schedulers ({} added, {} updated) and {} processes ({} added, {} moved, {} unchanged)",
+        state.schedulers.len(),
+        added,
+        updated,
+        state.process_schedulers.len(),
+        assigned,
+        moved,
+        unchanged
+    ))
+}
+
+fn check_router_state(state: &RouterState) -> Result<(), String> {
+    let mut urls = HashSet::new();
+    for row in &state.schedulers {
+        if row.url.is_empty() {
+            return Err("A scheduler has an empty url".to_string());
+        }
+        if !urls.insert(row.url.as_str()) {
+            return Err(format!("Scheduler {} is listed twice", row.url));
+        }
+    }
+    let mut process_ids = HashSet::new();
+    for row in &state.process_schedulers {
+        if !urls.contains(row.scheduler_url.as_str()) {
+            return Err(format!(
+                "Process {} is assigned to {} which is not in the schedulers",
+                row.process_id, row.scheduler_url
+            ));
+        }
+        if !process_ids.insert(row.process_id.as_str()) {
+            return Err(format!("Process {} is listed twice", row.process_id));
+        }
+    }
+    Ok(())
+}
+
+fn write_state(state: &RouterState, path: &Path, format: StateFormat) -> Result<(), String> {
+    let write = |path: &Path, contents: String| {
+        fs::write(path, contents).map_err(|e| format!("Failed to write {}: {}", path.display(), e))
+    };
+    match format {
+        StateFormat::Json => {
+            let json = serde_json::to_string_pretty(state).map_err(|e| format!("{:?}", e))?;
+            write(path, json)
+        }
+        StateFormat::Csv => {
+            fs::create_dir_all(path)
+                .map_err(|e| format!("Failed to create {}: {}", path.display(), e))?;
+            let schedulers = state.schedulers.iter().map(|row| {
+                vec![
+                    row.url.clone(),
+                    row.process_count.to_string(),
+                    optional(&row.no_route),
+                    optional(&row.wallets_to_route.as_ref().map(|w| w.join(","))),
+                    optional(&row.wallets_only),
+                    optional(&row.priority),
+                    optional(&row.large_objects),
+                    optional(&row.weight),
+                    optional(&row.public_url),
+                    optional(&row.drain),
+                    optional(&row.max_processes),
+                    optional(&row.region),
+                    optional(&row.tags_to_route),
+                    optional(&row.wallets_policy),
+                ]
+            });
+            write(
+                &path.join("schedulers.csv"),
+                csv_table(&SCHEDULER_COLUMNS, schedulers),
+            )?;
+            let processes = state
+                .process_schedulers
+                .iter()
+                .map(|row| vec![row.process_id.clone(), row.scheduler_url.clone()]);
+            write(
+                &path.join("process_schedulers.csv"),
+                csv_table(&PROCESS_COLUMNS, processes),
+            )
+        }
+    }
+}
+
+fn read_state(path: &Path, format: StateFormat) -> Result<RouterState, String> {
+    let read = |path: &Path| {
+        fs::read_to_string(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))
+    };
+    match format {
+        StateFormat::Json => {
+            serde_json::from_str(&read(path)?).map_err(|e| format!("Invalid router state: {}", e))
+        }
+        StateFormat::Csv => {
+            let mut state = RouterState::default();
+            for record in csv_records(&read(&path.join("schedulers.csv"))?, &SCHEDULER_COLUMNS)? {
+                state.schedulers.push(SchedulerRow {
+                    url: record[0].clone(),
+                    process_count: parse_field(&record[1], "process_count")?.unwrap_or(0),
+                    no_route: parse_field(&record[2], "no_route")?,
+                    wallets_to_route: parse_field::<String>(&record[3], "wallets_to_route")?
+                        .map(|joined| split_wallets(&joined)),
+                    wallets_only: parse_field(&record[4], "wallets_only")?,
+                    priority: parse_field(&record[5], "priority")?,
+                    large_objects: parse_field(&record[6], "large_objects")?,
+                    weight: parse_field(&record[7], "weight")?,
+                    public_url: parse_field(&record[8], "public_url")?,
+                    drain: parse_field(&record[9], "drain")?,
+                    max_processes: parse_field(&record[10], "max_processes")?,
+                    region: parse_field(&record[11], "region")?,
+                    tags_to_route: parse_field(&record[12], "tags_to_route")?,
+                    wallets_policy: parse_field(&record[13], "wallets_policy")?,
+                });
+            }
+            let processes = read(&path.join("process_schedulers.csv"))?;
+            for record in csv_records(&processes, &PROCESS_COLUMNS)? {
+                state.process_schedulers.push(ProcessRow {
+                    process_id: record[0].clone(),
+                    scheduler_url: record[1].clone(),
+                });
+            }
+            Ok(state)
+        }
+    }
+}
+
+// an unset column is an empty field
+fn optional<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+fn parse_field<T>(value: &str, column: &str) -> Result<Option<T>, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    if value.is_empty() {
+        return Ok(None);
+    }
+    value
+        .parse()
+        .map(Some)
+        .map_err(|e| format!("Invalid {} {}: {}", column, value, e))
+}
+
+fn csv_table(columns: &[&str], rows: impl Iterator<Item = Vec<String>>) -> String {
+    let mut table = csv_line(columns.iter().map(|column| column.to_string()));
+    for row in rows {
+        table.push_str(&csv_line(row.into_iter()));
+    }
+    table
+}
+
+fn csv_line(fields: impl Iterator<Item = String>) -> String {
+    let fields: Vec<String> = fields
+        .map(|field| {
+            if field.contains([',', '"', '\n', '\r']) {
+                format!("\"{}\"", field.replace('"', "\"\""))
+            } else {
+                field
+            }
+        })
+        .collect();
+    format!("{}\n", fields.join(","))
+}
+
+/*
+  The records after the header, which has to name the
+  columns in order. Quoted fields may hold commas, quotes
+  doubled and line breaks, blank lines are skipped.
+*/
+fn csv_records(contents: &str, columns: &[&str]) -> Result<Vec<Vec<String>>, String> {
+    let mut records = vec![];
+    let mut record = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = contents.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (true, c) => field.push(c),
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') if chars.peek() == Some(&'\n') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                records.push(std::mem::take(&mut record));
+            }
+            (false, c) => field.push(c),
+        }
+    }
+    if quoted {
+        return Err("Unterminated quoted field".to_string());
+    }
+    if !field.is_empty() || !record.is_empty() {
+        record.push(field);
+        records.push(record);
+    }
+    records.retain(|record| !(record.len() == 1 && record[0].is_empty()));
+
+    let mut records = records.into_iter();
+    match records.next() {
+        Some(header) if header == columns => {}
+        _ => return Err(format!("Expected the header {}", columns.join(","))),
+    }
+    records
+        .enumerate()
+        .map(|(i, record)| match record.len() == columns.len() {
+            true => Ok(record),
+            false => Err(format!(
+                "Record {} has {} fields, expected {}",
+                i + 1,
+                record.len(),
+                columns.len()
+            )),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_csv_quoting_round_trip() {
+        let row = vec![
+            "https://su1".to_string(),
+            "a, b".to_string(),
+            "say \"hi\"".to_string(),
+            "two\nlines".to_string(),
+            "".to_string(),
+        ];
+        let columns = ["url", "wallets", "note", "text", "empty"];
+        let table = csv_table(&columns, vec![row.clone()].into_iter());
+        assert_eq!(
+            table,
+            "url,wallets,note,text,empty\nhttps://su1,\"a, b\",\"say \"\"hi\"\"\",\"two\nlines\",\n"
+        );
+        assert_eq!(csv_records(&table, &columns).unwrap(), vec![row]);
+
+        // crlf line endings and a missing final newline
+        assert_eq!(
+            csv_records(
+                "process_id,scheduler_url\r\npid1,https://su1",
+                &PROCESS_COLUMNS
+            )
+            .unwrap(),
+            vec![vec!["pid1".to_string(), "https://su1".to_string()]]
+        );
+        assert!(csv_records("scheduler_url,process_id\n", &PROCESS_COLUMNS).is_err());
+        assert!(csv_records("process_id,scheduler_url\npid1\n", &PROCESS_COLUMNS).is_err());
+    }
+
+    #[test]
+    fn test_state_checked_before_import() {
+        let scheduler = SchedulerRow {
+            url: "https://su1".to_string(),
+            process_count: 1,
+            no_route: None,
+            wallets_to_route: None,
+            wallets_only: None,
+            priority: None,
+            large_objects: None,
+            weight: None,
+            public_url: None,
+            drain: None,
+            max_processes: None,
+            region: None,
+            tags_to_route: None,
+            wallets_policy: None,
+        };
+        let process = |id: &str, url: &str| ProcessRow {
+            process_id: id.to_string(),
+            scheduler_url: url.to_string(),
+        };
+        let mut state = RouterState {
+            schedulers: vec![scheduler.clone()],
+            process_schedulers: vec![process("pid1", "https://su1")],
+        };
+        assert!(check_router_state(&state).is_ok());
+
+        state
+            .process_schedulers
+            .push(process("pid2", "https://su2"));
+        assert!(check_router_state(&state).is_err());
+
+        state.process_schedulers[1] = process("pid1", "https://su1");
+        assert!(check_router_state(&state).is_err());
+
+        state.process_schedulers.pop();
+        state.schedulers.push(scheduler);
+        assert!(check_router_state(&state).is_err());
+    }
+
+    #[test]
+    fn test_older_exports_list_wallets_as_a_string() {
+        let row: SchedulerRow = serde_json::from_str(
+            r#"{"url": "https://su1", "process_count": 0, "no_route": null,
+                "wallets_to_route": "a, b", "wallets_only": null, "priority": null,
+                "large_objects": null, "weight": null, "public_url": null, "drain": null}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            row.wallets_to_route,
+            Some(vec!["a".to_string(), "b".to_string()])
+        );
+        assert!(serde_json::to_string(&row)
+            .unwrap()
+            .contains(r#""wallets_to_route":["a","b"]"#));
+    }
+}
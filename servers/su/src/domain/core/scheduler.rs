@@ -6,7 +6,7 @@ use dashmap::DashMap;
 use sha2::{Digest, Sha256};
 use tokio::sync::Mutex;
 
-use crate::domain::core::dal::{DataStore, Log, ScheduleProvider, StoreErrorType};
+use crate::domain::core::dal::{DataStore, Log, Message, ScheduleProvider, StoreErrorType};
 
 pub struct SchedulerDeps {
     pub data_store: Arc<dyn DataStore>,
@@ -96,7 +96,7 @@ impl ProcessScheduler {
         next_schedule_info: &ScheduleInfo,
         id: String,
         previous_assignment: String,
-    ) -> &mut ScheduleInfo {
+    ) -> &'a mut ScheduleInfo {
         schedule_info.epoch = next_schedule_info.epoch;
         schedule_info.nonce = next_schedule_info.nonce;
         schedule_info.hash_chain = next_schedule_info.hash_chain.clone();
@@ -112,6 +112,35 @@ impl ProcessScheduler {
         schedule_info
     }
 
+    /*
+      The hash chain of the latest assignment on a process,
+      the value a client last saw in its message list. None
+      before the process has any assignment. Takes the lock
+      like increment so nothing is scheduled in between.
+    */
+    pub async fn latest_hash_chain<'a>(
+        &'a self,
+        _schedule_info: &'a mut ScheduleInfo,
+        id: &str,
+    ) -> Result<Option<String>, String> {
+        if let Some(cached_info) = self.cache.get(id) {
+            return Ok(Some(cached_info.schedule_info.hash_chain.clone()));
+        }
+
+        let latest_message = match self.deps.data_store.get_latest_message(id).await {
+            Ok(m) => m,
+            Err(e) => return Err(format!("{:?}", e)),
+        };
+        match latest_message {
+            Some(message) => Ok(Some(message.hash_chain()?)),
+            None => match self.deps.data_store.get_process(id).await {
+                Ok(process) if process.assignment.is_some() => Ok(Some(process.hash_chain()?)),
+                Ok(_) | Err(StoreErrorType::NotFound(_)) => Ok(None),
+                Err(e) => Err(format!("{:?}", e)),
+            },
+        }
+    }
+
     /*
       Get the incremented scheduling info for a Process.
       We pass te metable reference to schedule info to
@@ -199,7 +228,7 @@ impl ProcessScheduler {
                                 self.deps
                                     .logger
                                     .log(format!("hash chain generated with assign - {}", &id));
-                                (epoch as i32, nonce as i32, hash_chain)
+                                (epoch, nonce, hash_chain)
                             }
                             None => {
                                 // this is the first message on an old process
@@ -207,7 +236,7 @@ impl ProcessScheduler {
                                 self.deps
                                     .logger
                                     .log(format!("hash chain generated no assign - {}", &id));
-                                (0 as i32, 0 as i32, hash_chain)
+                                (0_i32, 0_i32, hash_chain)
                             }
                         }
                     }
@@ -221,7 +250,7 @@ impl ProcessScheduler {
                             self.deps
                                 .logger
                                 .log(format!("hash chain generated new process - {}", &id));
-                            (0 as i32, 0 as i32, hash_chain)
+                            (0_i32, 0_i32, hash_chain)
                         }
                         _ => return Err(format!("{:?}", e)),
                     },
@@ -244,9 +273,23 @@ impl ProcessScheduler {
     }
 }
 
+/*
+    Schedule info for the item directly after an
+    existing one, used when an admin forces an
+    assignment at an explicit nonce instead of
+    taking the next one from increment
+*/
+pub fn schedule_after(previous: &Message, timestamp: i64) -> Result<ScheduleInfo, String> {
+    Ok(ScheduleInfo {
+        epoch: previous.epoch()?,
+        nonce: previous.nonce()? + 1,
+        hash_chain: gen_hash_chain(&previous.hash_chain()?, Some(&previous.assignment_id()?))?,
+        timestamp,
+    })
+}
+
 pub trait DecodeHash: Sized {
     fn from(base64_url_string: &str) -> Result<Self, String>;
-    fn empty() -> Self;
 }
 
 impl DecodeHash for [u8; 32] {
@@ -259,32 +302,19 @@ impl DecodeHash for [u8; 32] {
                     .map_err(|_| format!("Length mismatch 32 - {base64_url_string}"))
             })
     }
-
-    fn empty() -> Self {
-        [0u8; 32]
-    }
 }
 
-fn gen_hash_chain(
+pub fn gen_hash_chain(
     previous_or_seed: &str,
     previous_message_id: Option<&str>,
 ) -> Result<String, String> {
     let mut hasher = Sha256::new();
 
-    let prev_bytes: [u8; 32] = match DecodeHash::from(previous_or_seed) {
-        Ok(pb) => pb,
-        Err(e) => return Err(e),
-    };
+    let prev_bytes: [u8; 32] = DecodeHash::from(previous_or_seed)?;
 
-    match previous_message_id {
-        Some(id) => {
-            let id_bytes: [u8; 32] = match DecodeHash::from(id) {
-                Ok(idb) => idb,
-                Err(e) => return Err(e),
-            };
-            hasher.update(id_bytes);
-        }
-        None => (),
+    if let Some(id) = previous_message_id {
+        let id_bytes: [u8; 32] = DecodeHash::from(id)?;
+        hasher.update(id_bytes);
     }
 
     hasher.update(prev_bytes);
@@ -0,0 +1,662 @@
+use reqwest::Url;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::Value;
+
+use super::address::{is_wallet_address, is_wallet_prefix};
+
+/*
+  The newest scheduler list format, files without a
+  version are the flat array of version 1
+*/
+pub const CURRENT_VERSION: u64 = 2;
+
+/*
+    wallets_to_route is a list of wallets, a wallet
+    ending in * taking every address it prefixes. It can
+    instead be read from a file with wallets_to_route_file
+    or an environment variable with wallets_to_route_env,
+    keeping long or sensitive lists out of the scheduler
+    list itself
+*/
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulerEntry {
+    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub no_route: Option<bool>,
+    #[serde(
+        default,
+        deserialize_with = "deserialize_wallets",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub wallets_to_route: Option<Vec<String>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallets_to_route_file: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallets_to_route_env: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallets_only: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub priority: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub large_objects: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub weight: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub public_url: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub drain: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_processes: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags_to_route: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub wallets_policy: Option<String>,
+}
+
+/*
+  A set of schedulers sharing their settings, a field
+  set on the group applies to every scheduler in it
+  that doesn't set the field itself
+*/
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SchedulerGroup {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    no_route: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallets_only: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    priority: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    large_objects: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    weight: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    drain: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    max_processes: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    region: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tags_to_route: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    wallets_policy: Option<String>,
+    schedulers: Vec<SchedulerEntry>,
+}
+
+impl SchedulerGroup {
+    fn entries(self) -> Vec<SchedulerEntry> {
+        let SchedulerGroup {
+            no_route,
+            wallets_only,
+            priority,
+            large_objects,
+            weight,
+            drain,
+            max_processes,
+            region,
+            tags_to_route,
+            wallets_policy,
+            schedulers,
+            ..
+        } = self;
+        schedulers
+            .into_iter()
+            .map(|entry| SchedulerEntry {
+                no_route: entry.no_route.or(no_route),
+                wallets_only: entry.wallets_only.or(wallets_only),
+                priority: entry.priority.or(priority),
+                large_objects: entry.large_objects.or(large_objects),
+                weight: entry.weight.or(weight),
+                drain: entry.drain.or(drain),
+                max_processes: entry.max_processes.or(max_processes),
+                region: entry.region.or(region.clone()),
+                tags_to_route: entry.tags_to_route.or(tags_to_route.clone()),
+                wallets_policy: entry.wallets_policy.or(wallets_policy.clone()),
+                ..entry
+            })
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug)]
+#[serde(deny_unknown_fields)]
+struct SchedulerListV2 {
+    version: u64,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    groups: Vec<SchedulerGroup>,
+    // schedulers outside of any group
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    schedulers: Vec<SchedulerEntry>,
+}
+
+#[derive(Clone, Copy)]
+enum Kind {
+    Text,
+    Flag,
+    Number,
+    List,
+    Wallets,
+}
+
+impl Kind {
+    fn holds(&self, value: &Value) -> bool {
+        match self {
+            Kind::Text => value.is_string(),
+            Kind::Flag => value.is_boolean(),
+            Kind::Number => value.as_i64().is_some_and(|n| i32::try_from(n).is_ok()),
+            Kind::List => value.is_array(),
+            Kind::Wallets => {
+                value.is_string()
+                    || value
+                        .as_array()
+                        .is_some_and(|wallets| wallets.iter().all(Value::is_string))
+            }
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Kind::Text => "a string",
+            Kind::Flag => "true or false",
+            Kind::Number => "a whole number",
+            Kind::List => "a list",
+            Kind::Wallets => "a list of wallets",
+        }
+    }
+}
+
+// the fields a group shares with its schedulers
+const SHARED_FIELDS: [(&str, Kind); 10] = [
+    ("no_route", Kind::Flag),
+    ("wallets_only", Kind::Flag),
+    ("priority", Kind::Number),
+    ("large_objects", Kind::Flag),
+    ("weight", Kind::Number),
+    ("drain", Kind::Flag),
+    ("max_processes", Kind::Number),
+    ("region", Kind::Text),
+    ("tags_to_route", Kind::Text),
+    ("wallets_policy", Kind::Text),
+];
+
+const ENTRY_FIELDS: [(&str, Kind); 5] = [
+    ("url", Kind::Text),
+    ("wallets_to_route", Kind::Wallets),
+    ("wallets_to_route_file", Kind::Text),
+    ("wallets_to_route_env", Kind::Text),
+    ("public_url", Kind::Text),
+];
+
+const GROUP_FIELDS: [(&str, Kind); 2] = [("name", Kind::Text), ("schedulers", Kind::List)];
+
+const LIST_FIELDS: [(&str, Kind); 3] = [
+    ("version", Kind::Number),
+    ("groups", Kind::List),
+    ("schedulers", Kind::List),
+];
+
+/*
+    Check the fields of one object of the list before
+    serde reads it, so a misspelled or mistyped field is
+    reported with where it is instead of a line and column
+*/
+fn check_fields(value: &Value, at: &str, fields: &[&[(&str, Kind)]]) -> Result<(), String> {
+    let object = value
+        .as_object()
+        .ok_or(format!("{} must be an object, found {}", at, value))?;
+    let known = || fields.iter().flat_map(|fields| fields.iter());
+    for (name, field) in object {
+        let kind = match known().find(|(known, _)| known == name) {
+            Some((_, kind)) => kind,
+            None => {
+                let names: Vec<&str> = known().map(|(known, _)| *known).collect();
+                return Err(format!(
+                    "{} has an unknown field {}, expected one of {}",
+                    at,
+                    name,
+                    names.join(", ")
+                ));
+            }
+        };
+        if !field.is_null() && !kind.holds(field) {
+            return Err(format!(
+                "{} field {} must be {}, found {}",
+                at,
+                name,
+                kind.describe(),
+                field
+            ));
+        }
+    }
+    Ok(())
+}
+
+// where an entry is in the list, with its url when it has one
+fn entry_at(path: String, entry: &Value) -> String {
+    match entry.get("url").and_then(Value::as_str) {
+        Some(url) => format!("scheduler {} ({})", path, url),
+        None => format!("scheduler {}", path),
+    }
+}
+
+fn check_entry(value: &Value, path: String) -> Result<(), String> {
+    let at = entry_at(path, value);
+    check_fields(value, &at, &[&ENTRY_FIELDS, &SHARED_FIELDS])?;
+    if value.get("url").map_or(true, Value::is_null) {
+        return Err(format!("{} has no url", at));
+    }
+    Ok(())
+}
+
+fn check_list(value: &Value) -> Result<(), String> {
+    if let Value::Array(entries) = value {
+        for (i, entry) in entries.iter().enumerate() {
+            check_entry(entry, format!("[{}]", i))?;
+        }
+        return Ok(());
+    }
+    check_fields(value, "the scheduler list", &[&LIST_FIELDS])?;
+    let list = |value: &Value, name: &str| -> Vec<Value> {
+        value
+            .get(name)
+            .and_then(Value::as_array)
+            .cloned()
+            .unwrap_or_default()
+    };
+    for (i, entry) in list(value, "schedulers").iter().enumerate() {
+        check_entry(entry, format!("schedulers[{}]", i))?;
+    }
+    for (g, group) in list(value, "groups").iter().enumerate() {
+        let at = format!("group groups[{}]", g);
+        check_fields(group, &at, &[&GROUP_FIELDS, &SHARED_FIELDS])?;
+        if group.get("schedulers").map_or(true, Value::is_null) {
+            return Err(format!("{} has no schedulers", at));
+        }
+        for (i, entry) in list(group, "schedulers").iter().enumerate() {
+            check_entry(entry, format!("groups[{}].schedulers[{}]", g, i))?;
+        }
+    }
+    Ok(())
+}
+
+/*
+    Read a scheduler list in any of its formats into the
+    entries the router applies. Version 1 is the flat
+    array of entries. Version 2 is an object with a
+    version field, groups of schedulers that share their
+    settings and schedulers outside of any group.
+*/
+pub fn parse_scheduler_list(contents: &str) -> Result<Vec<SchedulerEntry>, String> {
+    let value: Value =
+        serde_json::from_str(contents).map_err(|e| format!("Failed to parse JSON: {}", e))?;
+    let version = match &value {
+        Value::Array(_) => 1,
+        Value::Object(fields) => match fields.get("version") {
+            Some(version) => version
+                .as_u64()
+                .ok_or(format!("Invalid scheduler list version {}", version))?,
+            None => return Err("Scheduler list object has no version".to_string()),
+        },
+        _ => return Err("Scheduler list must be an array or an object".to_string()),
+    };
+
+    if version <= CURRENT_VERSION {
+        check_list(&value).map_err(|e| format!("Invalid scheduler list, {}", e))?;
+    }
+
+    // each entry with where it was in the list
+    let entries: Vec<(String, SchedulerEntry)> = match version {
+        1 => serde_json::from_value::<Vec<SchedulerEntry>>(value)
+            .map_err(|e| format!("Invalid version 1 scheduler list: {}", e))?
+            .into_iter()
+            .enumerate()
+            .map(|(i, entry)| (format!("[{}]", i), entry))
+            .collect(),
+        2 => {
+            let list = serde_json::from_value::<SchedulerListV2>(value)
+                .map_err(|e| format!("Invalid version 2 scheduler list: {}", e))?;
+            let mut entries: Vec<(String, SchedulerEntry)> = list
+                .schedulers
+                .into_iter()
+                .enumerate()
+                .map(|(i, entry)| (format!("schedulers[{}]", i), entry))
+                .collect();
+            for (g, group) in list.groups.into_iter().enumerate() {
+                entries.extend(
+                    group
+                        .entries()
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, entry)| (format!("groups[{}].schedulers[{}]", g, i), entry)),
+                );
+            }
+            entries
+        }
+        version => {
+            return Err(format!(
+                "Unsupported scheduler list version {}, this su reads up to {}",
+                version, CURRENT_VERSION
+            ))
+        }
+    };
+
+    check_entries(&entries).map_err(|e| format!("Invalid scheduler list, {}", e))?;
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+// an absolute http or https url
+fn check_url(url: &str) -> Result<(), String> {
+    match Url::parse(url) {
+        Ok(parsed) if matches!(parsed.scheme(), "http" | "https") && parsed.has_host() => Ok(()),
+        Ok(_) => Err(format!("{} is not an http or https url", url)),
+        Err(e) => Err(format!("{} is not a url: {}", url, e)),
+    }
+}
+
+/*
+    wallets_to_route as the list it is now, lists written
+    before were one comma separated string and read as
+    the same wallets
+*/
+pub fn deserialize_wallets<'de, D>(deserializer: D) -> Result<Option<Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Wallets {
+        Joined(String),
+        Listed(Vec<String>),
+    }
+
+    Ok(match Option::<Wallets>::deserialize(deserializer)? {
+        Some(Wallets::Joined(joined)) => Some(split_wallets(&joined)),
+        Some(Wallets::Listed(listed)) => Some(
+            listed
+                .iter()
+                .map(|wallet| wallet.trim().to_string())
+                .filter(|wallet| !wallet.is_empty())
+                .collect(),
+        ),
+        None => None,
+    })
+}
+
+pub fn split_wallets(joined: &str) -> Vec<String> {
+    joined
+        .split(',')
+        .map(str::trim)
+        .filter(|wallet| !wallet.is_empty())
+        .map(str::to_string)
+        .collect()
+}
+
+/*
+    The addresses of a wallets_to_route list, each one
+    optionally followed by :<percent> of its processes.
+    A prefix ending in * only uses address characters.
+*/
+pub fn check_wallets(wallets: &[String]) -> Result<(), String> {
+    for listed in wallets {
+        let wallet = match listed.rsplit_once(':') {
+            Some((wallet, share)) => {
+                share.trim().parse::<u32>().map_err(|_| {
+                    format!("the share {} of {} is not a whole number", share, wallet)
+                })?;
+                wallet.trim()
+            }
+            None => listed,
+        };
+        if let Some(prefix) = wallet.strip_suffix('*') {
+            if !is_wallet_prefix(prefix) {
+                return Err(format!("{} is not a prefix of wallet addresses", wallet));
+            }
+            continue;
+        }
+        if !is_wallet_address(wallet) {
+            return Err(format!(
+                "{} is not an Arweave, 0x or base58 wallet address",
+                wallet
+            ));
+        }
+    }
+    Ok(())
+}
+
+/*
+    A strict wallets_policy refuses the spawns of the
+    wallets an entry lists while none of the schedulers
+    listing them can take one, prefer, the default, sends
+    them to the rest of the schedulers instead
+*/
+pub const WALLETS_STRICT: &str = "strict";
+pub const WALLETS_PREFER: &str = "prefer";
+
+pub fn check_wallets_policy(policy: &str) -> Result<(), String> {
+    match policy == WALLETS_STRICT || policy == WALLETS_PREFER {
+        true => Ok(()),
+        false => Err(format!(
+            "{} is not {} or {}",
+            policy, WALLETS_STRICT, WALLETS_PREFER
+        )),
+    }
+}
+
+fn check_entries(entries: &[(String, SchedulerEntry)]) -> Result<(), String> {
+    for (path, entry) in entries {
+        let at = format!("scheduler {} ({})", path, entry.url);
+        check_url(&entry.url).map_err(|e| format!("{} field url: {}", at, e))?;
+        if let Some(url) = &entry.public_url {
+            check_url(url).map_err(|e| format!("{} field public_url: {}", at, e))?;
+        }
+        if let Some(max) = entry.max_processes {
+            if max < 0 {
+                return Err(format!("{} field max_processes: {} is negative", at, max));
+            }
+        }
+        if let Some(wallets) = &entry.wallets_to_route {
+            check_wallets(wallets).map_err(|e| format!("{} field wallets_to_route: {}", at, e))?;
+        }
+        if let Some(rules) = &entry.tags_to_route {
+            parse_tag_rules(rules).map_err(|e| format!("{} field tags_to_route: {}", at, e))?;
+        }
+        if let Some(policy) = &entry.wallets_policy {
+            check_wallets_policy(policy)
+                .map_err(|e| format!("{} field wallets_policy: {}", at, e))?;
+        }
+    }
+    Ok(())
+}
+
+/*
+    The rules of a tags_to_route field, comma separated
+    and each one or more Name=Value conditions joined by
+    &, as the name and value pairs a spawn must all have
+*/
+pub fn parse_tag_rules(rules: &str) -> Result<Vec<Vec<(String, String)>>, String> {
+    rules
+        .split(',')
+        .map(str::trim)
+        .filter(|rule| !rule.is_empty())
+        .map(|rule| {
+            rule.split('&')
+                .map(|condition| match condition.split_once('=') {
+                    Some((name, value)) if !name.trim().is_empty() => {
+                        Ok((name.trim().to_string(), value.trim().to_string()))
+                    }
+                    _ => Err(format!(
+                        "Invalid tags_to_route condition {:?}, expected Name=Value",
+                        condition.trim()
+                    )),
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/*
+    Rewrite a scheduler list of any version in the current
+    format. Every entry lands in the ungrouped schedulers
+    so the result routes exactly like the original, group
+    them by hand afterwards.
+*/
+pub fn migrate_scheduler_list(contents: &str) -> Result<String, String> {
+    let list = SchedulerListV2 {
+        version: CURRENT_VERSION,
+        groups: vec![],
+        schedulers: parse_scheduler_list(contents)?,
+    };
+    serde_json::to_string_pretty(&list).map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_v1_and_v2_read_the_same() {
+        let v1 = r#"[
+            {"url": "https://su1", "weight": 2, "priority": 1},
+            {"url": "https://su2", "weight": 2, "priority": 1, "max_processes": 10},
+            {"url": "https://su3", "wallets_to_route": "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI,0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"}
+        ]"#;
+        let v2 = r#"{
+            "version": 2,
+            "groups": [{
+                "name": "large",
+                "weight": 2,
+                "priority": 1,
+                "schedulers": [
+                    {"url": "https://su1"},
+                    {"url": "https://su2", "max_processes": 10}
+                ]
+            }],
+            "schedulers": [{"url": "https://su3", "wallets_to_route": [
+                "vh-NTHVvlKZqRxc8LyyTNok65yQ55a_PJ1zWLb9G2JI",
+                "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf"
+            ]}]
+        }"#;
+
+        let mut from_v1 = parse_scheduler_list(v1).unwrap();
+        let mut from_v2 = parse_scheduler_list(v2).unwrap();
+        from_v1.sort_by(|a, b| a.url.cmp(&b.url));
+        from_v2.sort_by(|a, b| a.url.cmp(&b.url));
+        assert_eq!(from_v1, from_v2);
+
+        // migrating gives a v2 file with the same entries
+        let migrated = migrate_scheduler_list(v1).unwrap();
+        assert!(migrated.contains("\"version\": 2"));
+        assert_eq!(
+            parse_scheduler_list(&migrated).unwrap(),
+            parse_scheduler_list(v1).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_entry_overrides_group() {
+        let entries = parse_scheduler_list(
+            r#"{"version": 2, "groups": [{"weight": 3, "no_route": true, "schedulers": [
+                {"url": "https://su1", "weight": 1},
+                {"url": "https://su2", "no_route": false}
+            ]}]}"#,
+        )
+        .unwrap();
+        assert_eq!(entries[0].weight, Some(1));
+        assert_eq!(entries[0].no_route, Some(true));
+        assert_eq!(entries[1].weight, Some(3));
+        assert_eq!(entries[1].no_route, Some(false));
+    }
+
+    #[test]
+    fn test_rejects_bad_lists() {
+        assert!(parse_scheduler_list(r#"{"version": 3, "schedulers": []}"#)
+            .unwrap_err()
+            .starts_with("Unsupported scheduler list version 3"));
+        assert!(parse_scheduler_list(r#"{"schedulers": []}"#).is_err());
+        assert!(parse_scheduler_list(r#"[{"url": "https://su1", "max_processes": -1}]"#).is_err());
+        assert!(
+            parse_scheduler_list(r#"[{"url": "https://su1", "tags_to_route": "Module"}]"#)
+                .unwrap_err()
+                .ends_with("field tags_to_route: Invalid tags_to_route condition \"Module\", expected Name=Value")
+        );
+        assert!(
+            parse_scheduler_list(r#"[{"url": "https://su1", "wallets_policy": "hard"}]"#)
+                .unwrap_err()
+                .ends_with("field wallets_policy: hard is not strict or prefer")
+        );
+    }
+
+    #[test]
+    fn test_errors_name_the_entry_and_field() {
+        let error = |list: &str| parse_scheduler_list(list).unwrap_err();
+        assert_eq!(
+            error(r#"[{"url": "https://su1"}, {"url": "https://su2", "wallet_to_route": "x"}]"#),
+            "Invalid scheduler list, scheduler [1] (https://su2) has an unknown field \
+             wallet_to_route, expected one of url, wallets_to_route, wallets_to_route_file, \
+             wallets_to_route_env, public_url, no_route, wallets_only, priority, \
+             large_objects, weight, drain, max_processes, region, tags_to_route, \
+             wallets_policy"
+        );
+        assert_eq!(
+            error(
+                r#"{"version": 2, "groups": [{"schedulers": [{"url": "https://su1", "weight": "2"}]}]}"#
+            ),
+            "Invalid scheduler list, scheduler groups[0].schedulers[0] (https://su1) \
+             field weight must be a whole number, found \"2\""
+        );
+        assert_eq!(
+            error(r#"{"version": 2, "groups": [{"name": "a", "scheduler": []}]}"#),
+            "Invalid scheduler list, group groups[0] has an unknown field scheduler, \
+             expected one of name, schedulers, no_route, wallets_only, priority, \
+             large_objects, weight, drain, max_processes, region, tags_to_route, \
+             wallets_policy"
+        );
+        assert_eq!(
+            error(r#"{"version": 2, "schedulers": [{"weight": 1}]}"#),
+            "Invalid scheduler list, scheduler schedulers[0] has no url"
+        );
+        assert_eq!(
+            error(r#"[{"url": "su1.example.com"}]"#),
+            "Invalid scheduler list, scheduler [0] (su1.example.com) field url: \
+             su1.example.com is not a url: relative URL without a base"
+        );
+        assert_eq!(
+            error(r#"[{"url": "https://su1", "wallets_to_route": "wallet1:80"}]"#),
+            "Invalid scheduler list, scheduler [0] (https://su1) field wallets_to_route: \
+             wallet1 is not an Arweave, 0x or base58 wallet address"
+        );
+        assert!(error(r#"[{"url": "https://su1", "wallets_to_route": "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf:half"}]"#)
+            .ends_with("the share half of 0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf is not a whole number"));
+
+        assert_eq!(
+            error(r#"[{"url": "https://su1", "wallets_to_route": ["0x7E5F*", 1]}]"#),
+            "Invalid scheduler list, scheduler [0] (https://su1) field wallets_to_route \
+             must be a list of wallets, found [\"0x7E5F*\",1]"
+        );
+        assert!(
+            error(r#"[{"url": "https://su1", "wallets_to_route": ["0x7E/*"]}]"#)
+                .ends_with("0x7E/* is not a prefix of wallet addresses")
+        );
+
+        // shares, 0x addresses, Solana keys and prefixes are all fine
+        let entries = parse_scheduler_list(
+            r#"[{"url": "https://su1", "wallets_to_route": ["0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf:20", " 11111111111111111111111111111111", "0x7E5F*", "*:10"]}]"#,
+        )
+        .unwrap();
+        assert_eq!(
+            entries[0].wallets_to_route.as_deref().unwrap(),
+            [
+                "0x7E5F4552091A69125d5DfCb7b8C2659029395Bdf:20",
+                "11111111111111111111111111111111",
+                "0x7E5F*",
+                "*:10"
+            ]
+        );
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::bytes::{ByteErrorType, DataItem};
+use super::dal::{Signer, Wallet};
+use super::router::hash;
+use super::tags::Tag;
+
+/*
+  Prefix of the error writes get while the signing self
+  check is failing and SIGNING_CHECK_READ_ONLY is on
+*/
+pub const SIGNING_UNHEALTHY: &str = "Signing self check failing";
+
+/*
+  The result of the last signing self check, the error
+  it failed with while it keeps failing
+*/
+pub struct SigningHealth {
+    failure: Mutex<Option<String>>,
+}
+
+impl SigningHealth {
+    pub fn new() -> Self {
+        SigningHealth {
+            failure: Mutex::new(None),
+        }
+    }
+
+    pub fn failure(&self) -> Option<String> {
+        self.failure.lock().unwrap().clone()
+    }
+
+    // returns whether the check changed from passing to failing or back
+    pub fn update(&self, result: Result<(), String>) -> bool {
+        let mut failure = self.failure.lock().unwrap();
+        let changed = failure.is_some() != result.is_err();
+        *failure = result.err();
+        changed
+    }
+}
+
+impl Default for SigningHealth {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn byte_error(e: ByteErrorType) -> String {
+    let ByteErrorType::ByteError(e) = e;
+    e
+}
+
+/*
+  Sign a canary assignment, never saved or sent anywhere,
+  the way real ones are signed and verify it like any
+  data item. The key must also still be the wallet's,
+  its address is the sha256 of the key.
+*/
+pub async fn sign_canary(signer: &dyn Signer, wallet: &dyn Wallet) -> Result<(), String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|e| e.to_string())?
+        .as_millis();
+    let tags = vec![
+        Tag::new("Data-Protocol", "ao"),
+        Tag::new("Type", "Signing-Check"),
+        Tag::new("Timestamp", &now.to_string()),
+    ];
+    let public_key = signer.get_public_key();
+    let mut canary = DataItem::new(vec![], vec![], tags, public_key.clone()).map_err(byte_error)?;
+    let message = canary.get_message().map_err(byte_error)?.to_vec();
+    canary.signature = signer
+        .sign_tx(message)
+        .await
+        .map_err(|e| format!("Signing the canary failed: {}", e))?;
+    canary
+        .verify()
+        .map_err(|e| format!("The canary signature does not verify: {}", byte_error(e)))?;
+
+    let address = base64_url::encode(&hash(&public_key));
+    let wallet_address = wallet.wallet_address()?;
+    if address != wallet_address {
+        return Err(format!(
+            "The signing key is for {}, the wallet is {}",
+            address, wallet_address
+        ));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+
+    struct CorruptSigner;
+
+    #[async_trait]
+    impl Signer for CorruptSigner {
+        async fn sign_tx(&self, _buffer: Vec<u8>) -> Result<Vec<u8>, String> {
+            Ok(vec![7; 512])
+        }
+
+        fn get_public_key(&self) -> Vec<u8> {
+            vec![1; 512]
+        }
+    }
+
+    struct FixedWallet;
+
+    impl Wallet for FixedWallet {
+        fn wallet_json(&self) -> Result<String, String> {
+            Ok("{}".to_string())
+        }
+
+        fn wallet_address(&self) -> Result<String, String> {
+            Ok("address".to_string())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_corrupt_signature_fails_the_check() {
+        let result = sign_canary(&CorruptSigner, &FixedWallet).await;
+        assert!(result
+            .unwrap_err()
+            .starts_with("The canary signature does not verify"));
+
+        let health = SigningHealth::new();
+        assert!(!health.update(Ok(())));
+        assert!(health.update(Err("broken".to_string())));
+        assert!(!health.update(Err("still broken".to_string())));
+        assert_eq!(health.failure(), Some("still broken".to_string()));
+        assert!(health.update(Ok(())));
+        assert_eq!(health.failure(), None);
+    }
+}
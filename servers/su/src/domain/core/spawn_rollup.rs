@@ -0,0 +1,235 @@
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use super::router::SpawnEvent;
+
+const HOUR_MILLIS: u64 = 3_600_000;
+const DAY_MILLIS: u64 = 24 * HOUR_MILLIS;
+
+/*
+  The spawns of an hour or a day to one scheduler by one
+  rule, what the spawn audit log keeps of its events
+  once they are old enough. start is the first
+  millisecond of the period.
+*/
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SpawnRollup {
+    pub period: Period,
+    pub start: u64,
+    pub scheduler: String,
+    pub rule: String,
+    pub spawns: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum Period {
+    Hour,
+    Day,
+}
+
+impl Period {
+    fn millis(&self) -> u64 {
+        match self {
+            Period::Hour => HOUR_MILLIS,
+            Period::Day => DAY_MILLIS,
+        }
+    }
+}
+
+/*
+  How long each form of the log is kept. Events older
+  than raw_hours are rolled into hours, hours older than
+  hourly_days into days and anything older than
+  retention_days is dropped, 0 keeps it forever.
+*/
+#[derive(Debug, Clone, PartialEq)]
+pub struct Retention {
+    pub raw_hours: u64,
+    pub hourly_days: u64,
+    pub retention_days: u64,
+}
+
+impl Retention {
+    pub fn enabled(&self) -> bool {
+        self.raw_hours > 0 || self.retention_days > 0
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum AuditLine {
+    Event(SpawnEvent),
+    Rollup(SpawnRollup),
+}
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Compacted {
+    pub lines: Vec<String>,
+    pub rolled_up: usize,
+    pub dropped: usize,
+}
+
+/*
+  The lines of a spawn audit log after rolling up and
+  dropping what retention says, the rollups first by
+  period start and then the events still kept in their
+  order. Rollups of the same period, scheduler and rule
+  are merged, so compacting again changes nothing. A
+  line that is neither is kept as it is.
+*/
+pub fn compact(contents: &str, now: u64, retention: &Retention) -> Compacted {
+    let before = |days: u64| match days {
+        0 => 0,
+        days => now.saturating_sub(days * DAY_MILLIS),
+    };
+    let oldest = before(retention.retention_days);
+    let raw_until = match retention.raw_hours {
+        0 => 0,
+        hours => now.saturating_sub(hours * HOUR_MILLIS),
+    };
+    let hourly_until = before(retention.hourly_days);
+
+    let mut compacted = Compacted::default();
+    let mut rollups: BTreeMap<(u64, Period, String, String), SpawnRollup> = BTreeMap::new();
+    let mut kept = vec![];
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let mut rollup = match serde_json::from_str::<AuditLine>(line) {
+            Ok(AuditLine::Event(event)) if event.timestamp < oldest => {
+                compacted.dropped += 1;
+                continue;
+            }
+            Ok(AuditLine::Event(event)) if event.timestamp < raw_until => {
+                compacted.rolled_up += 1;
+                SpawnRollup {
+                    period: Period::Hour,
+                    start: event.timestamp - event.timestamp % HOUR_MILLIS,
+                    scheduler: event.scheduler,
+                    rule: event.rule,
+                    spawns: 1,
+                    bytes: event.size as u64,
+                }
+            }
+            Ok(AuditLine::Rollup(rollup)) if rollup.start + rollup.period.millis() <= oldest => {
+                compacted.dropped += 1;
+                continue;
+            }
+            Ok(AuditLine::Rollup(rollup)) => rollup,
+            Ok(AuditLine::Event(_)) | Err(_) => {
+                kept.push(line.to_string());
+                continue;
+            }
+        };
+        if rollup.period == Period::Hour && rollup.start + HOUR_MILLIS <= hourly_until {
+            rollup.period = Period::Day;
+            rollup.start -= rollup.start % DAY_MILLIS;
+        }
+
+        let key = (
+            rollup.start,
+            rollup.period,
+            rollup.scheduler.clone(),
+            rollup.rule.clone(),
+        );
+        match rollups.get_mut(&key) {
+            Some(merged) => {
+                merged.spawns += rollup.spawns;
+                merged.bytes += rollup.bytes;
+            }
+            None => {
+                rollups.insert(key, rollup);
+            }
+        }
+    }
+
+    compacted.lines = rollups
+        .into_values()
+        .filter_map(|rollup| serde_json::to_string(&rollup).ok())
+        .chain(kept)
+        .collect();
+    compacted
+}
+
+// a line of the log that is a rollup, not a spawn to replay
+pub fn is_rollup(line: &str) -> bool {
+    serde_json::from_str::<SpawnRollup>(line).is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(timestamp: u64, scheduler: &str) -> String {
+        serde_json::to_string(&SpawnEvent {
+            timestamp,
+            process_id: format!("p{}", timestamp),
+            owner: "owner".to_string(),
+            size: 10,
+            scheduler: scheduler.to_string(),
+            rule: "least-count".to_string(),
+            region: None,
+            strategy: None,
+            candidates: vec![],
+            skipped: vec![],
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn test_events_roll_up_into_hours_then_days() {
+        let now = 40 * DAY_MILLIS;
+        let retention = Retention {
+            raw_hours: 24,
+            hourly_days: 7,
+            retention_days: 30,
+        };
+        let log = [
+            event(5 * DAY_MILLIS, "https://su1"),
+            event(20 * DAY_MILLIS + 1, "https://su1"),
+            event(20 * DAY_MILLIS + 2 * HOUR_MILLIS, "https://su1"),
+            event(35 * DAY_MILLIS + 10, "https://su1"),
+            event(35 * DAY_MILLIS + 20, "https://su1"),
+            event(35 * DAY_MILLIS + 30, "https://su2"),
+            event(now - 1, "https://su2"),
+        ]
+        .join("\n");
+
+        let compacted = compact(&log, now, &retention);
+        assert_eq!((compacted.rolled_up, compacted.dropped), (5, 1));
+        let rollup = |line: &String| serde_json::from_str::<SpawnRollup>(line).unwrap();
+        let rollups: Vec<SpawnRollup> = compacted.lines[..3].iter().map(rollup).collect();
+        assert_eq!(
+            rollups
+                .iter()
+                .map(|r| (r.period, r.start, r.scheduler.as_str(), r.spawns, r.bytes))
+                .collect::<Vec<_>>(),
+            vec![
+                (Period::Day, 20 * DAY_MILLIS, "https://su1", 2, 20),
+                (Period::Hour, 35 * DAY_MILLIS, "https://su1", 2, 20),
+                (Period::Hour, 35 * DAY_MILLIS, "https://su2", 1, 10),
+            ]
+        );
+        assert_eq!(compacted.lines[3], event(now - 1, "https://su2"));
+        assert_eq!(compacted.lines.len(), 4);
+
+        // compacting again is a no-op, later the hours become a day
+        let again = compact(&compacted.lines.join("\n"), now, &retention);
+        assert_eq!(again.lines, compacted.lines);
+        let later = compact(&again.lines.join("\n"), now + 3 * DAY_MILLIS, &retention);
+        assert_eq!(
+            rollup(&later.lines[1]),
+            SpawnRollup {
+                period: Period::Day,
+                start: 35 * DAY_MILLIS,
+                scheduler: "https://su1".to_string(),
+                rule: "least-count".to_string(),
+                spawns: 2,
+                bytes: 20,
+            }
+        );
+        assert!(is_rollup(&later.lines[0]));
+        assert!(!is_rollup(&compacted.lines[3]));
+    }
+}
@@ -0,0 +1,80 @@
+use std::sync::Arc;
+
+use dashmap::DashMap;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+// events a subscriber may fall behind by before it misses some
+const CHANNEL_CAPACITY: usize = 256;
+
+/*
+  Fans the events of newly scheduled messages out to the
+  clients subscribed to their process. A process only has
+  a channel while someone is subscribed, the channel is
+  dropped on the first publish after the last one left.
+*/
+pub struct Subscriptions {
+    channels: DashMap<String, Sender<Arc<String>>>,
+}
+
+impl Subscriptions {
+    pub fn new() -> Self {
+        Subscriptions {
+            channels: DashMap::new(),
+        }
+    }
+
+    pub fn subscribe(&self, process_id: &str) -> Receiver<Arc<String>> {
+        self.channels
+            .entry(process_id.to_string())
+            .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+            .subscribe()
+    }
+
+    // lets the write path skip building events nobody reads
+    pub fn is_watched(&self, process_id: &str) -> bool {
+        self.channels.contains_key(process_id)
+    }
+
+    pub fn publish(&self, process_id: &str, event: String) {
+        let unwatched = match self.channels.get(process_id) {
+            // sending only fails when every receiver is gone
+            Some(sender) => sender.send(Arc::new(event)).is_err(),
+            None => false,
+        };
+        if unwatched {
+            self.channels
+                .remove_if(process_id, |_, sender| sender.receiver_count() == 0);
+        }
+    }
+}
+
+impl Default for Subscriptions {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_publishes_to_subscribers_of_the_process() {
+        let subscriptions = Subscriptions::new();
+        let mut first = subscriptions.subscribe("process-1");
+        let mut second = subscriptions.subscribe("process-1");
+        let mut other = subscriptions.subscribe("process-2");
+
+        subscriptions.publish("process-1", "event".to_string());
+        assert_eq!(first.try_recv().unwrap().as_str(), "event");
+        assert_eq!(second.try_recv().unwrap().as_str(), "event");
+        assert!(other.try_recv().is_err());
+
+        drop(first);
+        drop(second);
+        assert!(subscriptions.is_watched("process-1"));
+        subscriptions.publish("process-1", "event".to_string());
+        assert!(!subscriptions.is_watched("process-1"));
+        assert!(subscriptions.is_watched("process-2"));
+    }
+}
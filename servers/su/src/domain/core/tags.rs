@@ -1,6 +1,11 @@
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
 use avro_rs::{from_avro_datum, to_avro_datum, Schema};
 use bytes::Bytes;
 use lazy_static::lazy_static;
+use serde::de::{self, Deserializer, Visitor};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug)]
@@ -9,17 +14,119 @@ pub enum TagError {
     InvalidTagEncoding,
 }
 
+/*
+  Names and values found on nearly every item. Tags
+  holding one of these borrow the static str instead
+  of allocating their own copy, both when an item is
+  parsed and when the su builds its own tags.
+*/
+const COMMON_STRINGS: &[&str] = &[
+    "Data-Protocol",
+    "ao",
+    "Variant",
+    "ao.TN.1",
+    "Type",
+    "Message",
+    "Process",
+    "Assignment",
+    "Module",
+    "Scheduler",
+    "Scheduler-Location",
+    "Authority",
+    "SDK",
+    "aoconnect",
+    "Action",
+    "Name",
+    "Content-Type",
+    "text/plain",
+    "application/json",
+    "From-Process",
+    "From-Module",
+    "Pushed-For",
+    "Reference",
+    "On-Boot",
+    "Cron-Interval",
+    "Cron-Tag-Action",
+    "Memory-Limit",
+    "Compute-Limit",
+    "Module-Format",
+    "Input-Encoding",
+    "Output-Encoding",
+    "App-Name",
+    "App-Version",
+    "Epoch",
+    "Nonce",
+    "Hash-Chain",
+    "Block-Height",
+    "Timestamp",
+    "Exclude",
+    "Url",
+    "Time-To-Live",
+    "Bundle-Format",
+    "Bundle-Version",
+    "binary",
+    "2.0.0",
+    "0",
+    "1",
+];
+
+lazy_static! {
+    static ref COMMON_SET: HashSet<&'static str> = COMMON_STRINGS.iter().copied().collect();
+}
+
+pub fn intern(s: &str) -> Cow<'static, str> {
+    match COMMON_SET.get(s) {
+        Some(common) => Cow::Borrowed(common),
+        None => Cow::Owned(s.to_string()),
+    }
+}
+
+fn intern_owned(s: String) -> Cow<'static, str> {
+    match COMMON_SET.get(s.as_str()) {
+        Some(common) => Cow::Borrowed(common),
+        None => Cow::Owned(s),
+    }
+}
+
+struct InternVisitor;
+
+impl<'de> Visitor<'de> for InternVisitor {
+    type Value = Cow<'static, str>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a string")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<Self::Value, E> {
+        Ok(intern(v))
+    }
+
+    fn visit_string<E: de::Error>(self, v: String) -> Result<Self::Value, E> {
+        Ok(intern_owned(v))
+    }
+}
+
+// asks for a borrowed str so a common string is never copied
+fn deserialize_interned<'de, D>(deserializer: D) -> Result<Cow<'static, str>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserializer.deserialize_str(InternVisitor)
+}
+
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
 pub struct Tag {
-    pub name: String,
-    pub value: String,
+    #[serde(deserialize_with = "deserialize_interned")]
+    pub name: Cow<'static, str>,
+    #[serde(deserialize_with = "deserialize_interned")]
+    pub value: Cow<'static, str>,
 }
 
 impl Tag {
     pub fn new(name: &str, value: &str) -> Self {
         Tag {
-            name: name.to_string(),
-            value: value.to_string(),
+            name: intern(name),
+            value: intern(value),
         }
     }
 }
@@ -62,8 +169,7 @@ impl AvroEncode for Vec<Tag> {
 
 impl AvroDecode for &mut [u8] {
     fn decode(&mut self) -> Result<Vec<Tag>, TagError> {
-        let x = self.to_vec();
-        let v = from_avro_datum(&TAGS_SCHEMA, &mut x.as_slice(), Some(&TAGS_SCHEMA))
+        let v = from_avro_datum(&TAGS_SCHEMA, &mut &self[..], Some(&TAGS_SCHEMA))
             .map_err(|_| TagError::InvalidTagEncoding)?;
         avro_rs::from_value(&v).map_err(|_| TagError::InvalidTagEncoding)
     }
@@ -74,3 +180,29 @@ impl From<avro_rs::DeError> for TagError {
         TagError::InvalidTagEncoding
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // whether the name and the value borrow a common string
+    fn borrowed(tag: &Tag) -> (bool, bool) {
+        (
+            matches!(tag.name, Cow::Borrowed(_)),
+            matches!(tag.value, Cow::Borrowed(_)),
+        )
+    }
+
+    #[test]
+    fn test_common_strings_are_borrowed() {
+        let tags = vec![Tag::new("Type", "Message"), Tag::new("Action", "Eval-1")];
+        assert_eq!(borrowed(&tags[0]), (true, true));
+        assert_eq!(borrowed(&tags[1]), (true, false));
+
+        let json = serde_json::to_string(&tags).unwrap();
+        let parsed: Vec<Tag> = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed, tags);
+        assert_eq!(borrowed(&parsed[0]), (true, true));
+        assert_eq!(borrowed(&parsed[1]), (true, false));
+    }
+}
@@ -0,0 +1,390 @@
+use std::cell::RefCell;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+use data_encoding::HEXLOWER;
+use rand::{thread_rng, Rng, RngCore};
+
+use super::dal::SpanExporter;
+
+/*
+  Request tracing in the W3C trace context and OTLP
+  model. A request the server traces runs in a scope
+  holding the ids of its open spans. Spans started
+  anywhere under it, in the router, the stores or the
+  clients calling out, become children of the innermost
+  open one, and calls out carry its traceparent so the
+  scheduler or gateway on the other end can continue
+  the trace. Outside a traced request starting a span
+  does nothing, background jobs are never traced.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpanKind {
+    Internal,
+    // handling a request that came in
+    Server,
+    // waiting on a store or another service
+    Client,
+}
+
+impl SpanKind {
+    // the value of the SpanKind enum in OTLP
+    pub fn otlp(&self) -> u8 {
+        match self {
+            SpanKind::Internal => 1,
+            SpanKind::Server => 2,
+            SpanKind::Client => 3,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Span {
+    pub trace_id: String,
+    pub span_id: String,
+    pub parent_span_id: Option<String>,
+    pub name: String,
+    pub kind: SpanKind,
+    pub start_unix_nanos: u64,
+    pub end_unix_nanos: u64,
+    pub attributes: Vec<(String, String)>,
+    // set when the operation failed
+    pub error: Option<String>,
+}
+
+// a traceparent header of version 00, ids in lowercase hex
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceParent {
+    pub trace_id: String,
+    pub parent_id: String,
+    pub sampled: bool,
+}
+
+impl TraceParent {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().split('-').collect::<Vec<&str>>().as_slice() {
+            ["00", trace_id, parent_id, flags]
+                if is_id(trace_id, 32) && is_id(parent_id, 16) && flags.len() == 2 =>
+            {
+                let flags = u8::from_str_radix(flags, 16).ok()?;
+                Some(TraceParent {
+                    trace_id: trace_id.to_string(),
+                    parent_id: parent_id.to_string(),
+                    sampled: flags & 1 == 1,
+                })
+            }
+            _ => None,
+        }
+    }
+
+    pub fn header_value(&self) -> String {
+        format!(
+            "00-{}-{}-{}",
+            self.trace_id,
+            self.parent_id,
+            if self.sampled { "01" } else { "00" }
+        )
+    }
+}
+
+// hex of the given length that isn't all zeros, which is invalid
+fn is_id(value: &str, length: usize) -> bool {
+    value.len() == length
+        && value
+            .bytes()
+            .all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+        && value.bytes().any(|b| b != b'0')
+}
+
+fn random_id(bytes: usize) -> String {
+    let mut id = vec![0u8; bytes];
+    thread_rng().fill_bytes(&mut id);
+    HEXLOWER.encode(&id)
+}
+
+fn unix_nanos() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_nanos() as u64)
+        .unwrap_or(0)
+}
+
+struct Scope {
+    exporter: Arc<dyn SpanExporter>,
+    trace_id: String,
+    // innermost last
+    open: RefCell<Vec<String>>,
+}
+
+tokio::task_local! {
+    static SCOPE: Scope;
+}
+
+/*
+  An open span, exported when it is dropped. A child
+  span also closes in the scope on drop, so the spans
+  started after it go back to its parent.
+*/
+pub struct SpanGuard {
+    exporter: Arc<dyn SpanExporter>,
+    span: Span,
+    start: Instant,
+}
+
+impl SpanGuard {
+    fn new(
+        exporter: Arc<dyn SpanExporter>,
+        trace_id: String,
+        parent_span_id: Option<String>,
+        name: String,
+        kind: SpanKind,
+    ) -> Self {
+        SpanGuard {
+            exporter,
+            span: Span {
+                trace_id,
+                span_id: random_id(8),
+                parent_span_id,
+                name,
+                kind,
+                start_unix_nanos: unix_nanos(),
+                end_unix_nanos: 0,
+                attributes: vec![],
+                error: None,
+            },
+            start: Instant::now(),
+        }
+    }
+
+    pub fn attribute(&mut self, key: &str, value: impl ToString) {
+        self.span
+            .attributes
+            .push((key.to_string(), value.to_string()));
+    }
+
+    pub fn fail(&mut self, error: impl ToString) {
+        self.span.error = Some(error.to_string());
+    }
+
+    /*
+      Run fut with this span as the root of the scope,
+      for the server span of a request
+    */
+    pub async fn run<F: Future>(&self, fut: F) -> F::Output {
+        let scope = Scope {
+            exporter: self.exporter.clone(),
+            trace_id: self.span.trace_id.clone(),
+            open: RefCell::new(vec![self.span.span_id.clone()]),
+        };
+        SCOPE.scope(scope, fut).await
+    }
+}
+
+impl Drop for SpanGuard {
+    fn drop(&mut self) {
+        let span_id = &self.span.span_id;
+        let _ = SCOPE.try_with(|scope| scope.open.borrow_mut().retain(|id| id != span_id));
+        self.span.end_unix_nanos =
+            self.span.start_unix_nanos + self.start.elapsed().as_nanos() as u64;
+        self.exporter.export(self.span.clone());
+    }
+}
+
+/*
+  Starts the server spans of incoming requests, when
+  there is an exporter to send them to
+*/
+pub struct Tracer {
+    exporter: Option<Arc<dyn SpanExporter>>,
+    sample_ratio: f64,
+}
+
+impl Tracer {
+    pub fn new(exporter: Option<Arc<dyn SpanExporter>>, sample_ratio: f64) -> Self {
+        Tracer {
+            exporter,
+            sample_ratio,
+        }
+    }
+
+    /*
+      The server span of a request, continuing the trace of
+      the traceparent it was sent with. None when tracing
+      is off or the request isn't sampled.
+    */
+    pub fn request(&self, name: String, traceparent: Option<&str>) -> Option<SpanGuard> {
+        let exporter = self.exporter.clone()?;
+        let parent = traceparent.and_then(TraceParent::parse);
+        let sampled = match &parent {
+            Some(parent) => parent.sampled,
+            None => thread_rng().gen::<f64>() < self.sample_ratio,
+        };
+        if !sampled {
+            return None;
+        }
+        let (trace_id, parent_span_id) = match parent {
+            Some(parent) => (parent.trace_id, Some(parent.parent_id)),
+            None => (random_id(16), None),
+        };
+        Some(SpanGuard::new(
+            exporter,
+            trace_id,
+            parent_span_id,
+            name,
+            SpanKind::Server,
+        ))
+    }
+}
+
+// a child of the innermost open span, open until it is dropped
+pub fn span(name: &str, kind: SpanKind) -> Option<SpanGuard> {
+    SCOPE
+        .try_with(|scope| {
+            let mut open = scope.open.borrow_mut();
+            let guard = SpanGuard::new(
+                scope.exporter.clone(),
+                scope.trace_id.clone(),
+                open.last().cloned(),
+                name.to_string(),
+                kind,
+            );
+            open.push(guard.span.span_id.clone());
+            guard
+        })
+        .ok()
+}
+
+// a child of the innermost open span for a call that began at start and just returned
+pub fn record(name: &str, kind: SpanKind, start: Instant, attributes: &[(&str, &str)]) {
+    let _ = SCOPE.try_with(|scope| {
+        let elapsed = start.elapsed().as_nanos() as u64;
+        let end = unix_nanos();
+        scope.exporter.export(Span {
+            trace_id: scope.trace_id.clone(),
+            span_id: random_id(8),
+            parent_span_id: scope.open.borrow().last().cloned(),
+            name: name.to_string(),
+            kind,
+            start_unix_nanos: end.saturating_sub(elapsed),
+            end_unix_nanos: end,
+            attributes: attributes
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            error: None,
+        });
+    });
+}
+
+// the traceparent header for a call out made under the innermost open span
+pub fn traceparent() -> Option<String> {
+    SCOPE
+        .try_with(|scope| {
+            scope.open.borrow().last().map(|span_id| {
+                TraceParent {
+                    trace_id: scope.trace_id.clone(),
+                    parent_id: span_id.clone(),
+                    sampled: true,
+                }
+                .header_value()
+            })
+        })
+        .ok()
+        .flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    struct Collected(Mutex<Vec<Span>>);
+
+    impl SpanExporter for Collected {
+        fn export(&self, span: Span) {
+            self.0.lock().unwrap().push(span);
+        }
+    }
+
+    #[test]
+    fn test_traceparent() {
+        let header = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+        let parent = TraceParent::parse(header).unwrap();
+        assert_eq!(parent.trace_id, "4bf92f3577b34da6a3ce929d0e0e4736");
+        assert_eq!(parent.parent_id, "00f067aa0ba902b7");
+        assert!(parent.sampled);
+        assert_eq!(parent.header_value(), header);
+
+        assert!(
+            !TraceParent::parse("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")
+                .unwrap()
+                .sampled
+        );
+        assert_eq!(
+            TraceParent::parse("00-00000000000000000000000000000000-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(
+            TraceParent::parse("00-4BF92F3577B34DA6A3CE929D0E0E4736-00f067aa0ba902b7-01"),
+            None
+        );
+        assert_eq!(TraceParent::parse("garbage"), None);
+    }
+
+    #[tokio::test]
+    async fn test_spans_nest_under_the_request() {
+        let collected = Arc::new(Collected(Mutex::new(vec![])));
+        let tracer = Tracer::new(Some(collected.clone()), 1.0);
+
+        // nothing is traced outside a request
+        assert!(span("outside", SpanKind::Internal).is_none());
+        assert_eq!(traceparent(), None);
+
+        let root = tracer
+            .request(
+                "POST /".to_string(),
+                Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01"),
+            )
+            .unwrap();
+        let sent = root
+            .run(async {
+                let redirect = span("router redirect", SpanKind::Internal);
+                record("store get_process", SpanKind::Client, Instant::now(), &[]);
+                let sent = traceparent().unwrap();
+                drop(redirect);
+                record("store save_message", SpanKind::Client, Instant::now(), &[]);
+                sent
+            })
+            .await;
+        drop(root);
+
+        let spans = collected.0.lock().unwrap();
+        let find = |name: &str| spans.iter().find(|span| span.name == name).unwrap();
+        let (root, redirect) = (find("POST /"), find("router redirect"));
+        assert_eq!(root.parent_span_id.as_deref(), Some("00f067aa0ba902b7"));
+        assert!(spans
+            .iter()
+            .all(|span| span.trace_id == "4bf92f3577b34da6a3ce929d0e0e4736"));
+        assert_eq!(redirect.parent_span_id, Some(root.span_id.clone()));
+        assert_eq!(
+            find("store get_process").parent_span_id,
+            Some(redirect.span_id.clone())
+        );
+        // after the redirect span closed its sibling goes under the root
+        assert_eq!(
+            find("store save_message").parent_span_id,
+            Some(root.span_id.clone())
+        );
+        assert!(sent.contains(&redirect.span_id));
+
+        // an unsampled parent isn't traced
+        assert!(tracer
+            .request(
+                "GET /".to_string(),
+                Some("00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-00")
+            )
+            .is_none());
+    }
+}
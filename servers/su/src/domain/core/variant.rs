@@ -0,0 +1,102 @@
+use super::tags::Tag;
+
+/*
+  The ao protocol variants this su schedules. A process
+  is bound to the Variant tag it was spawned with, or
+  to ao.TN.1 when it has none, and its assignments are
+  generated for that variant for the rest of its life.
+  Behaviour that changes between variants is asked of
+  the process's variant instead of being hard coded in
+  the write path, so a new variant can be added without
+  changing how existing processes are scheduled.
+*/
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Variant {
+    TN1,
+}
+
+impl Variant {
+    // processes spawned before variants were tracked
+    pub const DEFAULT: Variant = Variant::TN1;
+
+    pub const SUPPORTED: [Variant; 1] = [Variant::TN1];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Variant::TN1 => "ao.TN.1",
+        }
+    }
+
+    pub fn parse(value: &str) -> Result<Variant, String> {
+        Variant::SUPPORTED
+            .iter()
+            .find(|variant| variant.as_str() == value)
+            .copied()
+            .ok_or(format!("Unsupported Variant {}", value))
+    }
+
+    // the Variant tag of a data item, None when it has none
+    pub fn from_tags(tags: &[Tag]) -> Result<Option<Variant>, String> {
+        match tags.iter().find(|tag| tag.name == "Variant") {
+            Some(tag) => Variant::parse(&tag.value).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /*
+      Whether a pushed message, one carrying a From-Process
+      tag, is refused when its deep hash was already
+      scheduled on the process
+    */
+    pub fn dedupes_pushed_messages(&self) -> bool {
+        match self {
+            Variant::TN1 => true,
+        }
+    }
+}
+
+/*
+  The variant of a process from its tags, used when a
+  Message or assignment is written to it. A Message may
+  leave its Variant tag off but may not name another one.
+*/
+pub fn check_message_variant(process_tags: &[Tag], message_tags: &[Tag]) -> Result<Variant, String> {
+    let process_variant = Variant::from_tags(process_tags)?.unwrap_or(Variant::DEFAULT);
+    match Variant::from_tags(message_tags) {
+        Ok(Some(variant)) if variant != process_variant => Err(format!(
+            "Message Variant {} does not match the process Variant {}",
+            variant.as_str(),
+            process_variant.as_str()
+        )),
+        Ok(_) => Ok(process_variant),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant_tag(value: &str) -> Vec<Tag> {
+        vec![Tag::new("Variant", value)]
+    }
+
+    #[test]
+    fn test_parse_variants() {
+        assert_eq!(Variant::parse("ao.TN.1"), Ok(Variant::TN1));
+        assert!(Variant::parse("ao.TN.2").is_err());
+        assert_eq!(Variant::from_tags(&[]), Ok(None));
+        assert_eq!(Variant::from_tags(&variant_tag("ao.TN.1")), Ok(Some(Variant::TN1)));
+    }
+
+    #[test]
+    fn test_message_variant_follows_process() {
+        assert_eq!(check_message_variant(&[], &[]), Ok(Variant::TN1));
+        assert_eq!(
+            check_message_variant(&variant_tag("ao.TN.1"), &variant_tag("ao.TN.1")),
+            Ok(Variant::TN1)
+        );
+        assert!(check_message_variant(&[], &variant_tag("ao.TN.2")).is_err());
+        assert!(check_message_variant(&variant_tag("ao.TN.9"), &[]).is_err());
+    }
+}
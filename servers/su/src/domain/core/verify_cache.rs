@@ -0,0 +1,88 @@
+use std::num::NonZeroUsize;
+use std::sync::Mutex;
+
+use bytes::Bytes;
+use lru::LruCache;
+use sha2::{Digest, Sha256};
+
+use super::bytes::{ByteErrorType, DataItem};
+
+/*
+  Remembers data items whose signature already verified
+  so a retried or duplicate submission skips the RSA or
+  ecdsa check. The key hashes the signed message, which
+  covers the signature type, owner, target, anchor, tags
+  and data, together with the signature, so an item only
+  hits the cache when it is byte for byte the one that
+  verified. The id alone is not enough, it is derived
+  from the signature and a forged item can reuse it.
+  Failed verifications are not remembered.
+*/
+pub struct VerifyCache {
+    verified: Option<Mutex<LruCache<[u8; 32], ()>>>,
+}
+
+impl VerifyCache {
+    // a size of 0 turns the cache off
+    pub fn new(size: usize) -> Self {
+        VerifyCache {
+            verified: NonZeroUsize::new(size).map(|size| Mutex::new(LruCache::new(size))),
+        }
+    }
+
+    pub fn verify(&self, item: &mut DataItem) -> Result<(), ByteErrorType> {
+        let message = item.get_message()?;
+        self.verify_message(item, &message)
+    }
+
+    // verifies a header only item against its data, held apart from it
+    pub fn verify_detached(&self, item: &DataItem, data: Bytes) -> Result<(), ByteErrorType> {
+        let message = item.message_with(data)?;
+        self.verify_message(item, &message)
+    }
+
+    fn verify_message(&self, item: &DataItem, message: &[u8]) -> Result<(), ByteErrorType> {
+        let verified = match &self.verified {
+            Some(verified) => verified,
+            None => return item.verify_message(message),
+        };
+
+        let key: [u8; 32] = Sha256::new()
+            .chain_update(message)
+            .chain_update(&item.signature)
+            .finalize()
+            .into();
+
+        if verified.lock().unwrap().get(&key).is_some() {
+            return Ok(());
+        }
+        item.verify_message(message)?;
+        verified.lock().unwrap().put(key, ());
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_failed_verification_is_not_cached() {
+        let cache = VerifyCache::new(10);
+        let mut item = DataItem::new(vec![], b"data".to_vec(), vec![], vec![1; 512]).unwrap();
+        item.signature = vec![2; 512];
+
+        assert!(cache.verify(&mut item).is_err());
+        assert!(cache.verify(&mut item).is_err());
+        assert_eq!(cache.verified.unwrap().lock().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_disabled_cache_still_verifies() {
+        let cache = VerifyCache::new(0);
+        let mut item = DataItem::new(vec![], b"data".to_vec(), vec![], vec![1; 512]).unwrap();
+        item.signature = vec![2; 512];
+
+        assert!(cache.verify(&mut item).is_err());
+    }
+}
@@ -1,23 +1,115 @@
-use std::sync::{Arc, Once};
+use std::env;
+use std::io::Write;
+use std::sync::{Arc, Once, OnceLock, RwLock};
 
-use env_logger::Env;
-use log::{error, info};
+use env_logger::{fmt::Formatter, Env};
+use log::{error, info, Level, Metadata, Record};
+use serde_json::{json, Map, Value};
 
+use crate::domain::core::dal::{LogFields, LogLevel};
 use crate::domain::Log;
 
-pub struct SuLog;
+/*
+  Logs go through env_logger so RUST_LOG still filters
+  them. With LOG_FORMAT=json every line, including those
+  from actix and other crates, is a json record with a
+  timestamp, level, module and message, plus the context
+  fields of events logged through SuLog::event.
+*/
+pub struct SuLog {
+    json: bool,
+}
 
 static INIT: Once = Once::new();
 
+/*
+  The env_logger every record goes through, rebuilt from
+  RUST_LOG when the configuration is reloaded
+*/
+struct ReloadableLogger {
+    inner: RwLock<env_logger::Logger>,
+}
+
+impl log::Log for ReloadableLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.inner.read().unwrap().enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        self.inner.read().unwrap().log(record)
+    }
+
+    fn flush(&self) {
+        self.inner.read().unwrap().flush()
+    }
+}
+
+static LOGGER: OnceLock<ReloadableLogger> = OnceLock::new();
+
+// target of records that already carry their json fields
+const EVENT_TARGET: &str = "su::event";
+
+fn build_logger(json: bool) -> env_logger::Logger {
+    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    if json {
+        builder.format(format_json);
+    }
+    builder.build()
+}
+
 impl SuLog {
     pub fn init() -> Arc<dyn Log> {
+        let json = env::var("LOG_FORMAT")
+            .map(|format| format == "json")
+            .unwrap_or(false);
         INIT.call_once(|| {
-            env_logger::init_from_env(Env::default().default_filter_or("info"));
+            let logger = build_logger(json);
+            let max_level = logger.filter();
+            let logger = LOGGER.get_or_init(|| ReloadableLogger {
+                inner: RwLock::new(logger),
+            });
+            if log::set_logger(logger).is_ok() {
+                log::set_max_level(max_level);
+            }
         });
-        Arc::new(SuLog {})
+        Arc::new(SuLog { json })
     }
 }
 
+fn format_json(buf: &mut Formatter, record: &Record) -> std::io::Result<()> {
+    let mut line = match record.target() {
+        EVENT_TARGET => match serde_json::from_str(&record.args().to_string()) {
+            Ok(Value::Object(fields)) => fields,
+            _ => Map::new(),
+        },
+        module => {
+            let mut fields = Map::new();
+            fields.insert("module".to_string(), json!(module));
+            fields.insert("message".to_string(), json!(record.args().to_string()));
+            fields
+        }
+    };
+    line.insert("timestamp".to_string(), json!(buf.timestamp_millis().to_string()));
+    line.insert("level".to_string(), json!(record.level().as_str()));
+    writeln!(buf, "{}", Value::Object(line))
+}
+
+fn event_json(module: &str, message: &str, fields: &LogFields) -> String {
+    let mut line = Map::new();
+    line.insert("module".to_string(), json!(module));
+    line.insert("message".to_string(), json!(message));
+    if let Some(process_id) = &fields.process_id {
+        line.insert("process_id".to_string(), json!(process_id));
+    }
+    if let Some(scheduler_url) = &fields.scheduler_url {
+        line.insert("scheduler_url".to_string(), json!(scheduler_url));
+    }
+    if let Some(latency_ms) = fields.latency_ms {
+        line.insert("latency_ms".to_string(), json!(latency_ms as u64));
+    }
+    Value::Object(line).to_string()
+}
+
 impl Log for SuLog {
     fn log(&self, message: String) {
         info!("{}", message);
@@ -26,4 +118,52 @@ impl Log for SuLog {
     fn error(&self, message: String) {
         error!("{}", message);
     }
+
+    fn event(&self, level: LogLevel, module: &str, message: String, fields: LogFields) {
+        let level = match level {
+            LogLevel::Debug => Level::Debug,
+            LogLevel::Info => Level::Info,
+            LogLevel::Warn => Level::Warn,
+            LogLevel::Error => Level::Error,
+        };
+        if self.json {
+            log::log!(target: EVENT_TARGET, level, "{}", event_json(module, &message, &fields));
+        } else {
+            let text = fields.to_text();
+            match text.is_empty() {
+                true => log::log!(target: module, level, "{}", message),
+                false => log::log!(target: module, level, "{} {}", message, text),
+            }
+        }
+    }
+
+    // LOG_FORMAT stays what it was started with
+    fn reload(&self) {
+        if let Some(logger) = LOGGER.get() {
+            let rebuilt = build_logger(self.json);
+            log::set_max_level(rebuilt.filter());
+            *logger.inner.write().unwrap() = rebuilt;
+        }
+    }
+
+    fn flush(&self) {
+        log::logger().flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn event_json_has_only_set_fields() {
+        let fields = LogFields::process("pid").latency(12);
+        let line: Value = serde_json::from_str(&event_json("flows", "saved", &fields)).unwrap();
+
+        assert_eq!(line["module"], "flows");
+        assert_eq!(line["message"], "saved");
+        assert_eq!(line["process_id"], "pid");
+        assert_eq!(line["latency_ms"], 12);
+        assert!(line.get("scheduler_url").is_none());
+    }
 }
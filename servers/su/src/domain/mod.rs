@@ -1,5 +1,6 @@
 use core::dal::RouterDataStore;
 use std::sync::Arc;
+use std::time::Duration;
 
 use tokio::task::spawn_blocking;
 
@@ -7,45 +8,282 @@ use dashmap::DashMap;
 
 mod clients;
 pub mod config;
+mod config_file;
 mod core;
 mod logger;
 
 use clients::{
-    gateway::ArweaveGateway, local_store, signer::ArweaveSigner, store, uploader::UploaderClient,
-    wallet::FileWallet, su_router::SuRouter
+    dual_store::DualWriteStore, gateway::ArweaveGateway, local_store, signer::ArweaveSigner, store,
+    metered_store::MeteredStore,
+    uploader::UploaderClient, wallet::FileWallet, su_router::SuRouter,
+    streamer::{NoopStreamer, StreamClient},
+    denylist::DenylistClient,
+    read_only::ReadOnlyClient,
+    raw_archive::{NoopRawArchive, RawArchiveClient},
+    standby::{NoopReplicator, StandbyClient},
+    memory_router_store::MemoryRouterStore,
+    router_stream::StreamingRouterStore,
+    spawn_audit::{NoopSpawnAudit, SpawnAuditLog},
+    api_keys::ApiKeyFile,
+    admin_audit::{AdminAuditFile, NoopAdminAudit},
+    deferred::{DeferredDir, NoopDeferredQueue},
+    redis_cache::{NoopSharedCache, RedisCache},
+    export_sink::{open_export_sink, S3Settings},
+    otlp::OtlpExporter,
 };
-use config::AoConfig;
-use core::dal::{Config, DataStore, Gateway, Log, MockRouterDataStore, ExtRouter};
+use config::{AoConfig, LiveConfig};
+use core::dal::{
+    AdminAudit, ApiKeys, Config, DataStore, DeferredQueue, Denylist, Gateway, Log, MockRouterDataStore, ExtRouter, RawArchive,
+    ReadOnlyProcesses, Replicator, SharedCache, SpanExporter, SpawnAudit, Streamer, Wallet,
+};
+use core::spawn_rollup::Retention;
 use logger::SuLog;
 
 pub use clients::metrics::PromMetrics;
 pub use core::flows;
+pub use core::api_error;
+pub use core::trace;
+pub use core::concurrency;
+pub use core::dal::Mode;
+pub use core::ip_access;
 pub use core::router;
+pub use core::scheduler_list;
+pub use core::router_state::StateFormat;
+pub use core::registration;
+pub use clients::conformance::run_conformance;
+pub use clients::dual_store::verify_dual_write;
 pub use flows::Deps;
 pub use local_store::migration::migrate_to_local;
 pub use local_store::sync_local::sync_local_drives;
 pub use store::migrate_to_disk;
 
+#[cfg(feature = "sqlite")]
+fn sqlite_router_store(config: &AoConfig) -> Arc<dyn RouterDataStore> {
+    Arc::new(
+        clients::sqlite_router_store::SqliteRouterStore::new(&config.router_sqlite_path)
+            .expect("Failed to open the sqlite router store"),
+    )
+}
+
+#[cfg(not(feature = "sqlite"))]
+fn sqlite_router_store(_config: &AoConfig) -> Arc<dyn RouterDataStore> {
+    panic!("ROUTER_STORE=sqlite needs the su built with the sqlite feature")
+}
+
+/*
+  The router store on its own for su router export and
+  import, which need none of the other deps. A memory
+  store starts empty and goes with the process so there
+  is nothing to move.
+*/
+fn open_router_store(config: &AoConfig) -> Result<Arc<dyn RouterDataStore>, String> {
+    match config.router_store.as_str() {
+        "memory" => Err("ROUTER_STORE=memory has no tables to export or import".to_string()),
+        "sqlite" => Ok(sqlite_router_store(config)),
+        _ if !config.use_local_store => {
+            let store = store::StoreClient::new()?;
+            store.run_migrations()?;
+            Ok(Arc::new(store))
+        }
+        _ => Err("The local store keeps no router tables, set ROUTER_STORE=sqlite".to_string()),
+    }
+}
+
+pub fn export_router_state(path: &str, format: StateFormat) -> Result<String, String> {
+    let config = AoConfig::new(Some("router".to_string()))?;
+    let store = open_router_store(&config)?;
+    core::router_state::export_router_state(store.as_ref(), path, format)
+}
+
+pub fn import_router_state(path: &str, format: StateFormat) -> Result<String, String> {
+    let config = AoConfig::new(Some("router".to_string()))?;
+    let store = open_router_store(&config)?;
+    core::router_state::import_router_state(store.as_ref(), path, format)
+}
+
+/*
+  su export-parquet reads the items from the data store on
+  its own. On postgres the reads go to DATABASE_READ_URL
+  when it is set, point it at a replica to keep the load
+  off the primary. The local store is opened read only,
+  next to the su running on it.
+*/
+pub async fn export_parquet(since: i64, until: i64, destination: &str) -> Result<String, String> {
+    let config = AoConfig::new(Some("su".to_string()))?;
+    let sink = open_export_sink(
+        destination,
+        S3Settings {
+            endpoint: config.aws_endpoint_url.clone(),
+            region: config.aws_region.clone(),
+            access_key_id: config.aws_access_key_id.clone(),
+            secret_access_key: config.aws_secret_access_key.clone(),
+            session_token: config.aws_session_token.clone(),
+        },
+    )?;
+    let store: Arc<dyn DataStore> = if config.use_local_store {
+        Arc::new(local_store::store::LocalStoreClient::new_read_only(
+            &config.su_file_db_dir,
+            &config.su_index_db_dir,
+        )?)
+    } else {
+        Arc::new(store::StoreClient::new()?)
+    };
+    core::analytics_export::export_parquet(store.as_ref(), sink.as_ref(), since, until).await
+}
+
+/*
+  su backfill-process-stats, recounts the process stats
+  of a data store that held processes before they were
+  kept. It writes to the store on its own, so run it
+  with the su stopped.
+*/
+pub async fn backfill_process_stats() -> Result<String, String> {
+    let config = AoConfig::new(Some("su".to_string()))?;
+    let counted = if config.use_local_store {
+        local_store::store::LocalStoreClient::new(&config.su_file_db_dir, &config.su_index_db_dir)?
+            .backfill_process_stats()
+            .await?
+    } else {
+        let store = store::StoreClient::new()?;
+        store.run_migrations()?;
+        store.backfill_process_stats()?
+    };
+    Ok(format!("Counted the stats of {} processes", counted))
+}
+
+/*
+  su check, for deploy pipelines. Reads the configuration
+  the server would start with and checks what it needs
+  is there without starting it: the data store answers,
+  the wallet parses, a router's scheduler list is valid
+  and the gateway and upload node can be reached. Nothing
+  is written, migrations are left to the server. Err
+  holds the report when any check failed.
+*/
+pub async fn check(mode: Option<String>) -> Result<String, String> {
+    let mut report = core::deploy_check::CheckReport::new();
+    let config = match AoConfig::new(mode) {
+        Ok(config) => config,
+        Err(e) => {
+            report.add("configuration", Err(e));
+            return Err(report.render());
+        }
+    };
+    let checked = match config.mode {
+        Mode::Router => router::check_config(&config),
+        Mode::Su => Ok(()),
+    };
+    report.add(
+        "configuration",
+        checked.map(|_| format!("mode {}", config.mode)),
+    );
+
+    report.add(
+        "database",
+        if config.use_local_store {
+            local_store::store::LocalStoreClient::new_read_only(
+                &config.su_file_db_dir,
+                &config.su_index_db_dir,
+            )
+            .map(|_| format!("local store at {} opened", config.su_file_db_dir))
+            .map_err(|e| format!("{:?}", e))
+        } else {
+            store::StoreClient::new()
+                .and_then(|store| store.get_conn().map(|_| ()))
+                .map(|_| "postgres connected".to_string())
+                .map_err(|e| format!("{:?}", e))
+        },
+    );
+
+    report.add(
+        "wallet",
+        ArweaveSigner::new(&config.su_wallet_path)
+            .and_then(|_| FileWallet.wallet_address())
+            .map(|address| format!("{} parsed, address {}", config.su_wallet_path, address)),
+    );
+
+    if config.mode == Mode::Router {
+        report.add(
+            "scheduler list",
+            std::fs::read_to_string(&config.scheduler_list_path)
+                .map_err(|e| format!("Failed to read {}: {}", config.scheduler_list_path, e))
+                .and_then(|contents| scheduler_list::parse_scheduler_list(&contents))
+                .map(|entries| format!("{} schedulers", entries.len())),
+        );
+    }
+
+    let gateway_info = format!("{}/info", config.arweave_url.trim_end_matches('/'));
+    report.add("gateway", reachable(&gateway_info).await);
+    report.add("upload node", reachable(&config.upload_node_url).await);
+
+    match report.passed() {
+        true => Ok(report.render()),
+        false => Err(report.render()),
+    }
+}
+
+// any answer short of a server error counts
+async fn reachable(url: &str) -> Result<String, String> {
+    let response = reqwest::Client::new()
+        .get(url)
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+        .map_err(|e| format!("{} unreachable: {}", url, e))?;
+    match response.status().is_server_error() {
+        true => Err(format!("{} answered {}", url, response.status())),
+        false => Ok(format!("{} answered {}", url, response.status())),
+    }
+}
+
 pub async fn init_deps(mode: Option<String>) -> (Arc<Deps>, Arc<PromMetrics>) {
+    init_deps_with(AoConfig::new(mode).expect("Failed to read configuration")).await
+}
+
+/*
+  Build the deps from a configuration the caller already
+  has instead of the environment, used by the embedded server
+*/
+pub async fn init_deps_with(config: AoConfig) -> (Arc<Deps>, Arc<PromMetrics>) {
     let logger: Arc<dyn Log> = SuLog::init();
 
-    let config = Arc::new(AoConfig::new(mode.clone()).expect("Failed to read configuration"));
+    let metrics = Arc::new(PromMetrics::new(config.clone()));
+
+    let config = Arc::new(config);
 
     let data_store = if !config.use_local_store {
-        let ds = Arc::new(store::StoreClient::new().expect("Failed to create StoreClient"));
+        let ds = Arc::new(
+            store::StoreClient::new()
+                .and_then(|ds| {
+                    ds.with_write_batch(
+                        config.write_batch_max,
+                        config.write_batch_max_wait,
+                        metrics.clone(),
+                    )
+                })
+                .expect("Failed to create StoreClient"),
+        );
         match ds.run_migrations() {
             Ok(m) => logger.log(m),
             Err(e) => logger.log(format!("{:?}", e)),
         }
+        match ds.check_schema(config.allow_newer_schema) {
+            Ok(m) => logger.log(m),
+            Err(e) => {
+                logger.error(format!("{:?}", e));
+                std::process::exit(1);
+            }
+        }
         Some(ds)
     } else {
         None
     };
 
-    let router_data_store: Arc<dyn RouterDataStore> = if !config.use_local_store {
-        data_store.clone().unwrap().clone()
-    } else {
-        Arc::new(MockRouterDataStore {}) as Arc<dyn RouterDataStore>
+    let router_data_store: Arc<dyn RouterDataStore> = match config.router_store.as_str() {
+        "memory" => Arc::new(MemoryRouterStore::new()),
+        "sqlite" => sqlite_router_store(&config),
+        _ if !config.use_local_store => data_store.clone().unwrap().clone(),
+        _ => Arc::new(MockRouterDataStore {}) as Arc<dyn RouterDataStore>,
     };
 
     let main_data_store: Arc<dyn DataStore> = if config.use_local_store {
@@ -56,11 +294,27 @@ pub async fn init_deps(mode: Option<String>) -> (Arc<Deps>, Arc<PromMetrics>) {
             )
             .expect("Failed to create LocalStoreClient"),
         ) as Arc<dyn DataStore>
+    } else if config.dual_write {
+        /*
+          Moving off postgres, keep it as the source of
+          truth and mirror every write into the local store
+        */
+        Arc::new(DualWriteStore::new(
+            data_store.clone().unwrap().clone(),
+            Arc::new(
+                local_store::store::LocalStoreClient::new(
+                    &config.su_file_db_dir,
+                    &config.su_index_db_dir,
+                )
+                .expect("Failed to create LocalStoreClient"),
+            ),
+            logger.clone(),
+        )) as Arc<dyn DataStore>
     } else {
         data_store.clone().unwrap().clone()
     };
 
-    if config.use_disk && config.mode != "router" {
+    if config.use_disk && config.mode != Mode::Router {
         let logger_clone = logger.clone();
         let d_clone = data_store.clone().unwrap().clone();
         /*
@@ -77,6 +331,31 @@ pub async fn init_deps(mode: Option<String>) -> (Arc<Deps>, Arc<PromMetrics>) {
         });
     }
 
+    let router_role = Arc::new(core::router::RouterRole::new(
+        config.mode == Mode::Router && config.router_standby,
+    ));
+    let router_data_store: Arc<dyn RouterDataStore> =
+        if config.mode != Mode::Router || config.router_standby_url.is_empty() {
+            router_data_store
+        } else {
+            Arc::new(
+                StreamingRouterStore::new(
+                    router_data_store,
+                    router_role.clone(),
+                    &config.router_standby_url,
+                    &config.router_standby_token,
+                    logger.clone(),
+                )
+                .expect("Invalid router standby configuration"),
+            )
+        };
+
+    // every store call is timed, the scheduler's included
+    let main_data_store: Arc<dyn DataStore> =
+        Arc::new(MeteredStore::new(main_data_store, metrics.clone()));
+    let router_data_store: Arc<dyn RouterDataStore> =
+        Arc::new(MeteredStore::new(router_data_store, metrics.clone()));
+
     let scheduler_deps = Arc::new(core::scheduler::SchedulerDeps {
         data_store: main_data_store.clone(),
         logger: logger.clone(),
@@ -95,24 +374,185 @@ pub async fn init_deps(mode: Option<String>) -> (Arc<Deps>, Arc<PromMetrics>) {
     let wallet = Arc::new(FileWallet);
 
     let uploader = Arc::new(
-        UploaderClient::new(&config.upload_node_url, logger.clone()).expect("Invalid uploader url"),
+        UploaderClient::new(&config.upload_node_url, logger.clone(), metrics.clone())
+            .expect("Invalid uploader url"),
     );
 
-    let metrics = Arc::new(PromMetrics::new(
-        AoConfig::new(mode).expect("Failed to read configuration"),
-    ));
+    let streamer: Arc<dyn Streamer> = if config.stream_url.is_empty() {
+        Arc::new(NoopStreamer)
+    } else {
+        Arc::new(
+            StreamClient::new(
+                &config.stream_url,
+                &config.stream_topic,
+                &config.stream_spool_dir,
+                logger.clone(),
+            )
+            .expect("Invalid stream configuration"),
+        )
+    };
+
+    let denylist: Arc<dyn Denylist> = Arc::new(
+        DenylistClient::new(
+            &config.denylist_url,
+            &config.denylist_owner,
+            config.denylist_refresh_interval,
+            &config.denylist_path,
+            logger.clone(),
+        )
+        .expect("Invalid denylist configuration"),
+    );
+
+    let read_only: Arc<dyn ReadOnlyProcesses> = Arc::new(
+        ReadOnlyClient::new(&config.read_only_path).expect("Invalid read only configuration"),
+    );
+
+    let raw_archive: Arc<dyn RawArchive> = if config.raw_archive_dir.is_empty() {
+        Arc::new(NoopRawArchive)
+    } else {
+        Arc::new(
+            RawArchiveClient::new(
+                &config.raw_archive_dir,
+                config.raw_retention_days,
+                logger.clone(),
+            )
+            .expect("Invalid raw archive configuration"),
+        )
+    };
+
     let metrics_clone = metrics.clone();
 
+    let replicator: Arc<dyn Replicator> = if config.standby_url.is_empty() {
+        Arc::new(NoopReplicator)
+    } else {
+        Arc::new(
+            StandbyClient::new(
+                &config.standby_url,
+                &config.standby_token,
+                config.standby_sync,
+                logger.clone(),
+            )
+            .expect("Invalid standby configuration"),
+        )
+    };
+
+    let memory = Arc::new(core::memory::MemoryGuard::new(
+        config.memory_soft_limit,
+        config.memory_hard_limit,
+    ));
+
+    let verify_cache = Arc::new(core::verify_cache::VerifyCache::new(
+        config.signature_cache_size,
+    ));
+
+    let owner_cache = Arc::new(core::owner_cache::OwnerCache::new(config.owner_cache_size));
+
+    let rate_limits = Arc::new(core::rate_limit::RateLimits::new(
+        config.wallet_spawns_per_hour,
+        config.wallet_messages_per_minute,
+        config.anonymous_reads_per_minute,
+        config.api_key_reads_per_minute,
+    ));
+
+    let concurrency = Arc::new(core::concurrency::ConcurrencyLimits::new(
+        config.write_concurrency,
+        config.read_concurrency,
+        config.admin_concurrency,
+        config.concurrency_queue_size,
+    ));
+
+    let api_keys: Arc<dyn ApiKeys> = Arc::new(
+        ApiKeyFile::new(&config.api_keys_path).expect("Invalid api keys file"),
+    );
+
+    /*
+      The admin audit log goes to the database unless a
+      file is configured, an su on the local store without
+      one doesn't keep it
+    */
+    let admin_audit: Arc<dyn AdminAudit> = match (&data_store, config.admin_audit_path.as_str()) {
+        (_, path) if !path.is_empty() => {
+            Arc::new(AdminAuditFile::new(path).expect("Failed to open the admin audit log"))
+        }
+        (Some(ds), _) => ds.clone(),
+        (None, _) => Arc::new(NoopAdminAudit),
+    };
+
     let deephash_locks = Arc::new(DashMap::new());
+    let scheduler_failures = Arc::new(DashMap::new());
+    // only a router redirects, a su would hold an empty cache
+    let route_cache_size = match config.mode {
+        Mode::Router => config.route_cache_size,
+        Mode::Su => 0,
+    };
+    let shared_cache: Arc<dyn SharedCache> =
+        if config.mode != Mode::Router || config.router_redis_url.is_empty() {
+            Arc::new(NoopSharedCache)
+        } else {
+            Arc::new(
+                RedisCache::new(&config.router_redis_url, logger.clone())
+                    .expect("Invalid redis configuration"),
+            )
+        };
+    let route_cache = Arc::new(
+        core::router::RouteCache::new(
+            route_cache_size,
+            Duration::from_secs(config.route_cache_ttl),
+        )
+        .with_shared(shared_cache.clone()),
+    );
+    let shared_health = Arc::new(core::router::SharedHealth::new(shared_cache.clone()));
 
     let ext_router: Arc<dyn ExtRouter>  = Arc::new(SuRouter{});
 
+    let spawn_audit: Arc<dyn SpawnAudit> =
+        if config.mode != Mode::Router || config.router_audit_log.is_empty() {
+            Arc::new(NoopSpawnAudit)
+        } else {
+            Arc::new(
+                SpawnAuditLog::new(
+                    &config.router_audit_log,
+                    Retention {
+                        raw_hours: config.router_audit_raw_hours,
+                        hourly_days: config.router_audit_hourly_days,
+                        retention_days: config.router_audit_retention_days,
+                    },
+                    logger.clone(),
+                )
+                .expect("Failed to open the router audit log"),
+            )
+        };
+
+    // a router forwards Schedule-At messages like any other
+    let deferred: Arc<dyn DeferredQueue> =
+        if config.mode == Mode::Router || config.deferred_dir.is_empty() {
+            Arc::new(NoopDeferredQueue)
+        } else {
+            Arc::new(
+                DeferredDir::new(&config.deferred_dir)
+                    .expect("Failed to open the deferred message directory"),
+            )
+        };
+
+    let span_exporter: Option<Arc<dyn SpanExporter>> = match config.otel_endpoint.as_str() {
+        "" => None,
+        endpoint => Some(Arc::new(OtlpExporter::new(
+            endpoint,
+            &config.otel_service_name,
+            logger.clone(),
+        ))),
+    };
+    let tracer = Arc::new(core::trace::Tracer::new(
+        span_exporter,
+        config.otel_sample_ratio,
+    ));
+
     (
         Arc::new(Deps {
             data_store: main_data_store,
             router_data_store,
             logger,
-            config,
+            config: Arc::new(LiveConfig::new(config)),
             scheduler,
             gateway,
             signer,
@@ -120,7 +560,31 @@ pub async fn init_deps(mode: Option<String>) -> (Arc<Deps>, Arc<PromMetrics>) {
             uploader,
             metrics,
             deephash_locks,
-            ext_router
+            scheduler_failures,
+            route_cache,
+            shared_cache,
+            shared_health,
+            signing_health: Arc::new(core::signing_check::SigningHealth::new()),
+            tracer,
+            router_health: Arc::new(core::router::RouterHealth::new()),
+            router_role,
+            ext_router,
+            streamer,
+            denylist,
+            read_only,
+            raw_archive,
+            replicator,
+            memory,
+            verify_cache,
+            owner_cache,
+            spawn_audit,
+            rate_limits,
+            concurrency,
+            api_keys,
+            subscriptions: Arc::new(core::subscriptions::Subscriptions::new()),
+            registration: Arc::new(core::registration::Registration::new()),
+            admin_audit,
+            deferred,
         }),
         metrics_clone,
     )
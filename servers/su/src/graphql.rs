@@ -0,0 +1,251 @@
+use std::sync::Arc;
+
+use async_graphql::{
+    Context, EmptyMutation, EmptySubscription, Enum, InputObject, Object, Result, Schema,
+    SimpleObject, ID,
+};
+
+use crate::domain::flows::{self, QueriedTransaction, TagQuery, TransactionQuery};
+use crate::domain::Deps;
+
+/*
+  A read only subset of the arweave gateway graphql
+  schema, transaction and transactions, answered from the
+  messages scheduled on this su. Queries written for a
+  gateway work here as long as they filter by ids or
+  recipients, the processes the messages were sent to.
+*/
+pub type SuSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn schema(deps: Arc<Deps>) -> SuSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(deps)
+        .limit_depth(10)
+        .limit_complexity(500)
+        .finish()
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum SortOrder {
+    #[graphql(name = "HEIGHT_ASC")]
+    HeightAsc,
+    #[graphql(name = "HEIGHT_DESC")]
+    HeightDesc,
+}
+
+#[derive(Enum, Copy, Clone, Eq, PartialEq)]
+pub enum TagOperator {
+    #[graphql(name = "EQ")]
+    Eq,
+    #[graphql(name = "NEQ")]
+    Neq,
+}
+
+#[derive(InputObject)]
+pub struct TagFilter {
+    name: String,
+    values: Vec<String>,
+    #[graphql(default_with = "TagOperator::Eq")]
+    op: TagOperator,
+}
+
+#[derive(SimpleObject)]
+pub struct Owner {
+    address: String,
+    key: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Amount {
+    winston: String,
+    ar: String,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "MetaData")]
+pub struct MetaData {
+    size: String,
+    #[graphql(name = "type")]
+    content_type: Option<String>,
+}
+
+#[derive(SimpleObject)]
+pub struct Tag {
+    name: String,
+    value: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Block {
+    id: String,
+    height: i64,
+    timestamp: i64,
+    previous: String,
+}
+
+#[derive(SimpleObject)]
+pub struct Transaction {
+    id: ID,
+    anchor: String,
+    signature: String,
+    recipient: String,
+    owner: Owner,
+    fee: Amount,
+    quantity: Amount,
+    data: MetaData,
+    tags: Vec<Tag>,
+    block: Option<Block>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "PageInfo")]
+pub struct PageInfo {
+    has_next_page: bool,
+    end_cursor: Option<String>,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "TransactionEdge")]
+pub struct TransactionEdge {
+    cursor: String,
+    node: Transaction,
+}
+
+#[derive(SimpleObject)]
+#[graphql(name = "TransactionConnection")]
+pub struct TransactionConnection {
+    page_info: PageInfo,
+    edges: Vec<TransactionEdge>,
+}
+
+// messages carry no fee or quantity, they are zero like a bundled data item
+fn zero() -> Amount {
+    Amount {
+        winston: "0".to_string(),
+        ar: "0.000000000000".to_string(),
+    }
+}
+
+impl From<QueriedTransaction> for TransactionEdge {
+    fn from(tx: QueriedTransaction) -> Self {
+        // messages aren't mined themselves, the block is the one they were assigned at
+        let block = match (tx.block_height, tx.timestamp) {
+            (Some(height), Some(timestamp)) => Some(Block {
+                id: String::new(),
+                height,
+                timestamp,
+                previous: String::new(),
+            }),
+            _ => None,
+        };
+        TransactionEdge {
+            cursor: tx.cursor,
+            node: Transaction {
+                id: ID(tx.id),
+                anchor: tx.anchor,
+                signature: tx.signature,
+                recipient: tx.recipient,
+                owner: Owner {
+                    address: tx.owner_address,
+                    key: tx.owner_key,
+                },
+                fee: zero(),
+                quantity: zero(),
+                data: MetaData {
+                    size: tx.data_size.to_string(),
+                    content_type: tx.content_type,
+                },
+                tags: tx
+                    .tags
+                    .into_iter()
+                    .map(|(name, value)| Tag { name, value })
+                    .collect(),
+                block,
+            },
+        }
+    }
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    async fn transaction(&self, ctx: &Context<'_>, id: ID) -> Result<Option<Transaction>> {
+        let deps = ctx.data::<Arc<Deps>>()?.clone();
+        let query = TransactionQuery {
+            ids: Some(vec![id.to_string()]),
+            first: Some(1),
+            ..Default::default()
+        };
+        let page = flows::query_transactions(deps, query).await?;
+        Ok(page
+            .edges
+            .into_iter()
+            .next()
+            .map(|tx| TransactionEdge::from(tx).node))
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn transactions(
+        &self,
+        ctx: &Context<'_>,
+        ids: Option<Vec<ID>>,
+        owners: Option<Vec<String>>,
+        recipients: Option<Vec<String>>,
+        tags: Option<Vec<TagFilter>>,
+        first: Option<i32>,
+        after: Option<String>,
+        #[graphql(default_with = "SortOrder::HeightDesc")] sort: SortOrder,
+    ) -> Result<TransactionConnection> {
+        let deps = ctx.data::<Arc<Deps>>()?.clone();
+        let query = TransactionQuery {
+            ids: ids.map(|ids| ids.into_iter().map(|id| id.to_string()).collect()),
+            owners,
+            recipients,
+            tags: tags
+                .unwrap_or_default()
+                .into_iter()
+                .map(|tag| TagQuery {
+                    name: tag.name,
+                    values: tag.values,
+                    negate: tag.op == TagOperator::Neq,
+                })
+                .collect(),
+            first: first.map(|first| first.max(0) as usize),
+            after,
+            descending: sort == SortOrder::HeightDesc,
+        };
+        let page = flows::query_transactions(deps, query).await?;
+        Ok(TransactionConnection {
+            page_info: PageInfo {
+                has_next_page: page.has_next_page,
+                end_cursor: page.end_cursor,
+            },
+            edges: page.edges.into_iter().map(TransactionEdge::from).collect(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schema_uses_gateway_names() {
+        let sdl = Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+            .finish()
+            .sdl();
+        for name in [
+            "transactions(",
+            "pageInfo: PageInfo!",
+            "hasNextPage: Boolean!",
+            "endCursor: String",
+            "edges: [TransactionEdge!]!",
+            "HEIGHT_DESC",
+            "NEQ",
+            "type MetaData",
+        ] {
+            assert!(sdl.contains(name), "{} missing from\n{}", name, sdl);
+        }
+    }
+}
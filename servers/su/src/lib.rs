@@ -1 +1,12 @@
 pub mod domain;
+
+// the http server, for binaries and programs embedding the su
+#[cfg(feature = "server")]
+pub mod server;
+
+#[cfg(feature = "server")]
+pub use server::Server;
+
+// the gateway style graphql schema served at /graphql
+#[cfg(feature = "server")]
+pub mod graphql;
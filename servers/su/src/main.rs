@@ -1,295 +1,35 @@
 use std::env;
+use std::fs;
 use std::io::{self, Error, ErrorKind};
-use std::sync::Arc;
-use std::time::{SystemTime, UNIX_EPOCH};
 
-use actix_cors::Cors;
-use actix_web::{
-    http::header::LOCATION, middleware::Logger, web, App, HttpRequest, HttpResponse, HttpServer,
-    Responder,
-};
+use su::domain::{self, router, scheduler_list, StateFormat};
+use su::Server;
 
-use serde::Deserialize;
-use serde_json::json;
-
-use su::domain::{flows, init_deps, router, Deps, PromMetrics};
-
-#[derive(Deserialize)]
-struct FromTo {
-    from: Option<String>,
-    to: Option<String>,
-    limit: Option<i32>,
-    #[serde(rename = "process-id")]
-    process_id: Option<String>,
-    #[serde(rename = "from-nonce")]
-    from_nonce: Option<String>,
-    #[serde(rename = "to-nonce")]
-    to_nonce: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct TxId {
-    tx_id: String,
-}
-
-#[derive(Deserialize)]
-struct ProcessId {
-    #[serde(rename = "process-id")]
-    process_id: Option<String>,
-}
-
-#[derive(Deserialize)]
-struct ProcessIdRequired {
-    process_id: String,
-}
-
-#[derive(Deserialize)]
-struct OptionalAssign {
-    #[serde(rename = "process-id")]
-    process_id: Option<String>,
-    assign: Option<String>,
-    // base-layer is either present or not, it has no value
-    #[serde(rename = "base-layer")]
-    base_layer: Option<String>,
-    exclude: Option<String>,
-}
-
-fn err_response(err: String) -> HttpResponse {
-    let error_json = json!({ "error": err });
-    HttpResponse::BadRequest()
-        .content_type("application/json")
-        .body(error_json.to_string())
-}
-
-async fn base(
-    data: web::Data<AppState>,
-    query_params: web::Query<ProcessId>,
-    req: HttpRequest,
-) -> impl Responder {
-    let process_id = query_params.process_id.clone();
-
-    match router::redirect_process_id(data.deps.clone(), process_id).await {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
-        }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
-    }
-
-    match flows::health(data.deps.clone()).await {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
-    }
-}
-
-async fn timestamp_route(
-    data: web::Data<AppState>,
-    query_params: web::Query<ProcessId>,
-    req: HttpRequest,
-) -> impl Responder {
-    let process_id = query_params.process_id.clone();
+#[actix_web::main]
+async fn main() -> io::Result<()> {
+    let args: Vec<String> = env::args().collect();
 
-    match router::redirect_process_id(data.deps.clone(), process_id).await {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
-        }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
+    if args.get(1).map(String::as_str) == Some("simulate-routing") {
+        return simulate_routing(&args);
     }
-
-    match flows::timestamp(data.deps.clone()).await {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
+    if args.get(1).map(String::as_str) == Some("migrate-scheduler-list") {
+        return migrate_scheduler_list(&args);
     }
-}
-
-async fn main_post_route(
-    data: web::Data<AppState>,
-    req_body: web::Bytes,
-    req: HttpRequest,
-    query_params: web::Query<OptionalAssign>,
-) -> impl Responder {
-    let current_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs();
-
-    if current_time < data.startup_time + data.deps.config.warmup_delay() {
-        return HttpResponse::ServiceUnavailable()
-            .json(json!({"error": "Server is warming up. Please try again later."}));
+    if args.get(1).map(String::as_str) == Some("export-parquet") {
+        return export_parquet(&args).await;
     }
-    match router::redirect_data_item(
-        data.deps.clone(),
-        req_body.to_vec(),
-        query_params.process_id.clone(),
-        query_params.assign.clone(),
-    )
-    .await
-    {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
-        }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
+    if args.get(1).map(String::as_str) == Some("backfill-process-stats") {
+        return backfill_process_stats().await;
     }
-
-    match flows::write_item(
-        data.deps.clone(),
-        req_body.to_vec(),
-        query_params.process_id.clone(),
-        query_params.assign.clone(),
-        query_params.base_layer.clone(),
-        query_params.exclude.clone(),
-    )
-    .await
-    {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
+    if args.get(1).map(String::as_str) == Some("check") {
+        return check(&args).await;
     }
-}
-
-async fn main_get_route(
-    data: web::Data<AppState>,
-    req: HttpRequest,
-    path: web::Path<TxId>,
-    query_params: web::Query<FromTo>,
-) -> impl Responder {
-    let tx_id = path.tx_id.clone();
-    let from = query_params.from.clone();
-    let to = query_params.to.clone();
-    let limit = query_params.limit.clone();
-    let process_id = query_params.process_id.clone();
-    let from_nonce = query_params.from_nonce.clone();
-    let to_nonce = query_params.to_nonce.clone();
-
-    match router::redirect_tx_id(data.deps.clone(), tx_id.clone(), process_id.clone()).await {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
+    if args.get(1).map(String::as_str) == Some("router") {
+        if let Some(command @ ("export" | "import")) = args.get(2).map(String::as_str) {
+            return router_state(command, &args);
         }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
     }
-
-    let result = flows::read_message_data(
-        data.deps.clone(),
-        tx_id,
-        from,
-        to,
-        limit,
-        from_nonce,
-        to_nonce,
-    )
-    .await;
-
-    match result {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
-    }
-}
-
-async fn read_latest_route(
-    data: web::Data<AppState>,
-    req: HttpRequest,
-    path: web::Path<ProcessIdRequired>,
-) -> impl Responder {
-    let process_id = path.process_id.clone();
-
-    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
-        }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
-    }
-
-    let result = flows::read_latest_message(data.deps.clone(), process_id).await;
-
-    match result {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
-    }
-}
-
-async fn read_process_route(
-    data: web::Data<AppState>,
-    req: HttpRequest,
-    path: web::Path<ProcessIdRequired>,
-) -> impl Responder {
-    let process_id = path.process_id.clone();
-
-    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
-        Ok(Some(redirect_url)) => {
-            let target_url = format!("{}{}", redirect_url, req.uri());
-            return HttpResponse::TemporaryRedirect()
-                .insert_header((LOCATION, target_url))
-                .finish();
-        }
-        Ok(None) => (),
-        Err(err) => return err_response(err.to_string()),
-    }
-
-    match flows::read_process(data.deps.clone(), process_id).await {
-        Ok(processed_str) => HttpResponse::Ok()
-            .content_type("application/json")
-            .body(processed_str),
-        Err(err) => err_response(err.to_string()),
-    }
-}
-
-async fn health_check() -> impl Responder {
-    HttpResponse::Ok()
-}
-
-async fn metrics_route(data: web::Data<AppState>) -> impl Responder {
-    let result = data.metrics.emit_metrics();
-    match result {
-        Ok(metrics_str) => HttpResponse::Ok()
-            .content_type("application/openmetrics-text; version=1.0.0; charset=utf-8")
-            .body(metrics_str),
-        Err(err) => HttpResponse::BadRequest()
-            .content_type("text/plain")
-            .body(err),
-    }
-}
-
-struct AppState {
-    deps: Arc<Deps>,
-    metrics: Arc<PromMetrics>,
-    startup_time: u64,
-}
-
-#[actix_web::main]
-async fn main() -> io::Result<()> {
-    let args: Vec<String> = env::args().collect();
-    let mode = match args.get(1) {
-        Some(m) => Some(m.clone()),
-        None => None,
-    };
+    let mode = args.get(1).cloned();
 
     let port = match args.get(2) {
         Some(port_str) => match port_str.parse::<u16>() {
@@ -305,48 +45,134 @@ async fn main() -> io::Result<()> {
         }
     };
 
-    let startup_time = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .expect("Time went backwards")
-        .as_secs();
-
-    let (deps, metrics) = init_deps(mode).await;
-    let app_state = web::Data::new(AppState {
-        deps,
-        metrics,
-        startup_time,
-    });
+    Server::builder()
+        .mode(mode)
+        .port(port)
+        .start()
+        .await?
+        .wait()
+        .await
+}
+
+/*
+    su simulate-routing <audit-log> <scheduler-list> [strategy] [large-process-threshold]
+*/
+fn simulate_routing(args: &[String]) -> io::Result<()> {
+    let (audit_log, scheduler_list) = match (args.get(2), args.get(3)) {
+        (Some(audit_log), Some(scheduler_list)) => (audit_log, scheduler_list),
+        _ => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Usage: su simulate-routing <audit-log> <scheduler-list> [strategy] [large-process-threshold]",
+            ))
+        }
+    };
+    let strategy = args.get(4).map(String::as_str).unwrap_or("least-count");
+    let threshold = match args.get(5).map(|value| value.parse::<usize>()) {
+        Some(Ok(threshold)) => threshold,
+        Some(Err(_)) => {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "Large process threshold is not valid",
+            ))
+        }
+        None => 0,
+    };
 
-    let run_deps = app_state.deps.clone();
+    let report = router::simulate_routing(audit_log, scheduler_list, strategy, threshold)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    println!("{}", report);
+    Ok(())
+}
+
+/*
+    su migrate-scheduler-list <scheduler-list>
+*/
+fn migrate_scheduler_list(args: &[String]) -> io::Result<()> {
+    let path = args.get(2).ok_or_else(|| {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Usage: su migrate-scheduler-list <scheduler-list>",
+        )
+    })?;
+    let contents = fs::read_to_string(path)?;
+    let migrated = scheduler_list::migrate_scheduler_list(&contents)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
+    println!("{}", migrated);
+    Ok(())
+}
+
+/*
+    su router export <path> [json|csv]
+    su router import <path> [json|csv]
+*/
+fn router_state(command: &str, args: &[String]) -> io::Result<()> {
+    let usage = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            format!("Usage: su router {} <path> [json|csv]", command),
+        )
+    };
+    let path = args.get(3).ok_or_else(usage)?;
+    let format = StateFormat::parse(args.get(4).map(String::as_str).unwrap_or("json"))
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?;
 
-    if run_deps.config.mode() == "router" {
-        match router::init_schedulers(run_deps.clone()).await {
-            Err(e) => run_deps.logger.log(format!("{}", e)),
-            Ok(m) => run_deps.logger.log(format!("{}", m)),
-        };
+    let report = match command {
+        "export" => domain::export_router_state(path, format),
+        _ => domain::import_router_state(path, format),
     }
+    .map_err(Error::other)?;
+    println!("{}", report);
+    Ok(())
+}
+
+/*
+    su check [su|router]
+*/
+async fn check(args: &[String]) -> io::Result<()> {
+    match domain::check(args.get(2).cloned()).await {
+        Ok(report) => {
+            println!("{}", report);
+            Ok(())
+        }
+        Err(report) => {
+            println!("{}", report);
+            std::process::exit(1)
+        }
+    }
+}
 
-    HttpServer::new(move || {
-        App::new()
-            .wrap(
-                Cors::default()
-                    .allow_any_origin()
-                    .allow_any_method()
-                    .allow_any_header(),
-            )
-            .wrap(Logger::default())
-            .app_data(app_state.clone())
-            .app_data(web::PayloadConfig::new(10485760))
-            .route("/", web::get().to(base))
-            .route("/", web::post().to(main_post_route))
-            .route("/timestamp", web::get().to(timestamp_route))
-            .route("/health", web::get().to(health_check))
-            .route("/metrics", web::get().to(metrics_route))
-            .route("/{tx_id}", web::get().to(main_get_route))
-            .route("/processes/{process_id}", web::get().to(read_process_route))
-            .route("/{process_id}/latest", web::get().to(read_latest_route))
-    })
-    .bind(("0.0.0.0", port))?
-    .run()
-    .await
+/*
+    su backfill-process-stats
+*/
+async fn backfill_process_stats() -> io::Result<()> {
+    let report = domain::backfill_process_stats()
+        .await
+        .map_err(Error::other)?;
+    println!("{}", report);
+    Ok(())
+}
+
+/*
+    su export-parquet <since> <until> <directory|s3://bucket/prefix>
+*/
+async fn export_parquet(args: &[String]) -> io::Result<()> {
+    let usage = || {
+        Error::new(
+            ErrorKind::InvalidInput,
+            "Usage: su export-parquet <since> <until> <directory|s3://bucket/prefix>",
+        )
+    };
+    let (since, until, destination) = match (args.get(2), args.get(3), args.get(4)) {
+        (Some(since), Some(until), Some(destination)) => (since, until, destination),
+        _ => return Err(usage()),
+    };
+    let since = since.parse::<i64>().map_err(|_| usage())?;
+    let until = until.parse::<i64>().map_err(|_| usage())?;
+
+    let report = domain::export_parquet(since, until, destination)
+        .await
+        .map_err(Error::other)?;
+    println!("{}", report);
+    Ok(())
 }
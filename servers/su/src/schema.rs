@@ -1,5 +1,53 @@
 // @generated automatically by Diesel CLI.
 
+diesel::table! {
+    admin_audit (seq) {
+        seq -> Int8,
+        timestamp -> Int8,
+        actor -> Text,
+        client -> Text,
+        #[max_length = 255]
+        action -> Varchar,
+        target -> Text,
+        before_value -> Nullable<Jsonb>,
+        after_value -> Nullable<Jsonb>,
+        #[max_length = 255]
+        prev_hash -> Varchar,
+        #[max_length = 255]
+        hash -> Varchar,
+    }
+}
+
+diesel::table! {
+    chain_snapshots (row_id) {
+        row_id -> Int4,
+        #[max_length = 255]
+        process_id -> Varchar,
+        epoch -> Int4,
+        nonce -> Int4,
+        timestamp -> Int8,
+        hash_chain -> Text,
+        #[max_length = 255]
+        assignment_id -> Varchar,
+        signature -> Text,
+    }
+}
+
+diesel::table! {
+    inclusion_proofs (row_id) {
+        row_id -> Int4,
+        #[max_length = 255]
+        item_id -> Varchar,
+        #[max_length = 255]
+        bundle_id -> Varchar,
+        #[max_length = 255]
+        root -> Varchar,
+        leaf_index -> Int4,
+        leaf_count -> Int4,
+        path -> Text,
+    }
+}
+
 diesel::table! {
     messages (row_id) {
         row_id -> Int4,
@@ -18,6 +66,14 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    owner_schedulers (owner) {
+        #[max_length = 255]
+        owner -> Varchar,
+        scheduler_row_id -> Int4,
+    }
+}
+
 diesel::table! {
     process_schedulers (row_id) {
         row_id -> Int4,
@@ -26,6 +82,16 @@ diesel::table! {
     }
 }
 
+diesel::table! {
+    process_stats (process_id) {
+        #[max_length = 255]
+        process_id -> Varchar,
+        message_count -> Int8,
+        byte_count -> Int8,
+        last_nonce -> Nullable<Int4>,
+    }
+}
+
 diesel::table! {
     processes (row_id) {
         row_id -> Int4,
@@ -48,14 +114,28 @@ diesel::table! {
         no_route -> Nullable<Bool>,
         wallets_to_route -> Nullable<Text>,
         wallets_only -> Nullable<Bool>,
+        priority -> Nullable<Int4>,
+        large_objects -> Nullable<Bool>,
+        weight -> Nullable<Int4>,
+        public_url -> Nullable<Varchar>,
+        drain -> Nullable<Bool>,
+        max_processes -> Nullable<Int4>,
+        region -> Nullable<Varchar>,
+        tags_to_route -> Nullable<Varchar>,
+        wallets_policy -> Nullable<Varchar>,
     }
 }
 
 diesel::joinable!(process_schedulers -> schedulers (scheduler_row_id));
 
 diesel::allow_tables_to_appear_in_same_query!(
+    admin_audit,
+    chain_snapshots,
+    inclusion_proofs,
     messages,
+    owner_schedulers,
     process_schedulers,
+    process_stats,
     processes,
     schedulers,
 );
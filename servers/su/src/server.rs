@@ -0,0 +1,2223 @@
+use std::io::{self, Error, ErrorKind};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use actix_cors::Cors;
+use actix_web::{
+    dev::{ServerHandle, Service, ServiceResponse},
+    http::{
+        header::{ALLOW, AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE, IF_MATCH, LOCATION},
+        Method, StatusCode,
+    },
+    middleware::Logger,
+    web, App, HttpRequest, HttpResponse, HttpServer, Responder,
+};
+use futures::StreamExt;
+use tokio::task::JoinHandle;
+
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::domain::config::AoConfig;
+use crate::domain::{
+    api_error::{self, ErrorCode},
+    concurrency::EndpointClass,
+    flows, init_deps_with,
+    ip_access::IP_FORBIDDEN,
+    registration, router,
+    router::{RouteDecision, SpawnQuery},
+    trace, Deps, Mode, PromMetrics,
+};
+use crate::graphql::{self, SuSchema};
+
+#[derive(Deserialize)]
+struct FromTo {
+    from: Option<String>,
+    to: Option<String>,
+    limit: Option<i32>,
+    #[serde(rename = "process-id")]
+    process_id: Option<String>,
+    #[serde(rename = "from-nonce")]
+    from_nonce: Option<String>,
+    #[serde(rename = "to-nonce")]
+    to_nonce: Option<String>,
+    // Name:Value pairs separated by commas
+    tags: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TxId {
+    tx_id: String,
+}
+
+#[derive(Deserialize)]
+struct ReplicaParams {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(rename = "deep-hash")]
+    deep_hash: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DecisionQuery {
+    #[serde(rename = "process-id")]
+    process_id: Option<String>,
+    owner: Option<String>,
+    scheduler: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ReconcileParams {
+    #[serde(rename = "dry-run")]
+    dry_run: Option<bool>,
+}
+
+// the scheduler a process is migrated to, a url or row id
+#[derive(Deserialize)]
+struct MigrateTarget {
+    to: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProcessIdPage {
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ProcessListing {
+    scheduler: Option<String>,
+    #[serde(rename = "spawned-after")]
+    spawned_after: Option<i64>,
+    after: Option<String>,
+    limit: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ExportRange {
+    from: Option<String>,
+    limit: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct ExportSince {
+    since: Option<i64>,
+    cursor: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AuditRange {
+    from: Option<i64>,
+    limit: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct SnapshotAt {
+    nonce: Option<i32>,
+}
+
+#[derive(Deserialize)]
+struct SchedulerId {
+    scheduler_id: i32,
+}
+
+#[derive(Deserialize)]
+struct Evacuate {
+    evacuate: Option<bool>,
+}
+
+#[derive(Deserialize)]
+struct KeyId {
+    key_id: String,
+}
+
+#[derive(Deserialize)]
+struct ProcessId {
+    #[serde(rename = "process-id")]
+    process_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ProcessIdRequired {
+    process_id: String,
+}
+
+#[derive(Deserialize)]
+struct OptionalAssign {
+    #[serde(rename = "process-id")]
+    process_id: Option<String>,
+    assign: Option<String>,
+    // base-layer is either present or not, it has no value
+    #[serde(rename = "base-layer")]
+    base_layer: Option<String>,
+    exclude: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ForceAssign {
+    #[serde(rename = "process-id")]
+    process_id: String,
+    assign: Option<String>,
+    #[serde(rename = "base-layer")]
+    base_layer: Option<String>,
+    exclude: Option<String>,
+    nonce: i32,
+    timestamp: Option<i64>,
+}
+
+#[derive(Deserialize)]
+struct ReplayRange {
+    #[serde(rename = "from-nonce")]
+    from_nonce: Option<i32>,
+    #[serde(rename = "to-nonce")]
+    to_nonce: Option<i32>,
+    #[serde(rename = "chunk-size")]
+    chunk_size: Option<i32>,
+    workers: Option<usize>,
+}
+
+/*
+    Every error a client gets is the json of api_error,
+    with the status and Retry-After of its code
+*/
+fn error_response(code: ErrorCode, err: String, details: serde_json::Value) -> HttpResponse {
+    let status = StatusCode::from_u16(code.status()).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR);
+    let mut response = HttpResponse::build(status);
+    if let Some(seconds) = code.retry_after() {
+        response.insert_header(("Retry-After", seconds.to_string()));
+    }
+    response.json(api_error::error_body(code, &err, details))
+}
+
+// an error from the flows or the router, coded by its message
+fn err_response(err: String) -> HttpResponse {
+    error_response(api_error::classify(&err), err, json!({}))
+}
+
+// a write refused before it is read, overloaded unless the error says otherwise
+fn admit_refused(err: String) -> HttpResponse {
+    match api_error::classify(&err) {
+        ErrorCode::BadRequest => error_response(ErrorCode::Overloaded, err, json!({})),
+        code => error_response(code, err, json!({})),
+    }
+}
+
+fn warming_up() -> HttpResponse {
+    error_response(
+        ErrorCode::WarmingUp,
+        "Server is warming up. Please try again later.".to_string(),
+        json!({}),
+    )
+}
+
+// a query, path or json body the route can't parse
+fn extractor_error(err: impl std::fmt::Display) -> actix_web::Error {
+    let message = err.to_string();
+    actix_web::error::InternalError::from_response(
+        message.clone(),
+        error_response(ErrorCode::BadRequest, message, json!({})),
+    )
+    .into()
+}
+
+/*
+    Read a data item body of at most limit bytes. A larger
+    one is refused with a 413 from its Content-Length, or
+    once the bytes received pass the limit when it has
+    none, so it is never buffered whole.
+*/
+async fn read_item_body(
+    mut payload: web::Payload,
+    req: &HttpRequest,
+    limit: usize,
+) -> Result<web::Bytes, HttpResponse> {
+    let too_large = |size: Option<usize>| {
+        error_response(
+            ErrorCode::DataItemTooLarge,
+            flows::data_item_too_large(limit, size),
+            json!({ "limit": limit, "size": size }),
+        )
+    };
+    let length = req
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+    if let Some(length) = length.filter(|length| *length > limit) {
+        return Err(too_large(Some(length)));
+    }
+
+    let mut body = web::BytesMut::with_capacity(length.unwrap_or(0));
+    while let Some(chunk) = payload.next().await {
+        let chunk = chunk.map_err(|e| err_response(format!("Failed to read body: {}", e)))?;
+        if body.len() + chunk.len() > limit {
+            return Err(too_large(None));
+        }
+        body.extend_from_slice(&chunk);
+    }
+    Ok(body.freeze())
+}
+
+/*
+    Redirect to the scheduler the router picked, optionally
+    telling the client why it was picked
+*/
+fn redirect_response(
+    data: &web::Data<AppState>,
+    decision: RouteDecision,
+    req: &HttpRequest,
+) -> HttpResponse {
+    data.deps
+        .metrics
+        .redirect_served(&decision.url, decision.rule.as_str());
+    let target_url = format!("{}{}", decision.url, req.uri());
+    let mut response = HttpResponse::TemporaryRedirect();
+    response.insert_header((LOCATION, target_url));
+    if data.deps.config.enable_router_decision_header() {
+        response.insert_header(("x-su-router", decision.header_value()));
+    }
+    response.finish()
+}
+
+/*
+    Send the client on to the scheduler the router picked,
+    forwarding the request to it instead when proxying.
+    body is the request body the route already read, GET
+    routes have none.
+*/
+async fn route_response(
+    data: &web::Data<AppState>,
+    decision: RouteDecision,
+    req: &HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    match &data.proxy {
+        Some(client) => proxy_response(data, client, decision, req, body).await,
+        None => redirect_response(data, decision, req),
+    }
+}
+
+// connection specific, never forwarded in either direction
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+const PROXY_CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/*
+    The client a proxying router forwards requests with,
+    None when the router redirects. reqwest keeps a pool of
+    idle connections per host, so every scheduler gets its
+    own. There is no overall timeout, a subscription
+    stream stays open as long as the scheduler keeps it.
+*/
+fn proxy_client(config: &AoConfig) -> io::Result<Option<reqwest::Client>> {
+    if config.mode != Mode::Router || !config.router_proxy {
+        return Ok(None);
+    }
+    reqwest::Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .pool_max_idle_per_host(config.router_proxy_pool_size)
+        .connect_timeout(PROXY_CONNECT_TIMEOUT)
+        .build()
+        .map(Some)
+        .map_err(|e| Error::new(ErrorKind::InvalidInput, e))
+}
+
+/*
+    Forward the request to the scheduler's internal url
+    and relay its response, the response body is passed
+    on chunk by chunk as the scheduler sends it
+*/
+async fn proxy_response(
+    data: &web::Data<AppState>,
+    client: &reqwest::Client,
+    decision: RouteDecision,
+    req: &HttpRequest,
+    body: web::Bytes,
+) -> HttpResponse {
+    data.deps
+        .metrics
+        .redirect_served(&decision.url, decision.rule.as_str());
+    let target_url = format!("{}{}", decision.upstream, req.uri());
+    let method = match reqwest::Method::from_bytes(req.method().as_str().as_bytes()) {
+        Ok(method) => method,
+        Err(e) => return err_response(e.to_string()),
+    };
+
+    // until the scheduler's response headers arrive
+    let mut span = trace::span("proxy", trace::SpanKind::Client);
+    if let Some(span) = &mut span {
+        span.attribute("server.address", &decision.url);
+    }
+    let traceparent = trace::traceparent();
+
+    let mut request = client.request(method, &target_url);
+    for (name, value) in req.headers() {
+        // a traced request continues with the proxy span as the parent
+        if name.as_str() == "traceparent" && traceparent.is_some() {
+            continue;
+        }
+        if name.as_str() != "host" && !HOP_BY_HOP.contains(&name.as_str()) {
+            request = request.header(name.as_str(), value.as_bytes());
+        }
+    }
+    if let Some(traceparent) = traceparent {
+        request = request.header("traceparent", traceparent);
+    }
+    if let Some(peer) = req.peer_addr() {
+        request = request.header("x-forwarded-for", peer.ip().to_string());
+    }
+
+    let response = match request.body(body).send().await {
+        Ok(response) => response,
+        Err(e) => {
+            if let Some(span) = &mut span {
+                span.fail(&e);
+            }
+            return error_response(
+                ErrorCode::SchedulerUnreachable,
+                format!("Scheduler {} did not answer: {}", decision.url, e),
+                json!({ "scheduler": decision.url }),
+            );
+        }
+    };
+
+    drop(span);
+
+    let status =
+        StatusCode::from_u16(response.status().as_u16()).unwrap_or(StatusCode::BAD_GATEWAY);
+    let mut relayed = HttpResponse::build(status);
+    for (name, value) in response.headers() {
+        // the body is streamed, actix sets its own framing
+        if name.as_str() != "content-length" && !HOP_BY_HOP.contains(&name.as_str()) {
+            relayed.append_header((name.as_str(), value.as_bytes()));
+        }
+    }
+    if data.deps.config.enable_router_decision_header() {
+        relayed.insert_header(("x-su-router", decision.header_value()));
+    }
+
+    // stops after the first error, the client sees the body cut short
+    let chunks = futures::stream::unfold(Some(response), |response| async move {
+        let mut response = response?;
+        match response.chunk().await {
+            Ok(Some(chunk)) => Some((Ok(chunk), Some(response))),
+            Ok(None) => None,
+            Err(e) => Some((Err(Error::other(e)), None)),
+        }
+    });
+    relayed.streaming(chunks)
+}
+
+async fn base(
+    data: web::Data<AppState>,
+    query_params: web::Query<ProcessId>,
+    req: HttpRequest,
+) -> impl Responder {
+    let process_id = query_params.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), process_id).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::health(data.deps.clone()).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn info_route(data: web::Data<AppState>) -> impl Responder {
+    match flows::info(data.deps.clone()).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn timestamp_route(
+    data: web::Data<AppState>,
+    query_params: web::Query<ProcessId>,
+    req: HttpRequest,
+) -> impl Responder {
+    let process_id = query_params.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), process_id).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::timestamp(data.deps.clone()).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn main_post_route(
+    data: web::Data<AppState>,
+    payload: web::Payload,
+    req: HttpRequest,
+    query_params: web::Query<OptionalAssign>,
+) -> impl Responder {
+    let current_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    if current_time < data.startup_time + data.deps.config.warmup_delay() {
+        return warming_up();
+    }
+
+    let req_body = match read_item_body(payload, &req, data.deps.config.max_data_item_size()).await
+    {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+    // where a spawn prefers to be placed, from ROUTER_REGION_HEADER
+    let region_header = data.deps.config.router_region_header();
+    let region = match region_header.is_empty() || !from_trusted_proxy(&data.deps, &req) {
+        true => None,
+        false => req
+            .headers()
+            .get(region_header.as_str())
+            .and_then(|value| value.to_str().ok())
+            .map(|value| value.to_string()),
+    };
+    match router::redirect_data_item(
+        data.deps.clone(),
+        req_body.clone(),
+        query_params.process_id.clone(),
+        query_params.assign.clone(),
+        region,
+    )
+    .await
+    {
+        Ok(Some(decision)) => return route_response(&data, decision, &req, req_body).await,
+        Ok(None) => (),
+        Err(err) => return err_response(err),
+    }
+
+    let _in_flight = match flows::admit_write(&data.deps, req_body.len()) {
+        Ok(in_flight) => in_flight,
+        Err(err) => return admit_refused(err),
+    };
+
+    let if_match = req
+        .headers()
+        .get(IF_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.to_string());
+
+    match flows::write_item(
+        data.deps.clone(),
+        req_body.to_vec(),
+        query_params.process_id.clone(),
+        query_params.assign.clone(),
+        query_params.base_layer.clone(),
+        query_params.exclude.clone(),
+        if_match,
+    )
+    .await
+    {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err),
+    }
+}
+
+async fn batch_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    let current_time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs();
+
+    if current_time < data.startup_time + data.deps.config.warmup_delay() {
+        return warming_up();
+    }
+
+    let ndjson = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.contains("ndjson"))
+        .unwrap_or(false);
+    let items = match flows::split_batch(&req_body, ndjson) {
+        Ok(items) => items,
+        Err(err) => return err_response(err),
+    };
+
+    match router::redirect_batch(data.deps.clone(), &items).await {
+        Ok(Some(decision)) => return route_response(&data, decision, &req, req_body).await,
+        Ok(None) => (),
+        Err(err) => return err_response(err),
+    }
+
+    let _in_flight = match flows::admit_write(&data.deps, req_body.len()) {
+        Ok(in_flight) => in_flight,
+        Err(err) => return admit_refused(err),
+    };
+
+    match flows::write_batch(data.deps.clone(), items).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err),
+    }
+}
+
+/*
+    Check the bearer token on an admin request, returns
+    the response to send when it is missing or wrong
+*/
+fn admin_unauthorized(data: &web::Data<AppState>, req: &HttpRequest) -> Option<HttpResponse> {
+    let token = req
+        .headers()
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|value| value.to_string());
+    match flows::authorize_admin(&data.deps, token) {
+        Ok(()) => None,
+        Err(err) => Some(error_response(ErrorCode::Unauthorized, err, json!({}))),
+    }
+}
+
+/*
+    Who an admin request is made by for the audit log,
+    the X-Admin-Actor it declares and the client address
+*/
+fn admin_actor(data: &web::Data<AppState>, req: &HttpRequest) -> flows::AdminActor {
+    let name = req
+        .headers()
+        .get("X-Admin-Actor")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("admin");
+    flows::AdminActor {
+        name: name.to_string(),
+        client: client_address(&data.deps, req),
+    }
+}
+
+/*
+    The address of the client that sent a request. With
+    TRUSTED_PROXIES set it is read back through the
+    X-Forwarded-For of those proxies only. Without it
+    TRUST_FORWARDED_FOR believes the header from anyone,
+    otherwise it is the address that connected.
+*/
+fn client_address(deps: &Arc<Deps>, req: &HttpRequest) -> String {
+    let access = deps.config.ip_access();
+    if !access.trusted_proxies.is_empty() {
+        if let Some(peer) = req.peer_addr() {
+            let forwarded_for: Vec<&str> = req
+                .headers()
+                .get_all("x-forwarded-for")
+                .filter_map(|value| value.to_str().ok())
+                .collect();
+            return access.client(peer.ip(), &forwarded_for).to_string();
+        }
+    }
+    let info = req.connection_info();
+    let client = match deps.config.trust_forwarded_for() {
+        true => info.realip_remote_addr(),
+        false => info.peer_addr(),
+    };
+    client.unwrap_or("").to_string()
+}
+
+/*
+    Check the client address against the rules of the
+    endpoint class, returns the response to send when it
+    is refused. An address that doesn't parse only gets
+    through a class without an allow list.
+*/
+fn ip_refused(deps: &Arc<Deps>, req: &HttpRequest, class: EndpointClass) -> Option<HttpResponse> {
+    let access = deps.config.ip_access();
+    let rules = access.rules(class);
+    if rules.is_empty() {
+        return None;
+    }
+    let client = client_address(deps, req);
+    let admitted = match client.parse::<IpAddr>() {
+        Ok(ip) => rules.admits(&ip),
+        Err(_) => rules.allow.is_empty(),
+    };
+    match admitted {
+        true => None,
+        false => Some(error_response(
+            ErrorCode::Forbidden,
+            format!("{} on {}: {}", IP_FORBIDDEN, class.as_str(), client),
+            json!({}),
+        )),
+    }
+}
+
+/*
+    Whether headers a proxy in front sets, like the
+    region of ROUTER_REGION_HEADER, can be believed. With
+    TRUSTED_PROXIES set only when one of them connected.
+*/
+fn from_trusted_proxy(deps: &Arc<Deps>, req: &HttpRequest) -> bool {
+    let access = deps.config.ip_access();
+    access.trusted_proxies.is_empty()
+        || req
+            .peer_addr()
+            .is_some_and(|peer| access.trusts(&peer.ip()))
+}
+
+/*
+    Count a read against the X-Api-Key it was sent with
+    or the client's address, returns the response to send
+    when the key is unknown or the limit is reached
+*/
+fn read_limited(data: &web::Data<AppState>, req: &HttpRequest) -> Option<HttpResponse> {
+    let api_key = req
+        .headers()
+        .get("X-Api-Key")
+        .and_then(|value| value.to_str().ok());
+    let client = client_address(&data.deps, req);
+    match flows::check_read_limit(&data.deps, api_key, &client) {
+        Ok(()) => None,
+        Err(err) => Some(err_response(err)),
+    }
+}
+
+async fn force_assign_route(
+    data: web::Data<AppState>,
+    payload: web::Payload,
+    req: HttpRequest,
+    query_params: web::Query<ForceAssign>,
+) -> impl Responder {
+    match router::redirect_process_id(data.deps.clone(), Some(query_params.process_id.clone()))
+        .await
+    {
+        Ok(Some(decision)) => {
+            // the scheduler checks the token, a proxied request takes its item along
+            let body = match data.proxy {
+                Some(_) => {
+                    let limit = data.deps.config.max_data_item_size();
+                    match read_item_body(payload, &req, limit).await {
+                        Ok(body) => body,
+                        Err(response) => return response,
+                    }
+                }
+                None => web::Bytes::new(),
+            };
+            return route_response(&data, decision, &req, body).await;
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    let req_body = match read_item_body(payload, &req, data.deps.config.max_data_item_size()).await
+    {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let params = query_params.into_inner();
+    match flows::force_assignment(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        req_body.to_vec(),
+        flows::ForcedAssignment {
+            process_id: params.process_id,
+            assign: params.assign,
+            base_layer: params.base_layer,
+            exclude: params.exclude,
+            nonce: params.nonce,
+            timestamp: params.timestamp,
+        },
+    )
+    .await
+    {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn read_raw_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<TxId>,
+    query_params: web::Query<ProcessId>,
+) -> impl Responder {
+    let tx_id = path.tx_id.clone();
+    let process_id = query_params.process_id.clone();
+
+    match router::redirect_tx_id(data.deps.clone(), tx_id.clone(), process_id).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    match flows::read_raw_item(data.deps.clone(), tx_id) {
+        Ok(raw) => HttpResponse::Ok()
+            .content_type("application/octet-stream")
+            .body(raw),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn routing_invariants_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    admin_json_response(router::check_routing_invariants(data.deps.clone()).await)
+}
+
+async fn routing_decisions_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<DecisionQuery>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    let query = query_params.into_inner();
+    admin_json_response(router::routing_decisions(
+        data.deps.clone(),
+        SpawnQuery {
+            process_id: query.process_id,
+            owner: query.owner,
+            scheduler: query.scheduler,
+            limit: query.limit.unwrap_or(0),
+        },
+    ))
+}
+
+async fn reconcile_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<ReconcileParams>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    admin_json_response(
+        router::reconcile_process_schedulers(
+            data.deps.clone(),
+            &admin_actor(&data, &req),
+            query_params.dry_run.unwrap_or(false),
+        )
+        .await,
+    )
+}
+
+async fn replica_route(
+    data: web::Data<AppState>,
+    payload: web::Payload,
+    req: HttpRequest,
+    query_params: web::Query<ReplicaParams>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    // a replica is the posted item plus its assignment, allow room for both
+    let limit = data.deps.config.max_data_item_size() * 2;
+    let req_body = match read_item_body(payload, &req, limit).await {
+        Ok(body) => body,
+        Err(response) => return response,
+    };
+
+    let params = query_params.into_inner();
+    admin_json_response(
+        flows::apply_replica(
+            data.deps.clone(),
+            params.kind,
+            params.deep_hash,
+            req_body.to_vec(),
+        )
+        .await,
+    )
+}
+
+async fn export_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+    query_params: web::Query<ExportRange>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    let range = query_params.into_inner();
+    admin_json_response(
+        flows::export_process(
+            data.deps.clone(),
+            path.process_id.clone(),
+            range.from,
+            range.limit,
+        )
+        .await,
+    )
+}
+
+async fn list_process_ids_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<ProcessIdPage>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    let page = query_params.into_inner();
+    admin_json_response(flows::list_process_ids(data.deps.clone(), page.after, page.limit).await)
+}
+
+async fn export_items_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<ExportSince>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+
+    let params = query_params.into_inner();
+    match flows::export_items(
+        data.deps.clone(),
+        params.since,
+        params.cursor,
+        params.format,
+    )
+    .await
+    {
+        Ok(items) => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(items),
+        Err(err) => err_response(err),
+    }
+}
+
+fn admin_json_response(result: Result<String, String>) -> HttpResponse {
+    match result {
+        Ok(body) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(body),
+        Err(err) => err_response(err),
+    }
+}
+
+async fn list_schedulers_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(router::list_schedulers(data.deps.clone()).await)
+}
+
+async fn add_scheduler_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(
+        router::add_scheduler(
+            data.deps.clone(),
+            &admin_actor(&data, &req),
+            req_body.to_vec(),
+        )
+        .await,
+    )
+}
+
+async fn update_scheduler_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+    path: web::Path<SchedulerId>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(
+        router::update_scheduler(
+            data.deps.clone(),
+            &admin_actor(&data, &req),
+            path.scheduler_id,
+            req_body.to_vec(),
+        )
+        .await,
+    )
+}
+
+async fn remove_scheduler_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<SchedulerId>,
+    query_params: web::Query<Evacuate>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(
+        router::remove_scheduler(
+            data.deps.clone(),
+            &admin_actor(&data, &req),
+            path.scheduler_id,
+            query_params.evacuate.unwrap_or(false),
+        )
+        .await,
+    )
+}
+
+async fn router_changes_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(router::apply_router_changes(data.deps.clone(), req_body.to_vec()).await)
+}
+
+async fn promote_router_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(router::promote_router(data.deps.clone(), &admin_actor(&data, &req)).await)
+}
+
+async fn migrate_process_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+    query_params: web::Query<MigrateTarget>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    let to = match query_params.into_inner().to {
+        Some(to) => to,
+        None => return err_response("to is required, a scheduler url or id".to_string()),
+    };
+    admin_json_response(
+        router::migrate_process(
+            data.deps.clone(),
+            &admin_actor(&data, &req),
+            path.into_inner().process_id,
+            to,
+        )
+        .await,
+    )
+}
+
+async fn mark_read_only_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+    query_params: web::Query<MigrateTarget>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::mark_read_only(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        path.into_inner().process_id,
+        query_params.into_inner().to.unwrap_or_default(),
+    ))
+}
+
+async fn clear_read_only_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::clear_read_only(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        path.into_inner().process_id,
+    ))
+}
+
+async fn list_denylist_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::list_denylist(data.deps.clone()))
+}
+
+async fn add_denylist_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::add_denylist(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        req_body.to_vec(),
+    ))
+}
+
+async fn remove_denylist_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::remove_denylist(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        req_body.to_vec(),
+    ))
+}
+
+async fn list_api_keys_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::list_api_keys(data.deps.clone()))
+}
+
+async fn issue_api_key_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::issue_api_key(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        req_body.to_vec(),
+    ))
+}
+
+async fn revoke_api_key_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<KeyId>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::revoke_api_key(
+        data.deps.clone(),
+        &admin_actor(&data, &req),
+        path.key_id.clone(),
+    ))
+}
+
+async fn reload_config_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(flows::reload_config(
+        data.deps.clone(),
+        Some(&admin_actor(&data, &req)),
+    ))
+}
+
+async fn registration_route(data: web::Data<AppState>, req: HttpRequest) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    admin_json_response(Ok(registration::registration_status(&data.deps)))
+}
+
+async fn admin_audit_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<AuditRange>,
+) -> impl Responder {
+    if let Some(unauthorized) = admin_unauthorized(&data, &req) {
+        return unauthorized;
+    }
+    let range = query_params.into_inner();
+    admin_json_response(flows::read_admin_audit(
+        data.deps.clone(),
+        range.from,
+        range.limit,
+    ))
+}
+
+async fn main_get_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<TxId>,
+    query_params: web::Query<FromTo>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let tx_id = path.tx_id.clone();
+    let from = query_params.from.clone();
+    let to = query_params.to.clone();
+    let limit = query_params.limit;
+    let process_id = query_params.process_id.clone();
+    let from_nonce = query_params.from_nonce.clone();
+    let to_nonce = query_params.to_nonce.clone();
+    let tags = query_params.tags.clone();
+
+    match router::redirect_tx_id(data.deps.clone(), tx_id.clone(), process_id.clone()).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    let result = flows::read_message_data(
+        data.deps.clone(),
+        tx_id,
+        from,
+        to,
+        limit,
+        from_nonce,
+        to_nonce,
+        tags,
+    )
+    .await;
+
+    match result {
+        Ok(processed_str) => signed_read_response(&data, processed_str).await,
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+/*
+    The 200 of a message or process read, with its body
+    signed in x-su-signature and x-su-signer when
+    SIGN_READ_RESPONSES is on
+*/
+async fn signed_read_response(data: &web::Data<AppState>, body: String) -> HttpResponse {
+    let signature = match flows::sign_response(&data.deps, &body).await {
+        Ok(signature) => signature,
+        Err(err) => return error_response(ErrorCode::Internal, err, json!({})),
+    };
+    let mut response = HttpResponse::Ok();
+    response.content_type("application/json");
+    if let Some(signature) = signature {
+        response.insert_header(("x-su-signature", signature.signature));
+        response.insert_header(("x-su-signer", signature.signer));
+    }
+    response.body(body)
+}
+
+async fn read_proof_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<TxId>,
+    query_params: web::Query<ProcessId>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let tx_id = path.tx_id.clone();
+    let process_id = query_params.process_id.clone();
+
+    match router::redirect_tx_id(data.deps.clone(), tx_id.clone(), process_id).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::read_inclusion_proof(data.deps.clone(), tx_id).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn read_latest_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    let result = flows::read_latest_message(data.deps.clone(), process_id).await;
+
+    match result {
+        Ok(processed_str) => signed_read_response(&data, processed_str).await,
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn read_process_stats_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::read_process_stats(data.deps.clone(), process_id).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn list_processes_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    query_params: web::Query<ProcessListing>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let listing = query_params.into_inner();
+    match flows::list_processes(
+        data.deps.clone(),
+        listing.scheduler,
+        listing.spawned_after,
+        listing.after,
+        listing.limit,
+    )
+    .await
+    {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err),
+    }
+}
+
+async fn read_processes_stats_route(
+    data: web::Data<AppState>,
+    req_body: web::Bytes,
+    req: HttpRequest,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_ids = match flows::parse_stats_request(&req_body) {
+        Ok(process_ids) => process_ids,
+        Err(err) => return err_response(err),
+    };
+
+    match router::redirect_process_ids(data.deps.clone(), &process_ids).await {
+        Ok(Some(decision)) => return route_response(&data, decision, &req, req_body).await,
+        Ok(None) => (),
+        Err(err) => return err_response(err),
+    }
+
+    match flows::read_processes_stats(data.deps.clone(), process_ids).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err),
+    }
+}
+
+async fn read_chain_snapshot_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+    query_params: web::Query<SnapshotAt>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::read_chain_snapshot(data.deps.clone(), process_id, query_params.nonce).await {
+        Ok(processed_str) => HttpResponse::Ok()
+            .content_type("application/json")
+            .body(processed_str),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn replay_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+    query_params: web::Query<ReplayRange>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    let result = flows::replay_messages(
+        data.deps.clone(),
+        process_id,
+        query_params.from_nonce,
+        query_params.to_nonce,
+        query_params.chunk_size,
+        query_params.workers,
+    )
+    .await;
+
+    match result {
+        Ok(replay) => HttpResponse::Ok()
+            .content_type("application/x-ndjson")
+            .streaming(replay),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn subscribe_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::subscribe_messages(data.deps.clone(), process_id).await {
+        Ok(events) => HttpResponse::Ok()
+            .content_type("text/event-stream")
+            .insert_header(("Cache-Control", "no-cache"))
+            .insert_header(("X-Accel-Buffering", "no"))
+            .streaming(events),
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+async fn graphql_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    req_body: web::Json<async_graphql::Request>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    /*
+      Errors are part of the graphql response, like a
+      gateway it is a 200 unless the request couldn't be
+      read at all
+    */
+    let response = data.graphql.execute(req_body.into_inner()).await;
+    HttpResponse::Ok().json(response)
+}
+
+async fn read_process_route(
+    data: web::Data<AppState>,
+    req: HttpRequest,
+    path: web::Path<ProcessIdRequired>,
+) -> impl Responder {
+    if let Some(limited) = read_limited(&data, &req) {
+        return limited;
+    }
+
+    let process_id = path.process_id.clone();
+
+    match router::redirect_process_id(data.deps.clone(), Some(process_id.clone())).await {
+        Ok(Some(decision)) => {
+            return route_response(&data, decision, &req, web::Bytes::new()).await
+        }
+        Ok(None) => (),
+        Err(err) => return err_response(err.to_string()),
+    }
+
+    match flows::read_process(data.deps.clone(), process_id).await {
+        Ok(processed_str) => signed_read_response(&data, processed_str).await,
+        Err(err) => err_response(err.to_string()),
+    }
+}
+
+// still a 200 when degraded, the router keeps serving what it can
+async fn health_check(data: web::Data<AppState>) -> impl Responder {
+    HttpResponse::Ok().json(router::health_status(&data.deps))
+}
+
+async fn metrics_route(data: web::Data<AppState>) -> impl Responder {
+    let result = data.metrics.emit_metrics();
+    match result {
+        Ok(metrics_str) => HttpResponse::Ok()
+            .content_type("application/openmetrics-text; version=1.0.0; charset=utf-8")
+            .body(metrics_str),
+        Err(err) => err_response(err),
+    }
+}
+
+struct AppState {
+    deps: Arc<Deps>,
+    metrics: Arc<PromMetrics>,
+    startup_time: u64,
+    graphql: SuSchema,
+    proxy: Option<reqwest::Client>,
+}
+
+/*
+    The su http server as a library, so another program
+    can run it in process, for example
+
+    let server = Server::builder().config(config).port(0).start().await?;
+    let url = format!("http://{}", server.addr());
+
+    With use_local_store set in the config the whole su,
+    stores included, lives in the calling process.
+*/
+pub struct Server;
+
+impl Server {
+    pub fn builder() -> ServerBuilder {
+        ServerBuilder {
+            config: None,
+            mode: None,
+            host: "0.0.0.0".to_string(),
+            port: 0,
+        }
+    }
+}
+
+pub struct ServerBuilder {
+    config: Option<AoConfig>,
+    mode: Option<String>,
+    host: String,
+    port: u16,
+}
+
+impl ServerBuilder {
+    /*
+        Use this configuration instead of reading it from
+        the environment
+    */
+    pub fn config(mut self, config: AoConfig) -> Self {
+        self.config = Some(config);
+        self
+    }
+
+    // su or router, only used when the config is read from the environment
+    pub fn mode(mut self, mode: Option<String>) -> Self {
+        self.mode = mode;
+        self
+    }
+
+    pub fn host(mut self, host: &str) -> Self {
+        self.host = host.to_string();
+        self
+    }
+
+    // 0, the default, binds a free port, see RunningServer::addr
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    /*
+        Build the deps, start the background jobs for the
+        mode and begin serving. Returns once the port is
+        bound, the server runs until stopped.
+    */
+    pub async fn start(self) -> io::Result<RunningServer> {
+        let config = match self.config {
+            Some(config) => config,
+            None => AoConfig::new(self.mode)
+                .map_err(|e| Error::new(ErrorKind::InvalidInput, e))?,
+        };
+
+        let proxy = proxy_client(&config)?;
+        let shutdown_timeout = config.shutdown_timeout;
+
+        let startup_time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .expect("Time went backwards")
+            .as_secs();
+
+        let (deps, metrics) = init_deps_with(config).await;
+
+        // a bad ASSIGNMENT_TAGS would fail every write, refuse to start instead
+        if let Err(e) = flows::init_builder(&deps) {
+            deps.logger.error(e.clone());
+            return Err(Error::new(ErrorKind::InvalidInput, e));
+        }
+
+        let jobs = start_jobs(deps.clone()).await;
+
+        let app_state = web::Data::new(AppState {
+            deps: deps.clone(),
+            metrics,
+            startup_time,
+            graphql: graphql::schema(deps.clone()),
+            proxy,
+        });
+
+        let http_server = HttpServer::new(move || {
+            App::new()
+                .wrap_fn(|req, srv| {
+                    let class = endpoint_class(req.method(), req.path());
+                    let deps = req
+                        .app_data::<web::Data<AppState>>()
+                        .map(|data| data.deps.clone());
+                    let http_req = req.request().clone();
+                    let root = deps
+                        .as_ref()
+                        .and_then(|deps| request_span(&deps.tracer, &http_req));
+                    // the handler only runs once this is polled, after a slot is taken
+                    let response = srv.call(req);
+                    async move {
+                        let handled = async {
+                            let _permit = match (class, deps) {
+                                (Some(class), Some(deps)) => {
+                                    if let Some(refused) = ip_refused(&deps, &http_req, class) {
+                                        return Ok(ServiceResponse::new(http_req, refused)
+                                            .map_into_right_body());
+                                    }
+                                    match flows::admit_request(&deps, class).await {
+                                        Ok(permit) => permit,
+                                        Err(err) => {
+                                            let limited = concurrency_limited(err);
+                                            return Ok(ServiceResponse::new(http_req, limited)
+                                                .map_into_right_body());
+                                        }
+                                    }
+                                }
+                                _ => None,
+                            };
+                            response.await.map(ServiceResponse::map_into_left_body)
+                        };
+                        let mut root = match root {
+                            Some(root) => root,
+                            None => return handled.await,
+                        };
+                        let result = root.run(handled).await;
+                        if let Ok(res) = &result {
+                            root.attribute("http.response.status_code", res.status().as_u16());
+                            if res.status().is_server_error() {
+                                root.fail(res.status());
+                            }
+                        }
+                        result
+                    }
+                })
+                .wrap(
+                    Cors::default()
+                        .allow_any_origin()
+                        .allow_any_method()
+                        .allow_any_header()
+                        .expose_headers(["x-su-signature", "x-su-signer"]),
+                )
+                .wrap(Logger::default())
+                .app_data(app_state.clone())
+                .configure(routes)
+        })
+        .shutdown_timeout(shutdown_timeout)
+        .bind((self.host.as_str(), self.port))?;
+
+        let addr = http_server
+            .addrs()
+            .first()
+            .copied()
+            .ok_or_else(|| Error::new(ErrorKind::AddrNotAvailable, "Server did not bind"))?;
+
+        let server = http_server.run();
+        let handle = server.handle();
+
+        Ok(RunningServer {
+            addr,
+            deps,
+            handle,
+            task: tokio::spawn(server),
+            jobs,
+            shutdown_timeout: Duration::from_secs(shutdown_timeout),
+        })
+    }
+}
+
+pub struct RunningServer {
+    addr: SocketAddr,
+    deps: Arc<Deps>,
+    handle: ServerHandle,
+    task: JoinHandle<io::Result<()>>,
+    jobs: Vec<JoinHandle<()>>,
+    shutdown_timeout: Duration,
+}
+
+impl RunningServer {
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    pub fn deps(&self) -> Arc<Deps> {
+        self.deps.clone()
+    }
+
+    /*
+        Serve until the server is stopped, by a signal or
+        another task. SIGTERM stops it gracefully, new
+        connections are refused and the requests in flight
+        get up to SHUTDOWN_TIMEOUT seconds to finish. The
+        bundle uploads they started and the assignment events
+        not yet delivered to the stream then share as long
+        again, before the jobs are stopped and the metrics
+        and logs flushed.
+    */
+    pub async fn wait(self) -> io::Result<()> {
+        let result = join(self.task).await;
+        let deadline = tokio::time::Instant::now() + self.shutdown_timeout;
+        drain(&self.deps, deadline, "uploads", || {
+            self.deps.uploader.pending()
+        })
+        .await;
+        drain(&self.deps, deadline, "stream events", || {
+            self.deps.streamer.pending()
+        })
+        .await;
+        for job in self.jobs {
+            job.abort();
+        }
+        self.deps.metrics.flush(self.deps.logger.as_ref());
+        self.deps.logger.flush();
+        result
+    }
+
+    /*
+        Stop accepting requests, finish the ones in flight
+        when graceful, and stop the background jobs
+    */
+    pub async fn stop(self, graceful: bool) -> io::Result<()> {
+        self.handle.stop(graceful).await;
+        self.wait().await
+    }
+}
+
+/*
+    Poll until nothing is pending or the deadline passes,
+    stream events left over stay in the spool and are
+    delivered on the next start
+*/
+async fn drain(
+    deps: &Arc<Deps>,
+    deadline: tokio::time::Instant,
+    what: &str,
+    pending: impl Fn() -> usize,
+) {
+    let count = pending();
+    if count == 0 {
+        return;
+    }
+    deps.logger.log(format!(
+        "waiting up to {:?} for {} pending {}",
+        deadline.saturating_duration_since(tokio::time::Instant::now()),
+        count,
+        what
+    ));
+    while pending() > 0 {
+        if tokio::time::Instant::now() >= deadline {
+            deps.logger.error(format!(
+                "stopping with {} {} still pending",
+                pending(),
+                what
+            ));
+            return;
+        }
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    deps.logger.log(format!("pending {} finished", what));
+}
+
+async fn join(task: JoinHandle<io::Result<()>>) -> io::Result<()> {
+    match task.await {
+        Ok(result) => result,
+        Err(e) => Err(Error::other(e)),
+    }
+}
+
+/*
+    The methods served at a path, for the Allow header of
+    requests no route takes. Kept next to routes below,
+    paths not listed are the read routes.
+*/
+fn allowed_methods(path: &str) -> &'static str {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    match segments.as_slice() {
+        [""] => "GET, HEAD, POST, OPTIONS",
+        ["health"] => "GET, HEAD, OPTIONS",
+        ["batch"]
+        | ["graphql"]
+        | ["admin", "assign"]
+        | ["admin", "replica"]
+        | ["admin", "reload"]
+        | ["admin", "routing", "reconcile"]
+        | ["admin", "processes", _, "migrate"] => "POST, OPTIONS",
+        ["admin", "processes", _, "read-only"] => "POST, DELETE, OPTIONS",
+        ["admin", "schedulers"] | ["admin", "api-keys"] => "GET, POST, OPTIONS",
+        ["admin", "denylist"] => "GET, POST, DELETE, OPTIONS",
+        ["admin", "schedulers", _] => "PATCH, DELETE, OPTIONS",
+        ["admin", "api-keys", _] => "DELETE, OPTIONS",
+        _ => "GET, OPTIONS",
+    }
+}
+
+/*
+    The concurrency class a request counts against, None
+    for the light reads that are never held back. Also
+    kept next to routes below.
+*/
+fn endpoint_class(method: &Method, path: &str) -> Option<EndpointClass> {
+    let segments: Vec<&str> = path.trim_matches('/').split('/').collect();
+    let (get, post) = (*method == Method::GET, *method == Method::POST);
+    match segments.as_slice() {
+        ["admin", ..] => Some(EndpointClass::Admin),
+        [""] | ["batch"] if post => Some(EndpointClass::Write),
+        ["graphql"] | ["processes", "stats"] if post => Some(EndpointClass::Read),
+        ["processes", _, "stats" | "replay"] if get => Some(EndpointClass::Read),
+        ["" | "timestamp" | "health" | "info" | "metrics"] => None,
+        [_] if get => Some(EndpointClass::Read),
+        _ => None,
+    }
+}
+
+/*
+    The server span of a request when it is traced, named
+    by its route so requests for different processes
+    share a name
+*/
+fn request_span(tracer: &trace::Tracer, req: &HttpRequest) -> Option<trace::SpanGuard> {
+    let traceparent = req
+        .headers()
+        .get("traceparent")
+        .and_then(|value| value.to_str().ok());
+    let route = req.match_pattern();
+    let name = match &route {
+        Some(route) => format!("{} {}", req.method(), route),
+        None => req.method().to_string(),
+    };
+    let mut span = tracer.request(name, traceparent)?;
+    span.attribute("http.request.method", req.method());
+    span.attribute("url.path", req.path());
+    if let Some(route) = route {
+        span.attribute("http.route", route);
+    }
+    Some(span)
+}
+
+// the class is full, the client should retry shortly
+fn concurrency_limited(err: String) -> HttpResponse {
+    error_response(ErrorCode::ConcurrencyLimited, err, json!({}))
+}
+
+/*
+    Every route is guarded by its method, so OPTIONS and
+    HEAD requests end up here without reaching a handler
+    or the data store. Well formed CORS preflights are
+    answered by the Cors middleware before this, which
+    also adds its headers to these responses. HEAD is only
+    served on the liveness paths load balancers probe.
+*/
+async fn unrouted(req: HttpRequest) -> HttpResponse {
+    let allow = allowed_methods(req.path());
+    match *req.method() {
+        Method::OPTIONS => HttpResponse::NoContent()
+            .insert_header((ALLOW, allow))
+            .finish(),
+        Method::HEAD if allow.contains("HEAD") => HttpResponse::Ok().finish(),
+        Method::HEAD => HttpResponse::MethodNotAllowed()
+            .insert_header((ALLOW, allow))
+            .finish(),
+        _ => error_response(
+            ErrorCode::NotFound,
+            format!("No route for {} {}", req.method(), req.path()),
+            json!({}),
+        ),
+    }
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.app_data(web::PayloadConfig::new(10485760))
+        .app_data(web::QueryConfig::default().error_handler(|err, _| extractor_error(err)))
+        .app_data(web::PathConfig::default().error_handler(|err, _| extractor_error(err)))
+        .app_data(web::JsonConfig::default().error_handler(|err, _| extractor_error(err)))
+        .route("/", web::get().to(base))
+        .route("/", web::post().to(main_post_route))
+        .route("/batch", web::post().to(batch_route))
+        .route("/timestamp", web::get().to(timestamp_route))
+        .route("/health", web::get().to(health_check))
+        .route("/info", web::get().to(info_route))
+        .route("/metrics", web::get().to(metrics_route))
+        .route("/admin/assign", web::post().to(force_assign_route))
+        .route("/admin/raw/{tx_id}", web::get().to(read_raw_route))
+        .route("/admin/replica", web::post().to(replica_route))
+        .route("/admin/export", web::get().to(export_items_route))
+        .route("/admin/processes", web::get().to(list_process_ids_route))
+        .route("/admin/export/{process_id}", web::get().to(export_route))
+        .route(
+            "/admin/processes/{process_id}/migrate",
+            web::post().to(migrate_process_route),
+        )
+        .route(
+            "/admin/processes/{process_id}/read-only",
+            web::post().to(mark_read_only_route),
+        )
+        .route(
+            "/admin/processes/{process_id}/read-only",
+            web::delete().to(clear_read_only_route),
+        )
+        .route(
+            "/admin/routing/invariants",
+            web::get().to(routing_invariants_route),
+        )
+        .route("/admin/routing/reconcile", web::post().to(reconcile_route))
+        .route(
+            "/admin/routing/decisions",
+            web::get().to(routing_decisions_route),
+        )
+        .route("/admin/schedulers", web::get().to(list_schedulers_route))
+        .route("/admin/schedulers", web::post().to(add_scheduler_route))
+        .route(
+            "/admin/schedulers/{scheduler_id}",
+            web::patch().to(update_scheduler_route),
+        )
+        .route(
+            "/admin/schedulers/{scheduler_id}",
+            web::delete().to(remove_scheduler_route),
+        )
+        .route(
+            "/admin/router/changes",
+            web::post().to(router_changes_route),
+        )
+        .route(
+            "/admin/router/promote",
+            web::post().to(promote_router_route),
+        )
+        .route("/admin/denylist", web::get().to(list_denylist_route))
+        .route("/admin/denylist", web::post().to(add_denylist_route))
+        .route("/admin/denylist", web::delete().to(remove_denylist_route))
+        .route("/admin/api-keys", web::get().to(list_api_keys_route))
+        .route("/admin/api-keys", web::post().to(issue_api_key_route))
+        .route(
+            "/admin/api-keys/{key_id}",
+            web::delete().to(revoke_api_key_route),
+        )
+        .route("/admin/audit", web::get().to(admin_audit_route))
+        .route("/admin/reload", web::post().to(reload_config_route))
+        .route("/admin/registration", web::get().to(registration_route))
+        .route("/graphql", web::post().to(graphql_route))
+        .route("/processes", web::get().to(list_processes_route))
+        .route("/{tx_id}", web::get().to(main_get_route))
+        .route(
+            "/processes/stats",
+            web::post().to(read_processes_stats_route),
+        )
+        .route("/processes/{process_id}", web::get().to(read_process_route))
+        .route(
+            "/processes/{process_id}/subscribe",
+            web::get().to(subscribe_route),
+        )
+        .route(
+            "/processes/{process_id}/stats",
+            web::get().to(read_process_stats_route),
+        )
+        .route(
+            "/processes/{process_id}/snapshot",
+            web::get().to(read_chain_snapshot_route),
+        )
+        .route(
+            "/processes/{process_id}/replay",
+            web::get().to(replay_route),
+        )
+        .route("/{tx_id}/proof", web::get().to(read_proof_route))
+        .route("/{process_id}/latest", web::get().to(read_latest_route))
+        .default_service(web::route().to(unrouted));
+}
+
+/*
+    Memory checks, the release of deferred messages, and
+    in router mode the scheduler list and its maintenance
+    loops
+*/
+async fn start_jobs(run_deps: Arc<Deps>) -> Vec<JoinHandle<()>> {
+    let mut jobs = Vec::new();
+
+    if run_deps.memory.enabled() {
+        let memory_deps = run_deps.clone();
+        jobs.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                flows::check_memory(&memory_deps);
+            }
+        }));
+    }
+
+    #[cfg(unix)]
+    {
+        let reload_deps = run_deps.clone();
+        jobs.push(tokio::spawn(async move {
+            use tokio::signal::unix::{signal, SignalKind};
+            let mut hangups = match signal(SignalKind::hangup()) {
+                Ok(hangups) => hangups,
+                Err(e) => {
+                    reload_deps
+                        .logger
+                        .error(format!("Failed to listen for SIGHUP: {}", e));
+                    return;
+                }
+            };
+            while hangups.recv().await.is_some() {
+                // a refused reload is logged by reload_config
+                let _ = flows::reload_config(reload_deps.clone(), None);
+            }
+        }));
+    }
+
+    /*
+        Runs even without SCHEDULER_LOCATION_URL, a reload
+        may set it. The first tick is immediate, publishing
+        on startup.
+    */
+    let location_deps = run_deps.clone();
+    jobs.push(tokio::spawn(async move {
+        let mut interval = tokio::time::interval(Duration::from_secs(60));
+        loop {
+            interval.tick().await;
+            match registration::refresh_scheduler_location(location_deps.clone()).await {
+                Err(e) => location_deps.logger.error(e),
+                Ok(Some(m)) => location_deps.logger.log(m),
+                Ok(None) => (),
+            };
+        }
+    }));
+
+    if run_deps.config.mode() != Mode::Router && run_deps.config.deferred_messages() {
+        let deferred_deps = run_deps.clone();
+        jobs.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(1));
+            loop {
+                interval.tick().await;
+                match flows::release_deferred(deferred_deps.clone()).await {
+                    Err(e) => deferred_deps.logger.error(e),
+                    Ok(0) => (),
+                    Ok(n) => deferred_deps
+                        .logger
+                        .log(format!("Released {} deferred messages", n)),
+                };
+            }
+        }));
+    }
+
+    // a router signs nothing, the first tick checks on startup
+    let signing_check_interval = run_deps.config.signing_check_interval();
+    if run_deps.config.mode() != Mode::Router && signing_check_interval > 0 {
+        let signing_deps = run_deps.clone();
+        jobs.push(tokio::spawn(async move {
+            let mut interval = tokio::time::interval(Duration::from_secs(signing_check_interval));
+            loop {
+                interval.tick().await;
+                flows::check_signing(&signing_deps).await;
+            }
+        }));
+    }
+
+    if run_deps.config.mode() == Mode::Router {
+        match router::init_schedulers(run_deps.clone()).await {
+            Err(e) => run_deps.logger.log(e.to_string()),
+            Ok(m) => run_deps.logger.log(m.to_string()),
+        };
+
+        let cleanup_interval = run_deps.config.process_scheduler_cleanup_interval();
+        if cleanup_interval > 0 {
+            let cleanup_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(cleanup_interval));
+                loop {
+                    interval.tick().await;
+                    // a standby cleans up its own tables once it is promoted
+                    if cleanup_deps.router_role.standby() {
+                        continue;
+                    }
+                    match router::cleanup_process_schedulers(cleanup_deps.clone()).await {
+                        Err(e) => cleanup_deps.logger.error(e),
+                        Ok(m) => cleanup_deps.logger.log(m),
+                    };
+                }
+            }));
+        }
+
+        let reload_interval = run_deps.config.scheduler_list_reload_interval();
+        if reload_interval > 0 {
+            let reload_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(reload_interval));
+                // the first tick is immediate and init_schedulers just ran
+                interval.tick().await;
+                loop {
+                    interval.tick().await;
+                    match router::reload_schedulers(reload_deps.clone()).await {
+                        Err(e) => reload_deps.logger.error(e),
+                        Ok(Some(m)) => reload_deps.logger.log(m),
+                        Ok(None) => (),
+                    };
+                }
+            }));
+        }
+
+        let health_interval = run_deps.config.scheduler_health_interval();
+        if health_interval > 0 {
+            let health_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(health_interval));
+                loop {
+                    interval.tick().await;
+                    match router::check_scheduler_health(health_deps.clone()).await {
+                        Err(e) => health_deps.logger.error(e),
+                        Ok(m) => health_deps.logger.log(m),
+                    };
+                }
+            }));
+        }
+
+        let probe_interval = run_deps.config.router_store_probe_interval();
+        if probe_interval > 0 {
+            let probe_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(probe_interval));
+                loop {
+                    interval.tick().await;
+                    // going down was logged when it happened, a failed probe adds nothing
+                    if let Ok(Some(m)) = router::probe_router_store(probe_deps.clone()).await {
+                        probe_deps.logger.log(m);
+                    }
+                }
+            }));
+        }
+
+        let drain_interval = run_deps.config.drain_interval();
+        if drain_interval > 0 {
+            let drain_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(drain_interval));
+                loop {
+                    interval.tick().await;
+                    match router::drain_schedulers(drain_deps.clone()).await {
+                        Err(e) => drain_deps.logger.error(e),
+                        Ok(Some(m)) => drain_deps.logger.log(m),
+                        Ok(None) => (),
+                    };
+                }
+            }));
+        }
+
+        let rebalance_interval = run_deps.config.rebalance_interval();
+        if rebalance_interval > 0 {
+            let rebalance_deps = run_deps.clone();
+            jobs.push(tokio::spawn(async move {
+                let mut interval = tokio::time::interval(Duration::from_secs(rebalance_interval));
+                loop {
+                    interval.tick().await;
+                    match router::rebalance_schedulers(rebalance_deps.clone()).await {
+                        Err(e) => rebalance_deps.logger.error(e),
+                        Ok(Some(m)) => rebalance_deps.logger.log(m),
+                        Ok(None) => (),
+                    };
+                }
+            }));
+        }
+    }
+
+    jobs
+}